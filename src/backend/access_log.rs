@@ -0,0 +1,85 @@
+use crate::backend::contract_params::CanonicalRedirect;
+use crate::backend::errors::rejection_to_error;
+use std::convert::Infallible;
+use std::time::Instant;
+use warp::http::header::HeaderValue;
+use warp::http::{HeaderMap, Method};
+use warp::path::FullPath;
+use warp::reject::Rejection;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// Longer client-supplied ids are replaced rather than echoed back
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Per-request data captured before routing, used for the access log
+/// and to tag the response with its request id.
+pub struct RequestContext {
+    pub request_id: String,
+    method: Method,
+    path: String,
+    started: Instant,
+}
+
+/// Assigns a request id, reusing the caller's `X-Request-Id` when it is sane
+pub fn request_context() -> impl Filter<Extract = (RequestContext,), Error = Infallible> + Clone {
+    warp::header::headers_cloned()
+        .and(warp::method())
+        .and(warp::path::full())
+        .map(|headers: HeaderMap, method: Method, path: FullPath| {
+            let request_id = headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .filter(|id| is_valid_request_id(id))
+                .map(str::to_string)
+                .unwrap_or_else(generate_request_id);
+            RequestContext {
+                request_id,
+                method,
+                path: path.as_str().to_string(),
+                started: Instant::now(),
+            }
+        })
+}
+
+fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn generate_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Renders the outcome of the routes, attaches the request id and writes the access log line.
+/// Rejections are rendered here rather than in a `recover` so the error body can carry the id.
+pub async fn finish(context: RequestContext, result: Result<Response, Rejection>) -> Response {
+    let mut response = match result {
+        Ok(response) => response,
+        Err(rejection) => match rejection.find::<CanonicalRedirect>() {
+            Some(redirect) => redirect.response(),
+            None => {
+                let (status, mut error_response) = rejection_to_error(&rejection);
+                error_response.request_id = Some(context.request_id.clone());
+                warp::reply::with_status(warp::reply::json(&error_response), status).into_response()
+            }
+        },
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&context.request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    println!(
+        "[{}] {} {} {} {}ms",
+        context.request_id,
+        context.method,
+        context.path,
+        response.status().as_u16(),
+        context.started.elapsed().as_millis()
+    );
+
+    response
+}
@@ -0,0 +1,205 @@
+use crate::backend::errors::ApiError;
+use crate::backend::holdings::{afterlife_points, contract_holdings, load_user_holdings};
+use crate::backend::levels::points_to_level;
+use crate::backend::queries::{self, TransferCounts};
+use crate::backend::response_cache;
+use crate::backend::usernames::get_all_addresses_for_name;
+use crate::common::file_loader::read_file;
+use crate::common::numeric::TokenId;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+/// A badge as defined in the achievements file (AFTERLIFE_PATH_ACHIEVEMENTS), e.g.
+///
+/// ```yaml
+/// - id: burner
+///   name: Burner
+///   description: Burned 10 tokens
+///   rule:
+///     type: burns
+///     min: 10
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct AchievementDefinition {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub image: Option<String>,
+    pub rule: AchievementRule,
+}
+
+/// Condition a user must meet to earn an achievement. `chain`/`contract` filters are
+/// optional where present; without them every indexed contract counts.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AchievementRule {
+    // Minted at least one token
+    FirstMint {
+        chain: Option<String>,
+        contract: Option<String>,
+    },
+    // Holds every token of a collection, all tokens in existence when token_ids is empty
+    FullSet {
+        chain: String,
+        contract: String,
+        #[serde(default)]
+        token_ids: Vec<TokenId>,
+    },
+    // Burned at least `min` times
+    Burns {
+        min: i64,
+        chain: Option<String>,
+        contract: Option<String>,
+    },
+    // Consumed at least `min` tokens by sending them to a sink address
+    Consumes {
+        min: i64,
+        chain: Option<String>,
+        contract: Option<String>,
+    },
+    // Reached at least level `min`
+    Level {
+        min: i32,
+    },
+}
+
+pub(crate) fn is_configured() -> bool {
+    env::var("AFTERLIFE_PATH_ACHIEVEMENTS").is_ok()
+}
+
+async fn load_achievement_definitions() -> Result<Vec<AchievementDefinition>, ApiError> {
+    let path = env::var("AFTERLIFE_PATH_ACHIEVEMENTS")
+        .map_err(|_| ApiError::Internal("Achievements are not configured".to_string()))?;
+    let contents = read_file(Path::new(&path))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to read achievements file: {}", e)))?;
+    serde_yaml::from_str(&contents)
+        .map_err(|e| ApiError::Internal(format!("Invalid achievements file: {}", e)))
+}
+
+fn matches_scope(
+    counts: &TransferCounts,
+    chain: &Option<String>,
+    contract: &Option<String>,
+) -> bool {
+    chain
+        .as_ref()
+        .is_none_or(|chain| chain.eq_ignore_ascii_case(&counts.chain_name))
+        && contract
+            .as_ref()
+            .is_none_or(|contract| contract.eq_ignore_ascii_case(&counts.contract_address))
+}
+
+pub async fn handle_get_user_achievements(
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!("user/achievements/{}", username);
+    let response =
+        response_cache::get_or_compute(cache_key, build_user_achievements(&username, &client))
+            .await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+pub(crate) async fn build_user_achievements(
+    username: &str,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    let user_addresses = get_all_addresses_for_name(username).await?;
+    if user_addresses.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)));
+    }
+    let definitions = load_achievement_definitions().await?;
+
+    let holdings = load_user_holdings(client, &user_addresses).await?;
+    let addresses: Vec<String> = user_addresses.into_iter().collect();
+    let transfer_counts = queries::get_transfer_counts_for_addresses(client, &addresses)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to count transfers: {}", e)))?;
+    let level = points_to_level(afterlife_points(&holdings).await as i32);
+
+    let mut achievements = Vec::with_capacity(definitions.len());
+    for definition in &definitions {
+        let earned = match &definition.rule {
+            AchievementRule::FirstMint { chain, contract } => transfer_counts
+                .iter()
+                .filter(|counts| matches_scope(counts, chain, contract))
+                .any(|counts| counts.mints > 0),
+            AchievementRule::FullSet {
+                chain,
+                contract,
+                token_ids,
+            } => {
+                let set = if token_ids.is_empty() {
+                    queries::get_entire_collection(client, chain, contract)
+                        .await
+                        .map_err(|e| {
+                            ApiError::Upstream(format!("Failed to get entire collection: {}", e))
+                        })?
+                } else {
+                    token_ids.clone()
+                };
+                let owned = contract_holdings(&holdings, chain, contract);
+                !set.is_empty()
+                    && set
+                        .iter()
+                        .all(|token_id| owned.is_some_and(|tokens| tokens.contains_key(token_id)))
+            }
+            AchievementRule::Burns {
+                min,
+                chain,
+                contract,
+            } => {
+                let burns: i64 = transfer_counts
+                    .iter()
+                    .filter(|counts| matches_scope(counts, chain, contract))
+                    .map(|counts| counts.burns)
+                    .sum();
+                burns >= *min
+            }
+            AchievementRule::Consumes {
+                min,
+                chain,
+                contract,
+            } => {
+                let consumed: i64 = transfer_counts
+                    .iter()
+                    .filter(|counts| matches_scope(counts, chain, contract))
+                    .map(|counts| counts.consumed)
+                    .sum();
+                consumed >= *min
+            }
+            AchievementRule::Level { min } => level >= *min,
+        };
+
+        achievements.push(json!({
+            "id": definition.id,
+            "name": definition.name,
+            "description": definition.description,
+            "image": definition.image,
+            "earned": earned,
+        }));
+    }
+
+    let earned = achievements
+        .iter()
+        .filter(|achievement| achievement["earned"] == json!(true))
+        .count();
+
+    Ok(json!({
+        "username": username,
+        "level": level,
+        "earned": earned,
+        "total": achievements.len(),
+        "achievements": achievements,
+    }))
+}
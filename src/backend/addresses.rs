@@ -0,0 +1,73 @@
+use crate::backend::errors::ApiError;
+use eth_checksum::checksum;
+use warp::path::FullPath;
+use warp::reject::Rejection;
+use warp::Filter;
+
+const ADDRESS_HEX_LEN: usize = 40;
+
+/// Validates an EVM address and returns its checksummed form. All lowercase or all
+/// uppercase input is accepted as is, mixed case has to be a valid EIP-55 checksum.
+pub fn normalize(input: &str) -> Result<String, ApiError> {
+    let hex = input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "Invalid address {}: expected 0x followed by {} hex characters",
+                input, ADDRESS_HEX_LEN
+            ))
+        })?;
+    if hex.len() != ADDRESS_HEX_LEN || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid address {}: expected 0x followed by {} hex characters, got {}",
+            input,
+            ADDRESS_HEX_LEN,
+            hex.len()
+        )));
+    }
+
+    let checksummed = checksum(&format!("0x{}", hex.to_lowercase()));
+    let mixed_case =
+        hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case && hex != &checksummed[2..] {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid address {}: bad checksum, did you mean {}?",
+            input, checksummed
+        )));
+    }
+    Ok(checksummed)
+}
+
+/// Like `normalize`, for an address given in the `field` of a request body
+pub fn normalize_field(field: &str, input: &str) -> Result<String, ApiError> {
+    normalize(input).map_err(|e| ApiError::invalid_field(field, e.message().to_string()))
+}
+
+/// Whether a value is meant as an address rather than a username: 0x followed by hex
+/// only, or address sized. "0xAlice" stays a username, "0x12ab" is a mistyped address.
+pub fn looks_like_address(value: &str) -> bool {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(rest) => rest.len() == ADDRESS_HEX_LEN || rest.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+/// Rejects with 400 any request with a malformed address in its path, rather than
+/// letting the routes answer with an empty result. Runs before routing, so every route
+/// is covered whatever its path parameters are.
+pub fn validate_path() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::full()
+        .and_then(|path: FullPath| async move {
+            for segment in path.as_str().split('/') {
+                if looks_like_address(segment) {
+                    normalize(segment)?;
+                }
+            }
+            Ok::<_, Rejection>(())
+        })
+        .untuple_one()
+}
@@ -1,426 +1,2259 @@
 use crate::backend;
+use crate::backend::access_log;
+use crate::backend::achievements;
+use crate::backend::addresses;
+use crate::backend::api_keys;
+use crate::backend::approvals;
+use crate::backend::auth;
+use crate::backend::avatars;
+use crate::backend::bot;
+use crate::backend::cache_events;
+use crate::backend::catalog;
+use crate::backend::collection_groups::{self, BridgedTokens};
+use crate::backend::collection_traits;
+use crate::backend::contract_params;
+use crate::backend::delegations;
+use crate::backend::directory;
+use crate::backend::ens;
+use crate::backend::errors::{json_body, ApiError};
+use crate::backend::exclusions;
+use crate::backend::export;
+use crate::backend::health;
+use crate::backend::hold_stats;
+use crate::backend::holdings::{load_staked_balances, load_staking_addresses};
+use crate::backend::jobs;
+use crate::backend::labels;
+use crate::backend::levels;
+use crate::backend::media;
+use crate::backend::metadata_store::{
+    MetadataStore, RarityMap, METADATA_BATCH_SIZE, METADATA_STORE,
+};
+use crate::backend::movers;
+use crate::backend::notifications;
+use crate::backend::portfolio;
+use crate::backend::preflight;
+use crate::backend::prices;
+use crate::backend::profiles;
+use crate::backend::projects::{self, Project, DEFAULT_PROJECT};
 use crate::backend::queries::{
-    get_all_users_collections, get_contract_name_from_chain_and_address, get_user_full_collection,
+    get_all_users_collections, get_all_users_peak_collections,
+    get_contract_name_from_chain_and_address, get_supply_history, get_users_full_collections,
+    ActivityCursor, SaleCursor, StakedBalance,
 };
+use crate::backend::rarity::{self, TierThresholds};
+use crate::backend::repository::CollectionRepository;
+use crate::backend::response_cache;
+use crate::backend::reveals;
+use crate::backend::score_weights;
+use crate::backend::seasons;
+use crate::backend::sets;
+use crate::backend::teams;
+use crate::backend::timeouts::{timed, timed_export, timed_write};
+use crate::backend::tls::{self, TlsConfig};
+use crate::backend::user_admin;
+use crate::backend::user_details_cache;
+use crate::backend::user_search;
 use crate::backend::usernames::{
-    get_all_addresses_for_username, get_username_or_checksummed_address,
+    addresses_for_name, get_username_or_checksummed_address,
+    resolve_username_or_checksummed_address, usernames_by_address,
 };
-use crate::common;
+use crate::backend::v1;
+use crate::backend::webhooks;
+use crate::common::addresses::{is_burn, is_mint, is_sink};
+use crate::common::database::Database;
+use crate::common::numeric::{Balance, TokenId};
+use crate::indexer::queries::SUPPLY_HISTORY_BUCKET_BLOCKS;
 use backend::queries;
-use common::file_loader::read_file;
-use eth_checksum::checksum;
 use futures::future::try_join_all;
+use futures::stream::{self, StreamExt};
 use once_cell::sync::Lazy;
+use serde::Deserialize;
 use serde_json::{json, Map, Number, Value};
 use std::collections::HashMap;
 use std::convert::Infallible;
-use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 use std::sync::Arc;
-use std::{env, fs};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
 use tokio::task;
 use tokio_postgres::Client;
-use warp::reject::{Reject, Rejection};
+use warp::http::Method;
+use warp::reject::Rejection;
 use warp::{Filter, Reply};
 
-#[derive(Debug)]
-struct CustomReject(String);
+pub(crate) type LeaderboardType = HashMap<String, f64>;
+static ALL_USERS_LEADERBOARD_CACHE: Lazy<Mutex<Option<LeaderboardType>>> =
+    Lazy::new(|| Mutex::new(None));
+// Set once the leaderboard has been computed at least once, used by the readiness probe
+static LEADERBOARD_READY: AtomicBool = AtomicBool::new(false);
+// computed_at (epoch ms) of the stored leaderboard in the cache, 0 if computed locally
+static LEADERBOARD_COMPUTED_AT: AtomicI64 = AtomicI64::new(0);
+const LEADERBOARD_CACHE_KEY: &str = "leaderboard";
+const LIFETIME_LEADERBOARD_CACHE_KEY: &str = "leaderboard/lifetime";
+// Min time between two leaderboard snapshots in score_history
+pub(crate) const SCORE_SNAPSHOT_INTERVAL_SECONDS: f64 = 3600.0;
+static LEGACY_ROUTES_DISABLED: Lazy<bool> = Lazy::new(|| {
+    std::env::var("AFTERLIFE_DISABLE_LEGACY_ROUTES")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+});
+
+// Max token ids accepted by a single batch metadata request
+const MAX_BATCH_TOKENS: usize = 200;
+const BATCH_TOKENS_BODY_LIMIT: u64 = 16 * 1024;
+const ADMIN_BODY_LIMIT: u64 = 16 * 1024;
+const ACCOUNT_BODY_LIMIT: u64 = 4 * 1024;
+const USERNAME_LOOKUP_BODY_LIMIT: u64 = 1024;
+
+// Sales listed in a provenance document, far more than any single token changes hands
+const MAX_PROVENANCE_SALES: i64 = 1000;
 
-impl Reject for CustomReject {}
+const DEFAULT_ACTIVITY_LIMIT: i64 = 50;
+const MAX_ACTIVITY_LIMIT: i64 = 200;
 
-#[derive(serde::Serialize)]
-struct ErrorResponse {
-    message: String,
+/// Which points a leaderboard ranks users by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ScoreMetric {
+    // Points of the tokens held now
+    #[default]
+    Current,
+    // Points of the highest balance ever held of each token, selling doesn't lower them
+    Lifetime,
 }
 
-type CollectionsType = HashMap<String, HashMap<String, HashMap<String, HashMap<u64, i64>>>>;
-type LeaderboardType = HashMap<String, f64>;
-static ALL_USERS_LEADERBOARD_CACHE: Lazy<Mutex<Option<LeaderboardType>>> =
-    Lazy::new(|| Mutex::new(None));
+/// Body of POST /get-username
+#[derive(Debug, Deserialize)]
+struct UsernameLookup {
+    // Address or ENS name
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LeaderboardQuery {
+    #[serde(default)]
+    metric: ScoreMetric,
+}
+
+#[derive(Debug, Deserialize)]
+struct ActivityQuery {
+    limit: Option<i64>,
+    // Unix timestamp, only activity strictly older than this is returned
+    before: Option<i64>,
+    // The `next_cursor` of the previous page, takes precedence over `before`
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CollectionActivityQuery {
+    // Period covered and size of its buckets, e.g. 7d and 1d, see parse_period
+    window: Option<String>,
+    granularity: Option<String>,
+}
+
+const DEFAULT_ACTIVITY_WINDOW: &str = "7d";
+const DEFAULT_ACTIVITY_GRANULARITY: &str = "1d";
+// Buckets in a single collection activity response
+const MAX_ACTIVITY_PERIODS: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+struct SalesQuery {
+    limit: Option<i64>,
+    // Unix timestamp, only sales strictly older than this are returned
+    before: Option<i64>,
+    // The `next_cursor` of the previous page
+    cursor: Option<String>,
+    token_id: Option<TokenId>,
+}
+
+/// `?block=` of the owners routes, ownership as of the end of that block
+#[derive(Debug, Deserialize)]
+pub(crate) struct OwnersQuery {
+    pub(crate) block: Option<i32>,
+}
+
+impl OwnersQuery {
+    /// The requested block, refused when negative or not indexed yet for the contract:
+    /// ownership past the last processed block would be incomplete
+    pub(crate) async fn block<R: CollectionRepository>(
+        &self,
+        repository: &R,
+        chain_name: &str,
+        contract_address: &str,
+    ) -> Result<Option<i32>, ApiError> {
+        let block = match self.block {
+            Some(block) if block < 0 => {
+                return Err(ApiError::BadRequest(format!("Invalid block {}", block)))
+            }
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        let last_processed_block = repository
+            .last_processed_block(chain_name, contract_address)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get the indexed block: {}", e)))?;
+        match last_processed_block {
+            Some(last) if block > last => Err(ApiError::BadRequest(format!(
+                "Block {} isn't indexed yet, the last indexed block is {}",
+                block, last
+            ))),
+            _ => Ok(Some(block)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FullCollectionQuery {
+    // json (default) or csv
+    format: Option<String>,
+    // Also list tokens of contracts flagged as spam or scams
+    #[serde(default)]
+    include_unverified: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContractVerification {
+    verified: bool,
+}
 
-// define const of excluded users or addresses for the leaderboard
-const EXCLUDED_USERS: [&str; 4] = [
-    "Danetron3030",
-    "AfterlifeTreasury",
-    "0x3cc35873a61D925Ac46984f8C4F85d8fa6A892eF",
-    "AfterlifeCoinBank",
-];
-const DEAD_ADDRESS: &str = "0x000000000000000000000000000000000000dEaD";
-const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+// Accept header value asking for the whole collection as a stream of JSON lines
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
 
-pub async fn run_server(client: Arc<Client>) {
-    //let client = Arc::new(client);
+pub async fn run_server(database: Arc<Database>) {
+    let client = database.client().await;
+    if let Err(e) = exclusions::reload(&client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = labels::reload(&client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = delegations::reload(&client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = api_keys::reload(&client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = score_weights::reload(&client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = rarity::reload(&client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = collection_groups::reload(&client).await {
+        eprintln!("{:?}", e);
+    }
+    api_keys::spawn_usage_flusher(database.clone());
+    api_keys::spawn_reloader(database.clone());
+    METADATA_STORE.attach_database(database.clone());
+    rarity::spawn_scheduler();
+    reveals::spawn_watcher(database.clone());
+    jobs::spawn_worker(database.clone());
+    // Contracts missing metadata or rarities score zero, say which up front and as it changes
+    preflight::spawn_scheduler(database.clone());
+
+    // Behind a reverse proxy unless the API terminates TLS itself
+    match TlsConfig::from_env() {
+        Some(Ok(config)) => {
+            if let Some(redirect_address) = config.redirect_address {
+                tokio::spawn(tls::serve_redirects(
+                    redirect_address,
+                    config.address.port(),
+                ));
+            }
+            println!("Serving HTTPS on {}", config.address);
+            warp::serve(routes(database))
+                .tls()
+                .cert_path(&config.cert_path)
+                .key_path(&config.key_path)
+                .run(config.address)
+                .await;
+            return;
+        }
+        // Serving plain HTTP instead would expose what was meant to be encrypted
+        Some(Err(e)) => {
+            eprintln!("Invalid TLS config: {}", e);
+            process::exit(1);
+        }
+        None => {}
+    }
+
+    warp::serve(routes(database))
+        .run(([127, 0, 0, 1], 3030))
+        .await;
+}
 
+/// Every route of the API, without the background tasks `run_server` starts, so the
+/// router can also be driven in-process with `warp::test`
+pub fn routes(
+    database: Arc<Database>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone + Send + Sync + 'static
+{
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_methods(vec!["GET", "POST", "OPTIONS"])
-        .allow_headers(vec!["Content-Type"]);
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+        .allow_headers(vec![
+            "Content-Type",
+            "Authorization",
+            access_log::REQUEST_ID_HEADER,
+            api_keys::API_KEY_HEADER,
+        ])
+        .expose_headers(vec![access_log::REQUEST_ID_HEADER]);
 
-    let routes = warp::path!(String / String / "collection" / String)
+    let public_routes = projects::with_default_project()
+        .and(
+            contract_params::canonical(
+                warp::path!(String / String / "collection" / ..),
+                database.clone(),
+            )
+            .and(warp::path!(String)),
+        )
         .and(warp::get())
-        .and(with_db(client.clone()))
-        .and_then(handle_get_collection_for_address)
-        .or(warp::path!(String / String / "collection")
+        .and(with_db(database.clone()))
+        .and_then(timed!(
+            handle_get_collection_for_address,
+            project,
+            chain_name,
+            contract_address,
+            wallet_address,
+            client
+        ))
+        .or(projects::with_default_project()
+            .and(contract_params::canonical(
+                warp::path!(String / String / "collection"),
+                database.clone(),
+            ))
+            .and(warp::get())
+            .and(warp::header::optional::<String>("accept"))
+            .and(api_keys::has_partner_key())
+            .and(with_db(database.clone()))
+            .and_then(timed_export!(
+                handle_get_entire_collection,
+                project,
+                chain_name,
+                contract_address,
+                accept,
+                partner,
+                client
+            )))
+        .or(
+            contract_params::canonical(warp::path!(String / String / "tokens"), database.clone())
+                .and(warp::post())
+                .and(warp::body::content_length_limit(BATCH_TOKENS_BODY_LIMIT))
+                .and(json_body())
+                .and(with_db(database.clone()))
+                .and_then(timed!(
+                    handle_get_tokens_batch,
+                    chain_name,
+                    contract_address,
+                    token_ids,
+                    client
+                )),
+        )
+        .or(
+            contract_params::canonical(warp::path!(String / String / "sales"), database.clone())
+                .and(warp::get())
+                .and(warp::query::<SalesQuery>())
+                .and(with_db(database.clone()))
+                .and_then(timed!(
+                    handle_get_sales,
+                    chain_name,
+                    contract_address,
+                    query,
+                    client
+                )),
+        )
+        .or(contract_params::canonical(
+            warp::path!(String / String / "token" / ..),
+            database.clone(),
+        )
+        .and(warp::path!(TokenId / "provenance"))
+        .and(warp::get())
+        .and(with_db(database.clone()))
+        .and_then(timed!(
+            handle_get_token_provenance,
+            chain_name,
+            contract_address,
+            token_id,
+            client
+        )))
+        .or(projects::with_default_project()
+            .and(contract_params::canonical(
+                warp::path!(String / String / "stats"),
+                database.clone(),
+            ))
             .and(warp::get())
-            .and(with_db(client.clone()))
-            .and_then(handle_get_entire_collection))
-        .or(warp::path!(String / String / "owners" / u64)
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_collection_stats,
+                project,
+                chain_name,
+                contract_address,
+                client
+            )))
+        .or(contract_params::canonical(
+            warp::path!(String / String / "supply" / "history"),
+            database.clone(),
+        )
+        .and(warp::get())
+        .and(with_db(database.clone()))
+        .and_then(timed!(
+            handle_get_supply_history,
+            chain_name,
+            contract_address,
+            client
+        )))
+        .or(
+            contract_params::canonical(warp::path!(String / String / "traits"), database.clone())
+                .and(warp::get())
+                .and(with_db(database.clone()))
+                .and_then(timed!(
+                    collection_traits::handle_get_collection_traits,
+                    chain_name,
+                    contract_address,
+                    client
+                )),
+        )
+        .or(projects::with_default_project()
+            .and(contract_params::canonical(
+                warp::path!(String / String / "activity"),
+                database.clone(),
+            ))
             .and(warp::get())
-            .and(with_db(client.clone()))
-            .and_then(handle_get_token_owners))
-        .or(warp::path!("get-username")
+            .and(warp::query::<CollectionActivityQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_collection_activity,
+                project,
+                chain_name,
+                contract_address,
+                query,
+                client
+            )))
+        .or(contract_params::canonical(
+            warp::path!(String / String / "owners" / ..),
+            database.clone(),
+        )
+        .and(warp::path!(TokenId))
+        .and(warp::get())
+        .and(warp::query::<OwnersQuery>())
+        .and(with_db(database.clone()))
+        .and_then(timed!(
+            handle_get_token_owners::<Client>,
+            chain_name,
+            contract_address,
+            token_id,
+            query,
+            repository
+        )))
+        .or(projects::with_default_project()
+            .and(warp::path!("get-username"))
             .and(warp::post())
-            .and(warp::body::json())
-            .and_then(handle_get_username_by_wallet))
-        .or(warp::path!("fullcollection" / String)
+            .and(warp::body::content_length_limit(USERNAME_LOOKUP_BODY_LIMIT))
+            .and(json_body())
+            .and_then(timed!(handle_get_username_by_wallet, project, body)))
+        .or(projects::with_default_project()
+            .and(warp::path!("fullcollection" / String))
+            .and(warp::get())
+            .and(warp::query::<FullCollectionQuery>())
+            .and(api_keys::has_partner_key())
+            .and(with_db(database.clone()))
+            .and_then(timed_export!(
+                handle_get_user_full_collection::<Client>,
+                project,
+                user_address,
+                query,
+                partner,
+                repository
+            )))
+        .or(projects::with_default_project()
+            .and(warp::path!("user" / "level" / String))
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(handle_get_user_details, project, username, client)))
+        .or(projects::with_default_project()
+            .and(warp::path!("user" / "level" / String / "progress"))
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_user_level_progress,
+                project,
+                username,
+                client
+            )))
+        .or(warp::path!("levels")
+            .and(warp::get())
+            .and_then(timed!(levels::handle_get_levels)))
+        .or(warp::path!("users")
+            .and(warp::get())
+            .and(warp::query::<directory::DirectoryQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(directory::handle_get_users, query, client)))
+        .or(warp::path!("users" / "search")
+            .and(warp::get())
+            .and(warp::query::<user_search::UserSearchQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(user_search::handle_search_users, query, client)))
+        .or(warp::path!("user" / "achievements" / String)
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                achievements::handle_get_user_achievements,
+                username,
+                client
+            )))
+        .or(warp::path!("user" / "sets" / String)
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(sets::handle_get_user_sets, username, client)))
+        .or(projects::with_default_project()
+            .and(warp::path!("user" / "stats" / String))
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                hold_stats::handle_get_user_stats,
+                project,
+                username,
+                client
+            )))
+        .or(warp::path!("user" / "value" / String)
             .and(warp::get())
-            .and(with_db(client.clone()))
-            .and_then(handle_get_user_full_collection))
-        .or(warp::path!("user" / "level" / String)
+            .and(with_db(database.clone()))
+            .and_then(timed!(portfolio::handle_get_user_value, username, client)))
+        .or(warp::path!("user" / "approvals" / String)
             .and(warp::get())
-            .and(with_db(client.clone()))
-            .and_then(handle_get_user_details))
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                approvals::handle_get_user_approvals,
+                address,
+                client
+            )))
         .or(warp::path!("leaderboard")
             .and(warp::get())
-            .and(with_db(client.clone()))
-            .and_then(handler_leaderboard))
+            .and(warp::query::<LeaderboardQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(handler_leaderboard, query, client)))
+        .or(warp::path!("leaderboard" / "meta")
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(score_weights::handle_get_leaderboard_meta, client)))
+        .or(warp::path!("leaderboard" / "movers")
+            .and(warp::get())
+            .and(warp::query::<movers::MoversQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(movers::handle_get_leaderboard_movers, query, client)))
+        .or(warp::path!("leaderboard" / "teams")
+            .and(warp::get())
+            .and(warp::query::<teams::TeamLeaderboardQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(teams::handle_get_team_leaderboard, query, client)))
+        .or(warp::path!("teams")
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(teams::handle_list_teams, client)))
+        .or(warp::path!("leaderboard" / "season" / u32)
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                seasons::handle_get_season_leaderboard,
+                number,
+                client
+            )))
         .or(warp::path!("full")
             .and(warp::get())
-            .and(with_db(client.clone()))
-            .and_then(handle_get_all_afterlife_collections))
-        .with(cors)
+            .and(with_db(database.clone()))
+            .and_then(timed_export!(handle_get_all_afterlife_collections, client)))
+        .or(warp::path!("catalog")
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(catalog::handle_get_catalog, client)))
+        .or(warp::path!("prices")
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(prices::handle_get_prices, client)))
+        .or(projects::with_default_project()
+            .and(warp::path!("activity" / String))
+            .and(warp::get())
+            .and(warp::query::<ActivityQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_activity,
+                project,
+                address_or_username,
+                query,
+                client
+            )))
         .with(warp::reply::with::header(
             "Cache-Control",
             "public, max-age=60",
-        ));
+        ))
+        .boxed();
 
-    warp::serve(routes.recover(handle_custom_rejection))
-        .run(([127, 0, 0, 1], 3030))
-        .await;
+    // Operational endpoints, not part of the versioned API and never deprecated
+    let service_routes = warp::path!("healthz")
+        .and(warp::get())
+        .and_then(timed!(health::handle_healthz))
+        .or(warp::path!("readyz")
+            .and(warp::get())
+            .and(with_database(database.clone()))
+            .and_then(timed!(health::handle_readyz, database)))
+        .or(warp::path!("status" / "sync")
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(handle_get_sync_status, client)))
+        .or(warp::path!("status" / "backfill")
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(handle_get_backfill_status, client)))
+        .or(warp::path!("status" / "data")
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(preflight::handle_get_data_status, client)))
+        .or(warp::path!("bot" / "user" / String)
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(bot::handle_get_bot_user, discord_id, client)))
+        .with(warp::reply::with::header(
+            "Cache-Control",
+            "public, max-age=60",
+        ))
+        .boxed();
+
+    // The same collection, user, activity and leaderboard routes scoped to one project:
+    // /p/{project}/...
+    let project_routes = projects::with_project()
+        .and(
+            contract_params::canonical(
+                warp::path!(String / String / "collection" / ..),
+                database.clone(),
+            )
+            .and(warp::path!(String)),
+        )
+        .and(warp::get())
+        .and(with_db(database.clone()))
+        .and_then(timed!(
+            handle_get_collection_for_address,
+            project,
+            chain_name,
+            contract_address,
+            wallet_address,
+            client
+        ))
+        .or(projects::with_project()
+            .and(contract_params::canonical(
+                warp::path!(String / String / "collection"),
+                database.clone(),
+            ))
+            .and(warp::get())
+            .and(warp::header::optional::<String>("accept"))
+            .and(api_keys::has_partner_key())
+            .and(with_db(database.clone()))
+            .and_then(timed_export!(
+                handle_get_entire_collection,
+                project,
+                chain_name,
+                contract_address,
+                accept,
+                partner,
+                client
+            )))
+        .or(projects::with_project()
+            .and(warp::path!("get-username"))
+            .and(warp::post())
+            .and(warp::body::content_length_limit(USERNAME_LOOKUP_BODY_LIMIT))
+            .and(json_body())
+            .and_then(timed!(handle_get_username_by_wallet, project, body)))
+        .or(projects::with_project()
+            .and(warp::path!("user" / "level" / String))
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(handle_get_user_details, project, username, client)))
+        .or(projects::with_project()
+            .and(warp::path!("user" / "level" / String / "progress"))
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_user_level_progress,
+                project,
+                username,
+                client
+            )))
+        .or(projects::with_project()
+            .and(contract_params::canonical(
+                warp::path!(String / String / "stats"),
+                database.clone(),
+            ))
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_collection_stats,
+                project,
+                chain_name,
+                contract_address,
+                client
+            )))
+        .or(projects::with_project()
+            .and(contract_params::canonical(
+                warp::path!(String / String / "activity"),
+                database.clone(),
+            ))
+            .and(warp::get())
+            .and(warp::query::<CollectionActivityQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_collection_activity,
+                project,
+                chain_name,
+                contract_address,
+                query,
+                client
+            )))
+        .or(projects::with_project()
+            .and(warp::path!("user" / "stats" / String))
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                hold_stats::handle_get_user_stats,
+                project,
+                username,
+                client
+            )))
+        .or(projects::with_project()
+            .and(warp::path!("activity" / String))
+            .and(warp::get())
+            .and(warp::query::<ActivityQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_activity,
+                project,
+                address_or_username,
+                query,
+                client
+            )))
+        .or(projects::with_project()
+            .and(warp::path!("leaderboard"))
+            .and(warp::get())
+            .and(warp::query::<LeaderboardQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_project_leaderboard,
+                project,
+                query,
+                client
+            )))
+        .with(warp::reply::with::header(
+            "Cache-Control",
+            "public, max-age=60",
+        ))
+        .boxed();
+
+    // /v1/... for the default project and /v1/p/{project}/... for the others
+    let v1_routes = warp::path("v1")
+        .and(
+            v1::routes(projects::with_project().boxed(), database.clone()).or(v1::routes(
+                projects::with_default_project().boxed(),
+                database.clone(),
+            )),
+        )
+        .with(warp::reply::with::header(
+            "Cache-Control",
+            "public, max-age=60",
+        ))
+        .boxed();
+
+    // The unprefixed routes predate /v1 and are kept as deprecated shims until clients move
+    let legacy_routes = legacy_routes_enabled()
+        .and(public_routes.or(project_routes))
+        .with(warp::reply::with::header("Deprecation", "true"));
+
+    let media_routes = contract_params::canonical(
+        warp::path!("media" / String / String / ..),
+        database.clone(),
+    )
+    .and(warp::path!(TokenId))
+    .and(warp::get())
+    .and(warp::query::<media::MediaQuery>())
+    .and_then(timed!(
+        media::handle_get_media,
+        chain_name,
+        contract_address,
+        token_id,
+        query
+    ))
+    .with(warp::reply::with::header(
+        "Cache-Control",
+        "public, max-age=2592000",
+    ))
+    .boxed();
+
+    let profile_routes = warp::path!("profile" / String)
+        .and(warp::get())
+        .and(with_db(database.clone()))
+        .and_then(timed!(profiles::handle_get_profile, username, client))
+        .with(warp::reply::with::header(
+            "Cache-Control",
+            "public, max-age=60",
+        ))
+        .boxed();
+
+    // Routes of signed-in users, see auth::user
+    let account_routes = warp::path!("auth" / "nonce")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(ACCOUNT_BODY_LIMIT))
+        .and(json_body())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(auth::handle_create_nonce, body, client))
+        .or(warp::path!("auth" / "login")
+            .and(warp::post())
+            .and(warp::body::content_length_limit(ACCOUNT_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(auth::handle_login, body, client)))
+        .or(warp::path!("auth" / "logout")
+            .and(warp::post())
+            .and(auth::session(database.clone()))
+            .and(warp::query::<auth::LogoutRequest>())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(auth::handle_logout, session, query, client)))
+        .or(warp::path!("user" / "avatar")
+            .and(warp::put())
+            .and(auth::user(database.clone()))
+            .and(warp::body::content_length_limit(ACCOUNT_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                avatars::handle_set_avatar,
+                address,
+                body,
+                client
+            )))
+        .or(warp::path!("user" / "privacy")
+            .and(warp::get())
+            .and(auth::user(database.clone()))
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                profiles::handle_get_privacy_settings,
+                address,
+                client
+            )))
+        .or(warp::path!("user" / "privacy")
+            .and(warp::put())
+            .and(auth::user(database.clone()))
+            .and(warp::body::content_length_limit(ACCOUNT_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                profiles::handle_set_privacy_settings,
+                address,
+                body,
+                client
+            )))
+        .or(warp::path!("user" / "notifications")
+            .and(warp::get())
+            .and(auth::user(database.clone()))
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                notifications::handle_get_notification_preferences,
+                address,
+                client
+            )))
+        .or(warp::path!("user" / "notifications")
+            .and(warp::put())
+            .and(auth::user(database.clone()))
+            .and(warp::body::content_length_limit(ACCOUNT_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                notifications::handle_set_notification_preferences,
+                address,
+                body,
+                client
+            )))
+        .or(warp::path!("user" / "notifications" / "confirm")
+            .and(warp::post())
+            .and(auth::user(database.clone()))
+            .and(warp::body::content_length_limit(ACCOUNT_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                notifications::handle_confirm_notification_email,
+                address,
+                body,
+                client
+            )))
+        .or(warp::path!("user" / "team")
+            .and(warp::get())
+            .and(auth::user(database.clone()))
+            .and(with_db(database.clone()))
+            .and_then(timed!(teams::handle_get_own_team, address, client)))
+        .or(warp::path!("user" / "team")
+            .and(warp::post())
+            .and(auth::user(database.clone()))
+            .and(warp::body::content_length_limit(ACCOUNT_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                teams::handle_create_own_team,
+                address,
+                body,
+                client
+            )))
+        .or(warp::path!("user" / "team")
+            .and(warp::delete())
+            .and(auth::user(database.clone()))
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(teams::handle_leave_team, address, client)))
+        .or(warp::path!("teams" / i32 / "join")
+            .and(warp::post())
+            .and(auth::user(database.clone()))
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                teams::handle_join_team,
+                team_id,
+                address,
+                client
+            )))
+        .with(warp::reply::with::header("Cache-Control", "no-store"))
+        .boxed();
+
+    let admin_routes = warp::path!("admin" / "overview")
+        .and(warp::get())
+        .and(auth::admin_only())
+        .and(with_database(database.clone()))
+        .and_then(timed_export!(health::handle_admin_overview, database))
+        .or(warp::path!("admin" / "webhooks")
+            .and(warp::post())
+            .and(auth::admin_only())
+            .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(webhooks::handle_create_webhook, body, client)))
+        .or(warp::path!("admin" / "webhooks")
+            .and(warp::get())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed!(webhooks::handle_list_webhooks, client)))
+        .or(warp::path!("admin" / "webhooks" / i32)
+            .and(warp::delete())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(webhooks::handle_delete_webhook, id, client)))
+        .or(warp::path!("admin" / "exclusions")
+            .and(warp::post())
+            .and(auth::admin_only())
+            .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                exclusions::handle_create_exclusion,
+                body,
+                client
+            )))
+        .or(warp::path!("admin" / "exclusions")
+            .and(warp::get())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed!(exclusions::handle_list_exclusions, client)))
+        .or(warp::path!("admin" / "exclusions" / i32)
+            .and(warp::delete())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                exclusions::handle_delete_exclusion,
+                id,
+                client
+            )))
+        .or(warp::path!("admin" / "labels" / String)
+            .and(warp::put())
+            .and(auth::admin_only())
+            .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                labels::handle_set_label,
+                address,
+                body,
+                client
+            )))
+        .or(warp::path!("admin" / "labels")
+            .and(warp::get())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed!(labels::handle_list_labels, client)))
+        .or(warp::path!("admin" / "labels" / String)
+            .and(warp::delete())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(labels::handle_delete_label, address, client)))
+        .or(contract_params::canonical(
+            warp::path!("admin" / "rarity" / "recompute" / String / String),
+            database.clone(),
+        )
+        .and(warp::post())
+        .and(auth::admin_only())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(
+            jobs::handle_queue_rarity_recompute,
+            chain,
+            contract_address,
+            client
+        )))
+        .or(contract_params::canonical(
+            warp::path!("admin" / "metadata" / "refresh" / String / String),
+            database.clone(),
+        )
+        .and(warp::post())
+        .and(auth::admin_only())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(
+            jobs::handle_queue_metadata_refresh,
+            chain,
+            contract_address,
+            client
+        )))
+        .or(contract_params::canonical(
+            warp::path!("admin" / "snapshots" / String / String),
+            database.clone(),
+        )
+        .and(warp::post())
+        .and(auth::admin_only())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(
+            jobs::handle_queue_holders_snapshot,
+            chain,
+            contract_address,
+            client
+        )))
+        .or(warp::path!("jobs" / i32)
+            .and(warp::get())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed!(jobs::handle_get_job, id, client)))
+        .or(warp::path!("jobs" / i32 / "download")
+            .and(warp::get())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed_export!(jobs::handle_download_job_result, id, client)))
+        .or(contract_params::canonical(
+            warp::path!("admin" / "contracts" / String / String / "verified"),
+            database.clone(),
+        )
+        .and(warp::put())
+        .and(auth::admin_only())
+        .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+        .and(json_body())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(
+            handle_set_contract_verified,
+            chain_name,
+            contract_address,
+            body,
+            client
+        )))
+        .or(contract_params::canonical(
+            warp::path!("admin" / "contracts" / String / String / "score-weight"),
+            database.clone(),
+        )
+        .and(warp::put())
+        .and(auth::admin_only())
+        .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+        .and(json_body())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(
+            score_weights::handle_set_score_weight,
+            chain_name,
+            contract_address,
+            body,
+            client
+        )))
+        .or(contract_params::canonical(
+            warp::path!("admin" / "contracts" / String / String / "rarity-tiers"),
+            database.clone(),
+        )
+        .and(warp::put())
+        .and(auth::admin_only())
+        .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+        .and(json_body())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(
+            rarity::handle_set_rarity_tiers,
+            chain_name,
+            contract_address,
+            body,
+            client
+        )))
+        .or(contract_params::canonical(
+            warp::path!("admin" / "contracts" / String / String / "rarity-tiers"),
+            database.clone(),
+        )
+        .and(warp::delete())
+        .and(auth::admin_only())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(
+            rarity::handle_reset_rarity_tiers,
+            chain_name,
+            contract_address,
+            client
+        )))
+        .or(contract_params::canonical(
+            warp::path!("admin" / "contracts" / String / String / "archive"),
+            database.clone(),
+        )
+        .and(warp::post())
+        .and(auth::admin_only())
+        .and(with_db(database.clone()))
+        .and_then(timed_write!(
+            jobs::handle_queue_contract_archive,
+            chain,
+            contract_address,
+            client
+        )))
+        .or(warp::path!("admin" / "users" / String / "discord")
+            .and(warp::put())
+            .and(auth::admin_only())
+            .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                bot::handle_link_discord_id,
+                username,
+                body,
+                client
+            )))
+        .or(warp::path!("admin" / "users" / String / "rename")
+            .and(warp::post())
+            .and(auth::admin_only())
+            .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                user_admin::handle_rename_user,
+                username,
+                body,
+                client
+            )))
+        .or(warp::path!("admin" / "api-keys")
+            .and(warp::post())
+            .and(auth::admin_only())
+            .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(api_keys::handle_create_api_key, body, client)))
+        .or(warp::path!("admin" / "api-keys")
+            .and(warp::get())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed!(api_keys::handle_list_api_keys, client)))
+        .or(warp::path!("admin" / "api-keys" / i32)
+            .and(warp::delete())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(api_keys::handle_revoke_api_key, id, client)))
+        .or(warp::path!("admin" / "teams")
+            .and(warp::post())
+            .and(auth::admin_only())
+            .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(teams::handle_admin_create_team, body, client)))
+        .or(warp::path!("admin" / "teams" / i32)
+            .and(warp::delete())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(teams::handle_admin_delete_team, id, client)))
+        .or(warp::path!("admin" / "teams" / i32 / "members" / String)
+            .and(warp::put())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                teams::handle_admin_add_member,
+                team_id,
+                username,
+                client
+            )))
+        .or(warp::path!("admin" / "teams" / i32 / "members" / String)
+            .and(warp::delete())
+            .and(auth::admin_only())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                teams::handle_admin_remove_member,
+                team_id,
+                username,
+                client
+            )))
+        .or(warp::path!("admin" / "users" / String / "merge")
+            .and(warp::post())
+            .and(auth::admin_only())
+            .and(warp::body::content_length_limit(ADMIN_BODY_LIMIT))
+            .and(json_body())
+            .and(with_db(database.clone()))
+            .and_then(timed_write!(
+                user_admin::handle_merge_users,
+                username,
+                body,
+                client
+            )))
+        // Admin responses must never end up in a shared cache
+        .with(warp::reply::with::header("Cache-Control", "no-store"))
+        .boxed();
+
+    // Service and admin routes aren't rate limited, probes and operators must always get in
+    let limited_routes = api_keys::rate_limit().and(
+        v1_routes
+            .or(legacy_routes)
+            .or(media_routes)
+            .or(profile_routes)
+            .or(account_routes),
+    );
+
+    let routes = addresses::validate_path()
+        .and(service_routes.or(admin_routes).or(limited_routes))
+        .with(cors)
+        .map(|reply| Ok::<_, Rejection>(Reply::into_response(reply)))
+        .or_else(|rejection: Rejection| async move { Ok::<_, Rejection>((Err(rejection),)) });
+
+    // Every request gets an id and an access log line, including rejected ones
+    access_log::request_context()
+        .and(routes)
+        .then(access_log::finish)
 }
 
-fn with_db(
-    client: Arc<Client>,
-) -> impl Filter<Extract = (Arc<Client>,), Error = Infallible> + Clone {
-    warp::any().map(move || client.clone())
+/// The owners and full collection routes served from any `CollectionRepository`, without
+/// the canonical redirects and timeouts of `routes`, so their handlers can be driven
+/// against an `InMemoryRepository`
+pub fn repository_routes<R: CollectionRepository + 'static>(
+    repository: Arc<R>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone + Send + Sync + 'static
+{
+    let with_repository = warp::any().map(move || repository.clone());
+
+    let routes = warp::path!(String / String / "owners" / TokenId)
+        .and(warp::get())
+        .and(warp::query::<OwnersQuery>())
+        .and(with_repository.clone())
+        .and_then(handle_get_token_owners::<R>)
+        .map(Reply::into_response)
+        .or(projects::with_default_project()
+            .and(warp::path!("fullcollection" / String))
+            .and(warp::get())
+            .and(warp::query::<FullCollectionQuery>())
+            .and(api_keys::has_partner_key())
+            .and(with_repository)
+            .and_then(handle_get_user_full_collection::<R>)
+            .map(Reply::into_response))
+        .unify()
+        .map(Ok::<_, Rejection>)
+        .or_else(|rejection: Rejection| async move { Ok::<_, Rejection>((Err(rejection),)) });
+
+    access_log::request_context()
+        .and(routes)
+        .then(access_log::finish)
 }
 
-async fn handle_custom_rejection(err: Rejection) -> Result<impl warp::Reply, Infallible> {
-    if let Some(custom_err) = err.find::<CustomReject>() {
-        let error_response = ErrorResponse {
-            message: custom_err.0.clone(),
-        };
-        let json = warp::reply::json(&error_response);
-        return Ok(warp::reply::with_status(
-            json,
-            warp::http::StatusCode::BAD_REQUEST,
-        ));
-    }
+pub(crate) fn is_leaderboard_ready() -> bool {
+    LEADERBOARD_READY.load(Ordering::SeqCst)
+}
 
-    let error_response = ErrorResponse {
-        message: "Unhandled error".to_string(),
-    };
-    let json = warp::reply::json(&error_response);
-    Ok(warp::reply::with_status(
-        json,
-        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-    ))
+// Set AFTERLIFE_DISABLE_LEGACY_ROUTES=true to serve only /v1 and the service endpoints
+fn legacy_routes_enabled() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(|| async {
+            if *LEGACY_ROUTES_DISABLED {
+                Err(warp::reject::not_found())
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
 }
 
-fn build_rarity_map(rarity_data: Result<String, std::io::Error>) -> HashMap<u64, (f64, u64)> {
-    let mut rarity_map: HashMap<u64, (f64, u64)> = HashMap::new();
-    if let Ok(rarity_json) = rarity_data {
-        if let Ok(rarities) = serde_json::from_str::<Vec<Value>>(&rarity_json) {
-            for rarity in rarities {
-                if let Some(rarity_obj) = rarity.as_object() {
-                    if let (Some(token_id), Some(rarity_score), Some(rarity_index)) = (
-                        rarity_obj.get("token_id").and_then(|v| v.as_u64()),
-                        rarity_obj.get("rarity_score").and_then(|v| v.as_f64()),
-                        rarity_obj.get("rarity_index").and_then(|v| v.as_u64()),
-                    ) {
-                        rarity_map.insert(token_id, (rarity_score, rarity_index));
-                    }
-                }
+// The connection is re-established here when dropped, handlers just get a client. GET
+// handlers only read, they are served from the read replica when there is one.
+pub(crate) fn with_db(
+    database: Arc<Database>,
+) -> impl Filter<Extract = (Arc<Client>,), Error = Infallible> + Clone {
+    warp::method().then(move |method: Method| {
+        let database = database.clone();
+        async move {
+            if method == Method::GET || method == Method::HEAD {
+                database.read_client().await
+            } else {
+                database.client().await
             }
         }
-    }
-    rarity_map
+    })
 }
 
-fn build_token_details(
-    token_id: u64,
-    metadata: Result<String, std::io::Error>,
-    rarity_map: &HashMap<u64, (f64, u64)>,
-) -> Option<(u64, Value)> {
-    if let Ok(metadata) = metadata {
-        if let Ok(token_details) = serde_json::from_str::<Value>(&metadata) {
-            if let Some(token_details_map) = token_details.as_object() {
-                let mut filtered_details = HashMap::new();
-                if let Some(description) = token_details_map.get("description") {
-                    filtered_details.insert("description".to_owned(), description.clone());
-                }
-                if let Some(attributes) = token_details_map.get("attributes") {
-                    filtered_details.insert("attributes".to_owned(), attributes.clone());
-                }
-                if let Some(&(rarity_score, rarity_index)) = rarity_map.get(&token_id) {
-                    filtered_details
-                        .insert("rarity_score".to_owned(), json!(rarity_score * 1000.0));
-                    filtered_details.insert("rarity_index".to_owned(), json!(rarity_index));
-                }
-                if let Some(name) = token_details_map.get("name") {
-                    filtered_details.insert("name".to_owned(), name.clone());
-                }
+pub(crate) fn with_database(
+    database: Arc<Database>,
+) -> impl Filter<Extract = (Arc<Database>,), Error = Infallible> + Clone {
+    warp::any().map(move || database.clone())
+}
 
-                return Some((token_id, json!(filtered_details)));
-            }
+fn build_token_details(
+    token_id: TokenId,
+    metadata: Option<&Value>,
+    rarity_map: &RarityMap,
+    tier_thresholds: &TierThresholds,
+) -> Option<(TokenId, Value)> {
+    if let Some(token_details_map) = metadata.and_then(|m| m.as_object()) {
+        let mut filtered_details = HashMap::new();
+        if let Some(description) = token_details_map.get("description") {
+            filtered_details.insert("description".to_owned(), description.clone());
+        }
+        if let Some(attributes) = token_details_map.get("attributes") {
+            filtered_details.insert("attributes".to_owned(), attributes.clone());
+        }
+        if let Some(&(rarity_score, rarity_index, rarity_percentile)) = rarity_map.get(&token_id) {
+            filtered_details.insert("rarity_score".to_owned(), json!(rarity_score * 1000.0));
+            filtered_details.insert("rarity_index".to_owned(), json!(rarity_index));
+            filtered_details.insert("rarity_percentile".to_owned(), json!(rarity_percentile));
+            filtered_details.insert(
+                "rarity_tier".to_owned(),
+                json!(tier_thresholds.tier(rarity_percentile)),
+            );
         }
+        if let Some(name) = token_details_map.get("name") {
+            filtered_details.insert("name".to_owned(), name.clone());
+        }
+
+        return Some((token_id, json!(filtered_details)));
     }
     None
 }
 
+// Floor prices are informational, a marketplace table problem must not fail the response
+pub(crate) async fn load_floor_prices(
+    client: &Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> HashMap<TokenId, f64> {
+    queries::get_token_floor_prices(client, chain_name, contract_address)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "Failed to get floor prices for {} on {}: {}",
+                contract_address, chain_name, e
+            );
+            HashMap::new()
+        })
+}
+
+// Same as floor prices, sales are informational and only exist when sale indexing is enabled
+pub(crate) async fn load_last_sales(
+    client: &Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> HashMap<TokenId, queries::SaleRow> {
+    queries::get_last_sales(client, chain_name, contract_address)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "Failed to get last sales for {} on {}: {}",
+                contract_address, chain_name, e
+            );
+            HashMap::new()
+        })
+}
+
+// Adds floor_price and last_sale to every token's details
+async fn add_market_data(
+    tokens: &mut HashMap<TokenId, Value>,
+    client: &Client,
+    chain_name: &str,
+    contract_address: &str,
+) {
+    let floor_prices = load_floor_prices(client, chain_name, contract_address).await;
+    let last_sales = load_last_sales(client, chain_name, contract_address).await;
+    for (token_id, token_details) in tokens.iter_mut() {
+        token_details["floor_price"] = json!(floor_prices.get(token_id));
+        token_details["last_sale"] = json!(last_sales.get(token_id));
+    }
+}
+
+// Reads the metadata of many tokens, METADATA_BATCH_SIZE per query
+async fn load_tokens_details(
+    metadata_store: &MetadataStore,
+    chain_name: &str,
+    contract_address: &str,
+    token_ids: Vec<TokenId>,
+    rarity_map: &RarityMap,
+) -> HashMap<TokenId, Value> {
+    let tier_thresholds = rarity::tier_thresholds(chain_name, contract_address);
+    let mut details = HashMap::with_capacity(token_ids.len());
+    for batch in token_ids.chunks(METADATA_BATCH_SIZE) {
+        let metadata = metadata_store
+            .tokens_metadata(chain_name, contract_address, batch)
+            .await;
+        details.extend(batch.iter().filter_map(|&token_id| {
+            build_token_details(
+                token_id,
+                metadata.get(&token_id).map(|metadata| &**metadata),
+                rarity_map,
+                &tier_thresholds,
+            )
+        }));
+    }
+    details
+}
+
 async fn handle_get_collection_for_address(
+    project: Arc<Project>,
     chain_name: String,
     contract_address: String,
     wallet_address: String,
     client: Arc<Client>,
 ) -> Result<impl warp::Reply, Rejection> {
-    // Fetch environment variables
-    let path_rarities = env::var("AFTERLIFE_PATH_RARITIES").unwrap();
-    let path_metadata = env::var("AFTERLIFE_PATH_METADATA").unwrap();
-    match queries::get_entire_collection_for_address(
-        &*client,
-        &chain_name,
-        &contract_address,
-        &wallet_address,
+    project.ensure_includes(&chain_name, &contract_address)?;
+    let wallet_address = ens::resolve_param(&wallet_address).await?;
+    // A wallet its user hid from their profile isn't listed here either
+    let hidden = queries::is_hidden_address(&client, &wallet_address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get privacy settings: {}", e)))?;
+    if hidden {
+        return Err(
+            ApiError::NotFound(format!("Unknown user or address {}", wallet_address)).into(),
+        );
+    }
+    let cache_key = format!(
+        "{}{}/{}/collection/{}",
+        project.cache_prefix(),
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase(),
+        wallet_address.to_lowercase()
+    );
+    let response = response_cache::get_or_compute(
+        cache_key,
+        build_collection_for_address(
+            &project,
+            &chain_name,
+            &contract_address,
+            &wallet_address,
+            &client,
+        ),
+    )
+    .await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&*response),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+async fn build_collection_for_address(
+    project: &Project,
+    chain_name: &str,
+    contract_address: &str,
+    wallet_address: &str,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    let balances = queries::get_entire_collection_for_address(
+        client,
+        chain_name,
+        contract_address,
+        wallet_address,
     )
     .await
-    .map_err(|e| format!("Failed to get collection: {}", e))
-    {
-        Ok(balances) => {
-            //println!("Found {} balances for {} on {}", balances.len(), wallet_address, contract_address);
-            let rarity_path = format!(
-                "{}/{}_{}_rarity.json",
-                path_rarities,
-                chain_name,
-                checksum(contract_address.as_str())
-            );
-            let rarity_data = read_file(Path::new(&rarity_path)).await;
-            let rarity_map = build_rarity_map(rarity_data);
-
-            let mut tokens: HashMap<u64, Value> = HashMap::new();
-            for (token_id, balance) in balances {
-                let metadata_path = format!(
-                    "{}/{}/{}/{}.json",
-                    path_metadata,
-                    chain_name,
-                    checksum(contract_address.as_str()),
-                    token_id
-                );
-                let metadata = read_file(Path::new(&metadata_path)).await;
-                if let Some((token_id, mut token_details)) =
-                    build_token_details(token_id, metadata, &rarity_map)
-                {
-                    token_details["balance"] = json!(balance);
-                    tokens.insert(token_id, token_details);
-                }
-            }
+    .map_err(|e| ApiError::Upstream(format!("Failed to get collection: {}", e)))?;
 
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({ "tokens": tokens })),
-                warp::http::StatusCode::OK,
-            ))
+    //println!("Found {} balances for {} on {}", balances.len(), wallet_address, contract_address);
+    let mut staked: HashMap<TokenId, Balance> = HashMap::new();
+    for staked_balance in load_staked_balances(client, Some(&[wallet_address.to_string()])).await {
+        if staked_balance.chain_name.eq_ignore_ascii_case(chain_name)
+            && staked_balance
+                .contract_address
+                .eq_ignore_ascii_case(contract_address)
+        {
+            *staked.entry(staked_balance.token_id).or_default() += &staked_balance.balance;
         }
-        Err(err_str) => Err(warp::reject::custom(CustomReject(err_str))),
     }
+
+    let metadata_store = project.metadata();
+    let rarity_map = metadata_store
+        .rarity_map(chain_name, contract_address)
+        .await;
+
+    let token_ids = balances.keys().chain(staked.keys()).copied().collect();
+    let mut tokens = load_tokens_details(
+        metadata_store,
+        chain_name,
+        contract_address,
+        token_ids,
+        &rarity_map,
+    )
+    .await;
+    for (token_id, token_details) in tokens.iter_mut() {
+        token_details["balance"] = json!(balances.get(token_id).cloned().unwrap_or_default());
+        // Held by a staking contract on the wallet's behalf
+        token_details["staked"] = json!(staked.get(token_id));
+    }
+    add_market_data(&mut tokens, client, chain_name, contract_address).await;
+
+    Ok(json!({ "tokens": tokens }))
 }
 
 async fn handle_get_entire_collection(
+    project: Arc<Project>,
     chain_name: String,
     contract_address: String,
+    accept: Option<String>,
+    partner: bool,
     client: Arc<Client>,
-) -> Result<impl warp::Reply, Rejection> {
-    // Fetch environment variables
-    let path_rarities = env::var("AFTERLIFE_PATH_RARITIES").unwrap();
-    let path_metadata = env::var("AFTERLIFE_PATH_METADATA").unwrap();
-    match queries::get_entire_collection(&*client, &chain_name, &contract_address)
+) -> Result<warp::reply::Response, Rejection> {
+    project.ensure_includes(&chain_name, &contract_address)?;
+    if accept.is_some_and(|accept| accept.contains(NDJSON_CONTENT_TYPE)) {
+        if !partner {
+            return Err(ApiError::Unauthorized(
+                "An API key is required to stream collections".to_string(),
+            )
+            .into());
+        }
+        return Ok(stream_entire_collection(project, chain_name, contract_address, &client).await?);
+    }
+    let cache_key = format!(
+        "{}{}/{}/collection",
+        project.cache_prefix(),
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase()
+    );
+    let response = response_cache::get_or_compute(
+        cache_key,
+        build_entire_collection(&project, &chain_name, &contract_address, &client),
+    )
+    .await?;
+
+    // Same URL as the NDJSON stream, caches must tell the two apart
+    Ok(warp::reply::with_header(
+        warp::reply::json(&*response),
+        warp::http::header::VARY,
+        "Accept",
+    )
+    .into_response())
+}
+
+// One token per line, written a batch of tokens at a time as their metadata is read, so a
+// large collection is never held in memory as a whole. Not cached, unlike the JSON document.
+async fn stream_entire_collection(
+    project: Arc<Project>,
+    chain_name: String,
+    contract_address: String,
+    client: &Client,
+) -> Result<warp::reply::Response, ApiError> {
+    let token_ids = queries::get_entire_collection(client, &chain_name, &contract_address)
         .await
-        .map_err(|e| format!("Failed to get entire collection: {}", e))
-    {
-        Ok(token_ids) => {
-            let rarity_path = format!(
-                "{}/{}_{}_rarity.json",
-                path_rarities,
-                chain_name,
-                checksum(contract_address.as_str())
-            );
-            let rarity_data = fs::read_to_string(&rarity_path).unwrap_or_else(|_| String::new());
-            let rarity_map = build_rarity_map(Ok(rarity_data));
-
-            let tokens: HashMap<u64, Value> = token_ids
-                .into_iter()
-                .filter_map(|token_id| {
-                    let metadata_path = format!(
-                        "{}/{}/{}/{}.json",
-                        path_metadata,
-                        chain_name,
-                        checksum(contract_address.as_str()),
-                        token_id
-                    );
-                    let metadata = fs::read_to_string(&metadata_path);
-                    build_token_details(token_id, metadata, &rarity_map)
-                })
-                .collect();
+        .map_err(|e| ApiError::Upstream(format!("Failed to get entire collection: {}", e)))?;
+    let rarity_map = project
+        .metadata()
+        .rarity_map(&chain_name, &contract_address)
+        .await;
+    let tier_thresholds = rarity::tier_thresholds(&chain_name, &contract_address);
+    let floor_prices = Arc::new(load_floor_prices(client, &chain_name, &contract_address).await);
+    let last_sales = Arc::new(load_last_sales(client, &chain_name, &contract_address).await);
+    let chain_name: Arc<str> = chain_name.into();
+    let contract_address: Arc<str> = contract_address.into();
 
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({ "tokens": tokens })),
-                warp::http::StatusCode::OK,
-            ))
-        }
-        Err(err_str) => Err(warp::reject::custom(CustomReject(err_str))),
+    let batches: Vec<Vec<TokenId>> = token_ids
+        .chunks(METADATA_BATCH_SIZE)
+        .map(<[TokenId]>::to_vec)
+        .collect();
+    // One batch after the other, the lines follow the token ids
+    let lines = stream::iter(batches)
+        .then(move |batch| {
+            let project = project.clone();
+            let chain_name = chain_name.clone();
+            let contract_address = contract_address.clone();
+            let rarity_map = rarity_map.clone();
+            let floor_prices = floor_prices.clone();
+            let last_sales = last_sales.clone();
+            async move {
+                let metadata = project
+                    .metadata()
+                    .tokens_metadata(&chain_name, &contract_address, &batch)
+                    .await;
+                let mut lines = String::new();
+                for token_id in batch {
+                    let Some((_, mut token_details)) = build_token_details(
+                        token_id,
+                        metadata.get(&token_id).map(|metadata| &**metadata),
+                        &rarity_map,
+                        &tier_thresholds,
+                    ) else {
+                        continue;
+                    };
+                    token_details["token_id"] = json!(token_id);
+                    token_details["floor_price"] = json!(floor_prices.get(&token_id));
+                    token_details["last_sale"] = json!(last_sales.get(&token_id));
+                    lines.push_str(&token_details.to_string());
+                    lines.push('\n');
+                }
+                lines
+            }
+        })
+        .map(Ok::<_, Infallible>);
+
+    let mut response = warp::reply::Response::new(warp::hyper::Body::wrap_stream(lines));
+    response.headers_mut().insert(
+        warp::http::header::CONTENT_TYPE,
+        warp::http::HeaderValue::from_static(NDJSON_CONTENT_TYPE),
+    );
+    response.headers_mut().insert(
+        warp::http::header::VARY,
+        warp::http::HeaderValue::from_static("Accept"),
+    );
+    Ok(response)
+}
+
+async fn build_entire_collection(
+    project: &Project,
+    chain_name: &str,
+    contract_address: &str,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    let token_ids = queries::get_entire_collection(client, chain_name, contract_address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get entire collection: {}", e)))?;
+
+    let metadata_store = project.metadata();
+    let rarity_map = metadata_store
+        .rarity_map(chain_name, contract_address)
+        .await;
+
+    let mut tokens = load_tokens_details(
+        metadata_store,
+        chain_name,
+        contract_address,
+        token_ids,
+        &rarity_map,
+    )
+    .await;
+    add_market_data(&mut tokens, client, chain_name, contract_address).await;
+
+    Ok(json!({ "tokens": tokens }))
+}
+
+async fn handle_get_tokens_batch(
+    chain_name: String,
+    contract_address: String,
+    mut token_ids: Vec<TokenId>,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    if token_ids.len() > MAX_BATCH_TOKENS {
+        return Err(ApiError::BadRequest(format!(
+            "At most {} token ids can be requested at once",
+            MAX_BATCH_TOKENS
+        ))
+        .into());
     }
+    token_ids.sort_unstable();
+    token_ids.dedup();
+
+    let rarity_map = METADATA_STORE
+        .rarity_map(&chain_name, &contract_address)
+        .await;
+    let mut tokens = load_tokens_details(
+        &METADATA_STORE,
+        &chain_name,
+        &contract_address,
+        token_ids,
+        &rarity_map,
+    )
+    .await;
+    add_market_data(&mut tokens, &client, &chain_name, &contract_address).await;
+
+    Ok(warp::reply::json(&json!({ "tokens": tokens })).into_response())
 }
 
-async fn handle_get_token_owners(
+async fn handle_get_sales(
     chain_name: String,
     contract_address: String,
-    token_id: u64,
+    query: SalesQuery,
     client: Arc<Client>,
 ) -> Result<impl warp::Reply, Rejection> {
-    match queries::get_token_owners(&*client, &chain_name, &contract_address, token_id).await {
-        Ok(owners) => Ok(warp::reply::with_status(
-            warp::reply::json(&json!(owners)),
-            warp::http::StatusCode::OK,
-        )),
-        Err(_) => Err(warp::reject::custom(CustomReject(
-            "Failed to fetch token owners".to_string(),
-        ))),
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_ACTIVITY_LIMIT)
+        .clamp(1, MAX_ACTIVITY_LIMIT);
+    let after: Option<SaleCursor> = query
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            cursor
+                .parse()
+                .map_err(|_| ApiError::BadRequest(format!("Invalid cursor {}", cursor)))
+        })
+        .transpose()?;
+
+    let sales = queries::get_sales(
+        &client,
+        &chain_name,
+        &contract_address,
+        query.token_id,
+        query.before,
+        after,
+        limit,
+    )
+    .await
+    .map_err(|e| ApiError::Upstream(format!("Failed to fetch sales: {}", e)))?;
+
+    // Cursor for the next page, if this one was full
+    let next_cursor = if sales.len() as i64 == limit {
+        sales.last().map(|sale| SaleCursor::from(sale).to_string())
+    } else {
+        None
+    };
+
+    // Sales are valued at the price of their day, USD values are an extra so a failed
+    // lookup leaves them out rather than failing the page
+    let timestamps: Vec<i64> = sales.iter().filter_map(|sale| sale.timestamp).collect();
+    let usd_prices = queries::get_native_prices_at(&client, &chain_name, &timestamps)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to get USD prices for {}: {}", chain_name, e);
+            HashMap::new()
+        });
+    let sales: Vec<Value> = sales
+        .iter()
+        .map(|sale| {
+            let usd = sale
+                .timestamp
+                .and_then(|timestamp| usd_prices.get(&timestamp).copied());
+            let mut sale_json = json!(sale);
+            sale_json["price_usd"] =
+                json!(prices::native_sale_usd(&sale.currency, &sale.price, usd));
+            sale_json
+        })
+        .collect();
+
+    Ok(warp::reply::json(&json!({
+        "sales": sales,
+        "next_cursor": next_cursor,
+    }))
+    .into_response())
+}
+
+async fn handle_get_collection_stats(
+    project: Arc<Project>,
+    chain_name: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    project.ensure_includes(&chain_name, &contract_address)?;
+    let cache_key = format!(
+        "{}{}/{}/stats",
+        project.cache_prefix(),
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase()
+    );
+    let response = response_cache::get_or_compute(
+        cache_key,
+        build_collection_stats(&project, &chain_name, &contract_address, &client),
+    )
+    .await?;
+
+    Ok(warp::reply::json(&*response))
+}
+
+async fn handle_get_supply_history(
+    chain_name: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!(
+        "{}/{}/supply/history",
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase()
+    );
+    let response = response_cache::get_or_compute(cache_key, async {
+        let history = get_supply_history(&client, &chain_name, &contract_address)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get supply history: {}", e)))?;
+        Ok(json!({
+            "chain": chain_name,
+            "contract_address": contract_address,
+            "bucket_blocks": SUPPLY_HISTORY_BUCKET_BLOCKS,
+            "history": history,
+        }))
+    })
+    .await?;
+
+    Ok(warp::reply::json(&*response))
+}
+
+// "90m", "12h", "7d" or "2w" in seconds
+fn parse_period(period: &str) -> Option<i64> {
+    let unit = period.chars().last()?;
+    let amount = &period[..period.len() - unit.len_utf8()];
+    let unit_seconds = match unit {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => return None,
+    };
+    amount
+        .parse::<i64>()
+        .ok()
+        .filter(|&amount| amount > 0)
+        .and_then(|amount| amount.checked_mul(unit_seconds))
+}
+
+async fn handle_get_collection_activity(
+    project: Arc<Project>,
+    chain_name: String,
+    contract_address: String,
+    query: CollectionActivityQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    project.ensure_includes(&chain_name, &contract_address)?;
+    let window = query.window.as_deref().unwrap_or(DEFAULT_ACTIVITY_WINDOW);
+    let granularity = query
+        .granularity
+        .as_deref()
+        .unwrap_or(DEFAULT_ACTIVITY_GRANULARITY);
+    let window_seconds = parse_period(window)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid window {}", window)))?;
+    let granularity_seconds = parse_period(granularity)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid granularity {}", granularity)))?;
+    if granularity_seconds > window_seconds {
+        return Err(ApiError::BadRequest(format!(
+            "Granularity {} is longer than the window {}",
+            granularity, window
+        ))
+        .into());
+    }
+    // Rounded up, so the window is always covered
+    let periods = (window_seconds + granularity_seconds - 1) / granularity_seconds;
+    if periods > MAX_ACTIVITY_PERIODS {
+        return Err(ApiError::BadRequest(format!(
+            "Window {} at granularity {} is more than {} periods",
+            window, granularity, MAX_ACTIVITY_PERIODS
+        ))
+        .into());
+    }
+
+    let cache_key = format!(
+        "{}{}/{}/activity/{}/{}",
+        project.cache_prefix(),
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase(),
+        window,
+        granularity
+    );
+    let response = response_cache::get_or_compute(cache_key, async {
+        // An unknown contract would otherwise look like one without any transfer
+        let indexed = queries::get_last_processed_block(&client, &chain_name, &contract_address)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get contract: {}", e)))?;
+        if indexed.is_none() {
+            return Err(ApiError::NotFound(format!(
+                "No contract {} on {}",
+                contract_address, chain_name
+            )));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or(0);
+        // The last period is the current one, still in progress
+        let first_period = (now / granularity_seconds - periods + 1) * granularity_seconds;
+        let counted = queries::get_collection_activity(
+            &client,
+            &chain_name,
+            &contract_address,
+            first_period,
+            granularity_seconds,
+        )
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get collection activity: {}", e)))?;
+
+        // Quiet periods are zeros rather than gaps, charts need every bucket
+        let mut counted: HashMap<i64, queries::ActivityPeriod> = counted
+            .into_iter()
+            .map(|period| (period.period_start, period))
+            .collect();
+        let series: Vec<queries::ActivityPeriod> = (0..periods)
+            .map(|n| {
+                let period_start = first_period + n * granularity_seconds;
+                counted
+                    .remove(&period_start)
+                    .unwrap_or(queries::ActivityPeriod {
+                        period_start,
+                        mints: 0,
+                        transfers: 0,
+                        burns: 0,
+                        active_wallets: 0,
+                    })
+            })
+            .collect();
+
+        Ok(json!({
+            "chain": chain_name,
+            "contract_address": contract_address,
+            "window": window,
+            "granularity": granularity,
+            "periods": series,
+        }))
+    })
+    .await?;
+
+    Ok(warp::reply::json(&*response))
+}
+
+async fn build_collection_stats(
+    project: &Project,
+    chain_name: &str,
+    contract_address: &str,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    let contract_name =
+        queries::get_contract_name_from_chain_and_address(client, chain_name, contract_address)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get contract name: {}", e)))?;
+    let token_ids = queries::get_entire_collection(client, chain_name, contract_address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get entire collection: {}", e)))?;
+    let excluded: Vec<String> = project.excluded_addresses().await?.into_iter().collect();
+    let holders = queries::get_holder_count(client, chain_name, contract_address, &excluded)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get holder count: {}", e)))?;
+
+    let consumed = queries::get_consumed_count(client, chain_name, contract_address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get consumed count: {}", e)))?;
+
+    let floor_price = load_floor_prices(client, chain_name, contract_address)
+        .await
+        .into_values()
+        .reduce(f64::min);
+
+    Ok(json!({
+        "chain": chain_name,
+        "contract_address": contract_address,
+        "contract_name": contract_name,
+        "token_count": token_ids.len(),
+        "holders": holders,
+        "consumed": consumed,
+        "floor_price": floor_price,
+    }))
+}
+
+async fn handle_get_token_owners<R: CollectionRepository>(
+    chain_name: String,
+    contract_address: String,
+    token_id: TokenId,
+    query: OwnersQuery,
+    repository: Arc<R>,
+) -> Result<impl warp::Reply, Rejection> {
+    let block = query
+        .block(&*repository, &chain_name, &contract_address)
+        .await?;
+    let excluded = DEFAULT_PROJECT.excluded_addresses().await?;
+    match repository
+        .token_owners(&chain_name, &contract_address, token_id, block)
+        .await
+    {
+        Ok(mut owners) => {
+            owners.retain(|owner| !excluded.contains(&owner.to_lowercase()));
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!(owners)),
+                warp::http::StatusCode::OK,
+            ))
+        }
+        Err(_) => Err(ApiError::Upstream("Failed to fetch token owners".to_string()).into()),
     }
 }
 
 async fn handle_get_username_by_wallet(
-    body: HashMap<String, String>,
+    project: Arc<Project>,
+    body: UsernameLookup,
 ) -> Result<impl warp::Reply, Rejection> {
-    let wallet_address = body
-        .get("address")
-        .ok_or_else(|| warp::reject::custom(CustomReject("Address not provided".to_string())))?;
+    let wallet_address = body.address.trim();
+    if wallet_address.is_empty() {
+        return Err(
+            ApiError::invalid_field("address", "Address must not be empty".to_string()).into(),
+        );
+    }
+    let wallet_address =
+        addresses::normalize_field("address", &ens::resolve_param(wallet_address).await?)?;
 
-    match get_username_or_checksummed_address(wallet_address).await {
+    let users = project.users().await?;
+    match resolve_username_or_checksummed_address(&usernames_by_address(&users), &wallet_address) {
         Ok(Some(result)) => Ok(warp::reply::with_status(
             warp::reply::json(&json!({ "username": result })),
             warp::http::StatusCode::OK,
         )),
-        Ok(None) => Err(warp::reject::custom(CustomReject(
-            "Wallet address not found".to_string(),
-        ))),
-        Err(error_message) => Err(warp::reject::custom(CustomReject(error_message))),
+        Ok(None) => Err(ApiError::NotFound("Wallet address not found".to_string()).into()),
+        Err(error_message) => Err(ApiError::BadRequest(error_message).into()),
     }
 }
 
-async fn handle_get_user_full_collection(
+async fn handle_get_user_full_collection<R: CollectionRepository>(
+    project: Arc<Project>,
     user_address: String,
-    client: Arc<Client>,
+    query: FullCollectionQuery,
+    partner: bool,
+    repository: Arc<R>,
 ) -> Result<impl warp::Reply, Rejection> {
+    let user_address = ens::resolve_param(&user_address).await?;
+    // Collections are per address here, a username would only get an empty one
+    if !addresses::looks_like_address(&user_address) {
+        return Err(ApiError::NotFound(format!("Unknown user or address {}", user_address)).into());
+    }
+    // A wallet its user hid from their profile isn't listed here either
+    let hidden = repository
+        .is_hidden_address(&user_address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get privacy settings: {}", e)))?;
+    if hidden {
+        return Err(ApiError::NotFound(format!("Unknown user or address {}", user_address)).into());
+    }
     println!(
         "Handling get user full collection, user_address: {}",
         user_address
     );
-    match get_user_full_collection(&*client, &user_address).await {
+    match query.format.as_deref() {
+        None | Some("json") => {}
+        Some("csv") if !partner => {
+            return Err(ApiError::Unauthorized(
+                "An API key is required for CSV exports".to_string(),
+            )
+            .into())
+        }
+        Some("csv") => {
+            return Ok(export::full_collection_csv(
+                project.clone(),
+                &*repository,
+                &user_address,
+                query.include_unverified,
+            )
+            .await?)
+        }
+        Some(format) => {
+            return Err(ApiError::BadRequest(format!(
+                "Unsupported format {}, expected json or csv",
+                format
+            ))
+            .into())
+        }
+    }
+    match repository
+        .user_full_collection(&user_address, query.include_unverified)
+        .await
+    {
         Ok(collection) => Ok(warp::reply::json(&collection).into_response()),
-        Err(_) => Err(warp::reject::custom(CustomReject(
-            "Failed to fetch user's full collection".to_string(),
-        ))),
+        Err(_) => {
+            Err(ApiError::Upstream("Failed to fetch user's full collection".to_string()).into())
+        }
     }
 }
 
 async fn handle_get_user_details(
+    project: Arc<Project>,
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!("{}user/level/{}", project.cache_prefix(), username);
+    let response =
+        response_cache::get_or_compute(cache_key, user_details(&project, &username, &client))
+            .await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// Reads the points off the cached /user/level response, so both always agree
+async fn handle_get_user_level_progress(
+    project: Arc<Project>,
     username: String,
     client: Arc<Client>,
 ) -> Result<impl warp::Reply, Rejection> {
-    let user_addresses = get_all_addresses_for_username(&username).await;
+    let cache_key = format!("{}user/level/{}", project.cache_prefix(), username);
+    let details =
+        response_cache::get_or_compute(cache_key, user_details(&project, &username, &client))
+            .await?;
+    let points = details["afterlifepoints"].as_f64().unwrap_or(0.0);
+
+    let mut progress = levels::LEVEL_CURVE.progress(points);
+    progress["username"] = json!(username);
+    Ok(warp::reply::json(&progress).into_response())
+}
+
+// The /user/level document with its computed_at in epoch seconds. The default project's
+// are warmed ahead of requests, the others' and the ones not warmed yet computed now.
+async fn user_details(
+    project: &Project,
+    username: &str,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    if project.id == DEFAULT_PROJECT.id {
+        if let Some(document) = user_details_cache::get(client, username).await {
+            return Ok(document);
+        }
+    }
+    let mut document = build_user_details(project, username, client).await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0);
+    document["computed_at"] = json!(now);
+    Ok(document)
+}
+
+// Balance of each token over all of a user's addresses, which is scored, and over the
+// listed ones, which is shown
+type HeldTokens = HashMap<TokenId, (Balance, Balance)>;
+
+pub(crate) async fn build_user_details(
+    project: &Project,
+    username: &str,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    let users = project.users().await?;
+    let user_addresses = addresses_for_name(&users, username).await?;
+    if user_addresses.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)));
+    }
+    let addresses: Vec<String> = user_addresses.iter().cloned().collect();
+    // Hidden wallets still count, they just aren't listed, and neither are their tokens
+    let visibility = profiles::visibility(client, &users, username, &addresses).await?;
     let mut total_rarity_score: f64 = 0.0;
     let mut collection_scores = HashMap::new();
     let mut all_nfts: HashMap<String, HashMap<String, Vec<_>>> = HashMap::new();
     let mut top_nfts = Vec::new();
 
-    let path_rarities = env::var("AFTERLIFE_PATH_RARITIES").map_err(|_| {
-        warp::reject::custom(CustomReject(
-            "Environment variable AFTERLIFE_PATH_RARITIES not set".to_string(),
-        ))
-    })?;
-    let path_metadata = env::var("AFTERLIFE_PATH_METADATA").unwrap();
-
-    for user_address in &user_addresses {
-        let user_collection = get_user_full_collection(&*client, user_address)
-            .await
-            .map_err(|_| {
-                warp::reject::custom(CustomReject(
-                    "Failed to fetch user's full collection".to_string(),
-                ))
-            })?;
-
+    // (chain, contract, tokens, score multiplier, staked) of every address, then of the
+    // tokens the user has in staking contracts
+    let mut holdings = Vec::new();
+    let user_collections = get_users_full_collections(client, &addresses)
+        .await
+        .map_err(|_| ApiError::Upstream("Failed to fetch user's full collection".to_string()))?;
+    // Summed over the addresses first, a token held by two of them is listed and scored once
+    let mut held: HashMap<(String, String), HashMap<TokenId, (Balance, Balance)>> = HashMap::new();
+    for (address, user_collection) in user_collections {
+        let listed = visibility.lists(&address);
         for (chain, contracts) in user_collection {
             for (contract_address, tokens) in contracts {
-                let contract_name =
-                    get_contract_name_from_chain_and_address(&*client, &chain, &contract_address)
-                        .await
-                        .map_err(|_| {
-                            warp::reject::custom(CustomReject(
-                                "Failed to fetch contract name".to_string(),
-                            ))
-                        })?;
-                let rarity_path = format!(
-                    "{}/{}_{}_rarity.json",
-                    path_rarities,
-                    chain,
-                    checksum(contract_address.as_str())
-                );
-                let rarity_data = read_file(Path::new(&rarity_path)).await;
-                let rarity_map = build_rarity_map(rarity_data);
-                let collection_name = format!("{}_{}", chain, contract_name);
-
+                let contract_tokens = held.entry((chain.clone(), contract_address)).or_default();
                 for (token_id, balance) in tokens {
-                    if let Some((rarity_score, _)) = rarity_map.get(&token_id) {
-                        let metadata_path = format!(
-                            "{}/{}/{}/{}.json",
-                            path_metadata,
-                            chain,
-                            checksum(contract_address.as_str()),
-                            token_id
-                        );
-                        let metadata = read_file(Path::new(&metadata_path)).await;
-                        let token_details = build_token_details(token_id, metadata, &rarity_map);
-                        let mut token_name = "".to_string();
-                        if let Some((_, token_details)) = token_details {
-                            token_name = token_details["name"].as_str().unwrap_or("").to_string();
-                        }
-                        let score = rarity_score * balance as f64;
-                        total_rarity_score += score;
-                        *collection_scores
-                            .entry(collection_name.clone())
-                            .or_insert(0.0) += score;
-                        top_nfts.push((
-                            rarity_score.clone(),
-                            token_id,
-                            contract_address.clone(),
-                            chain.clone(),
-                            token_name.clone(),
-                        ));
-                        let chain_map = all_nfts.entry(chain.clone()).or_default();
-                        let contract_tokens =
-                            chain_map.entry(contract_address.clone()).or_default();
-
-                        contract_tokens.push(json!({
-                        "rarity_score": (rarity_score * 1000.0).round(),
-                        "score": (rarity_score * 1000.0 * (balance as f64)).round(),
-                        "token_id": token_id,
-                        "balance": balance,
-                        "token_name": token_name,
-                        }));
+                    let (total, shown) = contract_tokens.entry(token_id).or_default();
+                    *total += &balance;
+                    if listed {
+                        *shown += &balance;
                     }
                 }
             }
         }
     }
+    for ((chain, contract_address), tokens) in held {
+        holdings.push((chain, contract_address, tokens, 1.0, false));
+    }
+    // Grouped per contract and multiplier, a contract can be staked in several places
+    let mut staked_tokens: HashMap<(String, String, u64), HeldTokens> = HashMap::new();
+    for staked in load_staked_balances(client, Some(&addresses)).await {
+        let (total, shown) = staked_tokens
+            .entry((
+                staked.chain_name,
+                staked.contract_address,
+                staked.multiplier.to_bits(),
+            ))
+            .or_default()
+            .entry(staked.token_id)
+            .or_default();
+        *total += &staked.balance;
+        if visibility.lists(&staked.address) {
+            *shown += &staked.balance;
+        }
+    }
+    for ((chain, contract_address, multiplier), tokens) in staked_tokens {
+        holdings.push((
+            chain,
+            contract_address,
+            tokens,
+            f64::from_bits(multiplier),
+            true,
+        ));
+    }
+
+    // Canonical contracts first, so bridged copies of their tokens are the ones skipped
+    holdings.sort_by_key(|(chain, contract_address, _, _, _)| {
+        collection_groups::priority(chain, contract_address)
+    });
+    let mut bridged_tokens = BridgedTokens::default();
+
+    for (chain, contract_address, tokens, multiplier, staked) in holdings {
+        if !project.includes(&chain, &contract_address) {
+            continue;
+        }
+        let contract_name =
+            get_contract_name_from_chain_and_address(client, &chain, &contract_address)
+                .await
+                .map_err(|_| ApiError::Upstream("Failed to fetch contract name".to_string()))?;
+        let rarity_map = project
+            .metadata()
+            .rarity_map(&chain, &contract_address)
+            .await;
+        let weight = score_weights::factor(&chain, &contract_address, &rarity_map);
+        let tier_thresholds = rarity::tier_thresholds(&chain, &contract_address);
+        let collection_name = format!("{}_{}", chain, contract_name);
+
+        // Scored tokens, the metadata of the listed ones is then read in one go
+        let scored: Vec<(TokenId, Balance, Balance, f64)> = tokens
+            .into_iter()
+            .filter(|(token_id, _)| bridged_tokens.first_seen(&chain, &contract_address, *token_id))
+            .filter_map(|(token_id, (balance, shown))| {
+                rarity_map
+                    .get(&token_id)
+                    .map(|&(rarity_score, _, _)| (token_id, balance, shown, rarity_score))
+            })
+            .collect();
+        let token_ids: Vec<TokenId> = scored
+            .iter()
+            .filter(|(_, _, shown, _)| !shown.is_zero())
+            .map(|(token_id, _, _, _)| *token_id)
+            .collect();
+        let metadata = project
+            .metadata()
+            .tokens_metadata(&chain, &contract_address, &token_ids)
+            .await;
+
+        for (token_id, balance, shown, rarity_score) in scored {
+            let score = rarity_score * balance.to_f64() * multiplier * weight;
+            total_rarity_score += score;
+            *collection_scores
+                .entry(collection_name.clone())
+                .or_insert(0.0) += score;
+            if shown.is_zero() {
+                continue;
+            }
+            let token_details = build_token_details(
+                token_id,
+                metadata.get(&token_id).map(|metadata| &**metadata),
+                &rarity_map,
+                &tier_thresholds,
+            );
+            let mut token_name = "".to_string();
+            if let Some((_, token_details)) = token_details {
+                token_name = token_details["name"].as_str().unwrap_or("").to_string();
+            }
+            top_nfts.push((
+                rarity_score,
+                token_id,
+                contract_address.clone(),
+                chain.clone(),
+                token_name.clone(),
+            ));
+            let chain_map = all_nfts.entry(chain.clone()).or_default();
+            let contract_tokens = chain_map.entry(contract_address.clone()).or_default();
+
+            contract_tokens.push(json!({
+                "rarity_score": (rarity_score * 1000.0).round(),
+                "score": (rarity_score * shown.to_f64() * multiplier * weight * 1000.0).round(),
+                "token_id": token_id,
+                "balance": shown,
+                "token_name": token_name,
+                "staked": staked,
+            }));
+        }
+    }
 
     // Sort by score in descending order and take the top 10 NFTs
     top_nfts.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
@@ -428,20 +2261,59 @@ async fn handle_get_user_details(
 
     // Process other data as before
     total_rarity_score = (total_rarity_score * 1000.0).round();
-    let addresses: Vec<String> = user_addresses.into_iter().collect();
     let mut collections: Vec<_> = collection_scores
         .into_iter()
         .map(|(k, v)| (k, (v * 1000.0).round()))
         .collect();
     collections.sort_by(|a, b| a.0.cmp(&b.0));
 
+    // Tokens the user sent to sink addresses, chain -> contract -> count
+    let mut consumed: HashMap<String, HashMap<String, i64>> = HashMap::new();
+    let transfer_counts = queries::get_transfer_counts_for_addresses(client, &addresses)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get transfer counts: {}", e)))?;
+    for counts in transfer_counts {
+        if counts.consumed > 0 && project.includes(&counts.chain_name, &counts.contract_address) {
+            consumed
+                .entry(counts.chain_name)
+                .or_default()
+                .insert(counts.contract_address, counts.consumed);
+        }
+    }
+
+    // Only registered users pick avatars, an address or ENS name just has none
+    let avatar = avatars::load_avatar(client, username).await;
+
+    // Lifetime points are only stored for the default project, and never fall below the
+    // current points
+    let lifetime_points = if project.id == DEFAULT_PROJECT.id {
+        let stored = queries::get_lifetime_points(client, username)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to get lifetime points of {}: {}", username, e);
+                None
+            });
+        Some(stored.map_or(total_rarity_score, |points| points.max(total_rarity_score)))
+    } else {
+        None
+    };
+
+    let listed_addresses: Vec<&String> = addresses
+        .iter()
+        .filter(|address| !visibility.is_hidden(address))
+        .collect();
+
     // Construct final JSON response including top NFTs
     let response = json!({
         "username": username,
-        "addresses": addresses,
+        "addresses": listed_addresses,
+        "avatar": avatar,
         "afterlifepoints": total_rarity_score,
-        "level": backend::usernames::points_to_level(total_rarity_score as i32),
+        "level": levels::points_to_level(total_rarity_score as i32),
+        "lifetime_points": lifetime_points,
+        "lifetime_level": lifetime_points.map(|points| levels::points_to_level(points as i32)),
         "collection_scores": collections.into_iter().collect::<HashMap<_, _>>(),
+        "consumed": consumed,
         "all_nfts": all_nfts,
         "top_nfts": top_nfts.into_iter().map(|(rarity_score, token_id, contract_address, chain, name)| json!({
             "rarity_score": (rarity_score * 1000.0).round(), // round to nearest integer
@@ -452,14 +2324,10 @@ async fn handle_get_user_details(
         })).collect::<Vec<_>>(),
     });
 
-    Ok(warp::reply::json(&response).into_response())
+    Ok(response)
 }
 
-async fn handler_leaderboard(client: Arc<Client>) -> Result<impl Reply, Rejection> {
-    // Retrieve the precomputed leaderboard from the cache.
-    let leaderboard = get_or_update_all_users_collections(&*client, false).await?;
-
-    // Convert the leaderboard HashMap into a JSON value.
+fn leaderboard_to_json(leaderboard: LeaderboardType) -> Value {
     let mut json_leaderboard = Map::new();
     for (username_or_addr, score) in leaderboard {
         json_leaderboard.insert(
@@ -467,124 +2335,713 @@ async fn handler_leaderboard(client: Arc<Client>) -> Result<impl Reply, Rejectio
             Value::Number(Number::from_f64(score).expect("Invalid score")),
         );
     }
+    Value::Object(json_leaderboard)
+}
+
+async fn handler_leaderboard(
+    query: LeaderboardQuery,
+    client: Arc<Client>,
+) -> Result<impl Reply, Rejection> {
+    let response = match query.metric {
+        ScoreMetric::Current => {
+            response_cache::get_or_compute(LEADERBOARD_CACHE_KEY.to_string(), async {
+                // Retrieve the precomputed leaderboard from the cache.
+                let leaderboard = get_or_update_all_users_collections(&client, false).await?;
+
+                Ok(leaderboard_to_json(leaderboard))
+            })
+            .await?
+        }
+        ScoreMetric::Lifetime => {
+            response_cache::get_or_compute(LIFETIME_LEADERBOARD_CACHE_KEY.to_string(), async {
+                let leaderboard = lifetime_leaderboard(&client).await?;
+                Ok(leaderboard_to_json(leaderboard))
+            })
+            .await?
+        }
+    };
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// The stored lifetime points of the default project, raised to the current points where
+// those are higher. Computed here while the leader hasn't stored any.
+async fn lifetime_leaderboard(client: &Client) -> Result<LeaderboardType, ApiError> {
+    let stored = queries::get_lifetime_scores(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get lifetime scores: {}", e)))?;
+    if stored.is_empty() {
+        return compute_leaderboard(DEFAULT_PROJECT.clone(), client, ScoreMetric::Lifetime).await;
+    }
+
+    let mut leaderboard = stored;
+    for (name, points) in get_or_update_all_users_collections(client, false).await? {
+        let lifetime = leaderboard.entry(name).or_insert(points);
+        *lifetime = lifetime.max(points);
+    }
+    Ok(leaderboard
+        .into_iter()
+        .filter(|(name, points)| *points > 0.0 && !DEFAULT_PROJECT.is_excluded(name))
+        .collect())
+}
+
+// Project leaderboards aren't precomputed, they live in the response cache only
+async fn handle_get_project_leaderboard(
+    project: Arc<Project>,
+    query: LeaderboardQuery,
+    client: Arc<Client>,
+) -> Result<impl Reply, Rejection> {
+    let cache_key = match query.metric {
+        ScoreMetric::Current => format!("{}{}", project.cache_prefix(), LEADERBOARD_CACHE_KEY),
+        ScoreMetric::Lifetime => format!(
+            "{}{}",
+            project.cache_prefix(),
+            LIFETIME_LEADERBOARD_CACHE_KEY
+        ),
+    };
+    let response = response_cache::get_or_compute(cache_key, async {
+        let leaderboard = compute_leaderboard(project.clone(), &client, query.metric).await?;
+        Ok(leaderboard_to_json(leaderboard))
+    })
+    .await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// Keep serving the last known exclusions, labels, delegations, score weights and rarity
+// tiers if the tables can't be read
+async fn reload_mirrors(client: &Client) {
+    if let Err(e) = exclusions::reload(client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = labels::reload(client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = delegations::reload(client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = score_weights::reload(client).await {
+        eprintln!("{:?}", e);
+    }
+    if let Err(e) = rarity::reload(client).await {
+        eprintln!("{:?}", e);
+    }
+}
 
-    let json_response = Value::Object(json_leaderboard);
-    Ok(warp::reply::json(&json_response).into_response())
+fn set_leaderboard(cache: &mut Option<LeaderboardType>, leaderboard: LeaderboardType) {
+    *cache = Some(leaderboard);
+    LEADERBOARD_READY.store(true, Ordering::SeqCst);
+    // Drop the serialized copies so the next request picks up the fresh scores
+    cache_events::invalidate_leaderboards();
 }
 
+/// The default project's leaderboard. A forced update computes it and stores it for the
+/// other API replicas; otherwise it's only computed here when no replica stored one yet.
 pub async fn get_or_update_all_users_collections(
     client: &Client,
     force_update: bool,
-) -> Result<LeaderboardType, Rejection> {
+) -> Result<LeaderboardType, ApiError> {
     let mut cache = ALL_USERS_LEADERBOARD_CACHE.lock().await;
 
-    if cache.is_none() || force_update {
-        let path_rarities = env::var("AFTERLIFE_PATH_RARITIES")
-            .expect("Expected AFTERLIFE_PATH_RARITIES to be set");
-        let all_users_collections = match get_all_users_collections(client).await {
-            Ok(collections) => collections,
-            Err(_) => {
-                return Err(warp::reject::custom(CustomReject(
-                    "Failed to fetch collections for all users".to_string(),
-                )))
+    if force_update {
+        reload_mirrors(client).await;
+        let leaderboard =
+            compute_leaderboard(DEFAULT_PROJECT.clone(), client, ScoreMetric::Current).await?;
+        record_score_snapshot(client, &leaderboard).await;
+        record_lifetime_scores(client).await;
+        match queries::store_leaderboard(client, &DEFAULT_PROJECT.id, &leaderboard).await {
+            Ok(computed_at) => LEADERBOARD_COMPUTED_AT.store(computed_at, Ordering::SeqCst),
+            Err(e) => eprintln!("Failed to store leaderboard: {}", e),
+        }
+        set_leaderboard(&mut cache, leaderboard);
+    } else if cache.is_none() {
+        reload_mirrors(client).await;
+        let stored = queries::get_stored_leaderboard(client, &DEFAULT_PROJECT.id, 0)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to read stored leaderboard: {}", e);
+                None
+            });
+        let leaderboard = match stored {
+            Some((leaderboard, computed_at)) => {
+                LEADERBOARD_COMPUTED_AT.store(computed_at, Ordering::SeqCst);
+                leaderboard
+            }
+            // The client may be a read replica, nothing is written from here
+            None => {
+                compute_leaderboard(DEFAULT_PROJECT.clone(), client, ScoreMetric::Current).await?
             }
         };
+        set_leaderboard(&mut cache, leaderboard);
+    }
+
+    cache
+        .clone()
+        .ok_or_else(|| ApiError::Internal("Leaderboard cache is not available".to_string()))
+}
+
+/// Replicas that don't lead the refresh pick up the leader's last stored leaderboard
+pub async fn sync_leaderboard(client: &Client) -> Result<(), ApiError> {
+    reload_mirrors(client).await;
+    let stored = queries::get_stored_leaderboard(
+        client,
+        &DEFAULT_PROJECT.id,
+        LEADERBOARD_COMPUTED_AT.load(Ordering::SeqCst),
+    )
+    .await
+    .map_err(|e| ApiError::Upstream(format!("Failed to read stored leaderboard: {}", e)))?;
+    if let Some((leaderboard, computed_at)) = stored {
+        let mut cache = ALL_USERS_LEADERBOARD_CACHE.lock().await;
+        LEADERBOARD_COMPUTED_AT.store(computed_at, Ordering::SeqCst);
+        set_leaderboard(&mut cache, leaderboard);
+    }
+    Ok(())
+}
 
-        let mut tasks = Vec::new();
+// Score history only feeds the movers endpoint, failing to record it must not fail the update
+async fn record_score_snapshot(client: &Client, leaderboard: &LeaderboardType) {
+    let scores: Vec<(String, f64, i32)> = rank_leaderboard(leaderboard.clone())
+        .into_iter()
+        .map(|(name, points, rank)| (name, points, rank as i32))
+        .collect();
+    match queries::insert_score_snapshot(client, &scores, SCORE_SNAPSHOT_INTERVAL_SECONDS).await {
+        Ok(true) => println!("Recorded leaderboard snapshot of {} users", scores.len()),
+        Ok(false) => {}
+        Err(e) => eprintln!("Failed to record leaderboard snapshot: {}", e),
+    }
+}
+
+// Like the snapshots, lifetime points failing to update must not fail the leaderboard update
+async fn record_lifetime_scores(client: &Client) {
+    let result =
+        match compute_leaderboard(DEFAULT_PROJECT.clone(), client, ScoreMetric::Lifetime).await {
+            Ok(lifetime) => queries::store_lifetime_scores(client, &lifetime)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(format!("{:?}", e)),
+        };
+    if let Err(e) = result {
+        eprintln!("Failed to record lifetime scores: {}", e);
+    }
+}
 
-        for (user_address, user_collection) in all_users_collections {
-            let path_rarities = path_rarities.clone();
-            let user_address = user_address.clone();
+/// (name, points, rank) from the highest score down, ties broken by name.
+/// Equal scores share a rank.
+pub(crate) fn rank_leaderboard(leaderboard: LeaderboardType) -> Vec<(String, f64, usize)> {
+    let mut scores: Vec<(String, f64)> = leaderboard.into_iter().collect();
+    scores.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.0.cmp(&b.0))
+    });
 
-            let task = task::spawn(async move {
-                let username_or_addr = get_username_or_checksummed_address(&user_address)
-                    .await
-                    .unwrap_or(Some(user_address.clone()))
-                    .unwrap_or_default();
+    let mut ranked: Vec<(String, f64, usize)> = Vec::with_capacity(scores.len());
+    for (position, (name, points)) in scores.into_iter().enumerate() {
+        let rank = match ranked.last() {
+            Some((_, previous, rank)) if *previous == points => *rank,
+            _ => position + 1,
+        };
+        ranked.push((name, points, rank));
+    }
+    ranked
+}
 
-                if EXCLUDED_USERS.contains(&username_or_addr.as_str()) {
-                    return Ok::<_, Rejection>((username_or_addr, 0.0));
-                }
+// The default project's leaderboard is precomputed, other projects are scored on demand
+pub(crate) async fn leaderboard_for(
+    project: &Arc<Project>,
+    client: &Client,
+) -> Result<LeaderboardType, ApiError> {
+    if Arc::ptr_eq(project, &DEFAULT_PROJECT) {
+        get_or_update_all_users_collections(client, false).await
+    } else {
+        compute_leaderboard(project.clone(), client, ScoreMetric::Current).await
+    }
+}
 
-                let mut total_rarity_score: f64 = 0.0;
+// Scores every holder of the project's contracts, keyed by username or checksummed address.
+// Lifetime scores count the peak balance of every token instead of the current one.
+async fn compute_leaderboard(
+    project: Arc<Project>,
+    client: &Client,
+    metric: ScoreMetric,
+) -> Result<LeaderboardType, ApiError> {
+    let usernames = usernames_by_address(&project.users().await?);
+    let all_users_collections = match metric {
+        ScoreMetric::Current => get_all_users_collections(client, true).await,
+        ScoreMetric::Lifetime => get_all_users_peak_collections(client, &usernames).await,
+    };
+    let all_users_collections = match all_users_collections {
+        Ok(collections) => collections,
+        Err(_) => {
+            return Err(ApiError::Upstream(
+                "Failed to fetch collections for all users".to_string(),
+            ))
+        }
+    };
+    let staking_addresses = load_staking_addresses(client).await;
+    let burn_addresses = queries::get_all_burn_addresses(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get burn addresses: {}", e)))?;
+
+    // Peak balances already count the tokens depositors held before staking them
+    let staked_balances = match metric {
+        ScoreMetric::Current => load_staked_balances(client, None).await,
+        ScoreMetric::Lifetime => Vec::new(),
+    };
+    let mut staked_by_address: HashMap<String, Vec<StakedBalance>> = HashMap::new();
+    for staked in staked_balances {
+        staked_by_address
+            .entry(staked.address.clone())
+            .or_default()
+            .push(staked);
+    }
+    let mut holders = Vec::with_capacity(all_users_collections.len());
+    for (user_address, mut user_collection) in all_users_collections {
+        // What a contract's burn addresses hold of it is burned, not held
+        for (chain, contracts) in user_collection.iter_mut() {
+            contracts.retain(|contract_address, _| {
+                let key = (chain.to_lowercase(), contract_address.to_lowercase());
+                !burn_addresses
+                    .get(&key)
+                    .is_some_and(|burn_addresses| is_burn(&user_address, burn_addresses))
+            });
+        }
+        let staked = staked_by_address
+            .remove(&user_address.to_lowercase())
+            .unwrap_or_default();
+        holders.push((user_address, user_collection, staked));
+    }
+    // Depositors who hold nothing else
+    holders.extend(
+        staked_by_address
+            .into_iter()
+            .map(|(address, staked)| (address, HashMap::new(), staked)),
+    );
+
+    // Scored per user rather than per address, so a user holding both copies of a bridged
+    // token only counts it once
+    let mut users: HashMap<String, Vec<_>> = HashMap::new();
+    for (user_address, user_collection, staked) in holders {
+        if staking_addresses.contains(&user_address.to_lowercase()) {
+            continue;
+        }
+        let username_or_addr = resolve_username_or_checksummed_address(&usernames, &user_address)
+            .unwrap_or(Some(user_address.clone()))
+            .unwrap_or_default();
+        users
+            .entry(username_or_addr)
+            .or_default()
+            .push((user_collection, staked));
+    }
+
+    let mut tasks = Vec::new();
+
+    for (username_or_addr, user_holdings) in users {
+        let project = project.clone();
+
+        let task = task::spawn(async move {
+            if project.is_excluded(&username_or_addr) {
+                return Ok::<_, ApiError>((username_or_addr, 0.0));
+            }
 
+            // (chain, contract, tokens, score multiplier)
+            let mut holdings = Vec::new();
+            for (user_collection, staked) in user_holdings {
                 for (chain, contracts) in user_collection {
                     for (contract_address, tokens) in contracts {
-                        let rarity_path = format!(
-                            "{}/{}_{}_rarity.json",
-                            path_rarities,
-                            chain,
-                            checksum(contract_address.as_str())
-                        );
-
-                        let rarity_data = read_file(Path::new(&rarity_path)).await;
-                        let rarity_map = build_rarity_map(rarity_data);
-
-                        for (token_id, balance) in tokens {
-                            if let Some((rarity_score, _)) = rarity_map.get(&token_id) {
-                                total_rarity_score += rarity_score * balance as f64;
-                            }
-                        }
+                        holdings.push((chain.clone(), contract_address, tokens, 1.0));
                     }
                 }
-
-                total_rarity_score = (total_rarity_score * 1000.0).round();
-
-                Ok::<_, Rejection>((username_or_addr, total_rarity_score))
+                // Tokens in staking contracts score for their depositors, with the
+                // contract's multiplier
+                for staked in staked {
+                    let tokens = HashMap::from([(staked.token_id, staked.balance)]);
+                    holdings.push((
+                        staked.chain_name,
+                        staked.contract_address,
+                        tokens,
+                        staked.multiplier,
+                    ));
+                }
+            }
+            holdings.sort_by_key(|(chain, contract_address, _, _)| {
+                collection_groups::priority(chain, contract_address)
             });
 
-            tasks.push(task);
-        }
+            let mut total_rarity_score: f64 = 0.0;
+            let mut bridged_tokens = BridgedTokens::default();
 
-        let mut leaderboard: LeaderboardType = HashMap::new();
-        let results = try_join_all(tasks)
-            .await
-            .map_err(|e| warp::reject::custom(CustomReject(format!("Task join error: {}", e))))?;
-
-        for task_result in results {
-            match task_result {
-                Ok((username_or_addr, score)) => {
-                    leaderboard
-                        .entry(username_or_addr)
-                        .and_modify(|e| *e += score) // Add to the existing score.
-                        .or_insert(score); // Insert if it does not exist.
+            for (chain, contract_address, tokens, multiplier) in holdings {
+                if !project.includes(&chain, &contract_address) {
+                    continue;
                 }
-                Err(e) => {
-                    return Err(e);
+                let rarity_map = project
+                    .metadata()
+                    .rarity_map(&chain, &contract_address)
+                    .await;
+                let weight = score_weights::factor(&chain, &contract_address, &rarity_map);
+
+                for (token_id, balance) in tokens {
+                    if !bridged_tokens.first_seen(&chain, &contract_address, token_id) {
+                        continue;
+                    }
+                    if let Some((rarity_score, _, _)) = rarity_map.get(&token_id) {
+                        total_rarity_score += rarity_score * balance.to_f64() * multiplier * weight;
+                    }
                 }
             }
-        }
 
-        let filtered_leaderboard = leaderboard
-            .into_iter()
-            .filter(|(username_or_addr, score)| {
-                *score > 0.0
-                    && !EXCLUDED_USERS
-                        .iter()
-                        .any(|&excluded| excluded.eq_ignore_ascii_case(username_or_addr))
-                    && !username_or_addr.eq_ignore_ascii_case(ZERO_ADDRESS)
-                    && !username_or_addr.eq_ignore_ascii_case(DEAD_ADDRESS)
-            })
-            .collect::<LeaderboardType>();
+            total_rarity_score = (total_rarity_score * 1000.0).round();
 
-        *cache = Some(filtered_leaderboard);
+            Ok::<_, ApiError>((username_or_addr, total_rarity_score))
+        });
+
+        tasks.push(task);
     }
 
-    cache.clone().ok_or_else(|| {
-        warp::reject::custom(CustomReject(
-            "Leaderboard cache is not available".to_string(),
-        ))
-    })
+    let mut leaderboard: LeaderboardType = HashMap::new();
+    let results = try_join_all(tasks)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Task join error: {}", e)))?;
+
+    for task_result in results {
+        let (username_or_addr, score) = task_result?;
+        leaderboard.insert(username_or_addr, score);
+    }
+
+    Ok(leaderboard
+        .into_iter()
+        .filter(|(username_or_addr, score)| *score > 0.0 && !project.is_excluded(username_or_addr))
+        .collect::<LeaderboardType>())
 }
 
 pub async fn handle_get_all_afterlife_collections(
     client: Arc<Client>,
 ) -> Result<impl warp::Reply, Rejection> {
-    let all_users_collections = get_all_users_collections(&client).await.map_err(|_| {
-        warp::reject::custom(CustomReject(
-            "Failed to fetch collections for all users".to_string(),
-        ))
-    })?;
+    let all_users_collections = get_all_users_collections(&client, false)
+        .await
+        .map_err(|_| ApiError::Upstream("Failed to fetch collections for all users".to_string()))?;
 
     Ok(warp::reply::json(&all_users_collections).into_response())
 }
+
+async fn handle_get_sync_status(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let contracts = queries::get_sync_status(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get sync status: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({ "contracts": contracts })).into_response())
+}
+
+async fn handle_set_contract_verified(
+    chain_name: String,
+    contract_address: String,
+    body: ContractVerification,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let updated =
+        queries::set_contract_verified(&client, &chain_name, &contract_address, body.verified)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to update contract: {}", e)))?;
+    if !updated {
+        return Err(ApiError::NotFound(format!(
+            "No contract {} on {}",
+            contract_address, chain_name
+        ))
+        .into());
+    }
+    response_cache::invalidate_all();
+
+    Ok(warp::reply::json(&json!({
+        "chain": chain_name,
+        "contract_address": contract_address,
+        "verified": body.verified,
+    }))
+    .into_response())
+}
+
+async fn handle_get_backfill_status(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let backfills = queries::get_backfill_status(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get backfill status: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({ "backfills": backfills })).into_response())
+}
+
+async fn handle_get_activity(
+    project: Arc<Project>,
+    address_or_username: String,
+    query: ActivityQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = project.users().await?;
+    let addresses: Vec<String> = addresses_for_name(&users, &address_or_username)
+        .await?
+        .into_iter()
+        .collect();
+    if addresses.is_empty() {
+        return Err(
+            ApiError::NotFound(format!("Unknown user or address {}", address_or_username)).into(),
+        );
+    }
+    let visibility =
+        profiles::visibility(&client, &users, &address_or_username, &addresses).await?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_ACTIVITY_LIMIT)
+        .clamp(1, MAX_ACTIVITY_LIMIT);
+    let after = match &query.cursor {
+        Some(cursor) => Some(
+            cursor
+                .parse()
+                .map_err(|_| ApiError::BadRequest(format!("Invalid cursor {}", cursor)))?,
+        ),
+        None => query.before.map(ActivityCursor::before),
+    };
+    // A user's hidden wallets are left out, and so are the transfers with them
+    let addresses: Vec<String> = addresses
+        .into_iter()
+        .filter(|address| visibility.lists(address))
+        .collect();
+    let activity = if addresses.is_empty() {
+        Vec::new()
+    } else {
+        build_activity(&client, &project, &addresses, after, limit).await?
+    };
+
+    // Cursor for the next page, if this one was full, before the hidden transfers are dropped
+    let next_cursor = if activity.len() as i64 == limit {
+        activity.last().and_then(|entry| {
+            Some(
+                ActivityCursor {
+                    timestamp: entry["timestamp"].as_i64().unwrap_or(0),
+                    block_number: entry["block_number"].as_i64()? as i32,
+                    log_index: entry["log_index"].as_i64()? as i32,
+                }
+                .to_string(),
+            )
+        })
+    } else {
+        None
+    };
+
+    Ok(warp::reply::json(&json!({
+        "activity": profiles::without_hidden_transfers(activity, &visibility.hidden_addresses),
+        "next_cursor": next_cursor,
+    }))
+    .into_response())
+}
+
+/// Transfers in and out of `addresses` on the project's contracts, newest first, as listed
+/// by /activity
+pub(crate) async fn build_activity(
+    client: &Client,
+    project: &Project,
+    addresses: &[String],
+    after: Option<ActivityCursor>,
+    limit: i64,
+) -> Result<Vec<Value>, ApiError> {
+    let addresses_lowercase: Vec<String> = addresses.iter().map(|a| a.to_lowercase()).collect();
+    let rows = queries::get_activity_for_addresses(
+        client,
+        addresses,
+        project.contracts().as_deref(),
+        after,
+        limit,
+    )
+    .await
+    .map_err(|e| ApiError::Upstream(format!("Failed to fetch activity: {}", e)))?;
+
+    let mut activity = Vec::with_capacity(rows.len());
+    for row in rows {
+        let kind = if is_mint(&row.from_address) {
+            "mint"
+        } else if is_burn(&row.to_address, &row.burn_addresses) {
+            "burn"
+        } else if is_sink(&row.to_address, &row.sink_addresses) {
+            "consume"
+        } else if addresses_lowercase.contains(&row.to_address.to_lowercase()) {
+            "in"
+        } else {
+            "out"
+        };
+
+        let mut tokens = Vec::with_capacity(row.ids.len());
+        for (&token_id, value) in row.ids.iter().zip(row.values.iter()) {
+            let token_name = METADATA_STORE
+                .token_metadata(&row.chain_name, &row.contract_address, token_id)
+                .await
+                .and_then(|metadata| metadata["name"].as_str().map(str::to_string));
+            tokens.push(json!({
+                "token_id": token_id,
+                "value": value,
+                "token_name": token_name,
+            }));
+        }
+
+        activity.push(json!({
+            "type": kind,
+            "chain": row.chain_name,
+            "contract_address": row.contract_address,
+            "contract_name": row.contract_name,
+            "from": row.from_address,
+            "from_label": labels::label_for(&row.from_address),
+            "to": row.to_address,
+            "to_label": labels::label_for(&row.to_address),
+            "tokens": tokens,
+            "block_number": row.block_number,
+            "log_index": row.log_index,
+            "transaction_hash": row.transaction_hash,
+            "timestamp": row.block_timestamp,
+        }));
+    }
+
+    Ok(activity)
+}
+
+async fn handle_get_token_provenance(
+    chain_name: String,
+    contract_address: String,
+    token_id: TokenId,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!(
+        "{}/{}/token/{}/provenance",
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase(),
+        token_id
+    );
+    let response = response_cache::get_or_compute(
+        cache_key,
+        build_token_provenance(&chain_name, &contract_address, token_id, &client),
+    )
+    .await?;
+
+    Ok(warp::reply::json(&*response))
+}
+
+async fn build_token_provenance(
+    chain_name: &str,
+    contract_address: &str,
+    token_id: TokenId,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    let transfers = queries::get_token_transfers(client, chain_name, contract_address, token_id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get token transfers: {}", e)))?;
+    if transfers.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "Token {} not found on {} {}",
+            token_id, chain_name, contract_address
+        )));
+    }
+
+    let burn_addresses = queries::get_contract_burn_addresses(client, chain_name, contract_address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get burn addresses: {}", e)))?;
+    let sink_addresses = queries::get_contract_sink_addresses(client, chain_name, contract_address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get sink addresses: {}", e)))?;
+
+    // Sales only exist when sale indexing is enabled, their absence is reported as null
+    let sales = match queries::get_sales(
+        client,
+        chain_name,
+        contract_address,
+        Some(token_id),
+        None,
+        None,
+        MAX_PROVENANCE_SALES,
+    )
+    .await
+    {
+        Ok(mut sales) => {
+            sales.reverse();
+            Some(sales)
+        }
+        Err(e) => {
+            eprintln!(
+                "Failed to get sales of {} on {} {}: {}",
+                token_id, chain_name, contract_address, e
+            );
+            None
+        }
+    };
+    let sales_by_transaction: HashMap<&str, &queries::SaleRow> = sales
+        .iter()
+        .flatten()
+        .map(|sale| (sale.transaction_hash.as_str(), sale))
+        .collect();
+
+    let mut balances: HashMap<String, Balance> = HashMap::new();
+    let mut mint = None;
+    let mut history = Vec::with_capacity(transfers.len());
+    for transfer in &transfers {
+        let kind = if is_mint(&transfer.from_address) {
+            "mint"
+        } else if is_burn(&transfer.to_address, &burn_addresses) {
+            "burn"
+        } else if is_sink(&transfer.to_address, &sink_addresses) {
+            "consume"
+        } else {
+            "transfer"
+        };
+
+        *balances
+            .entry(transfer.to_address.to_lowercase())
+            .or_default() += &transfer.value;
+        *balances
+            .entry(transfer.from_address.to_lowercase())
+            .or_default() -= &transfer.value;
+
+        let entry = json!({
+            "type": kind,
+            "from": transfer.from_address,
+            "from_label": labels::label_for(&transfer.from_address),
+            "to": transfer.to_address,
+            "to_label": labels::label_for(&transfer.to_address),
+            "operator": transfer.operator,
+            "value": transfer.value,
+            "block_number": transfer.block_number,
+            "transaction_hash": transfer.transaction_hash,
+            "timestamp": transfer.timestamp,
+            "sale": sales_by_transaction.get(transfer.transaction_hash.as_str()),
+        });
+        if kind == "mint" && mint.is_none() {
+            mint = Some(entry.clone());
+        }
+        history.push(entry);
+    }
+
+    let mut owners = Vec::new();
+    for (address, balance) in balances {
+        if !balance.is_positive() || is_burn(&address, &burn_addresses) {
+            continue;
+        }
+        owners.push(json!({
+            "address": address,
+            "name": get_username_or_checksummed_address(&address)
+                .await
+                .ok()
+                .flatten(),
+            "label": labels::label_for(&address),
+            "balance": balance,
+        }));
+    }
+
+    let token_name = METADATA_STORE
+        .token_metadata(chain_name, contract_address, token_id)
+        .await
+        .and_then(|metadata| metadata["name"].as_str().map(str::to_string));
+
+    Ok(json!({
+        "chain": chain_name,
+        "contract_address": contract_address,
+        "token_id": token_id,
+        "token_name": token_name,
+        "mint": mint,
+        "transfers": history,
+        "owners": owners,
+        "sales": sales,
+    }))
+}
@@ -0,0 +1,298 @@
+use crate::backend::errors::ApiError;
+use crate::backend::queries;
+use crate::common::database::Database;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::{Filter, Reply};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const DEFAULT_KEY_REQUESTS_PER_MINUTE: i32 = 1200;
+// Per client address, for requests without a key. 0 (the default) disables the limit.
+static ANONYMOUS_REQUESTS_PER_MINUTE: Lazy<u32> = Lazy::new(|| {
+    env::var("AFTERLIFE_RATE_LIMIT_PER_MINUTE")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(0)
+});
+// Reverse proxies whose X-Forwarded-For is believed, from AFTERLIFE_TRUSTED_PROXIES (comma
+// separated addresses). Requests from anyone else are limited on their own address.
+static TRUSTED_PROXIES: Lazy<HashSet<IpAddr>> = Lazy::new(|| {
+    env::var("AFTERLIFE_TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|proxy| !proxy.trim().is_empty())
+        .filter_map(|proxy| match proxy.trim().parse() {
+            Ok(address) => Some(address),
+            Err(_) => {
+                eprintln!("Ignoring invalid trusted proxy {}", proxy);
+                None
+            }
+        })
+        .collect()
+});
+const USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+// Revocations made through another replica take effect here within this period
+const KEYS_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+// Clients tracked at once, the least recently seen are forgotten past this
+const MAX_RATE_WINDOWS: usize = 100_000;
+
+/// A partner key, as recognized from the X-Api-Key header
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub id: i32,
+    pub requests_per_minute: u32,
+}
+
+// key hash -> key, mirror of the active rows of api_keys
+static API_KEYS: Lazy<RwLock<HashMap<String, ApiKey>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+// key id -> requests not flushed to api_key_usage yet
+static PENDING_USAGE: Lazy<Mutex<HashMap<i32, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// "key:{id}" or "ip:{address}" -> (minute, requests in that minute)
+static RATE_WINDOWS: Lazy<Mutex<LruCache<String, (u64, u32)>>> = Lazy::new(|| {
+    Mutex::new(LruCache::new(
+        NonZeroUsize::new(MAX_RATE_WINDOWS).expect("MAX_RATE_WINDOWS is zero"),
+    ))
+});
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    pub requests_per_minute: Option<i32>,
+}
+
+/// Reloads the active keys from the database
+pub async fn reload(client: &Client) -> Result<(), ApiError> {
+    let rows = queries::get_active_api_key_hashes(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to load API keys: {}", e)))?;
+
+    *API_KEYS.write().expect("API keys lock poisoned") = rows
+        .into_iter()
+        .map(|(id, key_hash, requests_per_minute)| {
+            (
+                key_hash,
+                ApiKey {
+                    id,
+                    requests_per_minute: requests_per_minute.max(0) as u32,
+                },
+            )
+        })
+        .collect();
+    Ok(())
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+// An unknown key is an error rather than an anonymous request, so a revoked or mistyped key
+// doesn't silently fall back to the anonymous limits
+fn lookup(key: Option<&str>) -> Result<Option<ApiKey>, ApiError> {
+    key.map(|key| {
+        API_KEYS
+            .read()
+            .expect("API keys lock poisoned")
+            .get(&hash_key(key))
+            .cloned()
+            .ok_or_else(|| ApiError::Unauthorized("Invalid API key".to_string()))
+    })
+    .transpose()
+}
+
+/// Counts the request against the limit of its API key, or of its client address when it
+/// has none, and rejects it once over the limit
+pub fn rate_limit() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>(API_KEY_HEADER)
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::addr::remote())
+        .and_then(
+            |key: Option<String>,
+             forwarded_for: Option<String>,
+             remote: Option<SocketAddr>| async move {
+                let key = lookup(key.as_deref())?;
+                let (identity, limit) = match &key {
+                    Some(key) => (format!("key:{}", key.id), key.requests_per_minute),
+                    None => (
+                        format!("ip:{}", client_address(forwarded_for.as_deref(), remote)),
+                        *ANONYMOUS_REQUESTS_PER_MINUTE,
+                    ),
+                };
+                if limit > 0 && !take(&identity, limit) {
+                    return Err(Rejection::from(ApiError::TooManyRequests(format!(
+                        "Rate limit of {} requests per minute exceeded",
+                        limit
+                    ))));
+                }
+                // Only served requests are billed
+                if let Some(key) = key {
+                    *PENDING_USAGE
+                        .lock()
+                        .expect("API key usage lock poisoned")
+                        .entry(key.id)
+                        .or_default() += 1;
+                }
+                Ok::<_, Rejection>(())
+            },
+        )
+        .untuple_one()
+}
+
+// The peer, unless it's a trusted proxy: then the hop that proxy appended to X-Forwarded-For.
+// Earlier hops are whatever the client sent and are never used.
+fn client_address(forwarded_for: Option<&str>, remote: Option<SocketAddr>) -> String {
+    let peer = match remote {
+        Some(remote) => remote.ip(),
+        None => return String::new(),
+    };
+    if TRUSTED_PROXIES.contains(&peer) {
+        if let Some(hop) = forwarded_for.and_then(|hops| hops.rsplit(',').next()) {
+            if let Ok(address) = hop.trim().parse::<IpAddr>() {
+                return address.to_string();
+            }
+        }
+    }
+    peer.to_string()
+}
+
+// Fixed one minute windows per identity, in a bounded map
+fn take(identity: &str, limit: u32) -> bool {
+    let minute = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 60)
+        .unwrap_or(0);
+    let mut windows = RATE_WINDOWS.lock().expect("Rate limit lock poisoned");
+    let window = windows.get_or_insert_mut(identity.to_string(), || (minute, 0));
+    if window.0 != minute {
+        *window = (minute, 0);
+    }
+    if window.1 >= limit {
+        return false;
+    }
+    window.1 += 1;
+    true
+}
+
+/// Whether the request carries a valid X-Api-Key. The heavier variants of some routes
+/// (exports, streams) are reserved to partners.
+pub fn has_partner_key() -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(API_KEY_HEADER).and_then(|key: Option<String>| async move {
+        Ok::<_, Rejection>(lookup(key.as_deref())?.is_some())
+    })
+}
+
+/// Reloads the keys periodically, so keys created or revoked through another replica are
+/// picked up here too
+pub fn spawn_reloader(database: Arc<Database>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(KEYS_RELOAD_INTERVAL);
+        // The first tick completes immediately, run_server has just loaded them
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let client = database.client().await;
+            if let Err(e) = reload(&client).await {
+                eprintln!("{:?}", e);
+            }
+        }
+    });
+}
+
+/// Writes the request counts of the keys to api_key_usage every minute
+pub fn spawn_usage_flusher(database: Arc<Database>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(USAGE_FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let usage: Vec<(i32, i64)> = PENDING_USAGE
+                .lock()
+                .expect("API key usage lock poisoned")
+                .drain()
+                .collect();
+            if usage.is_empty() {
+                continue;
+            }
+            let client = database.client().await;
+            if let Err(e) = queries::record_api_key_usage(&client, &usage).await {
+                eprintln!("Failed to record API key usage: {}", e);
+                // Counted again with the next flush
+                let mut pending = PENDING_USAGE.lock().expect("API key usage lock poisoned");
+                for (id, requests) in usage {
+                    *pending.entry(id).or_default() += requests;
+                }
+            }
+        }
+    });
+}
+
+// The key is only ever returned here, only its hash is stored
+pub async fn handle_create_api_key(
+    body: NewApiKey,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    if body.name.trim().is_empty() {
+        return Err(ApiError::invalid_field("name", "Name must not be empty".to_string()).into());
+    }
+    let requests_per_minute = body
+        .requests_per_minute
+        .unwrap_or(DEFAULT_KEY_REQUESTS_PER_MINUTE);
+    if requests_per_minute <= 0 {
+        return Err(ApiError::invalid_field(
+            "requests_per_minute",
+            format!("Invalid requests_per_minute {}", requests_per_minute),
+        )
+        .into());
+    }
+
+    let key = format!("afl_{}", hex::encode(rand::random::<[u8; 32]>()));
+    let row = queries::insert_api_key(
+        &client,
+        body.name.trim(),
+        &hash_key(&key),
+        requests_per_minute,
+    )
+    .await
+    .map_err(|e| ApiError::Upstream(format!("Failed to create API key: {}", e)))?;
+    reload(&client).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "api_key": row, "key": key })),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+pub async fn handle_list_api_keys(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let keys = queries::get_api_keys(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to list API keys: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({ "api_keys": keys })).into_response())
+}
+
+pub async fn handle_revoke_api_key(
+    id: i32,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let revoked = queries::revoke_api_key(&client, id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to revoke API key: {}", e)))?;
+    if !revoked {
+        return Err(ApiError::NotFound(format!("No active API key {}", id)).into());
+    }
+    reload(&client).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
@@ -0,0 +1,74 @@
+use crate::backend::errors::ApiError;
+use crate::backend::labels;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::backend::usernames::get_all_addresses_for_name;
+use eth_checksum::checksum;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+use web3::types::Address;
+
+pub async fn handle_get_user_approvals(
+    address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    // Addresses are normalized so every spelling shares a cache entry
+    let name = match address.parse::<Address>() {
+        Ok(owner) => format!("{:?}", owner),
+        Err(_) => address,
+    };
+    let cache_key = format!("user/approvals/{}", name);
+    let response =
+        response_cache::get_or_compute(cache_key, build_user_approvals(&name, &client)).await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// Operators that can move every token the user holds in a collection, across all their
+// addresses. Known operators (marketplaces, escrows) carry their label, unlabeled ones are
+// the ones to look at.
+async fn build_user_approvals(name: &str, client: &Client) -> Result<Value, ApiError> {
+    let owners: Vec<String> = get_all_addresses_for_name(name)
+        .await?
+        .into_iter()
+        .collect();
+    if owners.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "Unknown user or address {}",
+            name
+        )));
+    }
+
+    let approvals = queries::get_active_approvals(client, &owners)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get approvals: {}", e)))?;
+
+    let approvals: Vec<Value> = approvals
+        .iter()
+        .map(|approval| {
+            json!({
+                "owner": checksum(&approval.owner),
+                "chain": approval.chain,
+                "contract_address": checksum(&approval.contract_address),
+                "contract_name": approval.contract_name,
+                "operator": checksum(&approval.operator),
+                "operator_label": labels::label_for(&approval.operator),
+                "approved_at": approval.approved_at,
+                "block_number": approval.block_number,
+                "transaction_hash": approval.transaction_hash,
+            })
+        })
+        .collect();
+
+    let address = match name.parse::<Address>() {
+        Ok(_) => checksum(name),
+        Err(_) => name.to_string(),
+    };
+    Ok(json!({
+        "address": address,
+        "approvals": approvals,
+    }))
+}
@@ -0,0 +1,280 @@
+use crate::backend::errors::ApiError;
+use crate::backend::queries;
+use crate::common::database::Database;
+use eth_checksum::checksum;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::env;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::{Filter, Reply};
+use web3::signing::{hash_message, recover};
+use web3::types::Address;
+
+// How long a session token stays valid after signing in
+const SESSION_TTL_SECONDS: i64 = 7 * 24 * 3600;
+// How long a nonce can be signed in with, once
+const NONCE_TTL_SECONDS: i64 = 300;
+
+/// Rejects the request unless it carries `Authorization: Bearer <AFTERLIFE_ADMIN_TOKEN>`.
+/// The admin API is disabled entirely while the variable is unset.
+pub fn admin_only() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(|authorization: Option<String>| async move {
+            let admin_token = env::var("AFTERLIFE_ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty())
+                .ok_or_else(|| ApiError::Unauthorized("Admin API is disabled".to_string()))?;
+
+            let provided = bearer_token(authorization.as_deref());
+            if !constant_time_eq(provided.as_bytes(), admin_token.as_bytes()) {
+                return Err(Rejection::from(ApiError::Unauthorized(
+                    "Invalid admin token".to_string(),
+                )));
+            }
+            Ok::<_, Rejection>(())
+        })
+        .untuple_one()
+}
+
+/// A signed-in wallet, from the session token of `POST /auth/login`
+#[derive(Debug, Clone)]
+pub struct Session {
+    // Lowercased
+    pub address: String,
+    pub id: String,
+}
+
+/// Extracts the session of `Authorization: Bearer <token>`. The token is checked against
+/// the primary, so a session signed out of through any replica is rejected right away.
+pub fn session(
+    database: Arc<Database>,
+) -> impl Filter<Extract = (Session,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(
+        move |authorization: Option<String>| {
+            let database = database.clone();
+            async move {
+                let secret = session_secret()?;
+                let invalid = || {
+                    Rejection::from(ApiError::Unauthorized(
+                        "Missing, invalid or expired session token".to_string(),
+                    ))
+                };
+                let session =
+                    verify_session_token(&secret, bearer_token(authorization.as_deref()), now())
+                        .ok_or_else(invalid)?;
+                let client = database.client().await;
+                let active = queries::is_auth_session_active(&client, &session.id)
+                    .await
+                    .map_err(|e| ApiError::Upstream(format!("Failed to check session: {}", e)))?;
+                if !active {
+                    return Err(invalid());
+                }
+                Ok::<_, Rejection>(session)
+            }
+        },
+    )
+}
+
+/// Extracts the lowercased wallet address of the session, see `session`
+pub fn user(
+    database: Arc<Database>,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    session(database).map(|session: Session| session.address)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NonceRequest {
+    pub address: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    pub address: String,
+    // From POST /auth/nonce, in the signed message
+    pub nonce: String,
+    // 65 bytes hex signature of login_message by `address` (personal_sign)
+    pub signature: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LogoutRequest {
+    // Every session of the wallet rather than this one
+    #[serde(default)]
+    pub everywhere: bool,
+}
+
+/// The message a wallet signs to sign in
+pub fn login_message(address: &Address, nonce: &str) -> String {
+    format!(
+        "Sign in to Afterlife\n\nAddress: {}\nNonce: {}",
+        checksum(&format!("{:?}", address)),
+        nonce
+    )
+}
+
+// A fresh nonce for each sign-in, so a signed message can't be replayed for another session
+pub async fn handle_create_nonce(
+    body: NonceRequest,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    session_secret()?;
+    let address = parse_address(&body.address)?;
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+    let expires_at = queries::insert_auth_nonce(
+        &client,
+        &nonce,
+        &format!("{:?}", address),
+        NONCE_TTL_SECONDS,
+    )
+    .await
+    .map_err(|e| ApiError::Upstream(format!("Failed to create nonce: {}", e)))?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "nonce": nonce,
+        "message": login_message(&address, &nonce),
+        "expires_at": expires_at,
+    }))
+    .into_response())
+}
+
+pub async fn handle_login(
+    body: LoginRequest,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let secret = session_secret()?;
+    let address = parse_address(&body.address)?;
+
+    let signer = recover_signer(&login_message(&address, &body.nonce), &body.signature)
+        .ok_or_else(|| ApiError::invalid_field("signature", "Invalid signature".to_string()))?;
+    if signer != address {
+        return Err(
+            ApiError::Unauthorized("Signature doesn't match the address".to_string()).into(),
+        );
+    }
+
+    let address = format!("{:?}", address);
+    let expires_at =
+        queries::start_auth_session(&client, &body.nonce, &address, SESSION_TTL_SECONDS)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to start session: {}", e)))?
+            .ok_or_else(|| {
+                ApiError::Unauthorized(
+                    "Unknown, used or expired nonce, ask for a new one".to_string(),
+                )
+            })?;
+    let session = Session {
+        address,
+        id: body.nonce,
+    };
+    Ok(warp::reply::json(&serde_json::json!({
+        "token": session_token(&secret, &session, expires_at),
+        "address": checksum(&session.address),
+        "expires_at": expires_at,
+    }))
+    .into_response())
+}
+
+// Signs out of the session of the request, or of every session of its wallet
+pub async fn handle_logout(
+    session: Session,
+    body: LogoutRequest,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let id = (!body.everywhere).then_some(session.id.as_str());
+    let revoked = queries::revoke_auth_sessions(&client, &session.address, id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to sign out: {}", e)))?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "address": checksum(&session.address),
+        "revoked": revoked,
+    }))
+    .into_response())
+}
+
+fn parse_address(address: &str) -> Result<Address, ApiError> {
+    address
+        .parse::<Address>()
+        .map_err(|_| ApiError::invalid_field("address", format!("Invalid address {}", address)))
+}
+
+// User sign-in is disabled entirely while AFTERLIFE_SESSION_SECRET is unset
+fn session_secret() -> Result<String, ApiError> {
+    env::var("AFTERLIFE_SESSION_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .ok_or_else(|| ApiError::Unauthorized("User sign-in is disabled".to_string()))
+}
+
+fn recover_signer(message: &str, signature: &str) -> Option<Address> {
+    let signature = hex::decode(signature.trim_start_matches("0x")).ok()?;
+    if signature.len() != 65 {
+        return None;
+    }
+    // Wallets use either 27/28 or 0/1 for v
+    let recovery_id = match signature[64] {
+        v @ (27 | 28) => v - 27,
+        v @ (0 | 1) => v,
+        _ => return None,
+    };
+    recover(
+        hash_message(message).as_bytes(),
+        &signature[..64],
+        recovery_id as i32,
+    )
+    .ok()
+}
+
+// "{address}.{session id}.{expires_at}.{hmac}", the session must still be active too
+fn session_token(secret: &str, session: &Session, expires_at: i64) -> String {
+    let payload = format!("{}.{}.{}", session.address, session.id, expires_at);
+    let mac = session_mac(secret, &payload);
+    format!("{}.{}", payload, mac)
+}
+
+fn verify_session_token(secret: &str, token: &str, now: i64) -> Option<Session> {
+    let (payload, mac) = token.rsplit_once('.')?;
+    let mut parts = payload.split('.');
+    let (address, id, expires_at) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some()
+        || !constant_time_eq(session_mac(secret, payload).as_bytes(), mac.as_bytes())
+    {
+        return None;
+    }
+    if expires_at.parse::<i64>().ok()? <= now {
+        return None;
+    }
+    Some(Session {
+        address: address.to_string(),
+        id: id.to_string(),
+    })
+}
+
+fn session_mac(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn bearer_token(authorization: Option<&str>) -> &str {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("")
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// Compares without short-circuiting so the token can't be guessed byte by byte from timings
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,149 @@
+use crate::backend::errors::ApiError;
+use crate::backend::holdings::load_user_holdings;
+use crate::backend::projects::DEFAULT_PROJECT;
+use crate::backend::queries::{self, Avatar};
+use crate::backend::response_cache;
+use crate::backend::usernames::{addresses_for_username, signed_in_username};
+use crate::common::numeric::TokenId;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+#[derive(Debug, Deserialize)]
+pub struct AvatarSelection {
+    pub chain: String,
+    pub contract_address: String,
+    pub token_id: TokenId,
+}
+
+// Sets the avatar of the user the signed-in wallet belongs to, to a token one of the
+// user's addresses holds or has staked
+pub async fn handle_set_avatar(
+    address: String,
+    body: AvatarSelection,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+
+    let holdings = load_user_holdings(&client, &addresses_for_username(&users, &username)).await?;
+    // Stored as the indexer spells the contract address, whatever the case in the request
+    let contract_address = holdings
+        .iter()
+        .find(|((chain, contract_address), tokens)| {
+            chain == &body.chain
+                && contract_address.eq_ignore_ascii_case(&body.contract_address)
+                && tokens.contains_key(&body.token_id)
+        })
+        .map(|((_, contract_address), _)| contract_address.clone())
+        .ok_or_else(|| {
+            ApiError::invalid_field(
+                "token_id",
+                format!(
+                    "{} doesn't hold token {} of {} on {}",
+                    username, body.token_id, body.contract_address, body.chain
+                ),
+            )
+        })?;
+
+    let avatar = Avatar {
+        chain: body.chain,
+        contract_address,
+        token_id: body.token_id,
+    };
+    queries::set_avatar(&client, &username, Some(&avatar))
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to set avatar: {}", e)))?;
+    // The avatar is part of user details and leaderboard entries of every project
+    if let Err(e) = queries::delete_user_details_document(&client, &username).await {
+        eprintln!("Failed to drop warmed user details of {}: {}", username, e);
+    }
+    response_cache::invalidate_all();
+
+    Ok(warp::reply::json(&json!({
+        "username": username,
+        "avatar": avatar,
+    }))
+    .into_response())
+}
+
+// Avatars are cosmetic, a database problem must not fail the response they're part of
+pub(crate) async fn load_avatar(client: &Client, username: &str) -> Option<Avatar> {
+    let avatar = queries::get_avatar(client, username)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to get avatar of {}: {}", username, e);
+            None
+        })?;
+    let mut avatars = HashMap::from([(username.to_string(), avatar)]);
+    retain_held(client, &mut avatars).await;
+    avatars.remove(username)
+}
+
+pub(crate) async fn load_avatars_of(
+    client: &Client,
+    usernames: &[String],
+) -> HashMap<String, Avatar> {
+    if usernames.is_empty() {
+        return HashMap::new();
+    }
+    let mut avatars = queries::get_avatars_of(client, usernames)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to get avatars: {}", e);
+            HashMap::new()
+        });
+    retain_held(client, &mut avatars).await;
+    avatars
+}
+
+// The token may have changed hands since it was picked: an avatar is only shown while one
+// of its user's addresses still holds or stakes it, and not at all when that can't be told
+async fn retain_held(client: &Client, avatars: &mut HashMap<String, Avatar>) {
+    if avatars.is_empty() {
+        return;
+    }
+    let tokens: Vec<(String, String, TokenId)> = avatars
+        .values()
+        .map(|avatar| {
+            (
+                avatar.chain.clone(),
+                avatar.contract_address.clone(),
+                avatar.token_id,
+            )
+        })
+        .collect();
+    let users = match DEFAULT_PROJECT.users().await {
+        Ok(users) => users,
+        Err(e) => {
+            eprintln!("Failed to check avatars: {}", e.message());
+            avatars.clear();
+            return;
+        }
+    };
+    let holders = match queries::get_token_holders(client, &tokens).await {
+        Ok(holders) => holders,
+        Err(e) => {
+            eprintln!("Failed to get avatar holders: {}", e);
+            avatars.clear();
+            return;
+        }
+    };
+
+    avatars.retain(|username, avatar| {
+        let key = (
+            avatar.chain.to_lowercase(),
+            avatar.contract_address.to_lowercase(),
+            avatar.token_id,
+        );
+        holders.get(&key).is_some_and(|holders| {
+            addresses_for_username(&users, username)
+                .iter()
+                .any(|address| holders.contains(&address.to_lowercase()))
+        })
+    });
+}
@@ -0,0 +1,99 @@
+use crate::backend::api::get_or_update_all_users_collections;
+use crate::backend::errors::ApiError;
+use crate::backend::holdings::{load_user_holdings, top_nfts};
+use crate::backend::levels::points_to_level;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::backend::usernames::get_all_addresses_for_username;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+#[derive(Debug, Deserialize)]
+pub struct DiscordLink {
+    // None unlinks the current discord account
+    pub discord_id: Option<String>,
+}
+
+// Compact profile for the community Discord bot, a fraction of /user/level
+pub async fn handle_get_bot_user(
+    discord_id: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!("bot/user/{}", discord_id);
+    let response =
+        response_cache::get_or_compute(cache_key, build_bot_user(&discord_id, &client)).await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+async fn build_bot_user(discord_id: &str, client: &Client) -> Result<Value, ApiError> {
+    let username = queries::get_username_for_discord_id(client, discord_id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to look up discord id: {}", e)))?
+        .ok_or_else(|| {
+            ApiError::NotFound(format!("No user linked to discord id {}", discord_id))
+        })?;
+
+    let user_addresses = get_all_addresses_for_username(&username).await;
+    if user_addresses.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)));
+    }
+
+    // Points and rank come from the precomputed leaderboard so they always agree with it
+    let leaderboard = get_or_update_all_users_collections(client, false).await?;
+    let points = leaderboard.get(&username).copied().unwrap_or(0.0);
+    let rank = leaderboard.contains_key(&username).then(|| {
+        leaderboard
+            .values()
+            .filter(|&&score| score > points)
+            .count()
+            + 1
+    });
+
+    let holdings = load_user_holdings(client, &user_addresses).await?;
+
+    Ok(json!({
+        "username": username,
+        "level": points_to_level(points as i32),
+        "points": points,
+        "rank": rank,
+        "top_nft": top_nfts(&holdings, 1).await.into_iter().next(),
+    }))
+}
+
+pub async fn handle_link_discord_id(
+    username: String,
+    body: DiscordLink,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    // Discord ids are snowflakes, up to 20 digits
+    if let Some(discord_id) = &body.discord_id {
+        if discord_id.is_empty()
+            || discord_id.len() > 20
+            || !discord_id.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(ApiError::invalid_field(
+                "discord_id",
+                format!("Invalid discord id {}", discord_id),
+            )
+            .into());
+        }
+    }
+    if get_all_addresses_for_username(&username).await.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)).into());
+    }
+
+    queries::set_discord_id(&client, &username, body.discord_id.as_deref())
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to link discord id: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({
+        "username": username,
+        "discord_id": body.discord_id,
+    }))
+    .into_response())
+}
@@ -0,0 +1,235 @@
+use crate::backend::catalog::CATALOG_CACHE_KEY;
+use crate::backend::delegations;
+use crate::backend::projects;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::backend::user_details_cache;
+use crate::common::database::Database;
+use crate::indexer::queries::{EventsNotification, EVENTS_CHANNEL};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::{self, Duration};
+use tokio_postgres::Client;
+
+/// Channel the leading replica notifies once it stored a recomputed leaderboard, so the
+/// others read it right away
+pub const LEADERBOARD_CHANNEL: &str = "afterlife_leaderboard";
+
+// Wait before listening again after the connection is lost
+const LISTEN_RETRY_PERIOD: Duration = Duration::from_secs(5);
+
+// Per-user responses built from the user's holdings, keyed `.../{kind}/{username}`
+const USER_RESPONSE_KINDS: &[&str] =
+    &["level", "achievements", "sets", "stats", "profile", "value"];
+// Responses built from the leaderboard (points, ranks) or warmed with it, keyed after
+// these prefixes
+const LEADERBOARD_RESPONSE_PREFIXES: &[&str] =
+    &["leaderboard", "bot/", "users/", "profile/", "user/level/"];
+
+/// What the listener tells the leaderboard refresh. Notifications arriving while the
+/// previous one is still being handled are coalesced into one.
+#[derive(Default)]
+pub struct Signals {
+    /// An indexer write changed the holdings of a user
+    pub scores_changed: Notify,
+    /// Another replica stored a recomputed leaderboard
+    pub leaderboard_stored: Notify,
+    listening: AtomicBool,
+    // Users affected since the refresh last took them, None when every user may be, as
+    // before the first refresh
+    changed_users: Mutex<Option<HashSet<String>>>,
+}
+
+impl Signals {
+    /// The users affected by the writes notified since the last call, None when every user
+    /// may be
+    pub fn take_changed_users(&self) -> Option<HashSet<String>> {
+        self.changed_users
+            .lock()
+            .expect("Changed users lock poisoned")
+            .replace(HashSet::new())
+    }
+
+    /// Marks `usernames` as affected, every user when None
+    pub fn add_changed_users(&self, usernames: Option<&HashSet<String>>) {
+        let mut changed = self
+            .changed_users
+            .lock()
+            .expect("Changed users lock poisoned");
+        match (&mut *changed, usernames) {
+            (Some(changed), Some(usernames)) => changed.extend(usernames.iter().cloned()),
+            (changed, _) => *changed = None,
+        }
+    }
+
+    /// Whether notifications are being received, the refresh falls back to polling
+    /// while they're not
+    pub fn listening(&self) -> bool {
+        self.listening.load(Ordering::Relaxed)
+    }
+}
+
+/// Listens to the indexer's and the other replicas' notifications for as long as the
+/// process runs, listening again whenever the connection is lost
+pub fn spawn_listener(database: Arc<Database>) -> Arc<Signals> {
+    let signals = Arc::new(Signals::default());
+    let listener_signals = signals.clone();
+    tokio::spawn(async move {
+        let signals = listener_signals;
+        loop {
+            match database
+                .listen(&[EVENTS_CHANNEL, LEADERBOARD_CHANNEL])
+                .await
+            {
+                Ok(mut listener) => {
+                    signals.listening.store(true, Ordering::Relaxed);
+                    while let Some(notification) = listener.recv().await {
+                        if notification.channel() == LEADERBOARD_CHANNEL {
+                            signals.leaderboard_stored.notify_one();
+                            continue;
+                        }
+                        match serde_json::from_str::<EventsNotification>(notification.payload()) {
+                            Ok(events) => {
+                                let affected = invalidate_for(&events).await;
+                                if affected.as_ref().is_none_or(|users| !users.is_empty()) {
+                                    // Warmed documents would outlive the cached responses
+                                    let client = database.client().await;
+                                    user_details_cache::drop_documents(&client, affected.as_ref())
+                                        .await;
+                                    signals.add_changed_users(affected.as_ref());
+                                    signals.scores_changed.notify_one();
+                                }
+                            }
+                            Err(e) => eprintln!("Invalid {} notification: {}", EVENTS_CHANNEL, e),
+                        }
+                    }
+                    eprintln!("Cache invalidation listener disconnected");
+                }
+                Err(e) => eprintln!("Failed to listen for cache invalidations: {}", e),
+            }
+            if signals.listening.swap(false, Ordering::Relaxed) {
+                // Writes notified while disconnected were missed
+                response_cache::invalidate_all();
+                let client = database.client().await;
+                user_details_cache::drop_documents(&client, None).await;
+                signals.add_changed_users(None);
+                signals.scores_changed.notify_one();
+            }
+            time::sleep(LISTEN_RETRY_PERIOD).await;
+        }
+    });
+    signals
+}
+
+/// Tells every replica that scores may have changed outside of the indexer (weights,
+/// exclusions, users): their cached responses are dropped and the leader recomputes
+pub async fn notify_scores_changed(client: &Client) {
+    let everything = EventsNotification {
+        chain: String::new(),
+        contracts: Vec::new(),
+        addresses: None,
+    };
+    let payload = serde_json::to_string(&everything).unwrap_or_default();
+    if let Err(e) = queries::notify(client, EVENTS_CHANNEL, &payload).await {
+        eprintln!("Failed to notify the score change: {}", e);
+    }
+}
+
+/// Drops the cached responses an indexer write made stale: those of the contracts it
+/// changed, the approvals of the owners it changed and the responses of the users holding
+/// the addresses it changed. Returns the users affected, None when every user may be:
+/// their points and so the leaderboard may have changed.
+pub async fn invalidate_for(notification: &EventsNotification) -> Option<HashSet<String>> {
+    let addresses: HashSet<String> = match &notification.addresses {
+        Some(addresses) => addresses.iter().map(|a| a.to_lowercase()).collect(),
+        None => {
+            response_cache::invalidate_all();
+            return None;
+        }
+    };
+    let usernames = match affected_usernames(&addresses).await {
+        Some(usernames) => usernames,
+        None => {
+            response_cache::invalidate_all();
+            return None;
+        }
+    };
+    let contracts: Vec<String> = notification
+        .contracts
+        .iter()
+        .map(|contract| {
+            format!(
+                "{}/{}",
+                notification.chain.to_lowercase(),
+                contract.to_lowercase()
+            )
+        })
+        .collect();
+
+    let affected = usernames.clone();
+    response_cache::invalidate_matching(move |key| {
+        if key == CATALOG_CACHE_KEY
+            || contracts
+                .iter()
+                .any(|contract| key.contains(contract.as_str()))
+        {
+            return true;
+        }
+        let mut segments = key.rsplit('/');
+        let last = segments.next().unwrap_or_default();
+        match segments.next() {
+            Some("approvals") => addresses.contains(last),
+            Some(kind) => USER_RESPONSE_KINDS.contains(&kind) && usernames.contains(last),
+            None => false,
+        }
+    });
+    Some(affected)
+}
+
+/// Drops the cached responses built from the leaderboard, of every project, once a new
+/// one is in place
+pub fn invalidate_leaderboards() {
+    response_cache::invalidate_matching(|key| {
+        let key = key.strip_prefix("v1/").unwrap_or(key);
+        // Project scoped keys start with `p/{project}/`
+        let key = match key.strip_prefix("p/") {
+            Some(scoped) => scoped.split_once('/').map_or(scoped, |(_, rest)| rest),
+            None => key,
+        };
+        LEADERBOARD_RESPONSE_PREFIXES
+            .iter()
+            .any(|prefix| key.starts_with(prefix))
+    });
+}
+
+// Users of any project with one of `addresses`, directly or through a delegated vault.
+// None when a users file can't be read.
+async fn affected_usernames(addresses: &HashSet<String>) -> Option<HashSet<String>> {
+    let mut usernames = HashSet::new();
+    if addresses.is_empty() {
+        return Some(usernames);
+    }
+    for project in projects::all() {
+        let users = match project.users().await {
+            Ok(users) => users,
+            Err(e) => {
+                eprintln!("{}", e.message());
+                return None;
+            }
+        };
+        for (username, user_addresses) in users {
+            let user_addresses: Vec<String> =
+                user_addresses.iter().map(|a| a.to_lowercase()).collect();
+            let affected = user_addresses
+                .iter()
+                .chain(delegations::vaults_for(&user_addresses).iter())
+                .any(|address| addresses.contains(address));
+            if affected {
+                usernames.insert(username);
+            }
+        }
+    }
+    Some(usernames)
+}
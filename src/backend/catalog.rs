@@ -0,0 +1,47 @@
+use crate::backend::errors::ApiError;
+use crate::backend::projects::DEFAULT_PROJECT;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+
+/// Dropped on every indexer write, token and holder counts change with any of them
+pub const CATALOG_CACHE_KEY: &str = "catalog";
+
+/// Every chain with its live contracts, for frontends to build their navigation from
+pub async fn handle_get_catalog(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let response =
+        response_cache::get_or_compute(CATALOG_CACHE_KEY.to_string(), build_catalog(&client))
+            .await?;
+    Ok(warp::reply::json(&*response))
+}
+
+async fn build_catalog(client: &Client) -> Result<Value, ApiError> {
+    let chain_names = queries::get_chain_names(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to list chains: {}", e)))?;
+    let excluded: Vec<String> = DEFAULT_PROJECT
+        .excluded_addresses()
+        .await?
+        .into_iter()
+        .collect();
+    let contracts = queries::get_catalog(client, &excluded)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get catalog: {}", e)))?;
+
+    // Both are sorted by chain name
+    let mut contracts = contracts.into_iter().peekable();
+    let chains: Vec<Value> = chain_names
+        .into_iter()
+        .map(|name| {
+            let mut chain_contracts = Vec::new();
+            while let Some(contract) = contracts.next_if(|contract| contract.chain == name) {
+                chain_contracts.push(contract);
+            }
+            json!({ "name": name, "contracts": chain_contracts })
+        })
+        .collect();
+    Ok(json!({ "chains": chains }))
+}
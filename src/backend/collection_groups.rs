@@ -0,0 +1,130 @@
+use crate::backend::errors::ApiError;
+use crate::backend::queries;
+use crate::common::numeric::TokenId;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::sync::RwLock;
+use tokio_postgres::Client;
+
+/// A collection deployed on several chains through a bridge, as defined in the collection
+/// groups file (AFTERLIFE_PATH_COLLECTION_GROUPS). The canonical contract comes first; the
+/// same token id on any member is one logical token.
+///
+/// ```yaml
+/// - name: ghouls
+///   contracts:
+///     - chain: fantom
+///       address: "0x..."
+///     - chain: ethereum
+///       address: "0x..."
+/// ```
+#[derive(Debug, Deserialize)]
+struct CollectionGroup {
+    name: String,
+    contracts: Vec<GroupContract>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupContract {
+    chain: String,
+    address: String,
+}
+
+// (lowercased chain, lowercased address) -> (group index, position in the group)
+static GROUP_MEMBERS: Lazy<HashMap<(String, String), (usize, usize)>> =
+    Lazy::new(load_collection_groups);
+
+// (lowercased chain, lowercased address) of the ERC-1155 contracts, loaded by `reload`.
+// Their balances add up across a group, a bridge moves some of a token's supply.
+static FUNGIBLE_CONTRACTS: Lazy<RwLock<HashSet<(String, String)>>> =
+    Lazy::new(|| RwLock::new(HashSet::new()));
+
+/// Loads which contracts are ERC-1155, the ones not indexed yet count as ERC-721 until the
+/// next reload
+pub async fn reload(client: &Client) -> Result<(), ApiError> {
+    let contracts = queries::get_erc1155_contracts(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to load contract types: {}", e)))?;
+    *FUNGIBLE_CONTRACTS
+        .write()
+        .expect("Fungible contracts lock poisoned") = contracts
+        .into_iter()
+        .map(|(chain, address)| (chain.to_lowercase(), address.to_lowercase()))
+        .collect();
+    Ok(())
+}
+
+fn load_collection_groups() -> HashMap<(String, String), (usize, usize)> {
+    let path = match env::var("AFTERLIFE_PATH_COLLECTION_GROUPS") {
+        Ok(path) => path,
+        Err(_) => return HashMap::new(),
+    };
+    let groups: Vec<CollectionGroup> = match fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_yaml::from_str(&contents).map_err(|e| e.to_string()))
+    {
+        Ok(groups) => groups,
+        Err(e) => {
+            eprintln!("Failed to load collection groups file {}: {}", path, e);
+            return HashMap::new();
+        }
+    };
+
+    let mut members = HashMap::new();
+    for (group, definition) in groups.into_iter().enumerate() {
+        for (position, contract) in definition.contracts.into_iter().enumerate() {
+            let key = (
+                contract.chain.to_lowercase(),
+                contract.address.to_lowercase(),
+            );
+            if members.insert(key, (group, position)).is_some() {
+                eprintln!(
+                    "Contract {} on {} is in several collection groups, keeping {}",
+                    contract.address, contract.chain, definition.name
+                );
+            }
+        }
+    }
+    members
+}
+
+/// Sort key putting canonical contracts before their bridged copies. Contracts outside any
+/// group come first too, they are never deduplicated.
+pub fn priority(chain_name: &str, contract_address: &str) -> usize {
+    GROUP_MEMBERS
+        .get(&(chain_name.to_lowercase(), contract_address.to_lowercase()))
+        .map_or(0, |(_, position)| *position)
+}
+
+/// Logical tokens already counted for one user. Feed holdings in `priority` order so the
+/// canonical chain's copy is the one kept.
+#[derive(Default)]
+pub struct BridgedTokens {
+    seen: HashSet<(usize, TokenId)>,
+}
+
+impl BridgedTokens {
+    /// Whether the token counts, false for a copy of an ERC-721 token already counted on
+    /// another member of its group. ERC-1155 balances always count, so a token's balances
+    /// on the members of its group add up.
+    pub fn first_seen(
+        &mut self,
+        chain_name: &str,
+        contract_address: &str,
+        token_id: TokenId,
+    ) -> bool {
+        let key = (chain_name.to_lowercase(), contract_address.to_lowercase());
+        let group = match GROUP_MEMBERS.get(&key) {
+            Some((group, _)) => *group,
+            None => return true,
+        };
+        let fungible = FUNGIBLE_CONTRACTS
+            .read()
+            .expect("Fungible contracts lock poisoned")
+            .contains(&key);
+        self.seen.insert((group, token_id)) || fungible
+    }
+}
@@ -0,0 +1,102 @@
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::METADATA_STORE;
+use crate::backend::queries;
+use crate::backend::rarity_models::Traits;
+use crate::backend::response_cache;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+pub async fn handle_get_collection_traits(
+    chain_name: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!(
+        "{}/{}/traits",
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase()
+    );
+    let response = response_cache::get_or_compute(
+        cache_key,
+        build_collection_traits(&chain_name, &contract_address, &client),
+    )
+    .await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// Every trait type with its values, most common first, as in marketplace filter sidebars.
+// Counts cover the tokens in circulation with metadata, burned tokens are left out and
+// tokens without a trait type aren't counted under it.
+async fn build_collection_traits(
+    chain_name: &str,
+    contract_address: &str,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    let token_ids = queries::get_entire_collection(client, chain_name, contract_address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get entire collection: {}", e)))?;
+
+    let index = METADATA_STORE
+        .trait_index(chain_name, contract_address)
+        .await
+        .map_err(ApiError::Upstream)?;
+    let token_traits: Vec<&Traits> = token_ids
+        .iter()
+        .filter_map(|token_id| index.get(token_id))
+        .collect();
+    if token_traits.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "No metadata for contract {} on {}",
+            contract_address, chain_name
+        )));
+    }
+
+    // trait_type -> value -> count
+    let mut counts: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+    for traits in &token_traits {
+        for (trait_type, value) in traits.iter() {
+            *counts
+                .entry(trait_type.as_str())
+                .or_default()
+                .entry(value.as_str())
+                .or_default() += 1;
+        }
+    }
+
+    let total = token_traits.len();
+    let mut trait_types: Vec<(&str, HashMap<&str, usize>)> = counts.into_iter().collect();
+    trait_types.sort_by(|a, b| a.0.cmp(b.0));
+    let traits: Vec<Value> = trait_types
+        .into_iter()
+        .map(|(trait_type, values)| {
+            let mut values: Vec<(&str, usize)> = values.into_iter().collect();
+            values.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            let values: Vec<Value> = values
+                .into_iter()
+                .map(|(value, count)| {
+                    json!({
+                        "value": value,
+                        "count": count,
+                        "frequency": count as f64 * 100.0 / total as f64,
+                    })
+                })
+                .collect();
+            json!({
+                "trait_type": trait_type,
+                "values": values,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "chain": chain_name,
+        "contract_address": contract_address,
+        "token_count": total,
+        "traits": traits,
+    }))
+}
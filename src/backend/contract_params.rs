@@ -0,0 +1,216 @@
+use crate::backend::addresses::{looks_like_address, normalize};
+use crate::backend::api::with_database;
+use crate::backend::errors::ApiError;
+use crate::backend::queries;
+use crate::common::database::Database;
+use moka::future::Cache;
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use std::time::Duration;
+use warp::http::header::LOCATION;
+use warp::http::{Method, StatusCode};
+use warp::path::FullPath;
+use warp::reject::{Reject, Rejection};
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+// Known chains are reloaded on any unknown one, the TTL only bounds how long a removed
+// chain is still accepted
+const CHAIN_NAMES_TTL: Duration = Duration::from_secs(60);
+
+static CHAIN_NAMES: Lazy<Cache<(), Arc<Vec<String>>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(1)
+        .time_to_live(CHAIN_NAMES_TTL)
+        .build()
+});
+
+// Unknown chain names are remembered this long, so requests for made-up chains don't
+// each reload the known ones
+const UNKNOWN_CHAINS_TTL: Duration = Duration::from_secs(10);
+const UNKNOWN_CHAINS_MAX_ENTRIES: u64 = 10_000;
+
+static UNKNOWN_CHAINS: Lazy<Cache<String, ()>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(UNKNOWN_CHAINS_MAX_ENTRIES)
+        .time_to_live(UNKNOWN_CHAINS_TTL)
+        .build()
+});
+
+// A slug changed in the indexer config is picked up within a minute
+const SLUGS_TTL: Duration = Duration::from_secs(60);
+const SLUGS_MAX_ENTRIES: u64 = 10_000;
+
+// (chain, slug) -> contract address, None when no contract has the slug
+static SLUGS: Lazy<Cache<(String, String), Option<String>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(SLUGS_MAX_ENTRIES)
+        .time_to_live(SLUGS_TTL)
+        .build()
+});
+
+/// Rejection sending a GET for a non-canonical contract path to the canonical one,
+/// rendered by `access_log::finish`
+#[derive(Debug)]
+pub struct CanonicalRedirect {
+    location: String,
+}
+
+impl Reject for CanonicalRedirect {}
+
+impl CanonicalRedirect {
+    // 308 rather than 301, HEAD stays HEAD
+    pub fn response(&self) -> Response {
+        warp::reply::with_header(
+            StatusCode::PERMANENT_REDIRECT,
+            LOCATION,
+            self.location.as_str(),
+        )
+        .into_response()
+    }
+}
+
+/// The chain and contract path parameters extracted by `path`, in their canonical form:
+/// the chain as stored (the DB-driven routes' spelling) and the contract checksummed, so
+/// routes, cache keys and metadata paths don't depend on how the caller typed them. The contract can also be given by its
+/// slug (/ethereum/relics/collection), which is served as is. Unknown chains are a 404 listing the
+/// known ones, and a GET with a chain that's neither as stored nor lowercase or an address
+/// that's neither lowercase nor checksummed is redirected to the canonical path.
+///
+/// `path` should match up to the first literal after the contract (`String / String /
+/// "tokens" / ..`), so that paths of other routes, /fullcollection/{address} say, are
+/// never taken for a chain and a contract.
+pub fn canonical<F>(
+    path: F,
+    database: Arc<Database>,
+) -> impl Filter<Extract = (String, String), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (String, String), Error = Rejection> + Clone,
+{
+    path.and(warp::method())
+        .and(warp::path::full())
+        .and(
+            warp::query::raw()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .and(with_database(database))
+        .and_then(canonicalize)
+        .untuple_one()
+}
+
+async fn canonicalize(
+    chain_name: String,
+    contract_address: String,
+    method: Method,
+    full_path: FullPath,
+    query: Option<String>,
+    database: Arc<Database>,
+) -> Result<(String, String), Rejection> {
+    let lowercase = chain_name.to_lowercase();
+    let mut chains = chain_names(&database).await?;
+    let mut stored = stored_name(&chains, &lowercase);
+    if stored.is_none() && !UNKNOWN_CHAINS.contains_key(&lowercase) {
+        // It may have been added since the names were cached
+        CHAIN_NAMES.invalidate(&()).await;
+        chains = chain_names(&database).await?;
+        stored = stored_name(&chains, &lowercase);
+        if stored.is_none() {
+            UNKNOWN_CHAINS.insert(lowercase.clone(), ()).await;
+        }
+    }
+    let chain = match stored {
+        Some(chain) => chain,
+        None => {
+            return Err(ApiError::NotFound(format!(
+                "Unknown chain {}, expected one of: {}",
+                chain_name,
+                chains.join(", ")
+            ))
+            .into())
+        }
+    };
+    // Malformed addresses were rejected before routing, anything not address-like is a slug
+    if !looks_like_address(&contract_address) {
+        let address = slug_address(&database, &chain, &contract_address).await?;
+        return Ok((chain, normalize(&address)?));
+    }
+    let contract = normalize(&contract_address)?;
+
+    // Lowercase chains and addresses are as canonical as the stored and checksummed ones,
+    // most clients send those
+    let canonical = (chain == chain_name || chain_name == lowercase)
+        && (contract == contract_address || contract_address == contract.to_lowercase());
+    if !canonical && (method == Method::GET || method == Method::HEAD) {
+        if let Some(path) = canonical_path(
+            full_path.as_str(),
+            &chain_name,
+            &contract_address,
+            &chain,
+            &contract,
+        ) {
+            let location = match query {
+                Some(query) => format!("{}?{}", path, query),
+                None => path,
+            };
+            return Err(warp::reject::custom(CanonicalRedirect { location }));
+        }
+    }
+    // Anything else is served as is, with the canonical values
+    Ok((chain, contract))
+}
+
+// The known chain spelled `lowercase` once lowercased, as stored
+fn stored_name(chains: &[String], lowercase: &str) -> Option<String> {
+    chains
+        .iter()
+        .find(|chain| chain.to_lowercase() == lowercase)
+        .cloned()
+}
+
+// The request path with its chain and contract segments replaced
+fn canonical_path(
+    path: &str,
+    chain_name: &str,
+    contract_address: &str,
+    chain: &str,
+    contract: &str,
+) -> Option<String> {
+    let mut segments: Vec<&str> = path.split('/').collect();
+    let position = segments
+        .windows(2)
+        .position(|pair| pair[0] == chain_name && pair[1] == contract_address)?;
+    segments[position] = chain;
+    segments[position + 1] = contract;
+    Some(segments.join("/"))
+}
+
+async fn chain_names(database: &Database) -> Result<Arc<Vec<String>>, ApiError> {
+    // Database errors aren't cached, the next request tries again
+    CHAIN_NAMES
+        .try_get_with((), async {
+            let client = database.read_client().await;
+            queries::get_chain_names(&client)
+                .await
+                .map(Arc::new)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to list chains: {}", e)))
+}
+
+async fn slug_address(database: &Database, chain: &str, slug: &str) -> Result<String, ApiError> {
+    let key = (chain.to_string(), slug.to_lowercase());
+    // Database errors aren't cached, the next request tries again
+    let address = SLUGS
+        .try_get_with(key, async {
+            let client = database.read_client().await;
+            queries::get_contract_address_by_slug(&client, chain, slug)
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to resolve {}: {}", slug, e)))?;
+    address.ok_or_else(|| ApiError::NotFound(format!("No contract {} on {}", slug, chain)))
+}
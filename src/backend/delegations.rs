@@ -0,0 +1,58 @@
+use crate::backend::errors::ApiError;
+use crate::backend::queries;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use tokio_postgres::Client;
+
+// Mirror of the delegations table
+#[derive(Default)]
+struct Delegations {
+    // lowercased delegate -> lowercased vaults
+    by_delegate: HashMap<String, Vec<String>>,
+    // (vault, delegate), in the order of get_delegations
+    ordered: Vec<(String, String)>,
+}
+
+static DELEGATIONS: Lazy<RwLock<Delegations>> = Lazy::new(|| RwLock::new(Delegations::default()));
+
+/// Reloads the delegations from the database, along with the exclusions and labels
+pub async fn reload(client: &Client) -> Result<(), ApiError> {
+    let rows = queries::get_delegations(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to load delegations: {}", e)))?;
+
+    let mut delegations = Delegations::default();
+    for (vault, delegate) in rows {
+        let (vault, delegate) = (vault.to_lowercase(), delegate.to_lowercase());
+        delegations
+            .by_delegate
+            .entry(delegate.clone())
+            .or_default()
+            .push(vault.clone());
+        delegations.ordered.push((vault, delegate));
+    }
+    *DELEGATIONS.write().expect("Delegations lock poisoned") = delegations;
+    Ok(())
+}
+
+/// Lowercased vaults that delegated to any of `delegates`
+pub fn vaults_for<'a>(delegates: impl IntoIterator<Item = &'a String>) -> HashSet<String> {
+    let delegations = DELEGATIONS.read().expect("Delegations lock poisoned");
+    delegates
+        .into_iter()
+        .filter_map(|delegate| delegations.by_delegate.get(&delegate.to_lowercase()))
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// (lowercased vault, lowercased delegate) of every delegation, the latest first and then
+/// by delegate. A vault delegated to several wallets goes to the first one that resolves.
+pub fn all() -> Vec<(String, String)> {
+    DELEGATIONS
+        .read()
+        .expect("Delegations lock poisoned")
+        .ordered
+        .clone()
+}
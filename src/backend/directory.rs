@@ -0,0 +1,111 @@
+use crate::backend::api::{get_or_update_all_users_collections, rank_leaderboard};
+use crate::backend::avatars;
+use crate::backend::levels::points_to_level;
+use crate::backend::projects::DEFAULT_PROJECT;
+use serde::Deserialize;
+use serde_json::json;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+const DEFAULT_DIRECTORY_LIMIT: usize = 50;
+const MAX_DIRECTORY_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectorySort {
+    // Highest level first, then most points
+    Level,
+    // Most points first, the leaderboard order
+    #[default]
+    Points,
+    // Alphabetically, case-insensitive
+    Name,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DirectoryQuery {
+    #[serde(default)]
+    sort: DirectorySort,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+/// Every registered user with their points, level, leaderboard rank and avatar, one page at
+/// a time. Users off the leaderboard have no points and no rank.
+pub async fn handle_get_users(
+    query: DirectoryQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let offset = query.offset.unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DIRECTORY_LIMIT)
+        .clamp(1, MAX_DIRECTORY_LIMIT);
+
+    let users = DEFAULT_PROJECT.users().await?;
+    let ranks: HashMap<String, (f64, usize)> =
+        rank_leaderboard(get_or_update_all_users_collections(&client, false).await?)
+            .into_iter()
+            .map(|(name, points, rank)| (name, (points, rank)))
+            .collect();
+
+    let mut roster: Vec<(&String, f64, i32, Option<usize>)> = users
+        .keys()
+        .map(|username| {
+            let (points, rank) = match ranks.get(username) {
+                Some((points, rank)) => (*points, Some(*rank)),
+                None => (0.0, None),
+            };
+            (username, points, points_to_level(points as i32), rank)
+        })
+        .collect();
+    let by_name = |a: &String, b: &String| {
+        a.to_lowercase()
+            .cmp(&b.to_lowercase())
+            .then_with(|| a.cmp(b))
+    };
+    let by_points = |a: f64, b: f64| b.partial_cmp(&a).unwrap_or(Ordering::Equal);
+    match query.sort {
+        DirectorySort::Level => roster.sort_by(|a, b| {
+            b.2.cmp(&a.2)
+                .then_with(|| by_points(a.1, b.1))
+                .then_with(|| by_name(a.0, b.0))
+        }),
+        DirectorySort::Points => {
+            roster.sort_by(|a, b| by_points(a.1, b.1).then_with(|| by_name(a.0, b.0)))
+        }
+        DirectorySort::Name => roster.sort_by(|a, b| by_name(a.0, b.0)),
+    }
+
+    let total = roster.len();
+    let page: Vec<_> = roster.into_iter().skip(offset).take(limit).collect();
+    let usernames: Vec<String> = page
+        .iter()
+        .map(|(username, _, _, _)| (*username).clone())
+        .collect();
+    let avatars = avatars::load_avatars_of(&client, &usernames).await;
+    let users: Vec<_> = page
+        .into_iter()
+        .map(|(username, points, level, rank)| {
+            json!({
+                "username": username,
+                "points": points,
+                "level": level,
+                "rank": rank,
+                "avatar": avatars.get(username),
+            })
+        })
+        .collect();
+
+    Ok(warp::reply::json(&json!({
+        "total": total,
+        "offset": offset,
+        "limit": limit,
+        "users": users,
+    }))
+    .into_response())
+}
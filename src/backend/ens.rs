@@ -0,0 +1,152 @@
+use crate::backend::errors::ApiError;
+use eth_checksum::checksum;
+use ethabi::{ParamType, Token};
+use moka::future::Cache;
+use once_cell::sync::Lazy;
+use std::env;
+use std::time::Duration;
+use web3::signing::keccak256;
+use web3::transports::Http;
+use web3::types::{Address, Bytes, CallRequest};
+use web3::Web3;
+
+// Mainnet ENS registry, the same address on the testnets
+const DEFAULT_ENS_REGISTRY: &str = "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+// keccak256("resolver(bytes32)")[..4]
+const RESOLVER_SELECTOR: [u8; 4] = [0x01, 0x78, 0xb8, 0xbf];
+// keccak256("addr(bytes32)")[..4]
+const ADDR_SELECTOR: [u8; 4] = [0x3b, 0x3b, 0x57, 0xde];
+
+// Names rarely move, unresolved names are cached too so typos don't hit the RPC each time
+const ENS_CACHE_TTL: Duration = Duration::from_secs(600);
+const ENS_CACHE_MAX_ENTRIES: u64 = 10_000;
+
+/// ENS resolution, enabled by AFTERLIFE_ENS_RPC_URL (an Ethereum mainnet RPC).
+/// AFTERLIFE_ENS_REGISTRY overrides the registry address.
+struct Ens {
+    web3: Web3<Http>,
+    registry: Address,
+}
+
+static ENS: Lazy<Option<Ens>> = Lazy::new(|| {
+    let rpc_url = env::var("AFTERLIFE_ENS_RPC_URL").ok()?;
+    let registry =
+        env::var("AFTERLIFE_ENS_REGISTRY").unwrap_or_else(|_| DEFAULT_ENS_REGISTRY.to_string());
+    let registry = match registry.parse::<Address>() {
+        Ok(registry) => registry,
+        Err(_) => {
+            eprintln!("ENS disabled: invalid registry address {}", registry);
+            return None;
+        }
+    };
+    match Http::new(&rpc_url) {
+        Ok(transport) => Some(Ens {
+            web3: Web3::new(transport),
+            registry,
+        }),
+        Err(e) => {
+            eprintln!("ENS disabled: invalid rpc url: {}", e);
+            None
+        }
+    }
+});
+
+// name -> checksummed address, None when the name doesn't resolve
+static RESOLVED: Lazy<Cache<String, Option<String>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(ENS_CACHE_MAX_ENTRIES)
+        .time_to_live(ENS_CACHE_TTL)
+        .build()
+});
+
+pub fn is_ens_name(value: &str) -> bool {
+    let value = value.to_ascii_lowercase();
+    value.len() > ".eth".len() && value.ends_with(".eth")
+}
+
+// EIP-137 namehash. Names are only lowercased, not fully UTS-46 normalized, so names
+// outside ASCII are refused rather than possibly resolved to the wrong node.
+fn namehash(name: &str) -> Result<[u8; 32], ApiError> {
+    if !name.is_ascii() || name.split('.').any(str::is_empty) {
+        return Err(ApiError::BadRequest(format!(
+            "Unsupported ENS name {}",
+            name
+        )));
+    }
+    let mut node = [0u8; 32];
+    for label in name.to_ascii_lowercase().rsplit('.') {
+        let mut buffer = node.to_vec();
+        buffer.extend_from_slice(&keccak256(label.as_bytes()));
+        node = keccak256(&buffer);
+    }
+    Ok(node)
+}
+
+// Calls a `(bytes32) returns (address)` function
+async fn call_with_node(
+    web3: &Web3<Http>,
+    contract: Address,
+    selector: [u8; 4],
+    node: [u8; 32],
+) -> Result<Address, String> {
+    let mut data = selector.to_vec();
+    data.extend_from_slice(&node);
+    let output = web3
+        .eth()
+        .call(
+            CallRequest {
+                to: Some(contract),
+                data: Some(Bytes(data)),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .map_err(|e| format!("eth_call failed: {}", e))?;
+    match ethabi::decode(&[ParamType::Address], &output.0)
+        .map_err(|e| format!("Invalid return data: {}", e))?
+        .pop()
+    {
+        Some(Token::Address(address)) => Ok(address),
+        _ => Err("Call did not return an address".to_string()),
+    }
+}
+
+async fn lookup(ens: &Ens, name: &str, node: [u8; 32]) -> Result<Option<String>, String> {
+    let resolver = call_with_node(&ens.web3, ens.registry, RESOLVER_SELECTOR, node).await?;
+    if resolver.is_zero() {
+        return Ok(None);
+    }
+    let address = call_with_node(&ens.web3, resolver, ADDR_SELECTOR, node)
+        .await
+        .map_err(|e| format!("{} resolver: {}", name, e))?;
+    if address.is_zero() {
+        return Ok(None);
+    }
+    Ok(Some(checksum(&format!("{:?}", address))))
+}
+
+/// Resolves an ENS name to its checksummed address
+pub async fn resolve(name: &str) -> Result<String, ApiError> {
+    let ens = ENS.as_ref().ok_or_else(|| {
+        ApiError::BadRequest(format!("Cannot resolve {}, ENS is not enabled", name))
+    })?;
+    let node = namehash(name)?;
+    let name = name.to_ascii_lowercase();
+    // RPC errors aren't cached, the next lookup tries again
+    let resolved = RESOLVED
+        .try_get_with(name.clone(), lookup(ens, &name, node))
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to resolve {}: {}", name, e)))?;
+    resolved.ok_or_else(|| ApiError::NotFound(format!("{} does not resolve to an address", name)))
+}
+
+/// Address (or username) parameters accept ENS names, which are replaced by the address
+/// they resolve to. Anything else is returned as is.
+pub async fn resolve_param(value: &str) -> Result<String, ApiError> {
+    if is_ens_name(value) {
+        resolve(value).await
+    } else {
+        Ok(value.to_string())
+    }
+}
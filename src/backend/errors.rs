@@ -0,0 +1,179 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use warp::http::StatusCode;
+use warp::reject::{Reject, Rejection};
+use warp::Filter;
+
+/// Errors returned by API handlers, each mapped to its own HTTP status
+/// and a stable machine-readable `code` in the response body.
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    // Missing or wrong credentials
+    Unauthorized(String),
+    // Over the rate limit of the API key or client address
+    TooManyRequests(String),
+    // A dependency (database, RPC) failed or is unreachable
+    Upstream(String),
+    // The handler didn't answer within the route's timeout
+    Timeout(String),
+    Internal(String),
+    // Bad input with its own code, so clients can tell which rule it broke, and the request
+    // body field at fault when there's one
+    Validation(&'static str, Option<String>, String),
+}
+
+impl Reject for ApiError {}
+
+impl ApiError {
+    /// A request body field that's invalid, named in the response
+    pub fn invalid_field(field: &str, message: String) -> ApiError {
+        ApiError::Validation("invalid_field", Some(field.to_string()), message)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) | ApiError::Validation(..) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::TooManyRequests(_) => "rate_limited",
+            ApiError::Upstream(_) => "upstream_error",
+            ApiError::Timeout(_) => "timeout",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Validation(code, ..) => code,
+        }
+    }
+
+    /// The request body field the error is about
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            ApiError::Validation(_, field, _) => field.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::TooManyRequests(message)
+            | ApiError::Upstream(message)
+            | ApiError::Timeout(message)
+            | ApiError::Internal(message)
+            | ApiError::Validation(_, _, message) => message,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub code: &'static str,
+    pub message: String,
+    // The request body field at fault, for validation errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    // Echoes the X-Request-Id header so reported failures can be matched to the access log
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Converts any rejection, ours or warp's built-in ones, into a status code and error body
+pub fn rejection_to_error(err: &Rejection) -> (StatusCode, ErrorResponse) {
+    if let Some(api_err) = err.find::<ApiError>() {
+        return (
+            api_err.status(),
+            ErrorResponse {
+                code: api_err.code(),
+                message: api_err.message().to_string(),
+                field: api_err.field().map(str::to_string),
+                request_id: None,
+            },
+        );
+    }
+
+    let (status, code, message) = if err.is_not_found() {
+        (
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "Route not found".to_string(),
+        )
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            "Request body is too large".to_string(),
+        )
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        (
+            StatusCode::LENGTH_REQUIRED,
+            "length_required",
+            "Content-Length header is required".to_string(),
+        )
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            "Request body must be JSON".to_string(),
+        )
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        // Not JSON at all, bodies that don't fit their type are rejected by json_body
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            "Method not allowed".to_string(),
+        )
+    } else {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "Unhandled error".to_string(),
+        )
+    };
+    (
+        status,
+        ErrorResponse {
+            code,
+            message,
+            field: None,
+            request_id: None,
+        },
+    )
+}
+
+/// A JSON request body of type `T`. A body that doesn't fit it is a 400 naming the field
+/// at fault, from the path serde was at when it failed.
+pub fn json_body<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Copy
+where
+    T: DeserializeOwned + Send,
+{
+    warp::body::json::<Value>().and_then(|body: Value| async move {
+        serde_path_to_error::deserialize(body).map_err(|e| {
+            // Errors about the body as a whole, a missing field say, are at its root
+            let field = Some(e.path().to_string()).filter(|path| path != ".");
+            warp::reject::custom(ApiError::Validation(
+                "bad_request",
+                field,
+                e.into_inner().to_string(),
+            ))
+        })
+    })
+}
@@ -0,0 +1,135 @@
+use crate::backend::addresses;
+use crate::backend::cache_events;
+use crate::backend::errors::ApiError;
+use crate::backend::projects;
+use crate::backend::queries;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+// lowercased project id -> usernames and addresses, mirror of the exclusions table
+static EXCLUSIONS: Lazy<RwLock<HashMap<String, Vec<String>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Debug, Deserialize)]
+pub struct NewExclusion {
+    // Username or address
+    pub value: String,
+    // Defaults to the default project
+    pub project: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Reloads the exclusions from the database. Runs on startup and with every leaderboard
+/// update, so exclusions made through another API instance are picked up too.
+pub async fn reload(client: &Client) -> Result<(), ApiError> {
+    let rows = queries::get_exclusions(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to load exclusions: {}", e)))?;
+
+    let mut exclusions: HashMap<String, Vec<String>> = HashMap::new();
+    for row in rows {
+        exclusions
+            .entry(row.project.to_lowercase())
+            .or_default()
+            .push(row.value);
+    }
+    *EXCLUSIONS.write().expect("Exclusions lock poisoned") = exclusions;
+    Ok(())
+}
+
+pub fn is_excluded(project_id: &str, username_or_address: &str) -> bool {
+    EXCLUSIONS
+        .read()
+        .expect("Exclusions lock poisoned")
+        .get(&project_id.to_lowercase())
+        .is_some_and(|values| {
+            values
+                .iter()
+                .any(|excluded| excluded.eq_ignore_ascii_case(username_or_address))
+        })
+}
+
+/// Usernames and addresses excluded from a project through the database
+pub fn excluded_values(project_id: &str) -> Vec<String> {
+    EXCLUSIONS
+        .read()
+        .expect("Exclusions lock poisoned")
+        .get(&project_id.to_lowercase())
+        .cloned()
+        .unwrap_or_default()
+}
+
+// Changes reach the leaderboard with its next update, notified to the leading replica
+pub async fn handle_create_exclusion(
+    body: NewExclusion,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let value = body.value.trim();
+    if value.is_empty() {
+        return Err(ApiError::invalid_field(
+            "value",
+            "Exclusion value must not be empty".to_string(),
+        )
+        .into());
+    }
+    // Usernames are taken as is, addresses must be valid
+    let value = if addresses::looks_like_address(value) {
+        addresses::normalize_field("value", value)?
+    } else {
+        value.to_string()
+    };
+    let value = value.as_str();
+    let project_id = body
+        .project
+        .as_deref()
+        .unwrap_or(projects::DEFAULT_PROJECT_ID);
+    let project = projects::find(project_id)
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown project {}", project_id)))?;
+
+    let exclusion = queries::add_exclusion(&client, &project.id, value, body.reason.as_deref())
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to add exclusion: {}", e)))?
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!("{} is already excluded from {}", value, project.id))
+        })?;
+    reload(&client).await?;
+    cache_events::notify_scores_changed(&client).await;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "exclusion": exclusion })),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+pub async fn handle_list_exclusions(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let exclusions = queries::get_exclusions(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to list exclusions: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({ "exclusions": exclusions })).into_response())
+}
+
+pub async fn handle_delete_exclusion(
+    id: i32,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let deleted = queries::delete_exclusion(&client, id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to delete exclusion: {}", e)))?;
+    if !deleted {
+        return Err(ApiError::NotFound(format!("Unknown exclusion {}", id)).into());
+    }
+    reload(&client).await?;
+    cache_events::notify_scores_changed(&client).await;
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
@@ -0,0 +1,158 @@
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::METADATA_BATCH_SIZE;
+use crate::backend::projects::Project;
+use crate::backend::repository::CollectionRepository;
+use crate::backend::score_weights;
+use crate::common::numeric::TokenId;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::Arc;
+use warp::http::header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
+use warp::hyper::Body;
+use warp::reply::Response;
+use web3::types::Address;
+
+const CSV_HEADER: &str =
+    "chain,contract,token_name,token_id,balance,rarity_score,score_contribution\r\n";
+
+// RFC 4180 quoting, only when the field needs it. Cells a spreadsheet would read as a
+// formula are prefixed with ' so they stay text.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// One row per token held by `address`, scored like /user/level (x1000, rounded). Rows
+/// are written a batch of tokens at a time as their metadata is read, the CSV is never
+/// held in memory as a whole.
+async fn stream_full_collection_csv(
+    project: Arc<Project>,
+    repository: &dyn CollectionRepository,
+    address: &str,
+    include_unverified: bool,
+) -> Result<Body, ApiError> {
+    let collection = repository
+        .user_full_collection(address, include_unverified)
+        .await
+        .map_err(|_| ApiError::Upstream("Failed to fetch user's full collection".to_string()))?;
+
+    let mut contracts: Vec<_> = collection
+        .into_iter()
+        .flat_map(|(chain, contracts)| {
+            contracts
+                .into_iter()
+                .map(move |(contract_address, tokens)| (chain.clone(), contract_address, tokens))
+        })
+        .collect();
+    contracts.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    // (chain, contract, rarity map, weight, tokens), at most METADATA_BATCH_SIZE tokens each,
+    // in row order
+    let mut batches = Vec::new();
+    for (chain, contract_address, tokens) in contracts {
+        let rarity_map = project
+            .metadata()
+            .rarity_map(&chain, &contract_address)
+            .await;
+        let weight = score_weights::factor(&chain, &contract_address, &rarity_map);
+        let chain: Arc<str> = chain.into();
+        let contract_address: Arc<str> = contract_address.into();
+        let mut tokens: Vec<_> = tokens.into_iter().collect();
+        tokens.sort_by_key(|(token_id, _)| *token_id);
+        for batch in tokens.chunks(METADATA_BATCH_SIZE) {
+            batches.push((
+                chain.clone(),
+                contract_address.clone(),
+                rarity_map.clone(),
+                weight,
+                batch.to_vec(),
+            ));
+        }
+    }
+
+    // One batch after the other, the rows follow the contracts and token ids
+    let lines = stream::iter(batches).then(
+        move |(chain, contract_address, rarity_map, weight, tokens)| {
+            let project = project.clone();
+            async move {
+                let token_ids: Vec<TokenId> =
+                    tokens.iter().map(|(token_id, _)| *token_id).collect();
+                let metadata = project
+                    .metadata()
+                    .tokens_metadata(&chain, &contract_address, &token_ids)
+                    .await;
+                let mut lines = String::new();
+                for (token_id, balance) in tokens {
+                    let token_name = metadata
+                        .get(&token_id)
+                        .and_then(|m| m["name"].as_str())
+                        .unwrap_or("");
+                    // Unscored tokens keep empty score columns rather than a misleading 0
+                    let (rarity_score, contribution) = match rarity_map.get(&token_id) {
+                        Some((rarity_score, _, _)) => (
+                            format!("{}", (rarity_score * 1000.0).round()),
+                            format!(
+                                "{}",
+                                (rarity_score * balance.to_f64() * weight * 1000.0).round()
+                            ),
+                        ),
+                        None => (String::new(), String::new()),
+                    };
+                    let _ = write!(
+                        lines,
+                        "{},{},{},{},{},{},{}\r\n",
+                        csv_field(&chain),
+                        csv_field(&contract_address),
+                        csv_field(token_name),
+                        token_id,
+                        balance,
+                        rarity_score,
+                        contribution
+                    );
+                }
+                lines
+            }
+        },
+    );
+    let lines = stream::once(future::ready(CSV_HEADER.to_string()))
+        .chain(lines)
+        .map(Ok::<_, Infallible>);
+    Ok(Body::wrap_stream(lines))
+}
+
+pub async fn full_collection_csv(
+    project: Arc<Project>,
+    repository: &dyn CollectionRepository,
+    address: &str,
+    include_unverified: bool,
+) -> Result<Response, ApiError> {
+    let parsed = address
+        .parse::<Address>()
+        .map_err(|_| ApiError::BadRequest(format!("Invalid address {}", address)))?;
+    let body = stream_full_collection_csv(project, repository, address, include_unverified).await?;
+
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/csv; charset=utf-8"),
+    );
+    // The parsed address is plain hex, safe in a header value
+    if let Ok(disposition) = HeaderValue::from_str(&format!(
+        "attachment; filename=\"collection-{:?}.csv\"",
+        parsed
+    )) {
+        headers.insert(CONTENT_DISPOSITION, disposition);
+    }
+    Ok(response)
+}
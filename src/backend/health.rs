@@ -0,0 +1,109 @@
+use crate::backend::api::is_leaderboard_ready;
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::METADATA_STORE;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::common::database::Database;
+use crate::common::storage::STORAGE;
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::reject::Rejection;
+
+// Liveness: the process is up and serving requests
+pub async fn handle_healthz() -> Result<impl warp::Reply, Rejection> {
+    Ok(warp::reply::json(&json!({ "status": "ok" })))
+}
+
+// Readiness: every dependency needed to serve real traffic is available
+pub async fn handle_readyz(database: Arc<Database>) -> Result<impl warp::Reply, Rejection> {
+    let client = database.client().await;
+    let reconnects = database.reconnects();
+    // A lagging replica isn't a readiness failure, reads fall back to the primary
+    let replica_in_sync = database.replica_in_sync();
+    let database = match client.simple_query("SELECT 1").await {
+        Ok(_) => json!({
+            "ok": true,
+            "reconnects": reconnects,
+            "replica_in_sync": replica_in_sync,
+        }),
+        Err(e) => json!({
+            "ok": false,
+            "reconnects": reconnects,
+            "replica_in_sync": replica_in_sync,
+            "error": e.to_string(),
+        }),
+    };
+    let leaderboard_cache = json!({ "ok": is_leaderboard_ready() });
+    let metadata_path = check_dir_readable(METADATA_STORE.metadata_root()).await;
+    let rarities_path = check_dir_readable(METADATA_STORE.rarities_root()).await;
+
+    let checks = json!({
+        "database": database,
+        "leaderboard_cache": leaderboard_cache,
+        "metadata_path": metadata_path,
+        "rarities_path": rarities_path,
+    });
+    let ready = checks
+        .as_object()
+        .map(|checks| checks.values().all(|check| check["ok"] == json!(true)))
+        .unwrap_or(false);
+
+    let (status, status_code) = if ready {
+        ("ok", StatusCode::OK)
+    } else {
+        ("unavailable", StatusCode::SERVICE_UNAVAILABLE)
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "status": status, "checks": checks })),
+        status_code,
+    ))
+}
+
+// Aggregate operational data for an ops dashboard, see the admin routes in api.rs
+pub async fn handle_admin_overview(database: Arc<Database>) -> Result<impl warp::Reply, Rejection> {
+    let client = database.client().await;
+    let upstream = |what: &str, e: Box<dyn std::error::Error + Send>| {
+        ApiError::Upstream(format!("Failed to get {}: {}", what, e))
+    };
+
+    let chains = queries::get_chain_activity(&client)
+        .await
+        .map_err(|e| upstream("chain activity", e))?;
+    let rpc_failures = queries::get_rpc_failure_counts(&client)
+        .await
+        .map_err(|e| upstream("RPC failures", e))?;
+    let database_size = queries::get_database_size(&client)
+        .await
+        .map_err(|e| upstream("database size", e))?;
+    let freshness = queries::get_data_freshness(&client)
+        .await
+        .map_err(|e| upstream("data freshness", e))?;
+    let metadata_backlog = queries::get_metadata_backlog(&client)
+        .await
+        .map_err(|e| upstream("metadata backlog", e))?;
+
+    Ok(warp::reply::json(&json!({
+        "chains": chains,
+        "rpc_failures": rpc_failures,
+        "database": {
+            "size_bytes": database_size,
+            "reconnects": database.reconnects(),
+            "replica_in_sync": database.replica_in_sync(),
+        },
+        "caches": {
+            "leaderboard_ready": is_leaderboard_ready(),
+            "response_cache_entries": response_cache::entry_count(),
+            "freshness": freshness,
+        },
+        "metadata_backlog": metadata_backlog,
+    })))
+}
+
+async fn check_dir_readable(path: &str) -> serde_json::Value {
+    match STORAGE.list(Path::new(path)).await {
+        Ok(_) => json!({ "ok": true, "path": path }),
+        Err(e) => json!({ "ok": false, "path": path, "error": e.to_string() }),
+    }
+}
@@ -0,0 +1,119 @@
+use crate::backend::errors::ApiError;
+use crate::backend::holdings::{contract_holdings, load_staking_addresses, load_user_holdings};
+use crate::backend::projects::Project;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::backend::usernames::addresses_for_name;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+pub async fn handle_get_user_stats(
+    project: Arc<Project>,
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!("{}user/stats/{}", project.cache_prefix(), username);
+    let response =
+        response_cache::get_or_compute(cache_key, build_user_stats(&project, &username, &client))
+            .await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// How long the user has held what they hold now, from the block timestamps of the
+// transfers that brought each token in. Only the project's contracts count.
+async fn build_user_stats(
+    project: &Project,
+    username: &str,
+    client: &Client,
+) -> Result<Value, ApiError> {
+    let user_addresses = addresses_for_name(&project.users().await?, username).await?;
+    if user_addresses.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)));
+    }
+
+    let holdings = load_user_holdings(client, &user_addresses).await?;
+    let addresses: Vec<String> = user_addresses.into_iter().collect();
+    let staking_addresses: Vec<String> = load_staking_addresses(client).await.into_iter().collect();
+    let history = queries::get_token_hold_history(client, &addresses, &staking_addresses)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get transfer history: {}", e)))?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0);
+
+    let mut transfers_in = 0;
+    let mut transfers_out = 0;
+    let mut tokens_held = 0;
+    let mut never_transferred = 0;
+    let mut total_hold_seconds: i64 = 0;
+    let mut timed_tokens: i64 = 0;
+    let mut longest_held: Option<&queries::TokenHoldHistory> = None;
+    for token in history
+        .iter()
+        .filter(|token| project.includes(&token.chain_name, &token.contract_address))
+    {
+        transfers_in += token.transfers_in;
+        transfers_out += token.transfers_out;
+
+        let held = contract_holdings(&holdings, &token.chain_name, &token.contract_address)
+            .is_some_and(|tokens| tokens.contains_key(&token.token_id));
+        if !held {
+            continue;
+        }
+        tokens_held += 1;
+        if token.minted && token.transfers_out == 0 {
+            never_transferred += 1;
+        }
+        if let Some(acquired_at) = token.acquired_at {
+            total_hold_seconds += (now - acquired_at).max(0);
+            timed_tokens += 1;
+            if longest_held
+                .is_none_or(|longest| longest.acquired_at.is_none_or(|since| acquired_at < since))
+            {
+                longest_held = Some(token);
+            }
+        }
+    }
+
+    let longest_held = match longest_held {
+        Some(token) => {
+            let token_name = project
+                .metadata()
+                .token_metadata(&token.chain_name, &token.contract_address, token.token_id)
+                .await
+                .and_then(|metadata| metadata["name"].as_str().map(str::to_string));
+            let held_since = token.acquired_at.unwrap_or(now);
+            Some(json!({
+                "chain": token.chain_name,
+                "contract_address": token.contract_address,
+                "token_id": token.token_id,
+                "token_name": token_name,
+                "held_since": held_since,
+                "held_seconds": (now - held_since).max(0),
+            }))
+        }
+        None => None,
+    };
+
+    Ok(json!({
+        "username": username,
+        "transfers": {
+            "in": transfers_in,
+            "out": transfers_out,
+            "total": transfers_in + transfers_out,
+        },
+        "tokens_held": tokens_held,
+        // Over the held tokens whose acquisition time is known
+        "average_hold_seconds": (timed_tokens > 0).then(|| total_hold_seconds / timed_tokens),
+        "longest_held": longest_held,
+        // Held since their mint to the user, never sent anywhere else
+        "never_transferred": never_transferred,
+    }))
+}
@@ -0,0 +1,134 @@
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::METADATA_STORE;
+use crate::backend::queries::{
+    get_staked_balances, get_staking_addresses, get_users_full_collections, StakedBalance,
+};
+use crate::backend::score_weights;
+use crate::common::numeric::{Balance, TokenId};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use tokio_postgres::Client;
+
+/// Balances of every address of a user merged together, keyed by (chain, contract)
+/// as stored in the database.
+pub type UserHoldings = HashMap<(String, String), HashMap<TokenId, Balance>>;
+
+pub async fn load_user_holdings(
+    client: &Client,
+    addresses: &HashSet<String>,
+) -> Result<UserHoldings, ApiError> {
+    let mut holdings: UserHoldings = HashMap::new();
+    let addresses: Vec<String> = addresses.iter().cloned().collect();
+    let collections = get_users_full_collections(client, &addresses)
+        .await
+        .map_err(|_| ApiError::Upstream("Failed to fetch user's full collection".to_string()))?;
+    for collection in collections.into_values() {
+        for (chain, contracts) in collection {
+            for (contract_address, tokens) in contracts {
+                let contract_holdings = holdings
+                    .entry((chain.clone(), contract_address))
+                    .or_default();
+                for (token_id, balance) in tokens {
+                    *contract_holdings.entry(token_id).or_default() += &balance;
+                }
+            }
+        }
+    }
+
+    // Staked tokens still belong to their depositor
+    for staked in load_staked_balances(client, Some(&addresses)).await {
+        *holdings
+            .entry((staked.chain_name, staked.contract_address))
+            .or_default()
+            .entry(staked.token_id)
+            .or_default() += &staked.balance;
+    }
+
+    for tokens in holdings.values_mut() {
+        tokens.retain(|_, balance| balance.is_positive());
+    }
+    holdings.retain(|_, tokens| !tokens.is_empty());
+
+    Ok(holdings)
+}
+
+// Staking is optional, a deployment without the staking tables just has no staked tokens
+pub async fn load_staked_balances(
+    client: &Client,
+    addresses: Option<&[String]>,
+) -> Vec<StakedBalance> {
+    get_staked_balances(client, addresses)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to get staked balances: {}", e);
+            Vec::new()
+        })
+}
+
+pub async fn load_staking_addresses(client: &Client) -> HashSet<String> {
+    get_staking_addresses(client).await.unwrap_or_else(|e| {
+        eprintln!("Failed to get staking contracts: {}", e);
+        HashSet::new()
+    })
+}
+
+/// Tokens held on a contract, matching chain name and address case-insensitively
+pub fn contract_holdings<'a>(
+    holdings: &'a UserHoldings,
+    chain_name: &str,
+    contract_address: &str,
+) -> Option<&'a HashMap<TokenId, Balance>> {
+    holdings
+        .iter()
+        .find(|((chain, contract), _)| {
+            chain.eq_ignore_ascii_case(chain_name)
+                && contract.eq_ignore_ascii_case(contract_address)
+        })
+        .map(|(_, tokens)| tokens)
+}
+
+/// Afterlife points of the holdings, computed the same way as in /user/level
+pub async fn afterlife_points(holdings: &UserHoldings) -> f64 {
+    let mut total_rarity_score: f64 = 0.0;
+    for ((chain, contract_address), tokens) in holdings {
+        let rarity_map = METADATA_STORE.rarity_map(chain, contract_address).await;
+        let weight = score_weights::factor(chain, contract_address, &rarity_map);
+        for (token_id, balance) in tokens {
+            if let Some((rarity_score, _, _)) = rarity_map.get(token_id) {
+                total_rarity_score += rarity_score * balance.to_f64() * weight;
+            }
+        }
+    }
+    (total_rarity_score * 1000.0).round()
+}
+
+/// The `limit` held tokens with the highest rarity scores, best first
+pub async fn top_nfts(holdings: &UserHoldings, limit: usize) -> Vec<Value> {
+    let mut scored: Vec<(f64, TokenId, &String, &String)> = Vec::new();
+    for ((chain, contract_address), tokens) in holdings {
+        let rarity_map = METADATA_STORE.rarity_map(chain, contract_address).await;
+        for token_id in tokens.keys() {
+            if let Some(&(rarity_score, _, _)) = rarity_map.get(token_id) {
+                scored.push((rarity_score, *token_id, chain, contract_address));
+            }
+        }
+    }
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    scored.truncate(limit);
+
+    let mut top = Vec::with_capacity(scored.len());
+    for (rarity_score, token_id, chain, contract_address) in scored {
+        let token_name = METADATA_STORE
+            .token_metadata(chain, contract_address, token_id)
+            .await
+            .and_then(|metadata| metadata["name"].as_str().map(str::to_string));
+        top.push(json!({
+            "token_id": token_id,
+            "token_name": token_name,
+            "chain": chain,
+            "contract_address": contract_address,
+            "rarity_score": (rarity_score * 1000.0).round(),
+        }));
+    }
+    top
+}
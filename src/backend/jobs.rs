@@ -0,0 +1,505 @@
+use crate::backend::api::get_or_update_all_users_collections;
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::METADATA_STORE;
+use crate::backend::queries::{self, JobRow};
+use crate::backend::rarity;
+use crate::backend::response_cache;
+use crate::backend::score_weights;
+use crate::common::database::Database;
+use crate::indexer::indexer_config::IndexerConfig;
+use crate::metadata::token_uri::fetch_token_metadata;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_postgres::Client;
+use warp::http::header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
+use warp::reject::Rejection;
+use warp::Reply;
+use web3::transports::Http;
+use web3::types::Address;
+use web3::Web3;
+
+// How long the worker sleeps when the queue is empty
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+// Min time between two progress writes of a job
+const PROGRESS_WRITE_INTERVAL: Duration = Duration::from_secs(1);
+// A running job whose lease isn't renewed in that time is claimed by another worker
+const JOB_LEASE: Duration = Duration::from_secs(60);
+const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(20);
+// Runs of a job before it's failed, when its workers keep dying
+const MAX_JOB_ATTEMPTS: i32 = 3;
+
+/// An expensive operation run in the background by the job worker, stored as the params
+/// of its jobs row
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    // Recomputes a contract's rarity file, then the leaderboard
+    RarityRecompute {
+        chain: String,
+        contract_address: String,
+    },
+    // Re-fetches the metadata of every token of a contract, then recomputes its rarity.
+    // The RPC and contract type come from the indexer config when not given.
+    MetadataRefresh {
+        chain: String,
+        contract_address: String,
+        #[serde(default)]
+        rpc_url: Option<String>,
+        #[serde(default)]
+        contract_type: Option<String>,
+    },
+    // Writes the current owners of every token of a contract to a CSV file, stored with the
+    // job so that any replica can serve it
+    HoldersSnapshot {
+        chain: String,
+        contract_address: String,
+    },
+    // Moves the events and sales of a contract removed from the indexer config to the archive,
+    // then drops the caches and leaderboard points built from them
+    ContractArchive {
+        chain: String,
+        contract_address: String,
+    },
+}
+
+impl Job {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Job::RarityRecompute { .. } => "rarity_recompute",
+            Job::MetadataRefresh { .. } => "metadata_refresh",
+            Job::HoldersSnapshot { .. } => "holders_snapshot",
+            Job::ContractArchive { .. } => "contract_archive",
+        }
+    }
+}
+
+/// Queues a job, any replica's worker picks it up
+pub async fn enqueue(client: &Client, job: &Job) -> Result<JobRow, ApiError> {
+    let params = serde_json::to_value(job)
+        .map_err(|e| ApiError::Internal(format!("Failed to encode job: {}", e)))?;
+    queries::insert_job(client, job.kind(), &params)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to queue job: {}", e)))
+}
+
+/// Progress of the running job, written to its row at most every PROGRESS_WRITE_INTERVAL
+struct Progress<'a> {
+    client: &'a Client,
+    id: i32,
+    last_write: Option<Instant>,
+}
+
+impl<'a> Progress<'a> {
+    async fn update(&mut self, done: usize, total: usize) {
+        if self
+            .last_write
+            .is_some_and(|last| last.elapsed() < PROGRESS_WRITE_INTERVAL)
+            && done < total
+        {
+            return;
+        }
+        self.last_write = Some(Instant::now());
+        let done = i32::try_from(done).unwrap_or(i32::MAX);
+        let total = i32::try_from(total).ok();
+        if let Err(e) = queries::update_job_progress(self.client, self.id, done, total).await {
+            eprintln!("Job {}: failed to record progress: {}", self.id, e);
+        }
+    }
+}
+
+/// Runs the queued jobs one at a time, forever
+pub fn spawn_worker(database: Arc<Database>) {
+    tokio::spawn(async move {
+        loop {
+            let client = database.client().await;
+            let claimed = queries::claim_job(&client, JOB_LEASE.as_secs_f64(), MAX_JOB_ATTEMPTS)
+                .await
+                .map_err(|e| e.to_string());
+            match claimed {
+                Ok(Some(row)) => {
+                    let id = row.id;
+                    tokio::select! {
+                        _ = run(&client, row) => {}
+                        _ = renew_lease(&client, id) => {}
+                    }
+                }
+                Ok(None) => tokio::time::sleep(IDLE_POLL_INTERVAL).await,
+                Err(e) => {
+                    eprintln!("Failed to claim a job: {}", e);
+                    tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+                }
+            }
+        }
+    });
+}
+
+// Keeps the lease of the running job, until the job is done and this future dropped
+async fn renew_lease(client: &Client, id: i32) {
+    loop {
+        tokio::time::sleep(LEASE_RENEW_INTERVAL).await;
+        if let Err(e) = queries::renew_job_lease(client, id, JOB_LEASE.as_secs_f64()).await {
+            eprintln!("Job {}: failed to renew its lease: {}", id, e);
+        }
+    }
+}
+
+async fn run(client: &Client, row: JobRow) {
+    println!("Job {}: running {}", row.id, row.kind);
+    let mut progress = Progress {
+        client,
+        id: row.id,
+        last_write: None,
+    };
+    let outcome = match serde_json::from_value::<Job>(row.params) {
+        Ok(job) => execute(client, &job, row.id, &mut progress).await,
+        Err(e) => Err(format!("Invalid job: {}", e)),
+    };
+
+    let finished = match &outcome {
+        Ok(result) => {
+            println!("Job {}: done", row.id);
+            queries::finish_job(client, row.id, Some(result), None).await
+        }
+        Err(e) => {
+            eprintln!("Job {}: failed: {}", row.id, e);
+            queries::finish_job(client, row.id, None, Some(e)).await
+        }
+    };
+    if let Err(e) = finished {
+        eprintln!("Job {}: failed to record the outcome: {}", row.id, e);
+    }
+}
+
+async fn execute(
+    client: &Client,
+    job: &Job,
+    id: i32,
+    progress: &mut Progress<'_>,
+) -> Result<Value, String> {
+    match job {
+        Job::RarityRecompute {
+            chain,
+            contract_address,
+        } => {
+            let ranked = recompute_rarity(client, chain, contract_address).await?;
+            Ok(json!({ "ranked_tokens": ranked }))
+        }
+        Job::MetadataRefresh {
+            chain,
+            contract_address,
+            rpc_url,
+            contract_type,
+        } => {
+            let (rpc_url, contract_type) = match (rpc_url, contract_type) {
+                (Some(rpc_url), Some(contract_type)) => (rpc_url.clone(), contract_type.clone()),
+                _ => {
+                    let (configured_url, configured_type) =
+                        configured_contract(chain, contract_address)?;
+                    (
+                        rpc_url.clone().unwrap_or(configured_url),
+                        contract_type.clone().unwrap_or(configured_type),
+                    )
+                }
+            };
+            let (refreshed, failed) = refresh_metadata(
+                client,
+                chain,
+                contract_address,
+                &rpc_url,
+                &contract_type,
+                progress,
+            )
+            .await?;
+            let ranked = recompute_rarity(client, chain, contract_address).await?;
+            Ok(json!({
+                "refreshed_tokens": refreshed,
+                "failed_tokens": failed,
+                "ranked_tokens": ranked,
+            }))
+        }
+        Job::HoldersSnapshot {
+            chain,
+            contract_address,
+        } => {
+            let balances = queries::get_contract_balances(client, chain, contract_address)
+                .await
+                .map_err(|e| format!("Failed to get balances: {}", e))?;
+            let mut csv = String::from("token_id,owner,balance\n");
+            for (owner, token_id, balance) in &balances {
+                csv.push_str(&format!("{},{},{}\n", token_id, owner, balance));
+            }
+            queries::save_job_file(client, id, csv.as_bytes())
+                .await
+                .map_err(|e| format!("Failed to save snapshot: {}", e))?;
+            progress.update(balances.len(), balances.len()).await;
+            Ok(json!({
+                "file": format!("/jobs/{}/download", id),
+                "rows": balances.len(),
+            }))
+        }
+        Job::ContractArchive {
+            chain,
+            contract_address,
+        } => {
+            let archived = queries::archive_contract(client, chain, contract_address)
+                .await
+                .map_err(|e| format!("Failed to archive: {}", e))?
+                .ok_or_else(|| format!("No contract {} on {}", contract_address, chain))?;
+            response_cache::invalidate_all();
+            score_weights::reload(client)
+                .await
+                .map_err(|e| e.message().to_string())?;
+            get_or_update_all_users_collections(client, true)
+                .await
+                .map_err(|e| e.message().to_string())?;
+            Ok(json!(archived))
+        }
+    }
+}
+
+async fn recompute_rarity(
+    client: &Client,
+    chain: &str,
+    contract_address: &str,
+) -> Result<usize, String> {
+    let ranked = rarity::recompute(&METADATA_STORE, chain, contract_address)
+        .await
+        .map_err(|e| e.message().to_string())?;
+    // Scores depend on rarities, don't wait for the next scheduled leaderboard update
+    get_or_update_all_users_collections(client, true)
+        .await
+        .map_err(|e| e.message().to_string())?;
+    Ok(ranked)
+}
+
+// (rpc url, contract type) of a contract in the indexer config
+// The indexer config, None when the API runs without one (AFTERLIFE_PATH_IDXCFG unset)
+fn indexer_config() -> Result<Option<IndexerConfig>, String> {
+    let path = match env::var("AFTERLIFE_PATH_IDXCFG") {
+        Ok(path) => path,
+        Err(_) => return Ok(None),
+    };
+    // IndexerConfig::from_env panics on a missing file
+    if !Path::new(&path).is_file() {
+        return Err(format!("Indexer config {} not found", path));
+    }
+    IndexerConfig::from_env()
+        .map(Some)
+        .map_err(|e| format!("Invalid indexer config {}: {}", path, e))
+}
+
+fn configured_contract(chain: &str, contract_address: &str) -> Result<(String, String), String> {
+    let config = indexer_config()?
+        .ok_or_else(|| "No indexer config to find the contract's RPC in".to_string())?;
+    config
+        .chains
+        .iter()
+        .filter(|c| c.name.eq_ignore_ascii_case(chain))
+        .find_map(|c| {
+            let contract = c
+                .contracts
+                .iter()
+                .find(|contract| contract.address.eq_ignore_ascii_case(contract_address))?;
+            Some((c.rpc_endpoints().first()?.clone(), contract.r#type.clone()))
+        })
+        .ok_or_else(|| {
+            format!(
+                "Contract {} on {} isn't in the indexer config",
+                contract_address, chain
+            )
+        })
+}
+
+// Returns (refreshed, failed) token counts
+async fn refresh_metadata(
+    client: &Client,
+    chain: &str,
+    contract_address: &str,
+    rpc_url: &str,
+    contract_type: &str,
+    progress: &mut Progress<'_>,
+) -> Result<(usize, usize), String> {
+    let address: Address = contract_address
+        .parse()
+        .map_err(|_| format!("Invalid contract address {}", contract_address))?;
+    let web3 = Web3::new(Http::new(rpc_url).map_err(|e| format!("Invalid RPC url: {}", e))?);
+    let token_ids = queries::get_entire_collection(client, chain, contract_address)
+        .await
+        .map_err(|e| format!("Failed to list tokens: {}", e))?;
+
+    let mut failed = 0;
+    for (done, token_id) in token_ids.iter().enumerate() {
+        let result = match fetch_token_metadata(&web3, address, contract_type, token_id.0).await {
+            Ok(metadata) => {
+                METADATA_STORE
+                    .save_token_metadata(chain, contract_address, *token_id, &metadata)
+                    .await
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "Metadata refresh: [{}] {} token {}: {}",
+                chain, contract_address, token_id, e
+            );
+            failed += 1;
+        }
+        progress.update(done + 1, token_ids.len()).await;
+    }
+    Ok((token_ids.len() - failed, failed))
+}
+
+// Both end up in paths, see rarity::recompute
+fn validate_contract(chain: &str, contract_address: &str) -> Result<(), ApiError> {
+    if !chain
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        || contract_address.parse::<Address>().is_err()
+    {
+        return Err(ApiError::BadRequest(
+            "Invalid chain or contract address".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+async fn enqueue_reply(client: &Client, job: Job) -> Result<warp::reply::Response, Rejection> {
+    let row = enqueue(client, &job).await?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "job": row })),
+        warp::http::StatusCode::ACCEPTED,
+    )
+    .into_response())
+}
+
+pub async fn handle_queue_rarity_recompute(
+    chain: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    validate_contract(&chain, &contract_address)?;
+    enqueue_reply(
+        &client,
+        Job::RarityRecompute {
+            chain,
+            contract_address,
+        },
+    )
+    .await
+}
+
+pub async fn handle_queue_metadata_refresh(
+    chain: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    validate_contract(&chain, &contract_address)?;
+    enqueue_reply(
+        &client,
+        Job::MetadataRefresh {
+            chain,
+            contract_address,
+            rpc_url: None,
+            contract_type: None,
+        },
+    )
+    .await
+}
+
+pub async fn handle_queue_holders_snapshot(
+    chain: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    validate_contract(&chain, &contract_address)?;
+    enqueue_reply(
+        &client,
+        Job::HoldersSnapshot {
+            chain,
+            contract_address,
+        },
+    )
+    .await
+}
+
+// Only contracts the indexer no longer writes to can be archived, or their events would
+// come back on its next run
+pub async fn handle_queue_contract_archive(
+    chain: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    validate_contract(&chain, &contract_address)?;
+    // A config that can't be read might still list the contract, archiving it would
+    // leave the indexer writing to an archived contract
+    let config = indexer_config().map_err(|e| {
+        ApiError::Internal(format!(
+            "Can't tell whether the contract is configured: {}",
+            e
+        ))
+    })?;
+    let configured = config.is_some_and(|config| {
+        config.chains.iter().any(|c| {
+            c.name.eq_ignore_ascii_case(&chain)
+                && c.contracts
+                    .iter()
+                    .any(|contract| contract.address.eq_ignore_ascii_case(&contract_address))
+        })
+    });
+    if configured {
+        return Err(ApiError::BadRequest(format!(
+            "Contract {} on {} is still in the indexer config, remove it first",
+            contract_address, chain
+        ))
+        .into());
+    }
+    enqueue_reply(
+        &client,
+        Job::ContractArchive {
+            chain,
+            contract_address,
+        },
+    )
+    .await
+}
+
+async fn load_job(client: &Client, id: i32) -> Result<JobRow, ApiError> {
+    queries::get_job(client, id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get job: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No job {}", id)))
+}
+
+pub async fn handle_get_job(id: i32, client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let job = load_job(&client, id).await?;
+    Ok(warp::reply::json(&json!({ "job": job })).into_response())
+}
+
+// The file written by a finished snapshot job
+pub async fn handle_download_job_result(
+    id: i32,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    load_job(&client, id).await?;
+    let contents = queries::get_job_file(&client, id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to read the file of job {}: {}", id, e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("Job {} has no file to download", id)))?;
+
+    let mut response = contents.into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/csv; charset=utf-8"),
+    );
+    if let Ok(disposition) =
+        HeaderValue::from_str(&format!("attachment; filename=\"job-{}.csv\"", id))
+    {
+        headers.insert(CONTENT_DISPOSITION, disposition);
+    }
+    Ok(response)
+}
@@ -0,0 +1,115 @@
+use crate::backend::addresses;
+use crate::backend::errors::ApiError;
+use crate::backend::queries::{self, LabelRow};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+// lowercased address -> label, mirror of the labels table
+static LABELS: Lazy<RwLock<HashMap<String, LabelRow>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Debug, Deserialize)]
+pub struct LabelUpdate {
+    pub label: String,
+    pub kind: String,
+    #[serde(default)]
+    pub excluded: bool,
+}
+
+/// Reloads the labels from the database, along with the exclusions
+pub async fn reload(client: &Client) -> Result<(), ApiError> {
+    let rows = queries::get_labels(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to load labels: {}", e)))?;
+
+    *LABELS.write().expect("Labels lock poisoned") = rows
+        .into_iter()
+        .map(|row| (row.address.to_lowercase(), row))
+        .collect();
+    Ok(())
+}
+
+pub fn label_for(address: &str) -> Option<String> {
+    LABELS
+        .read()
+        .expect("Labels lock poisoned")
+        .get(&address.to_lowercase())
+        .map(|row| row.label.clone())
+}
+
+pub fn is_protocol_owned(address: &str) -> bool {
+    LABELS
+        .read()
+        .expect("Labels lock poisoned")
+        .get(&address.to_lowercase())
+        .is_some_and(|row| row.excluded)
+}
+
+/// Lowercased addresses of every protocol owned address
+pub fn protocol_owned_addresses() -> Vec<String> {
+    LABELS
+        .read()
+        .expect("Labels lock poisoned")
+        .values()
+        .filter(|row| row.excluded)
+        .map(|row| row.address.to_lowercase())
+        .collect()
+}
+
+pub async fn handle_set_label(
+    address: String,
+    body: LabelUpdate,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let address = addresses::normalize(&address)?;
+    if body.label.trim().is_empty() {
+        return Err(ApiError::invalid_field("label", "Label must not be empty".to_string()).into());
+    }
+    if body.kind.trim().is_empty() {
+        return Err(ApiError::invalid_field("kind", "Kind must not be empty".to_string()).into());
+    }
+
+    let label = LabelRow {
+        address: address.to_lowercase(),
+        label: body.label.trim().to_string(),
+        kind: body.kind.trim().to_lowercase(),
+        excluded: body.excluded,
+    };
+    queries::set_label(&client, &label)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to set label: {}", e)))?;
+    reload(&client).await?;
+
+    Ok(warp::reply::json(&json!({ "label": label })).into_response())
+}
+
+pub async fn handle_list_labels(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let labels = queries::get_labels(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to list labels: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({ "labels": labels })).into_response())
+}
+
+pub async fn handle_delete_label(
+    address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let deleted = queries::delete_label(&client, &address)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to delete label: {}", e)))?;
+    if !deleted {
+        return Err(ApiError::NotFound(format!("No label for {}", address)).into());
+    }
+    reload(&client).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
@@ -0,0 +1,127 @@
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::env;
+use std::str::FromStr;
+use warp::reject::Rejection;
+use warp::Reply;
+
+const DEFAULT_BASE_XP: f64 = 100.0;
+const DEFAULT_GROWTH: f64 = 1.0625;
+const DEFAULT_MAX_LEVEL: i32 = 60;
+
+/// Geometric level curve: going from level n to n + 1 takes `base_xp * growth^(n - 1)`
+/// points, so level n needs `base_xp * (growth^(n - 1) - 1) / (growth - 1)` points in total.
+///
+/// Configured with AFTERLIFE_LEVEL_BASE_XP, AFTERLIFE_LEVEL_GROWTH and AFTERLIFE_LEVEL_MAX.
+#[derive(Debug, Clone, Copy)]
+pub struct LevelCurve {
+    pub base_xp: f64,
+    pub growth: f64,
+    pub max_level: i32,
+}
+
+pub static LEVEL_CURVE: Lazy<LevelCurve> = Lazy::new(LevelCurve::from_env);
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid {} {}, using the default", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+impl Default for LevelCurve {
+    fn default() -> Self {
+        Self {
+            base_xp: DEFAULT_BASE_XP,
+            growth: DEFAULT_GROWTH,
+            max_level: DEFAULT_MAX_LEVEL,
+        }
+    }
+}
+
+impl LevelCurve {
+    pub fn from_env() -> Self {
+        let curve = Self {
+            base_xp: env_or("AFTERLIFE_LEVEL_BASE_XP", DEFAULT_BASE_XP),
+            growth: env_or("AFTERLIFE_LEVEL_GROWTH", DEFAULT_GROWTH),
+            max_level: env_or("AFTERLIFE_LEVEL_MAX", DEFAULT_MAX_LEVEL),
+        };
+        // A shrinking or flat-zero curve would make every level free
+        if curve.base_xp <= 0.0 || curve.growth < 1.0 || curve.max_level < 1 {
+            eprintln!("Invalid level curve {:?}, using the default", curve);
+            return Self::default();
+        }
+        curve
+    }
+
+    /// Total points needed to reach `level`
+    pub fn cumulative_xp(&self, level: i32) -> f64 {
+        if level <= 1 {
+            return 0.0;
+        }
+        if self.growth == 1.0 {
+            return self.base_xp * (level - 1) as f64;
+        }
+        self.base_xp * (self.growth.powi(level - 1) - 1.0) / (self.growth - 1.0)
+    }
+
+    pub fn level_for(&self, points: i32) -> i32 {
+        // Level 0 until the first point
+        if points <= 0 {
+            return 0;
+        }
+
+        let mut level = 1;
+        while level < self.max_level && (points as f64) >= self.cumulative_xp(level + 1) {
+            level += 1;
+        }
+        level
+    }
+
+    /// Where `points` stand within their level
+    pub fn progress(&self, points: f64) -> Value {
+        let level = self.level_for(points as i32);
+        let level_xp = self.cumulative_xp(level);
+        let next_level_xp = (level < self.max_level).then(|| self.cumulative_xp(level + 1));
+
+        json!({
+            "points": points,
+            "level": level,
+            "level_xp": level_xp,
+            "next_level_xp": next_level_xp,
+            "points_into_level": points - level_xp,
+            // None at the max level
+            "points_to_next_level": next_level_xp.map(|xp| xp - points),
+            "progress": next_level_xp.map(|xp| (points - level_xp) / (xp - level_xp)),
+        })
+    }
+}
+
+pub fn points_to_level(points: i32) -> i32 {
+    LEVEL_CURVE.level_for(points)
+}
+
+pub async fn handle_get_levels() -> Result<impl warp::Reply, Rejection> {
+    let curve = *LEVEL_CURVE;
+    let levels: Vec<Value> = (1..=curve.max_level)
+        .map(|level| {
+            json!({
+                "level": level,
+                "xp_required": curve.cumulative_xp(level),
+                "xp_to_next": (level < curve.max_level)
+                    .then(|| curve.cumulative_xp(level + 1) - curve.cumulative_xp(level)),
+            })
+        })
+        .collect();
+
+    Ok(warp::reply::json(&json!({
+        "base_xp": curve.base_xp,
+        "growth": curve.growth,
+        "max_level": curve.max_level,
+        "levels": levels,
+    }))
+    .into_response())
+}
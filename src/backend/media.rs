@@ -0,0 +1,131 @@
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::METADATA_STORE;
+use crate::common::numeric::TokenId;
+use crate::metadata::ipfs::CONTENT_FETCHER;
+use eth_checksum::checksum;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use serde::Deserialize;
+use std::env;
+use std::io::Cursor;
+use std::path::PathBuf;
+use warp::http::header::{HeaderValue, CONTENT_TYPE};
+use warp::reject::Rejection;
+use warp::Reply;
+use web3::types::Address;
+
+// Thumbnail edge lengths that may be requested, anything else would let clients fill the disk
+const ALLOWED_SIZES: [u32; 5] = [64, 128, 256, 512, 1024];
+const DEFAULT_SIZE: u32 = 256;
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct MediaQuery {
+    size: Option<u32>,
+}
+
+fn media_cache_root() -> String {
+    env::var("AFTERLIFE_PATH_MEDIA_CACHE").unwrap_or_else(|_| "media_cache".to_owned())
+}
+
+fn thumbnail_path(
+    chain_name: &str,
+    contract_address: &str,
+    token_id: TokenId,
+    size: u32,
+) -> PathBuf {
+    PathBuf::from(format!(
+        "{}/{}/{}/{}_{}.png",
+        media_cache_root(),
+        chain_name,
+        checksum(contract_address),
+        token_id,
+        size
+    ))
+}
+
+fn resize_to_png(original: &[u8], size: u32) -> Result<Vec<u8>, ApiError> {
+    let image = image::load_from_memory(original)
+        .map_err(|e| ApiError::Upstream(format!("Failed to decode image: {}", e)))?;
+    let mut png = Vec::new();
+    image
+        .resize(size, size, FilterType::Lanczos3)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| ApiError::Internal(format!("Failed to encode thumbnail: {}", e)))?;
+    Ok(png)
+}
+
+// Writes through a temporary file so a concurrent reader never sees a half-written thumbnail
+async fn store_thumbnail(path: &PathBuf, png: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp_path = path.with_extension(format!("{}.tmp", rand::random::<u32>()));
+    tokio::fs::write(&tmp_path, png).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+async fn load_thumbnail(
+    chain_name: &str,
+    contract_address: &str,
+    token_id: TokenId,
+    size: u32,
+) -> Result<Vec<u8>, ApiError> {
+    let path = thumbnail_path(chain_name, contract_address, token_id, size);
+    if let Ok(png) = tokio::fs::read(&path).await {
+        return Ok(png);
+    }
+
+    let metadata = METADATA_STORE
+        .token_metadata(chain_name, contract_address, token_id)
+        .await
+        .ok_or_else(|| ApiError::NotFound(format!("No metadata for token {}", token_id)))?;
+    let image = metadata["image"]
+        .as_str()
+        .ok_or_else(|| ApiError::NotFound(format!("Token {} has no image", token_id)))?;
+
+    let original = CONTENT_FETCHER
+        .fetch(image, MAX_IMAGE_BYTES)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to fetch image: {}", e)))?;
+    let png = tokio::task::spawn_blocking(move || resize_to_png(&original, size))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Resize task failed: {}", e)))??;
+
+    if let Err(e) = store_thumbnail(&path, &png).await {
+        eprintln!("Failed to cache thumbnail {}: {}", path.display(), e);
+    }
+    Ok(png)
+}
+
+pub async fn handle_get_media(
+    chain_name: String,
+    contract_address: String,
+    token_id: TokenId,
+    query: MediaQuery,
+) -> Result<impl warp::Reply, Rejection> {
+    // Both end up in a path we write to
+    if !chain_name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        || contract_address.parse::<Address>().is_err()
+    {
+        return Err(ApiError::BadRequest("Invalid chain or contract address".to_string()).into());
+    }
+    let size = query.size.unwrap_or(DEFAULT_SIZE);
+    if !ALLOWED_SIZES.contains(&size) {
+        return Err(ApiError::BadRequest(format!(
+            "Unsupported size {}, expected one of {:?}",
+            size, ALLOWED_SIZES
+        ))
+        .into());
+    }
+
+    let png = load_thumbnail(&chain_name, &contract_address, token_id, size).await?;
+
+    let mut response = png.into_response();
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+    Ok(response)
+}
@@ -0,0 +1,574 @@
+use crate::backend::queries;
+use crate::backend::rarity;
+use crate::backend::rarity_models::Traits;
+use crate::common::database::Database;
+use crate::common::numeric::TokenId;
+use crate::common::storage::STORAGE;
+use eth_checksum::checksum;
+use futures::stream::{self, StreamExt};
+use moka::future::Cache;
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_postgres::Client;
+
+// token_id -> (rarity_score, rarity_index, rarity_percentile)
+pub type RarityMap = HashMap<TokenId, (f64, u64, f64)>;
+
+// token_id -> trait_type -> value, of every token of a contract with metadata
+pub type TraitIndex = HashMap<TokenId, Traits>;
+
+pub static METADATA_STORE: Lazy<MetadataStore> = Lazy::new(MetadataStore::from_env);
+
+// A file as last read, None for files that didn't exist or couldn't be parsed
+struct CachedFile<T> {
+    version: Option<String>,
+    value: Option<Arc<T>>,
+    checked_at: Instant,
+}
+
+type FileCache<T> = Cache<PathBuf, Arc<CachedFile<T>>>;
+
+// Same lifetime as cached responses, a metadata refresh shows up within a minute
+const METADATA_ROW_TTL: Duration = Duration::from_secs(60);
+// Cached files are trusted that long before their version is checked again
+const FILE_REVALIDATE_AFTER: Duration = Duration::from_secs(60);
+const METADATA_ROW_MAX_ENTRIES: u64 = 100_000;
+const METADATA_FILE_MAX_ENTRIES: u64 = 100_000;
+// A rarity map holds every token of its contract
+const RARITY_FILE_MAX_ENTRIES: u64 = 1_000;
+/// Tokens whose metadata is read in one query, for callers that batch their lookups
+pub const METADATA_BATCH_SIZE: usize = 500;
+// Metadata files of a batch read at once, for the tokens the table doesn't have
+const METADATA_FILE_READ_CONCURRENCY: usize = 32;
+
+// (chain, contract, token_id) -> row, None when the table has no row for the token
+type RowCache = Cache<(String, String, TokenId), Option<Arc<Value>>>;
+
+// Dropped when the store saves metadata of the contract, the TTL only bounds how long
+// files edited by hand or another replica's refresh take to show up
+const TRAIT_INDEX_TTL: Duration = Duration::from_secs(10 * 60);
+const TRAIT_INDEX_MAX_ENTRIES: u64 = 1_000;
+
+// Set AFTERLIFE_METADATA_EXPORT_FILES=true to keep writing metadata files next to the table
+static EXPORT_FILES: Lazy<bool> = Lazy::new(|| {
+    env::var("AFTERLIFE_METADATA_EXPORT_FILES")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+});
+
+/// Token metadata and rarity lookups.
+///
+/// Once a database is attached, token metadata is read from the token_metadata table and
+/// the metadata directory is only a fallback for tokens without a row. Files are read
+/// through the configured storage and cached by path along with their version (mtime or
+/// ETag). The version is checked again once the cached file is a minute old, a rewritten
+/// file is reloaded then.
+pub struct MetadataStore {
+    path_metadata: String,
+    path_rarities: String,
+    database: OnceCell<Arc<Database>>,
+    rows: RowCache,
+    // (chain, contract) -> trait index
+    traits: Cache<(String, String), Arc<TraitIndex>>,
+    metadata: FileCache<Value>,
+    rarities: FileCache<RarityMap>,
+}
+
+impl MetadataStore {
+    pub fn new(path_metadata: String, path_rarities: String) -> Self {
+        Self {
+            path_metadata,
+            path_rarities,
+            database: OnceCell::new(),
+            rows: Cache::builder()
+                .max_capacity(METADATA_ROW_MAX_ENTRIES)
+                .time_to_live(METADATA_ROW_TTL)
+                .build(),
+            traits: Cache::builder()
+                .max_capacity(TRAIT_INDEX_MAX_ENTRIES)
+                .time_to_live(TRAIT_INDEX_TTL)
+                .build(),
+            metadata: Cache::new(METADATA_FILE_MAX_ENTRIES),
+            rarities: Cache::new(RARITY_FILE_MAX_ENTRIES),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(
+            env::var("AFTERLIFE_PATH_METADATA")
+                .expect("Environment variable AFTERLIFE_PATH_METADATA not set"),
+            env::var("AFTERLIFE_PATH_RARITIES")
+                .expect("Environment variable AFTERLIFE_PATH_RARITIES not set"),
+        )
+    }
+
+    /// Makes the token_metadata table the primary metadata store
+    pub fn attach_database(&self, database: Arc<Database>) {
+        if self.database.set(database).is_err() {
+            eprintln!("Metadata store already has a database");
+        }
+    }
+
+    pub(crate) async fn client(&self) -> Option<Arc<Client>> {
+        match self.database.get() {
+            Some(database) => Some(database.client().await),
+            None => None,
+        }
+    }
+
+    pub fn metadata_root(&self) -> &str {
+        &self.path_metadata
+    }
+
+    pub fn rarities_root(&self) -> &str {
+        &self.path_rarities
+    }
+
+    /// Directory holding one `{token_id}.json` metadata file per token of a contract
+    pub fn contract_metadata_dir(&self, chain_name: &str, contract_address: &str) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/{}/{}",
+            self.path_metadata,
+            chain_name,
+            checksum(contract_address)
+        ))
+    }
+
+    pub fn metadata_path(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+        token_id: TokenId,
+    ) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/{}/{}/{}.json",
+            self.path_metadata,
+            chain_name,
+            checksum(contract_address),
+            token_id
+        ))
+    }
+
+    pub fn rarity_path(&self, chain_name: &str, contract_address: &str) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/{}_{}_rarity.json",
+            self.path_rarities,
+            chain_name,
+            checksum(contract_address)
+        ))
+    }
+
+    /// Parsed metadata JSON for a token, or None if neither the table nor the metadata
+    /// directory has it
+    pub async fn token_metadata(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+        token_id: TokenId,
+    ) -> Option<Arc<Value>> {
+        if let Some(metadata) = self
+            .stored_metadata(chain_name, contract_address, token_id)
+            .await
+        {
+            return Some(metadata);
+        }
+
+        let path = self.metadata_path(chain_name, contract_address, token_id);
+        load_cached(&self.metadata, path, |contents| {
+            serde_json::from_str::<Value>(contents).ok()
+        })
+        .await
+    }
+
+    /// Parsed metadata JSON of several tokens of a contract, tokens that neither the table
+    /// nor the metadata directory has are left out. The rows missing from the cache are read
+    /// in one query.
+    pub async fn tokens_metadata(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+        token_ids: &[TokenId],
+    ) -> HashMap<TokenId, Arc<Value>> {
+        let mut found = HashMap::with_capacity(token_ids.len());
+        let mut without_row = Vec::new();
+        if let Some(client) = self.client().await {
+            let chain_key = chain_name.to_lowercase();
+            let contract_key = contract_address.to_lowercase();
+            let mut uncached = Vec::new();
+            for &token_id in token_ids {
+                match self
+                    .rows
+                    .get(&(chain_key.clone(), contract_key.clone(), token_id))
+                    .await
+                {
+                    Some(Some(metadata)) => {
+                        found.insert(token_id, metadata);
+                    }
+                    Some(None) => without_row.push(token_id),
+                    None => uncached.push(token_id),
+                }
+            }
+            if !uncached.is_empty() {
+                match queries::get_tokens_metadata(&client, chain_name, contract_address, &uncached)
+                    .await
+                {
+                    Ok(mut stored) => {
+                        for token_id in uncached {
+                            let metadata = stored.remove(&token_id).map(Arc::new);
+                            let key = (chain_key.clone(), contract_key.clone(), token_id);
+                            self.rows.insert(key, metadata.clone()).await;
+                            match metadata {
+                                Some(metadata) => {
+                                    found.insert(token_id, metadata);
+                                }
+                                None => without_row.push(token_id),
+                            }
+                        }
+                    }
+                    // Database errors aren't cached, the next lookup tries again
+                    Err(e) => {
+                        eprintln!("Failed to read token metadata: {}", e);
+                        without_row.extend(uncached);
+                    }
+                }
+            }
+        } else {
+            without_row.extend_from_slice(token_ids);
+        }
+
+        let from_files: Vec<(TokenId, Option<Arc<Value>>)> = stream::iter(without_row)
+            .map(|token_id| async move {
+                let path = self.metadata_path(chain_name, contract_address, token_id);
+                let metadata = load_cached(&self.metadata, path, |contents| {
+                    serde_json::from_str::<Value>(contents).ok()
+                })
+                .await;
+                (token_id, metadata)
+            })
+            .buffer_unordered(METADATA_FILE_READ_CONCURRENCY)
+            .collect()
+            .await;
+        found.extend(
+            from_files
+                .into_iter()
+                .filter_map(|(token_id, metadata)| Some((token_id, metadata?))),
+        );
+        found
+    }
+
+    async fn stored_metadata(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+        token_id: TokenId,
+    ) -> Option<Arc<Value>> {
+        let client = self.client().await?;
+        let key = (
+            chain_name.to_lowercase(),
+            contract_address.to_lowercase(),
+            token_id,
+        );
+        // Database errors aren't cached, the next lookup tries again
+        self.rows
+            .try_get_with(key, async {
+                queries::get_token_metadata(&client, chain_name, contract_address, token_id)
+                    .await
+                    .map(|metadata| metadata.map(Arc::new))
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to read token metadata: {}", e);
+                None
+            })
+    }
+
+    /// Stores a token's metadata in the table, and in the metadata directory when there is
+    /// no database or files are exported
+    pub async fn save_token_metadata(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+        token_id: TokenId,
+        metadata: &Value,
+    ) -> Result<(), String> {
+        if let Some(client) = self.client().await {
+            queries::upsert_token_metadata(
+                &client,
+                chain_name,
+                contract_address,
+                token_id,
+                metadata,
+            )
+            .await
+            .map_err(|e| format!("Failed to store token metadata: {}", e))?;
+            self.rows
+                .invalidate(&(
+                    chain_name.to_lowercase(),
+                    contract_address.to_lowercase(),
+                    token_id,
+                ))
+                .await;
+        }
+        self.traits
+            .invalidate(&(chain_name.to_lowercase(), contract_address.to_lowercase()))
+            .await;
+        if self.database.get().is_none() || *EXPORT_FILES {
+            self.write_metadata_file(chain_name, contract_address, token_id, metadata)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn write_metadata_file(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+        token_id: TokenId,
+        metadata: &Value,
+    ) -> Result<(), String> {
+        let path = self.metadata_path(chain_name, contract_address, token_id);
+        STORAGE
+            .write(&path, metadata.to_string().into_bytes())
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        self.metadata.invalidate(&path).await;
+        Ok(())
+    }
+
+    /// (chain, contract address) of every contract with metadata, in the table or in the
+    /// metadata directory
+    pub async fn metadata_contracts(&self) -> Vec<(String, String)> {
+        let mut contracts: HashMap<(String, String), (String, String)> = HashMap::new();
+        if let Some(client) = self.client().await {
+            match queries::get_metadata_contracts(&client).await {
+                Ok(stored) => contracts.extend(stored.into_iter().map(|(chain, address)| {
+                    (
+                        (chain.to_lowercase(), address.to_lowercase()),
+                        (chain, address),
+                    )
+                })),
+                Err(e) => eprintln!("Failed to list metadata contracts: {}", e),
+            }
+        }
+
+        let root = Path::new(&self.path_metadata);
+        for chain_name in STORAGE.list(root).await.unwrap_or_default() {
+            let addresses = match STORAGE.list(&root.join(&chain_name)).await {
+                Ok(addresses) => addresses,
+                Err(_) => continue,
+            };
+            for address in addresses {
+                contracts
+                    .entry((chain_name.to_lowercase(), address.to_lowercase()))
+                    .or_insert((chain_name.clone(), address));
+            }
+        }
+        contracts.into_values().collect()
+    }
+
+    /// Metadata of every token of a contract, from the table and, for the tokens without a
+    /// row, from the metadata directory
+    pub async fn contract_metadata(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+    ) -> Result<Vec<(TokenId, Value)>, String> {
+        let mut metadata = match self.client().await {
+            Some(client) => queries::get_contract_metadata(&client, chain_name, contract_address)
+                .await
+                .map_err(|e| format!("Failed to read contract metadata: {}", e))?,
+            None => Vec::new(),
+        };
+        let stored: HashSet<TokenId> = metadata.iter().map(|(token_id, _)| *token_id).collect();
+        metadata.extend(
+            read_metadata_dir(
+                &self.contract_metadata_dir(chain_name, contract_address),
+                &stored,
+            )
+            .await?,
+        );
+        Ok(metadata)
+    }
+
+    /// The traits of every token of a contract, built from one read of its metadata and
+    /// kept until the contract's metadata is saved again
+    pub async fn trait_index(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+    ) -> Result<Arc<TraitIndex>, String> {
+        let key = (chain_name.to_lowercase(), contract_address.to_lowercase());
+        // Read errors aren't cached, the next lookup tries again
+        self.traits
+            .try_get_with(key, async {
+                let metadata = self.contract_metadata(chain_name, contract_address).await?;
+                let index: TraitIndex = metadata
+                    .iter()
+                    .map(|(token_id, metadata)| (*token_id, rarity::token_traits(metadata)))
+                    .collect();
+                Ok(Arc::new(index))
+            })
+            .await
+            .map_err(|e: Arc<String>| (*e).clone())
+    }
+
+    /// Stores a contract's rarity file, read back by the next lookup
+    pub async fn save_rarity_file(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+        contents: Vec<u8>,
+    ) -> Result<(), String> {
+        let path = self.rarity_path(chain_name, contract_address);
+        STORAGE
+            .write(&path, contents)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        self.rarities.invalidate(&path).await;
+        Ok(())
+    }
+
+    /// Rarity map for a contract, empty if the contract has no rarity file
+    pub async fn rarity_map(&self, chain_name: &str, contract_address: &str) -> Arc<RarityMap> {
+        let path = self.rarity_path(chain_name, contract_address);
+        load_cached(&self.rarities, path, |contents| {
+            Some(parse_rarity_map(contents))
+        })
+        .await
+        .unwrap_or_default()
+    }
+}
+
+async fn load_cached<T, F>(cache: &FileCache<T>, path: PathBuf, parse: F) -> Option<Arc<T>>
+where
+    T: Send + Sync + 'static,
+    F: FnOnce(&str) -> Option<T>,
+{
+    let cached = cache.get(&path).await.map(|cached| {
+        (
+            cached.version.clone(),
+            cached.value.clone(),
+            cached.checked_at.elapsed() < FILE_REVALIDATE_AFTER,
+        )
+    });
+    if let Some((_, value, true)) = &cached {
+        return value.clone();
+    }
+
+    // Served when the storage can't be reached, the last contents are better than none
+    let stale = cached.as_ref().and_then(|(_, value, _)| value.clone());
+    let version = match STORAGE.version(&path).await {
+        Ok(version) => Some(version),
+        // The file is gone, don't keep serving the old contents
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            eprintln!("Failed to check {}: {}", path.display(), e);
+            return stale;
+        }
+    };
+
+    let value = match cached {
+        Some((cached_version, value, _)) if cached_version == version => value,
+        _ if version.is_none() => None,
+        _ => match STORAGE.read(&path).await {
+            Ok(contents) => String::from_utf8(contents)
+                .ok()
+                .and_then(|contents| parse(&contents))
+                .map(Arc::new),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                return stale;
+            }
+        },
+    };
+    cache
+        .insert(
+            path,
+            Arc::new(CachedFile {
+                version,
+                value: value.clone(),
+                checked_at: Instant::now(),
+            }),
+        )
+        .await;
+    value
+}
+
+// Files of the tokens in `skip` aren't read
+async fn read_metadata_dir(
+    dir: &Path,
+    skip: &HashSet<TokenId>,
+) -> Result<Vec<(TokenId, Value)>, String> {
+    let names = match STORAGE.list(dir).await {
+        Ok(names) => names,
+        // No directory, no metadata
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to list {}: {}", dir.display(), e)),
+    };
+
+    let mut tokens = Vec::new();
+    for name in names {
+        let token_id = match name
+            .strip_suffix(".json")
+            .and_then(|id| id.parse::<TokenId>().ok())
+        {
+            Some(token_id) if !skip.contains(&token_id) => token_id,
+            _ => continue,
+        };
+        let path = dir.join(&name);
+        // Tokens whose metadata can't be read are left out
+        match STORAGE
+            .read(&path)
+            .await
+            .map(|contents| serde_json::from_slice(&contents))
+        {
+            Ok(Ok(metadata)) => tokens.push((token_id, metadata)),
+            _ => eprintln!("Skipping unreadable metadata {}", path.display()),
+        }
+    }
+    Ok(tokens)
+}
+
+// Files are {"rarity_model": ..., "rarities": [...]}, or just the list when written before
+// models were recorded. Percentiles aren't stored, they follow from the scores of the
+// whole file.
+fn parse_rarity_map(rarity_json: &str) -> RarityMap {
+    let mut ranked: Vec<(TokenId, f64, u64)> = Vec::new();
+    let rarities = match serde_json::from_str::<Value>(rarity_json) {
+        Ok(Value::Array(rarities)) => rarities,
+        Ok(Value::Object(mut file)) => match file.remove("rarities") {
+            Some(Value::Array(rarities)) => rarities,
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    for rarity in rarities {
+        if let Some(rarity_obj) = rarity.as_object() {
+            if let (Some(token_id), Some(rarity_score), Some(rarity_index)) = (
+                rarity_obj
+                    .get("token_id")
+                    .and_then(|v| serde_json::from_value::<TokenId>(v.clone()).ok()),
+                rarity_obj.get("rarity_score").and_then(|v| v.as_f64()),
+                rarity_obj.get("rarity_index").and_then(|v| v.as_u64()),
+            ) {
+                ranked.push((token_id, rarity_score, rarity_index));
+            }
+        }
+    }
+    let scores: Vec<f64> = ranked.iter().map(|(_, score, _)| *score).collect();
+    ranked
+        .into_iter()
+        .zip(rarity::percentiles(&scores))
+        .map(
+            |((token_id, rarity_score, rarity_index), rarity_percentile)| {
+                (token_id, (rarity_score, rarity_index, rarity_percentile))
+            },
+        )
+        .collect()
+}
@@ -1,3 +1,53 @@
+mod access_log;
+mod achievements;
+mod addresses;
 pub mod api;
+mod api_keys;
+mod approvals;
+mod auth;
+mod avatars;
+mod bot;
+pub(crate) mod cache_events;
+mod catalog;
+mod collection_groups;
+mod collection_traits;
+mod contract_params;
+mod delegations;
+mod directory;
+mod ens;
+pub mod errors;
+mod exclusions;
+mod export;
+mod health;
+mod hold_stats;
+mod holdings;
+mod jobs;
+mod labels;
+mod levels;
+mod media;
+pub(crate) mod metadata_store;
+mod movers;
+mod notifications;
+mod portfolio;
+pub mod preflight;
+mod prices;
+mod profiles;
+mod projects;
 pub mod queries;
+mod rarity;
+pub mod rarity_models;
+pub mod repository;
+mod response_cache;
+pub(crate) mod reveals;
+mod score_weights;
+pub(crate) mod seasons;
+mod sets;
+mod teams;
+pub mod timeouts;
+pub mod tls;
+mod user_admin;
+pub(crate) mod user_details_cache;
+mod user_search;
 mod usernames;
+mod v1;
+mod webhooks;
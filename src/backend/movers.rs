@@ -0,0 +1,147 @@
+use crate::backend::api::{get_or_update_all_users_collections, rank_leaderboard};
+use crate::backend::errors::ApiError;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+const DEFAULT_WINDOW: &str = "24h";
+// Snapshots are hourly, shorter windows would mostly compare against the same one
+const MIN_WINDOW_SECONDS: u64 = 3600;
+const MAX_WINDOW_SECONDS: u64 = 30 * 24 * 3600;
+const DEFAULT_MOVERS_LIMIT: usize = 20;
+const MAX_MOVERS_LIMIT: usize = 100;
+// Username, points, rank, and points and rank at the start of the window if ranked then
+type Mover = (String, f64, usize, Option<(f64, i32)>);
+
+#[derive(Debug, Deserialize)]
+pub struct MoversQuery {
+    // e.g. 6h, 24h, 7d
+    window: Option<String>,
+    limit: Option<usize>,
+}
+
+fn parse_window(window: &str) -> Result<u64, ApiError> {
+    let invalid = || {
+        ApiError::BadRequest(format!(
+            "Invalid window {}, expected e.g. 24h or 7d",
+            window
+        ))
+    };
+    let (amount, unit_seconds) = if let Some(hours) = window.strip_suffix('h') {
+        (hours, 3600)
+    } else if let Some(days) = window.strip_suffix('d') {
+        (days, 24 * 3600)
+    } else {
+        return Err(invalid());
+    };
+    let seconds = amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(unit_seconds))
+        .ok_or_else(invalid)?;
+    if !(MIN_WINDOW_SECONDS..=MAX_WINDOW_SECONDS).contains(&seconds) {
+        return Err(ApiError::BadRequest(format!(
+            "Window must be between 1h and 30d, got {}",
+            window
+        )));
+    }
+    Ok(seconds)
+}
+
+pub async fn handle_get_leaderboard_movers(
+    query: MoversQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let window = query.window.as_deref().unwrap_or(DEFAULT_WINDOW);
+    let window_seconds = parse_window(window)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_MOVERS_LIMIT)
+        .clamp(1, MAX_MOVERS_LIMIT);
+
+    let cache_key = format!("leaderboard/movers/{}/{}", window, limit);
+    let response = response_cache::get_or_compute(
+        cache_key,
+        build_leaderboard_movers(&client, window, window_seconds, limit),
+    )
+    .await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+async fn build_leaderboard_movers(
+    client: &Client,
+    window: &str,
+    window_seconds: u64,
+    limit: usize,
+) -> Result<Value, ApiError> {
+    let previous = queries::get_score_snapshot(client, window_seconds as f64)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get score history: {}", e)))?;
+    if previous.is_empty() {
+        // No snapshot is old enough yet, everyone would look like a newcomer
+        return Ok(json!({ "window": window, "has_history": false, "movers": [] }));
+    }
+    let current = rank_leaderboard(get_or_update_all_users_collections(client, false).await?);
+
+    // Users who weren't ranked before count as coming from just below the last rank
+    let unranked = previous.len() + 1;
+
+    let mut movers: Vec<Mover> = current
+        .into_iter()
+        .map(|(name, points, rank)| {
+            let before = previous.get(&name).copied();
+            (name, points, rank, before)
+        })
+        .filter(|(_, points, rank, before)| match before {
+            Some((previous_points, previous_rank)) => {
+                *previous_rank as usize != *rank || previous_points != points
+            }
+            None => true,
+        })
+        .collect();
+
+    let rank_change = |rank: usize, before: &Option<(f64, i32)>| {
+        let previous_rank = before.map_or(unranked, |(_, rank)| rank as usize);
+        previous_rank as i64 - rank as i64
+    };
+    let points_change = |points: f64, before: &Option<(f64, i32)>| {
+        points - before.map_or(0.0, |(points, _)| points)
+    };
+
+    // Biggest moves first, in either direction
+    movers.sort_by(
+        |(a_name, a_points, a_rank, a_before), (b_name, b_points, b_rank, b_before)| {
+            rank_change(*b_rank, b_before)
+                .abs()
+                .cmp(&rank_change(*a_rank, a_before).abs())
+                .then_with(|| {
+                    points_change(*b_points, b_before)
+                        .abs()
+                        .partial_cmp(&points_change(*a_points, a_before).abs())
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .then_with(|| a_name.cmp(b_name))
+        },
+    );
+    movers.truncate(limit);
+
+    Ok(json!({
+        "window": window,
+        "has_history": true,
+        "movers": movers.iter().map(|(name, points, rank, before)| json!({
+            "name": name,
+            "points": points,
+            "previous_points": before.map(|(points, _)| points),
+            "points_change": points_change(*points, before),
+            "rank": rank,
+            "previous_rank": before.map(|(_, rank)| rank),
+            "rank_change": rank_change(*rank, before),
+        })).collect::<Vec<_>>(),
+    }))
+}
@@ -0,0 +1,178 @@
+use crate::backend::errors::ApiError;
+use crate::backend::projects::DEFAULT_PROJECT;
+use crate::backend::queries::{self, NotificationChannel, NotificationPreferences};
+use crate::backend::usernames::signed_in_username;
+use crate::backend::webhooks::generate_secret;
+use crate::indexer::notifications::SmtpRelay;
+use crate::indexer::webhooks::resolve_public_url;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+pub async fn handle_get_notification_preferences(
+    address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let preferences = queries::get_notification_preferences(&client, &username)
+        .await
+        .map_err(|e| {
+            ApiError::Upstream(format!("Failed to get notification preferences: {}", e))
+        })?;
+
+    Ok(warp::reply::json(&json!({
+        "username": username,
+        "notifications": preferences,
+    }))
+    .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailConfirmation {
+    pub token: String,
+}
+
+// Replaces the notification preferences of the user the signed-in wallet belongs to. A
+// webhook keeps its signing secret while its URL doesn't change. A new email address is
+// mailed a token and only notified once the token is confirmed, an unchanged one stays
+// confirmed.
+pub async fn handle_set_notification_preferences(
+    address: String,
+    body: NotificationPreferences,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let current = queries::get_notification_preferences(&client, &username)
+        .await
+        .map_err(|e| {
+            ApiError::Upstream(format!("Failed to get notification preferences: {}", e))
+        })?;
+
+    let mut preferences = NotificationPreferences {
+        channel: body.channel,
+        webhook_url: None,
+        email: None,
+        events: body.events,
+        webhook_secret: None,
+        email_confirmed: false,
+        confirmation_token: None,
+    };
+    let mut smtp = None;
+    match body.channel {
+        NotificationChannel::None => {}
+        NotificationChannel::Webhook => {
+            let url = body.webhook_url.ok_or_else(|| {
+                ApiError::invalid_field(
+                    "webhook_url",
+                    "webhook_url is required for webhook notifications".to_string(),
+                )
+            })?;
+            // The indexer posts to it, so it must not reach anything internal
+            resolve_public_url(&url)
+                .await
+                .map_err(|e| ApiError::invalid_field("webhook_url", e))?;
+            preferences.webhook_secret = match current.webhook_secret {
+                Some(secret) if current.webhook_url.as_deref() == Some(url.as_str()) => {
+                    Some(secret)
+                }
+                _ => Some(generate_secret()),
+            };
+            preferences.webhook_url = Some(url);
+        }
+        NotificationChannel::Email => {
+            smtp = Some(SmtpRelay::from_env().ok_or_else(|| {
+                ApiError::BadRequest("Email notifications are disabled".to_string())
+            })?);
+            let email = body
+                .email
+                .map(|email| email.trim().to_string())
+                .ok_or_else(|| {
+                    ApiError::invalid_field(
+                        "email",
+                        "email is required for email notifications".to_string(),
+                    )
+                })?;
+            let valid = email
+                .split_once('@')
+                .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.'));
+            if !valid {
+                return Err(
+                    ApiError::invalid_field("email", format!("Invalid email {}", email)).into(),
+                );
+            }
+            let unchanged = current.channel == NotificationChannel::Email
+                && current.email.as_deref() == Some(email.as_str());
+            if unchanged && current.email_confirmed {
+                preferences.email_confirmed = true;
+            } else {
+                preferences.confirmation_token = Some(match current.confirmation_token {
+                    Some(token) if unchanged => token,
+                    _ => generate_secret()[..16].to_string(),
+                });
+            }
+            preferences.email = Some(email);
+        }
+    }
+
+    queries::set_notification_preferences(&client, &username, &preferences)
+        .await
+        .map_err(|e| {
+            ApiError::Upstream(format!("Failed to save notification preferences: {}", e))
+        })?;
+
+    if let (Some(smtp), Some(email), Some(token)) =
+        (smtp, &preferences.email, &preferences.confirmation_token)
+    {
+        let text = format!(
+            "Afterlife notifications of {} were requested for this address.\n\n\
+            If that was you, confirm them with the token {}\n\n\
+            Otherwise ignore this email, nothing will be sent without the confirmation.\n",
+            username, token
+        );
+        smtp.send(email, "Confirm your Afterlife notifications", text)
+            .await
+            .map_err(ApiError::Upstream)?;
+    }
+
+    Ok(warp::reply::json(&json!({
+        "username": username,
+        "notifications": preferences,
+    }))
+    .into_response())
+}
+
+// Confirms the pending notification email of the signed-in user with the token mailed to it
+pub async fn handle_confirm_notification_email(
+    address: String,
+    body: EmailConfirmation,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let confirmed = queries::confirm_notification_email(&client, &username, body.token.trim())
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to confirm notification email: {}", e)))?;
+    if !confirmed {
+        return Err(ApiError::invalid_field(
+            "token",
+            "Invalid or expired confirmation token".to_string(),
+        )
+        .into());
+    }
+
+    let preferences = queries::get_notification_preferences(&client, &username)
+        .await
+        .map_err(|e| {
+            ApiError::Upstream(format!("Failed to get notification preferences: {}", e))
+        })?;
+    Ok(warp::reply::json(&json!({
+        "username": username,
+        "notifications": preferences,
+    }))
+    .into_response())
+}
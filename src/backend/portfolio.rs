@@ -0,0 +1,124 @@
+use crate::backend::errors::ApiError;
+use crate::backend::holdings::load_user_holdings;
+use crate::backend::prices;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::backend::usernames::get_all_addresses_for_name;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+pub async fn handle_get_user_value(
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!("user/value/{}", username);
+    let response =
+        response_cache::get_or_compute(cache_key, build_user_value(&username, &client)).await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// Estimated value of what the user holds, at floor prices. An ERC-1155 token is valued at
+// the lowest ask for its own id when there is one, ERC-721 tokens and the other ERC-1155
+// ones at the collection floor: an ERC-721 token's own ask can only be its holder's. Native
+// currencies differ between chains so they're only summed per chain, the overall total is
+// in USD.
+async fn build_user_value(username: &str, client: &Client) -> Result<Value, ApiError> {
+    let user_addresses = get_all_addresses_for_name(username).await?;
+    if user_addresses.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)));
+    }
+
+    let holdings = load_user_holdings(client, &user_addresses).await?;
+    let usd_prices = prices::usd_prices(client).await;
+    let usd_price = |chain: &str| usd_prices.usd(chain);
+
+    let contracts: Vec<(String, String)> = holdings.keys().cloned().collect();
+    let markets = queries::get_contracts_market(client, &contracts)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get floor prices: {}", e)))?;
+
+    let mut collections = Vec::new();
+    // chain -> (native value, tokens without a price)
+    let mut chains: BTreeMap<String, (f64, f64)> = BTreeMap::new();
+    for ((chain, contract_address), tokens) in &holdings {
+        let market = markets.get(&(chain.to_lowercase(), contract_address.to_lowercase()));
+        let floor_prices = market.map(|market| &market.floor_prices);
+        let collection_floor =
+            floor_prices.and_then(|floor_prices| floor_prices.values().copied().reduce(f64::min));
+        let per_token = market.is_some_and(|market| market.r#type.eq_ignore_ascii_case("erc1155"));
+
+        let mut token_count = 0.0;
+        let mut unpriced = 0.0;
+        let mut value = 0.0;
+        for (token_id, balance) in tokens {
+            let balance = balance.to_f64();
+            token_count += balance;
+            let token_floor = floor_prices
+                .filter(|_| per_token)
+                .and_then(|floor_prices| floor_prices.get(token_id).copied());
+            match token_floor.or(collection_floor) {
+                Some(price) => value += price * balance,
+                None => unpriced += balance,
+            }
+        }
+
+        let contract_name = market.map_or("Unknown", |market| market.name.as_str());
+        let chain_total = chains.entry(chain.clone()).or_default();
+        chain_total.0 += value;
+        chain_total.1 += unpriced;
+        collections.push(json!({
+            "chain": chain,
+            "contract_address": contract_address,
+            "contract_name": contract_name,
+            "token_count": token_count,
+            "unpriced_tokens": unpriced,
+            "floor_price": collection_floor,
+            "value": value,
+            "value_usd": usd_price(chain).map(|price| value * price),
+        }));
+    }
+    collections.sort_by(|a, b| {
+        let (a, b) = (a["value"].as_f64(), b["value"].as_f64());
+        b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    // Only in USD when every chain has a price, a partial sum would read as the total
+    let mut total_usd = Some(0.0);
+    // Fetch time of the oldest price used
+    let mut prices_at: Option<i64> = None;
+    let chains: Vec<Value> = chains
+        .into_iter()
+        .map(|(chain, (value, unpriced))| {
+            let value_usd = usd_price(&chain).map(|price| value * price);
+            total_usd = total_usd.zip(value_usd).map(|(total, value)| total + value);
+            let usd_price_at = usd_prices.get(&chain).map(|price| price.fetched_at);
+            prices_at = prices_at.into_iter().chain(usd_price_at).min();
+            json!({
+                "chain": chain,
+                "value": value,
+                "unpriced_tokens": unpriced,
+                "usd_price": usd_price(&chain),
+                "value_usd": value_usd,
+                "usd_price_at": usd_price_at,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "username": username,
+        "collections": collections,
+        "chains": chains,
+        "total_usd": total_usd,
+        "prices_at": prices_at,
+        "computed_at": SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or(0),
+    }))
+}
@@ -0,0 +1,154 @@
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::{MetadataStore, METADATA_STORE};
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::common::database::Database;
+use crate::common::storage::STORAGE;
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+
+// Rarity files checked in parallel
+const CHECK_CONCURRENCY: usize = 16;
+// Metadata and rarity files come and go with jobs and uploads, the report follows
+const CHECK_PERIOD: Duration = Duration::from_secs(15 * 60);
+// Cache key of the report computed on request, before the first scheduled one is in
+const DATA_STATUS_CACHE_KEY: &str = "status/data";
+
+static LAST_REPORT: Lazy<RwLock<Option<Arc<DataReport>>>> = Lazy::new(|| RwLock::new(None));
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContractRef {
+    pub chain: String,
+    pub contract_name: String,
+    pub contract_address: String,
+}
+
+/// Registered contracts the API has no metadata or no rarity file for. Their tokens
+/// score zero until the data shows up.
+#[derive(Debug, Clone, Serialize)]
+pub struct DataReport {
+    // Unix timestamp (seconds) of the check
+    pub checked_at: i64,
+    pub contracts_checked: usize,
+    // Neither rows in token_metadata nor a folder in AFTERLIFE_PATH_METADATA
+    pub missing_metadata: Vec<ContractRef>,
+    // No rarity file in AFTERLIFE_PATH_RARITIES
+    pub missing_rarity: Vec<ContractRef>,
+}
+
+/// Checks the metadata and rarity data of every registered contract, logs the contracts
+/// missing some and keeps the report for /status/data
+pub async fn check_contract_data(
+    store: &MetadataStore,
+    client: &Client,
+) -> Result<Arc<DataReport>, String> {
+    let contracts = queries::get_registered_contracts(client)
+        .await
+        .map_err(|e| format!("Failed to list contracts: {}", e))?;
+    let with_metadata: HashSet<(String, String)> = store
+        .metadata_contracts()
+        .await
+        .into_iter()
+        .map(|(chain, address)| (chain.to_lowercase(), address.to_lowercase()))
+        .collect();
+
+    let checks: Vec<(ContractRef, bool, bool)> = stream::iter(contracts)
+        .map(|(chain, contract_name, contract_address)| {
+            let has_metadata =
+                with_metadata.contains(&(chain.to_lowercase(), contract_address.to_lowercase()));
+            async move {
+                let rarity_path = store.rarity_path(&chain, &contract_address);
+                let has_rarity = STORAGE.version(&rarity_path).await.is_ok();
+                let contract = ContractRef {
+                    chain,
+                    contract_name,
+                    contract_address,
+                };
+                (contract, has_metadata, has_rarity)
+            }
+        })
+        .buffered(CHECK_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut report = DataReport {
+        checked_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default(),
+        contracts_checked: checks.len(),
+        missing_metadata: Vec::new(),
+        missing_rarity: Vec::new(),
+    };
+    for (contract, has_metadata, has_rarity) in checks {
+        if !has_metadata {
+            eprintln!(
+                "Preflight: no metadata for {} ({}) on {}, in {}",
+                contract.contract_name,
+                contract.contract_address,
+                contract.chain,
+                store.metadata_root()
+            );
+            report.missing_metadata.push(contract.clone());
+        }
+        if !has_rarity {
+            eprintln!(
+                "Preflight: no rarity file for {} ({}) on {}, expected {}",
+                contract.contract_name,
+                contract.contract_address,
+                contract.chain,
+                store
+                    .rarity_path(&contract.chain, &contract.contract_address)
+                    .display()
+            );
+            report.missing_rarity.push(contract);
+        }
+    }
+    println!(
+        "Preflight: {} contracts, {} without metadata, {} without rarities",
+        report.contracts_checked,
+        report.missing_metadata.len(),
+        report.missing_rarity.len()
+    );
+
+    let report = Arc::new(report);
+    *LAST_REPORT.write().unwrap() = Some(report.clone());
+    Ok(report)
+}
+
+/// Checks the contracts' data at startup, then every CHECK_PERIOD
+pub fn spawn_scheduler(database: Arc<Database>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_PERIOD);
+        loop {
+            interval.tick().await;
+            let client = database.client().await;
+            if let Err(e) = check_contract_data(&METADATA_STORE, &client).await {
+                eprintln!("Preflight failed: {}", e);
+            }
+        }
+    });
+}
+
+/// The report of the last scheduled check. Until one succeeded, a check made on request
+/// and shared by the concurrent ones.
+pub async fn handle_get_data_status(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let last_report = LAST_REPORT.read().unwrap().clone();
+    if let Some(report) = last_report {
+        return Ok(warp::reply::json(&*report));
+    }
+    let report = response_cache::get_or_compute(DATA_STATUS_CACHE_KEY.to_string(), async {
+        let report = check_contract_data(&METADATA_STORE, &client)
+            .await
+            .map_err(ApiError::Upstream)?;
+        serde_json::to_value(&*report).map_err(|e| ApiError::Internal(e.to_string()))
+    })
+    .await?;
+    Ok(warp::reply::json(&*report))
+}
@@ -0,0 +1,84 @@
+use crate::backend::errors::ApiError;
+use crate::backend::queries::{self, NativePrice};
+use crate::common::addresses::ZERO_ADDRESS;
+use moka::future::Cache;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+
+// Prices are refreshed every few minutes by the price refresh, reading them once a
+// minute is plenty
+const PRICES_TTL: Duration = Duration::from_secs(60);
+// The EVM native currencies all have 18 decimals
+const NATIVE_DECIMALS: i32 = 18;
+
+/// USD prices of the chains' native currencies, as last stored by the price refresh
+#[derive(Debug, Clone, Default)]
+pub struct UsdPrices {
+    // lowercased chain name -> price
+    prices: HashMap<String, NativePrice>,
+}
+
+impl UsdPrices {
+    pub fn get(&self, chain_name: &str) -> Option<&NativePrice> {
+        self.prices.get(&chain_name.to_lowercase())
+    }
+
+    pub fn usd(&self, chain_name: &str) -> Option<f64> {
+        self.get(chain_name).map(|price| price.usd)
+    }
+}
+
+static PRICES: Lazy<Cache<(), Arc<UsdPrices>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(1)
+        .time_to_live(PRICES_TTL)
+        .build()
+});
+
+/// The stored prices, empty when prices aren't fetched (no AFTERLIFE_PATH_PRICES). USD
+/// values are an extra, callers carry on with native values when a price is missing.
+pub async fn usd_prices(client: &Client) -> Arc<UsdPrices> {
+    // Database errors aren't cached, the next request tries again
+    PRICES
+        .try_get_with((), async {
+            queries::get_native_prices(client)
+                .await
+                .map(|prices| {
+                    Arc::new(UsdPrices {
+                        prices: prices
+                            .into_iter()
+                            .map(|price| (price.chain.clone(), price))
+                            .collect(),
+                    })
+                })
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to get USD prices: {}", e);
+            Arc::new(UsdPrices::default())
+        })
+}
+
+/// USD value of a sale paid in the chain's native currency, `price` being in its smallest
+/// unit and `usd` the native price on the day of the sale. None for sales paid in tokens or
+/// without a price for their day.
+pub fn native_sale_usd(currency: &str, price: &str, usd: Option<f64>) -> Option<f64> {
+    if !currency.eq_ignore_ascii_case(ZERO_ADDRESS) {
+        return None;
+    }
+    let price: f64 = price.parse().ok()?;
+    Some(price / 10f64.powi(NATIVE_DECIMALS) * usd?)
+}
+
+pub async fn handle_get_prices(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let prices = queries::get_native_prices(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get prices: {}", e)))?;
+    Ok(warp::reply::json(&json!({ "prices": prices })))
+}
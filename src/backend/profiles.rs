@@ -0,0 +1,257 @@
+use crate::backend::achievements;
+use crate::backend::api::{build_activity, get_or_update_all_users_collections};
+use crate::backend::avatars;
+use crate::backend::ens;
+use crate::backend::errors::ApiError;
+use crate::backend::holdings::{load_user_holdings, top_nfts};
+use crate::backend::levels::points_to_level;
+use crate::backend::projects::DEFAULT_PROJECT;
+use crate::backend::queries::{self, PrivacySettings};
+use crate::backend::response_cache;
+use crate::backend::usernames::{addresses_for_username, signed_in_username, UsersData};
+use eth_checksum::checksum;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+use web3::types::Address;
+
+const PROFILE_TOP_NFTS: usize = 10;
+const PROFILE_ACTIVITY_LIMIT: i64 = 10;
+
+fn profile_cache_key(username: &str) -> String {
+    format!("profile/{}", username)
+}
+
+pub async fn handle_get_profile(
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let response = response_cache::get_or_compute(
+        profile_cache_key(&username),
+        build_profile(&username, &client),
+    )
+    .await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// Level, rank and achievements count every wallet of the user, the wallets and tokens
+// listed only the ones the user didn't hide
+async fn build_profile(username: &str, client: &Client) -> Result<Value, ApiError> {
+    let users = DEFAULT_PROJECT.users().await?;
+    if !users.contains_key(username) {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)));
+    }
+    let settings = queries::get_privacy_settings(client, username)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get privacy settings: {}", e)))?;
+
+    // Points and rank come from the precomputed leaderboard so they always agree with it
+    let leaderboard = get_or_update_all_users_collections(client, false).await?;
+    let points = leaderboard.get(username).copied().unwrap_or(0.0);
+    let rank = leaderboard.contains_key(username).then(|| {
+        leaderboard
+            .values()
+            .filter(|&&score| score > points)
+            .count()
+            + 1
+    });
+
+    let mut visible_addresses: Vec<String> = addresses_for_username(&users, username)
+        .into_iter()
+        .filter(|address| !settings.hidden_addresses.contains(&address.to_lowercase()))
+        .collect();
+    visible_addresses.sort();
+
+    let (top, activity) = if settings.hide_collection {
+        (Vec::new(), Vec::new())
+    } else {
+        let visible: HashSet<String> = visible_addresses.iter().cloned().collect();
+        let holdings = load_user_holdings(client, &visible).await?;
+        // Transfers to or from a hidden wallet would give it away
+        let activity = build_activity(
+            client,
+            &DEFAULT_PROJECT,
+            &visible_addresses,
+            None,
+            PROFILE_ACTIVITY_LIMIT,
+        )
+        .await?;
+        let activity = without_hidden_transfers(activity, &settings.hidden_addresses);
+        (top_nfts(&holdings, PROFILE_TOP_NFTS).await, activity)
+    };
+
+    let earned_achievements = if achievements::is_configured() {
+        let achievements = achievements::build_user_achievements(username, client).await?;
+        achievements["achievements"]
+            .as_array()
+            .map(|all| {
+                all.iter()
+                    .filter(|achievement| achievement["earned"] == json!(true))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    Ok(json!({
+        "username": username,
+        "avatar": avatars::load_avatar(client, username).await,
+        "level": points_to_level(points as i32),
+        "points": points,
+        "rank": rank,
+        "addresses": visible_addresses
+            .iter()
+            .map(|address| checksum(address))
+            .collect::<Vec<_>>(),
+        "collection_hidden": settings.hide_collection,
+        "top_nfts": top,
+        "achievements": earned_achievements,
+        "recent_activity": activity,
+    }))
+}
+
+/// What the public routes showing a user's tokens or activity leave out
+pub(crate) struct Visibility {
+    /// The queried wallets their user hid, lowercased
+    pub hidden_addresses: Vec<String>,
+    /// The user hid their tokens and activity altogether
+    pub collection_hidden: bool,
+}
+
+impl Visibility {
+    pub fn is_hidden(&self, address: &str) -> bool {
+        self.hidden_addresses.contains(&address.to_lowercase())
+    }
+
+    /// Whether the tokens and activity of `address` are shown
+    pub fn lists(&self, address: &str) -> bool {
+        !self.collection_hidden && !self.is_hidden(address)
+    }
+}
+
+/// The privacy settings as they apply to `name`, a username, address or ENS name, and the
+/// `addresses` it stands for. Like on /fullcollection, a wallet its user hid is unknown
+/// when asked for directly.
+pub(crate) async fn visibility(
+    client: &Client,
+    users: &UsersData,
+    name: &str,
+    addresses: &[String],
+) -> Result<Visibility, ApiError> {
+    let hidden_addresses = queries::get_hidden_addresses(client, addresses)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get privacy settings: {}", e)))?;
+    if !users.contains_key(name) {
+        let address = ens::resolve_param(name).await?;
+        if hidden_addresses.contains(&address.to_lowercase()) {
+            return Err(ApiError::NotFound(format!(
+                "Unknown user or address {}",
+                name
+            )));
+        }
+        return Ok(Visibility {
+            hidden_addresses,
+            collection_hidden: false,
+        });
+    }
+    let settings = queries::get_privacy_settings(client, name)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get privacy settings: {}", e)))?;
+    Ok(Visibility {
+        hidden_addresses,
+        collection_hidden: settings.hide_collection,
+    })
+}
+
+// Transfers to or from a hidden wallet would give it away
+pub(crate) fn without_hidden_transfers(activity: Vec<Value>, hidden: &[String]) -> Vec<Value> {
+    activity
+        .into_iter()
+        .filter(|entry| {
+            ["from", "to"].iter().all(|side| {
+                entry[side]
+                    .as_str()
+                    .is_none_or(|address| !hidden.contains(&address.to_lowercase()))
+            })
+        })
+        .collect()
+}
+
+pub async fn handle_get_privacy_settings(
+    address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let settings = queries::get_privacy_settings(&client, &username)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get privacy settings: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({
+        "username": username,
+        "privacy": settings,
+    }))
+    .into_response())
+}
+
+// Replaces the privacy settings of the user the signed-in wallet belongs to
+pub async fn handle_set_privacy_settings(
+    address: String,
+    body: PrivacySettings,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let own_addresses: HashSet<String> = addresses_for_username(&users, &username)
+        .iter()
+        .map(|address| address.to_lowercase())
+        .collect();
+
+    let mut hidden_addresses = Vec::with_capacity(body.hidden_addresses.len());
+    for hidden in &body.hidden_addresses {
+        let parsed = hidden.parse::<Address>().map_err(|_| {
+            ApiError::invalid_field("hidden_addresses", format!("Invalid address {}", hidden))
+        })?;
+        let hidden = format!("{:?}", parsed);
+        if !own_addresses.contains(&hidden) {
+            return Err(ApiError::invalid_field(
+                "hidden_addresses",
+                format!(
+                    "Address {} doesn't belong to {}",
+                    checksum(&hidden),
+                    username
+                ),
+            )
+            .into());
+        }
+        hidden_addresses.push(hidden);
+    }
+    hidden_addresses.sort();
+    hidden_addresses.dedup();
+
+    let settings = PrivacySettings {
+        hidden_addresses,
+        hide_collection: body.hide_collection,
+    };
+    queries::set_privacy_settings(&client, &username, &settings)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to save privacy settings: {}", e)))?;
+    // The warmed /user/level document was built with the previous settings
+    if let Err(e) = queries::delete_user_details_document(&client, &username).await {
+        eprintln!("Failed to drop warmed user details of {}: {}", username, e);
+    }
+    response_cache::invalidate(&profile_cache_key(&username)).await;
+    response_cache::invalidate(&format!("user/level/{}", username)).await;
+
+    Ok(warp::reply::json(&json!({
+        "username": username,
+        "privacy": settings,
+    }))
+    .into_response())
+}
@@ -0,0 +1,255 @@
+use crate::backend::errors::ApiError;
+use crate::backend::exclusions;
+use crate::backend::labels;
+use crate::backend::metadata_store::{MetadataStore, METADATA_STORE};
+use crate::backend::usernames::{addresses_for_username, UsersData};
+use crate::common::file_loader::{load_users_data_from, users_file_from_env};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::env;
+use std::fs;
+use std::sync::Arc;
+use warp::reject::Rejection;
+use warp::Filter;
+
+pub const DEFAULT_PROJECT_ID: &str = "default";
+
+/// A project as defined in the projects file (AFTERLIFE_PATH_PROJECTS), keyed by its id
+///
+/// ```yaml
+/// ghouls:
+///   name: Ghouls
+///   users_file: /data/ghouls/users.json
+///   path_metadata: /data/ghouls/metadata
+///   path_rarities: /data/ghouls/rarities
+///   contracts:
+///     - chain: fantom
+///       address: "0x..."
+///   excluded_users: [GhoulsTreasury]
+/// ```
+#[derive(Debug, Deserialize)]
+struct ProjectDefinition {
+    name: String,
+    users_file: String,
+    // Both default to the deployment's metadata and rarity directories
+    #[serde(default)]
+    path_metadata: Option<String>,
+    #[serde(default)]
+    path_rarities: Option<String>,
+    contracts: Vec<ProjectContract>,
+    #[serde(default)]
+    excluded_users: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectContract {
+    chain: String,
+    address: String,
+}
+
+/// An NFT community served by this deployment: its own user registry, metadata and
+/// contracts, and therefore its own leaderboard.
+///
+/// Unprefixed routes use the default project, made of the environment's users file and
+/// metadata paths and every indexed contract.
+///
+/// Scoped under /p/{project} and /v1/p/{project}: collections and their stats and activity,
+/// /user/level, /user/stats, /activity, /get-username and the leaderboard. The other user
+/// routes (achievements, sets, value, approvals, profiles) and the admin routes only know
+/// the default project.
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    users_file: String,
+    metadata: Option<MetadataStore>,
+    // (lowercased chain, lowercased address), None allows every indexed contract
+    contracts: Option<HashSet<(String, String)>>,
+    excluded_users: Vec<String>,
+}
+
+impl Project {
+    fn from_definition(id: String, definition: ProjectDefinition) -> Self {
+        let metadata = match (definition.path_metadata, definition.path_rarities) {
+            (None, None) => None,
+            (path_metadata, path_rarities) => Some(MetadataStore::new(
+                path_metadata.unwrap_or_else(|| METADATA_STORE.metadata_root().to_string()),
+                path_rarities.unwrap_or_else(|| METADATA_STORE.rarities_root().to_string()),
+            )),
+        };
+        Self {
+            id,
+            name: definition.name,
+            users_file: definition.users_file,
+            metadata,
+            contracts: Some(
+                definition
+                    .contracts
+                    .into_iter()
+                    .map(|c| (c.chain.to_lowercase(), c.address.to_lowercase()))
+                    .collect(),
+            ),
+            excluded_users: definition.excluded_users,
+        }
+    }
+
+    pub fn metadata(&self) -> &MetadataStore {
+        self.metadata.as_ref().unwrap_or(&*METADATA_STORE)
+    }
+
+    /// The project's (lowercased chain, lowercased address), None for every indexed contract
+    pub fn contracts(&self) -> Option<Vec<(String, String)>> {
+        self.contracts
+            .as_ref()
+            .map(|contracts| contracts.iter().cloned().collect())
+    }
+
+    pub fn includes(&self, chain_name: &str, contract_address: &str) -> bool {
+        match &self.contracts {
+            Some(contracts) => {
+                contracts.contains(&(chain_name.to_lowercase(), contract_address.to_lowercase()))
+            }
+            None => true,
+        }
+    }
+
+    // Rejects contracts outside the project, so its routes can't read other projects' data
+    pub fn ensure_includes(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+    ) -> Result<(), ApiError> {
+        if self.includes(chain_name, contract_address) {
+            Ok(())
+        } else {
+            Err(ApiError::NotFound(format!(
+                "Contract {} on {} is not part of project {}",
+                contract_address, chain_name, self.id
+            )))
+        }
+    }
+
+    /// Whether a user or address is excluded, by the projects file, the exclusions table or
+    /// a protocol owned label
+    pub fn is_excluded(&self, username_or_address: &str) -> bool {
+        self.excluded_users
+            .iter()
+            .any(|excluded| excluded.eq_ignore_ascii_case(username_or_address))
+            || exclusions::is_excluded(&self.id, username_or_address)
+            || labels::is_protocol_owned(username_or_address)
+    }
+
+    /// Lowercased addresses of every excluded user and address, for holder and owner lists
+    pub async fn excluded_addresses(&self) -> Result<HashSet<String>, ApiError> {
+        let values: Vec<String> = self
+            .excluded_users
+            .iter()
+            .cloned()
+            .chain(exclusions::excluded_values(&self.id))
+            .collect();
+        let mut addresses: HashSet<String> =
+            labels::protocol_owned_addresses().into_iter().collect();
+        if values.is_empty() {
+            return Ok(addresses);
+        }
+
+        let users = self.users().await?;
+        for value in values {
+            // Resolves usernames, and passes addresses through
+            addresses.extend(
+                addresses_for_username(&users, &value)
+                    .into_iter()
+                    .map(|address| address.to_lowercase()),
+            );
+        }
+        Ok(addresses)
+    }
+
+    pub async fn users(&self) -> Result<UsersData, ApiError> {
+        load_users_data_from(&self.users_file)
+            .await
+            .map_err(|e| ApiError::Internal(format!("[{}] {}", self.id, e)))
+    }
+
+    /// Prefix for response cache keys, so projects never share cached responses
+    pub fn cache_prefix(&self) -> String {
+        if self.contracts.is_none() {
+            String::new()
+        } else {
+            format!("p/{}/", self.id)
+        }
+    }
+}
+
+pub static DEFAULT_PROJECT: Lazy<Arc<Project>> = Lazy::new(|| {
+    Arc::new(Project {
+        id: DEFAULT_PROJECT_ID.to_string(),
+        name: "Afterlife".to_string(),
+        users_file: users_file_from_env(),
+        metadata: None,
+        contracts: None,
+        // Managed in the exclusions table
+        excluded_users: Vec::new(),
+    })
+});
+
+static PROJECTS: Lazy<HashMap<String, Arc<Project>>> = Lazy::new(load_projects);
+
+fn load_projects() -> HashMap<String, Arc<Project>> {
+    let path = match env::var("AFTERLIFE_PATH_PROJECTS") {
+        Ok(path) => path,
+        Err(_) => return HashMap::new(),
+    };
+    let definitions: HashMap<String, ProjectDefinition> = match fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_yaml::from_str(&contents).map_err(|e| e.to_string()))
+    {
+        Ok(definitions) => definitions,
+        Err(e) => {
+            eprintln!("Failed to load projects file {}: {}", path, e);
+            return HashMap::new();
+        }
+    };
+
+    definitions
+        .into_iter()
+        .map(|(id, definition)| {
+            let project = Project::from_definition(id.clone(), definition);
+            println!("Serving project {} ({}) under /p/{}", project.name, id, id);
+            (id.to_lowercase(), Arc::new(project))
+        })
+        .collect()
+}
+
+/// The project with this id, the default project included
+pub fn find(id: &str) -> Option<Arc<Project>> {
+    if id.eq_ignore_ascii_case(DEFAULT_PROJECT_ID) {
+        Some(DEFAULT_PROJECT.clone())
+    } else {
+        PROJECTS.get(&id.to_lowercase()).cloned()
+    }
+}
+
+/// Every project served, the default one first
+pub fn all() -> Vec<Arc<Project>> {
+    std::iter::once(DEFAULT_PROJECT.clone())
+        .chain(PROJECTS.values().cloned())
+        .collect()
+}
+
+/// Matches the `p/{project}` prefix of project scoped routes
+pub fn with_project() -> impl Filter<Extract = (Arc<Project>,), Error = Rejection> + Clone {
+    warp::path("p")
+        .and(warp::path::param::<String>())
+        .and_then(|id: String| async move {
+            PROJECTS.get(&id.to_lowercase()).cloned().ok_or_else(|| {
+                Rejection::from(ApiError::NotFound(format!("Unknown project {}", id)))
+            })
+        })
+}
+
+pub fn with_default_project() -> impl Filter<Extract = (Arc<Project>,), Error = Infallible> + Clone
+{
+    warp::any().map(|| DEFAULT_PROJECT.clone())
+}
@@ -1,19 +1,29 @@
-use serde_json::from_str;
+use crate::backend::repository::FullCollection;
+use crate::common::addresses::{self, ZERO_ADDRESS};
+use crate::common::numeric::{Balance, TokenId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::option::Option;
+use std::str::FromStr;
 use tokio_postgres::Row;
-use warp::Filter;
-use web3::types::U256;
-
-const DEAD_ADDRESS: &str = "0x000000000000000000000000000000000000dEaD";
-const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
 
 #[derive(Debug)]
 pub struct Event {
     pub from_address: Option<String>,
     pub to_address: Option<String>,
-    pub ids: Option<String>,
-    pub values: Option<String>,
+    pub ids: Option<Vec<String>>,
+    pub values: Option<Vec<String>>,
+}
+
+// ids/values are numeric[] columns selected as text[], the whole array is dropped if an entry doesn't fit T
+fn parse_numeric_array<T: FromStr>(values: Option<Vec<String>>) -> Vec<T> {
+    values
+        .unwrap_or_default()
+        .iter()
+        .map(|v| v.parse().ok())
+        .collect::<Option<Vec<T>>>()
+        .unwrap_or_default()
 }
 
 async fn row_to_event(row: Row) -> Event {
@@ -29,12 +39,12 @@ pub async fn get_entire_collection_for_address(
     chain_name: &str,
     contract_address: &str,
     wallet_address: &str,
-) -> Result<HashMap<u64, i64>, Box<dyn std::error::Error + Send>> {
+) -> Result<HashMap<TokenId, Balance>, Box<dyn std::error::Error + Send>> {
     let wallet_address_lowercase = wallet_address.to_lowercase();
     let rows = client
         .query(
             r#"
-            SELECT e.from_address, e.to_address, e.ids, e.values
+            SELECT e.from_address, e.to_address, e.ids::text[] AS ids, e.values::text[] AS values
             FROM events e
             JOIN contracts c ON e.contract_id = c.id
             JOIN chains ch ON c.chain_id = ch.id
@@ -50,7 +60,7 @@ pub async fn get_entire_collection_for_address(
         .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
 
-    let mut balances = HashMap::new();
+    let mut balances: HashMap<TokenId, Balance> = HashMap::new();
     let mut events = Vec::new();
 
     for row in rows {
@@ -60,69 +70,158 @@ pub async fn get_entire_collection_for_address(
     //println!("Found {} events for {} on {}", events.len(), wallet_address, contract_address);
 
     // Process events to determine token owners and their balances
-    // Using i64 to allow for negative values temporarily
+    // Balances are signed to allow for negative values temporarily
     // Convert all addresses to lowercase for comparison
     for event in events {
-        let ids: Vec<u64> = event
-            .ids
-            .as_deref()
-            .and_then(|s| from_str(s).ok())
-            .unwrap_or_default();
-        let values: Vec<i64> = event
-            .values
-            .as_deref()
-            .and_then(|s| from_str::<Vec<u64>>(s).ok())
-            .unwrap_or_default()
-            .into_iter()
-            .map(|v| v as i64) // Convert u64 values to i64 for arithmetic
-            .collect();
+        let ids: Vec<TokenId> = parse_numeric_array(event.ids);
+        let values: Vec<Balance> = parse_numeric_array(event.values);
 
-        for (&id, &value) in ids.iter().zip(values.iter()) {
+        for (&id, value) in ids.iter().zip(values.iter()) {
             if let Some(to_address) = &event.to_address {
                 if to_address.to_lowercase() == wallet_address_lowercase {
-                    *balances.entry(id).or_insert(0) += value;
+                    *balances.entry(id).or_default() += value;
                 }
             }
             if let Some(from_address) = &event.from_address {
                 if from_address.to_lowercase() == wallet_address_lowercase {
-                    *balances.entry(id).or_insert(0) -= value;
+                    *balances.entry(id).or_default() -= value;
                 }
             }
         }
     }
 
     // Remove any items that have a zero balance
-    balances.retain(|_, &mut value| value > 0);
+    balances.retain(|_, value| value.is_positive());
 
     //println!("Found {} balances for {} on {}", balances.len(), wallet_address, contract_address);
 
     Ok(balances)
 }
 
+/// Name of the rarity model configured for the contract, None for the default one
+pub async fn get_rarity_model(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT c.rarity_model
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+            "#,
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.and_then(|row| row.get("rarity_model")))
+}
+
+/// Lowercased addresses transfers to count as consumed for the contract, empty for
+/// contracts without sink addresses
+pub async fn get_contract_sink_addresses(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT c.sink_addresses::text[] AS sink_addresses
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+            "#,
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(|row| row.get("sink_addresses")).unwrap_or_default())
+}
+
+/// Lowercased addresses transfers to count as burns for the contract: the zero and dead
+/// addresses and the contract's own burn addresses
+pub async fn get_contract_burn_addresses(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT c.burn_addresses::text[] AS burn_addresses
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+            "#,
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    let contract_burn_addresses: Vec<String> =
+        row.map(|row| row.get("burn_addresses")).unwrap_or_default();
+    Ok(addresses::burn_addresses(&contract_burn_addresses))
+}
+
+/// `get_contract_burn_addresses` of every contract, keyed by lowercased chain name and
+/// contract address
+pub async fn get_all_burn_addresses(
+    client: &tokio_postgres::Client,
+) -> Result<HashMap<(String, String), Vec<String>>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT LOWER(ch.name) AS chain_name, LOWER(c.address) AS contract_address,
+                c.burn_addresses::text[] AS burn_addresses
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let contract_burn_addresses: Vec<String> = row.get("burn_addresses");
+            (
+                (row.get("chain_name"), row.get("contract_address")),
+                addresses::burn_addresses(&contract_burn_addresses),
+            )
+        })
+        .collect())
+}
+
 pub async fn get_entire_collection(
     client: &tokio_postgres::Client,
     chain_name: &str,
     contract_address: &str,
-) -> Result<Vec<u64>, Box<dyn std::error::Error + Send>> {
+) -> Result<Vec<TokenId>, Box<dyn std::error::Error + Send>> {
     //println!("Get entire collection for {} on {}", contract_address.to_lowercase(), chain_name);
+    let burn_addresses = get_contract_burn_addresses(client, chain_name, contract_address).await?;
     let rows = client
         .query(
             r#"
-            SELECT e.from_address, e.to_address, e.ids, e.values
+            SELECT e.from_address, e.to_address, e.ids::text[] AS ids, e.values::text[] AS values
             FROM events e
             JOIN contracts c ON e.contract_id = c.id
             JOIN chains ch ON c.chain_id = ch.id
             WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2 AND (
                 LOWER(e.from_address) = $3 OR
-                LOWER(e.to_address) = $3 OR
-                LOWER(e.to_address) = $4
+                LOWER(e.to_address) = ANY($4)
             )
             "#,
             &[
                 &contract_address.to_lowercase(),
                 &chain_name.to_lowercase(),
-                &ZERO_ADDRESS.to_lowercase(),
-                &DEAD_ADDRESS.to_lowercase(),
+                &ZERO_ADDRESS,
+                &burn_addresses,
             ],
         )
         .await
@@ -130,60 +229,94 @@ pub async fn get_entire_collection(
 
     //println!("Found {} events for {} on {}", rows.len(), contract_address, chain_name);
 
-    let mut existing_tokens = HashMap::new(); // HashMap<u64, i64>
+    let mut existing_tokens: HashMap<TokenId, Balance> = HashMap::new();
     for row in rows {
         let event = row_to_event(row).await;
-        let ids: Vec<u64> = event
-            .ids
+        let ids: Vec<TokenId> = parse_numeric_array(event.ids);
+        let values: Vec<Balance> = parse_numeric_array(event.values);
+
+        let minted = if event
+            .from_address
             .as_deref()
-            .and_then(|s| serde_json::from_str(s).ok())
-            .unwrap_or_default();
-        let values: Vec<u64> = event
-            .values
+            .is_some_and(addresses::is_mint)
+        {
+            true
+        } else if event
+            .to_address
             .as_deref()
-            .and_then(|s| serde_json::from_str(s).ok())
-            .unwrap_or_default();
-
-        let multiplier = if event.from_address.as_deref() == Some(ZERO_ADDRESS) {
-            1i64
-        } else if event.to_address.as_deref() == Some(DEAD_ADDRESS)
-            || event.to_address.as_deref() == Some(ZERO_ADDRESS)
+            .is_some_and(|to| addresses::is_burn(to, &burn_addresses))
         {
-            -1i64
+            false
         } else {
             continue;
         };
 
-        for (&id, &value) in ids.iter().zip(values.iter()) {
-            *existing_tokens.entry(id).or_insert(0) += value as i64 * multiplier;
+        for (&id, value) in ids.iter().zip(values.iter()) {
+            let supply = existing_tokens.entry(id).or_default();
+            if minted {
+                *supply += value;
+            } else {
+                *supply -= value;
+            }
         }
     }
 
-    let result: Vec<u64> = existing_tokens
+    let result: Vec<TokenId> = existing_tokens
         .into_iter()
-        .filter_map(|(id, count)| if count > 0 { Some(id) } else { None })
+        .filter_map(|(id, count)| if count.is_positive() { Some(id) } else { None })
         .collect();
 
     Ok(result)
 }
 
+/// Last block the indexer processed for a contract, None for unknown contracts
+pub async fn get_last_processed_block(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<Option<i32>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT c.last_processed_block
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+            "#,
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.map(|row| row.get("last_processed_block")))
+}
+
+/// Owners of a token, as of the end of `block` when given (only events up to it are
+/// replayed), currently otherwise
 pub async fn get_token_owners(
     client: &tokio_postgres::Client,
     chain_name: &str,
     contract_address: &str,
-    token_id: u64,
+    token_id: TokenId,
+    block: Option<i32>,
 ) -> Result<Vec<String>, Box<dyn std::error::Error + Send>> {
     // Retrieve token events from the database
     let rows = client
         .query(
             r#"
-            SELECT e.from_address, e.to_address, e.ids, e.values
+            SELECT e.from_address, e.to_address, e.ids::text[] AS ids, e.values::text[] AS values
             FROM events e
             JOIN contracts c ON e.contract_id = c.id
             JOIN chains ch ON c.chain_id = ch.id
             WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+                AND e.ids @> ARRAY[$3::text::numeric]
+                AND ($4::int4 IS NULL OR e.block_number <= $4)
             "#,
-            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &token_id.to_string(),
+                &block,
+            ],
         )
         .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
@@ -194,142 +327,154 @@ pub async fn get_token_owners(
     }
 
     // Process events to determine token owners and their balances
-    let mut owners: HashMap<String, i64> = HashMap::new(); // Using i64 to allow for negative values temporarily
+    let mut owners: HashMap<String, Balance> = HashMap::new(); // Signed to allow for negative values temporarily
 
     for event in events {
-        let ids: Vec<u64> = event
-            .ids
-            .as_deref()
-            .and_then(|s| from_str(s).ok())
-            .unwrap_or_default();
-        let values: Vec<i64> = event
-            .values
-            .as_deref()
-            .and_then(|s| from_str::<Vec<u64>>(s).ok())
-            .unwrap_or_default()
-            .into_iter()
-            .map(|v| v as i64) // Convert u64 values to i64 for arithmetic
-            .collect();
+        let ids: Vec<TokenId> = parse_numeric_array(event.ids);
+        let values: Vec<Balance> = parse_numeric_array(event.values);
 
-        for (&id, &value) in ids.iter().zip(values.iter()) {
+        for (&id, value) in ids.iter().zip(values.iter()) {
             if id == token_id {
                 if let Some(to_address) = &event.to_address {
-                    *owners.entry(to_address.clone()).or_insert(0) += value;
+                    *owners.entry(to_address.clone()).or_default() += value;
                 }
                 if let Some(from_address) = &event.from_address {
-                    *owners.entry(from_address.clone()).or_insert(0) -= value;
+                    *owners.entry(from_address.clone()).or_default() -= value;
                 }
             }
         }
     }
-    // Remove 0x000000000000000000000000000000000000dEaD from results
-    owners.remove("0x000000000000000000000000000000000000dead");
-    owners.remove("0x000000000000000000000000000000000000dEaD");
+    // Tokens sent to a burn address are gone, not owned
+    let burn_addresses = get_contract_burn_addresses(client, chain_name, contract_address).await?;
+    owners.retain(|address, _| !addresses::is_burn(address, &burn_addresses));
 
     // Filter out the addresses with zero balances and collect the owners
     let owner_addresses: Vec<String> = owners
         .into_iter()
-        .filter(|&(_, value)| value > 0)
+        .filter(|(_, value)| value.is_positive())
         .map(|(address, _)| address)
         .collect();
 
     Ok(owner_addresses)
 }
 
+/// Collection of one wallet. Contracts flagged as unverified (spam, scams) are left out
+/// unless `include_unverified` is set.
 pub async fn get_user_full_collection(
     client: &tokio_postgres::Client,
     wallet_address: &str,
-) -> Result<HashMap<String, HashMap<String, HashMap<u64, i64>>>, Box<dyn std::error::Error + Send>>
-{
+    include_unverified: bool,
+) -> Result<FullCollection, Box<dyn std::error::Error + Send>> {
     let wallet_address_lowercase = wallet_address.to_lowercase();
+    let mut collections = get_wallets_collections(
+        client,
+        std::slice::from_ref(&wallet_address_lowercase),
+        include_unverified,
+    )
+    .await?;
+    Ok(collections
+        .remove(&wallet_address_lowercase)
+        .unwrap_or_default())
+}
+
+/// Collections of several wallets in one round trip, keyed by lowercased address.
+/// Wallets without tokens are left out, so are unverified contracts.
+pub async fn get_users_full_collections(
+    client: &tokio_postgres::Client,
+    wallet_addresses: &[String],
+) -> Result<HashMap<String, FullCollection>, Box<dyn std::error::Error + Send>> {
+    get_wallets_collections(client, wallet_addresses, false).await
+}
+
+async fn get_wallets_collections(
+    client: &tokio_postgres::Client,
+    wallet_addresses: &[String],
+    include_unverified: bool,
+) -> Result<HashMap<String, FullCollection>, Box<dyn std::error::Error + Send>> {
+    let wallet_addresses_lowercase: Vec<String> = wallet_addresses
+        .iter()
+        .map(|address| address.to_lowercase())
+        .collect();
     let rows = client
         .query(
             r#"
-            SELECT ch.name as chain_name, c.address as contract_address, e.from_address, e.to_address, e.ids, e.values
+            SELECT ch.name as chain_name, c.address as contract_address, e.from_address, e.to_address, e.ids::text[] AS ids, e.values::text[] AS values
             FROM events e
             INNER JOIN contracts c ON e.contract_id = c.id
             INNER JOIN chains ch ON c.chain_id = ch.id
-            WHERE LOWER(e.from_address) = $1 OR LOWER(e.to_address) = $1
+            WHERE (LOWER(e.from_address) = ANY($1) OR LOWER(e.to_address) = ANY($1))
+                AND (c.verified OR $2)
             "#,
-            &[&wallet_address_lowercase],
+            &[&wallet_addresses_lowercase, &include_unverified],
         )
         .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
 
-    let mut collection: HashMap<String, HashMap<String, HashMap<u64, i64>>> = HashMap::new();
+    let mut collections: HashMap<String, FullCollection> = HashMap::new();
 
     for row in rows {
         let chain_name: String = row.get("chain_name");
         let contract_address: String = row.get("contract_address");
-        let ids: Vec<u64> = serde_json::from_str(row.get::<_, &str>("ids")).unwrap_or_default();
-        let values: Vec<i64> = serde_json::from_str::<Vec<u64>>(row.get::<_, &str>("values"))
-            .unwrap_or_default()
-            .into_iter()
-            .map(|v| v as i64)
-            .collect();
+        let ids: Vec<TokenId> = parse_numeric_array(row.get("ids"));
+        let values: Vec<Balance> = parse_numeric_array(row.get("values"));
 
-        let from_address: String = row.get("from_address");
-        let to_address: String = row.get("to_address");
+        let from_address = row
+            .get::<_, Option<String>>("from_address")
+            .unwrap_or_default()
+            .to_lowercase();
+        let to_address = row
+            .get::<_, Option<String>>("to_address")
+            .unwrap_or_default()
+            .to_lowercase();
 
-        // This closure will determine how to adjust the balance based on the event's 'from' and 'to' addresses
-        let adjust_balance = |current_balance: &mut i64,
-                              id_value: i64,
-                              event_from_address: &str,
-                              event_to_address: &str| {
-            if event_to_address.to_lowercase() == wallet_address_lowercase {
-                *current_balance += id_value;
+        // A transfer between two of the wallets moves the tokens from one to the other
+        for (wallet_address, sign) in [(&to_address, 1), (&from_address, -1)] {
+            if !wallet_addresses_lowercase.contains(wallet_address) {
+                continue;
             }
-            if event_from_address.to_lowercase() == wallet_address_lowercase {
-                *current_balance -= id_value;
+            // Get or create the balances map for the wallet, chain and contract
+            let contract_balances = collections
+                .entry(wallet_address.clone())
+                .or_default()
+                .entry(chain_name.clone())
+                .or_default()
+                .entry(contract_address.clone())
+                .or_default();
+            for (&id, value) in ids.iter().zip(values.iter()) {
+                let balance = contract_balances.entry(id).or_default();
+                if sign > 0 {
+                    *balance += value;
+                } else {
+                    *balance -= value;
+                }
             }
-        };
-
-        // Get or create the balances map for the current chain and contract
-        let contract_balances = collection
-            .entry(chain_name)
-            .or_insert_with(HashMap::new)
-            .entry(contract_address)
-            .or_insert_with(HashMap::new);
-
-        // Adjust balances based on the event data
-        for (&id, &value) in ids.iter().zip(values.iter()) {
-            adjust_balance(
-                contract_balances.entry(id).or_insert(0),
-                value,
-                &from_address,
-                &to_address,
-            );
         }
     }
 
-    // Clean up the data by removing entries with zero balance
-    for chain_balances in collection.values_mut() {
-        for contract_balances in chain_balances.values_mut() {
-            contract_balances.retain(|_, &mut v| v != 0);
+    // Clean up the data by removing empty balances, then empty contracts, chains and
+    // wallets. Negative balances (incoming transfers missing from the events) count as none.
+    for collection in collections.values_mut() {
+        for chain_balances in collection.values_mut() {
+            for contract_balances in chain_balances.values_mut() {
+                contract_balances.retain(|_, v| v.is_positive());
+            }
+            chain_balances.retain(|_, v| !v.is_empty());
         }
+        collection.retain(|_, v| !v.is_empty());
     }
+    collections.retain(|_, v| !v.is_empty());
 
-    // Remove empty contracts
-    for chain_balances in collection.values_mut() {
-        chain_balances.retain(|_, v| !v.is_empty());
-    }
-
-    // Remove empty chains
-    collection.retain(|_, v| !v.is_empty());
-
-    Ok(collection)
+    Ok(collections)
 }
 
+// Balances of every address per chain and contract. With `credit_consumed`, tokens sent
+// to a sink address of a contract that credits consumption stay with their sender, and
+// what the sink sends on is left out so those tokens aren't credited twice.
 pub async fn get_all_users_collections(
     client: &tokio_postgres::Client,
-) -> Result<
-    HashMap<String, HashMap<String, HashMap<String, HashMap<u64, i64>>>>,
-    Box<dyn std::error::Error + Send + Sync>,
-> {
-    let mut all_users_collections: HashMap<
-        String,
-        HashMap<String, HashMap<String, HashMap<u64, i64>>>,
-    > = HashMap::new();
+    credit_consumed: bool,
+) -> Result<HashMap<String, FullCollection>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut all_users_collections: HashMap<String, FullCollection> = HashMap::new();
 
     let query = r#"
         SELECT
@@ -337,8 +482,10 @@ pub async fn get_all_users_collections(
             e.from_address AS from_address,
             ch.name AS chain_name,
             c.address AS contract_address,
-            e.ids AS ids,
-            e.values AS values
+            e.ids::text[] AS ids,
+            e.values::text[] AS values,
+            CASE WHEN c.credit_consumed THEN c.sink_addresses::text[] ELSE '{}' END
+                AS credited_sinks
         FROM events e
         INNER JOIN contracts c ON e.contract_id = c.id
         INNER JOIN chains ch ON c.chain_id = ch.id;
@@ -353,18 +500,22 @@ pub async fn get_all_users_collections(
     for row in rows {
         let to_address: String = row.get("to_address");
         let from_address: String = row.get("from_address");
-        let ids: Vec<u64> = serde_json::from_str(row.get::<_, &str>("ids"))?;
-        let values: Vec<i64> = serde_json::from_str::<Vec<u64>>(row.get::<_, &str>("values"))
-            .unwrap_or_default()
-            .into_iter()
-            .map(|v| v as i64)
-            .collect();
+        let ids: Vec<TokenId> = parse_numeric_array(row.get("ids"));
+        let values: Vec<Balance> = parse_numeric_array(row.get("values"));
 
         let chain_name: String = row.get("chain_name");
         let contract_address: String = row.get("contract_address");
+        if credit_consumed {
+            let credited_sinks: Vec<String> = row.get("credited_sinks");
+            if addresses::is_sink(&to_address, &credited_sinks)
+                || addresses::is_sink(&from_address, &credited_sinks)
+            {
+                continue;
+            }
+        }
 
         // Using a single loop to update both to and from addresses
-        for (&id, &value) in ids.iter().zip(values.iter()) {
+        for (&id, value) in ids.iter().zip(values.iter()) {
             if !to_address.is_empty() {
                 let balance = all_users_collections
                     .entry(to_address.clone())
@@ -374,7 +525,7 @@ pub async fn get_all_users_collections(
                     .entry(contract_address.clone())
                     .or_default()
                     .entry(id)
-                    .or_insert(0);
+                    .or_default();
                 *balance += value;
             }
 
@@ -387,7 +538,7 @@ pub async fn get_all_users_collections(
                     .entry(contract_address.clone())
                     .or_default()
                     .entry(id)
-                    .or_insert(0);
+                    .or_default();
                 *balance -= value;
             }
         }
@@ -397,7 +548,7 @@ pub async fn get_all_users_collections(
     all_users_collections.retain(|_, chains| {
         chains.retain(|_, contracts| {
             contracts.retain(|_, balances| {
-                balances.retain(|_, &mut v| v != 0);
+                balances.retain(|_, v| !v.is_zero());
                 !balances.is_empty()
             });
             !contracts.is_empty()
@@ -408,6 +559,87 @@ pub async fn get_all_users_collections(
     Ok(all_users_collections)
 }
 
+// Highest balance every holder ever held of each token, per chain and contract. The
+// addresses of a user, keys of `usernames_by_address`, hold together: moving a token
+// between them doesn't raise the user's peak. Holders are keyed by one of their lowercased
+// addresses. Tokens are counted when received and never taken back when sent on; transfers
+// to the sink addresses of a contract that credits consumption are left out, like in the
+// leaderboard.
+pub async fn get_all_users_peak_collections(
+    client: &tokio_postgres::Client,
+    usernames_by_address: &HashMap<String, String>,
+) -> Result<HashMap<String, FullCollection>, Box<dyn std::error::Error + Send + Sync>> {
+    let (addresses, usernames): (Vec<&str>, Vec<&str>) = usernames_by_address
+        .iter()
+        .map(|(address, username)| (address.as_str(), username.as_str()))
+        .unzip();
+    // Without a ROWS frame the running sum takes in every row of the same log at once, so a
+    // transfer to oneself, or between a user's addresses, doesn't raise the peak
+    let query = r#"
+        WITH owners AS (
+            SELECT * FROM unnest($1::text[], $2::text[]) AS o(address, username)
+        ),
+        transfers AS (
+            SELECT e.contract_id, LOWER(e.from_address) AS from_address,
+                LOWER(e.to_address) AS to_address, e.block_number, e.log_index, t.id, t.value
+            FROM events e
+            JOIN contracts c ON e.contract_id = c.id
+            CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value)
+            WHERE NOT (c.credit_consumed AND LOWER(e.to_address) = ANY(c.sink_addresses::text[]))
+        ),
+        deltas AS (
+            SELECT contract_id, to_address AS address, block_number, log_index, id, value AS delta
+            FROM transfers
+            UNION ALL
+            SELECT contract_id, from_address, block_number, log_index, id, -value
+            FROM transfers
+        ),
+        holders AS (
+            SELECT d.*, COALESCE('user:' || o.username, d.address) AS holder
+            FROM deltas d
+            LEFT JOIN owners o ON o.address = d.address
+        ),
+        running AS (
+            SELECT contract_id, holder, address, id, SUM(delta) OVER (
+                PARTITION BY contract_id, holder, id ORDER BY block_number, log_index
+            ) AS balance
+            FROM holders
+        )
+        SELECT MIN(r.address) AS address, ch.name AS chain_name, c.address AS contract_address,
+            r.id::text AS token_id, MAX(r.balance)::text AS peak
+        FROM running r
+        JOIN contracts c ON r.contract_id = c.id
+        JOIN chains ch ON c.chain_id = ch.id
+        GROUP BY r.holder, ch.name, c.address, r.id
+        HAVING MAX(r.balance) > 0
+    "#;
+    let rows = client.query(query, &[&addresses, &usernames]).await?;
+
+    let mut peak_collections: HashMap<String, FullCollection> = HashMap::new();
+    for row in rows {
+        let address: String = row.get("address");
+        let token_id: String = row.get("token_id");
+        let peak: String = row.get("peak");
+        let (token_id, peak) = match (token_id.parse(), peak.parse()) {
+            (Ok(token_id), Ok(peak)) => (token_id, peak),
+            _ => continue,
+        };
+        if address.is_empty() {
+            continue;
+        }
+        peak_collections
+            .entry(address)
+            .or_default()
+            .entry(row.get("chain_name"))
+            .or_default()
+            .entry(row.get("contract_address"))
+            .or_default()
+            .insert(token_id, peak);
+    }
+
+    Ok(peak_collections)
+}
+
 pub async fn get_contract_name_from_chain_and_address(
     client: &tokio_postgres::Client,
     chain_name: &str,
@@ -426,7 +658,3715 @@ pub async fn get_contract_name_from_chain_and_address(
         .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
 
-    let row = rows.get(0);
+    let row = rows.first();
     // return the name or "Unknown"
     Ok(row.map(|r| r.get("name")).unwrap_or("Unknown".to_string()))
 }
+
+// Returns false when no such contract is indexed
+pub async fn set_contract_verified(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    verified: bool,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let updated = client
+        .execute(
+            r#"
+            UPDATE contracts SET verified = $3
+            WHERE LOWER(address) = $1
+                AND chain_id IN (SELECT id FROM chains WHERE LOWER(name) = $2)
+            "#,
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &verified,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(updated > 0)
+}
+
+/// Rows removed from the live tables by archive_contract
+#[derive(Debug, Serialize)]
+pub struct ArchivedContract {
+    pub archived_events: i64,
+    pub deleted_balances: i64,
+    pub deleted_listings: i64,
+    pub deleted_approvals: i64,
+    pub archived_sales: i64,
+    pub deleted_supply_history: i64,
+    pub deleted_token_metadata: i64,
+}
+
+/// Moves the events and sales of a contract to events_archive and sales_archive, drops the
+/// balances, listings, approvals, supply history and token metadata kept for it and marks
+/// it archived, all in one statement. The contract row is locked like the indexer's
+/// writes. None when no such contract is indexed.
+pub async fn archive_contract(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<Option<ArchivedContract>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            r#"
+            WITH contract AS (
+                SELECT c.id
+                FROM contracts c
+                JOIN chains ch ON c.chain_id = ch.id
+                WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+                FOR UPDATE OF c
+            ), moved AS (
+                DELETE FROM events
+                WHERE contract_id IN (SELECT id FROM contract)
+                RETURNING *
+            ), archived AS (
+                INSERT INTO events_archive (id, contract_id, operator, from_address, to_address,
+                    ids, values, block_number, transaction_hash, block_timestamp,
+                    transaction_index, log_index)
+                SELECT id, contract_id, operator, from_address, to_address, ids, values,
+                    block_number, transaction_hash, block_timestamp, transaction_index, log_index
+                FROM moved
+                RETURNING 1
+            ), balances_deleted AS (
+                DELETE FROM balances WHERE contract_id IN (SELECT id FROM contract) RETURNING 1
+            ), listings_deleted AS (
+                DELETE FROM listings WHERE contract_id IN (SELECT id FROM contract) RETURNING 1
+            ), approvals_deleted AS (
+                DELETE FROM approvals WHERE contract_id IN (SELECT id FROM contract) RETURNING 1
+            ), sales_moved AS (
+                DELETE FROM sales
+                WHERE contract_id IN (SELECT id FROM contract)
+                RETURNING *
+            ), sales_archived AS (
+                INSERT INTO sales_archive (id, contract_id, marketplace, token_id, amount,
+                    seller, buyer, price, currency, block_number, transaction_hash, log_index,
+                    block_timestamp)
+                SELECT id, contract_id, marketplace, token_id, amount, seller, buyer, price,
+                    currency, block_number, transaction_hash, log_index, block_timestamp
+                FROM sales_moved
+                RETURNING 1
+            ), supply_deleted AS (
+                DELETE FROM supply_history WHERE contract_id IN (SELECT id FROM contract)
+                RETURNING 1
+            ), metadata_deleted AS (
+                DELETE FROM token_metadata
+                WHERE contract_address = $1 AND chain = $2 AND EXISTS (SELECT 1 FROM contract)
+                RETURNING 1
+            ), marked AS (
+                UPDATE contracts SET archived_at = COALESCE(archived_at, now())
+                WHERE id IN (SELECT id FROM contract)
+                RETURNING 1
+            )
+            SELECT
+                (SELECT COUNT(*) FROM marked) AS contracts,
+                (SELECT COUNT(*) FROM archived) AS archived_events,
+                (SELECT COUNT(*) FROM balances_deleted) AS deleted_balances,
+                (SELECT COUNT(*) FROM listings_deleted) AS deleted_listings,
+                (SELECT COUNT(*) FROM approvals_deleted) AS deleted_approvals,
+                (SELECT COUNT(*) FROM sales_archived) AS archived_sales,
+                (SELECT COUNT(*) FROM supply_deleted) AS deleted_supply_history,
+                (SELECT COUNT(*) FROM metadata_deleted) AS deleted_token_metadata
+            "#,
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    let contracts: i64 = row.get("contracts");
+    Ok((contracts > 0).then(|| ArchivedContract {
+        archived_events: row.get("archived_events"),
+        deleted_balances: row.get("deleted_balances"),
+        deleted_listings: row.get("deleted_listings"),
+        deleted_approvals: row.get("deleted_approvals"),
+        archived_sales: row.get("archived_sales"),
+        deleted_supply_history: row.get("deleted_supply_history"),
+        deleted_token_metadata: row.get("deleted_token_metadata"),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct SupplyBucket {
+    pub start_block: i32,
+    pub end_block: i32,
+    // Unix timestamp (seconds) of the bucket's last event
+    pub timestamp: Option<i64>,
+    // Decimal strings, ERC-1155 amounts don't fit an i64
+    pub minted: String,
+    pub burned: String,
+    // Minted minus burned up to the end of the bucket
+    pub supply: String,
+}
+
+// Mints and burns of the contract per block bucket, oldest first, with the running supply
+pub async fn get_supply_history(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<Vec<SupplyBucket>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT s.start_block, s.end_block,
+                EXTRACT(EPOCH FROM s.block_timestamp)::bigint AS timestamp,
+                s.minted::text AS minted, s.burned::text AS burned,
+                (SUM(s.minted - s.burned) OVER (ORDER BY s.start_block))::text AS supply
+            FROM supply_history s
+            JOIN contracts c ON s.contract_id = c.id
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+            ORDER BY s.start_block
+            "#,
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| SupplyBucket {
+            start_block: row.get("start_block"),
+            end_block: row.get("end_block"),
+            timestamp: row.get("timestamp"),
+            minted: row.get("minted"),
+            burned: row.get("burned"),
+            supply: row.get("supply"),
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScoreWeightRow {
+    pub chain: String,
+    pub contract_address: String,
+    pub contract_name: String,
+    pub weight: f64,
+    pub normalization: String,
+}
+
+pub async fn get_score_weights(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<ScoreWeightRow>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT ch.name AS chain, c.address AS contract_address, c.name AS contract_name,
+                c.score_weight, c.score_normalization
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE c.archived_at IS NULL
+            ORDER BY ch.name, c.name
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ScoreWeightRow {
+            chain: row.get("chain"),
+            contract_address: row.get("contract_address"),
+            contract_name: row.get("contract_name"),
+            weight: row.get("score_weight"),
+            normalization: row.get("score_normalization"),
+        })
+        .collect())
+}
+
+// Returns false when no such contract is indexed
+pub async fn set_score_weight(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    weight: f64,
+    normalization: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let updated = client
+        .execute(
+            r#"
+            UPDATE contracts SET score_weight = $3, score_normalization = $4
+            WHERE LOWER(address) = $1
+                AND chain_id IN (SELECT id FROM chains WHERE LOWER(name) = $2)
+            "#,
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &weight,
+                &normalization,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(updated > 0)
+}
+
+#[derive(Debug)]
+pub struct RarityTiersRow {
+    pub chain: String,
+    pub contract_address: String,
+    pub rare: f64,
+    pub epic: f64,
+    pub legendary: f64,
+}
+
+/// Contracts with their own rarity tier thresholds
+pub async fn get_rarity_tiers(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<RarityTiersRow>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT ch.name AS chain, c.address AS contract_address,
+                c.rarity_tier_rare, c.rarity_tier_epic, c.rarity_tier_legendary
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE c.rarity_tier_rare IS NOT NULL
+                AND c.rarity_tier_epic IS NOT NULL
+                AND c.rarity_tier_legendary IS NOT NULL
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| RarityTiersRow {
+            chain: row.get("chain"),
+            contract_address: row.get("contract_address"),
+            rare: row.get("rarity_tier_rare"),
+            epic: row.get("rarity_tier_epic"),
+            legendary: row.get("rarity_tier_legendary"),
+        })
+        .collect())
+}
+
+// (rare, epic, legendary), None goes back to the defaults. Returns false when no such
+// contract is indexed.
+pub async fn set_rarity_tiers(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    thresholds: Option<(f64, f64, f64)>,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let (rare, epic, legendary) = match thresholds {
+        Some((rare, epic, legendary)) => (Some(rare), Some(epic), Some(legendary)),
+        None => (None, None, None),
+    };
+    let updated = client
+        .execute(
+            r#"
+            UPDATE contracts
+            SET rarity_tier_rare = $3, rarity_tier_epic = $4, rarity_tier_legendary = $5
+            WHERE LOWER(address) = $1
+                AND chain_id IN (SELECT id FROM chains WHERE LOWER(name) = $2)
+            "#,
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &rare,
+                &epic,
+                &legendary,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(updated > 0)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContractSyncStatus {
+    pub chain: String,
+    pub contract_name: String,
+    pub contract_address: String,
+    pub last_processed_block: i32,
+    pub head_block: Option<i32>,
+    // Last final block, the indexer doesn't move last_processed_block past it
+    pub safe_block: Option<i32>,
+    pub blocks_behind: Option<i32>,
+    // Unix timestamp (seconds) of the last successful indexing run
+    pub last_indexed_at: Option<i64>,
+}
+
+pub async fn get_sync_status(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<ContractSyncStatus>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT ch.name AS chain_name, ch.head_block, ch.safe_block, c.name AS contract_name,
+                c.address AS contract_address, c.last_processed_block,
+                EXTRACT(EPOCH FROM c.last_indexed_at)::bigint AS last_indexed_at
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE c.archived_at IS NULL
+            ORDER BY ch.name, c.name
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let last_processed_block: i32 = row.get("last_processed_block");
+            let head_block: Option<i32> = row.get("head_block");
+            let safe_block: Option<i32> = row.get("safe_block");
+            ContractSyncStatus {
+                chain: row.get("chain_name"),
+                contract_name: row.get("contract_name"),
+                contract_address: row.get("contract_address"),
+                last_processed_block,
+                head_block,
+                safe_block,
+                blocks_behind: safe_block
+                    .or(head_block)
+                    .map(|block| (block - last_processed_block).max(0)),
+                last_indexed_at: row.get("last_indexed_at"),
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct CatalogContract {
+    pub chain: String,
+    pub name: String,
+    pub address: String,
+    pub slug: Option<String>,
+    pub r#type: String,
+    // NULL until the indexer synced it
+    pub start_block: Option<i32>,
+    pub verified: bool,
+    // Tokens in circulation and addresses holding them, burn and excluded addresses aside
+    pub token_count: i64,
+    pub holder_count: i64,
+}
+
+// Every live contract, with the holder count of get_holder_count
+pub async fn get_catalog(
+    client: &tokio_postgres::Client,
+    excluded_addresses: &[String],
+) -> Result<Vec<CatalogContract>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            WITH held AS (
+                SELECT b.contract_id,
+                    COUNT(DISTINCT b.token_id) AS token_count,
+                    COUNT(DISTINCT b.address) AS holder_count
+                FROM balances b
+                JOIN contracts c ON b.contract_id = c.id
+                WHERE c.archived_at IS NULL
+                    AND b.address <> ALL($1)
+                    AND b.address <> ALL(c.burn_addresses::text[])
+                    AND b.address <> ALL($2)
+                GROUP BY b.contract_id
+            )
+            SELECT ch.name AS chain_name, c.name, c.address, c.slug, c.type, c.start_block,
+                c.verified, COALESCE(h.token_count, 0) AS token_count,
+                COALESCE(h.holder_count, 0) AS holder_count
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            LEFT JOIN held h ON h.contract_id = c.id
+            WHERE c.archived_at IS NULL
+            ORDER BY LOWER(ch.name), c.name
+            "#,
+            &[&addresses::burn_addresses(&[]), &excluded_addresses],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| CatalogContract {
+            chain: row.get::<_, String>("chain_name").to_lowercase(),
+            name: row.get("name"),
+            address: row.get("address"),
+            slug: row.get("slug"),
+            r#type: row.get("type"),
+            start_block: row.get("start_block"),
+            verified: row.get("verified"),
+            token_count: row.get("token_count"),
+            holder_count: row.get("holder_count"),
+        })
+        .collect())
+}
+
+// Lowercased name of every chain, sorted
+// Chain names as stored, the way the indexer config spells them
+pub async fn get_chain_names(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query("SELECT name FROM chains ORDER BY LOWER(name)", &[])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows.into_iter().map(|row| row.get("name")).collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NativePrice {
+    pub chain: String,
+    pub coin: String,
+    pub usd: f64,
+    // Unix timestamp (seconds) of the fetch
+    pub fetched_at: i64,
+}
+
+// Latest USD price of every chain's native currency, sorted by chain
+pub async fn get_native_prices(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<NativePrice>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT chain, coin, usd, EXTRACT(EPOCH FROM fetched_at)::bigint AS fetched_at
+            FROM native_prices
+            ORDER BY chain
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| NativePrice {
+            chain: row.get("chain"),
+            coin: row.get("coin"),
+            usd: row.get("usd"),
+            fetched_at: row.get("fetched_at"),
+        })
+        .collect())
+}
+
+// USD price of a chain's native currency on the UTC day of each timestamp, keyed by
+// timestamp. Falls back to the last price of the week before, timestamps without a price
+// that recent are left out.
+pub async fn get_native_prices_at(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    timestamps: &[i64],
+) -> Result<HashMap<i64, f64>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT t.ts, price.usd
+            FROM unnest($2::bigint[]) AS t(ts)
+            CROSS JOIN LATERAL (
+                SELECT usd
+                FROM native_price_history
+                WHERE chain = $1
+                  AND day <= (to_timestamp(t.ts) AT TIME ZONE 'UTC')::date
+                  AND day > (to_timestamp(t.ts) AT TIME ZONE 'UTC')::date - 7
+                ORDER BY day DESC
+                LIMIT 1
+            ) price
+            "#,
+            &[&chain_name.to_lowercase(), &timestamps],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("ts"), row.get("usd")))
+        .collect())
+}
+
+// Address of the live contract with a slug on a chain
+pub async fn get_contract_address_by_slug(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    slug: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT c.address
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(ch.name) = $1 AND c.slug = $2 AND c.archived_at IS NULL
+            "#,
+            &[&chain_name.to_lowercase(), &slug.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(|row| row.get("address")))
+}
+
+// (chain, contract name, contract address) of every contract being indexed
+// (chain, address) of the ERC-1155 contracts, as detected or else as configured
+pub async fn get_erc1155_contracts(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT ch.name AS chain_name, c.address AS contract_address
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(COALESCE(NULLIF(c.detected_type, 'unknown'), c.type)) = 'erc1155'
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("chain_name"), row.get("contract_address")))
+        .collect())
+}
+
+pub async fn get_registered_contracts(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<(String, String, String)>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT ch.name AS chain_name, c.name AS contract_name, c.address AS contract_address
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE c.archived_at IS NULL
+            ORDER BY ch.name, c.name
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get("chain_name"),
+                row.get("contract_name"),
+                row.get("contract_address"),
+            )
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChainActivity {
+    pub chain: String,
+    pub events_24h: i64,
+    pub head_block: Option<i32>,
+    // Unix timestamp (seconds)
+    pub head_updated_at: Option<i64>,
+}
+
+// Events per chain with a block timestamp in the last 24 hours
+pub async fn get_chain_activity(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<ChainActivity>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT ch.name AS chain_name, ch.head_block,
+                EXTRACT(EPOCH FROM ch.head_updated_at)::bigint AS head_updated_at,
+                (SELECT COUNT(*) FROM events e
+                    JOIN contracts c ON e.contract_id = c.id
+                    WHERE c.chain_id = ch.id AND e.block_timestamp > now() - interval '24 hours'
+                ) AS events_24h
+            FROM chains ch
+            ORDER BY ch.name
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ChainActivity {
+            chain: row.get("chain_name"),
+            events_24h: row.get("events_24h"),
+            head_block: row.get("head_block"),
+            head_updated_at: row.get("head_updated_at"),
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcFailureCount {
+    pub chain: String,
+    pub host: String,
+    pub failures_24h: i64,
+    pub last_error: String,
+    // Unix timestamp (seconds)
+    pub last_failed_at: i64,
+}
+
+// Failed RPC requests of the last 24 hours per chain and provider, as recorded by the indexer
+pub async fn get_rpc_failure_counts(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<RpcFailureCount>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT DISTINCT ON (chain, host) chain, host,
+                SUM(failures) OVER (PARTITION BY chain, host)::bigint AS failures_24h,
+                last_error, EXTRACT(EPOCH FROM last_failed_at)::bigint AS last_failed_at
+            FROM rpc_failures
+            WHERE hour > now() - interval '24 hours'
+            ORDER BY chain, host, last_failed_at DESC
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| RpcFailureCount {
+            chain: row.get("chain"),
+            host: row.get("host"),
+            failures_24h: row.get("failures_24h"),
+            last_error: row.get("last_error"),
+            last_failed_at: row.get("last_failed_at"),
+        })
+        .collect())
+}
+
+pub async fn get_database_size(
+    client: &tokio_postgres::Client,
+) -> Result<i64, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one("SELECT pg_database_size(current_database()) AS size", &[])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.get("size"))
+}
+
+/// Latest refresh of the data computed or fetched in the background, Unix timestamps
+#[derive(Debug, Serialize)]
+pub struct DataFreshness {
+    pub leaderboard_computed_at: Option<i64>,
+    pub listings_updated_at: Option<i64>,
+    pub delegations_updated_at: Option<i64>,
+    pub metadata_fetched_at: Option<i64>,
+    pub last_indexed_at: Option<i64>,
+}
+
+pub async fn get_data_freshness(
+    client: &tokio_postgres::Client,
+) -> Result<DataFreshness, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            r#"
+            SELECT
+                (SELECT EXTRACT(EPOCH FROM MAX(computed_at))::bigint FROM leaderboards)
+                    AS leaderboard_computed_at,
+                (SELECT EXTRACT(EPOCH FROM MAX(updated_at))::bigint FROM listings)
+                    AS listings_updated_at,
+                (SELECT EXTRACT(EPOCH FROM MAX(updated_at))::bigint FROM delegations)
+                    AS delegations_updated_at,
+                (SELECT EXTRACT(EPOCH FROM MAX(fetched_at))::bigint FROM token_metadata)
+                    AS metadata_fetched_at,
+                (SELECT EXTRACT(EPOCH FROM MAX(last_indexed_at))::bigint FROM contracts
+                    WHERE archived_at IS NULL) AS last_indexed_at
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(DataFreshness {
+        leaderboard_computed_at: row.get("leaderboard_computed_at"),
+        listings_updated_at: row.get("listings_updated_at"),
+        delegations_updated_at: row.get("delegations_updated_at"),
+        metadata_fetched_at: row.get("metadata_fetched_at"),
+        last_indexed_at: row.get("last_indexed_at"),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetadataBacklog {
+    // Tokens in circulation the metadata store has no row for
+    pub tokens_without_metadata: i64,
+    // metadata_refresh jobs queued or running
+    pub pending_refresh_jobs: i64,
+}
+
+pub async fn get_metadata_backlog(
+    client: &tokio_postgres::Client,
+) -> Result<MetadataBacklog, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM (
+                    SELECT DISTINCT b.contract_id, b.token_id
+                    FROM balances b
+                    JOIN contracts c ON b.contract_id = c.id
+                    JOIN chains ch ON c.chain_id = ch.id
+                    WHERE c.archived_at IS NULL AND NOT EXISTS (
+                        SELECT 1 FROM token_metadata m
+                        WHERE m.chain = LOWER(ch.name)
+                            AND m.contract_address = LOWER(c.address)
+                            AND m.token_id = b.token_id
+                    )
+                ) missing) AS tokens_without_metadata,
+                (SELECT COUNT(*) FROM jobs
+                    WHERE kind = 'metadata_refresh' AND status IN ('queued', 'running')
+                ) AS pending_refresh_jobs
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(MetadataBacklog {
+        tokens_without_metadata: row.get("tokens_without_metadata"),
+        pending_refresh_jobs: row.get("pending_refresh_jobs"),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackfillProgress {
+    pub chain: String,
+    pub contract_address: String,
+    pub from_block: i32,
+    pub to_block: i32,
+    pub chunks_done: i32,
+    pub chunks_total: i32,
+    // Unix timestamps (seconds)
+    pub started_at: i64,
+    pub updated_at: i64,
+    // None once the backfill is done
+    pub eta: Option<i64>,
+}
+
+// The latest backfill of every contract, unfinished ones first
+pub async fn get_backfill_status(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<BackfillProgress>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT chain, contract_address, from_block, to_block, chunks_done, chunks_total,
+                EXTRACT(EPOCH FROM started_at)::bigint AS started_at,
+                EXTRACT(EPOCH FROM updated_at)::bigint AS updated_at,
+                EXTRACT(EPOCH FROM eta)::bigint AS eta
+            FROM sync_progress
+            ORDER BY chunks_done >= chunks_total, chain, contract_address
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| BackfillProgress {
+            chain: row.get("chain"),
+            contract_address: row.get("contract_address"),
+            from_block: row.get("from_block"),
+            to_block: row.get("to_block"),
+            chunks_done: row.get("chunks_done"),
+            chunks_total: row.get("chunks_total"),
+            started_at: row.get("started_at"),
+            updated_at: row.get("updated_at"),
+            eta: row.get("eta"),
+        })
+        .collect())
+}
+
+#[derive(Debug)]
+pub struct ActivityRow {
+    pub chain_name: String,
+    pub contract_address: String,
+    pub contract_name: String,
+    pub from_address: String,
+    pub to_address: String,
+    pub ids: Vec<TokenId>,
+    pub values: Vec<Balance>,
+    pub block_number: i32,
+    pub log_index: i32,
+    pub transaction_hash: String,
+    pub block_timestamp: Option<i64>,
+    // The contract's own burn addresses, on top of the zero and dead addresses
+    pub burn_addresses: Vec<String>,
+    pub sink_addresses: Vec<String>,
+}
+
+/// Position of a transfer in the activity feed, which is sorted by timestamp, block and
+/// log index. Transfers without a timestamp sort last, as if at timestamp 0.
+/// Written `timestamp-block-log_index` in the `cursor` clients pass back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActivityCursor {
+    pub timestamp: i64,
+    pub block_number: i32,
+    pub log_index: i32,
+}
+
+impl ActivityCursor {
+    // Cursor of `?before=`, everything strictly older than the timestamp
+    pub fn before(timestamp: i64) -> Self {
+        ActivityCursor {
+            timestamp,
+            block_number: i32::MIN,
+            log_index: i32::MIN,
+        }
+    }
+}
+
+impl FromStr for ActivityCursor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '-');
+        let mut next = || parts.next().ok_or(());
+        Ok(ActivityCursor {
+            timestamp: next()?.parse().map_err(|_| ())?,
+            block_number: next()?.parse().map_err(|_| ())?,
+            log_index: next()?.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+impl std::fmt::Display for ActivityCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}",
+            self.timestamp, self.block_number, self.log_index
+        )
+    }
+}
+
+// Transfers involving any of the given addresses, newest first, strictly after `after`
+// in that order
+// `contracts` is a list of lowercased (chain, address), None reads every contract
+pub async fn get_activity_for_addresses(
+    client: &tokio_postgres::Client,
+    addresses: &[String],
+    contracts: Option<&[(String, String)]>,
+    after: Option<ActivityCursor>,
+    limit: i64,
+) -> Result<Vec<ActivityRow>, Box<dyn std::error::Error + Send>> {
+    let addresses_lowercase: Vec<String> = addresses.iter().map(|a| a.to_lowercase()).collect();
+    let (chain_names, contract_addresses): (Option<Vec<String>>, Option<Vec<String>>) =
+        match contracts {
+            Some(contracts) => {
+                let (chains, addresses) = contracts.iter().cloned().unzip();
+                (Some(chains), Some(addresses))
+            }
+            None => (None, None),
+        };
+    let rows = client
+        .query(
+            r#"
+            SELECT ch.name AS chain_name, c.address AS contract_address, c.name AS contract_name,
+                e.from_address, e.to_address, e.ids::text[] AS ids, e.values::text[] AS values, e.block_number,
+                e.log_index, e.transaction_hash,
+                EXTRACT(EPOCH FROM e.block_timestamp)::bigint AS block_timestamp,
+                c.burn_addresses::text[] AS burn_addresses,
+                c.sink_addresses::text[] AS sink_addresses
+            FROM events e
+            JOIN contracts c ON e.contract_id = c.id
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE (LOWER(e.from_address) = ANY($1) OR LOWER(e.to_address) = ANY($1))
+                AND ($2::bigint IS NULL
+                    OR (COALESCE(EXTRACT(EPOCH FROM e.block_timestamp)::bigint, 0), e.block_number, e.log_index)
+                        < ($2::bigint, $3::int4, $4::int4))
+                AND ($6::text[] IS NULL
+                    OR (LOWER(ch.name), LOWER(c.address)) IN (SELECT * FROM unnest($6::text[], $7::text[])))
+            ORDER BY e.block_timestamp DESC NULLS LAST, e.block_number DESC, e.log_index DESC
+            LIMIT $5
+            "#,
+            &[
+                &addresses_lowercase,
+                &after.map(|cursor| cursor.timestamp),
+                &after.map(|cursor| cursor.block_number),
+                &after.map(|cursor| cursor.log_index),
+                &limit,
+                &chain_names,
+                &contract_addresses,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ActivityRow {
+            chain_name: row.get("chain_name"),
+            contract_address: row.get("contract_address"),
+            contract_name: row.get("contract_name"),
+            from_address: row.get("from_address"),
+            to_address: row.get("to_address"),
+            ids: parse_numeric_array(row.get("ids")),
+            values: parse_numeric_array(row.get("values")),
+            block_number: row.get("block_number"),
+            log_index: row.get("log_index"),
+            transaction_hash: row.get("transaction_hash"),
+            block_timestamp: row.get("block_timestamp"),
+            burn_addresses: row.get("burn_addresses"),
+            sink_addresses: row.get("sink_addresses"),
+        })
+        .collect())
+}
+
+#[derive(Debug)]
+pub struct TransferCounts {
+    pub chain_name: String,
+    pub contract_address: String,
+    pub mints: i64,
+    pub burns: i64,
+    // Tokens sent to the contract's sink addresses, summed over the transfers
+    pub consumed: i64,
+}
+
+// Number of mint and burn transfers, and of consumed tokens, per contract for the given
+// addresses
+pub async fn get_transfer_counts_for_addresses(
+    client: &tokio_postgres::Client,
+    addresses: &[String],
+) -> Result<Vec<TransferCounts>, Box<dyn std::error::Error + Send>> {
+    let addresses_lowercase: Vec<String> = addresses.iter().map(|a| a.to_lowercase()).collect();
+    let burn_addresses = addresses::burn_addresses(&[]);
+    let rows = client
+        .query(
+            r#"
+            SELECT ch.name AS chain_name, c.address AS contract_address,
+                COUNT(*) FILTER (
+                    WHERE LOWER(e.from_address) = $2 AND LOWER(e.to_address) = ANY($1)
+                ) AS mints,
+                COUNT(*) FILTER (
+                    WHERE LOWER(e.from_address) = ANY($1)
+                        AND LOWER(e.to_address) = ANY($3::text[] || c.burn_addresses::text[])
+                ) AS burns,
+                COALESCE(SUM((SELECT SUM(v) FROM unnest(e.values) AS v)) FILTER (
+                    WHERE LOWER(e.from_address) = ANY($1)
+                        AND LOWER(e.to_address) = ANY(c.sink_addresses::text[])
+                ), 0)::bigint AS consumed
+            FROM events e
+            JOIN contracts c ON e.contract_id = c.id
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(e.from_address) = ANY($1) OR LOWER(e.to_address) = ANY($1)
+            GROUP BY ch.name, c.address
+            "#,
+            &[&addresses_lowercase, &ZERO_ADDRESS, &burn_addresses],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| TransferCounts {
+            chain_name: row.get("chain_name"),
+            contract_address: row.get("contract_address"),
+            mints: row.get("mints"),
+            burns: row.get("burns"),
+            consumed: row.get("consumed"),
+        })
+        .collect())
+}
+
+/// Tokens sent to the contract's sink addresses, by anyone
+pub async fn get_consumed_count(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<i64, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            r#"
+            SELECT COALESCE(SUM((SELECT SUM(v) FROM unnest(e.values) AS v)), 0)::bigint AS consumed
+            FROM events e
+            JOIN contracts c ON e.contract_id = c.id
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+                AND LOWER(e.to_address) = ANY(c.sink_addresses::text[])
+            "#,
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.get("consumed"))
+}
+
+#[derive(Debug)]
+pub struct TokenHoldHistory {
+    pub chain_name: String,
+    pub contract_address: String,
+    pub token_id: TokenId,
+    // Last time the token came in from someone else, moves between the addresses and in and
+    // out of staking contracts don't count. None when the block timestamp isn't known.
+    pub acquired_at: Option<i64>,
+    pub transfers_in: i64,
+    pub transfers_out: i64,
+    // Minted straight to one of the addresses
+    pub minted: bool,
+}
+
+// Per token the addresses ever held, when they got it and how often it changed hands
+pub async fn get_token_hold_history(
+    client: &tokio_postgres::Client,
+    addresses: &[String],
+    staking_addresses: &[String],
+) -> Result<Vec<TokenHoldHistory>, Box<dyn std::error::Error + Send>> {
+    let addresses_lowercase: Vec<String> = addresses.iter().map(|a| a.to_lowercase()).collect();
+    let staking_lowercase: Vec<String> =
+        staking_addresses.iter().map(|a| a.to_lowercase()).collect();
+    let rows = client
+        .query(
+            r#"
+            WITH transfers AS (
+                SELECT ch.name AS chain_name, c.address AS contract_address, t.token_id,
+                    e.block_timestamp,
+                    LOWER(e.from_address) = $2 AS is_mint,
+                    LOWER(e.to_address) = ANY($1)
+                        AND NOT COALESCE(LOWER(e.from_address) = ANY($1), false)
+                        AND NOT COALESCE(LOWER(e.from_address) = ANY($3), false) AS is_in,
+                    LOWER(e.from_address) = ANY($1)
+                        AND NOT COALESCE(LOWER(e.to_address) = ANY($1), false)
+                        AND NOT COALESCE(LOWER(e.to_address) = ANY($3), false) AS is_out
+                FROM events e
+                JOIN contracts c ON e.contract_id = c.id
+                JOIN chains ch ON c.chain_id = ch.id
+                CROSS JOIN LATERAL unnest(e.ids) AS t(token_id)
+                WHERE LOWER(e.from_address) = ANY($1) OR LOWER(e.to_address) = ANY($1)
+            )
+            SELECT chain_name, contract_address, token_id::text AS token_id,
+                EXTRACT(EPOCH FROM MAX(block_timestamp) FILTER (WHERE is_in))::bigint AS acquired_at,
+                COUNT(*) FILTER (WHERE is_in) AS transfers_in,
+                COUNT(*) FILTER (WHERE is_out) AS transfers_out,
+                COALESCE(BOOL_OR(is_in AND is_mint), false) AS minted
+            FROM transfers
+            GROUP BY chain_name, contract_address, token_id
+            "#,
+            &[
+                &addresses_lowercase,
+                &ZERO_ADDRESS,
+                &staking_lowercase,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let token_id: String = row.get("token_id");
+            Some(TokenHoldHistory {
+                chain_name: row.get("chain_name"),
+                contract_address: row.get("contract_address"),
+                token_id: token_id.parse().ok()?,
+                acquired_at: row.get("acquired_at"),
+                transfers_in: row.get("transfers_in"),
+                transfers_out: row.get("transfers_out"),
+                minted: row.get("minted"),
+            })
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApprovalRow {
+    pub owner: String,
+    pub chain: String,
+    pub contract_address: String,
+    pub contract_name: String,
+    pub operator: String,
+    pub block_number: i32,
+    pub transaction_hash: String,
+    pub approved_at: Option<i64>,
+}
+
+// Operators the owners approved for all their tokens of a contract and didn't revoke since,
+// from the latest ApprovalForAll event of every (owner, contract, operator)
+pub async fn get_active_approvals(
+    client: &tokio_postgres::Client,
+    owners: &[String],
+) -> Result<Vec<ApprovalRow>, Box<dyn std::error::Error + Send>> {
+    let owners: Vec<String> = owners.iter().map(|owner| owner.to_lowercase()).collect();
+    let rows = client
+        .query(
+            r#"
+            SELECT a.owner, ch.name AS chain, c.address AS contract_address,
+                c.name AS contract_name, a.operator, a.block_number, a.transaction_hash,
+                a.approved_at
+            FROM (
+                SELECT DISTINCT ON (owner, contract_id, operator) owner, contract_id, operator,
+                    approved, block_number, transaction_hash,
+                    EXTRACT(EPOCH FROM block_timestamp)::bigint AS approved_at
+                FROM approvals
+                WHERE owner = ANY($1)
+                ORDER BY owner, contract_id, operator, block_number DESC, log_index DESC
+            ) a
+            JOIN contracts c ON a.contract_id = c.id
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE a.approved
+            ORDER BY a.block_number DESC
+            "#,
+            &[&owners],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ApprovalRow {
+            owner: row.get("owner"),
+            chain: row.get("chain"),
+            contract_address: row.get("contract_address"),
+            contract_name: row.get("contract_name"),
+            operator: row.get("operator"),
+            block_number: row.get("block_number"),
+            transaction_hash: row.get("transaction_hash"),
+            approved_at: row.get("approved_at"),
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookRow {
+    pub id: i32,
+    pub url: String,
+    // None for global hooks
+    pub username: Option<String>,
+    pub active: bool,
+    pub created_at: Option<i64>,
+}
+
+fn row_to_webhook(row: Row) -> WebhookRow {
+    WebhookRow {
+        id: row.get("id"),
+        url: row.get("url"),
+        username: row.get("username"),
+        active: row.get("active"),
+        created_at: row.get("created_at"),
+    }
+}
+
+pub async fn create_webhook(
+    client: &tokio_postgres::Client,
+    url: &str,
+    username: Option<&str>,
+    secret: &str,
+) -> Result<WebhookRow, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            r#"
+            INSERT INTO webhooks (url, username, secret) VALUES ($1, $2, $3)
+            RETURNING id, url, username, active, EXTRACT(EPOCH FROM created_at)::bigint AS created_at
+            "#,
+            &[&url, &username, &secret],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row_to_webhook(row))
+}
+
+pub async fn get_webhooks(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<WebhookRow>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT id, url, username, active, EXTRACT(EPOCH FROM created_at)::bigint AS created_at
+            FROM webhooks
+            ORDER BY id
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows.into_iter().map(row_to_webhook).collect())
+}
+
+// Returns false if no webhook has this id
+pub async fn delete_webhook(
+    client: &tokio_postgres::Client,
+    id: i32,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let deleted = client
+        .execute("DELETE FROM webhooks WHERE id = $1", &[&id])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(deleted > 0)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExclusionRow {
+    pub id: i32,
+    pub project: String,
+    // Username or address
+    pub value: String,
+    pub reason: Option<String>,
+    pub created_at: Option<i64>,
+}
+
+fn row_to_exclusion(row: Row) -> ExclusionRow {
+    ExclusionRow {
+        id: row.get("id"),
+        project: row.get("project"),
+        value: row.get("value"),
+        reason: row.get("reason"),
+        created_at: row.get("created_at"),
+    }
+}
+
+pub async fn get_exclusions(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<ExclusionRow>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT id, project, value, reason, EXTRACT(EPOCH FROM created_at)::bigint AS created_at
+            FROM exclusions
+            ORDER BY id
+            "#,
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows.into_iter().map(row_to_exclusion).collect())
+}
+
+// Returns None if the value is already excluded from the project
+pub async fn add_exclusion(
+    client: &tokio_postgres::Client,
+    project: &str,
+    value: &str,
+    reason: Option<&str>,
+) -> Result<Option<ExclusionRow>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            INSERT INTO exclusions (project, value, reason) VALUES ($1, $2, $3)
+            ON CONFLICT (project, LOWER(value)) DO NOTHING
+            RETURNING id, project, value, reason, EXTRACT(EPOCH FROM created_at)::bigint AS created_at
+            "#,
+            &[&project, &value, &reason],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(row_to_exclusion))
+}
+
+// Returns false if no exclusion has this id
+pub async fn delete_exclusion(
+    client: &tokio_postgres::Client,
+    id: i32,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let deleted = client
+        .execute("DELETE FROM exclusions WHERE id = $1", &[&id])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(deleted > 0)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TeamRow {
+    pub id: i32,
+    pub name: String,
+    pub self_service: bool,
+    pub created_by: Option<String>,
+    pub created_at: i64,
+    // Usernames, in the order they joined
+    pub members: Vec<String>,
+}
+
+const TEAM_COLUMNS: &str = r#"
+    SELECT t.id, t.name, t.self_service, t.created_by,
+        EXTRACT(EPOCH FROM t.created_at)::bigint AS created_at,
+        ARRAY(
+            SELECT m.username FROM team_members m WHERE m.team_id = t.id
+            ORDER BY m.joined_at, m.username
+        ) AS members
+    FROM teams t
+"#;
+
+fn row_to_team(row: Row) -> TeamRow {
+    TeamRow {
+        id: row.get("id"),
+        name: row.get("name"),
+        self_service: row.get("self_service"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+        members: row.get("members"),
+    }
+}
+
+pub async fn get_teams(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<TeamRow>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(&format!("{} ORDER BY t.name", TEAM_COLUMNS), &[])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows.into_iter().map(row_to_team).collect())
+}
+
+pub async fn get_team(
+    client: &tokio_postgres::Client,
+    id: i32,
+) -> Result<Option<TeamRow>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(&format!("{} WHERE t.id = $1", TEAM_COLUMNS), &[&id])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(row_to_team))
+}
+
+/// The team the user is a member of, if any
+pub async fn get_user_team(
+    client: &tokio_postgres::Client,
+    username: &str,
+) -> Result<Option<TeamRow>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            &format!(
+                "{} WHERE t.id = (SELECT team_id FROM team_members WHERE username = $1)",
+                TEAM_COLUMNS
+            ),
+            &[&username],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(row_to_team))
+}
+
+// Returns the new team's id, None if the name is taken
+pub async fn create_team(
+    client: &tokio_postgres::Client,
+    name: &str,
+    self_service: bool,
+    created_by: Option<&str>,
+) -> Result<Option<i32>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            INSERT INTO teams (name, self_service, created_by) VALUES ($1, $2, $3)
+            ON CONFLICT (LOWER(name)) DO NOTHING
+            RETURNING id
+            "#,
+            &[&name, &self_service, &created_by],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(|row| row.get("id")))
+}
+
+// Returns false if no team has this id
+pub async fn delete_team(
+    client: &tokio_postgres::Client,
+    id: i32,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let deleted = client
+        .execute("DELETE FROM teams WHERE id = $1", &[&id])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(deleted > 0)
+}
+
+// Returns false if the user is already in a team
+pub async fn add_team_member(
+    client: &tokio_postgres::Client,
+    team_id: i32,
+    username: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let inserted = client
+        .execute(
+            r#"
+            INSERT INTO team_members (username, team_id) VALUES ($1, $2)
+            ON CONFLICT (username) DO NOTHING
+            "#,
+            &[&username, &team_id],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(inserted > 0)
+}
+
+/// Removes the user from the team, false if they weren't in it. A self-service team is
+/// deleted along with its last member.
+pub async fn remove_team_member(
+    client: &tokio_postgres::Client,
+    team_id: i32,
+    username: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            r#"
+            WITH removed AS (
+                DELETE FROM team_members WHERE team_id = $1 AND username = $2
+                RETURNING team_id
+            ),
+            emptied AS (
+                DELETE FROM teams t USING removed r
+                WHERE t.id = r.team_id AND t.self_service AND NOT EXISTS (
+                    SELECT 1 FROM team_members m WHERE m.team_id = t.id AND m.username <> $2
+                )
+            )
+            SELECT COUNT(*) AS removed FROM removed
+            "#,
+            &[&team_id, &username],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.get::<_, i64>("removed") > 0)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LabelRow {
+    // Lowercased
+    pub address: String,
+    pub label: String,
+    // treasury, bank, vesting, escrow...
+    pub kind: String,
+    // Protocol owned addresses are left out of holder counts and leaderboards
+    pub excluded: bool,
+}
+
+fn row_to_label(row: Row) -> LabelRow {
+    LabelRow {
+        address: row.get("address"),
+        label: row.get("label"),
+        kind: row.get("kind"),
+        excluded: row.get("excluded"),
+    }
+}
+
+pub async fn get_labels(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<LabelRow>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            "SELECT address, label, kind, excluded FROM labels ORDER BY address",
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows.into_iter().map(row_to_label).collect())
+}
+
+pub async fn set_label(
+    client: &tokio_postgres::Client,
+    label: &LabelRow,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            r#"
+            INSERT INTO labels (address, label, kind, excluded, updated_at)
+            VALUES ($1, $2, $3, $4, now())
+            ON CONFLICT (address) DO UPDATE SET label = EXCLUDED.label, kind = EXCLUDED.kind,
+                excluded = EXCLUDED.excluded, updated_at = now()
+            "#,
+            &[
+                &label.address.to_lowercase(),
+                &label.label,
+                &label.kind,
+                &label.excluded,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+// Returns false if the address has no label
+pub async fn delete_label(
+    client: &tokio_postgres::Client,
+    address: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let deleted = client
+        .execute(
+            "DELETE FROM labels WHERE address = $1",
+            &[&address.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(deleted > 0)
+}
+
+pub async fn get_username_for_discord_id(
+    client: &tokio_postgres::Client,
+    discord_id: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            "SELECT username FROM user_settings WHERE discord_id = $1",
+            &[&discord_id],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(|r| r.get("username")))
+}
+
+// Links a discord account to a username, None unlinks it
+pub async fn set_discord_id(
+    client: &tokio_postgres::Client,
+    username: &str,
+    discord_id: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            r#"
+            INSERT INTO user_settings (username, discord_id, updated_at) VALUES ($1, $2, now())
+            ON CONFLICT (username) DO UPDATE SET discord_id = EXCLUDED.discord_id, updated_at = now()
+            "#,
+            &[&username, &discord_id],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+/// The token a user picked as their avatar
+#[derive(Debug, Clone, Serialize)]
+pub struct Avatar {
+    pub chain: String,
+    pub contract_address: String,
+    pub token_id: TokenId,
+}
+
+fn row_to_avatar(row: &Row) -> Option<Avatar> {
+    let chain: Option<String> = row.get("avatar_chain");
+    let contract_address: Option<String> = row.get("avatar_contract");
+    let token_id: Option<String> = row.get("avatar_token_id");
+    Some(Avatar {
+        chain: chain?,
+        contract_address: contract_address?,
+        token_id: TokenId::from_str(&token_id?).ok()?,
+    })
+}
+
+// None clears the avatar
+pub async fn set_avatar(
+    client: &tokio_postgres::Client,
+    username: &str,
+    avatar: Option<&Avatar>,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    let chain = avatar.map(|a| a.chain.as_str());
+    let contract_address = avatar.map(|a| a.contract_address.as_str());
+    let token_id = avatar.map(|a| a.token_id.to_string());
+    client
+        .execute(
+            r#"
+            INSERT INTO user_settings (username, avatar_chain, avatar_contract, avatar_token_id, updated_at)
+            VALUES ($1, $2, $3, $4::text::numeric, now())
+            ON CONFLICT (username) DO UPDATE
+            SET avatar_chain = EXCLUDED.avatar_chain,
+                avatar_contract = EXCLUDED.avatar_contract,
+                avatar_token_id = EXCLUDED.avatar_token_id,
+                updated_at = now()
+            "#,
+            &[&username, &chain, &contract_address, &token_id],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+pub async fn get_avatar(
+    client: &tokio_postgres::Client,
+    username: &str,
+) -> Result<Option<Avatar>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT avatar_chain, avatar_contract, avatar_token_id::text AS avatar_token_id
+            FROM user_settings WHERE username = $1
+            "#,
+            &[&username],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.as_ref().and_then(row_to_avatar))
+}
+
+// Username -> avatar, for the users of `usernames` who picked one
+pub async fn get_avatars_of(
+    client: &tokio_postgres::Client,
+    usernames: &[String],
+) -> Result<HashMap<String, Avatar>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT username, avatar_chain, avatar_contract, avatar_token_id::text AS avatar_token_id
+            FROM user_settings WHERE username = ANY($1) AND avatar_token_id IS NOT NULL
+            "#,
+            &[&usernames],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| Some((row.get("username"), row_to_avatar(row)?)))
+        .collect())
+}
+
+/// Current holders of each (chain, contract, token), depositors of staked tokens included.
+/// Keyed by lowercased chain and contract, holders are lowercased addresses.
+pub async fn get_token_holders(
+    client: &tokio_postgres::Client,
+    tokens: &[(String, String, TokenId)],
+) -> Result<HashMap<(String, String, TokenId), HashSet<String>>, Box<dyn std::error::Error + Send>>
+{
+    let chains: Vec<String> = tokens.iter().map(|(chain, _, _)| chain.clone()).collect();
+    let contracts: Vec<String> = tokens.iter().map(|(_, c, _)| c.clone()).collect();
+    let token_ids: Vec<String> = tokens.iter().map(|(_, _, id)| id.to_string()).collect();
+    let rows = client
+        .query(
+            r#"
+            WITH wanted AS (
+                SELECT c.id AS contract_id, LOWER(ch.name) AS chain_name,
+                    LOWER(c.address) AS contract_address, t.token_id::numeric AS token_id
+                FROM unnest($1::text[], $2::text[], $3::text[]) AS t(chain_name, contract_address, token_id)
+                JOIN chains ch ON LOWER(ch.name) = LOWER(t.chain_name)
+                JOIN contracts c ON c.chain_id = ch.id AND LOWER(c.address) = LOWER(t.contract_address)
+            )
+            SELECT w.chain_name, w.contract_address, w.token_id::text AS token_id, b.address
+            FROM wanted w
+            JOIN balances b ON b.contract_id = w.contract_id AND b.token_id = w.token_id
+            UNION
+            SELECT w.chain_name, w.contract_address, w.token_id::text AS token_id, sb.address
+            FROM wanted w
+            JOIN staked_balances sb ON sb.contract_id = w.contract_id AND sb.token_id = w.token_id
+            WHERE sb.balance > 0
+            "#,
+            &[&chains, &contracts, &token_ids],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    let mut holders: HashMap<(String, String, TokenId), HashSet<String>> = HashMap::new();
+    for row in rows {
+        if let Ok(token_id) = row.get::<_, String>("token_id").parse() {
+            holders
+                .entry((row.get("chain_name"), row.get("contract_address"), token_id))
+                .or_default()
+                .insert(row.get("address"));
+        }
+    }
+    Ok(holders)
+}
+
+/// Records a sign-in nonce for `address`, valid for `ttl_seconds`. Expired sessions and
+/// nonces nobody signed in with are dropped on the way.
+pub async fn insert_auth_nonce(
+    client: &tokio_postgres::Client,
+    nonce: &str,
+    address: &str,
+    ttl_seconds: i64,
+) -> Result<i64, Box<dyn std::error::Error + Send>> {
+    client
+        .execute("DELETE FROM auth_sessions WHERE expires_at < now()", &[])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    let row = client
+        .query_one(
+            r#"
+            INSERT INTO auth_sessions (id, address, expires_at)
+            VALUES ($1, $2, now() + make_interval(secs => $3))
+            RETURNING EXTRACT(EPOCH FROM expires_at)::bigint AS expires_at
+            "#,
+            &[&nonce, &address.to_lowercase(), &(ttl_seconds as f64)],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.get("expires_at"))
+}
+
+/// Turns an unused, unexpired nonce of `address` into a session lasting `ttl_seconds`,
+/// returns its expiry. None when the nonce is unknown, was already used or expired.
+pub async fn start_auth_session(
+    client: &tokio_postgres::Client,
+    nonce: &str,
+    address: &str,
+    ttl_seconds: i64,
+) -> Result<Option<i64>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            UPDATE auth_sessions
+            SET signed_in_at = now(), expires_at = now() + make_interval(secs => $3)
+            WHERE id = $1 AND address = $2 AND signed_in_at IS NULL AND revoked_at IS NULL
+                AND expires_at > now()
+            RETURNING EXTRACT(EPOCH FROM expires_at)::bigint AS expires_at
+            "#,
+            &[&nonce, &address.to_lowercase(), &(ttl_seconds as f64)],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.map(|row| row.get("expires_at")))
+}
+
+pub async fn is_auth_session_active(
+    client: &tokio_postgres::Client,
+    id: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM auth_sessions
+                WHERE id = $1 AND signed_in_at IS NOT NULL AND revoked_at IS NULL
+                    AND expires_at > now()
+            )
+            "#,
+            &[&id],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.get(0))
+}
+
+/// Revokes the session `id` of `address`, or every session of it when None. Returns how
+/// many were revoked.
+pub async fn revoke_auth_sessions(
+    client: &tokio_postgres::Client,
+    address: &str,
+    id: Option<&str>,
+) -> Result<u64, Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            r#"
+            UPDATE auth_sessions SET revoked_at = now()
+            WHERE address = $1 AND ($2::varchar IS NULL OR id = $2)
+                AND signed_in_at IS NOT NULL AND revoked_at IS NULL
+            "#,
+            &[&address.to_lowercase(), &id],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)
+}
+
+/// What a user keeps off their public profile, it all still counts for scoring
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrivacySettings {
+    // Lowercased
+    #[serde(default)]
+    pub hidden_addresses: Vec<String>,
+    #[serde(default)]
+    pub hide_collection: bool,
+}
+
+pub async fn get_privacy_settings(
+    client: &tokio_postgres::Client,
+    username: &str,
+) -> Result<PrivacySettings, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            "SELECT hidden_addresses, hide_collection FROM user_settings WHERE username = $1",
+            &[&username],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row
+        .map(|r| PrivacySettings {
+            hidden_addresses: r.get("hidden_addresses"),
+            hide_collection: r.get("hide_collection"),
+        })
+        .unwrap_or_default())
+}
+
+/// Whether a user hid `wallet_address` from their public profile
+pub async fn is_hidden_address(
+    client: &tokio_postgres::Client,
+    wallet_address: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM user_settings WHERE $1 = ANY(hidden_addresses))",
+            &[&wallet_address.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.get(0))
+}
+
+/// The ones of `wallet_addresses` a user hid from their profile, lowercased
+pub async fn get_hidden_addresses(
+    client: &tokio_postgres::Client,
+    wallet_addresses: &[String],
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send>> {
+    let wallet_addresses: Vec<String> = wallet_addresses
+        .iter()
+        .map(|address| address.to_lowercase())
+        .collect();
+    let rows = client
+        .query(
+            "SELECT DISTINCT hidden FROM user_settings, unnest(hidden_addresses) AS hidden \
+            WHERE hidden = ANY($1)",
+            &[&wallet_addresses],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(rows.iter().map(|row| row.get("hidden")).collect())
+}
+
+pub async fn set_privacy_settings(
+    client: &tokio_postgres::Client,
+    username: &str,
+    settings: &PrivacySettings,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            r#"
+            INSERT INTO user_settings (username, hidden_addresses, hide_collection, updated_at)
+            VALUES ($1, $2, $3, now())
+            ON CONFLICT (username) DO UPDATE
+            SET hidden_addresses = EXCLUDED.hidden_addresses,
+                hide_collection = EXCLUDED.hide_collection,
+                updated_at = now()
+            "#,
+            &[
+                &username,
+                &settings.hidden_addresses,
+                &settings.hide_collection,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+/// Where a user is notified of the transfers involving their addresses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationChannel {
+    #[default]
+    None,
+    Webhook,
+    Email,
+}
+
+impl NotificationChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationChannel::None => "none",
+            NotificationChannel::Webhook => "webhook",
+            NotificationChannel::Email => "email",
+        }
+    }
+}
+
+/// Which transfers a user is notified of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvents {
+    Mints,
+    Sales,
+    #[default]
+    All,
+}
+
+impl NotificationEvents {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationEvents::Mints => "mints",
+            NotificationEvents::Sales => "sales",
+            NotificationEvents::All => "all",
+        }
+    }
+}
+
+/// Notification preferences of a user, delivered by the indexer with the webhooks
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    #[serde(default)]
+    pub channel: NotificationChannel,
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub events: NotificationEvents,
+    // HMAC key the webhook notifications are signed with, generated by the API
+    #[serde(default, skip_deserializing)]
+    pub webhook_secret: Option<String>,
+    // Emails are only sent once the address owner entered the token mailed to them
+    #[serde(default, skip_deserializing)]
+    pub email_confirmed: bool,
+    #[serde(skip)]
+    pub confirmation_token: Option<String>,
+}
+
+pub async fn get_notification_preferences(
+    client: &tokio_postgres::Client,
+    username: &str,
+) -> Result<NotificationPreferences, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT notify_channel, notify_target, notify_secret, notify_events,
+                notify_confirm_token, notify_confirmed_at IS NOT NULL AS confirmed
+            FROM user_settings WHERE username = $1
+            "#,
+            &[&username],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row
+        .map(|r| {
+            let target: Option<String> = r.get("notify_target");
+            let channel = match r.get::<_, String>("notify_channel").as_str() {
+                "webhook" => NotificationChannel::Webhook,
+                "email" => NotificationChannel::Email,
+                _ => NotificationChannel::None,
+            };
+            let events = match r.get::<_, String>("notify_events").as_str() {
+                "mints" => NotificationEvents::Mints,
+                "sales" => NotificationEvents::Sales,
+                _ => NotificationEvents::All,
+            };
+            NotificationPreferences {
+                channel,
+                webhook_url: target
+                    .clone()
+                    .filter(|_| channel == NotificationChannel::Webhook),
+                email: target.filter(|_| channel == NotificationChannel::Email),
+                events,
+                webhook_secret: r.get("notify_secret"),
+                email_confirmed: r.get("confirmed"),
+                confirmation_token: r.get("notify_confirm_token"),
+            }
+        })
+        .unwrap_or_default())
+}
+
+// Only transfers from after the preferences are saved get notified
+pub async fn set_notification_preferences(
+    client: &tokio_postgres::Client,
+    username: &str,
+    preferences: &NotificationPreferences,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    let target = match preferences.channel {
+        NotificationChannel::Webhook => preferences.webhook_url.as_deref(),
+        NotificationChannel::Email => preferences.email.as_deref(),
+        NotificationChannel::None => None,
+    };
+    client
+        .execute(
+            r#"
+            INSERT INTO user_settings
+                (username, notify_channel, notify_target, notify_secret, notify_events,
+                    notify_confirm_token, notify_confirmed_at, notify_since, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, CASE WHEN $7 THEN now() END, now(), now())
+            ON CONFLICT (username) DO UPDATE
+            SET notify_channel = EXCLUDED.notify_channel,
+                notify_target = EXCLUDED.notify_target,
+                notify_secret = EXCLUDED.notify_secret,
+                notify_events = EXCLUDED.notify_events,
+                notify_confirm_token = EXCLUDED.notify_confirm_token,
+                notify_confirmed_at = CASE WHEN $7
+                    THEN COALESCE(user_settings.notify_confirmed_at, now()) END,
+                notify_since = now(),
+                updated_at = now()
+            "#,
+            &[
+                &username,
+                &preferences.channel.as_str(),
+                &target,
+                &preferences.webhook_secret,
+                &preferences.events.as_str(),
+                &preferences.confirmation_token,
+                &preferences.email_confirmed,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+/// Confirms the pending notification email of the user, false when the token isn't theirs
+pub async fn confirm_notification_email(
+    client: &tokio_postgres::Client,
+    username: &str,
+    token: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let updated = client
+        .execute(
+            r#"
+            UPDATE user_settings
+            SET notify_confirmed_at = now(), notify_confirm_token = NULL, updated_at = now()
+            WHERE username = $1 AND notify_channel = 'email' AND notify_confirm_token = $2
+            "#,
+            &[&username, &token],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(updated == 1)
+}
+
+// Moves everything stored under a username to its new name
+pub async fn rename_user_records(
+    transaction: &tokio_postgres::Transaction<'_>,
+    from: &str,
+    to: &str,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    for statement in [
+        // Warmed documents of both names are stale, the next warm-up rebuilds them
+        "DELETE FROM user_profiles_cache WHERE username IN ($1, $2)",
+        "UPDATE score_history SET name = $2 WHERE name = $1",
+        "UPDATE lifetime_scores SET name = $2 WHERE name = $1",
+        "UPDATE season_results SET name = $2 WHERE name = $1",
+        "UPDATE team_members SET username = $2 WHERE username = $1",
+        "UPDATE teams SET created_by = $2 WHERE created_by = $1",
+        "UPDATE user_settings SET username = $2, updated_at = now() WHERE username = $1",
+        "UPDATE webhooks SET username = $2 WHERE username = $1",
+        "UPDATE notification_deliveries SET username = $2 WHERE username = $1",
+        "UPDATE exclusions SET value = $2 WHERE LOWER(value) = LOWER($1)",
+    ] {
+        transaction
+            .execute(statement, &[&from, &to])
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    }
+
+    Ok(())
+}
+
+// Folds everything stored under `from` into `into`. Snapshots both users appear in are
+// summed, as the merged user holds the tokens of both, and keep the better rank. Lifetime
+// points and season results are summed too.
+pub async fn merge_user_records(
+    transaction: &tokio_postgres::Transaction<'_>,
+    from: &str,
+    into: &str,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    for statement in [
+        // The merged user keeps its own discord account, avatar and notification channel, or
+        // takes over the other one's. Wallets hidden by either stay hidden.
+        r#"
+        WITH moved AS (
+            DELETE FROM user_settings WHERE username = $1
+            RETURNING discord_id, avatar_chain, avatar_contract, avatar_token_id,
+                hidden_addresses, hide_collection, notify_channel, notify_target, notify_secret,
+                notify_events, notify_since
+        )
+        INSERT INTO user_settings
+            (username, discord_id, avatar_chain, avatar_contract, avatar_token_id,
+                hidden_addresses, hide_collection, notify_channel, notify_target, notify_secret,
+                notify_events, notify_since, updated_at)
+        SELECT $2, discord_id, avatar_chain, avatar_contract, avatar_token_id,
+            hidden_addresses, hide_collection, notify_channel, notify_target, notify_secret,
+            notify_events, notify_since, now()
+        FROM moved
+        ON CONFLICT (username) DO UPDATE
+        SET discord_id = COALESCE(user_settings.discord_id, EXCLUDED.discord_id),
+            hidden_addresses = ARRAY(
+                SELECT DISTINCT unnest(user_settings.hidden_addresses || EXCLUDED.hidden_addresses)
+            ),
+            hide_collection = user_settings.hide_collection OR EXCLUDED.hide_collection,
+            avatar_chain = CASE WHEN user_settings.avatar_token_id IS NULL
+                THEN EXCLUDED.avatar_chain ELSE user_settings.avatar_chain END,
+            avatar_contract = CASE WHEN user_settings.avatar_token_id IS NULL
+                THEN EXCLUDED.avatar_contract ELSE user_settings.avatar_contract END,
+            avatar_token_id = COALESCE(user_settings.avatar_token_id, EXCLUDED.avatar_token_id),
+            notify_channel = CASE WHEN user_settings.notify_channel = 'none'
+                THEN EXCLUDED.notify_channel ELSE user_settings.notify_channel END,
+            notify_target = CASE WHEN user_settings.notify_channel = 'none'
+                THEN EXCLUDED.notify_target ELSE user_settings.notify_target END,
+            notify_secret = CASE WHEN user_settings.notify_channel = 'none'
+                THEN EXCLUDED.notify_secret ELSE user_settings.notify_secret END,
+            notify_events = CASE WHEN user_settings.notify_channel = 'none'
+                THEN EXCLUDED.notify_events ELSE user_settings.notify_events END,
+            notify_since = CASE WHEN user_settings.notify_channel = 'none'
+                THEN EXCLUDED.notify_since ELSE user_settings.notify_since END,
+            updated_at = now()
+        "#,
+        // Warmed documents of both names are stale, the next warm-up rebuilds them
+        "DELETE FROM user_profiles_cache WHERE username IN ($1, $2)",
+        r#"
+        UPDATE score_history t SET points = t.points + s.points, rank = LEAST(t.rank, s.rank)
+        FROM score_history s
+        WHERE t.name = $2 AND s.name = $1 AND s.recorded_at = t.recorded_at
+        "#,
+        r#"
+        DELETE FROM score_history s USING score_history t
+        WHERE s.name = $1 AND t.name = $2 AND s.recorded_at = t.recorded_at
+        "#,
+        "UPDATE score_history SET name = $2 WHERE name = $1",
+        r#"
+        WITH moved AS (DELETE FROM lifetime_scores WHERE name = $1 RETURNING points)
+        INSERT INTO lifetime_scores (name, points, updated_at)
+        SELECT $2, points, now() FROM moved
+        ON CONFLICT (name) DO UPDATE
+        SET points = lifetime_scores.points + EXCLUDED.points, updated_at = now()
+        "#,
+        r#"
+        UPDATE season_results t SET points = t.points + s.points, rank = LEAST(t.rank, s.rank)
+        FROM season_results s
+        WHERE t.name = $2 AND s.name = $1 AND s.season = t.season
+        "#,
+        r#"
+        DELETE FROM season_results s USING season_results t
+        WHERE s.name = $1 AND t.name = $2 AND s.season = t.season
+        "#,
+        "UPDATE season_results SET name = $2 WHERE name = $1",
+        // The merged user stays in its own team, if in one
+        r#"
+        DELETE FROM team_members s USING team_members t
+        WHERE s.username = $1 AND t.username = $2
+        "#,
+        "UPDATE team_members SET username = $2 WHERE username = $1",
+        "UPDATE teams SET created_by = $2 WHERE created_by = $1",
+        "UPDATE webhooks SET username = $2 WHERE username = $1",
+        r#"
+        DELETE FROM notification_deliveries s USING notification_deliveries t
+        WHERE s.username = $1 AND t.username = $2
+            AND s.transaction_hash = t.transaction_hash AND s.log_index = t.log_index
+        "#,
+        "UPDATE notification_deliveries SET username = $2 WHERE username = $1",
+        r#"
+        DELETE FROM exclusions s USING exclusions t
+        WHERE LOWER(s.value) = LOWER($1) AND LOWER(t.value) = LOWER($2) AND s.project = t.project
+        "#,
+        "UPDATE exclusions SET value = $2 WHERE LOWER(value) = LOWER($1)",
+    ] {
+        transaction
+            .execute(statement, &[&from, &into])
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    }
+
+    Ok(())
+}
+
+// Lowest active listing price per token, in the chain's native currency
+pub async fn get_token_floor_prices(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<HashMap<TokenId, f64>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT l.token_id::text AS token_id, MIN(l.price) AS floor_price
+            FROM listings l
+            JOIN contracts c ON l.contract_id = c.id
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+                AND (l.valid_until IS NULL OR l.valid_until > now())
+            GROUP BY l.token_id
+            "#,
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let token_id: String = row.get("token_id");
+            Some((token_id.parse().ok()?, row.get("floor_price")))
+        })
+        .collect())
+}
+
+#[derive(Debug, Default)]
+pub struct ContractMarket {
+    pub name: String,
+    pub r#type: String,
+    // Lowest active listing price per token, in the chain's native currency
+    pub floor_prices: HashMap<TokenId, f64>,
+}
+
+// Name, type and floor prices of several contracts in two queries, keyed by lowercased
+// (chain name, contract address). Contracts that aren't indexed are left out.
+pub async fn get_contracts_market(
+    client: &tokio_postgres::Client,
+    contracts: &[(String, String)],
+) -> Result<HashMap<(String, String), ContractMarket>, Box<dyn std::error::Error + Send>> {
+    let (chain_names, addresses): (Vec<String>, Vec<String>) = contracts
+        .iter()
+        .map(|(chain, address)| (chain.to_lowercase(), address.to_lowercase()))
+        .unzip();
+    let rows = client
+        .query(
+            r#"
+            SELECT c.id, LOWER(ch.name) AS chain_name, LOWER(c.address) AS address, c.name, c.type
+            FROM contracts c
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE (LOWER(ch.name), LOWER(c.address)) IN (
+                SELECT * FROM unnest($1::text[], $2::text[])
+            )
+            "#,
+            &[&chain_names, &addresses],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    let mut keys_by_id: HashMap<i32, (String, String)> = HashMap::new();
+    let mut markets = HashMap::new();
+    for row in rows {
+        let key: (String, String) = (row.get("chain_name"), row.get("address"));
+        keys_by_id.insert(row.get("id"), key.clone());
+        markets.insert(
+            key,
+            ContractMarket {
+                name: row.get("name"),
+                r#type: row.get("type"),
+                floor_prices: HashMap::new(),
+            },
+        );
+    }
+
+    let contract_ids: Vec<i32> = keys_by_id.keys().copied().collect();
+    let rows = client
+        .query(
+            r#"
+            SELECT contract_id, token_id::text AS token_id, MIN(price) AS floor_price
+            FROM listings
+            WHERE contract_id = ANY($1) AND (valid_until IS NULL OR valid_until > now())
+            GROUP BY contract_id, token_id
+            "#,
+            &[&contract_ids],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    for row in rows {
+        let token_id: String = row.get("token_id");
+        let (Ok(token_id), Some(market)) = (
+            token_id.parse(),
+            keys_by_id
+                .get(&row.get::<_, i32>("contract_id"))
+                .and_then(|key| markets.get_mut(key)),
+        ) else {
+            continue;
+        };
+        market.floor_prices.insert(token_id, row.get("floor_price"));
+    }
+
+    Ok(markets)
+}
+
+// Addresses holding a positive balance of any token of the contract
+// Holders other than the burn addresses and `excluded_addresses` (lowercased)
+pub async fn get_holder_count(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    excluded_addresses: &[String],
+) -> Result<i64, Box<dyn std::error::Error + Send>> {
+    let burn_addresses = get_contract_burn_addresses(client, chain_name, contract_address).await?;
+    let row = client
+        .query_one(
+            r#"
+            WITH transfers AS (
+                SELECT e.from_address, e.to_address, t.id, t.value
+                FROM events e
+                JOIN contracts c ON e.contract_id = c.id
+                JOIN chains ch ON c.chain_id = ch.id
+                CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value)
+                WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+            ),
+            balances AS (
+                SELECT LOWER(to_address) AS address, id, value FROM transfers
+                UNION ALL
+                SELECT LOWER(from_address) AS address, id, -value FROM transfers
+            )
+            SELECT COUNT(DISTINCT address) AS holders
+            FROM (
+                SELECT address, id FROM balances
+                WHERE address <> ALL($3) AND address <> ALL($4)
+                GROUP BY address, id
+                HAVING SUM(value) > 0
+            ) held
+            "#,
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &burn_addresses,
+                &excluded_addresses,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.get("holders"))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityPeriod {
+    // Unix timestamp, a multiple of the granularity
+    pub period_start: i64,
+    pub mints: i64,
+    pub transfers: i64,
+    pub burns: i64,
+    // Distinct addresses sending or receiving, the zero and burn addresses aside
+    pub active_wallets: i64,
+}
+
+// Transfers of a contract since `since`, counted per `granularity` seconds long period.
+// Periods without transfers are left out, events without a block timestamp aren't counted.
+pub async fn get_collection_activity(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    since: i64,
+    granularity: i64,
+) -> Result<Vec<ActivityPeriod>, Box<dyn std::error::Error + Send>> {
+    let burn_addresses = get_contract_burn_addresses(client, chain_name, contract_address).await?;
+    let rows = client
+        .query(
+            r#"
+            WITH window_events AS (
+                SELECT e.from_address, e.to_address,
+                    (FLOOR(EXTRACT(EPOCH FROM e.block_timestamp) / $3::bigint) * $3::bigint)::bigint
+                        AS period_start
+                FROM events e
+                JOIN contracts c ON e.contract_id = c.id
+                JOIN chains ch ON c.chain_id = ch.id
+                WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+                    AND e.block_timestamp >= to_timestamp($6::bigint)
+            ),
+            counts AS (
+                SELECT period_start,
+                    COUNT(*) FILTER (WHERE LOWER(from_address) = $4) AS mints,
+                    COUNT(*) FILTER (
+                        WHERE LOWER(from_address) <> $4 AND LOWER(to_address) = ANY($5)
+                    ) AS burns,
+                    COUNT(*) FILTER (
+                        WHERE LOWER(from_address) <> $4 AND LOWER(to_address) <> ALL($5)
+                    ) AS transfers
+                FROM window_events
+                GROUP BY period_start
+            ),
+            wallets AS (
+                SELECT period_start, COUNT(DISTINCT LOWER(w.address)) AS active_wallets
+                FROM window_events
+                CROSS JOIN LATERAL (VALUES (from_address), (to_address)) AS w(address)
+                WHERE LOWER(w.address) <> $4 AND LOWER(w.address) <> ALL($5)
+                GROUP BY period_start
+            )
+            SELECT c.period_start, c.mints, c.transfers, c.burns,
+                COALESCE(w.active_wallets, 0) AS active_wallets
+            FROM counts c
+            LEFT JOIN wallets w USING (period_start)
+            ORDER BY c.period_start
+            "#,
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &granularity,
+                &ZERO_ADDRESS,
+                &burn_addresses,
+                &since,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ActivityPeriod {
+            period_start: row.get("period_start"),
+            mints: row.get("mints"),
+            transfers: row.get("transfers"),
+            burns: row.get("burns"),
+            active_wallets: row.get("active_wallets"),
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SaleRow {
+    pub token_id: TokenId,
+    pub amount: Balance,
+    pub seller: String,
+    pub buyer: String,
+    // Smallest unit of the currency as a decimal string, fees included
+    pub price: String,
+    // Payment token address, the zero address for the chain's native currency
+    pub currency: String,
+    pub marketplace: String,
+    pub block_number: i32,
+    pub log_index: i32,
+    pub transaction_hash: String,
+    pub timestamp: Option<i64>,
+}
+
+/// Position of a sale in a contract's sales, sorted by block and log index.
+/// Written `block-log_index` in the `cursor` clients pass back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaleCursor {
+    pub block_number: i32,
+    pub log_index: i32,
+}
+
+impl FromStr for SaleCursor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (block_number, log_index) = s.split_once('-').ok_or(())?;
+        Ok(SaleCursor {
+            block_number: block_number.parse().map_err(|_| ())?,
+            log_index: log_index.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+impl std::fmt::Display for SaleCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.block_number, self.log_index)
+    }
+}
+
+impl From<&SaleRow> for SaleCursor {
+    fn from(sale: &SaleRow) -> Self {
+        SaleCursor {
+            block_number: sale.block_number,
+            log_index: sale.log_index,
+        }
+    }
+}
+
+const SALE_COLUMNS: &str = r#"
+    s.token_id::text AS token_id, s.amount::text AS amount, s.seller, s.buyer,
+    s.price::text AS price, s.currency, s.marketplace, s.block_number, s.log_index,
+    s.transaction_hash, EXTRACT(EPOCH FROM s.block_timestamp)::bigint AS timestamp
+"#;
+
+fn row_to_sale(row: &Row) -> Option<SaleRow> {
+    let token_id: String = row.get("token_id");
+    let amount: String = row.get("amount");
+    Some(SaleRow {
+        token_id: token_id.parse().ok()?,
+        amount: amount.parse().ok()?,
+        seller: row.get("seller"),
+        buyer: row.get("buyer"),
+        price: row.get("price"),
+        currency: row.get("currency"),
+        marketplace: row.get("marketplace"),
+        block_number: row.get("block_number"),
+        log_index: row.get("log_index"),
+        transaction_hash: row.get("transaction_hash"),
+        timestamp: row.get("timestamp"),
+    })
+}
+
+// Sales of a contract, newest first, optionally of a single token. Only sales strictly
+// older than the `before` unix timestamp and strictly after `after` are returned.
+pub async fn get_sales(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    token_id: Option<TokenId>,
+    before: Option<i64>,
+    after: Option<SaleCursor>,
+    limit: i64,
+) -> Result<Vec<SaleRow>, Box<dyn std::error::Error + Send>> {
+    let query = format!(
+        r#"
+        SELECT {}
+        FROM sales s
+        JOIN contracts c ON s.contract_id = c.id
+        JOIN chains ch ON c.chain_id = ch.id
+        WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+            AND ($3::text IS NULL OR s.token_id = $3::text::numeric)
+            AND ($4::bigint IS NULL OR s.block_timestamp < to_timestamp($4::bigint))
+            AND ($5::int4 IS NULL OR (s.block_number, s.log_index) < ($5::int4, $6::int4))
+        ORDER BY s.block_number DESC, s.log_index DESC
+        LIMIT $7
+        "#,
+        SALE_COLUMNS
+    );
+    let rows = client
+        .query(
+            query.as_str(),
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &token_id.map(|id| id.to_string()),
+                &before,
+                &after.map(|cursor| cursor.block_number),
+                &after.map(|cursor| cursor.log_index),
+                &limit,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows.iter().filter_map(row_to_sale).collect())
+}
+
+// Most recent sale of every token of a contract that has been sold
+pub async fn get_last_sales(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<HashMap<TokenId, SaleRow>, Box<dyn std::error::Error + Send>> {
+    let query = format!(
+        r#"
+        SELECT DISTINCT ON (s.token_id) {}
+        FROM sales s
+        JOIN contracts c ON s.contract_id = c.id
+        JOIN chains ch ON c.chain_id = ch.id
+        WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+        ORDER BY s.token_id, s.block_number DESC, s.log_index DESC
+        "#,
+        SALE_COLUMNS
+    );
+    let rows = client
+        .query(
+            query.as_str(),
+            &[&contract_address.to_lowercase(), &chain_name.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .iter()
+        .filter_map(row_to_sale)
+        .map(|sale| (sale.token_id, sale))
+        .collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenTransfer {
+    pub operator: String,
+    pub from_address: String,
+    pub to_address: String,
+    // Amount of this token moved, a batch transfer may carry other ids as well
+    pub value: Balance,
+    pub block_number: i32,
+    pub transaction_hash: String,
+    pub log_index: i32,
+    pub timestamp: Option<i64>,
+}
+
+// Every transfer of one token, oldest first
+pub async fn get_token_transfers(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    token_id: TokenId,
+) -> Result<Vec<TokenTransfer>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT e.operator, e.from_address, e.to_address, e.ids::text[] AS ids, e.values::text[] AS values,
+                e.block_number, e.transaction_hash, e.log_index,
+                EXTRACT(EPOCH FROM e.block_timestamp)::bigint AS block_timestamp
+            FROM events e
+            JOIN contracts c ON e.contract_id = c.id
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+                AND e.ids @> ARRAY[$3::text::numeric]
+            ORDER BY e.block_number, e.transaction_index, e.log_index
+            "#,
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &token_id.to_string(),
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let ids: Vec<TokenId> = parse_numeric_array(row.get("ids"));
+            let values: Vec<Balance> = parse_numeric_array(row.get("values"));
+            let mut value = Balance::default();
+            for (id, id_value) in ids.iter().zip(values.iter()) {
+                if *id == token_id {
+                    value += id_value;
+                }
+            }
+            TokenTransfer {
+                operator: row.get("operator"),
+                from_address: row.get("from_address"),
+                to_address: row.get("to_address"),
+                value,
+                block_number: row.get("block_number"),
+                transaction_hash: row.get("transaction_hash"),
+                log_index: row.get("log_index"),
+                timestamp: row.get("block_timestamp"),
+            }
+        })
+        .collect())
+}
+
+/// Appends a leaderboard snapshot, unless one was taken less than `min_interval_seconds` ago.
+/// Returns whether a snapshot was written.
+pub async fn insert_score_snapshot(
+    client: &tokio_postgres::Client,
+    scores: &[(String, f64, i32)],
+    min_interval_seconds: f64,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let names: Vec<&str> = scores.iter().map(|(name, _, _)| name.as_str()).collect();
+    let points: Vec<f64> = scores.iter().map(|(_, points, _)| *points).collect();
+    let ranks: Vec<i32> = scores.iter().map(|(_, _, rank)| *rank).collect();
+
+    // Not exclusive: two statements running at once both pass the check. Only the refresh
+    // leader snapshots, and a duplicate gets its own recorded_at, which readers never mix
+    let inserted = client
+        .execute(
+            r#"
+            INSERT INTO score_history (name, points, rank, recorded_at)
+            SELECT s.name, s.points, s.rank, now()
+            FROM unnest($1::text[], $2::float8[], $3::int4[]) AS s(name, points, rank)
+            WHERE NOT EXISTS (
+                SELECT 1 FROM score_history
+                WHERE recorded_at > now() - make_interval(secs => $4)
+            )
+            "#,
+            &[&names, &points, &ranks, &min_interval_seconds],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(inserted > 0)
+}
+
+// Session level, held by the leader's connection until it closes
+const LEADERBOARD_LEADER_LOCK: i64 = 0x4166_4c42;
+
+/// Whether this connection leads the leaderboard refresh, taking the lead if it's free.
+/// The advisory lock is only taken when not already held, so it isn't stacked every call.
+pub async fn try_lead_leaderboard(
+    client: &tokio_postgres::Client,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            r#"
+            SELECT CASE
+                WHEN EXISTS (
+                    SELECT 1 FROM pg_locks
+                    WHERE locktype = 'advisory' AND pid = pg_backend_pid() AND granted
+                    AND ((classid::bigint << 32) | objid::bigint) = $1
+                ) THEN true
+                ELSE pg_try_advisory_lock($1)
+            END
+            "#,
+            &[&LEADERBOARD_LEADER_LOCK],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.get(0))
+}
+
+/// NOTIFYs `channel` of the primary, the API replicas LISTEN to it
+pub async fn notify(
+    client: &tokio_postgres::Client,
+    channel: &str,
+    payload: &str,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute("SELECT pg_notify($1, $2)", &[&channel, &payload])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(())
+}
+
+/// Replaces a project's stored leaderboard, returns its computed_at in epoch milliseconds
+pub async fn store_leaderboard(
+    client: &tokio_postgres::Client,
+    project: &str,
+    leaderboard: &HashMap<String, f64>,
+) -> Result<i64, Box<dyn std::error::Error + Send>> {
+    let leaderboard = serde_json::to_string(leaderboard)
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    let row = client
+        .query_one(
+            r#"
+            INSERT INTO leaderboards (project, leaderboard, computed_at)
+            VALUES ($1, $2::text::jsonb, now())
+            ON CONFLICT (project) DO UPDATE
+            SET leaderboard = EXCLUDED.leaderboard, computed_at = EXCLUDED.computed_at
+            RETURNING (EXTRACT(EPOCH FROM computed_at) * 1000)::bigint
+            "#,
+            &[&project, &leaderboard],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.get(0))
+}
+
+/// A project's stored leaderboard and its computed_at in epoch milliseconds, unless it
+/// isn't newer than `newer_than`
+pub async fn get_stored_leaderboard(
+    client: &tokio_postgres::Client,
+    project: &str,
+    newer_than: i64,
+) -> Result<Option<(HashMap<String, f64>, i64)>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT leaderboard::text AS leaderboard,
+                   (EXTRACT(EPOCH FROM computed_at) * 1000)::bigint AS computed_at
+            FROM leaderboards
+            WHERE project = $1 AND (EXTRACT(EPOCH FROM computed_at) * 1000)::bigint > $2
+            "#,
+            &[&project, &newer_than],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    match row {
+        Some(row) => {
+            let leaderboard: String = row.get("leaderboard");
+            let leaderboard = serde_json::from_str(&leaderboard)
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+            Ok(Some((leaderboard, row.get("computed_at"))))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Raises the stored lifetime points of every name in `scores` to its new points, points
+/// already stored are never lowered
+pub async fn store_lifetime_scores(
+    client: &tokio_postgres::Client,
+    scores: &HashMap<String, f64>,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    let (names, points): (Vec<&str>, Vec<f64>) = scores
+        .iter()
+        .map(|(name, points)| (name.as_str(), *points))
+        .unzip();
+    client
+        .execute(
+            r#"
+            INSERT INTO lifetime_scores (name, points, updated_at)
+            SELECT name, points, now() FROM unnest($1::text[], $2::float8[]) AS s(name, points)
+            ON CONFLICT (name) DO UPDATE
+            SET points = EXCLUDED.points, updated_at = EXCLUDED.updated_at
+            WHERE lifetime_scores.points < EXCLUDED.points
+            "#,
+            &[&names, &points],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(())
+}
+
+/// Stored lifetime points, name -> points
+pub async fn get_lifetime_scores(
+    client: &tokio_postgres::Client,
+) -> Result<HashMap<String, f64>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query("SELECT name, points FROM lifetime_scores", &[])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("name"), row.get("points")))
+        .collect())
+}
+
+// None until the user was on a leaderboard computed by the leader
+pub async fn get_lifetime_points(
+    client: &tokio_postgres::Client,
+    name: &str,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            "SELECT points FROM lifetime_scores WHERE name = $1",
+            &[&name],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(row.map(|row| row.get("points")))
+}
+
+/// The latest leaderboard snapshot taken at least `age_seconds` ago, name -> (points, rank).
+/// Empty if no snapshot is that old.
+pub async fn get_score_snapshot(
+    client: &tokio_postgres::Client,
+    age_seconds: f64,
+) -> Result<HashMap<String, (f64, i32)>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT name, points, rank FROM score_history
+            WHERE recorded_at = (
+                SELECT max(recorded_at) FROM score_history
+                WHERE recorded_at <= now() - make_interval(secs => $1)
+            )
+            "#,
+            &[&age_seconds],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("name"), (row.get("points"), row.get("rank"))))
+        .collect())
+}
+
+/// The leaderboard snapshot taken the closest to the unix timestamp `at`, no more than
+/// `tolerance` seconds away, name -> (points, rank). Empty if there's none.
+pub async fn get_score_snapshot_at(
+    client: &tokio_postgres::Client,
+    at: i64,
+    tolerance: i64,
+) -> Result<HashMap<String, (f64, i32)>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT name, points, rank FROM score_history
+            WHERE recorded_at = (
+                SELECT recorded_at FROM score_history
+                WHERE recorded_at BETWEEN to_timestamp($1::bigint - $2::bigint)
+                    AND to_timestamp($1::bigint + $2::bigint)
+                ORDER BY abs(EXTRACT(EPOCH FROM recorded_at) - $1::bigint), recorded_at
+                LIMIT 1
+            )
+            "#,
+            &[&at, &tolerance],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("name"), (row.get("points"), row.get("rank"))))
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeasonResult {
+    pub name: String,
+    pub points: f64,
+    pub rank: i32,
+}
+
+/// Final standings of a season, best rank first. Empty until they're recorded.
+pub async fn get_season_results(
+    client: &tokio_postgres::Client,
+    season: i32,
+) -> Result<Vec<SeasonResult>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT name, points, rank FROM season_results
+            WHERE season = $1
+            ORDER BY rank, name
+            "#,
+            &[&season],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| SeasonResult {
+            name: row.get("name"),
+            points: row.get("points"),
+            rank: row.get("rank"),
+        })
+        .collect())
+}
+
+/// Records the final standings of a season, (name, points, rank). A season's standings are
+/// written once, false if they already were.
+pub async fn store_season_results(
+    client: &tokio_postgres::Client,
+    season: i32,
+    standings: &[(String, f64, i32)],
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let names: Vec<&str> = standings.iter().map(|(name, _, _)| name.as_str()).collect();
+    let points: Vec<f64> = standings.iter().map(|(_, points, _)| *points).collect();
+    let ranks: Vec<i32> = standings.iter().map(|(_, _, rank)| *rank).collect();
+
+    let inserted = client
+        .execute(
+            r#"
+            INSERT INTO season_results (season, name, points, rank)
+            SELECT $1, s.name, s.points, s.rank
+            FROM unnest($2::text[], $3::float8[], $4::int4[]) AS s(name, points, rank)
+            WHERE NOT EXISTS (SELECT 1 FROM season_results WHERE season = $1)
+            "#,
+            &[&season, &names, &points, &ranks],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(inserted > 0)
+}
+
+#[derive(Debug, Clone)]
+pub struct StakedBalance {
+    // Depositor, lowercased
+    pub address: String,
+    pub chain_name: String,
+    pub contract_address: String,
+    pub token_id: TokenId,
+    pub balance: Balance,
+    pub multiplier: f64,
+}
+
+/// Tokens held by staking contracts on behalf of `addresses`, or of every depositor when None
+pub async fn get_staked_balances(
+    client: &tokio_postgres::Client,
+    addresses: Option<&[String]>,
+) -> Result<Vec<StakedBalance>, Box<dyn std::error::Error + Send>> {
+    let addresses_lowercase: Option<Vec<String>> =
+        addresses.map(|addresses| addresses.iter().map(|a| a.to_lowercase()).collect());
+    let rows = client
+        .query(
+            r#"
+            SELECT sb.address, ch.name AS chain_name, c.address AS contract_address,
+                sb.token_id::text AS token_id, sb.balance::text AS balance, s.multiplier
+            FROM staked_balances sb
+            JOIN staking_contracts s ON sb.staking_contract_id = s.id
+            JOIN contracts c ON sb.contract_id = c.id
+            JOIN chains ch ON c.chain_id = ch.id
+            WHERE $1::text[] IS NULL OR sb.address = ANY($1)
+            "#,
+            &[&addresses_lowercase],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(StakedBalance {
+                address: row.get("address"),
+                chain_name: row.get("chain_name"),
+                contract_address: row.get("contract_address"),
+                token_id: row.get::<_, String>("token_id").parse().ok()?,
+                balance: row.get::<_, String>("balance").parse().ok()?,
+                multiplier: row.get("multiplier"),
+            })
+        })
+        .collect())
+}
+
+// Lowercased addresses of every staking contract
+pub async fn get_staking_addresses(
+    client: &tokio_postgres::Client,
+) -> Result<HashSet<String>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query("SELECT address FROM staking_contracts", &[])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows.into_iter().map(|row| row.get("address")).collect())
+}
+
+// (vault, delegate) of every stored delegation, both lowercased. Latest first by the block
+// they were first seen at, then by delegate, so a vault delegated to several wallets
+// always resolves to the same one.
+pub async fn get_delegations(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            "SELECT vault, delegate FROM delegations GROUP BY vault, delegate \
+            ORDER BY MAX(block_number) DESC NULLS LAST, delegate, vault",
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("vault"), row.get("delegate")))
+        .collect())
+}
+
+pub async fn get_token_metadata(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    token_id: TokenId,
+) -> Result<Option<Value>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT metadata::text AS metadata FROM token_metadata
+            WHERE chain = $1 AND contract_address = $2 AND token_id = $3::text::numeric
+            "#,
+            &[
+                &chain_name.to_lowercase(),
+                &contract_address.to_lowercase(),
+                &token_id.to_string(),
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    row.map(|row| serde_json::from_str(row.get("metadata")))
+        .transpose()
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)
+}
+
+/// Stored metadata of the given tokens of a contract, tokens without a row are left out
+pub async fn get_tokens_metadata(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    token_ids: &[TokenId],
+) -> Result<HashMap<TokenId, Value>, Box<dyn std::error::Error + Send>> {
+    let token_ids: Vec<String> = token_ids.iter().map(|id| id.to_string()).collect();
+    let rows = client
+        .query(
+            r#"
+            SELECT token_id::text AS token_id, metadata::text AS metadata FROM token_metadata
+            WHERE chain = $1 AND contract_address = $2 AND token_id = ANY($3::text[]::numeric[])
+            "#,
+            &[
+                &chain_name.to_lowercase(),
+                &contract_address.to_lowercase(),
+                &token_ids,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    // Rows that don't parse are skipped, like a corrupt metadata file would be
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some((
+                row.get::<_, String>("token_id").parse().ok()?,
+                serde_json::from_str(row.get("metadata")).ok()?,
+            ))
+        })
+        .collect())
+}
+
+/// Every stored metadata document of a contract
+pub async fn get_contract_metadata(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<Vec<(TokenId, Value)>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT token_id::text AS token_id, metadata::text AS metadata FROM token_metadata
+            WHERE chain = $1 AND contract_address = $2
+            "#,
+            &[&chain_name.to_lowercase(), &contract_address.to_lowercase()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    // Rows that don't parse are skipped, like a corrupt metadata file would be
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some((
+                row.get::<_, String>("token_id").parse().ok()?,
+                serde_json::from_str(row.get("metadata")).ok()?,
+            ))
+        })
+        .collect())
+}
+
+pub async fn upsert_token_metadata(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+    token_id: TokenId,
+    metadata: &Value,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    let image_url = metadata["image"].as_str();
+    client
+        .execute(
+            r#"
+            INSERT INTO token_metadata
+                (chain, contract_address, token_id, metadata, image_url, fetched_at)
+            VALUES ($1, $2, $3::text::numeric, $4::text::jsonb, $5, now())
+            ON CONFLICT (chain, contract_address, token_id) DO UPDATE
+            SET metadata = EXCLUDED.metadata,
+                image_url = EXCLUDED.image_url,
+                fetched_at = EXCLUDED.fetched_at
+            "#,
+            &[
+                &chain_name.to_lowercase(),
+                &contract_address.to_lowercase(),
+                &token_id.to_string(),
+                &metadata.to_string(),
+                &image_url,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(())
+}
+
+// (chain, contract address) of every contract in token_metadata
+pub async fn get_metadata_contracts(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            "SELECT DISTINCT chain, contract_address FROM token_metadata",
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("chain"), row.get("contract_address")))
+        .collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyRow {
+    pub id: i32,
+    pub name: String,
+    pub requests_per_minute: i32,
+    pub active: bool,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    // UTC day so far, and the last 30 days including today
+    pub requests_today: i64,
+    pub requests_30d: i64,
+}
+
+fn row_to_api_key(row: Row) -> ApiKeyRow {
+    ApiKeyRow {
+        id: row.get("id"),
+        name: row.get("name"),
+        requests_per_minute: row.get("requests_per_minute"),
+        active: row.get("active"),
+        created_at: row.get("created_at"),
+        last_used_at: row.get("last_used_at"),
+        requests_today: row.get("requests_today"),
+        requests_30d: row.get("requests_30d"),
+    }
+}
+
+const API_KEY_COLUMNS: &str = r#"
+    k.id, k.name, k.requests_per_minute, k.active,
+    EXTRACT(EPOCH FROM k.created_at)::bigint AS created_at,
+    EXTRACT(EPOCH FROM k.last_used_at)::bigint AS last_used_at,
+    COALESCE((
+        SELECT SUM(u.requests) FROM api_key_usage u
+        WHERE u.api_key_id = k.id AND u.day = (now() AT TIME ZONE 'utc')::date
+    ), 0)::bigint AS requests_today,
+    COALESCE((
+        SELECT SUM(u.requests) FROM api_key_usage u
+        WHERE u.api_key_id = k.id AND u.day > (now() AT TIME ZONE 'utc')::date - 30
+    ), 0)::bigint AS requests_30d
+"#;
+
+pub async fn insert_api_key(
+    client: &tokio_postgres::Client,
+    name: &str,
+    key_hash: &str,
+    requests_per_minute: i32,
+) -> Result<ApiKeyRow, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            &format!(
+                r#"
+                WITH k AS (
+                    INSERT INTO api_keys (name, key_hash, requests_per_minute)
+                    VALUES ($1, $2, $3)
+                    RETURNING *
+                )
+                SELECT {} FROM k
+                "#,
+                API_KEY_COLUMNS
+            ),
+            &[&name, &key_hash, &requests_per_minute],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row_to_api_key(row))
+}
+
+pub async fn get_api_keys(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<ApiKeyRow>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            &format!("SELECT {} FROM api_keys k ORDER BY k.id", API_KEY_COLUMNS),
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows.into_iter().map(row_to_api_key).collect())
+}
+
+// (id, key hash, requests per minute) of every key that isn't revoked
+pub async fn get_active_api_key_hashes(
+    client: &tokio_postgres::Client,
+) -> Result<Vec<(i32, String, i32)>, Box<dyn std::error::Error + Send>> {
+    let rows = client
+        .query(
+            "SELECT id, key_hash, requests_per_minute FROM api_keys WHERE active",
+            &[],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get("id"),
+                row.get("key_hash"),
+                row.get("requests_per_minute"),
+            )
+        })
+        .collect())
+}
+
+// Keys are revoked rather than deleted, their usage stays on record
+pub async fn revoke_api_key(
+    client: &tokio_postgres::Client,
+    id: i32,
+) -> Result<bool, Box<dyn std::error::Error + Send>> {
+    let updated = client
+        .execute(
+            "UPDATE api_keys SET active = false WHERE id = $1 AND active",
+            &[&id],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(updated > 0)
+}
+
+// Adds (key id, requests) counted since the last flush to today's usage
+pub async fn record_api_key_usage(
+    client: &tokio_postgres::Client,
+    usage: &[(i32, i64)],
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    let ids: Vec<i32> = usage.iter().map(|(id, _)| *id).collect();
+    let requests: Vec<i64> = usage.iter().map(|(_, requests)| *requests).collect();
+    client
+        .execute(
+            r#"
+            WITH counted AS (
+                SELECT * FROM unnest($1::int4[], $2::int8[]) AS c(api_key_id, requests)
+            ),
+            used AS (
+                UPDATE api_keys SET last_used_at = now()
+                WHERE id IN (SELECT api_key_id FROM counted)
+            )
+            INSERT INTO api_key_usage (api_key_id, day, requests)
+            SELECT api_key_id, (now() AT TIME ZONE 'utc')::date, requests FROM counted
+            ON CONFLICT (api_key_id, day) DO UPDATE
+            SET requests = api_key_usage.requests + EXCLUDED.requests
+            "#,
+            &[&ids, &requests],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRow {
+    pub id: i32,
+    pub kind: String,
+    pub params: Value,
+    pub status: String,
+    pub progress_done: i32,
+    pub progress_total: Option<i32>,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+fn row_to_job(row: Row) -> JobRow {
+    let params: String = row.get("params");
+    let result: Option<String> = row.get("result");
+    JobRow {
+        id: row.get("id"),
+        kind: row.get("kind"),
+        params: serde_json::from_str(&params).unwrap_or(Value::Null),
+        status: row.get("status"),
+        progress_done: row.get("progress_done"),
+        progress_total: row.get("progress_total"),
+        result: result.and_then(|result| serde_json::from_str(&result).ok()),
+        error: row.get("error"),
+        created_at: row.get("created_at"),
+        started_at: row.get("started_at"),
+        finished_at: row.get("finished_at"),
+    }
+}
+
+const JOB_COLUMNS: &str = r#"
+    id, kind, params::text AS params, status, progress_done, progress_total,
+    result::text AS result, error,
+    EXTRACT(EPOCH FROM created_at)::bigint AS created_at,
+    EXTRACT(EPOCH FROM started_at)::bigint AS started_at,
+    EXTRACT(EPOCH FROM finished_at)::bigint AS finished_at
+"#;
+
+pub async fn insert_job(
+    client: &tokio_postgres::Client,
+    kind: &str,
+    params: &Value,
+) -> Result<JobRow, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_one(
+            &format!(
+                "INSERT INTO jobs (kind, params) VALUES ($1, $2::text::jsonb) RETURNING {}",
+                JOB_COLUMNS
+            ),
+            &[&kind, &params.to_string()],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row_to_job(row))
+}
+
+pub async fn get_job(
+    client: &tokio_postgres::Client,
+    id: i32,
+) -> Result<Option<JobRow>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            &format!("SELECT {} FROM jobs WHERE id = $1", JOB_COLUMNS),
+            &[&id],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(row_to_job))
+}
+
+/// Marks the oldest queued job as running, leased for `lease` seconds, and returns it.
+/// Running jobs whose lease expired, their replica having died, are claimed again, up to
+/// `max_attempts` runs, then failed. Replicas skip the rows another one is claiming.
+pub async fn claim_job(
+    client: &tokio_postgres::Client,
+    lease: f64,
+    max_attempts: i32,
+) -> Result<Option<JobRow>, Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            r#"
+            UPDATE jobs SET status = 'failed', finished_at = now(), lease_until = NULL,
+                error = 'The worker running it stopped ' || attempts || ' times'
+            WHERE status = 'running' AND lease_until < now() AND attempts >= $1
+            "#,
+            &[&max_attempts],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    let row = client
+        .query_opt(
+            &format!(
+                r#"
+                UPDATE jobs SET status = 'running', started_at = now(), attempts = attempts + 1,
+                    lease_until = now() + make_interval(secs => $1)
+                WHERE id = (
+                    SELECT id FROM jobs
+                    WHERE status = 'queued' OR (status = 'running' AND lease_until < now())
+                    ORDER BY id
+                    FOR UPDATE SKIP LOCKED
+                    LIMIT 1
+                )
+                RETURNING {}
+                "#,
+                JOB_COLUMNS
+            ),
+            &[&lease],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(row_to_job))
+}
+
+/// Extends the lease of a job the worker is still running
+pub async fn renew_job_lease(
+    client: &tokio_postgres::Client,
+    id: i32,
+    lease: f64,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            "UPDATE jobs SET lease_until = now() + make_interval(secs => $2) \
+            WHERE id = $1 AND status = 'running'",
+            &[&id, &lease],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+/// Stores the file a job produced, served by its download route
+pub async fn save_job_file(
+    client: &tokio_postgres::Client,
+    id: i32,
+    contents: &[u8],
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            "INSERT INTO job_files (job_id, contents) VALUES ($1, $2) \
+            ON CONFLICT (job_id) DO UPDATE SET contents = EXCLUDED.contents",
+            &[&id, &contents],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+pub async fn get_job_file(
+    client: &tokio_postgres::Client,
+    id: i32,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt("SELECT contents FROM job_files WHERE job_id = $1", &[&id])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(row.map(|row| row.get("contents")))
+}
+
+pub async fn update_job_progress(
+    client: &tokio_postgres::Client,
+    id: i32,
+    done: i32,
+    total: Option<i32>,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            "UPDATE jobs SET progress_done = $2, progress_total = $3 WHERE id = $1",
+            &[&id, &done, &total],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+// Exactly one of result and error is set
+pub async fn finish_job(
+    client: &tokio_postgres::Client,
+    id: i32,
+    result: Option<&Value>,
+    error: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    let status = if error.is_some() {
+        "failed"
+    } else {
+        "succeeded"
+    };
+    let result = result.map(|result| result.to_string());
+    client
+        .execute(
+            r#"
+            UPDATE jobs SET status = $2, result = $3::text::jsonb, error = $4, finished_at = now(),
+                lease_until = NULL
+            WHERE id = $1
+            "#,
+            &[&id, &status, &result, &error],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(())
+}
+
+/// Current balance of every owner of every token of a contract, as (owner, token id,
+/// balance) ordered by token id. The zero and burn addresses aren't owners.
+pub async fn get_contract_balances(
+    client: &tokio_postgres::Client,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<Vec<(String, TokenId, Balance)>, Box<dyn std::error::Error + Send>> {
+    let burn_addresses = get_contract_burn_addresses(client, chain_name, contract_address).await?;
+    let rows = client
+        .query(
+            r#"
+            WITH transfers AS (
+                SELECT e.from_address, e.to_address, t.id, t.value
+                FROM events e
+                JOIN contracts c ON e.contract_id = c.id
+                JOIN chains ch ON c.chain_id = ch.id
+                CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value)
+                WHERE LOWER(c.address) = $1 AND LOWER(ch.name) = $2
+            ),
+            balances AS (
+                SELECT LOWER(to_address) AS address, id, value FROM transfers
+                UNION ALL
+                SELECT LOWER(from_address) AS address, id, -value FROM transfers
+            )
+            SELECT address, id::text AS token_id, SUM(value)::text AS balance
+            FROM balances
+            WHERE address <> ALL($3)
+            GROUP BY address, id
+            HAVING SUM(value) > 0
+            ORDER BY id, address
+            "#,
+            &[
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+                &burn_addresses,
+            ],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let token_id: String = row.get("token_id");
+            let balance: String = row.get("balance");
+            Some((
+                row.get("address"),
+                token_id.parse().ok()?,
+                balance.parse().ok()?,
+            ))
+        })
+        .collect())
+}
+
+/// Upserts warmed /user/level documents, (username, document)
+pub async fn store_user_details_documents(
+    client: &tokio_postgres::Client,
+    documents: &[(String, Value)],
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    let usernames: Vec<&str> = documents
+        .iter()
+        .map(|(username, _)| username.as_str())
+        .collect();
+    let documents: Vec<String> = documents
+        .iter()
+        .map(|(_, document)| document.to_string())
+        .collect();
+    client
+        .execute(
+            r#"
+            INSERT INTO user_profiles_cache (username, document, computed_at)
+            SELECT username, document::jsonb, now()
+            FROM unnest($1::text[], $2::text[]) AS t(username, document)
+            ON CONFLICT (username) DO UPDATE
+            SET document = EXCLUDED.document, computed_at = EXCLUDED.computed_at
+            "#,
+            &[&usernames, &documents],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(())
+}
+
+/// Drops the warmed documents of users no longer in `usernames`
+pub async fn prune_user_details_documents(
+    client: &tokio_postgres::Client,
+    usernames: &[String],
+) -> Result<u64, Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            "DELETE FROM user_profiles_cache WHERE username <> ALL($1)",
+            &[&usernames],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)
+}
+
+/// A user's warmed /user/level document and its computed_at in epoch seconds, unless it's
+/// older than `max_age_seconds`
+pub async fn get_user_details_document(
+    client: &tokio_postgres::Client,
+    username: &str,
+    max_age_seconds: f64,
+) -> Result<Option<(Value, i64)>, Box<dyn std::error::Error + Send>> {
+    let row = client
+        .query_opt(
+            r#"
+            SELECT document::text AS document,
+                   EXTRACT(EPOCH FROM computed_at)::bigint AS computed_at
+            FROM user_profiles_cache
+            WHERE username = $1 AND computed_at > now() - make_interval(secs => $2)
+            "#,
+            &[&username, &max_age_seconds],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    match row {
+        Some(row) => {
+            let document: String = row.get("document");
+            let document = serde_json::from_str(&document)
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+            Ok(Some((document, row.get("computed_at"))))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn delete_user_details_document(
+    client: &tokio_postgres::Client,
+    username: &str,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            "DELETE FROM user_profiles_cache WHERE username = $1",
+            &[&username],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(())
+}
+
+pub async fn delete_user_details_documents(
+    client: &tokio_postgres::Client,
+    usernames: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute(
+            "DELETE FROM user_profiles_cache WHERE username = ANY($1)",
+            &[&usernames],
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(())
+}
+
+pub async fn delete_all_user_details_documents(
+    client: &tokio_postgres::Client,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    client
+        .execute("DELETE FROM user_profiles_cache", &[])
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    Ok(())
+}
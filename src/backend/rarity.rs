@@ -0,0 +1,372 @@
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::{MetadataStore, METADATA_STORE};
+use crate::backend::queries;
+use crate::backend::rarity_models::{self, RarityModel, Traits};
+use crate::backend::response_cache;
+use crate::common::numeric::TokenId;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+use web3::types::Address;
+
+// One recompute at a time, they share the rarity directory and are IO heavy
+static RECOMPUTE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Named rarity of a token, from the percentile of its score in the collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RarityTier {
+    Common,
+    Rare,
+    Epic,
+    Legendary,
+}
+
+impl RarityTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RarityTier::Common => "common",
+            RarityTier::Rare => "rare",
+            RarityTier::Epic => "epic",
+            RarityTier::Legendary => "legendary",
+        }
+    }
+}
+
+/// Lowest percentile of each tier above common
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TierThresholds {
+    pub rare: f64,
+    pub epic: f64,
+    pub legendary: f64,
+}
+
+// Top 20%, 5% and 1%
+pub const DEFAULT_TIER_THRESHOLDS: TierThresholds = TierThresholds {
+    rare: 80.0,
+    epic: 95.0,
+    legendary: 99.0,
+};
+
+impl TierThresholds {
+    pub fn tier(&self, percentile: f64) -> RarityTier {
+        if percentile >= self.legendary {
+            RarityTier::Legendary
+        } else if percentile >= self.epic {
+            RarityTier::Epic
+        } else if percentile >= self.rare {
+            RarityTier::Rare
+        } else {
+            RarityTier::Common
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        [self.rare, self.epic, self.legendary]
+            .iter()
+            .all(|threshold| threshold.is_finite())
+            && 0.0 <= self.rare
+            && self.rare <= self.epic
+            && self.epic <= self.legendary
+            && self.legendary <= 100.0
+    }
+}
+
+// (lowercased chain, lowercased contract) -> thresholds, mirror of the contracts' tier
+// columns. Contracts on the defaults aren't kept.
+static TIER_THRESHOLDS: Lazy<RwLock<HashMap<(String, String), TierThresholds>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Reloads the tier thresholds from the database
+pub async fn reload(client: &Client) -> Result<(), ApiError> {
+    let rows = queries::get_rarity_tiers(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to load rarity tiers: {}", e)))?;
+
+    *TIER_THRESHOLDS.write().expect("Rarity tiers lock poisoned") = rows
+        .into_iter()
+        .map(|row| {
+            (
+                (
+                    row.chain.to_lowercase(),
+                    row.contract_address.to_lowercase(),
+                ),
+                TierThresholds {
+                    rare: row.rare,
+                    epic: row.epic,
+                    legendary: row.legendary,
+                },
+            )
+        })
+        .collect();
+    Ok(())
+}
+
+/// The tier thresholds of a contract, DEFAULT_TIER_THRESHOLDS unless an admin set its own
+pub fn tier_thresholds(chain_name: &str, contract_address: &str) -> TierThresholds {
+    TIER_THRESHOLDS
+        .read()
+        .expect("Rarity tiers lock poisoned")
+        .get(&(chain_name.to_lowercase(), contract_address.to_lowercase()))
+        .copied()
+        .unwrap_or(DEFAULT_TIER_THRESHOLDS)
+}
+
+/// Percentile of each score among `scores`: the share of the scores strictly below it, in
+/// percent. Tied tokens get the same percentile, the rarest token of 100 gets 99.
+pub fn percentiles(scores: &[f64]) -> Vec<f64> {
+    let mut sorted = scores.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    scores
+        .iter()
+        .map(|score| {
+            let below = sorted.partition_point(|other| other < score);
+            below as f64 * 100.0 / scores.len() as f64
+        })
+        .collect()
+}
+
+/// trait_type -> value of a token, from the attributes of its metadata
+pub(crate) fn token_traits(metadata: &Value) -> Traits {
+    let mut traits = HashMap::new();
+    if let Some(attributes) = metadata["attributes"].as_array() {
+        for attribute in attributes {
+            let trait_type = match attribute["trait_type"].as_str() {
+                Some(trait_type) => trait_type.to_string(),
+                None => continue,
+            };
+            let value = match &attribute["value"] {
+                Value::String(value) => value.clone(),
+                Value::Null => continue,
+                value => value.to_string(),
+            };
+            traits.insert(trait_type, value);
+        }
+    }
+    traits
+}
+
+// Rarest first, `rarity_index` starts at 1. Ties keep the token id order.
+fn rank_tokens(
+    model: &dyn RarityModel,
+    tokens: Vec<(TokenId, Traits)>,
+) -> Vec<(TokenId, f64, u64)> {
+    let scores = model.score(&tokens);
+    let mut scored: Vec<(TokenId, f64)> = tokens
+        .into_iter()
+        .zip(scores)
+        .map(|((token_id, _), score)| (token_id, score))
+        .collect();
+    scored.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.0.cmp(&b.0))
+    });
+
+    scored
+        .into_iter()
+        .enumerate()
+        .map(|(rank, (token_id, score))| (token_id, score, rank as u64 + 1))
+        .collect()
+}
+
+// The model configured for the contract, the default one without a database
+async fn contract_model(
+    store: &MetadataStore,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<&'static dyn RarityModel, ApiError> {
+    let name = match store.client().await {
+        Some(client) => queries::get_rarity_model(&client, chain_name, contract_address)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get rarity model: {}", e)))?,
+        None => None,
+    };
+    let name = name.unwrap_or_else(|| rarity_models::DEFAULT_MODEL.to_string());
+    rarity_models::model(&name).ok_or_else(|| {
+        ApiError::BadRequest(format!(
+            "Unknown rarity model {}, expected one of {}",
+            name,
+            rarity_models::model_names().join(", ")
+        ))
+    })
+}
+
+/// Recomputes a contract's rarity file from its metadata and swaps it in, returns the
+/// number of ranked tokens
+pub async fn recompute(
+    store: &MetadataStore,
+    chain_name: &str,
+    contract_address: &str,
+) -> Result<usize, ApiError> {
+    // Both end up in paths we write to
+    if !chain_name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        || contract_address.parse::<Address>().is_err()
+    {
+        return Err(ApiError::BadRequest(
+            "Invalid chain or contract address".to_string(),
+        ));
+    }
+
+    let _guard = RECOMPUTE_LOCK.lock().await;
+    let metadata = store
+        .contract_metadata(chain_name, contract_address)
+        .await
+        .map_err(ApiError::Upstream)?;
+    if metadata.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "No metadata for contract {} on {}",
+            contract_address, chain_name
+        )));
+    }
+
+    let model = contract_model(store, chain_name, contract_address).await?;
+    let tokens: Vec<(TokenId, Traits)> = metadata
+        .iter()
+        .map(|(token_id, metadata)| (*token_id, token_traits(metadata)))
+        .collect();
+    // Some models are quadratic in the number of tokens, keep them off the runtime threads
+    let ranked = tokio::task::spawn_blocking(move || rank_tokens(model, tokens))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Rarity scoring failed: {}", e)))?;
+    let rarities: Vec<Value> = ranked
+        .iter()
+        .map(|(token_id, rarity_score, rarity_index)| {
+            json!({
+                "token_id": token_id,
+                "rarity_score": rarity_score,
+                "rarity_index": rarity_index,
+            })
+        })
+        .collect();
+    // The model is recorded with the scores it produced
+    let contents = serde_json::to_string(&json!({
+        "rarity_model": model.name(),
+        "rarities": rarities,
+    }))
+    .map_err(|e| ApiError::Internal(format!("Failed to encode rarities: {}", e)))?;
+
+    store
+        .save_rarity_file(chain_name, contract_address, contents.into_bytes())
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to write rarities: {}", e)))?;
+
+    // The metadata store reloads the file, cached responses are stale though
+    response_cache::invalidate_all();
+    Ok(ranked.len())
+}
+
+/// Recomputes every contract with metadata, for the rarity scheduler
+pub async fn recompute_all(store: &MetadataStore) {
+    for (chain_name, contract_address) in store.metadata_contracts().await {
+        if contract_address.parse::<Address>().is_err() {
+            continue;
+        }
+        match recompute(store, &chain_name, &contract_address).await {
+            Ok(count) => println!(
+                "Rarity: ranked {} tokens of {} on {}",
+                count, contract_address, chain_name
+            ),
+            Err(e) => eprintln!(
+                "Rarity: {} on {} failed: {:?}",
+                contract_address, chain_name, e
+            ),
+        }
+    }
+}
+
+/// Spawns the periodic recompute of every contract when AFTERLIFE_RARITY_RECOMPUTE_SECONDS
+/// is set
+pub fn spawn_scheduler() {
+    let seconds = match env::var("AFTERLIFE_RARITY_RECOMPUTE_SECONDS") {
+        Ok(value) => match value.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => seconds,
+            _ => {
+                eprintln!("Rarity scheduler disabled: invalid interval {}", value);
+                return;
+            }
+        },
+        Err(_) => return,
+    };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(seconds));
+        loop {
+            interval.tick().await;
+            recompute_all(&METADATA_STORE).await;
+        }
+    });
+}
+
+pub async fn handle_set_rarity_tiers(
+    chain_name: String,
+    contract_address: String,
+    body: TierThresholds,
+    client: Arc<Client>,
+) -> Result<impl Reply, Rejection> {
+    if !body.is_valid() {
+        return Err(ApiError::BadRequest(
+            "Thresholds must be percentiles with rare <= epic <= legendary".to_string(),
+        )
+        .into());
+    }
+    set_rarity_tiers(
+        &chain_name,
+        &contract_address,
+        Some((body.rare, body.epic, body.legendary)),
+        &client,
+    )
+    .await?;
+
+    Ok(warp::reply::json(&json!({
+        "chain": chain_name,
+        "contract_address": contract_address,
+        "rarity_tiers": body,
+    }))
+    .into_response())
+}
+
+// Back to DEFAULT_TIER_THRESHOLDS
+pub async fn handle_reset_rarity_tiers(
+    chain_name: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl Reply, Rejection> {
+    set_rarity_tiers(&chain_name, &contract_address, None, &client).await?;
+
+    Ok(warp::reply::json(&json!({
+        "chain": chain_name,
+        "contract_address": contract_address,
+        "rarity_tiers": DEFAULT_TIER_THRESHOLDS,
+    }))
+    .into_response())
+}
+
+async fn set_rarity_tiers(
+    chain_name: &str,
+    contract_address: &str,
+    thresholds: Option<(f64, f64, f64)>,
+    client: &Client,
+) -> Result<(), ApiError> {
+    let updated = queries::set_rarity_tiers(client, chain_name, contract_address, thresholds)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to set rarity tiers: {}", e)))?;
+    if !updated {
+        return Err(ApiError::NotFound(format!(
+            "No contract {} on {}",
+            contract_address, chain_name
+        )));
+    }
+    reload(client).await?;
+    // Tiers are part of the token details
+    response_cache::invalidate_all();
+    Ok(())
+}
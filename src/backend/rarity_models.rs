@@ -0,0 +1,195 @@
+use crate::common::numeric::TokenId;
+use std::collections::{HashMap, HashSet};
+
+// Trait value of tokens that don't have the trait type at all
+const MISSING_TRAIT: &str = "None";
+// Pseudo trait type holding how many traits a token has, as OpenRarity scores it
+const TRAIT_COUNT: &str = "Trait count";
+
+/// trait_type -> value of one token
+pub type Traits = HashMap<String, String>;
+
+/// A way of scoring how rare the tokens of a collection are, selected per contract with
+/// `rarity_model` in the indexer config
+pub trait RarityModel: Send + Sync {
+    /// Name used in the config and recorded in the rarity files
+    fn name(&self) -> &'static str;
+
+    /// Score of every token, in the order of `tokens`. Higher is rarer, scores are only
+    /// compared within the collection.
+    fn score(&self, tokens: &[(TokenId, Traits)]) -> Vec<f64>;
+}
+
+pub const DEFAULT_MODEL: &str = "trait_sum";
+
+static MODELS: &[&dyn RarityModel] = &[&TraitSum, &InformationContent, &Jaccard];
+
+/// The model called `name`, None for unknown names
+pub fn model(name: &str) -> Option<&'static dyn RarityModel> {
+    MODELS
+        .iter()
+        .find(|model| model.name().eq_ignore_ascii_case(name))
+        .copied()
+}
+
+pub fn model_names() -> Vec<&'static str> {
+    MODELS.iter().map(|model| model.name()).collect()
+}
+
+// Every trait type of the collection, sorted
+fn trait_types(tokens: &[(TokenId, Traits)]) -> Vec<String> {
+    let mut trait_types: Vec<String> = tokens
+        .iter()
+        .flat_map(|(_, traits)| traits.keys().cloned())
+        .collect();
+    trait_types.sort();
+    trait_types.dedup();
+    trait_types
+}
+
+fn value_of<'a>(traits: &'a Traits, trait_type: &str) -> &'a str {
+    traits
+        .get(trait_type)
+        .map(String::as_str)
+        .unwrap_or(MISSING_TRAIT)
+}
+
+// (trait_type, value) -> number of tokens having it, tokens missing a trait type counted
+// under its "None" value
+fn value_counts<'a>(
+    tokens: &'a [(TokenId, Traits)],
+    trait_types: &'a [String],
+) -> HashMap<(&'a str, &'a str), usize> {
+    let mut counts = HashMap::new();
+    for (_, traits) in tokens {
+        for trait_type in trait_types {
+            *counts
+                .entry((trait_type.as_str(), value_of(traits, trait_type)))
+                .or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// Every trait value is worth 1 point split evenly between the tokens that have it, a
+/// token scores the sum over all trait types. Tokens missing a trait type share its
+/// "None" value.
+pub struct TraitSum;
+
+impl RarityModel for TraitSum {
+    fn name(&self) -> &'static str {
+        "trait_sum"
+    }
+
+    fn score(&self, tokens: &[(TokenId, Traits)]) -> Vec<f64> {
+        let trait_types = trait_types(tokens);
+        let counts = value_counts(tokens, &trait_types);
+        tokens
+            .iter()
+            .map(|(_, traits)| {
+                trait_types
+                    .iter()
+                    .map(|trait_type| {
+                        1.0 / counts[&(trait_type.as_str(), value_of(traits, trait_type))] as f64
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// OpenRarity: the information content of a token's traits, -log2 of the probability of
+/// each value summed over the trait types, divided by the collection's entropy. The
+/// number of traits of a token counts as one more trait type.
+pub struct InformationContent;
+
+impl RarityModel for InformationContent {
+    fn name(&self) -> &'static str {
+        "openrarity"
+    }
+
+    fn score(&self, tokens: &[(TokenId, Traits)]) -> Vec<f64> {
+        let tokens: Vec<(TokenId, Traits)> = tokens
+            .iter()
+            .map(|(token_id, traits)| {
+                let mut traits = traits.clone();
+                traits.insert(TRAIT_COUNT.to_string(), traits.len().to_string());
+                (*token_id, traits)
+            })
+            .collect();
+        let trait_types = trait_types(&tokens);
+        let counts = value_counts(&tokens, &trait_types);
+        let total = tokens.len() as f64;
+
+        let entropy: f64 = counts
+            .values()
+            .map(|&count| {
+                let probability = count as f64 / total;
+                -probability * probability.log2()
+            })
+            .sum();
+        tokens
+            .iter()
+            .map(|(_, traits)| {
+                let information: f64 = trait_types
+                    .iter()
+                    .map(|trait_type| {
+                        let count = counts[&(trait_type.as_str(), value_of(traits, trait_type))];
+                        -(count as f64 / total).log2()
+                    })
+                    .sum();
+                // Every token is the same, none is rarer
+                if entropy > 0.0 {
+                    information / entropy
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+}
+
+/// Average Jaccard distance between a token's set of trait values and every other
+/// token's: tokens unlike the rest of the collection score higher. Quadratic in the
+/// number of tokens.
+pub struct Jaccard;
+
+impl RarityModel for Jaccard {
+    fn name(&self) -> &'static str {
+        "jaccard"
+    }
+
+    fn score(&self, tokens: &[(TokenId, Traits)]) -> Vec<f64> {
+        if tokens.len() < 2 {
+            return vec![0.0; tokens.len()];
+        }
+        let sets: Vec<HashSet<(&str, &str)>> = tokens
+            .iter()
+            .map(|(_, traits)| {
+                traits
+                    .iter()
+                    .map(|(trait_type, value)| (trait_type.as_str(), value.as_str()))
+                    .collect()
+            })
+            .collect();
+
+        let mut distances = vec![0.0; sets.len()];
+        for i in 0..sets.len() {
+            for j in i + 1..sets.len() {
+                let union = sets[i].union(&sets[j]).count();
+                let distance = if union == 0 {
+                    0.0
+                } else {
+                    1.0 - sets[i].intersection(&sets[j]).count() as f64 / union as f64
+                };
+                distances[i] += distance;
+                distances[j] += distance;
+            }
+        }
+        let others = (sets.len() - 1) as f64;
+        distances
+            .into_iter()
+            .map(|distance| distance / others)
+            .collect()
+    }
+}
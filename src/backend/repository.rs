@@ -0,0 +1,395 @@
+use crate::backend::queries;
+use crate::common::addresses;
+use crate::common::numeric::{Balance, TokenId};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::RwLock;
+use tokio_postgres::Client;
+
+pub type RepositoryFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, Box<dyn Error + Send>>> + Send + 'a>>;
+
+/// Chain name -> contract address -> token id -> balance
+pub type FullCollection = HashMap<String, HashMap<String, HashMap<TokenId, Balance>>>;
+
+/// Who holds what, as replayed from the indexed transfers. Handlers taking this rather
+/// than a database client can be run against `InMemoryRepository`.
+pub trait CollectionRepository: Send + Sync {
+    /// Ids of the tokens minted and not burned since
+    fn entire_collection<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+    ) -> RepositoryFuture<'a, Vec<TokenId>>;
+
+    /// Positive balances of a wallet in one contract
+    fn collection_for_address<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+        wallet_address: &'a str,
+    ) -> RepositoryFuture<'a, HashMap<TokenId, Balance>>;
+
+    /// Owners of a token as of the end of `block`, currently when None. Burn addresses
+    /// aren't owners.
+    fn token_owners<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+        token_id: TokenId,
+        block: Option<i32>,
+    ) -> RepositoryFuture<'a, Vec<String>>;
+
+    /// Last block indexed for a contract, None for unknown contracts
+    fn last_processed_block<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+    ) -> RepositoryFuture<'a, Option<i32>>;
+
+    /// Every positive balance of a wallet, unverified contracts left out unless
+    /// `include_unverified`
+    fn user_full_collection<'a>(
+        &'a self,
+        wallet_address: &'a str,
+        include_unverified: bool,
+    ) -> RepositoryFuture<'a, FullCollection>;
+
+    /// Whether a user hid the wallet from their public profile
+    fn is_hidden_address<'a>(&'a self, wallet_address: &'a str) -> RepositoryFuture<'a, bool>;
+}
+
+/// The indexer's database, through the functions of `queries`
+impl CollectionRepository for Client {
+    fn entire_collection<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+    ) -> RepositoryFuture<'a, Vec<TokenId>> {
+        Box::pin(queries::get_entire_collection(
+            self,
+            chain_name,
+            contract_address,
+        ))
+    }
+
+    fn collection_for_address<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+        wallet_address: &'a str,
+    ) -> RepositoryFuture<'a, HashMap<TokenId, Balance>> {
+        Box::pin(queries::get_entire_collection_for_address(
+            self,
+            chain_name,
+            contract_address,
+            wallet_address,
+        ))
+    }
+
+    fn token_owners<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+        token_id: TokenId,
+        block: Option<i32>,
+    ) -> RepositoryFuture<'a, Vec<String>> {
+        Box::pin(queries::get_token_owners(
+            self,
+            chain_name,
+            contract_address,
+            token_id,
+            block,
+        ))
+    }
+
+    fn last_processed_block<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+    ) -> RepositoryFuture<'a, Option<i32>> {
+        Box::pin(queries::get_last_processed_block(
+            self,
+            chain_name,
+            contract_address,
+        ))
+    }
+
+    fn user_full_collection<'a>(
+        &'a self,
+        wallet_address: &'a str,
+        include_unverified: bool,
+    ) -> RepositoryFuture<'a, FullCollection> {
+        Box::pin(queries::get_user_full_collection(
+            self,
+            wallet_address,
+            include_unverified,
+        ))
+    }
+
+    fn is_hidden_address<'a>(&'a self, wallet_address: &'a str) -> RepositoryFuture<'a, bool> {
+        Box::pin(queries::is_hidden_address(self, wallet_address))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InMemoryContract {
+    pub chain_name: String,
+    pub address: String,
+    pub verified: bool,
+    pub burn_addresses: Vec<String>,
+    pub last_processed_block: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct InMemoryTransfer {
+    pub chain_name: String,
+    pub contract_address: String,
+    pub from_address: String,
+    pub to_address: String,
+    pub token_id: TokenId,
+    pub value: Balance,
+    pub block_number: i32,
+}
+
+/// Contracts and transfers held in memory, replayed the way the queries replay events.
+/// Transfers of contracts that weren't added count as verified without burn addresses.
+#[derive(Debug, Default)]
+pub struct InMemoryRepository {
+    contracts: RwLock<Vec<InMemoryContract>>,
+    transfers: RwLock<Vec<InMemoryTransfer>>,
+    // Lowercased
+    hidden_addresses: RwLock<HashSet<String>>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_contract(&self, contract: InMemoryContract) {
+        self.contracts
+            .write()
+            .expect("Repository lock poisoned")
+            .push(contract);
+    }
+
+    pub fn add_transfer(&self, transfer: InMemoryTransfer) {
+        self.transfers
+            .write()
+            .expect("Repository lock poisoned")
+            .push(transfer);
+    }
+
+    /// As if a user hid the wallet from their public profile
+    pub fn hide_address(&self, wallet_address: &str) {
+        self.hidden_addresses
+            .write()
+            .expect("Repository lock poisoned")
+            .insert(wallet_address.to_lowercase());
+    }
+
+    fn contract(&self, chain_name: &str, contract_address: &str) -> Option<InMemoryContract> {
+        self.contracts
+            .read()
+            .expect("Repository lock poisoned")
+            .iter()
+            .find(|contract| {
+                contract.chain_name.eq_ignore_ascii_case(chain_name)
+                    && contract.address.eq_ignore_ascii_case(contract_address)
+            })
+            .cloned()
+    }
+
+    fn burn_addresses(&self, chain_name: &str, contract_address: &str) -> Vec<String> {
+        self.contract(chain_name, contract_address)
+            .map(|contract| contract.burn_addresses)
+            .unwrap_or_default()
+    }
+
+    fn verified(&self, chain_name: &str, contract_address: &str) -> bool {
+        self.contract(chain_name, contract_address)
+            .is_none_or(|contract| contract.verified)
+    }
+
+    // Transfers of one contract, in block order
+    fn contract_transfers(
+        &self,
+        chain_name: &str,
+        contract_address: &str,
+    ) -> Vec<InMemoryTransfer> {
+        let mut transfers: Vec<InMemoryTransfer> = self
+            .transfers
+            .read()
+            .expect("Repository lock poisoned")
+            .iter()
+            .filter(|transfer| {
+                transfer.chain_name.eq_ignore_ascii_case(chain_name)
+                    && transfer
+                        .contract_address
+                        .eq_ignore_ascii_case(contract_address)
+            })
+            .cloned()
+            .collect();
+        transfers.sort_by_key(|transfer| transfer.block_number);
+        transfers
+    }
+}
+
+impl CollectionRepository for InMemoryRepository {
+    fn entire_collection<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+    ) -> RepositoryFuture<'a, Vec<TokenId>> {
+        Box::pin(async move {
+            let burn_addresses = self.burn_addresses(chain_name, contract_address);
+            let mut supplies: HashMap<TokenId, Balance> = HashMap::new();
+            for transfer in self.contract_transfers(chain_name, contract_address) {
+                if addresses::is_mint(&transfer.from_address) {
+                    *supplies.entry(transfer.token_id).or_default() += &transfer.value;
+                } else if addresses::is_burn(&transfer.to_address, &burn_addresses) {
+                    *supplies.entry(transfer.token_id).or_default() -= &transfer.value;
+                }
+            }
+            let mut token_ids: Vec<TokenId> = supplies
+                .into_iter()
+                .filter(|(_, supply)| supply.is_positive())
+                .map(|(token_id, _)| token_id)
+                .collect();
+            token_ids.sort_unstable();
+            Ok(token_ids)
+        })
+    }
+
+    fn collection_for_address<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+        wallet_address: &'a str,
+    ) -> RepositoryFuture<'a, HashMap<TokenId, Balance>> {
+        Box::pin(async move {
+            let mut balances: HashMap<TokenId, Balance> = HashMap::new();
+            for transfer in self.contract_transfers(chain_name, contract_address) {
+                if transfer.to_address.eq_ignore_ascii_case(wallet_address) {
+                    *balances.entry(transfer.token_id).or_default() += &transfer.value;
+                }
+                if transfer.from_address.eq_ignore_ascii_case(wallet_address) {
+                    *balances.entry(transfer.token_id).or_default() -= &transfer.value;
+                }
+            }
+            balances.retain(|_, balance| balance.is_positive());
+            Ok(balances)
+        })
+    }
+
+    fn token_owners<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+        token_id: TokenId,
+        block: Option<i32>,
+    ) -> RepositoryFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let burn_addresses = self.burn_addresses(chain_name, contract_address);
+            let mut owners: HashMap<String, Balance> = HashMap::new();
+            for transfer in self
+                .contract_transfers(chain_name, contract_address)
+                .into_iter()
+                .filter(|transfer| transfer.token_id == token_id)
+                .filter(|transfer| block.is_none_or(|block| transfer.block_number <= block))
+            {
+                *owners
+                    .entry(transfer.to_address.to_lowercase())
+                    .or_default() += &transfer.value;
+                *owners
+                    .entry(transfer.from_address.to_lowercase())
+                    .or_default() -= &transfer.value;
+            }
+            let mut owners: Vec<String> = owners
+                .into_iter()
+                .filter(|(address, balance)| {
+                    balance.is_positive() && !addresses::is_burn(address, &burn_addresses)
+                })
+                .map(|(address, _)| address)
+                .collect();
+            owners.sort();
+            Ok(owners)
+        })
+    }
+
+    fn last_processed_block<'a>(
+        &'a self,
+        chain_name: &'a str,
+        contract_address: &'a str,
+    ) -> RepositoryFuture<'a, Option<i32>> {
+        Box::pin(async move {
+            Ok(self
+                .contract(chain_name, contract_address)
+                .map(|contract| contract.last_processed_block))
+        })
+    }
+
+    fn user_full_collection<'a>(
+        &'a self,
+        wallet_address: &'a str,
+        include_unverified: bool,
+    ) -> RepositoryFuture<'a, FullCollection> {
+        Box::pin(async move {
+            let transfers = self
+                .transfers
+                .read()
+                .expect("Repository lock poisoned")
+                .clone();
+            let mut collection = FullCollection::new();
+            for transfer in transfers {
+                if !include_unverified
+                    && !self.verified(&transfer.chain_name, &transfer.contract_address)
+                {
+                    continue;
+                }
+                for (address, incoming) in [
+                    (&transfer.to_address, true),
+                    (&transfer.from_address, false),
+                ] {
+                    if !address.eq_ignore_ascii_case(wallet_address) {
+                        continue;
+                    }
+                    let balance = collection
+                        .entry(transfer.chain_name.clone())
+                        .or_default()
+                        .entry(transfer.contract_address.clone())
+                        .or_default()
+                        .entry(transfer.token_id)
+                        .or_default();
+                    if incoming {
+                        *balance += &transfer.value;
+                    } else {
+                        *balance -= &transfer.value;
+                    }
+                }
+            }
+            for chain_balances in collection.values_mut() {
+                for contract_balances in chain_balances.values_mut() {
+                    // Balances never go negative, like the queries'
+                    contract_balances.retain(|_, balance| balance.is_positive());
+                }
+                chain_balances.retain(|_, balances| !balances.is_empty());
+            }
+            collection.retain(|_, balances| !balances.is_empty());
+            Ok(collection)
+        })
+    }
+
+    fn is_hidden_address<'a>(&'a self, wallet_address: &'a str) -> RepositoryFuture<'a, bool> {
+        Box::pin(async move {
+            Ok(self
+                .hidden_addresses
+                .read()
+                .expect("Repository lock poisoned")
+                .contains(&wallet_address.to_lowercase()))
+        })
+    }
+}
@@ -0,0 +1,60 @@
+use crate::backend::errors::ApiError;
+use moka::future::Cache;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Keep responses for as long as clients are told to cache them (see Cache-Control in api.rs)
+const RESPONSE_CACHE_TTL: Duration = Duration::from_secs(60);
+const RESPONSE_CACHE_MAX_ENTRIES: u64 = 10_000;
+
+static RESPONSE_CACHE: Lazy<Cache<String, Arc<Value>>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(RESPONSE_CACHE_MAX_ENTRIES)
+        .time_to_live(RESPONSE_CACHE_TTL)
+        .support_invalidation_closures()
+        .build()
+});
+
+/// Returns the cached response for `key`, computing it with `init` on a miss.
+///
+/// Concurrent requests for the same cold key share a single evaluation of `init`,
+/// so a burst of requests for one user only reads the metadata files once.
+/// Errors are returned to every waiting caller and are not cached.
+pub async fn get_or_compute<F>(key: String, init: F) -> Result<Arc<Value>, ApiError>
+where
+    F: Future<Output = Result<Value, ApiError>>,
+{
+    RESPONSE_CACHE
+        .try_get_with(key, async move { init.await.map(Arc::new) })
+        .await
+        .map_err(|e| (*e).clone())
+}
+
+pub async fn invalidate(key: &str) {
+    RESPONSE_CACHE.invalidate(key).await;
+}
+
+/// Drops every response whose key matches `predicate`. Entries are dropped lazily, but a
+/// dropped entry is never served again.
+pub fn invalidate_matching<F>(predicate: F)
+where
+    F: Fn(&str) -> bool + Send + Sync + 'static,
+{
+    if let Err(e) = RESPONSE_CACHE.invalidate_entries_if(move |key, _| predicate(key.as_str())) {
+        // Only when the cache wasn't built to support it, fall back to dropping everything
+        eprintln!("Failed to invalidate cached responses: {}", e);
+        RESPONSE_CACHE.invalidate_all();
+    }
+}
+
+pub fn invalidate_all() {
+    RESPONSE_CACHE.invalidate_all();
+}
+
+/// Responses currently cached by this replica
+pub fn entry_count() -> u64 {
+    RESPONSE_CACHE.entry_count()
+}
@@ -0,0 +1,243 @@
+use crate::backend::jobs::{self, Job};
+use crate::backend::metadata_store::METADATA_STORE;
+use crate::backend::queries::get_entire_collection;
+use crate::common::database::Database;
+use crate::common::numeric::TokenId;
+use crate::metadata::token_uri::fetch_token_metadata;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_postgres::Client;
+use web3::transports::Http;
+use web3::types::Address;
+use web3::Web3;
+
+const DEFAULT_REFRESH_SECONDS: u64 = 900;
+const DEFAULT_SAMPLE_SIZE: usize = 5;
+
+/// Reveal watcher settings, read from the YAML file in AFTERLIFE_PATH_REVEALS. Contracts
+/// listed as unrevealed have a sample of their token URIs re-fetched every refresh; once
+/// the content changes, a metadata refresh job re-fetches all their metadata and
+/// recomputes their rarity.
+///
+/// ```yaml
+/// refresh_seconds: 900
+/// sample_size: 5
+/// unrevealed:
+///   - chain: fantom
+///     rpc_url: https://...
+///     address: "0x..."
+///     type: erc721
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct RevealConfig {
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+    #[serde(default = "default_sample_size")]
+    pub sample_size: usize,
+    pub unrevealed: Vec<UnrevealedContract>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnrevealedContract {
+    pub chain: String,
+    pub rpc_url: String,
+    pub address: String,
+    #[serde(default = "default_contract_type")]
+    pub r#type: String,
+}
+
+fn default_refresh_seconds() -> u64 {
+    DEFAULT_REFRESH_SECONDS
+}
+
+fn default_sample_size() -> usize {
+    DEFAULT_SAMPLE_SIZE
+}
+
+fn default_contract_type() -> String {
+    "erc721".to_string()
+}
+
+impl RevealConfig {
+    pub fn from_env() -> Option<Result<Self, String>> {
+        let path = env::var("AFTERLIFE_PATH_REVEALS").ok()?;
+        Some(
+            fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read reveal config {}: {}", path, e))
+                .and_then(|content| {
+                    serde_yaml::from_str(&content)
+                        .map_err(|e| format!("Invalid reveal config: {}", e))
+                }),
+        )
+    }
+}
+
+// The tokens that hash first, seeded with the contract: spread over the collection, so a
+// partial reveal of the first ids isn't required, and the same ones on every check. A new
+// mint only takes a spot when it hashes before one of them.
+fn sample(contract: &UnrevealedContract, token_ids: &[TokenId], size: usize) -> Vec<TokenId> {
+    if token_ids.len() <= size {
+        return token_ids.to_vec();
+    }
+    let seed = format!(
+        "{}:{}",
+        contract.chain.to_lowercase(),
+        contract.address.to_lowercase()
+    );
+    let mut ranked: Vec<([u8; 32], TokenId)> = token_ids
+        .iter()
+        .map(|token_id| {
+            let hash = Sha256::digest(format!("{}:{}", seed, token_id).as_bytes());
+            (hash.into(), *token_id)
+        })
+        .collect();
+    ranked.sort_unstable();
+    ranked
+        .into_iter()
+        .take(size)
+        .map(|(_, token_id)| token_id)
+        .collect()
+}
+
+async fn write_metadata(
+    contract: &UnrevealedContract,
+    token_id: TokenId,
+    metadata: &Value,
+) -> Result<(), String> {
+    METADATA_STORE
+        .save_token_metadata(&contract.chain, &contract.address, token_id, metadata)
+        .await
+}
+
+/// Whether the sampled metadata differs from the stored one. Tokens without stored
+/// metadata get it written, as the baseline for the next check.
+async fn has_revealed(
+    web3: &Web3<Http>,
+    contract: &UnrevealedContract,
+    address: Address,
+    token_ids: &[TokenId],
+) -> Result<bool, String> {
+    for token_id in token_ids {
+        let fetched = fetch_token_metadata(web3, address, &contract.r#type, token_id.0)
+            .await
+            .map_err(|e| format!("token {}: {}", token_id, e))?;
+        match METADATA_STORE
+            .token_metadata(&contract.chain, &contract.address, *token_id)
+            .await
+        {
+            Some(stored) if *stored != fetched => return Ok(true),
+            Some(_) => {}
+            None => write_metadata(contract, *token_id, &fetched).await?,
+        }
+    }
+    Ok(false)
+}
+
+/// Checks every unrevealed contract once, returns the ones that revealed
+async fn check_reveals(
+    client: &Client,
+    config: &RevealConfig,
+    revealed: &HashSet<String>,
+) -> Vec<String> {
+    let mut newly_revealed = Vec::new();
+    for contract in &config.unrevealed {
+        let key = format!("{}/{}", contract.chain, contract.address.to_lowercase());
+        if revealed.contains(&key) {
+            continue;
+        }
+        let address: Address = match contract.address.parse() {
+            Ok(address) => address,
+            Err(_) => {
+                eprintln!(
+                    "Reveal: [{}] invalid contract address {}",
+                    contract.chain, contract.address
+                );
+                continue;
+            }
+        };
+        let web3 = match Http::new(&contract.rpc_url) {
+            Ok(transport) => Web3::new(transport),
+            Err(e) => {
+                eprintln!("Reveal: [{}] invalid rpc url: {}", contract.chain, e);
+                continue;
+            }
+        };
+        let token_ids =
+            match get_entire_collection(client, &contract.chain, &contract.address).await {
+                Ok(token_ids) => token_ids,
+                Err(e) => {
+                    eprintln!(
+                        "Reveal: [{}] {} failed to list tokens: {}",
+                        contract.chain, contract.address, e
+                    );
+                    continue;
+                }
+            };
+        match has_revealed(
+            &web3,
+            contract,
+            address,
+            &sample(contract, &token_ids, config.sample_size),
+        )
+        .await
+        {
+            Ok(true) => {
+                let job = Job::MetadataRefresh {
+                    chain: contract.chain.clone(),
+                    contract_address: contract.address.clone(),
+                    rpc_url: Some(contract.rpc_url.clone()),
+                    contract_type: Some(contract.r#type.clone()),
+                };
+                // Not marked revealed when queueing fails, so the next check retries
+                match jobs::enqueue(client, &job).await {
+                    Ok(row) => {
+                        println!(
+                            "Reveal: [{}] {} revealed, queued metadata refresh job {}",
+                            contract.chain, contract.address, row.id
+                        );
+                        newly_revealed.push(key);
+                    }
+                    Err(e) => eprintln!(
+                        "Reveal: [{}] {} failed to queue metadata refresh: {}",
+                        contract.chain,
+                        contract.address,
+                        e.message()
+                    ),
+                }
+            }
+            Ok(false) => {}
+            Err(e) => eprintln!("Reveal: [{}] {}: {}", contract.chain, contract.address, e),
+        }
+    }
+    newly_revealed
+}
+
+/// Checks the unrevealed contracts every refresh, forever
+pub async fn run_watcher(database: Arc<Database>, config: RevealConfig) {
+    // Revealed contracts stop being watched until the next restart, they should be
+    // removed from the config
+    let mut revealed = HashSet::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.refresh_seconds.max(1)));
+    loop {
+        interval.tick().await;
+        let client = database.client().await;
+        revealed.extend(check_reveals(&client, &config, &revealed).await);
+    }
+}
+
+/// Spawns the reveal watcher when a reveal config is provided
+pub fn spawn_watcher(database: Arc<Database>) {
+    match RevealConfig::from_env() {
+        Some(Ok(config)) => {
+            tokio::spawn(run_watcher(database, config));
+        }
+        Some(Err(e)) => eprintln!("Reveal watcher disabled: {}", e),
+        None => {}
+    }
+}
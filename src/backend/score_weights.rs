@@ -0,0 +1,212 @@
+use crate::backend::cache_events;
+use crate::backend::errors::ApiError;
+use crate::backend::metadata_store::{RarityMap, METADATA_STORE};
+use crate::backend::projects::DEFAULT_PROJECT;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+/// How a collection's rarity scores are rescaled before its weight applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    None,
+    // The collection's average token scores 1, so collections with many trait values
+    // don't outweigh the others
+    Mean,
+}
+
+impl Normalization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Normalization::None => "none",
+            Normalization::Mean => "mean",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Normalization::None),
+            "mean" => Some(Normalization::Mean),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CollectionWeight {
+    weight: f64,
+    normalization: Normalization,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScoreWeightUpdate {
+    pub weight: f64,
+    #[serde(default = "default_normalization")]
+    pub normalization: Normalization,
+}
+
+fn default_normalization() -> Normalization {
+    Normalization::None
+}
+
+// (lowercased chain, lowercased contract) -> weight, mirror of the contracts' score columns.
+// Contracts left at weight 1 without normalization aren't kept.
+static WEIGHTS: Lazy<RwLock<HashMap<(String, String), CollectionWeight>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+// Mean score of the rarity map it was computed from, recomputed when the map is replaced
+type Means = HashMap<(String, String), (Arc<RarityMap>, f64)>;
+static MEANS: Lazy<Mutex<Means>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn key(chain_name: &str, contract_address: &str) -> (String, String) {
+    (chain_name.to_lowercase(), contract_address.to_lowercase())
+}
+
+/// Reloads the weights from the database
+pub async fn reload(client: &Client) -> Result<(), ApiError> {
+    let rows = queries::get_score_weights(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to load score weights: {}", e)))?;
+
+    *WEIGHTS.write().expect("Score weights lock poisoned") = rows
+        .into_iter()
+        .filter_map(|row| {
+            let normalization = Normalization::parse(&row.normalization).unwrap_or_else(|| {
+                eprintln!(
+                    "Unknown score normalization {} of {}, ignored",
+                    row.normalization, row.contract_address
+                );
+                Normalization::None
+            });
+            (row.weight != 1.0 || normalization != Normalization::None).then(|| {
+                (
+                    key(&row.chain, &row.contract_address),
+                    CollectionWeight {
+                        weight: row.weight,
+                        normalization,
+                    },
+                )
+            })
+        })
+        .collect();
+    Ok(())
+}
+
+fn mean_score(chain_name: &str, contract_address: &str, rarity_map: &Arc<RarityMap>) -> f64 {
+    let mut means = MEANS.lock().expect("Score means lock poisoned");
+    if let Some((_, mean)) = means
+        .get(&key(chain_name, contract_address))
+        .filter(|(map, _)| Arc::ptr_eq(map, rarity_map))
+    {
+        return *mean;
+    }
+    let mean = if rarity_map.is_empty() {
+        0.0
+    } else {
+        rarity_map.values().map(|(score, _, _)| score).sum::<f64>() / rarity_map.len() as f64
+    };
+    means.insert(
+        key(chain_name, contract_address),
+        (rarity_map.clone(), mean),
+    );
+    mean
+}
+
+/// What a collection's rarity scores are multiplied by in the leaderboard points, 1 unless
+/// a weight or a normalization is set on the contract
+pub fn factor(chain_name: &str, contract_address: &str, rarity_map: &Arc<RarityMap>) -> f64 {
+    let weight = match WEIGHTS
+        .read()
+        .expect("Score weights lock poisoned")
+        .get(&key(chain_name, contract_address))
+    {
+        Some(weight) => *weight,
+        None => return 1.0,
+    };
+    match weight.normalization {
+        Normalization::None => weight.weight,
+        Normalization::Mean => {
+            let mean = mean_score(chain_name, contract_address, rarity_map);
+            if mean > 0.0 {
+                weight.weight / mean
+            } else {
+                weight.weight
+            }
+        }
+    }
+}
+
+/// The weight and normalization of every collection of the leaderboard
+pub async fn handle_get_leaderboard_meta(client: Arc<Client>) -> Result<impl Reply, Rejection> {
+    let rows = queries::get_score_weights(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get score weights: {}", e)))?;
+
+    let mut collections = Vec::new();
+    for row in rows {
+        if !DEFAULT_PROJECT.includes(&row.chain, &row.contract_address) {
+            continue;
+        }
+        let rarity_map = METADATA_STORE
+            .rarity_map(&row.chain, &row.contract_address)
+            .await;
+        collections.push(json!({
+            "chain": row.chain,
+            "contract_address": row.contract_address,
+            "name": row.contract_name,
+            "weight": row.weight,
+            "normalization": row.normalization,
+            // Points of a token are rarity_score * balance * factor * 1000
+            "factor": factor(&row.chain, &row.contract_address, &rarity_map),
+        }));
+    }
+
+    Ok(warp::reply::json(&json!({ "collections": collections })).into_response())
+}
+
+pub async fn handle_set_score_weight(
+    chain_name: String,
+    contract_address: String,
+    body: ScoreWeightUpdate,
+    client: Arc<Client>,
+) -> Result<impl Reply, Rejection> {
+    if !body.weight.is_finite() || body.weight < 0.0 {
+        return Err(
+            ApiError::invalid_field("weight", format!("Invalid weight {}", body.weight)).into(),
+        );
+    }
+    let updated = queries::set_score_weight(
+        &client,
+        &chain_name,
+        &contract_address,
+        body.weight,
+        body.normalization.as_str(),
+    )
+    .await
+    .map_err(|e| ApiError::Upstream(format!("Failed to set score weight: {}", e)))?;
+    if !updated {
+        return Err(ApiError::NotFound(format!(
+            "No contract {} on {}",
+            contract_address, chain_name
+        ))
+        .into());
+    }
+    reload(&client).await?;
+    response_cache::invalidate_all();
+    cache_events::notify_scores_changed(&client).await;
+
+    Ok(warp::reply::json(&json!({
+        "chain": chain_name,
+        "contract_address": contract_address,
+        "weight": body.weight,
+        "normalization": body.normalization.as_str(),
+    }))
+    .into_response())
+}
@@ -0,0 +1,300 @@
+use crate::backend::api::{
+    get_or_update_all_users_collections, rank_leaderboard, LeaderboardType,
+    SCORE_SNAPSHOT_INTERVAL_SECONDS,
+};
+use crate::backend::errors::ApiError;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::common::file_loader::read_file;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+/// A season as defined in the seasons file (AFTERLIFE_PATH_SEASONS), dates in UTC, e.g.
+///
+/// ```yaml
+/// - number: 1
+///   name: Season of the Dead
+///   start: 2026-01-01
+///   end: 2026-04-01T12:00:00Z
+/// ```
+#[derive(Debug, Deserialize)]
+struct SeasonDefinition {
+    number: u32,
+    #[serde(default)]
+    name: Option<String>,
+    start: String,
+    end: String,
+}
+
+#[derive(Debug)]
+struct Season {
+    number: u32,
+    name: Option<String>,
+    // Unix timestamps
+    start: i64,
+    end: i64,
+}
+
+// Unix timestamp of a UTC date, 2026-01-01 or 2026-01-01T12:00:00Z
+fn parse_utc(value: &str) -> Option<i64> {
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, time.strip_suffix('Z')?),
+        None => (value, "00:00:00"),
+    };
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let time: Vec<i64> = time
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (year, month, day, hour, minute, second) = match (date.as_slice(), time.as_slice()) {
+        ([year, month, day], [hour, minute, second]) => {
+            (*year, *month, *day, *hour, *minute, *second)
+        }
+        _ => return None,
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..60).contains(&second)
+    {
+        return None;
+    }
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar, years starting in March
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+pub(crate) fn is_configured() -> bool {
+    env::var("AFTERLIFE_PATH_SEASONS").is_ok()
+}
+
+async fn load_seasons() -> Result<Vec<Season>, ApiError> {
+    let path = env::var("AFTERLIFE_PATH_SEASONS")
+        .map_err(|_| ApiError::Internal("Seasons are not configured".to_string()))?;
+    let contents = read_file(Path::new(&path))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to read seasons file: {}", e)))?;
+    let definitions: Vec<SeasonDefinition> = serde_yaml::from_str(&contents)
+        .map_err(|e| ApiError::Internal(format!("Invalid seasons file: {}", e)))?;
+
+    definitions
+        .into_iter()
+        .map(|definition| {
+            let date = |value: &str| {
+                parse_utc(value).ok_or_else(|| {
+                    ApiError::Internal(format!(
+                        "Invalid date {} of season {}, expected e.g. 2026-01-01 or \
+                        2026-01-01T00:00:00Z",
+                        value, definition.number
+                    ))
+                })
+            };
+            let (start, end) = (date(&definition.start)?, date(&definition.end)?);
+            if start >= end {
+                return Err(ApiError::Internal(format!(
+                    "Season {} ends before it starts",
+                    definition.number
+                )));
+            }
+            Ok(Season {
+                number: definition.number,
+                name: definition.name.clone(),
+                start,
+                end,
+            })
+        })
+        .collect()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+// Points gained since the snapshot at the start of the season, ranked. Users who held
+// nothing at the start gain all their points, those who sold everything lose theirs.
+fn season_standings(
+    start: &HashMap<String, (f64, i32)>,
+    end: &LeaderboardType,
+) -> Vec<(String, f64, usize)> {
+    let mut gains: LeaderboardType = end
+        .iter()
+        .map(|(name, points)| {
+            let before = start.get(name).map_or(0.0, |(points, _)| *points);
+            (name.clone(), points - before)
+        })
+        .collect();
+    for (name, (points, _)) in start {
+        gains.entry(name.clone()).or_insert(-points);
+    }
+    gains.retain(|_, points| *points != 0.0);
+    rank_leaderboard(gains)
+}
+
+// How far from the start or end of a season its snapshots may be taken. A snapshot is
+// recorded every SCORE_SNAPSHOT_INTERVAL_SECONDS while the leaderboard updates, a gap
+// longer than that means the updater was down.
+const SNAPSHOT_TOLERANCE_SECONDS: i64 = 2 * SCORE_SNAPSHOT_INTERVAL_SECONDS as i64;
+
+// Standings of a running season against the current leaderboard, of an ended one against
+// the snapshot taken when it ended. Without a snapshot close enough to the start (or the end
+// of an ended season) the gains can't be told, the standings are refused rather than
+// guessed.
+async fn compute_standings(
+    client: &Client,
+    season: &Season,
+) -> Result<Vec<(String, f64, usize)>, ApiError> {
+    let snapshot = |at: i64, bound: &'static str| async move {
+        let snapshot = queries::get_score_snapshot_at(client, at, SNAPSHOT_TOLERANCE_SECONDS)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get score history: {}", e)))?;
+        if snapshot.is_empty() {
+            return Err(ApiError::Internal(format!(
+                "No leaderboard snapshot within {}s of the {} of season {}",
+                SNAPSHOT_TOLERANCE_SECONDS, bound, season.number
+            )));
+        }
+        Ok(snapshot)
+    };
+    let start = snapshot(season.start, "start").await?;
+    let end: LeaderboardType = if season.end <= now() {
+        snapshot(season.end, "end")
+            .await?
+            .into_iter()
+            .map(|(name, (points, _))| (name, points))
+            .collect()
+    } else {
+        get_or_update_all_users_collections(client, false).await?
+    };
+    Ok(season_standings(&start, &end))
+}
+
+/// Records the final standings of the seasons that ended and have none yet. Run by the
+/// replica leading the leaderboard refresh, after each update.
+pub(crate) async fn record_ended_seasons(client: &Client) {
+    if !is_configured() {
+        return;
+    }
+    let seasons = match load_seasons().await {
+        Ok(seasons) => seasons,
+        Err(e) => {
+            eprintln!("{}", e.message());
+            return;
+        }
+    };
+
+    for season in seasons.iter().filter(|season| season.end <= now()) {
+        let recorded = queries::get_season_results(client, season.number as i32).await;
+        match recorded {
+            Ok(results) if !results.is_empty() => continue,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Failed to get results of season {}: {}", season.number, e);
+                continue;
+            }
+        }
+
+        let standings: Vec<(String, f64, i32)> = match compute_standings(client, season).await {
+            Ok(standings) => standings
+                .into_iter()
+                .map(|(name, points, rank)| (name, points, rank as i32))
+                .collect(),
+            Err(e) => {
+                // Retried after the next update, snapshots missing for good need fixing
+                // by hand
+                eprintln!(
+                    "Not finalizing season {}, failed to compute its standings: {}",
+                    season.number,
+                    e.message()
+                );
+                continue;
+            }
+        };
+        match queries::store_season_results(client, season.number as i32, &standings).await {
+            Ok(true) => {
+                println!(
+                    "Recorded final standings of season {}, {} users",
+                    season.number,
+                    standings.len()
+                );
+                response_cache::invalidate(&format!("leaderboard/season/{}", season.number)).await;
+            }
+            Ok(false) => {}
+            Err(e) => eprintln!("Failed to record season {}: {}", season.number, e),
+        }
+    }
+}
+
+pub async fn handle_get_season_leaderboard(
+    number: u32,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!("leaderboard/season/{}", number);
+    let response =
+        response_cache::get_or_compute(cache_key, build_season_leaderboard(number, &client))
+            .await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+async fn build_season_leaderboard(number: u32, client: &Client) -> Result<Value, ApiError> {
+    let seasons = load_seasons().await?;
+    let season = seasons
+        .iter()
+        .find(|season| season.number == number)
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown season {}", number)))?;
+
+    let now = now();
+    let (status, standings) = if season.start > now {
+        ("upcoming", Vec::new())
+    } else {
+        let recorded = queries::get_season_results(client, number as i32)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get season results: {}", e)))?;
+        if !recorded.is_empty() {
+            ("final", recorded)
+        } else {
+            let standings = compute_standings(client, season)
+                .await?
+                .into_iter()
+                .map(|(name, points, rank)| queries::SeasonResult {
+                    name,
+                    points,
+                    rank: rank as i32,
+                })
+                .collect();
+            // Ended seasons are final once the leaderboard updater recorded them
+            let status = if season.end <= now { "ended" } else { "live" };
+            (status, standings)
+        }
+    };
+
+    Ok(json!({
+        "season": season.number,
+        "name": season.name,
+        "start": season.start,
+        "end": season.end,
+        "status": status,
+        "standings": standings,
+    }))
+}
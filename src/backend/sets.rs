@@ -0,0 +1,96 @@
+use crate::backend::errors::ApiError;
+use crate::backend::holdings::{contract_holdings, load_user_holdings};
+use crate::backend::response_cache;
+use crate::backend::usernames::get_all_addresses_for_name;
+use crate::common::file_loader::read_file;
+use crate::common::numeric::TokenId;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+/// A collectible set as defined in the sets file (AFTERLIFE_PATH_SETS), e.g.
+///
+/// ```yaml
+/// - id: genesis
+///   name: Genesis
+///   chain: fantom
+///   contract: "0x..."
+///   token_ids: [1, 2, 3]
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct SetDefinition {
+    pub id: String,
+    pub name: String,
+    pub chain: String,
+    pub contract: String,
+    pub token_ids: Vec<TokenId>,
+}
+
+async fn load_set_definitions() -> Result<Vec<SetDefinition>, ApiError> {
+    let path = env::var("AFTERLIFE_PATH_SETS")
+        .map_err(|_| ApiError::Internal("Sets are not configured".to_string()))?;
+    let contents = read_file(Path::new(&path))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to read sets file: {}", e)))?;
+    serde_yaml::from_str(&contents)
+        .map_err(|e| ApiError::Internal(format!("Invalid sets file: {}", e)))
+}
+
+pub async fn handle_get_user_sets(
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let cache_key = format!("user/sets/{}", username);
+    let response =
+        response_cache::get_or_compute(cache_key, build_user_sets(&username, &client)).await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+async fn build_user_sets(username: &str, client: &Client) -> Result<Value, ApiError> {
+    let user_addresses = get_all_addresses_for_name(username).await?;
+    if user_addresses.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)));
+    }
+    let definitions = load_set_definitions().await?;
+    let holdings = load_user_holdings(client, &user_addresses).await?;
+
+    let mut sets = Vec::with_capacity(definitions.len());
+    for definition in &definitions {
+        let owned_tokens = contract_holdings(&holdings, &definition.chain, &definition.contract);
+        let (owned, missing): (Vec<TokenId>, Vec<TokenId>) =
+            definition.token_ids.iter().copied().partition(|token_id| {
+                owned_tokens.is_some_and(|tokens| tokens.contains_key(token_id))
+            });
+
+        let total = definition.token_ids.len();
+        let completion = if total == 0 {
+            0.0
+        } else {
+            (owned.len() as f64 * 10000.0 / total as f64).round() / 100.0
+        };
+
+        sets.push(json!({
+            "id": definition.id,
+            "name": definition.name,
+            "chain": definition.chain,
+            "contract_address": definition.contract,
+            "owned_count": owned.len(),
+            "total": total,
+            "completion": completion,
+            "complete": total > 0 && missing.is_empty(),
+            "owned": owned,
+            "missing": missing,
+        }));
+    }
+
+    Ok(json!({
+        "username": username,
+        "sets": sets,
+    }))
+}
@@ -0,0 +1,317 @@
+use crate::backend::api::{get_or_update_all_users_collections, rank_leaderboard};
+use crate::backend::errors::ApiError;
+use crate::backend::projects::DEFAULT_PROJECT;
+use crate::backend::queries::{self, TeamRow};
+use crate::backend::response_cache;
+use crate::backend::usernames::signed_in_username;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+const MAX_TEAM_NAME_LENGTH: usize = 40;
+// Bounds `?top=`, each value being cached apart
+const MAX_COUNTED_MEMBERS: usize = 100;
+// Like the leaderboard, team changes show once the cached response expires, within a minute
+const TEAM_LEADERBOARD_CACHE_KEY: &str = "leaderboard/teams";
+
+#[derive(Debug, Deserialize)]
+pub struct NewTeam {
+    pub name: String,
+    // Only read from admins, teams users create are always self-service
+    #[serde(default)]
+    pub self_service: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TeamLeaderboardQuery {
+    // Only the best `top` members of each team count, every member when not set or 0
+    top: Option<usize>,
+}
+
+fn validate_team_name(name: &str) -> Result<String, ApiError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_TEAM_NAME_LENGTH {
+        return Err(ApiError::invalid_field(
+            "name",
+            format!(
+                "Team names must be 1 to {} characters",
+                MAX_TEAM_NAME_LENGTH
+            ),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+async fn find_team(client: &Client, id: i32) -> Result<TeamRow, ApiError> {
+    queries::get_team(client, id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get team: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown team {}", id)))
+}
+
+async fn create_team(
+    client: &Client,
+    name: &str,
+    self_service: bool,
+    created_by: Option<&str>,
+) -> Result<i32, ApiError> {
+    let name = validate_team_name(name)?;
+    queries::create_team(client, &name, self_service, created_by)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to create team: {}", e)))?
+        .ok_or_else(|| ApiError::BadRequest(format!("Team name {} is taken", name)))
+}
+
+async fn add_member(client: &Client, team_id: i32, username: &str) -> Result<(), ApiError> {
+    let added = queries::add_team_member(client, team_id, username)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to add team member: {}", e)))?;
+    if !added {
+        return Err(ApiError::BadRequest(format!(
+            "{} is already in a team",
+            username
+        )));
+    }
+    Ok(())
+}
+
+async fn remove_member(client: &Client, team_id: i32, username: &str) -> Result<(), ApiError> {
+    let removed = queries::remove_team_member(client, team_id, username)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to remove team member: {}", e)))?;
+    if !removed {
+        return Err(ApiError::NotFound(format!(
+            "{} is not in team {}",
+            username, team_id
+        )));
+    }
+    Ok(())
+}
+
+pub async fn handle_list_teams(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let teams = queries::get_teams(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to list teams: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({ "teams": teams })).into_response())
+}
+
+pub async fn handle_get_team_leaderboard(
+    query: TeamLeaderboardQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let top = query
+        .top
+        .filter(|top| *top > 0)
+        .map(|top| top.min(MAX_COUNTED_MEMBERS));
+    let cache_key = match top {
+        Some(top) => format!("{}?top={}", TEAM_LEADERBOARD_CACHE_KEY, top),
+        None => TEAM_LEADERBOARD_CACHE_KEY.to_string(),
+    };
+    let response =
+        response_cache::get_or_compute(cache_key, build_team_leaderboard(&client, top)).await?;
+
+    Ok(warp::reply::json(&*response).into_response())
+}
+
+// Teams ranked by the summed points of their best `top` members, from the default
+// leaderboard. Members off the leaderboard (excluded, or holding nothing) score 0.
+async fn build_team_leaderboard(client: &Client, top: Option<usize>) -> Result<Value, ApiError> {
+    let teams = queries::get_teams(client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to list teams: {}", e)))?;
+    let leaderboard = get_or_update_all_users_collections(client, false).await?;
+
+    let mut members_by_team: HashMap<String, Vec<(String, f64)>> = HashMap::new();
+    let mut team_points = HashMap::new();
+    for team in teams {
+        let mut members: Vec<(String, f64)> = team
+            .members
+            .into_iter()
+            .map(|username| {
+                let points = leaderboard.get(&username).copied().unwrap_or(0.0);
+                (username, points)
+            })
+            .collect();
+        members.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        let counted = top.unwrap_or(members.len());
+        team_points.insert(
+            team.name.clone(),
+            members.iter().take(counted).map(|(_, points)| points).sum(),
+        );
+        members_by_team.insert(team.name, members);
+    }
+
+    let teams: Vec<Value> = rank_leaderboard(team_points)
+        .into_iter()
+        .map(|(name, points, rank)| {
+            let members = members_by_team.remove(&name).unwrap_or_default();
+            let counted = top.unwrap_or(members.len());
+            json!({
+                "rank": rank,
+                "name": name,
+                "points": points,
+                "members": members
+                    .into_iter()
+                    .enumerate()
+                    .map(|(position, (username, points))| json!({
+                        "username": username,
+                        "points": points,
+                        "counted": position < counted,
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    Ok(json!({ "top": top, "teams": teams }))
+}
+
+pub async fn handle_get_own_team(
+    address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let team = queries::get_user_team(&client, &username)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get team: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({ "username": username, "team": team })).into_response())
+}
+
+// Creates a self-service team with the signed-in user as its first member
+pub async fn handle_create_own_team(
+    address: String,
+    body: NewTeam,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let current = queries::get_user_team(&client, &username)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get team: {}", e)))?;
+    if let Some(team) = current {
+        return Err(ApiError::BadRequest(format!("Already in team {}", team.name)).into());
+    }
+
+    let id = create_team(&client, &body.name, true, Some(&username)).await?;
+    if let Err(e) = add_member(&client, id, &username).await {
+        // Joined another team in the meantime, don't leave an empty team behind
+        if let Err(e) = queries::delete_team(&client, id).await {
+            eprintln!("Failed to delete empty team {}: {}", id, e);
+        }
+        return Err(e.into());
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "team": find_team(&client, id).await? })),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+pub async fn handle_join_team(
+    team_id: i32,
+    address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let team = find_team(&client, team_id).await?;
+    if !team.self_service {
+        return Err(
+            ApiError::BadRequest(format!("Team {} is managed by the admins", team.name)).into(),
+        );
+    }
+    add_member(&client, team_id, &username).await?;
+
+    Ok(warp::reply::json(&json!({ "team": find_team(&client, team_id).await? })).into_response())
+}
+
+pub async fn handle_leave_team(
+    address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    let username = signed_in_username(&users, &address)?;
+    let team = queries::get_user_team(&client, &username)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get team: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("{} is not in a team", username)))?;
+    if !team.self_service {
+        return Err(
+            ApiError::BadRequest(format!("Team {} is managed by the admins", team.name)).into(),
+        );
+    }
+    remove_member(&client, team.id, &username).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
+
+pub async fn handle_admin_create_team(
+    body: NewTeam,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let id = create_team(&client, &body.name, body.self_service, None).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "team": find_team(&client, id).await? })),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+pub async fn handle_admin_delete_team(
+    id: i32,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let deleted = queries::delete_team(&client, id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to delete team: {}", e)))?;
+    if !deleted {
+        return Err(ApiError::NotFound(format!("Unknown team {}", id)).into());
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
+
+pub async fn handle_admin_add_member(
+    team_id: i32,
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let users = DEFAULT_PROJECT.users().await?;
+    if !users.contains_key(&username) {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)).into());
+    }
+    find_team(&client, team_id).await?;
+    add_member(&client, team_id, &username).await?;
+
+    Ok(warp::reply::json(&json!({ "team": find_team(&client, team_id).await? })).into_response())
+}
+
+pub async fn handle_admin_remove_member(
+    team_id: i32,
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    remove_member(&client, team_id, &username).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
@@ -0,0 +1,105 @@
+use crate::backend::errors::ApiError;
+use once_cell::sync::Lazy;
+use std::env;
+use std::future::Future;
+use std::time::Duration;
+use warp::reject::Rejection;
+
+const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+const DEFAULT_EXPORT_TIMEOUT_SECONDS: u64 = 60;
+
+/// Time a handler has to produce its response, AFTERLIFE_REQUEST_TIMEOUT_SECONDS
+pub(crate) static REQUEST_TIMEOUT: Lazy<Duration> =
+    Lazy::new(|| timeout_from_env("AFTERLIFE_REQUEST_TIMEOUT_SECONDS", DEFAULT_TIMEOUT_SECONDS));
+/// Same for the routes answering with a whole collection or a file,
+/// AFTERLIFE_EXPORT_TIMEOUT_SECONDS
+pub(crate) static EXPORT_TIMEOUT: Lazy<Duration> = Lazy::new(|| {
+    timeout_from_env(
+        "AFTERLIFE_EXPORT_TIMEOUT_SECONDS",
+        DEFAULT_EXPORT_TIMEOUT_SECONDS,
+    )
+});
+
+fn timeout_from_env(name: &str, default_seconds: u64) -> Duration {
+    let seconds = match env::var(name) {
+        Ok(value) => match value.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => seconds,
+            _ => {
+                eprintln!("Invalid {} {}, using {}s", name, value, default_seconds);
+                default_seconds
+            }
+        },
+        Err(_) => default_seconds,
+    };
+    Duration::from_secs(seconds)
+}
+
+/// Runs `handler`, answering 504 instead when it takes longer than `limit`. The handler
+/// future is dropped then, so whatever query or read it was waiting on is abandoned.
+pub async fn within<T>(
+    limit: Duration,
+    handler: impl Future<Output = Result<T, Rejection>>,
+) -> Result<T, Rejection> {
+    match tokio::time::timeout(limit, handler).await {
+        Ok(result) => result,
+        Err(_) => Err(ApiError::Timeout(format!("No response within {}s", limit.as_secs())).into()),
+    }
+}
+
+/// Runs `handler` on a task of its own, answering 504 when it takes longer than `limit`
+/// without cancelling it. For the handlers that change state, which must not stop halfway
+/// through their writes.
+pub async fn detached_within<T: Send + 'static>(
+    limit: Duration,
+    handler: impl Future<Output = Result<T, Rejection>> + Send + 'static,
+) -> Result<T, Rejection> {
+    match tokio::time::timeout(limit, tokio::spawn(handler)).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(ApiError::Internal(format!("Handler failed: {}", e)).into()),
+        Err(_) => Err(ApiError::Timeout(format!(
+            "No response within {}s, the change is still being applied",
+            limit.as_secs()
+        ))
+        .into()),
+    }
+}
+
+/// Wraps a handler for `and_then`, naming the values the filter extracts:
+/// `timed!(handler, a, b)` runs `handler(a, b)` within REQUEST_TIMEOUT
+macro_rules! timed {
+    ($handler:expr $(, $arg:ident)*) => {
+        move |$($arg),*| {
+            $crate::backend::timeouts::within(
+                *$crate::backend::timeouts::REQUEST_TIMEOUT,
+                $handler($($arg),*),
+            )
+        }
+    };
+}
+pub(crate) use timed;
+
+/// `timed!` within EXPORT_TIMEOUT
+macro_rules! timed_export {
+    ($handler:expr $(, $arg:ident)*) => {
+        move |$($arg),*| {
+            $crate::backend::timeouts::within(
+                *$crate::backend::timeouts::EXPORT_TIMEOUT,
+                $handler($($arg),*),
+            )
+        }
+    };
+}
+pub(crate) use timed_export;
+
+/// `timed!` for the handlers that change state, see detached_within
+macro_rules! timed_write {
+    ($handler:expr $(, $arg:ident)*) => {
+        move |$($arg),*| {
+            $crate::backend::timeouts::detached_within(
+                *$crate::backend::timeouts::REQUEST_TIMEOUT,
+                $handler($($arg),*),
+            )
+        }
+    };
+}
+pub(crate) use timed_write;
@@ -0,0 +1,125 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
+use warp::http::Uri;
+use warp::reject::Rejection;
+use warp::{Filter, Reply};
+
+const DEFAULT_TLS_ADDRESS: &str = "0.0.0.0:443";
+
+/// Native TLS for deployments without a reverse proxy in front of the API, from
+/// AFTERLIFE_TLS_CERT and AFTERLIFE_TLS_KEY (PEM files), served on AFTERLIFE_TLS_ADDRESS
+/// (default 0.0.0.0:443). With AFTERLIFE_TLS_REDIRECT_ADDRESS set (0.0.0.0:80 usually),
+/// plain HTTP requests there are redirected to HTTPS.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub address: SocketAddr,
+    pub redirect_address: Option<SocketAddr>,
+}
+
+impl TlsConfig {
+    pub fn from_env() -> Option<Result<Self, String>> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let cert_path = var("AFTERLIFE_TLS_CERT");
+        let key_path = var("AFTERLIFE_TLS_KEY");
+        if cert_path.is_none() && key_path.is_none() {
+            return None;
+        }
+        Some(Self::parse(
+            cert_path,
+            key_path,
+            var("AFTERLIFE_TLS_ADDRESS"),
+            var("AFTERLIFE_TLS_REDIRECT_ADDRESS"),
+        ))
+    }
+
+    fn parse(
+        cert_path: Option<String>,
+        key_path: Option<String>,
+        address: Option<String>,
+        redirect_address: Option<String>,
+    ) -> Result<Self, String> {
+        let (cert_path, key_path) = match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            _ => return Err("AFTERLIFE_TLS_CERT and AFTERLIFE_TLS_KEY go together".to_string()),
+        };
+        // warp panics on files it can't read, check them up front
+        for path in [&cert_path, &key_path] {
+            if !Path::new(path).is_file() {
+                return Err(format!("No TLS file {}", path));
+            }
+        }
+        let parse_address = |address: &str| {
+            address
+                .parse::<SocketAddr>()
+                .map_err(|_| format!("Invalid address {}", address))
+        };
+        Ok(TlsConfig {
+            cert_path,
+            key_path,
+            address: parse_address(address.as_deref().unwrap_or(DEFAULT_TLS_ADDRESS))?,
+            redirect_address: redirect_address.as_deref().map(parse_address).transpose()?,
+        })
+    }
+}
+
+/// Permanently redirects every request to the same path and query over HTTPS on
+/// `https_port`, on the host the request was made to
+pub fn redirect_to_https(
+    https_port: u16,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("host")
+        .and(warp::path::full())
+        .and(
+            warp::query::raw()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .map(
+            move |host: Option<String>, path: warp::path::FullPath, query: Option<String>| {
+                let host = match host {
+                    Some(host) => host,
+                    None => {
+                        return warp::reply::with_status(
+                            "Missing Host header",
+                            warp::http::StatusCode::BAD_REQUEST,
+                        )
+                        .into_response()
+                    }
+                };
+                // The port the request came in on is dropped, HTTPS has its own
+                let hostname = match host.rsplit_once(':') {
+                    Some((hostname, port)) if port.chars().all(|c| c.is_ascii_digit()) => hostname,
+                    _ => host.as_str(),
+                };
+                let authority = if https_port == 443 {
+                    hostname.to_string()
+                } else {
+                    format!("{}:{}", hostname, https_port)
+                };
+                let location = match query {
+                    Some(query) => format!("https://{}{}?{}", authority, path.as_str(), query),
+                    None => format!("https://{}{}", authority, path.as_str()),
+                };
+                match location.parse::<Uri>() {
+                    Ok(uri) => warp::redirect::permanent(uri).into_response(),
+                    Err(_) => warp::reply::with_status(
+                        "Invalid Host header",
+                        warp::http::StatusCode::BAD_REQUEST,
+                    )
+                    .into_response(),
+                }
+            },
+        )
+}
+
+/// Serves the HTTP to HTTPS redirects on `address`
+pub async fn serve_redirects(address: SocketAddr, https_port: u16) {
+    println!("Redirecting HTTP on {} to HTTPS", address);
+    warp::serve(redirect_to_https(https_port))
+        .run(address)
+        .await;
+}
@@ -0,0 +1,198 @@
+use crate::backend::api::get_or_update_all_users_collections;
+use crate::backend::cache_events;
+use crate::backend::errors::ApiError;
+use crate::backend::exclusions;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::backend::usernames::{validate_username, UsersData};
+use crate::common::database;
+use crate::common::file_loader::{load_users_data_from, save_users_data_to, users_file_from_env};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+// Users file edits are read-modify-write, one at a time
+static USERS_FILE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, Deserialize)]
+pub struct RenameUser {
+    pub new_username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeUsers {
+    // The user that remains, `username` is folded into it
+    pub into: String,
+}
+
+/// Renames a user in the users file and in every table keyed by username
+pub async fn handle_rename_user(
+    username: String,
+    body: RenameUser,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let new_username = body.new_username.trim().to_string();
+    let _guard = USERS_FILE_LOCK.lock().await;
+    let path = users_file_from_env();
+    let mut users = load_users(&path).await?;
+
+    let addresses = users
+        .remove(&username)
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown user {}", username)))?;
+    validate_username(&users, &new_username).map_err(|e| e.into_api_error(&new_username))?;
+    users.insert(new_username.clone(), addresses.clone());
+
+    let change = UserChange::Rename {
+        from: &username,
+        to: &new_username,
+    };
+    apply(&path, &users, change, client).await?;
+
+    Ok(warp::reply::json(&json!({
+        "username": new_username,
+        "addresses": addresses,
+    }))
+    .into_response())
+}
+
+/// Folds a user into another: its addresses, score history, discord account, webhooks
+/// and exclusions move to the remaining user
+pub async fn handle_merge_users(
+    username: String,
+    body: MergeUsers,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    if body.into.trim().is_empty() {
+        return Err(
+            ApiError::invalid_field("into", "Username must not be empty".to_string()).into(),
+        );
+    }
+    if username == body.into {
+        return Err(
+            ApiError::invalid_field("into", "Can't merge a user into itself".to_string()).into(),
+        );
+    }
+    let _guard = USERS_FILE_LOCK.lock().await;
+    let path = users_file_from_env();
+    let mut users = load_users(&path).await?;
+
+    let merged = users
+        .remove(&username)
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown user {}", username)))?;
+    let addresses = users
+        .get_mut(&body.into)
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown user {}", body.into)))?;
+    for address in merged {
+        if !addresses.iter().any(|a| a.eq_ignore_ascii_case(&address)) {
+            addresses.push(address);
+        }
+    }
+    let addresses = addresses.clone();
+
+    let change = UserChange::Merge {
+        from: &username,
+        into: &body.into,
+    };
+    apply(&path, &users, change, client).await?;
+
+    Ok(warp::reply::json(&json!({
+        "username": body.into,
+        "addresses": addresses,
+    }))
+    .into_response())
+}
+
+async fn load_users(path: &str) -> Result<UsersData, ApiError> {
+    load_users_data_from(path).await.map_err(ApiError::Internal)
+}
+
+// Applies the change to every table keyed by username, in one transaction
+async fn update_records(db_client: &mut Client, change: &UserChange<'_>) -> Result<(), ApiError> {
+    let transaction = db_client
+        .transaction()
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to start transaction: {}", e)))?;
+    match *change {
+        UserChange::Rename { from, to } => {
+            queries::rename_user_records(&transaction, from, to).await
+        }
+        UserChange::Merge { from, into } => {
+            queries::merge_user_records(&transaction, from, into).await
+        }
+    }
+    .map_err(|e| ApiError::Upstream(format!("Failed to update user records: {}", e)))?;
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to commit user records: {}", e)))
+}
+
+enum UserChange<'a> {
+    Rename { from: &'a str, to: &'a str },
+    Merge { from: &'a str, into: &'a str },
+}
+
+// Updates the tables in a transaction on a dedicated connection, as the shared client
+// can't hold one, and replaces the users file once it's committed. Caches are refreshed
+// afterwards, the leaderboard is recomputed and stored for the other replicas.
+async fn apply(
+    path: &str,
+    users: &UsersData,
+    change: UserChange<'_>,
+    client: Arc<Client>,
+) -> Result<(), ApiError> {
+    let mut db_client = database::connect()
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to connect to database: {}", e)))?;
+    update_records(&mut db_client, &change).await?;
+
+    if let Err(e) = save_users_data_to(path, users).await {
+        return Err(match change {
+            // Renamed back, the tables match the users file again
+            UserChange::Rename { from, to } => {
+                let undo = UserChange::Rename { from: to, to: from };
+                match update_records(&mut db_client, &undo).await {
+                    Ok(()) => ApiError::Internal(e),
+                    Err(undo_error) => {
+                        eprintln!(
+                            "Failed to rename {} back to {} after the users file write failed: {:?}",
+                            to, from, undo_error
+                        );
+                        ApiError::Internal(format!(
+                            "{}, the database already uses {}, retry the request",
+                            e, to
+                        ))
+                    }
+                }
+            }
+            // Can't be undone, merging again moves nothing more and updates the file
+            UserChange::Merge { from, into } => {
+                eprintln!(
+                    "Merged {} into {} in the database but not in the users file: {}",
+                    from, into, e
+                );
+                ApiError::Internal(format!(
+                    "{}, the database is already merged, retry the request",
+                    e
+                ))
+            }
+        });
+    }
+
+    response_cache::invalidate_all();
+    cache_events::notify_scores_changed(&client).await;
+    if let Err(e) = exclusions::reload(&client).await {
+        eprintln!("{:?}", e);
+    }
+    tokio::spawn(async move {
+        if let Err(e) = get_or_update_all_users_collections(&client, true).await {
+            eprintln!("Failed to refresh leaderboard after user change: {:?}", e);
+        }
+    });
+    Ok(())
+}
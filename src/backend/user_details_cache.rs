@@ -0,0 +1,173 @@
+use crate::backend::api::build_user_details;
+use crate::backend::projects::DEFAULT_PROJECT;
+use crate::backend::queries;
+use crate::backend::response_cache;
+use crate::common::database::Database;
+use futures::stream::{self, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_postgres::Client;
+
+// Documents older than this were left by a warmer that stopped (no leader, failing runs),
+// they're computed on request instead
+const MAX_DOCUMENT_AGE: Duration = Duration::from_secs(15 * 60);
+const WARM_CONCURRENCY: usize = 8;
+// Documents stored per statement
+const WARM_BATCH_SIZE: usize = 100;
+// Every document is recomputed this often, for what changes without a notification (users
+// file edited by hand, rarities); well within MAX_DOCUMENT_AGE
+const FULL_WARM_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Warms documents in its own task, so the leaderboard refresh doesn't wait on it.
+/// Requests made while a warm runs are merged into the next one.
+pub struct Warmer {
+    // Users whose documents are due, None once every user's is
+    due: Mutex<Option<HashSet<String>>>,
+    wake: Notify,
+}
+
+impl Warmer {
+    pub fn spawn(database: Arc<Database>) -> Arc<Warmer> {
+        let warmer = Arc::new(Warmer {
+            due: Mutex::new(Some(HashSet::new())),
+            wake: Notify::new(),
+        });
+        let task_warmer = warmer.clone();
+        tokio::spawn(async move {
+            let mut last_full_warm: Option<Instant> = None;
+            loop {
+                task_warmer.wake.notified().await;
+                let mut due = task_warmer
+                    .due
+                    .lock()
+                    .expect("Warmer lock poisoned")
+                    .replace(HashSet::new());
+                if last_full_warm.is_none_or(|at| at.elapsed() >= FULL_WARM_PERIOD) {
+                    due = None;
+                }
+                if due.as_ref().is_some_and(HashSet::is_empty) {
+                    continue;
+                }
+                let client = database.client().await;
+                if warm(&client, due.as_ref()).await && due.is_none() {
+                    last_full_warm = Some(Instant::now());
+                }
+            }
+        });
+        warmer
+    }
+
+    /// Asks for the documents of `usernames` to be warmed, every user's when None
+    pub fn request(&self, usernames: Option<HashSet<String>>) {
+        {
+            let mut due = self.due.lock().expect("Warmer lock poisoned");
+            match (&mut *due, usernames) {
+                (Some(due), Some(usernames)) => due.extend(usernames),
+                (due, _) => *due = None,
+            }
+        }
+        self.wake.notify_one();
+    }
+}
+
+/// Precomputes into user_profiles_cache the /user/level documents of the registered users
+/// of the default project in `only`, of all of them when None. Returns false when the
+/// users couldn't be read.
+pub async fn warm(client: &Client, only: Option<&HashSet<String>>) -> bool {
+    let users = match DEFAULT_PROJECT.users().await {
+        Ok(users) => users,
+        Err(e) => {
+            eprintln!("Failed to warm user details: {}", e.message());
+            return false;
+        }
+    };
+    let registered: Vec<String> = users.into_keys().collect();
+    let usernames: Vec<String> = match only {
+        Some(only) => registered
+            .iter()
+            .filter(|username| only.contains(*username))
+            .cloned()
+            .collect(),
+        None => registered.clone(),
+    };
+    let started = Instant::now();
+
+    let mut documents = stream::iter(&usernames)
+        .map(|username| async move {
+            let document = build_user_details(&DEFAULT_PROJECT, username, client).await;
+            (username, document)
+        })
+        .buffer_unordered(WARM_CONCURRENCY)
+        .filter_map(|(username, document)| async move {
+            match document {
+                Ok(document) => Some((username.clone(), document)),
+                Err(e) => {
+                    eprintln!(
+                        "Failed to warm user details of {}: {}",
+                        username,
+                        e.message()
+                    );
+                    None
+                }
+            }
+        })
+        .chunks(WARM_BATCH_SIZE)
+        .boxed();
+    let mut warmed = 0;
+    while let Some(batch) = documents.next().await {
+        if let Err(e) = queries::store_user_details_documents(client, &batch).await {
+            eprintln!("Failed to store warmed user details: {}", e);
+            continue;
+        }
+        for (username, _) in &batch {
+            response_cache::invalidate(&format!("user/level/{}", username)).await;
+        }
+        warmed += batch.len();
+    }
+    if let Err(e) = queries::prune_user_details_documents(client, &registered).await {
+        eprintln!("Failed to prune warmed user details: {}", e);
+    }
+    println!(
+        "Warmed user details of {}/{} users in {:.1}s",
+        warmed,
+        usernames.len(),
+        started.elapsed().as_secs_f64()
+    );
+    true
+}
+
+/// Drops the warmed documents of `usernames`, every one when None, they're computed on
+/// request until warmed again
+pub async fn drop_documents(client: &Client, usernames: Option<&HashSet<String>>) {
+    let result = match usernames {
+        Some(usernames) if usernames.is_empty() => return,
+        Some(usernames) => {
+            let usernames: Vec<String> = usernames.iter().cloned().collect();
+            queries::delete_user_details_documents(client, &usernames).await
+        }
+        None => queries::delete_all_user_details_documents(client).await,
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to drop warmed user details: {}", e);
+    }
+}
+
+/// The warmed /user/level document of a user of the default project, with its
+/// computed_at in epoch seconds. None when there's none recent enough.
+pub async fn get(client: &Client, username: &str) -> Option<Value> {
+    match queries::get_user_details_document(client, username, MAX_DOCUMENT_AGE.as_secs_f64()).await
+    {
+        Ok(Some((mut document, computed_at))) => {
+            document["computed_at"] = json!(computed_at);
+            Some(document)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("Failed to get warmed user details of {}: {}", username, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::backend::api::get_or_update_all_users_collections;
+use crate::backend::avatars;
+use crate::backend::errors::ApiError;
+use crate::backend::levels::points_to_level;
+use crate::backend::projects::DEFAULT_PROJECT;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+const MAX_SEARCH_LIMIT: usize = 50;
+const MAX_QUERY_LENGTH: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub struct UserSearchQuery {
+    q: String,
+    limit: Option<usize>,
+}
+
+// Usernames containing `query`, case-insensitively: those starting with it first, then
+// alphabetically
+fn matching_usernames<'a>(
+    usernames: impl Iterator<Item = &'a String>,
+    query: &str,
+    limit: usize,
+) -> Vec<&'a String> {
+    let query = query.to_lowercase();
+    let mut matches: Vec<(bool, String, &String)> = usernames
+        .filter_map(|username| {
+            let lowercase = username.to_lowercase();
+            let position = lowercase.find(&query)?;
+            Some((position != 0, lowercase, username))
+        })
+        .collect();
+    matches.sort();
+    matches
+        .into_iter()
+        .take(limit)
+        .map(|(_, _, username)| username)
+        .collect()
+}
+
+/// Registered users whose name matches `q`, for the user search box. Levels come from the
+/// leaderboard, users off it have no points.
+pub async fn handle_search_users(
+    query: UserSearchQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let q = query.q.trim();
+    if q.is_empty() || q.chars().count() > MAX_QUERY_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "q must be 1 to {} characters",
+            MAX_QUERY_LENGTH
+        ))
+        .into());
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+
+    let users = DEFAULT_PROJECT.users().await?;
+    let leaderboard = get_or_update_all_users_collections(&client, false).await?;
+
+    let usernames: Vec<String> = matching_usernames(users.keys(), q, limit)
+        .into_iter()
+        .cloned()
+        .collect();
+    let mut avatars = avatars::load_avatars_of(&client, &usernames).await;
+    let results: Vec<_> = usernames
+        .into_iter()
+        .map(|username| {
+            let points = leaderboard.get(&username).copied().unwrap_or(0.0);
+            json!({
+                "level": points_to_level(points as i32),
+                "avatar": avatars.remove(&username),
+                "username": username,
+            })
+        })
+        .collect();
+
+    Ok(warp::reply::json(&json!({ "query": q, "users": results })).into_response())
+}
@@ -1,22 +1,213 @@
+use crate::backend::delegations;
+use crate::backend::ens;
+use crate::backend::errors::ApiError;
 use crate::common::file_loader::load_users_data;
+use crate::indexer::indexer_config::IndexerConfig;
 use eth_checksum::checksum;
+use once_cell::sync::Lazy;
 use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::Path;
 use web3::types::Address;
 
-pub async fn get_username_or_checksummed_address(
-    wallet_address: &str,
-) -> Result<Option<String>, String> {
-    let address = wallet_address
-        .parse::<Address>()
-        .map_err(|_| "Invalid address".to_string())?;
-    let users_data = load_users_data().await;
+// username -> addresses, as stored in a users file
+pub type UsersData = HashMap<String, Vec<String>>;
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+// Always reserved, on top of the reserved usernames file and the indexed contracts' names
+const BUILTIN_RESERVED_USERNAMES: &[&str] = &[
+    "admin",
+    "administrator",
+    "afterlife",
+    "api",
+    "mod",
+    "moderator",
+    "null",
+    "root",
+    "staff",
+    "support",
+    "system",
+    "treasury",
+    "undefined",
+];
+
+/// Why a username can't be registered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsernameError {
+    TooShort,
+    TooLong,
+    // Only ASCII letters, digits, '_' and '-', starting with a letter or digit
+    InvalidCharacters,
+    // Would be read as an address or an ENS name
+    Ambiguous,
+    Reserved,
+    // Taken by another user, case-insensitively
+    Taken,
+}
+
+impl UsernameError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            UsernameError::TooShort => "username_too_short",
+            UsernameError::TooLong => "username_too_long",
+            UsernameError::InvalidCharacters => "username_invalid_characters",
+            UsernameError::Ambiguous => "username_ambiguous",
+            UsernameError::Reserved => "username_reserved",
+            UsernameError::Taken => "username_taken",
+        }
+    }
+
+    pub fn message(&self, username: &str) -> String {
+        match self {
+            UsernameError::TooShort => format!(
+                "Username {} is shorter than {} characters",
+                username, MIN_USERNAME_LENGTH
+            ),
+            UsernameError::TooLong => format!(
+                "Username {} is longer than {} characters",
+                username, MAX_USERNAME_LENGTH
+            ),
+            UsernameError::InvalidCharacters => format!(
+                "Username {} may only contain letters, digits, '_' and '-', and must start with a letter or digit",
+                username
+            ),
+            UsernameError::Ambiguous => format!(
+                "Username {} looks like an address or an ENS name",
+                username
+            ),
+            UsernameError::Reserved => format!("Username {} is reserved", username),
+            UsernameError::Taken => format!("Username {} is already taken", username),
+        }
+    }
+
+    pub fn into_api_error(self, username: &str) -> ApiError {
+        ApiError::Validation(self.code(), None, self.message(username))
+    }
+}
+
+// Reserved names compare without case and separators, "Tre_asury" is "treasury"
+fn reserved_form(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+static RESERVED_USERNAMES: Lazy<HashSet<String>> = Lazy::new(load_reserved_usernames);
+
+// The built-in names, the names listed in the reserved usernames file
+// (AFTERLIFE_PATH_RESERVED_USERNAMES, a YAML list) and the names of the indexed contracts
+fn load_reserved_usernames() -> HashSet<String> {
+    let mut names: Vec<String> = BUILTIN_RESERVED_USERNAMES
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+
+    if let Ok(path) = env::var("AFTERLIFE_PATH_RESERVED_USERNAMES") {
+        match fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                serde_yaml::from_str::<Vec<String>>(&contents).map_err(|e| e.to_string())
+            }) {
+            Ok(reserved) => names.extend(reserved),
+            Err(e) => eprintln!("Failed to load reserved usernames file {}: {}", path, e),
+        }
+    }
+
+    // IndexerConfig::from_env panics on a missing file
+    let indexer_config_exists = env::var("AFTERLIFE_PATH_IDXCFG")
+        .map(|path| Path::new(&path).is_file())
+        .unwrap_or(false);
+    if indexer_config_exists {
+        match IndexerConfig::from_env() {
+            Ok(config) => names.extend(
+                config
+                    .chains
+                    .iter()
+                    .flat_map(|chain| chain.contracts.iter().map(|c| c.name.clone())),
+            ),
+            Err(e) => eprintln!("Failed to load contract names to reserve: {}", e),
+        }
+    }
+
+    names
+        .iter()
+        .map(|name| reserved_form(name))
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Checks a username someone wants to register or rename to. `users` must not hold the
+/// user being renamed, so that changing the case of one's own name is allowed.
+pub fn validate_username(users: &UsersData, username: &str) -> Result<(), UsernameError> {
+    let length = username.chars().count();
+    if length < MIN_USERNAME_LENGTH {
+        return Err(UsernameError::TooShort);
+    }
+    if length > MAX_USERNAME_LENGTH {
+        return Err(UsernameError::TooLong);
+    }
+    let starts_alphanumeric = username
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric());
+    if !starts_alphanumeric
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(UsernameError::InvalidCharacters);
+    }
+    if username.parse::<Address>().is_ok() || username.to_lowercase().starts_with("0x") {
+        return Err(UsernameError::Ambiguous);
+    }
+    if RESERVED_USERNAMES.contains(&reserved_form(username)) {
+        return Err(UsernameError::Reserved);
+    }
+    if users.keys().any(|name| name.eq_ignore_ascii_case(username)) {
+        return Err(UsernameError::Taken);
+    }
+    Ok(())
+}
 
+// Lowercased address -> username
+pub fn usernames_by_address(users_data: &UsersData) -> HashMap<String, String> {
     let mut address_to_username = HashMap::new();
-    for (username, addresses) in &users_data {
+    for (username, addresses) in users_data {
         for addr in addresses {
             address_to_username.insert(addr.to_lowercase(), username.clone());
         }
     }
+    // Unregistered vaults belong to the user of the wallet they delegated to
+    for (vault, delegate) in delegations::all() {
+        if address_to_username.contains_key(&vault) {
+            continue;
+        }
+        if let Some(username) = address_to_username.get(&delegate).cloned() {
+            address_to_username.insert(vault, username);
+        }
+    }
+    address_to_username
+}
+
+/// Username of a signed-in wallet, which has to be registered (or delegated to a registered
+/// wallet) to have settings
+pub fn signed_in_username(users_data: &UsersData, address: &str) -> Result<String, ApiError> {
+    usernames_by_address(users_data)
+        .remove(&address.to_lowercase())
+        .ok_or_else(|| ApiError::NotFound(format!("No user registered for address {}", address)))
+}
+
+pub fn resolve_username_or_checksummed_address(
+    address_to_username: &HashMap<String, String>,
+    wallet_address: &str,
+) -> Result<Option<String>, String> {
+    let address = wallet_address
+        .parse::<Address>()
+        .map_err(|_| "Invalid address".to_string())?;
 
     let address_str = format!("{:?}", address).to_lowercase();
     // Return the username if found, otherwise return the checksummed address
@@ -26,8 +217,14 @@ pub async fn get_username_or_checksummed_address(
         .or_else(|| Some(checksum(&address_str))))
 }
 
-pub async fn get_all_addresses_for_username(username: &str) -> HashSet<String> {
+pub async fn get_username_or_checksummed_address(
+    wallet_address: &str,
+) -> Result<Option<String>, String> {
     let users_data = load_users_data().await;
+    resolve_username_or_checksummed_address(&usernames_by_address(&users_data), wallet_address)
+}
+
+pub fn addresses_for_username(users_data: &UsersData, username: &str) -> HashSet<String> {
     let mut found_addresses = HashSet::new();
     // check if username is a valid address
     // Let's see first if there's a match for the username
@@ -52,30 +249,38 @@ pub async fn get_all_addresses_for_username(username: &str) -> HashSet<String> {
             }
         }
     }
+    // Vaults delegated to these addresses count as the user's own, unless another user
+    // registered them or they resolve to another user's wallet first
+    let owners = usernames_by_address(users_data);
+    let vaults = delegations::vaults_for(&found_addresses);
+    found_addresses.extend(
+        vaults
+            .into_iter()
+            .filter(|vault| owners.get(vault).is_none_or(|owner| owner == username)),
+    );
     // return the addresses, if any, if not, it will be an empty HashSet
     found_addresses
 }
 
-pub fn points_to_level(rarity_score: i32) -> i32 {
-    // Constants
-    let a: f64 = 100.0;
-    let r: f64 = 1.0625;
-
-    // Level 0 if rarity_score is 0
-    if rarity_score == 0 {
-        return 0;
-    }
+pub async fn get_all_addresses_for_username(username: &str) -> HashSet<String> {
+    let users_data = load_users_data().await;
+    addresses_for_username(&users_data, username)
+}
 
-    // Start from level 1 and iterate
-    let mut level = 1;
-    while level <= 60 {
-        let cumulative_xp = a * (r.powi(level - 1) - 1.0) / (r - 1.0);
-        if (rarity_score as f64) < cumulative_xp {
-            return level - 1;
-        }
-        level += 1;
+/// Same as addresses_for_username, ENS names that aren't registered usernames are looked
+/// up as the address they resolve to
+pub async fn addresses_for_name(
+    users_data: &UsersData,
+    name: &str,
+) -> Result<HashSet<String>, ApiError> {
+    if users_data.contains_key(name) || !ens::is_ens_name(name) {
+        return Ok(addresses_for_username(users_data, name));
     }
+    let address = ens::resolve(name).await?;
+    Ok(addresses_for_username(users_data, &address))
+}
 
-    // If we reach here, return the max level (60)
-    60
+pub async fn get_all_addresses_for_name(name: &str) -> Result<HashSet<String>, ApiError> {
+    let users_data = load_users_data().await;
+    addresses_for_name(&users_data, name).await
 }
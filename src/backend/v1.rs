@@ -0,0 +1,656 @@
+use crate::backend::api::{
+    leaderboard_for, load_floor_prices, load_last_sales, rank_leaderboard, with_db, OwnersQuery,
+};
+use crate::backend::avatars;
+use crate::backend::contract_params;
+use crate::backend::errors::ApiError;
+use crate::backend::holdings::load_user_holdings;
+use crate::backend::labels;
+use crate::backend::levels::points_to_level;
+use crate::backend::projects::Project;
+use crate::backend::queries::{self, Avatar, SaleRow};
+use crate::backend::rarity::{self, RarityTier};
+use crate::backend::repository::CollectionRepository;
+use crate::backend::response_cache;
+use crate::backend::timeouts::timed;
+use crate::backend::usernames::addresses_for_name;
+use crate::common::database::Database;
+use crate::common::numeric::{Balance, TokenId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::filters::BoxedFilter;
+use warp::reject::Rejection;
+use warp::{Filter, Reply};
+
+const DEFAULT_PAGE_LIMIT: usize = 50;
+const MAX_PAGE_LIMIT: usize = 200;
+
+/// `?limit=&cursor=` of paginated endpoints. Cursors are opaque to clients,
+/// they pass back the `next_cursor` of the previous page.
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+impl PageQuery {
+    fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    // Cursor of endpoints paginated by position
+    fn offset(&self) -> Result<usize, ApiError> {
+        match &self.cursor {
+            Some(cursor) => cursor
+                .parse()
+                .map_err(|_| ApiError::BadRequest(format!("Invalid cursor {}", cursor))),
+            None => Ok(0),
+        }
+    }
+
+    // Cursor of endpoints paginated by token id, the last id of the previous page
+    fn after_token(&self) -> Result<Option<TokenId>, ApiError> {
+        self.cursor
+            .as_deref()
+            .map(|cursor| {
+                cursor
+                    .parse()
+                    .map_err(|_| ApiError::BadRequest(format!("Invalid cursor {}", cursor)))
+            })
+            .transpose()
+    }
+
+    fn cache_suffix(&self) -> String {
+        format!(
+            "?limit={}&cursor={}",
+            self.limit(),
+            self.cursor.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// `?tier=` of the tokens of a collection, e.g. `?tier=legendary`
+#[derive(Debug, Deserialize)]
+pub struct TierQuery {
+    tier: Option<RarityTier>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    // None on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    // Slices a fully loaded list, the cursor being the offset of the next page
+    fn from_offset(mut all: Vec<T>, offset: usize, limit: usize) -> Result<Self, ApiError> {
+        let end = offset
+            .checked_add(limit)
+            .ok_or_else(|| ApiError::BadRequest(format!("Invalid cursor {}", offset)))?;
+        let total = all.len();
+        let items: Vec<T> = all.drain(offset.min(total)..end.min(total)).collect();
+        Ok(Page {
+            items,
+            next_cursor: (end < total).then(|| end.to_string()),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionSummary {
+    pub chain: String,
+    pub contract_address: String,
+    pub name: String,
+    pub token_count: usize,
+    pub holders: i64,
+    pub floor_price: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Token {
+    pub token_id: TokenId,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub attributes: Option<Value>,
+    // Scaled by 1000, as in the legacy routes
+    pub rarity_score: Option<f64>,
+    pub rarity_index: Option<u64>,
+    // Share of the collection scoring lower, in percent
+    pub rarity_percentile: Option<f64>,
+    pub rarity_tier: Option<RarityTier>,
+    pub floor_price: Option<f64>,
+    pub last_sale: Option<SaleRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenOwners {
+    pub token_id: TokenId,
+    // Only for historical owners, `?block=`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block: Option<i32>,
+    pub owners: Vec<Owner>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Owner {
+    pub address: String,
+    // e.g. "OpenSea escrow", for addresses in the label registry
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub username: String,
+    pub addresses: Vec<String>,
+    pub points: f64,
+    pub level: i32,
+    // None when the user isn't on the leaderboard
+    pub rank: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<Avatar>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OwnedToken {
+    pub chain: String,
+    pub contract_address: String,
+    pub token_id: TokenId,
+    pub balance: Balance,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    // Username, or checksummed address for holders without one
+    pub name: String,
+    pub points: f64,
+    pub level: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<Avatar>,
+}
+
+/// The /v1 route tree below `project`, which extracts the project the request is scoped to
+pub fn routes(
+    project: BoxedFilter<(Arc<Project>,)>,
+    database: Arc<Database>,
+) -> BoxedFilter<(warp::reply::Response,)> {
+    project
+        .clone()
+        .and(contract_params::canonical(
+            warp::path!("collections" / String / String),
+            database.clone(),
+        ))
+        .and(warp::get())
+        .and(with_db(database.clone()))
+        .and_then(timed!(
+            handle_get_collection,
+            project,
+            chain_name,
+            contract_address,
+            client
+        ))
+        .map(Reply::into_response)
+        .or(project
+            .clone()
+            .and(contract_params::canonical(
+                warp::path!("collections" / String / String / "tokens"),
+                database.clone(),
+            ))
+            .and(warp::get())
+            .and(warp::query::<PageQuery>())
+            .and(warp::query::<TierQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_tokens,
+                project,
+                chain_name,
+                contract_address,
+                query,
+                tier_query,
+                client
+            ))
+            .map(Reply::into_response))
+        .unify()
+        .or(project
+            .clone()
+            .and(
+                contract_params::canonical(
+                    warp::path!("collections" / String / String / "tokens" / ..),
+                    database.clone(),
+                )
+                .and(warp::path!(TokenId)),
+            )
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_token,
+                project,
+                chain_name,
+                contract_address,
+                token_id,
+                client
+            ))
+            .map(Reply::into_response))
+        .unify()
+        .or(project
+            .clone()
+            .and(
+                contract_params::canonical(
+                    warp::path!("collections" / String / String / "tokens" / ..),
+                    database.clone(),
+                )
+                .and(warp::path!(TokenId / "owners")),
+            )
+            .and(warp::get())
+            .and(warp::query::<OwnersQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_token_owners::<Client>,
+                project,
+                chain_name,
+                contract_address,
+                token_id,
+                query,
+                repository
+            ))
+            .map(Reply::into_response))
+        .unify()
+        .or(project
+            .clone()
+            .and(warp::path!("users" / String))
+            .and(warp::get())
+            .and(with_db(database.clone()))
+            .and_then(timed!(handle_get_user, project, username, client))
+            .map(Reply::into_response))
+        .unify()
+        .or(project
+            .clone()
+            .and(warp::path!("users" / String / "tokens"))
+            .and(warp::get())
+            .and(warp::query::<PageQuery>())
+            .and(with_db(database.clone()))
+            .and_then(timed!(
+                handle_get_user_tokens,
+                project,
+                username,
+                query,
+                client
+            ))
+            .map(Reply::into_response))
+        .unify()
+        .or(project
+            .and(warp::path!("leaderboard"))
+            .and(warp::get())
+            .and(warp::query::<PageQuery>())
+            .and(with_db(database))
+            .and_then(timed!(handle_get_leaderboard, project, query, client))
+            .map(Reply::into_response))
+        .unify()
+        .boxed()
+}
+
+fn to_value<T: Serialize>(model: &T) -> Result<Value, ApiError> {
+    serde_json::to_value(model)
+        .map_err(|e| ApiError::Internal(format!("Failed to serialize response: {}", e)))
+}
+
+// Serves a cached response, built with `init` on a miss
+async fn cached_reply<F>(key: String, init: F) -> Result<impl warp::Reply, Rejection>
+where
+    F: std::future::Future<Output = Result<Value, ApiError>>,
+{
+    let response = response_cache::get_or_compute(key, init).await?;
+    Ok(warp::reply::json(&*response))
+}
+
+async fn handle_get_collection(
+    project: Arc<Project>,
+    chain_name: String,
+    contract_address: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    project.ensure_includes(&chain_name, &contract_address)?;
+    let key = format!(
+        "v1/{}collections/{}/{}",
+        project.cache_prefix(),
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase()
+    );
+    cached_reply(key, async move {
+        let name = queries::get_contract_name_from_chain_and_address(
+            &client,
+            &chain_name,
+            &contract_address,
+        )
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to get contract name: {}", e)))?;
+        let token_ids = queries::get_entire_collection(&client, &chain_name, &contract_address)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get entire collection: {}", e)))?;
+        if token_ids.is_empty() {
+            return Err(ApiError::NotFound(format!(
+                "Unknown collection {} on {}",
+                contract_address, chain_name
+            )));
+        }
+        let excluded: Vec<String> = project.excluded_addresses().await?.into_iter().collect();
+        let holders = queries::get_holder_count(&client, &chain_name, &contract_address, &excluded)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get holder count: {}", e)))?;
+        let floor_price = load_floor_prices(&client, &chain_name, &contract_address)
+            .await
+            .into_values()
+            .reduce(f64::min);
+
+        to_value(&CollectionSummary {
+            chain: chain_name.clone(),
+            contract_address: contract_address.clone(),
+            name,
+            token_count: token_ids.len(),
+            holders,
+            floor_price,
+        })
+    })
+    .await
+}
+
+// Tokens with their metadata, in the order of `token_ids`. Tokens without metadata are skipped.
+async fn load_tokens(
+    project: &Project,
+    client: &Client,
+    chain_name: &str,
+    contract_address: &str,
+    token_ids: Vec<TokenId>,
+) -> Vec<Token> {
+    let metadata_store = project.metadata();
+    let rarity_map = metadata_store
+        .rarity_map(chain_name, contract_address)
+        .await;
+    let tier_thresholds = rarity::tier_thresholds(chain_name, contract_address);
+    let floor_prices = load_floor_prices(client, chain_name, contract_address).await;
+    let mut last_sales = load_last_sales(client, chain_name, contract_address).await;
+
+    let mut metadata = metadata_store
+        .tokens_metadata(chain_name, contract_address, &token_ids)
+        .await;
+
+    token_ids
+        .into_iter()
+        .filter_map(|token_id| {
+            let metadata = metadata.remove(&token_id)?;
+            let rarity = rarity_map.get(&token_id);
+            Some(Token {
+                token_id,
+                name: metadata["name"].as_str().map(str::to_string),
+                description: metadata["description"].as_str().map(str::to_string),
+                attributes: metadata.get("attributes").cloned(),
+                rarity_score: rarity.map(|&(score, _, _)| score * 1000.0),
+                rarity_index: rarity.map(|&(_, index, _)| index),
+                rarity_percentile: rarity.map(|&(_, _, percentile)| percentile),
+                rarity_tier: rarity.map(|&(_, _, percentile)| tier_thresholds.tier(percentile)),
+                floor_price: floor_prices.get(&token_id).copied(),
+                last_sale: last_sales.remove(&token_id),
+            })
+        })
+        .collect()
+}
+
+async fn handle_get_tokens(
+    project: Arc<Project>,
+    chain_name: String,
+    contract_address: String,
+    query: PageQuery,
+    tier_query: TierQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    project.ensure_includes(&chain_name, &contract_address)?;
+    let after = query.after_token()?;
+    let limit = query.limit();
+    let key = format!(
+        "v1/{}collections/{}/{}/tokens{}&tier={}",
+        project.cache_prefix(),
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase(),
+        query.cache_suffix(),
+        tier_query.tier.map_or("", |tier| tier.as_str())
+    );
+    cached_reply(key, async move {
+        let mut token_ids = queries::get_entire_collection(&client, &chain_name, &contract_address)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get entire collection: {}", e)))?;
+        token_ids.sort_unstable();
+        token_ids.dedup();
+        // Unranked tokens have no tier
+        if let Some(tier) = tier_query.tier {
+            let rarity_map = project
+                .metadata()
+                .rarity_map(&chain_name, &contract_address)
+                .await;
+            let tier_thresholds = rarity::tier_thresholds(&chain_name, &contract_address);
+            token_ids.retain(|token_id| {
+                rarity_map
+                    .get(token_id)
+                    .is_some_and(|&(_, _, percentile)| tier_thresholds.tier(percentile) == tier)
+            });
+        }
+
+        let start = match after {
+            Some(after) => token_ids.partition_point(|id| *id <= after),
+            None => 0,
+        };
+        let page_ids: Vec<TokenId> = token_ids.iter().skip(start).take(limit).copied().collect();
+        let next_cursor = (start + page_ids.len() < token_ids.len())
+            .then(|| page_ids.last().map(|id| id.to_string()))
+            .flatten();
+
+        let items = load_tokens(&project, &client, &chain_name, &contract_address, page_ids).await;
+        to_value(&Page { items, next_cursor })
+    })
+    .await
+}
+
+async fn handle_get_token(
+    project: Arc<Project>,
+    chain_name: String,
+    contract_address: String,
+    token_id: TokenId,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    project.ensure_includes(&chain_name, &contract_address)?;
+    let key = format!(
+        "v1/{}collections/{}/{}/tokens/{}",
+        project.cache_prefix(),
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase(),
+        token_id
+    );
+    cached_reply(key, async move {
+        let token = load_tokens(
+            &project,
+            &client,
+            &chain_name,
+            &contract_address,
+            vec![token_id],
+        )
+        .await
+        .pop()
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown token {}", token_id)))?;
+        to_value(&token)
+    })
+    .await
+}
+
+async fn handle_get_token_owners<R: CollectionRepository>(
+    project: Arc<Project>,
+    chain_name: String,
+    contract_address: String,
+    token_id: TokenId,
+    query: OwnersQuery,
+    repository: Arc<R>,
+) -> Result<impl warp::Reply, Rejection> {
+    project.ensure_includes(&chain_name, &contract_address)?;
+    let block = query
+        .block(&*repository, &chain_name, &contract_address)
+        .await?;
+    let key = format!(
+        "v1/{}collections/{}/{}/tokens/{}/owners?block={}",
+        project.cache_prefix(),
+        chain_name.to_lowercase(),
+        contract_address.to_lowercase(),
+        token_id,
+        block.map(|block| block.to_string()).unwrap_or_default()
+    );
+    cached_reply(key, async move {
+        let mut owners = repository
+            .token_owners(&chain_name, &contract_address, token_id, block)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("Failed to get token owners: {}", e)))?;
+        let excluded = project.excluded_addresses().await?;
+        owners.retain(|owner| !excluded.contains(&owner.to_lowercase()));
+        owners.sort();
+        let owners = owners
+            .into_iter()
+            .map(|address| Owner {
+                label: labels::label_for(&address),
+                address,
+            })
+            .collect();
+        to_value(&TokenOwners {
+            token_id,
+            block,
+            owners,
+        })
+    })
+    .await
+}
+
+async fn user_addresses(project: &Project, username: &str) -> Result<Vec<String>, ApiError> {
+    let mut addresses: Vec<String> = addresses_for_name(&project.users().await?, username)
+        .await?
+        .into_iter()
+        .collect();
+    if addresses.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown user {}", username)));
+    }
+    addresses.sort();
+    Ok(addresses)
+}
+
+async fn handle_get_user(
+    project: Arc<Project>,
+    username: String,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let key = format!("v1/{}users/{}", project.cache_prefix(), username);
+    cached_reply(key, async move {
+        let addresses = user_addresses(&project, &username).await?;
+
+        // Points and rank come from the leaderboard so they always agree with it
+        let leaderboard = leaderboard_for(&project, &client).await?;
+        let points = leaderboard.get(&username).copied().unwrap_or(0.0);
+        let rank = leaderboard.contains_key(&username).then(|| {
+            leaderboard
+                .values()
+                .filter(|&&score| score > points)
+                .count()
+                + 1
+        });
+
+        to_value(&UserSummary {
+            username: username.clone(),
+            addresses,
+            points,
+            level: points_to_level(points as i32),
+            rank,
+            avatar: avatars::load_avatar(&client, &username).await,
+        })
+    })
+    .await
+}
+
+async fn handle_get_user_tokens(
+    project: Arc<Project>,
+    username: String,
+    query: PageQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let offset = query.offset()?;
+    let limit = query.limit();
+    let key = format!(
+        "v1/{}users/{}/tokens{}",
+        project.cache_prefix(),
+        username,
+        query.cache_suffix()
+    );
+    cached_reply(key, async move {
+        let addresses = user_addresses(&project, &username).await?;
+        let holdings = load_user_holdings(&client, &addresses.into_iter().collect()).await?;
+
+        let mut tokens: Vec<OwnedToken> = holdings
+            .into_iter()
+            .filter(|((chain, contract_address), _)| project.includes(chain, contract_address))
+            .flat_map(|((chain, contract_address), tokens)| {
+                tokens
+                    .into_iter()
+                    .map(move |(token_id, balance)| OwnedToken {
+                        chain: chain.clone(),
+                        contract_address: contract_address.clone(),
+                        token_id,
+                        balance,
+                    })
+            })
+            .collect();
+        tokens.sort_by(|a, b| {
+            (&a.chain, &a.contract_address, a.token_id).cmp(&(
+                &b.chain,
+                &b.contract_address,
+                b.token_id,
+            ))
+        });
+
+        to_value(&Page::from_offset(tokens, offset, limit)?)
+    })
+    .await
+}
+
+async fn handle_get_leaderboard(
+    project: Arc<Project>,
+    query: PageQuery,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let offset = query.offset()?;
+    let limit = query.limit();
+    let key = format!(
+        "v1/{}leaderboard{}",
+        project.cache_prefix(),
+        query.cache_suffix()
+    );
+    cached_reply(key, async move {
+        let entries: Vec<LeaderboardEntry> =
+            rank_leaderboard(leaderboard_for(&project, &client).await?)
+                .into_iter()
+                .map(|(name, points, rank)| LeaderboardEntry {
+                    rank,
+                    name,
+                    points,
+                    level: points_to_level(points as i32),
+                    avatar: None,
+                })
+                .collect();
+        // Only the avatars of the page are looked up
+        let mut page = Page::from_offset(entries, offset, limit)?;
+        let names: Vec<String> = page.items.iter().map(|entry| entry.name.clone()).collect();
+        let mut avatars = avatars::load_avatars_of(&client, &names).await;
+        for entry in &mut page.items {
+            entry.avatar = avatars.remove(&entry.name);
+        }
+
+        to_value(&page)
+    })
+    .await
+}
@@ -0,0 +1,95 @@
+use crate::backend::addresses;
+use crate::backend::errors::ApiError;
+use crate::backend::queries;
+use crate::backend::usernames::get_all_addresses_for_username;
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio_postgres::Client;
+use warp::reject::Rejection;
+use warp::Reply;
+
+#[derive(Debug, Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    // Only transfers of this user's addresses are sent, every transfer when omitted
+    pub username: Option<String>,
+}
+
+pub(crate) fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    hex::encode(secret)
+}
+
+// The signing secret is only returned here, store it on creation
+pub async fn handle_create_webhook(
+    body: NewWebhook,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    // Admin webhooks may point at internal services, unlike the users' ones
+    let url = reqwest::Url::parse(body.url.trim())
+        .map_err(|e| ApiError::invalid_field("url", format!("Invalid url {}: {}", body.url, e)))?;
+    if !matches!(url.scheme(), "https" | "http") || url.host_str().is_none() {
+        return Err(ApiError::invalid_field(
+            "url",
+            format!("Webhook url {} must be http(s) with a host", body.url),
+        )
+        .into());
+    }
+    let username = match body.username.as_deref().map(str::trim) {
+        Some("") => {
+            return Err(ApiError::invalid_field(
+                "username",
+                "Username must not be empty".to_string(),
+            )
+            .into())
+        }
+        Some(username) if addresses::looks_like_address(username) => {
+            Some(addresses::normalize_field("username", username)?)
+        }
+        Some(username) => Some(username.to_string()),
+        None => None,
+    };
+    if let Some(username) = &username {
+        if get_all_addresses_for_username(username).await.is_empty() {
+            return Err(ApiError::NotFound(format!("Unknown user {}", username)).into());
+        }
+    }
+
+    let secret = generate_secret();
+    let webhook = queries::create_webhook(&client, url.as_str(), username.as_deref(), &secret)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to create webhook: {}", e)))?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "webhook": webhook, "secret": secret })),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+pub async fn handle_list_webhooks(client: Arc<Client>) -> Result<impl warp::Reply, Rejection> {
+    let webhooks = queries::get_webhooks(&client)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to list webhooks: {}", e)))?;
+
+    Ok(warp::reply::json(&json!({ "webhooks": webhooks })).into_response())
+}
+
+pub async fn handle_delete_webhook(
+    id: i32,
+    client: Arc<Client>,
+) -> Result<impl warp::Reply, Rejection> {
+    let deleted = queries::delete_webhook(&client, id)
+        .await
+        .map_err(|e| ApiError::Upstream(format!("Failed to delete webhook: {}", e)))?;
+    if !deleted {
+        return Err(ApiError::NotFound(format!("Unknown webhook {}", id)).into());
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
@@ -0,0 +1,118 @@
+use afterlife_backend::commands;
+use afterlife_backend::common::logging;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "afterlife", about = "Afterlife indexer and API")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the API
+    Serve,
+    /// Index every configured chain
+    Index {
+        /// Draw backfill progress bars
+        #[arg(long)]
+        progress: bool,
+    },
+    /// Watch unrevealed contracts for their reveal, without the API
+    WatchMetadata,
+    /// Re-index a contract from a block, its configured start block by default
+    Backfill {
+        #[arg(long)]
+        chain: String,
+        #[arg(long)]
+        contract: String,
+        #[arg(long)]
+        from_block: Option<i32>,
+    },
+    /// Check the configuration, database and storage
+    Verify,
+    /// Apply the pending schema migrations
+    Migrate,
+    /// Compare the balances table with a replay of the events, optionally repairing it
+    Reconcile {
+        #[arg(long)]
+        chain: Option<String>,
+        #[arg(long)]
+        contract: Option<String>,
+        /// Rewrite the differing balances from the events
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Dump the events to Parquet or CSV files partitioned by chain, contract and month
+    Export {
+        #[arg(long)]
+        chain: Option<String>,
+        #[arg(long)]
+        contract: Option<String>,
+        #[arg(long, value_enum, default_value = "parquet")]
+        format: commands::export::Format,
+        /// Directory to write to, AFTERLIFE_PATH_EXPORTS/events by default
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Only rewrite the months from this one on (YYYY-MM)
+        #[arg(long)]
+        since: Option<String>,
+    },
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Serve => "serve",
+            Command::Index { .. } => "index",
+            Command::WatchMetadata => "watch-metadata",
+            Command::Backfill { .. } => "backfill",
+            Command::Verify => "verify",
+            Command::Migrate => "migrate",
+            Command::Reconcile { .. } => "reconcile",
+            Command::Export { .. } => "export",
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    logging::init(cli.command.name());
+
+    match cli.command {
+        Command::Serve => commands::serve::run().await,
+        Command::Index { progress } => commands::index::run(progress).await,
+        Command::WatchMetadata => commands::watch_metadata::run().await,
+        Command::Backfill {
+            chain,
+            contract,
+            from_block,
+        } => commands::backfill::run(&chain, &contract, from_block).await,
+        Command::Verify => commands::verify::run().await,
+        Command::Migrate => commands::migrate::run().await,
+        Command::Reconcile {
+            chain,
+            contract,
+            repair,
+        } => commands::reconcile::run(chain.as_deref(), contract.as_deref(), repair).await,
+        Command::Export {
+            chain,
+            contract,
+            format,
+            output,
+            since,
+        } => {
+            commands::export::run(
+                chain.as_deref(),
+                contract.as_deref(),
+                format,
+                output.as_deref(),
+                since.as_deref(),
+            )
+            .await
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::common::database;
+use crate::indexer::indexer_config::IndexerConfig;
+use crate::indexer::queries::rewind_contract;
+use std::process;
+
+/// `afterlife backfill`: re-indexes a contract from `from_block`, or from its configured
+/// start block. The running indexer picks the rewound cursor up on its next iteration.
+pub async fn run(chain_name: &str, contract_address: &str, from_block: Option<i32>) {
+    let from_block = match from_block {
+        Some(block) => block,
+        None => {
+            let config = IndexerConfig::from_env().expect("Failed to load indexer config");
+            let contract = config
+                .chains
+                .iter()
+                .filter(|chain| chain.name.eq_ignore_ascii_case(chain_name))
+                .flat_map(|chain| &chain.contracts)
+                .find(|contract| contract.address.eq_ignore_ascii_case(contract_address));
+            match contract {
+                Some(contract) => contract.startblock,
+                None => {
+                    eprintln!(
+                        "Contract {} on {} is not in the indexer config",
+                        contract_address, chain_name
+                    );
+                    process::exit(1);
+                }
+            }
+        }
+    };
+
+    let client = database::connect()
+        .await
+        .expect("Failed to connect to database");
+    match rewind_contract(chain_name, contract_address, from_block, &client).await {
+        Ok(true) => println!(
+            "Contract {} on {} will be re-indexed from block {}",
+            contract_address, chain_name, from_block
+        ),
+        Ok(false) => {
+            eprintln!(
+                "Contract {} on {} hasn't been indexed yet",
+                contract_address, chain_name
+            );
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to rewind contract: {}", e);
+            process::exit(1);
+        }
+    }
+}
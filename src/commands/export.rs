@@ -0,0 +1,259 @@
+use crate::common::database;
+use crate::common::storage::STORAGE;
+use crate::indexer::queries::{
+    get_event_months, get_exported_transfers, get_indexed_contracts, ExportedTransfer,
+    IndexedContract,
+};
+use arrow_array::{ArrayRef, Int32Array, RecordBatch, StringArray, TimestampMicrosecondArray};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use tokio_postgres::Client;
+
+// Partition of the events without a block timestamp
+const UNKNOWN_MONTH: &str = "unknown";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Parquet,
+    Csv,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Parquet => "parquet",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+/// `afterlife export`: writes the events of every indexed contract, or of the ones
+/// matching `chain_name` and `contract_address`, one row per token id, to
+/// `<output>/chain=<chain>/contract=<address>/month=<YYYY-MM>/events.<format>`.
+///
+/// The layout is the hive partitioning DuckDB and Spark understand, e.g.
+/// `read_parquet('exports/events/*/*/*/*.parquet', hive_partitioning = true)`. Files go
+/// through the configured storage, so an S3 storage exports to the bucket. Partitions
+/// are rewritten whole, `since` (YYYY-MM) skips the months before it for incremental runs.
+pub async fn run(
+    chain_name: Option<&str>,
+    contract_address: Option<&str>,
+    format: Format,
+    output: Option<&Path>,
+    since: Option<&str>,
+) {
+    let output = output.map(Path::to_path_buf).unwrap_or_else(default_output);
+    let client = database::connect()
+        .await
+        .expect("Failed to connect to database");
+    let contracts: Vec<IndexedContract> = match get_indexed_contracts(&client).await {
+        Ok(contracts) => contracts
+            .into_iter()
+            .filter(|contract| {
+                chain_name.is_none_or(|name| contract.chain_name.eq_ignore_ascii_case(name))
+            })
+            .filter(|contract| {
+                contract_address
+                    .is_none_or(|address| contract.address.eq_ignore_ascii_case(address))
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to list contracts: {}", e);
+            process::exit(1);
+        }
+    };
+    if contracts.is_empty() {
+        eprintln!("No indexed contract matches");
+        process::exit(1);
+    }
+
+    let mut files = 0;
+    let mut rows = 0;
+    for contract in &contracts {
+        match export_contract(contract, format, &output, since, &client).await {
+            Ok((contract_files, contract_rows)) => {
+                files += contract_files;
+                rows += contract_rows;
+            }
+            Err(e) => {
+                eprintln!(
+                    "[{}] Failed to export {} ({}): {}",
+                    contract.chain_name, contract.name, contract.address, e
+                );
+                process::exit(1);
+            }
+        }
+    }
+    println!(
+        "Exported {} rows of {} contracts to {} files under {}",
+        rows,
+        contracts.len(),
+        files,
+        output.display()
+    );
+}
+
+fn default_output() -> PathBuf {
+    PathBuf::from(env::var("AFTERLIFE_PATH_EXPORTS").unwrap_or_else(|_| "exports".to_string()))
+        .join("events")
+}
+
+// Returns (files, rows) written
+async fn export_contract(
+    contract: &IndexedContract,
+    format: Format,
+    output: &Path,
+    since: Option<&str>,
+    client: &Client,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut files = 0;
+    let mut rows = 0;
+    for month in get_event_months(contract.id, client).await? {
+        // YYYY-MM compares as a string, undated events are always exported
+        if let (Some(since), Some(month)) = (since, &month) {
+            if month.as_str() < since {
+                continue;
+            }
+        }
+        let transfers = get_exported_transfers(contract.id, month.as_deref(), client).await?;
+        let contents = match format {
+            Format::Parquet => to_parquet(contract, &transfers)?,
+            Format::Csv => to_csv(contract, &transfers),
+        };
+        let path = output
+            .join(format!("chain={}", contract.chain_name.to_lowercase()))
+            .join(format!("contract={}", contract.address.to_lowercase()))
+            .join(format!(
+                "month={}",
+                month.as_deref().unwrap_or(UNKNOWN_MONTH)
+            ))
+            .join(format!("events.{}", format.extension()));
+        STORAGE.write(&path, contents).await?;
+        println!(
+            "[{}] {} {}: {} rows",
+            contract.chain_name,
+            contract.address,
+            month.as_deref().unwrap_or(UNKNOWN_MONTH),
+            transfers.len()
+        );
+        files += 1;
+        rows += transfers.len();
+    }
+    Ok((files, rows))
+}
+
+fn to_csv(contract: &IndexedContract, transfers: &[ExportedTransfer]) -> Vec<u8> {
+    let mut csv = String::from(
+        "chain,contract_address,block_number,block_timestamp,transaction_hash,\
+        transaction_index,log_index,operator,from_address,to_address,token_id,value\n",
+    );
+    // Nothing in there needs quoting: names of chains, hex strings and numbers
+    for transfer in transfers {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            contract.chain_name.to_lowercase(),
+            contract.address.to_lowercase(),
+            transfer.block_number,
+            transfer.block_time.as_deref().unwrap_or_default(),
+            transfer.transaction_hash,
+            transfer
+                .transaction_index
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+            transfer.log_index,
+            transfer.operator.as_deref().unwrap_or_default(),
+            transfer.from_address.as_deref().unwrap_or_default(),
+            transfer.to_address.as_deref().unwrap_or_default(),
+            transfer.token_id,
+            transfer.value,
+        ));
+    }
+    csv.into_bytes()
+}
+
+fn to_parquet(
+    contract: &IndexedContract,
+    transfers: &[ExportedTransfer],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("chain", DataType::Utf8, false),
+        Field::new("contract_address", DataType::Utf8, false),
+        Field::new("block_number", DataType::Int32, false),
+        Field::new(
+            "block_timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            true,
+        ),
+        Field::new("transaction_hash", DataType::Utf8, false),
+        Field::new("transaction_index", DataType::Int32, true),
+        Field::new("log_index", DataType::Int32, false),
+        Field::new("operator", DataType::Utf8, true),
+        Field::new("from_address", DataType::Utf8, true),
+        Field::new("to_address", DataType::Utf8, true),
+        Field::new("token_id", DataType::Utf8, false),
+        Field::new("value", DataType::Utf8, false),
+    ]));
+
+    let chain = contract.chain_name.to_lowercase();
+    let address = contract.address.to_lowercase();
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec![chain.as_str(); transfers.len()])),
+        Arc::new(StringArray::from(vec![address.as_str(); transfers.len()])),
+        Arc::new(Int32Array::from_iter_values(
+            transfers.iter().map(|t| t.block_number),
+        )),
+        Arc::new(
+            TimestampMicrosecondArray::from(
+                transfers
+                    .iter()
+                    .map(|t| t.block_timestamp_micros)
+                    .collect::<Vec<_>>(),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(StringArray::from_iter_values(
+            transfers.iter().map(|t| t.transaction_hash.as_str()),
+        )),
+        Arc::new(Int32Array::from(
+            transfers
+                .iter()
+                .map(|t| t.transaction_index)
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(Int32Array::from_iter_values(
+            transfers.iter().map(|t| t.log_index),
+        )),
+        Arc::new(StringArray::from_iter(
+            transfers.iter().map(|t| t.operator.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter(
+            transfers.iter().map(|t| t.from_address.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter(
+            transfers.iter().map(|t| t.to_address.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            transfers.iter().map(|t| t.token_id.as_str()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            transfers.iter().map(|t| t.value.as_str()),
+        )),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut contents = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut contents, schema, Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(contents)
+}
@@ -0,0 +1,448 @@
+use crate::common::database;
+use crate::indexer::contract_types;
+use crate::indexer::deliveries;
+use crate::indexer::indexer_config::{Chain, IndexerConfig};
+use crate::indexer::lag_watcher::{self, ChainLags};
+use crate::indexer::notifications;
+use crate::indexer::queries::{
+    contract_and_chain_to_contractid, get_earliest_last_processed_block, record_rpc_failures,
+    sync_approvals_backfill, sync_burn_addresses, sync_contract_slugs, sync_rarity_models,
+    sync_sink_addresses, sync_staking_contracts, sync_start_blocks, update_chain_head,
+    update_sync_progress, write_events_for_chain, Approval, Event, MetadataUpdate, Sale,
+};
+use crate::indexer::remote_calls::{EventFetcher, FetchProgress};
+use crate::indexer::rpc_pool;
+use crate::indexer::webhooks;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_postgres::Client;
+
+// Min time between two iterations of a chain
+const MIN_ITERATION_PERIOD: Duration = Duration::from_secs(1);
+// A failing chain retries after 2s, 4s, ... up to this
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+// How often the supervisor reloads the config and checks the chain tasks
+const SUPERVISOR_PERIOD: Duration = Duration::from_secs(60);
+// How often the supervisor logs every chain's metrics
+const METRICS_LOG_PERIOD: Duration = Duration::from_secs(300);
+// Runs spanning more chunks than this are backfills, their progress is reported
+const BACKFILL_MIN_CHUNKS: usize = 10;
+// Min time between two sync_progress writes of a backfill
+const PROGRESS_WRITE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Per chain counters, updated by the chain's task and logged by the supervisor
+#[derive(Debug, Default)]
+struct ChainMetrics {
+    iterations: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    events_written: u64,
+    last_duration: Option<Duration>,
+    last_success: Option<Instant>,
+    last_error: Option<String>,
+}
+
+struct ChainTask {
+    // The chain's config as loaded, the task is restarted when it changes
+    config: String,
+    handle: JoinHandle<()>,
+    metrics: Arc<Mutex<ChainMetrics>>,
+}
+
+/// `afterlife index`: indexes every configured chain, forever. Each chain runs its own
+/// fetch and write loop with its own connection and cursor, so a stalled or failing chain
+/// doesn't hold the others back.
+///
+/// Backfill progress is recorded in sync_progress, and drawn as terminal progress bars
+/// when `progress` is set.
+pub async fn run(progress: bool) {
+    println!("Starting Afterlife Indexer, Insanity Edition");
+    println!("SWED");
+
+    let bars = progress.then(MultiProgress::new);
+    let lags = ChainLags::default();
+    tokio::spawn(lag_watcher::run(lags.clone()));
+    tokio::spawn(deliveries::run());
+
+    let mut tasks: HashMap<String, ChainTask> = HashMap::new();
+    let mut last_metrics_log = Instant::now();
+    loop {
+        match IndexerConfig::from_env() {
+            Ok(config) => supervise(&mut tasks, config.chains, &bars),
+            // Keep indexing with the last good config
+            Err(e) => println!("Failed to load indexer config: {}", e),
+        }
+
+        if last_metrics_log.elapsed() >= METRICS_LOG_PERIOD {
+            log_metrics(&tasks, &lags);
+            last_metrics_log = Instant::now();
+        }
+        tokio::time::sleep(SUPERVISOR_PERIOD).await;
+    }
+}
+
+// Starts the new chains, restarts the changed and dead ones, stops the removed ones
+fn supervise(
+    tasks: &mut HashMap<String, ChainTask>,
+    chains: Vec<Chain>,
+    bars: &Option<MultiProgress>,
+) {
+    let names: Vec<String> = chains.iter().map(|chain| chain.name.clone()).collect();
+    tasks.retain(|name, task| {
+        let keep = names.contains(name);
+        if !keep {
+            println!("[{}] Removed from the config, stopping", name);
+            task.handle.abort();
+        }
+        keep
+    });
+
+    for chain in chains {
+        let config = serde_json::to_string(&chain).unwrap_or_default();
+        if let Some(task) = tasks.get(&chain.name) {
+            if task.config == config && !task.handle.is_finished() {
+                continue;
+            }
+            if task.handle.is_finished() {
+                eprintln!("[{}] Indexing task died, restarting", chain.name);
+            } else {
+                println!("[{}] Config changed, restarting", chain.name);
+                task.handle.abort();
+            }
+        }
+
+        let metrics = Arc::new(Mutex::new(ChainMetrics::default()));
+        let name = chain.name.clone();
+        let handle = tokio::spawn(index_chain(chain, metrics.clone(), bars.clone()));
+        tasks.insert(
+            name,
+            ChainTask {
+                config,
+                handle,
+                metrics,
+            },
+        );
+    }
+}
+
+fn log_metrics(tasks: &HashMap<String, ChainTask>, lags: &ChainLags) {
+    let lags = lags.lock().expect("Lags lock poisoned");
+    let mut names: Vec<&String> = tasks.keys().collect();
+    names.sort();
+    for name in names {
+        let metrics = tasks[name].metrics.lock().expect("Metrics lock poisoned");
+        println!(
+            "[{}] {} blocks behind, {} iterations, {} failed ({} in a row), {} events written, last run {:?}, last success {:?} ago{}",
+            name,
+            lags.get(name).map_or("?".to_string(), |lag| lag.to_string()),
+            metrics.iterations,
+            metrics.failures,
+            metrics.consecutive_failures,
+            metrics.events_written,
+            metrics.last_duration,
+            metrics.last_success.map(|at| at.elapsed()),
+            metrics
+                .last_error
+                .as_ref()
+                .map(|e| format!(", last error: {}", e))
+                .unwrap_or_default()
+        );
+    }
+}
+
+fn retry_delay(consecutive_failures: u32) -> Duration {
+    let delay = Duration::from_secs(1) * 2u32.saturating_pow(consecutive_failures.min(6));
+    delay.min(MAX_RETRY_DELAY)
+}
+
+// One chain's loop, only returns when the task is aborted
+async fn index_chain(chain: Chain, metrics: Arc<Mutex<ChainMetrics>>, bars: Option<MultiProgress>) {
+    let mut db_client: Option<Client> = None;
+    // Contract types are checked once per task, the task restarts when the config changes
+    let mut types_verified = false;
+    loop {
+        let start = Instant::now();
+
+        // Reconnect when the connection dropped, nothing else can be done without one
+        if db_client.as_ref().is_none_or(Client::is_closed) {
+            db_client = match database::connect().await.map_err(|e| e.to_string()) {
+                Ok(client) => Some(client),
+                Err(e) => {
+                    println!("[{}] Failed to connect to database: {}", chain.name, e);
+                    None
+                }
+            };
+        }
+        let result = match db_client.as_mut() {
+            Some(client) => {
+                let verified = if types_verified {
+                    Ok(())
+                } else {
+                    contract_types::verify_chain(&chain, client).await
+                };
+                match verified {
+                    Ok(()) => {
+                        types_verified = true;
+                        index_once(&chain, client, bars.as_ref()).await
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            None => Err("No database connection".to_string()),
+        };
+
+        let delay = {
+            let mut metrics = metrics.lock().expect("Metrics lock poisoned");
+            metrics.iterations += 1;
+            metrics.last_duration = Some(start.elapsed());
+            match result {
+                Ok(events_written) => {
+                    metrics.events_written += events_written as u64;
+                    metrics.consecutive_failures = 0;
+                    metrics.last_success = Some(Instant::now());
+                    MIN_ITERATION_PERIOD.saturating_sub(start.elapsed())
+                }
+                Err(e) => {
+                    eprintln!("[{}] {}", chain.name, e);
+                    metrics.failures += 1;
+                    metrics.consecutive_failures += 1;
+                    metrics.last_error = Some(e);
+                    retry_delay(metrics.consecutive_failures)
+                }
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Fetches from the chain's earliest cursor to its head and writes the results, returns
+// the number of events written
+async fn index_once(
+    chain: &Chain,
+    db_client: &mut Client,
+    bars: Option<&MultiProgress>,
+) -> Result<usize, String> {
+    // Before the cursor is read, a rewound contract takes it back to its start block
+    match sync_approvals_backfill(chain, db_client).await {
+        Ok(rewound) => {
+            for address in rewound {
+                println!(
+                    "[{}] Backfilling approvals of {} from its start block",
+                    chain.name, address
+                );
+            }
+        }
+        Err(e) => eprintln!("[{}] Failed to sync approvals backfill: {}", chain.name, e),
+    }
+
+    let block = match get_earliest_last_processed_block(chain, db_client)
+        .await
+        .map_err(|e| format!("Failed to get earliest last processed block: {}", e))?
+    {
+        Some(block) => block,
+        // Nothing left to index on the chain
+        None => return Ok(0),
+    };
+
+    let (progress_tx, mut progress_rx) = watch::channel(FetchProgress::default());
+    let event_fetcher = EventFetcher::new(chain, block as usize)
+        .map_err(|e| format!("Failed to initialize fetcher: {:?}", e))?
+        .with_progress(progress_tx);
+    let mut reporter = BackfillReporter::new(chain, bars);
+
+    // The fetch error isn't Send, so it's formatted before the select holds it
+    let fetch = async {
+        event_fetcher
+            .execute()
+            .await
+            .map_err(|e| format!("Failed to fetch events: {:?}", e))
+    };
+    tokio::pin!(fetch);
+    let result = loop {
+        tokio::select! {
+            result = &mut fetch => break result,
+            Ok(()) = progress_rx.changed() => {
+                let progress = progress_rx.borrow_and_update().clone();
+                reporter.update(&progress, db_client, false).await;
+            }
+        }
+    };
+    // Failed runs count too, they're usually the ones with RPC failures
+    let rpc_failures = rpc_pool::take_failures(&chain.rpc_endpoints());
+    if let Err(e) = record_rpc_failures(chain, &rpc_failures, db_client).await {
+        eprintln!("[{}] Failed to record RPC failures: {}", chain.name, e);
+    }
+    let (
+        (events, metadata_updates, sales, approvals, undecodable),
+        (from_block, to_block),
+        (head_block, safe_block),
+    ) = result?;
+    let progress = progress_rx.borrow().clone();
+    reporter.update(&progress, db_client, true).await;
+
+    if let Err(e) = update_chain_head(chain, head_block as u64, safe_block as u64, db_client).await
+    {
+        eprintln!("[{}] Failed to record chain head: {}", chain.name, e);
+    }
+    if let Err(e) = sync_staking_contracts(chain, db_client).await {
+        eprintln!("[{}] Failed to sync staking contracts: {}", chain.name, e);
+    }
+    if let Err(e) = sync_burn_addresses(chain, db_client).await {
+        eprintln!("[{}] Failed to sync burn addresses: {}", chain.name, e);
+    }
+    if let Err(e) = sync_rarity_models(chain, db_client).await {
+        eprintln!("[{}] Failed to sync rarity models: {}", chain.name, e);
+    }
+    if let Err(e) = sync_sink_addresses(chain, db_client).await {
+        eprintln!("[{}] Failed to sync sink addresses: {}", chain.name, e);
+    }
+    if let Err(e) = sync_contract_slugs(chain, db_client).await {
+        eprintln!("[{}] Failed to sync contract slugs: {}", chain.name, e);
+    }
+    if let Err(e) = sync_start_blocks(chain, db_client).await {
+        eprintln!("[{}] Failed to sync start blocks: {}", chain.name, e);
+    }
+
+    let mut contract_ids: HashMap<String, i32> = HashMap::new();
+    let contracts = events
+        .iter()
+        .map(|e| &e.contract)
+        .chain(metadata_updates.iter().map(|u| &u.contract))
+        .chain(sales.iter().map(|s| &s.contract))
+        .chain(approvals.iter().map(|a| &a.contract));
+    for contract in contracts {
+        if contract_ids.contains_key(&contract.address) {
+            continue;
+        }
+        let contract_id = contract_and_chain_to_contractid(contract, chain, &*db_client)
+            .await
+            .map_err(|e| format!("Failed to get contract id for {}: {}", contract.address, e))?;
+        contract_ids.insert(contract.address.clone(), contract_id);
+    }
+
+    let mut events_by_contract: HashMap<i32, Vec<Event>> = HashMap::new();
+    let mut metadata_updates_by_contract: HashMap<i32, Vec<MetadataUpdate>> = HashMap::new();
+    let mut sales_by_contract: HashMap<i32, Vec<Sale>> = HashMap::new();
+    let mut approvals_by_contract: HashMap<i32, Vec<Approval>> = HashMap::new();
+    for event in events {
+        events_by_contract
+            .entry(contract_ids[&event.contract.address])
+            .or_default()
+            .push(event);
+    }
+    for update in metadata_updates {
+        metadata_updates_by_contract
+            .entry(contract_ids[&update.contract.address])
+            .or_default()
+            .push(update);
+    }
+    for sale in sales {
+        sales_by_contract
+            .entry(contract_ids[&sale.contract.address])
+            .or_default()
+            .push(sale);
+    }
+    for approval in approvals {
+        approvals_by_contract
+            .entry(contract_ids[&approval.contract.address])
+            .or_default()
+            .push(approval);
+    }
+
+    write_events_for_chain(
+        chain,
+        &events_by_contract,
+        &metadata_updates_by_contract,
+        &sales_by_contract,
+        &approvals_by_contract,
+        &undecodable,
+        from_block as u64,
+        to_block as u64,
+        safe_block as u64,
+        db_client,
+    )
+    .await
+    .map_err(|e| format!("Failed to write events: {}", e))?;
+
+    let chain_events: Vec<&Event> = events_by_contract.values().flatten().collect();
+    webhooks::notify_transfers(db_client, chain, &chain_events).await;
+    let chain_sales: Vec<&Sale> = sales_by_contract.values().flatten().collect();
+    notifications::notify_users(db_client, chain, &chain_events, &chain_sales).await;
+    Ok(chain_events.len())
+}
+
+// Reports the progress of backfill runs to sync_progress and, when enabled, to a
+// terminal progress bar. Runs that catch up within a few chunks aren't reported.
+struct BackfillReporter<'a> {
+    chain: &'a Chain,
+    bars: Option<&'a MultiProgress>,
+    bar: Option<ProgressBar>,
+    last_write: Option<Instant>,
+}
+
+impl<'a> BackfillReporter<'a> {
+    fn new(chain: &'a Chain, bars: Option<&'a MultiProgress>) -> Self {
+        Self {
+            chain,
+            bars,
+            bar: None,
+            last_write: None,
+        }
+    }
+
+    async fn update(&mut self, progress: &FetchProgress, db_client: &Client, done: bool) {
+        // Once a run is reported, it is reported until it ends
+        let reporting = self.last_write.is_some() || self.bar.is_some();
+        if !reporting && progress.chunks_total <= BACKFILL_MIN_CHUNKS {
+            return;
+        }
+
+        if let Some(bars) = self.bars {
+            let chain = self.chain;
+            let bar = self.bar.get_or_insert_with(|| {
+                let bar = bars.add(ProgressBar::new(progress.chunks_total as u64));
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "[{prefix}] {bar:40} {pos}/{len} chunks, {elapsed} elapsed, eta {eta}",
+                    )
+                    .unwrap_or_else(|_| ProgressStyle::default_bar()),
+                );
+                bar.set_prefix(chain.name.clone());
+                bar
+            });
+            bar.set_length(progress.chunks_total as u64);
+            bar.set_position(progress.chunks_done as u64);
+            if done {
+                bar.finish();
+            }
+        }
+
+        let due = self
+            .last_write
+            .is_none_or(|at| at.elapsed() >= PROGRESS_WRITE_PERIOD);
+        if done || due {
+            if let Err(e) = update_sync_progress(self.chain, progress, db_client).await {
+                eprintln!(
+                    "[{}] Failed to record sync progress: {}",
+                    self.chain.name, e
+                );
+            }
+            self.last_write = Some(Instant::now());
+        }
+    }
+}
+
+impl Drop for BackfillReporter<'_> {
+    // A failed run leaves its bar where it stopped
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            if !bar.is_finished() {
+                bar.abandon();
+            }
+        }
+    }
+}
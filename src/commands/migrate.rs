@@ -0,0 +1,18 @@
+use crate::common::database;
+use crate::common::migrations;
+use std::process;
+
+/// `afterlife migrate`: applies the pending schema migrations
+pub async fn run() {
+    let mut client = database::connect()
+        .await
+        .expect("Failed to connect to database");
+    match migrations::run(&mut client).await {
+        Ok(applied) if applied.is_empty() => println!("Schema is up to date"),
+        Ok(applied) => println!("Applied {} migrations", applied.len()),
+        Err(e) => {
+            eprintln!("Migration failed: {}", e);
+            process::exit(1);
+        }
+    }
+}
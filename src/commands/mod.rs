@@ -0,0 +1,10 @@
+//! Entry points of the `afterlife` binary, also used by the standalone `backend` and
+//! `indexer` binaries
+pub mod backfill;
+pub mod export;
+pub mod index;
+pub mod migrate;
+pub mod reconcile;
+pub mod serve;
+pub mod verify;
+pub mod watch_metadata;
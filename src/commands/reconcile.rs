@@ -0,0 +1,136 @@
+use crate::common::database;
+use crate::indexer::queries::{
+    diff_balances, get_indexed_contracts, negative_balances, refresh_balances, IndexedContract,
+};
+use std::collections::BTreeSet;
+use std::process;
+use tokio_postgres::Client;
+
+// Rows printed per contract, the counts cover the rest
+const MAX_REPORTED_ROWS: usize = 20;
+
+/// `afterlife reconcile`: replays the events of every indexed contract, or of the ones
+/// matching `chain_name` and `contract_address`, and compares the balances with the
+/// balances table. With `repair`, the tokens that differ are rewritten from the replay.
+///
+/// Exits non-zero when differences are left unrepaired.
+pub async fn run(chain_name: Option<&str>, contract_address: Option<&str>, repair: bool) {
+    let mut client = database::connect()
+        .await
+        .expect("Failed to connect to database");
+    let contracts: Vec<IndexedContract> = match get_indexed_contracts(&client).await {
+        Ok(contracts) => contracts
+            .into_iter()
+            .filter(|contract| {
+                chain_name.is_none_or(|name| contract.chain_name.eq_ignore_ascii_case(name))
+            })
+            .filter(|contract| {
+                contract_address
+                    .is_none_or(|address| contract.address.eq_ignore_ascii_case(address))
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to list contracts: {}", e);
+            process::exit(1);
+        }
+    };
+    if contracts.is_empty() {
+        eprintln!("No indexed contract matches");
+        process::exit(1);
+    }
+
+    let mut diverging = 0;
+    let mut repaired = 0;
+    for contract in &contracts {
+        match reconcile_contract(contract, repair, &mut client).await {
+            Ok((differences, repaired_tokens)) => {
+                diverging += differences;
+                repaired += repaired_tokens;
+            }
+            Err(e) => {
+                eprintln!(
+                    "[{}] Failed to reconcile {} ({}): {}",
+                    contract.chain_name, contract.name, contract.address, e
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    println!(
+        "Checked {} contracts: {} differing balances, {} tokens repaired",
+        contracts.len(),
+        diverging,
+        repaired
+    );
+    if diverging > 0 && !repair {
+        process::exit(1);
+    }
+}
+
+// Prints the contract's differences, returns their count and the number of tokens repaired
+async fn reconcile_contract(
+    contract: &IndexedContract,
+    repair: bool,
+    client: &mut Client,
+) -> Result<(usize, usize), tokio_postgres::Error> {
+    let label = format!(
+        "[{}] {} ({})",
+        contract.chain_name, contract.name, contract.address
+    );
+
+    // Not something the table can fix, the events themselves are incomplete
+    let negative = negative_balances(contract.id, client).await?;
+    if !negative.is_empty() {
+        println!(
+            "{}: {} negative balances, events are missing",
+            label,
+            negative.len()
+        );
+        for (address, token_id, balance) in negative.iter().take(MAX_REPORTED_ROWS) {
+            println!("  token {} {}: {}", token_id, address, balance);
+        }
+    }
+
+    let differences = diff_balances(contract.id, client).await?;
+    if differences.is_empty() {
+        println!("{}: ok", label);
+        return Ok((0, 0));
+    }
+    println!("{}: {} differing balances", label, differences.len());
+    for difference in differences.iter().take(MAX_REPORTED_ROWS) {
+        println!(
+            "  token {} {}: stored {}, events {}",
+            difference.token_id,
+            difference.address,
+            difference.stored.as_deref().unwrap_or("none"),
+            difference.replayed.as_deref().unwrap_or("none")
+        );
+    }
+    if differences.len() > MAX_REPORTED_ROWS {
+        println!("  ... and {} more", differences.len() - MAX_REPORTED_ROWS);
+    }
+    if !repair {
+        return Ok((differences.len(), 0));
+    }
+
+    let token_ids: Vec<String> = differences
+        .iter()
+        .map(|difference| difference.token_id.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let transaction = client.transaction().await?;
+    // Same lock as the indexer's writes, so a run in progress can't interleave
+    transaction
+        .execute(
+            "SELECT 1 FROM contracts WHERE id = $1 FOR UPDATE",
+            &[&contract.id],
+        )
+        .await?;
+    refresh_balances(&transaction, contract.id, Some(token_ids.as_slice())).await?;
+    transaction.commit().await?;
+    println!("{}: repaired {} tokens", label, token_ids.len());
+
+    Ok((differences.len(), token_ids.len()))
+}
@@ -0,0 +1,191 @@
+use crate::backend::api::{self, get_or_update_all_users_collections, sync_leaderboard};
+use crate::backend::cache_events::{self, LEADERBOARD_CHANNEL};
+use crate::backend::queries;
+use crate::backend::seasons;
+use crate::backend::user_details_cache;
+use crate::common::database::{Database, ReplicaConfig};
+use crate::delegation::{self, DelegationConfig};
+use crate::marketplace::{self, MarketplaceConfig};
+use crate::prices::{self, PriceConfig};
+use tokio::time::{self, Duration, Instant};
+
+// Idle connections are pinged this often, so a dropped one is replaced before a request
+// needs it
+const DATABASE_HEALTH_CHECK_PERIOD: Duration = Duration::from_secs(30);
+// How often replicas check who leads, and followers read the stored leaderboard
+const LEADERSHIP_CHECK_PERIOD: Duration = Duration::from_secs(60);
+// The leader recomputes when the indexer notifies a change to a user's holdings, and this
+// often regardless, for what isn't notified (users file edited by hand, rarities) or
+// while not listening
+const LEADERBOARD_FALLBACK_REFRESH_PERIOD: Duration = Duration::from_secs(600);
+// Notifications arriving faster than this are batched into one recompute
+const LEADERBOARD_MIN_REFRESH_GAP: Duration = Duration::from_secs(5);
+
+enum RefreshTrigger {
+    Tick,
+    ScoresChanged,
+    LeaderboardStored,
+}
+
+/// `afterlife serve`: the API, with the leaderboard refresh and optional ingestion loops
+pub async fn run() {
+    println!("Starting Afterlife API, Insanity Edition");
+    let api_db = Database::connect("api")
+        .await
+        .expect("Failed to connect to API database");
+    let cache_db = Database::connect("cache")
+        .await
+        .expect("Failed to connect to Cache database");
+    api_db.spawn_health_check(DATABASE_HEALTH_CHECK_PERIOD);
+    cache_db.spawn_health_check(DATABASE_HEALTH_CHECK_PERIOD);
+
+    // API reads go to the replica when one is configured, the indexer and the background
+    // loops below keep writing to the primary
+    match ReplicaConfig::from_env() {
+        Some(Ok(config)) => {
+            if let Err(e) = api_db.attach_replica(config).await {
+                eprintln!("Read replica disabled: {}", e);
+            }
+        }
+        Some(Err(e)) => eprintln!("Read replica disabled: {}", e),
+        None => {}
+    }
+
+    // Marketplace listings are only ingested when a marketplace config is provided
+    match MarketplaceConfig::from_env() {
+        Some(Ok(config)) => {
+            // Own connection, the listing swaps run in transactions
+            let marketplace_db = Database::connect("marketplace")
+                .await
+                .expect("Failed to connect to Marketplace database");
+            tokio::spawn(async move {
+                let mut interval =
+                    time::interval(Duration::from_secs(config.refresh_seconds.max(1)));
+                loop {
+                    interval.tick().await;
+                    let client = marketplace_db.client().await;
+                    marketplace::refresh_listings(&client, &config).await;
+                }
+            });
+        }
+        Some(Err(e)) => eprintln!("Marketplace ingestion disabled: {}", e),
+        None => {}
+    }
+
+    // Native currency prices are only fetched when a price config is provided
+    match PriceConfig::from_env() {
+        Some(Ok(config)) => {
+            let prices_db = api_db.clone();
+            tokio::spawn(async move {
+                let mut interval =
+                    time::interval(Duration::from_secs(config.refresh_seconds.max(1)));
+                loop {
+                    interval.tick().await;
+                    let client = prices_db.client().await;
+                    prices::refresh_prices(&client, &config).await;
+                }
+            });
+        }
+        Some(Err(e)) => eprintln!("Prices disabled: {}", e),
+        None => {}
+    }
+
+    // Delegations are only read when a delegation config is provided
+    match DelegationConfig::from_env() {
+        Some(Ok(config)) => {
+            // Own connection, the snapshot swaps run in transactions
+            let delegation_db = Database::connect("delegation")
+                .await
+                .expect("Failed to connect to Delegation database");
+            tokio::spawn(async move {
+                let mut interval =
+                    time::interval(Duration::from_secs(config.refresh_seconds.max(1)));
+                loop {
+                    interval.tick().await;
+                    let client = delegation_db.client().await;
+                    delegation::refresh_delegations(&client, &config).await;
+                }
+            });
+        }
+        Some(Err(e)) => eprintln!("Delegations disabled: {}", e),
+        None => {}
+    }
+
+    // Indexer writes are notified: the responses they made stale are dropped right away
+    // and the leaderboard is recomputed when they changed a user's holdings
+    let signals = cache_events::spawn_listener(cache_db.clone());
+    let warmer = user_details_cache::Warmer::spawn(cache_db.clone());
+    let mut interval = time::interval(LEADERSHIP_CHECK_PERIOD);
+
+    // One API replica leads (Postgres advisory lock held by its cache connection) and
+    // computes the leaderboard, the others read what it stored. A dead leader's lock goes
+    // with its connection and the next replica to try takes over.
+    tokio::spawn(async move {
+        let mut leading = false;
+        let mut last_refresh: Option<Instant> = None;
+        loop {
+            let trigger = tokio::select! {
+                _ = interval.tick() => RefreshTrigger::Tick,
+                _ = signals.scores_changed.notified() => RefreshTrigger::ScoresChanged,
+                _ = signals.leaderboard_stored.notified() => RefreshTrigger::LeaderboardStored,
+            };
+            let client = cache_db.client().await;
+            let leader = match queries::try_lead_leaderboard(&client).await {
+                Ok(leader) => leader,
+                Err(e) => {
+                    eprintln!("Failed to check leaderboard leadership: {}", e);
+                    continue;
+                }
+            };
+            if leader != leading {
+                println!(
+                    "{} the leaderboard refresh",
+                    if leader { "Leading" } else { "Following" }
+                );
+                leading = leader;
+                // A new leader recomputes right away
+                last_refresh = None;
+            }
+
+            let result = if leader {
+                let due = match (trigger, last_refresh) {
+                    (_, None) => true,
+                    (RefreshTrigger::ScoresChanged, Some(_)) => true,
+                    (RefreshTrigger::Tick, Some(at)) => {
+                        !signals.listening() || at.elapsed() >= LEADERBOARD_FALLBACK_REFRESH_PERIOD
+                    }
+                    (RefreshTrigger::LeaderboardStored, Some(_)) => false,
+                };
+                if !due {
+                    continue;
+                }
+                if let Some(at) = last_refresh {
+                    time::sleep_until(at + LEADERBOARD_MIN_REFRESH_GAP).await;
+                }
+                last_refresh = Some(Instant::now());
+                let changed_users = signals.take_changed_users();
+                let result = get_or_update_all_users_collections(&client, true)
+                    .await
+                    .map(|_| ());
+                if result.is_ok() {
+                    // Only the users the writes affected, their lifetime points included
+                    warmer.request(changed_users);
+                    if let Err(e) = queries::notify(&client, LEADERBOARD_CHANNEL, "").await {
+                        eprintln!("Failed to notify the leaderboard update: {}", e);
+                    }
+                } else {
+                    signals.add_changed_users(changed_users.as_ref());
+                }
+                seasons::record_ended_seasons(&client).await;
+                result
+            } else {
+                sync_leaderboard(&client).await
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update cache: {:?}", e);
+            }
+        }
+    });
+
+    api::run_server(api_db).await;
+}
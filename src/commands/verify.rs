@@ -0,0 +1,67 @@
+use crate::common::database;
+use crate::common::migrations;
+use crate::common::storage::STORAGE;
+use crate::indexer::indexer_config::IndexerConfig;
+use std::env;
+use std::path::Path;
+use std::process;
+
+fn report(check: &str, result: Result<String, String>) -> bool {
+    match result {
+        Ok(detail) => {
+            println!("ok      {}: {}", check, detail);
+            true
+        }
+        Err(e) => {
+            println!("FAILED  {}: {}", check, e);
+            false
+        }
+    }
+}
+
+async fn check_dir(variable: &str) -> Result<String, String> {
+    let path = env::var(variable).map_err(|_| format!("{} is not set", variable))?;
+    STORAGE
+        .list(Path::new(&path))
+        .await
+        .map(|entries| format!("{} ({} entries)", path, entries.len()))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+/// `afterlife verify`: checks the configuration, database and storage without changing
+/// anything, exits non-zero if a check fails
+pub async fn run() {
+    let mut ok = true;
+
+    let indexer_config = match env::var("AFTERLIFE_PATH_IDXCFG") {
+        Ok(path) if !Path::new(&path).is_file() => Err(format!("{} is not a file", path)),
+        Ok(_) => IndexerConfig::from_env().map(|config| format!("{} chains", config.chains.len())),
+        Err(_) => Err("AFTERLIFE_PATH_IDXCFG is not set".to_string()),
+    };
+    ok &= report("indexer config", indexer_config);
+
+    match database::connect().await {
+        Ok(client) => {
+            ok &= report("database", Ok("connected".to_string()));
+            let schema = migrations::pending(&client)
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|pending| {
+                    if pending.is_empty() {
+                        Ok("up to date".to_string())
+                    } else {
+                        Err(format!("pending migrations {}", pending.join(", ")))
+                    }
+                });
+            ok &= report("schema", schema);
+        }
+        Err(e) => ok &= report("database", Err(e.to_string())),
+    }
+
+    ok &= report("metadata", check_dir("AFTERLIFE_PATH_METADATA").await);
+    ok &= report("rarities", check_dir("AFTERLIFE_PATH_RARITIES").await);
+
+    if !ok {
+        process::exit(1);
+    }
+}
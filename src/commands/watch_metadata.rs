@@ -0,0 +1,28 @@
+use crate::backend::metadata_store::METADATA_STORE;
+use crate::backend::reveals::{self, RevealConfig};
+use crate::common::database::Database;
+use std::process;
+
+/// `afterlife watch-metadata`: the reveal watcher without the API, for hosts that only
+/// refresh metadata. Don't also give AFTERLIFE_PATH_REVEALS to `serve` then, it would run a
+/// second watcher.
+pub async fn run() {
+    println!("Starting Afterlife metadata watcher");
+    let config = match RevealConfig::from_env() {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        None => {
+            eprintln!("AFTERLIFE_PATH_REVEALS is not set, nothing to watch");
+            process::exit(1);
+        }
+    };
+    let database = Database::connect("metadata")
+        .await
+        .expect("Failed to connect to Metadata database");
+    METADATA_STORE.attach_database(database.clone());
+
+    reveals::run_watcher(database, config).await;
+}
@@ -0,0 +1,41 @@
+/// Tokens come from this address when minted, and go back to it when burned by contracts
+/// that implement burning as a transfer to zero
+pub const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+/// The conventional address to send tokens to when burning them without a burn function
+pub const DEAD_ADDRESS: &str = "0x000000000000000000000000000000000000dead";
+
+/// Lowercased addresses a contract's tokens are burned by being sent to: the zero and dead
+/// addresses, and the contract's own `burn_addresses`
+pub fn burn_addresses(contract_burn_addresses: &[String]) -> Vec<String> {
+    let mut addresses = vec![ZERO_ADDRESS.to_string(), DEAD_ADDRESS.to_string()];
+    for address in contract_burn_addresses {
+        let address = address.to_lowercase();
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    addresses
+}
+
+/// Whether a transfer from this address is a mint
+pub fn is_mint(from_address: &str) -> bool {
+    from_address.eq_ignore_ascii_case(ZERO_ADDRESS)
+}
+
+/// Whether a transfer to this address is a burn, `contract_burn_addresses` being the
+/// contract's own burn addresses on top of the zero and dead addresses
+pub fn is_burn(to_address: &str, contract_burn_addresses: &[String]) -> bool {
+    to_address.eq_ignore_ascii_case(ZERO_ADDRESS)
+        || to_address.eq_ignore_ascii_case(DEAD_ADDRESS)
+        || contract_burn_addresses
+            .iter()
+            .any(|address| to_address.eq_ignore_ascii_case(address))
+}
+
+/// Whether a transfer to this address consumes the tokens, like sending game items to a
+/// crafting contract, `contract_sink_addresses` being the contract's sink addresses
+pub fn is_sink(to_address: &str, contract_sink_addresses: &[String]) -> bool {
+    contract_sink_addresses
+        .iter()
+        .any(|address| to_address.eq_ignore_ascii_case(address))
+}
@@ -1,24 +1,52 @@
+use crate::common::storage::STORAGE;
 use serde_json;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::io;
 use std::path::Path;
-use std::{env, fs};
-use tokio::fs::{read_to_string, File};
-use tokio::io;
-use tokio::io::{AsyncReadExt, BufReader};
 
+/// Reads a text file through the configured storage
 pub async fn read_file(path: &Path) -> io::Result<String> {
-    let file = File::open(path).await?;
-    let mut buf_reader = BufReader::new(file);
-    let mut contents = String::new();
-    buf_reader.read_to_string(&mut contents).await?;
-    Ok(contents)
+    let contents = STORAGE.read(path).await?;
+    String::from_utf8(contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn users_file_from_env() -> String {
+    env::var("AFTERLIFE_FILE_USERS").unwrap_or_else(|_| "users.json".to_owned())
+}
+
+// Reads a users file (username -> addresses) from an explicit path
+pub async fn load_users_data_from(path: &str) -> Result<HashMap<String, Vec<String>>, String> {
+    let data = read_file(Path::new(path))
+        .await
+        .map_err(|e| format!("Failed to read users file: {}", e))?;
+    serde_json::from_str(&data).map_err(|e| format!("Failed to parse users data: {}", e))
+}
+
+// Replaces a users file in one step, readers never see a partial one. Usernames are
+// written sorted to keep diffs of the file readable.
+pub async fn save_users_data_to(
+    path: &str,
+    users: &HashMap<String, Vec<String>>,
+) -> Result<(), String> {
+    let sorted: BTreeMap<&String, &Vec<String>> = users.iter().collect();
+    let data = serde_json::to_string_pretty(&sorted)
+        .map_err(|e| format!("Failed to serialize users data: {}", e))?;
+    STORAGE
+        .write(Path::new(path), data.into_bytes())
+        .await
+        .map_err(|e| format!("Failed to write users file: {}", e))
+}
+
+// Same as load_users_data, for callers that must not panic when the file is missing
+pub async fn try_load_users_data() -> Result<HashMap<String, Vec<String>>, String> {
+    load_users_data_from(&users_file_from_env()).await
 }
 
 pub async fn load_users_data() -> HashMap<String, Vec<String>> {
-    let env_users_file =
-        env::var("AFTERLIFE_FILE_USERS").unwrap_or_else(|_| "users.json".to_owned());
+    let env_users_file = users_file_from_env();
     let file_path = Path::new(&env_users_file);
-    let data = read_to_string(file_path)
+    let data = read_file(file_path)
         .await
         .expect("Failed to read users file");
     serde_json::from_str(&data).expect("Failed to parse users data")
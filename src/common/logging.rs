@@ -0,0 +1,19 @@
+use std::backtrace::Backtrace;
+use std::panic;
+
+/// Setup shared by every binary: the .env file, then a panic hook that logs panics to
+/// stderr tagged with the command, like the other errors. Panics in spawned tasks are
+/// otherwise only seen when the task is awaited.
+pub fn init(command: &'static str) {
+    dotenv::dotenv().ok();
+    panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        eprintln!(
+            "[{}] Thread {} {}\n{}",
+            command,
+            thread.name().unwrap_or("<unnamed>"),
+            info,
+            Backtrace::capture()
+        );
+    }));
+}
@@ -0,0 +1,689 @@
+use tokio_postgres::{Client, Error};
+
+/// Schema migrations, applied in order by `afterlife migrate` and recorded by name in
+/// schema_migrations. Never edit an applied migration, append a new one instead.
+///
+/// The first one creates the schema documented in indexer::queries, everything is
+/// IF NOT EXISTS so deployments created by hand can adopt migrations as they are.
+pub const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "0001_initial_schema",
+        r#"
+    CREATE TABLE IF NOT EXISTS chains (
+        id SERIAL PRIMARY KEY,
+        name VARCHAR NOT NULL,
+        rpc_url VARCHAR,
+        chunk_size INTEGER,
+        head_block INTEGER,
+        head_updated_at TIMESTAMPTZ
+    );
+
+    CREATE TABLE IF NOT EXISTS contracts (
+        id SERIAL PRIMARY KEY,
+        chain_id INTEGER NOT NULL REFERENCES chains (id),
+        name VARCHAR NOT NULL,
+        address VARCHAR NOT NULL,
+        type VARCHAR NOT NULL,
+        last_processed_block INTEGER NOT NULL,
+        last_indexed_at TIMESTAMPTZ
+    );
+
+    CREATE TABLE IF NOT EXISTS events (
+        id SERIAL PRIMARY KEY,
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        operator VARCHAR,
+        from_address VARCHAR,
+        to_address VARCHAR,
+        ids NUMERIC[] NOT NULL,
+        values NUMERIC[] NOT NULL,
+        block_number INTEGER NOT NULL,
+        transaction_hash VARCHAR NOT NULL,
+        block_timestamp TIMESTAMPTZ,
+        transaction_index INTEGER,
+        log_index INTEGER NOT NULL,
+        UNIQUE (contract_id, transaction_hash, log_index)
+    );
+    CREATE INDEX IF NOT EXISTS events_from_address ON events (LOWER(from_address));
+    CREATE INDEX IF NOT EXISTS events_to_address ON events (LOWER(to_address));
+    CREATE INDEX IF NOT EXISTS events_block_timestamp ON events (block_timestamp);
+    CREATE INDEX IF NOT EXISTS events_ids_gin ON events USING GIN (ids);
+
+    CREATE TABLE IF NOT EXISTS metadata_updates (
+        id SERIAL PRIMARY KEY,
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        token_id VARCHAR NOT NULL,
+        uri VARCHAR NOT NULL,
+        block_number INTEGER NOT NULL,
+        transaction_hash VARCHAR NOT NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+
+    CREATE TABLE IF NOT EXISTS webhooks (
+        id SERIAL PRIMARY KEY,
+        url VARCHAR NOT NULL,
+        secret VARCHAR NOT NULL,
+        username VARCHAR,
+        active BOOLEAN NOT NULL DEFAULT true,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+
+    CREATE TABLE IF NOT EXISTS webhook_deliveries (
+        id SERIAL PRIMARY KEY,
+        webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+        transaction_hash VARCHAR NOT NULL,
+        log_index INTEGER NOT NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+        UNIQUE (webhook_id, transaction_hash, log_index)
+    );
+
+    CREATE TABLE IF NOT EXISTS user_settings (
+        username VARCHAR PRIMARY KEY,
+        discord_id VARCHAR UNIQUE,
+        updated_at TIMESTAMPTZ
+    );
+
+    CREATE TABLE IF NOT EXISTS listings (
+        id SERIAL PRIMARY KEY,
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        token_id NUMERIC NOT NULL,
+        order_id VARCHAR NOT NULL,
+        maker VARCHAR NOT NULL,
+        price DOUBLE PRECISION NOT NULL,
+        currency VARCHAR NOT NULL,
+        source VARCHAR,
+        valid_until TIMESTAMPTZ,
+        updated_at TIMESTAMPTZ,
+        UNIQUE (contract_id, order_id)
+    );
+    CREATE INDEX IF NOT EXISTS listings_token ON listings (contract_id, token_id);
+
+    CREATE TABLE IF NOT EXISTS sales (
+        id SERIAL PRIMARY KEY,
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        marketplace VARCHAR NOT NULL,
+        token_id NUMERIC NOT NULL,
+        amount NUMERIC NOT NULL,
+        seller VARCHAR NOT NULL,
+        buyer VARCHAR NOT NULL,
+        price NUMERIC NOT NULL,
+        currency VARCHAR NOT NULL,
+        block_number INTEGER NOT NULL,
+        transaction_hash VARCHAR NOT NULL,
+        log_index INTEGER NOT NULL,
+        block_timestamp TIMESTAMPTZ,
+        UNIQUE (contract_id, transaction_hash, log_index)
+    );
+    CREATE INDEX IF NOT EXISTS sales_token ON sales (contract_id, token_id, block_number);
+
+    CREATE TABLE IF NOT EXISTS score_history (
+        id SERIAL PRIMARY KEY,
+        name VARCHAR NOT NULL,
+        points DOUBLE PRECISION NOT NULL,
+        rank INTEGER NOT NULL,
+        recorded_at TIMESTAMPTZ NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS score_history_recorded_at ON score_history (recorded_at);
+
+    CREATE TABLE IF NOT EXISTS exclusions (
+        id SERIAL PRIMARY KEY,
+        project VARCHAR NOT NULL,
+        value VARCHAR NOT NULL,
+        reason VARCHAR,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS exclusions_project_value
+        ON exclusions (project, LOWER(value));
+    INSERT INTO exclusions (project, value) VALUES
+        ('default', 'Danetron3030'),
+        ('default', 'AfterlifeTreasury'),
+        ('default', '0x3cc35873a61D925Ac46984f8C4F85d8fa6A892eF'),
+        ('default', 'AfterlifeCoinBank')
+    ON CONFLICT DO NOTHING;
+
+    CREATE TABLE IF NOT EXISTS labels (
+        address VARCHAR PRIMARY KEY,
+        label VARCHAR NOT NULL,
+        kind VARCHAR NOT NULL,
+        excluded BOOLEAN NOT NULL DEFAULT false,
+        updated_at TIMESTAMPTZ
+    );
+
+    CREATE TABLE IF NOT EXISTS staking_contracts (
+        id SERIAL PRIMARY KEY,
+        chain_id INTEGER NOT NULL REFERENCES chains (id),
+        name VARCHAR NOT NULL,
+        address VARCHAR NOT NULL,
+        multiplier DOUBLE PRECISION NOT NULL DEFAULT 1,
+        UNIQUE (chain_id, address)
+    );
+
+    CREATE OR REPLACE VIEW staked_balances AS
+    SELECT s.id AS staking_contract_id, e.contract_id, m.address, t.id AS token_id,
+           SUM(m.sign * t.value) AS balance
+    FROM events e
+    JOIN contracts c ON e.contract_id = c.id
+    JOIN staking_contracts s ON s.chain_id = c.chain_id
+        AND (LOWER(e.to_address) = s.address OR LOWER(e.from_address) = s.address)
+    CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value)
+    CROSS JOIN LATERAL (
+        SELECT LOWER(e.from_address) AS address, 1 AS sign WHERE LOWER(e.to_address) = s.address
+        UNION ALL
+        SELECT LOWER(e.to_address), -1 WHERE LOWER(e.from_address) = s.address
+    ) m
+    GROUP BY s.id, e.contract_id, m.address, t.id
+    HAVING SUM(m.sign * t.value) > 0;
+
+    CREATE TABLE IF NOT EXISTS delegations (
+        chain VARCHAR NOT NULL,
+        registry VARCHAR NOT NULL,
+        vault VARCHAR NOT NULL,
+        delegate VARCHAR NOT NULL,
+        updated_at TIMESTAMPTZ,
+        UNIQUE (chain, registry, vault, delegate)
+    );
+
+    CREATE TABLE IF NOT EXISTS token_metadata (
+        chain VARCHAR NOT NULL,
+        contract_address VARCHAR NOT NULL,
+        token_id NUMERIC NOT NULL,
+        metadata JSONB NOT NULL,
+        image_url VARCHAR,
+        fetched_at TIMESTAMPTZ NOT NULL,
+        UNIQUE (chain, contract_address, token_id)
+    );
+    "#,
+    ),
+    (
+        "0002_leaderboards",
+        r#"
+    CREATE TABLE IF NOT EXISTS leaderboards (
+        project VARCHAR PRIMARY KEY,
+        leaderboard JSONB NOT NULL,
+        computed_at TIMESTAMPTZ NOT NULL
+    );
+    "#,
+    ),
+    (
+        "0003_chain_safe_block",
+        r#"
+    ALTER TABLE chains ADD COLUMN IF NOT EXISTS safe_block INTEGER;
+    "#,
+    ),
+    (
+        "0004_metadata_updates_key",
+        r#"
+    DELETE FROM metadata_updates a USING metadata_updates b
+    WHERE a.contract_id = b.contract_id AND a.transaction_hash = b.transaction_hash
+        AND a.token_id = b.token_id AND a.id < b.id;
+    CREATE UNIQUE INDEX IF NOT EXISTS metadata_updates_key
+        ON metadata_updates (contract_id, transaction_hash, token_id);
+    "#,
+    ),
+    (
+        "0005_sync_progress",
+        r#"
+    CREATE TABLE IF NOT EXISTS sync_progress (
+        chain VARCHAR NOT NULL,
+        contract_address VARCHAR NOT NULL,
+        from_block INTEGER NOT NULL,
+        to_block INTEGER NOT NULL,
+        chunks_done INTEGER NOT NULL,
+        chunks_total INTEGER NOT NULL,
+        started_at TIMESTAMPTZ NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL,
+        eta TIMESTAMPTZ,
+        PRIMARY KEY (chain, contract_address)
+    );
+    "#,
+    ),
+    (
+        "0006_user_avatars",
+        r#"
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS avatar_chain VARCHAR;
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS avatar_contract VARCHAR;
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS avatar_token_id NUMERIC;
+    "#,
+    ),
+    (
+        "0007_profile_privacy",
+        r#"
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS hidden_addresses VARCHAR[] NOT NULL DEFAULT '{}';
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS hide_collection BOOLEAN NOT NULL DEFAULT false;
+    "#,
+    ),
+    (
+        "0008_api_keys",
+        r#"
+    CREATE TABLE IF NOT EXISTS api_keys (
+        id SERIAL PRIMARY KEY,
+        name VARCHAR NOT NULL,
+        key_hash VARCHAR NOT NULL UNIQUE,
+        requests_per_minute INTEGER NOT NULL,
+        active BOOLEAN NOT NULL DEFAULT true,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+        last_used_at TIMESTAMPTZ
+    );
+
+    CREATE TABLE IF NOT EXISTS api_key_usage (
+        api_key_id INTEGER NOT NULL REFERENCES api_keys (id) ON DELETE CASCADE,
+        day DATE NOT NULL,
+        requests BIGINT NOT NULL,
+        PRIMARY KEY (api_key_id, day)
+    );
+    "#,
+    ),
+    (
+        "0009_jobs",
+        r#"
+    CREATE TABLE IF NOT EXISTS jobs (
+        id SERIAL PRIMARY KEY,
+        kind VARCHAR NOT NULL,
+        params JSONB NOT NULL,
+        status VARCHAR NOT NULL DEFAULT 'queued',
+        progress_done INTEGER NOT NULL DEFAULT 0,
+        progress_total INTEGER,
+        result JSONB,
+        error VARCHAR,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+        started_at TIMESTAMPTZ,
+        finished_at TIMESTAMPTZ
+    );
+    CREATE INDEX IF NOT EXISTS jobs_queued ON jobs (id) WHERE status = 'queued';
+    "#,
+    ),
+    (
+        "0010_contract_detected_type",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS detected_type VARCHAR;
+    "#,
+    ),
+    (
+        "0011_approvals",
+        r#"
+    CREATE TABLE IF NOT EXISTS approvals (
+        id SERIAL PRIMARY KEY,
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        owner VARCHAR NOT NULL,
+        operator VARCHAR NOT NULL,
+        approved BOOLEAN NOT NULL,
+        block_number INTEGER NOT NULL,
+        transaction_hash VARCHAR NOT NULL,
+        log_index INTEGER NOT NULL,
+        block_timestamp TIMESTAMPTZ,
+        UNIQUE (contract_id, transaction_hash, log_index)
+    );
+    CREATE INDEX IF NOT EXISTS approvals_owner ON approvals (owner, contract_id, operator, block_number);
+    "#,
+    ),
+    (
+        "0012_contract_verified",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS verified BOOLEAN NOT NULL DEFAULT true;
+    "#,
+    ),
+    (
+        "0013_contract_score_weights",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS score_weight DOUBLE PRECISION NOT NULL DEFAULT 1;
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS score_normalization VARCHAR NOT NULL DEFAULT 'none';
+    "#,
+    ),
+    (
+        "0014_contract_burn_addresses",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS burn_addresses VARCHAR[] NOT NULL DEFAULT '{}';
+    "#,
+    ),
+    (
+        "0015_balances",
+        r#"
+    CREATE TABLE IF NOT EXISTS balances (
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        address VARCHAR NOT NULL,
+        token_id NUMERIC NOT NULL,
+        balance NUMERIC NOT NULL,
+        PRIMARY KEY (contract_id, token_id, address)
+    );
+    CREATE INDEX IF NOT EXISTS balances_address ON balances (address);
+
+    INSERT INTO balances (contract_id, address, token_id, balance)
+    SELECT contract_id, address, id, SUM(value)
+    FROM (
+        SELECT e.contract_id, LOWER(e.to_address) AS address, t.id, t.value
+        FROM events e
+        CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value)
+        UNION ALL
+        SELECT e.contract_id, LOWER(e.from_address), t.id, -t.value
+        FROM events e
+        CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value)
+    ) transfers
+    WHERE address IS NOT NULL
+    GROUP BY contract_id, address, id
+    HAVING SUM(value) > 0
+    ON CONFLICT DO NOTHING;
+    "#,
+    ),
+    (
+        "0016_events_archive",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS archived_at TIMESTAMPTZ;
+
+    CREATE TABLE IF NOT EXISTS events_archive (
+        id INTEGER PRIMARY KEY,
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        operator VARCHAR,
+        from_address VARCHAR,
+        to_address VARCHAR,
+        ids NUMERIC[] NOT NULL,
+        values NUMERIC[] NOT NULL,
+        block_number INTEGER NOT NULL,
+        transaction_hash VARCHAR NOT NULL,
+        block_timestamp TIMESTAMPTZ,
+        transaction_index INTEGER,
+        log_index INTEGER NOT NULL,
+        archived_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    CREATE INDEX IF NOT EXISTS events_archive_contract ON events_archive (contract_id);
+    "#,
+    ),
+    (
+        "0017_contract_rarity_tiers",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS rarity_tier_rare DOUBLE PRECISION;
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS rarity_tier_epic DOUBLE PRECISION;
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS rarity_tier_legendary DOUBLE PRECISION;
+    "#,
+    ),
+    (
+        "0018_contract_rarity_model",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS rarity_model VARCHAR;
+    "#,
+    ),
+    (
+        "0019_notification_preferences",
+        r#"
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS notify_channel VARCHAR NOT NULL DEFAULT 'none';
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS notify_target VARCHAR;
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS notify_secret VARCHAR;
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS notify_events VARCHAR NOT NULL DEFAULT 'all';
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS notify_since TIMESTAMPTZ;
+
+    CREATE TABLE IF NOT EXISTS notification_deliveries (
+        id SERIAL PRIMARY KEY,
+        username VARCHAR NOT NULL,
+        transaction_hash VARCHAR NOT NULL,
+        log_index INTEGER NOT NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+        UNIQUE (username, transaction_hash, log_index)
+    );
+    "#,
+    ),
+    (
+        "0020_rpc_failures",
+        r#"
+    CREATE TABLE IF NOT EXISTS rpc_failures (
+        chain VARCHAR NOT NULL,
+        host VARCHAR NOT NULL,
+        hour TIMESTAMPTZ NOT NULL,
+        failures BIGINT NOT NULL,
+        last_error VARCHAR NOT NULL,
+        last_failed_at TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY (chain, host, hour)
+    );
+    "#,
+    ),
+    (
+        "0021_supply_history",
+        r#"
+    CREATE TABLE IF NOT EXISTS supply_history (
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        start_block INTEGER NOT NULL,
+        end_block INTEGER NOT NULL,
+        minted NUMERIC NOT NULL,
+        burned NUMERIC NOT NULL,
+        block_timestamp TIMESTAMPTZ,
+        PRIMARY KEY (contract_id, start_block)
+    );
+
+    -- The events indexed so far, later ranges are written by the indexer
+    INSERT INTO supply_history (contract_id, start_block, end_block, minted, burned, block_timestamp)
+    SELECT contract_id, bucket, bucket + 999, minted, burned, block_timestamp FROM (
+        SELECT e.contract_id, e.block_number / 1000 * 1000 AS bucket,
+            COALESCE(SUM(t.value) FILTER (
+                WHERE LOWER(e.from_address) = '0x0000000000000000000000000000000000000000'
+            ), 0) AS minted,
+            COALESCE(SUM(t.value) FILTER (
+                WHERE LOWER(e.to_address) = ANY(c.burn_addresses || ARRAY[
+                    '0x0000000000000000000000000000000000000000',
+                    '0x000000000000000000000000000000000000dead'
+                ]::VARCHAR[])
+            ), 0) AS burned,
+            MAX(e.block_timestamp) AS block_timestamp
+        FROM events e
+        JOIN contracts c ON e.contract_id = c.id
+        CROSS JOIN LATERAL unnest(e.values) AS t(value)
+        GROUP BY e.contract_id, bucket
+    ) buckets
+    WHERE minted > 0 OR burned > 0
+    ON CONFLICT (contract_id, start_block) DO NOTHING;
+    "#,
+    ),
+    (
+        "0022_contract_sink_addresses",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS sink_addresses VARCHAR[] NOT NULL DEFAULT '{}';
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS credit_consumed BOOLEAN NOT NULL DEFAULT false;
+    "#,
+    ),
+    (
+        "0023_lifetime_scores",
+        r#"
+    CREATE TABLE IF NOT EXISTS lifetime_scores (
+        name VARCHAR PRIMARY KEY,
+        points DOUBLE PRECISION NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    "#,
+    ),
+    (
+        "0024_season_results",
+        r#"
+    CREATE TABLE IF NOT EXISTS season_results (
+        season INTEGER NOT NULL,
+        name VARCHAR NOT NULL,
+        points DOUBLE PRECISION NOT NULL,
+        rank INTEGER NOT NULL,
+        recorded_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+        PRIMARY KEY (season, name)
+    );
+    "#,
+    ),
+    (
+        "0025_teams",
+        r#"
+    CREATE TABLE IF NOT EXISTS teams (
+        id SERIAL PRIMARY KEY,
+        name VARCHAR NOT NULL,
+        self_service BOOLEAN NOT NULL DEFAULT false,
+        created_by VARCHAR,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS teams_name ON teams (LOWER(name));
+
+    CREATE TABLE IF NOT EXISTS team_members (
+        username VARCHAR PRIMARY KEY,
+        team_id INTEGER NOT NULL REFERENCES teams (id) ON DELETE CASCADE,
+        joined_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    CREATE INDEX IF NOT EXISTS team_members_team_id ON team_members (team_id);
+    "#,
+    ),
+    (
+        "0026_user_profiles_cache",
+        r#"
+    CREATE TABLE IF NOT EXISTS user_profiles_cache (
+        username VARCHAR PRIMARY KEY,
+        document JSONB NOT NULL,
+        computed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    "#,
+    ),
+    (
+        "0027_contract_slugs",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS slug VARCHAR;
+    CREATE UNIQUE INDEX IF NOT EXISTS contracts_slug ON contracts (chain_id, slug)
+        WHERE slug IS NOT NULL AND archived_at IS NULL;
+    "#,
+    ),
+    (
+        "0028_contract_start_blocks",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS start_block INTEGER;
+    "#,
+    ),
+    (
+        "0029_native_prices",
+        r#"
+    CREATE TABLE IF NOT EXISTS native_prices (
+        chain VARCHAR PRIMARY KEY,
+        coin VARCHAR NOT NULL,
+        usd DOUBLE PRECISION NOT NULL,
+        fetched_at TIMESTAMPTZ NOT NULL
+    );
+    "#,
+    ),
+    (
+        "0030_notification_email_confirmation",
+        r#"
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS notify_confirm_token VARCHAR;
+    ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS notify_confirmed_at TIMESTAMPTZ;
+    "#,
+    ),
+    (
+        "0031_job_leases",
+        r#"
+    ALTER TABLE jobs ADD COLUMN IF NOT EXISTS lease_until TIMESTAMPTZ;
+    ALTER TABLE jobs ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX IF NOT EXISTS jobs_running ON jobs (lease_until) WHERE status = 'running';
+    CREATE TABLE IF NOT EXISTS job_files (
+        job_id INTEGER PRIMARY KEY REFERENCES jobs(id) ON DELETE CASCADE,
+        contents BYTEA NOT NULL
+    );
+    "#,
+    ),
+    (
+        "0032_native_price_history",
+        r#"
+    CREATE TABLE IF NOT EXISTS native_price_history (
+        chain VARCHAR NOT NULL,
+        day DATE NOT NULL,
+        usd DOUBLE PRECISION NOT NULL,
+        PRIMARY KEY (chain, day)
+    );
+    INSERT INTO native_price_history (chain, day, usd)
+    SELECT chain, (fetched_at AT TIME ZONE 'UTC')::date, usd FROM native_prices
+    ON CONFLICT DO NOTHING;
+    "#,
+    ),
+    (
+        "0033_sales_archive",
+        r#"
+    CREATE TABLE IF NOT EXISTS sales_archive (
+        LIKE sales,
+        archived_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    CREATE INDEX IF NOT EXISTS sales_archive_contract ON sales_archive (contract_id);
+    "#,
+    ),
+    (
+        "0034_approvals_indexed",
+        r#"
+    ALTER TABLE contracts ADD COLUMN IF NOT EXISTS approvals_indexed BOOLEAN NOT NULL DEFAULT false;
+    "#,
+    ),
+    (
+        "0035_delivery_retries",
+        r#"
+    ALTER TABLE webhook_deliveries ADD COLUMN IF NOT EXISTS payload TEXT;
+    ALTER TABLE webhook_deliveries ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE webhook_deliveries ADD COLUMN IF NOT EXISTS next_attempt_at TIMESTAMPTZ;
+    ALTER TABLE webhook_deliveries ADD COLUMN IF NOT EXISTS delivered_at TIMESTAMPTZ;
+    CREATE INDEX IF NOT EXISTS webhook_deliveries_due
+        ON webhook_deliveries (next_attempt_at) WHERE next_attempt_at IS NOT NULL;
+
+    ALTER TABLE notification_deliveries ADD COLUMN IF NOT EXISTS payload TEXT;
+    ALTER TABLE notification_deliveries ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE notification_deliveries ADD COLUMN IF NOT EXISTS next_attempt_at TIMESTAMPTZ;
+    ALTER TABLE notification_deliveries ADD COLUMN IF NOT EXISTS delivered_at TIMESTAMPTZ;
+    CREATE INDEX IF NOT EXISTS notification_deliveries_due
+        ON notification_deliveries (next_attempt_at) WHERE next_attempt_at IS NOT NULL;
+    "#,
+    ),
+    (
+        "0036_delegation_blocks",
+        r#"
+    ALTER TABLE delegations ADD COLUMN IF NOT EXISTS block_number BIGINT;
+    "#,
+    ),
+    (
+        "0037_auth_sessions",
+        r#"
+    CREATE TABLE IF NOT EXISTS auth_sessions (
+        id VARCHAR PRIMARY KEY,
+        address VARCHAR NOT NULL,
+        expires_at TIMESTAMPTZ NOT NULL,
+        signed_in_at TIMESTAMPTZ,
+        revoked_at TIMESTAMPTZ
+    );
+    CREATE INDEX IF NOT EXISTS auth_sessions_address ON auth_sessions (address);
+    "#,
+    ),
+];
+
+/// Names of the migrations not applied yet, without touching the database
+pub async fn pending(client: &Client) -> Result<Vec<&'static str>, Error> {
+    let tracked: Option<String> = client
+        .query_one("SELECT to_regclass('schema_migrations')::text", &[])
+        .await?
+        .get(0);
+    let applied: Vec<String> = match tracked {
+        Some(_) => client
+            .query("SELECT name FROM schema_migrations", &[])
+            .await?
+            .into_iter()
+            .map(|row| row.get("name"))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    Ok(MIGRATIONS
+        .iter()
+        .map(|(name, _)| *name)
+        .filter(|name| !applied.iter().any(|applied| applied == name))
+        .collect())
+}
+
+/// Applies the pending migrations, each in its own transaction, returns their names
+pub async fn run(client: &mut Client) -> Result<Vec<&'static str>, Error> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (\
+                name VARCHAR PRIMARY KEY, \
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now())",
+        )
+        .await?;
+
+    let pending = pending(client).await?;
+    for (name, sql) in MIGRATIONS.iter().filter(|(name, _)| pending.contains(name)) {
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(sql).await?;
+        transaction
+            .execute("INSERT INTO schema_migrations (name) VALUES ($1)", &[name])
+            .await?;
+        transaction.commit().await?;
+        println!("Applied migration {}", name);
+    }
+    Ok(pending)
+}
@@ -1,2 +1,7 @@
+pub mod addresses;
 pub mod database;
 pub mod file_loader;
+pub mod logging;
+pub mod migrations;
+pub mod numeric;
+pub mod storage;
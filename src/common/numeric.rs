@@ -0,0 +1,130 @@
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::{AddAssign, SubAssign};
+use std::str::FromStr;
+use web3::types::U256;
+
+/// ERC-1155 token id.
+///
+/// Ids are 256-bit, so they are written to JSON as decimal strings; numbers are still
+/// accepted on input for clients that send small ids as plain integers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TokenId(pub U256);
+
+impl From<u64> for TokenId {
+    fn from(id: u64) -> Self {
+        TokenId(U256::from(id))
+    }
+}
+
+impl From<U256> for TokenId {
+    fn from(id: U256) -> Self {
+        TokenId(id)
+    }
+}
+
+impl FromStr for TokenId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        U256::from_dec_str(s.trim())
+            .map(TokenId)
+            .map_err(|e| format!("Invalid token id {}: {:?}", s, e))
+    }
+}
+
+impl fmt::Display for TokenId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // U256 displays as decimal
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for TokenId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(u64),
+            String(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(id) => Ok(TokenId::from(id)),
+            Repr::String(id) => id.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Token balance or transfer value.
+///
+/// Signed and unbounded: balances are built by adding incoming and subtracting outgoing
+/// transfers, so they can dip below zero until every event has been applied.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct Balance(pub BigDecimal);
+
+impl Balance {
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > BigDecimal::zero()
+    }
+
+    /// Lossy conversion for score arithmetic
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64().unwrap_or(0.0)
+    }
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Balance(BigDecimal::zero())
+    }
+}
+
+impl FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BigDecimal::from_str(s.trim())
+            .map(Balance)
+            .map_err(|e| format!("Invalid balance {}: {}", s, e))
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl AddAssign<&Balance> for Balance {
+    fn add_assign(&mut self, other: &Balance) {
+        self.0 += &other.0;
+    }
+}
+
+impl SubAssign<&Balance> for Balance {
+    fn sub_assign(&mut self, other: &Balance) {
+        self.0 -= &other.0;
+    }
+}
+
+impl Serialize for Balance {
+    // Plain JSON number whenever it fits, a decimal string otherwise
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.to_i64() {
+            Some(balance) => serializer.serialize_i64(balance),
+            None => serializer.collect_str(self),
+        }
+    }
+}
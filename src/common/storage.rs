@@ -0,0 +1,387 @@
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Where metadata and rarity files live. Paths are the same for every implementation,
+/// object stores use them as keys.
+pub trait Storage: Send + Sync {
+    fn read<'a>(&'a self, path: &'a Path) -> StorageFuture<'a, Vec<u8>>;
+
+    /// Replaces the file in one step, readers see either the old or the new contents
+    fn write<'a>(&'a self, path: &'a Path, contents: Vec<u8>) -> StorageFuture<'a, ()>;
+
+    /// Changes whenever the file is rewritten, NotFound if there is no such file
+    fn version<'a>(&'a self, path: &'a Path) -> StorageFuture<'a, String>;
+
+    /// Names of the files and directories right under `dir`
+    fn list<'a>(&'a self, dir: &'a Path) -> StorageFuture<'a, Vec<String>>;
+}
+
+/// Storage selected by AFTERLIFE_STORAGE, `local` (default) or `s3`
+pub static STORAGE: Lazy<Box<dyn Storage>> =
+    Lazy::new(|| match env::var("AFTERLIFE_STORAGE").as_deref() {
+        Ok("s3") => Box::new(S3Storage::from_env()),
+        Ok("local") | Err(_) => Box::new(LocalStorage),
+        Ok(other) => panic!("Unknown AFTERLIFE_STORAGE {}, expected local or s3", other),
+    });
+
+pub struct LocalStorage;
+
+impl Storage for LocalStorage {
+    fn read<'a>(&'a self, path: &'a Path) -> StorageFuture<'a, Vec<u8>> {
+        Box::pin(tokio::fs::read(path))
+    }
+
+    fn write<'a>(&'a self, path: &'a Path, contents: Vec<u8>) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            // Renamed over the live file once complete, concurrent writers get their own
+            let tmp_path = path.with_extension(format!("{}.tmp", rand::random::<u32>()));
+            tokio::fs::write(&tmp_path, contents).await?;
+            tokio::fs::rename(&tmp_path, path).await
+        })
+    }
+
+    fn version<'a>(&'a self, path: &'a Path) -> StorageFuture<'a, String> {
+        Box::pin(async move {
+            let modified = tokio::fs::metadata(path).await?.modified()?;
+            let since_epoch = modified
+                .duration_since(UNIX_EPOCH)
+                .map_err(io::Error::other)?;
+            Ok(since_epoch.as_nanos().to_string())
+        })
+    }
+
+    fn list<'a>(&'a self, dir: &'a Path) -> StorageFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            let mut names = Vec::new();
+            while let Some(entry) = entries.next_entry().await? {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+            Ok(names)
+        })
+    }
+}
+
+/// S3-compatible object storage (AWS S3, MinIO, R2...), addressed path-style and signed
+/// with AWS Signature Version 4.
+///
+/// Configured with AFTERLIFE_S3_ENDPOINT, AFTERLIFE_S3_BUCKET, AFTERLIFE_S3_REGION
+/// (default us-east-1), AFTERLIFE_S3_ACCESS_KEY_ID and AFTERLIFE_S3_SECRET_ACCESS_KEY.
+pub struct S3Storage {
+    http: reqwest::Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+fn required_env(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| panic!("Environment variable {} not set", name))
+}
+
+fn other_error(message: String) -> io::Error {
+    io::Error::other(message)
+}
+
+// RFC 3986 unreserved characters stay as they are, '/' too in paths
+fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// (YYYYMMDD, YYYYMMDDTHHMMSSZ) in UTC
+fn amz_dates(now: SystemTime) -> (String, String) {
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .expect("Clock before 1970")
+        .as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Days since 1970-01-01 to a civil date, from Howard Hinnant's date algorithms
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let date_time = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    );
+    (date, date_time)
+}
+
+// Text of every <tag>...</tag> element, good enough for the flat S3 listing documents
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        match after.find(&close) {
+            Some(end) => {
+                elements.push(&after[..end]);
+                rest = &after[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    elements
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+impl S3Storage {
+    pub fn from_env() -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            endpoint: required_env("AFTERLIFE_S3_ENDPOINT")
+                .trim_end_matches('/')
+                .to_string(),
+            bucket: required_env("AFTERLIFE_S3_BUCKET"),
+            region: env::var("AFTERLIFE_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            access_key_id: required_env("AFTERLIFE_S3_ACCESS_KEY_ID"),
+            secret_access_key: required_env("AFTERLIFE_S3_SECRET_ACCESS_KEY"),
+        }
+    }
+
+    fn key(path: &Path) -> String {
+        path.to_string_lossy()
+            .trim_start_matches("./")
+            .trim_start_matches('/')
+            .to_string()
+    }
+
+    /// Sends a signed request, `query` must already be sorted by parameter name
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        query: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> io::Result<reqwest::Response> {
+        // Bucket level requests (listings) have no key
+        let canonical_uri = if key.is_empty() {
+            format!("/{}", uri_encode(&self.bucket, false))
+        } else {
+            format!(
+                "/{}/{}",
+                uri_encode(&self.bucket, false),
+                uri_encode(key, true)
+            )
+        };
+        let canonical_query = query
+            .iter()
+            .map(|(name, value)| {
+                format!("{}={}", uri_encode(name, false), uri_encode(value, false))
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        let mut url = format!("{}{}", self.endpoint, canonical_uri);
+        if !canonical_query.is_empty() {
+            url = format!("{}?{}", url, canonical_query);
+        }
+        let parsed = reqwest::Url::parse(&url).map_err(|e| other_error(e.to_string()))?;
+        let host = match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(other_error(format!("No host in {}", url))),
+        };
+
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let (date, date_time) = amz_dates(SystemTime::now());
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            canonical_uri,
+            canonical_query,
+            host,
+            payload_hash,
+            date_time,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            date_time,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = ["s3", "aws4_request"].iter().fold(
+            hmac_sha256(
+                &hmac_sha256(format!("AWS4{}", self.secret_access_key).as_bytes(), &date),
+                &self.region,
+            ),
+            |key, part| hmac_sha256(&key, part),
+        );
+        let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+
+        let response = self
+            .http
+            .request(method, parsed)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", date_time)
+            .header(
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key_id, scope, signed_headers, signature
+                ),
+            )
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| other_error(format!("S3 request failed: {}", e)))?;
+
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            reqwest::StatusCode::NOT_FOUND => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No object {}", key),
+            )),
+            status => {
+                let body = response.text().await.unwrap_or_default();
+                Err(other_error(format!(
+                    "S3 returned {} for {}: {}",
+                    status, key, body
+                )))
+            }
+        }
+    }
+}
+
+impl Storage for S3Storage {
+    fn read<'a>(&'a self, path: &'a Path) -> StorageFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let response = self
+                .send(reqwest::Method::GET, &Self::key(path), &[], Vec::new())
+                .await?;
+            let body = response
+                .bytes()
+                .await
+                .map_err(|e| other_error(format!("S3 read failed: {}", e)))?;
+            Ok(body.to_vec())
+        })
+    }
+
+    // A PUT replaces the object atomically
+    fn write<'a>(&'a self, path: &'a Path, contents: Vec<u8>) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            self.send(reqwest::Method::PUT, &Self::key(path), &[], contents)
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn version<'a>(&'a self, path: &'a Path) -> StorageFuture<'a, String> {
+        Box::pin(async move {
+            let response = self
+                .send(reqwest::Method::HEAD, &Self::key(path), &[], Vec::new())
+                .await?;
+            response
+                .headers()
+                .get(reqwest::header::ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .map(|etag| etag.to_string())
+                .ok_or_else(|| other_error(format!("No ETag for {}", path.display())))
+        })
+    }
+
+    fn list<'a>(&'a self, dir: &'a Path) -> StorageFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut prefix = Self::key(dir);
+            if !prefix.is_empty() && !prefix.ends_with('/') {
+                prefix.push('/');
+            }
+
+            let mut names = Vec::new();
+            let mut continuation_token: Option<String> = None;
+            loop {
+                // Sorted by name for the signature
+                let mut query = Vec::new();
+                if let Some(token) = &continuation_token {
+                    query.push(("continuation-token", token.clone()));
+                }
+                query.push(("delimiter", "/".to_string()));
+                query.push(("list-type", "2".to_string()));
+                query.push(("prefix", prefix.clone()));
+
+                let response = self
+                    .send(reqwest::Method::GET, "", &query, Vec::new())
+                    .await?;
+                let xml = response
+                    .text()
+                    .await
+                    .map_err(|e| other_error(format!("S3 list failed: {}", e)))?;
+
+                let keys = xml_elements(&xml, "Key").into_iter();
+                let prefixes = xml_elements(&xml, "CommonPrefixes")
+                    .into_iter()
+                    .flat_map(|common| xml_elements(common, "Prefix"));
+                for entry in keys.chain(prefixes) {
+                    let entry = xml_unescape(entry);
+                    if let Some(name) = entry.strip_prefix(&prefix) {
+                        let name = name.trim_end_matches('/');
+                        if !name.is_empty() {
+                            names.push(name.to_string());
+                        }
+                    }
+                }
+
+                continuation_token = xml_elements(&xml, "NextContinuationToken")
+                    .first()
+                    .map(|token| xml_unescape(token));
+                if continuation_token.is_none() {
+                    break;
+                }
+            }
+            Ok(names)
+        })
+    }
+}
@@ -0,0 +1,204 @@
+pub mod queries;
+
+use crate::common::file_loader::try_load_users_data;
+use crate::delegation::queries::{replace_delegations, Delegation};
+use ethabi::{ParamType, Token};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use tokio_postgres::Client;
+use web3::transports::Http;
+use web3::types::{Address, Bytes, CallRequest};
+use web3::Web3;
+
+const DEFAULT_REFRESH_SECONDS: u64 = 600;
+
+// keccak256("getIncomingDelegations(address)")[..4], delegate.cash v2 registry
+const GET_INCOMING_DELEGATIONS_SELECTOR: [u8; 4] = [0x42, 0xf8, 0x7c, 0x25];
+// DelegationType.ALL, the whole wallet is delegated
+const DELEGATION_TYPE_ALL: u64 = 1;
+
+/// Delegation registry settings, read from the YAML file in AFTERLIFE_PATH_DELEGATION.
+/// Delegations are ignored when the variable is unset.
+///
+/// ```yaml
+/// refresh_seconds: 600
+/// registries:
+///   - chain: ethereum
+///     rpc_url: https://...
+///     address: "0x00000000000000447e69651d841bD8D104Bed493"
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct DelegationConfig {
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+    pub registries: Vec<Registry>,
+}
+
+/// A delegate.cash v2 compatible registry
+#[derive(Debug, Deserialize)]
+pub struct Registry {
+    pub chain: String,
+    pub rpc_url: String,
+    pub address: String,
+}
+
+fn default_refresh_seconds() -> u64 {
+    DEFAULT_REFRESH_SECONDS
+}
+
+impl DelegationConfig {
+    pub fn from_env() -> Option<Result<Self, String>> {
+        let path = env::var("AFTERLIFE_PATH_DELEGATION").ok()?;
+        Some(
+            fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read delegation config {}: {}", path, e))
+                .and_then(|content| {
+                    serde_yaml::from_str(&content)
+                        .map_err(|e| format!("Invalid delegation config: {}", e))
+                }),
+        )
+    }
+}
+
+// Wallet-wide delegations to `delegate`. Delegations scoped to a contract or token, or
+// limited to specific rights, don't hand over a whole collection and are skipped.
+async fn read_incoming_delegations(
+    web3: &Web3<Http>,
+    registry: Address,
+    delegate: Address,
+) -> Result<Vec<Delegation>, String> {
+    let mut data = GET_INCOMING_DELEGATIONS_SELECTOR.to_vec();
+    data.extend(ethabi::encode(&[Token::Address(delegate)]));
+
+    let output = web3
+        .eth()
+        .call(
+            CallRequest {
+                to: Some(registry),
+                data: Some(Bytes(data)),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .map_err(|e| format!("eth_call failed: {}", e))?;
+
+    // Delegation(type, to, from, rights, contract, tokenId, amount)[]
+    let delegation_type = ParamType::Tuple(vec![
+        ParamType::Uint(8),
+        ParamType::Address,
+        ParamType::Address,
+        ParamType::FixedBytes(32),
+        ParamType::Address,
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+    ]);
+    let decoded = ethabi::decode(&[ParamType::Array(Box::new(delegation_type))], &output.0)
+        .map_err(|e| format!("Invalid delegations return data: {}", e))?;
+
+    let mut delegations = Vec::new();
+    if let Some(Token::Array(entries)) = decoded.into_iter().next() {
+        for entry in entries {
+            let fields = match entry {
+                Token::Tuple(fields) => fields,
+                _ => continue,
+            };
+            match fields.as_slice() {
+                [Token::Uint(kind), Token::Address(to), Token::Address(from), Token::FixedBytes(rights), ..]
+                    if kind.as_u64() == DELEGATION_TYPE_ALL
+                        && rights.iter().all(|byte| *byte == 0) =>
+                {
+                    delegations.push(Delegation {
+                        vault: format!("{:?}", from),
+                        delegate: format!("{:?}", to),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(delegations)
+}
+
+/// Replaces the stored delegations of every registry with the wallet-wide delegations
+/// made to the addresses of the users file
+pub async fn refresh_delegations(client: &Client, config: &DelegationConfig) {
+    let users = match try_load_users_data().await {
+        Ok(users) => users,
+        Err(e) => {
+            eprintln!("Delegation: {}", e);
+            return;
+        }
+    };
+    let addresses: HashSet<Address> = users
+        .values()
+        .flatten()
+        .filter_map(|address| address.parse().ok())
+        .collect();
+
+    for registry in &config.registries {
+        let registry_address: Address = match registry.address.parse() {
+            Ok(address) => address,
+            Err(_) => {
+                eprintln!(
+                    "Delegation: [{}] invalid registry address {}",
+                    registry.chain, registry.address
+                );
+                continue;
+            }
+        };
+        let web3 = match Http::new(&registry.rpc_url) {
+            Ok(transport) => Web3::new(transport),
+            Err(e) => {
+                eprintln!("Delegation: [{}] invalid rpc url: {}", registry.chain, e);
+                continue;
+            }
+        };
+
+        // Read first, a delegation made while the registry is read gets a later block
+        let block_number = match web3.eth().block_number().await {
+            Ok(block_number) => block_number.as_u64() as i64,
+            Err(e) => {
+                eprintln!(
+                    "Delegation: [{}] failed to get the head block: {}",
+                    registry.chain, e
+                );
+                continue;
+            }
+        };
+        let mut delegations = HashSet::new();
+        let mut failed = false;
+        for address in &addresses {
+            match read_incoming_delegations(&web3, registry_address, *address).await {
+                Ok(found) => delegations.extend(found),
+                Err(e) => {
+                    eprintln!("Delegation: [{}] {:?}: {}", registry.chain, address, e);
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        // A partial snapshot would drop delegations, keep the previous one instead
+        if failed {
+            continue;
+        }
+
+        let delegations: Vec<Delegation> = delegations.into_iter().collect();
+        if let Err(e) = replace_delegations(
+            client,
+            &registry.chain,
+            &registry.address.to_lowercase(),
+            &delegations,
+            block_number,
+        )
+        .await
+        {
+            eprintln!(
+                "Delegation: [{}] failed to store delegations: {}",
+                registry.chain, e
+            );
+        }
+    }
+}
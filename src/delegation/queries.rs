@@ -0,0 +1,57 @@
+use tokio_postgres::{Client, Error};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Delegation {
+    // Lowercased, the wallet holding the tokens
+    pub vault: String,
+    // Lowercased, the wallet acting for the vault
+    pub delegate: String,
+}
+
+/// Swaps the delegations read from one registry for a fresh snapshot in one transaction.
+/// `block_number` is the block the snapshot was read at, delegations already stored keep
+/// the block they were first seen at.
+pub async fn replace_delegations(
+    client: &Client,
+    chain_name: &str,
+    registry: &str,
+    delegations: &[Delegation],
+    block_number: i64,
+) -> Result<(), Error> {
+    let vaults: Vec<&str> = delegations.iter().map(|d| d.vault.as_str()).collect();
+    let delegates: Vec<&str> = delegations.iter().map(|d| d.delegate.as_str()).collect();
+
+    client.batch_execute("BEGIN").await?;
+    let result = async {
+        client
+            .execute(
+                "DELETE FROM delegations WHERE chain = $1 AND registry = $2 \
+                AND (vault, delegate) NOT IN ( \
+                    SELECT * FROM unnest($3::varchar[], $4::varchar[]) \
+                )",
+                &[&chain_name, &registry, &vaults, &delegates],
+            )
+            .await?;
+        client
+            .execute(
+                "INSERT INTO delegations (chain, registry, vault, delegate, block_number, updated_at) \
+                SELECT $1, $2, vault, delegate, $5, now() \
+                FROM unnest($3::varchar[], $4::varchar[]) AS d (vault, delegate) \
+                ON CONFLICT (chain, registry, vault, delegate) DO UPDATE SET \
+                    block_number = COALESCE(delegations.block_number, EXCLUDED.block_number), \
+                    updated_at = now()",
+                &[&chain_name, &registry, &vaults, &delegates, &block_number],
+            )
+            .await?;
+        Ok::<_, Error>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => client.batch_execute("COMMIT").await,
+        Err(e) => {
+            client.batch_execute("ROLLBACK").await?;
+            Err(e)
+        }
+    }
+}
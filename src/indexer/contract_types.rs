@@ -0,0 +1,171 @@
+use crate::indexer::indexer_config::Chain;
+use crate::indexer::queries::{
+    contract_and_chain_to_contractid, get_detected_contract_type, set_detected_contract_type,
+};
+use crate::indexer::rpc_pool::RpcPool;
+use ethabi::Token;
+use tokio_postgres::Client;
+use web3::error::Error as Web3Error;
+use web3::types::{Address, Bytes, CallRequest};
+
+// keccak256("supportsInterface(bytes4)")[..4], also the ERC-165 interface id
+const SUPPORTS_INTERFACE_SELECTOR: [u8; 4] = [0x01, 0xff, 0xc9, 0xa7];
+// Must not be supported by an ERC-165 contract
+const INVALID_INTERFACE_ID: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+const ERC721_INTERFACE_ID: [u8; 4] = [0x80, 0xac, 0x58, 0xcd];
+const ERC1155_INTERFACE_ID: [u8; 4] = [0xd9, 0xb6, 0x7a, 0x26];
+
+// Stored for contracts that don't implement ERC-165 or neither standard, so they aren't
+// probed again
+pub const UNKNOWN_TYPE: &str = "unknown";
+
+// A revert means the call was answered, just not by a supportsInterface. Nodes answer
+// reverts with code 3 (EIP-1474 execution error) or at least say so in the message.
+fn is_revert(err: &Web3Error) -> bool {
+    match err {
+        Web3Error::Rpc(rpc_error) => {
+            rpc_error.code.code() == 3 || rpc_error.message.to_lowercase().contains("revert")
+        }
+        _ => false,
+    }
+}
+
+// A revert or an empty return means not supported. Any other failure is an error, tried
+// on the next endpoint, so a flaky provider is never taken for a contract without ERC-165.
+async fn supports_interface(
+    rpc: &RpcPool,
+    contract_address: Address,
+    interface_id: [u8; 4],
+) -> Result<bool, Web3Error> {
+    let mut data = SUPPORTS_INTERFACE_SELECTOR.to_vec();
+    data.extend(ethabi::encode(&[Token::FixedBytes(interface_id.to_vec())]));
+    let request = CallRequest {
+        to: Some(contract_address),
+        data: Some(Bytes(data)),
+        ..Default::default()
+    };
+
+    let mut last_error = None;
+    for _ in 0..rpc.len() {
+        let (endpoint, web3, permit) = rpc.pick().await;
+        let result = web3.eth().call(request.clone(), None).await;
+        drop(permit);
+        match result {
+            // A bool is one 32 byte word, the last byte set
+            Ok(output) => {
+                rpc.report_success(endpoint);
+                return Ok(output.0.len() >= 32 && output.0[31] == 1);
+            }
+            Err(e) if is_revert(&e) => {
+                rpc.report_success(endpoint);
+                return Ok(false);
+            }
+            Err(e) => {
+                rpc.report_failure(endpoint, &e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.expect("RPC pools have at least one endpoint"))
+}
+
+/// The token standard a contract implements according to ERC-165: "erc721", "erc1155" or
+/// UNKNOWN_TYPE
+pub async fn detect(rpc: &RpcPool, contract_address: Address) -> Result<String, Web3Error> {
+    // The check from the ERC-165 spec, contracts answering true to anything don't count
+    if !supports_interface(rpc, contract_address, SUPPORTS_INTERFACE_SELECTOR).await?
+        || supports_interface(rpc, contract_address, INVALID_INTERFACE_ID).await?
+    {
+        return Ok(UNKNOWN_TYPE.to_string());
+    }
+    if supports_interface(rpc, contract_address, ERC721_INTERFACE_ID).await? {
+        return Ok("erc721".to_string());
+    }
+    if supports_interface(rpc, contract_address, ERC1155_INTERFACE_ID).await? {
+        return Ok("erc1155".to_string());
+    }
+    Ok(UNKNOWN_TYPE.to_string())
+}
+
+/// Detects the standard of the chain's contracts that weren't detected yet, stores it on
+/// their contracts row and compares it with the configured `type`. Mismatches are logged,
+/// or returned as an error when the chain has `strict_contract_types` set.
+///
+/// Contracts already detected aren't called again, so this is cheap to run on every
+/// start of the chain's indexing task.
+pub async fn verify_chain(chain: &Chain, client: &Client) -> Result<(), String> {
+    let endpoints = chain.rpc_endpoints();
+    let rpc = if endpoints.is_empty() {
+        None
+    } else {
+        Some(
+            RpcPool::new(
+                &endpoints,
+                chain.max_concurrent_requests,
+                chain.requests_per_second,
+            )
+            .map_err(|e| format!("Invalid RPC url: {}", e))?,
+        )
+    };
+
+    let mut mismatches = Vec::new();
+    for contract in &chain.contracts {
+        let contract_id = contract_and_chain_to_contractid(contract, chain, client)
+            .await
+            .map_err(|e| format!("Failed to get contract id for {}: {}", contract.address, e))?;
+        let stored = get_detected_contract_type(contract_id, client)
+            .await
+            .map_err(|e| format!("Failed to get detected type of {}: {}", contract.address, e))?;
+
+        let detected = match (stored, &rpc, contract.address.parse::<Address>()) {
+            (Some(stored), _, _) => stored,
+            (None, Some(rpc), Ok(address)) => match detect(rpc, address).await {
+                Ok(detected) => {
+                    set_detected_contract_type(contract_id, &detected, client)
+                        .await
+                        .map_err(|e| {
+                            format!(
+                                "Failed to store detected type of {}: {}",
+                                contract.address, e
+                            )
+                        })?;
+                    println!(
+                        "[{}] {} ({}) detected as {}",
+                        chain.name, contract.name, contract.address, detected
+                    );
+                    detected
+                }
+                // Not stored, detected again on the next start
+                Err(e) => {
+                    eprintln!(
+                        "[{}] Failed to detect the type of {} ({}): {}",
+                        chain.name, contract.name, contract.address, e
+                    );
+                    continue;
+                }
+            },
+            _ => continue,
+        };
+
+        if detected != UNKNOWN_TYPE && !detected.eq_ignore_ascii_case(&contract.r#type) {
+            mismatches.push(format!(
+                "{} ({}) is configured as {} but implements {}",
+                contract.name, contract.address, contract.r#type, detected
+            ));
+        }
+    }
+
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    if chain.strict_contract_types {
+        return Err(format!(
+            "Refusing to index, contract types disagree with the chain: {}",
+            mismatches.join("; ")
+        ));
+    }
+    for mismatch in mismatches {
+        eprintln!("[{}] Warning: {}", chain.name, mismatch);
+    }
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use crate::common::database;
+use crate::indexer::notifications::{self, email_text, Channel, SmtpRelay};
+use crate::indexer::webhooks::{self, pinned_client, resolve_public_url, send_signed};
+use futures::stream::{self, StreamExt};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio_postgres::{Client, Row};
+
+// How often the queues are checked for deliveries that are due
+const POLL_PERIOD: Duration = Duration::from_secs(2);
+// Deliveries claimed from each queue at once
+const CLAIM_BATCH_SIZE: i64 = 100;
+const SEND_CONCURRENCY: usize = 16;
+/// Attempts of a delivery before it's given up on
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 8;
+// Doubled after every failed attempt: 30s, 1m, 2m, ... 32m
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+const WEBHOOK_QUEUE: &str = "webhook_deliveries";
+const NOTIFICATION_QUEUE: &str = "notification_deliveries";
+
+enum Outcome {
+    Delivered,
+    Failed(String),
+    // The hook was deactivated or the user turned notifications off since
+    Dropped,
+}
+
+/// Sends the transfers queued in webhook_deliveries and notification_deliveries, forever.
+/// A failed delivery is tried again with backoff, up to `MAX_DELIVERY_ATTEMPTS` times, so
+/// a recipient that's down for a while still gets its transfers once it's back.
+pub async fn run() {
+    let smtp = SmtpRelay::from_env();
+    let mut db_client: Option<Client> = None;
+
+    loop {
+        if db_client.as_ref().is_none_or(Client::is_closed) {
+            db_client = match database::connect().await.map_err(|e| e.to_string()) {
+                Ok(client) => Some(client),
+                Err(e) => {
+                    eprintln!("Delivery sender failed to connect to database: {}", e);
+                    None
+                }
+            };
+        }
+        if let Some(client) = &db_client {
+            if let Err(e) = send_due(client, smtp.as_ref()).await {
+                eprintln!("Failed to send the queued deliveries: {}", e);
+            }
+        }
+        tokio::time::sleep(POLL_PERIOD).await;
+    }
+}
+
+/// Sends the deliveries that are due once, returns how many were attempted
+pub async fn send_due(
+    client: &Client,
+    smtp: Option<&SmtpRelay>,
+) -> Result<usize, tokio_postgres::Error> {
+    Ok(send_due_webhooks(client).await? + send_due_notifications(client, smtp).await?)
+}
+
+async fn send_due_webhooks(client: &Client) -> Result<usize, tokio_postgres::Error> {
+    let due = claim_due(client, WEBHOOK_QUEUE).await?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+    let webhooks: HashMap<i32, webhooks::Webhook> = webhooks::get_active_webhooks(client)
+        .await?
+        .into_iter()
+        .map(|webhook| (webhook.id, webhook))
+        .collect();
+
+    let http = reqwest::Client::new();
+    let outcomes: Vec<(i32, i32, String, Outcome)> = stream::iter(due)
+        .map(|row| {
+            let webhook = webhooks.get(&row.get::<_, i32>("webhook_id")).cloned();
+            let http = http.clone();
+            async move {
+                let label = format!("Webhook {}", row.get::<_, i32>("webhook_id"));
+                let outcome = match webhook {
+                    Some(webhook) => {
+                        let payload: String = row.get("payload");
+                        match send_signed(&http, &webhook.url, &webhook.secret, &payload).await {
+                            Ok(()) => Outcome::Delivered,
+                            Err(e) => Outcome::Failed(e),
+                        }
+                    }
+                    None => Outcome::Dropped,
+                };
+                (row.get("id"), row.get("attempts"), label, outcome)
+            }
+        })
+        .buffer_unordered(SEND_CONCURRENCY)
+        .collect()
+        .await;
+
+    record_outcomes(client, WEBHOOK_QUEUE, outcomes).await
+}
+
+async fn send_due_notifications(
+    client: &Client,
+    smtp: Option<&SmtpRelay>,
+) -> Result<usize, tokio_postgres::Error> {
+    let due = claim_due(client, NOTIFICATION_QUEUE).await?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+    // The channel as it is now, the user may have changed it since the transfer was queued
+    let channels: HashMap<String, Channel> = notifications::get_notification_preferences(client)
+        .await?
+        .into_iter()
+        .map(|preference| (preference.username, preference.channel))
+        .collect();
+
+    let outcomes: Vec<(i32, i32, String, Outcome)> = stream::iter(due)
+        .map(|row| {
+            let username: String = row.get("username");
+            let channel = channels.get(&username).cloned();
+            async move {
+                let payload: String = row.get("payload");
+                let outcome = match channel {
+                    Some(channel) => match notify(&channel, &payload, smtp).await {
+                        Ok(()) => Outcome::Delivered,
+                        Err(e) => Outcome::Failed(e),
+                    },
+                    None => Outcome::Dropped,
+                };
+                let label = format!("Notification of {}", username);
+                (row.get("id"), row.get("attempts"), label, outcome)
+            }
+        })
+        .buffer_unordered(SEND_CONCURRENCY)
+        .collect()
+        .await;
+
+    record_outcomes(client, NOTIFICATION_QUEUE, outcomes).await
+}
+
+async fn notify(channel: &Channel, payload: &str, smtp: Option<&SmtpRelay>) -> Result<(), String> {
+    match channel {
+        Channel::Webhook { url, secret } => {
+            // The URL was checked when saved, but what its host resolves to may have
+            // changed since
+            let http = resolve_public_url(url)
+                .await
+                .and_then(|address| pinned_client(url, address))?;
+            send_signed(&http, url, secret, payload).await
+        }
+        Channel::Email(address) => {
+            let smtp = smtp.ok_or("No SMTP relay configured")?;
+            let payload: Value = serde_json::from_str(payload)
+                .map_err(|e| format!("Invalid queued payload: {}", e))?;
+            let (subject, text) = email_text(&payload);
+            smtp.send(address, &subject, text).await
+        }
+    }
+}
+
+// Takes the due deliveries of the queue and schedules their next attempt right away, so a
+// sender that dies mid-delivery leaves them to be tried again rather than lost, and other
+// replicas skip them meanwhile
+async fn claim_due(client: &Client, queue: &str) -> Result<Vec<Row>, tokio_postgres::Error> {
+    client
+        .query(
+            &format!(
+                "UPDATE {queue} d SET attempts = d.attempts + 1, \
+                next_attempt_at = CASE WHEN d.attempts + 1 >= $2 THEN NULL \
+                ELSE now() + make_interval(secs => $3 * power(2, d.attempts)) END \
+                WHERE d.id IN ( \
+                    SELECT id FROM {queue} WHERE next_attempt_at <= now() \
+                    ORDER BY next_attempt_at LIMIT $1 FOR UPDATE SKIP LOCKED \
+                ) \
+                RETURNING d.*",
+                queue = queue
+            ),
+            &[
+                &CLAIM_BATCH_SIZE,
+                &MAX_DELIVERY_ATTEMPTS,
+                &INITIAL_RETRY_DELAY.as_secs_f64(),
+            ],
+        )
+        .await
+}
+
+async fn record_outcomes(
+    client: &Client,
+    queue: &str,
+    outcomes: Vec<(i32, i32, String, Outcome)>,
+) -> Result<usize, tokio_postgres::Error> {
+    let count = outcomes.len();
+    let mut delivered = Vec::new();
+    let mut dropped = Vec::new();
+    for (id, attempts, label, outcome) in outcomes {
+        match outcome {
+            Outcome::Delivered => delivered.push(id),
+            Outcome::Dropped => dropped.push(id),
+            Outcome::Failed(e) if attempts >= MAX_DELIVERY_ATTEMPTS => {
+                eprintln!("{} failed after {} attempts: {}", label, attempts, e)
+            }
+            Outcome::Failed(e) => eprintln!("{} failed, attempt {}: {}", label, attempts, e),
+        }
+    }
+
+    client
+        .execute(
+            &format!(
+                "UPDATE {} SET next_attempt_at = NULL, payload = NULL, delivered_at = now() \
+                WHERE id = ANY($1)",
+                queue
+            ),
+            &[&delivered],
+        )
+        .await?;
+    client
+        .execute(
+            &format!(
+                "UPDATE {} SET next_attempt_at = NULL WHERE id = ANY($1)",
+                queue
+            ),
+            &[&dropped],
+        )
+        .await?;
+    Ok(count)
+}
@@ -1,4 +1,6 @@
+use crate::backend::rarity_models;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::env;
 use std::fs::File;
 use std::io::{BufReader, Read};
@@ -7,9 +9,76 @@ use std::io::{BufReader, Read};
 pub struct Chain {
     pub id: u32,
     pub name: String,
+    #[serde(default)]
     pub rpc_url: String,
+    // Additional providers to fail over to, tried in round-robin order with rpc_url
+    #[serde(default)]
+    pub rpc_urls: Vec<String>,
+    // Initial block range per eth_getLogs call, adjusted at runtime by the fetcher
+    #[serde(default = "default_chunk_size")]
     pub chunk_size: usize,
     pub contracts: Vec<Contract>,
+    // Marketplace contracts whose sale events are decoded for the configured contracts
+    #[serde(default)]
+    pub marketplaces: Vec<Marketplace>,
+    // Contracts holding staked tokens on behalf of their depositors
+    #[serde(default)]
+    pub staking: Vec<StakingContract>,
+    // Also index the contracts' ApprovalForAll events, for the approvals security panel
+    #[serde(default)]
+    pub index_approvals: bool,
+    // Blocks are final once this many blocks deep, ignored when `finalized` is set
+    #[serde(default = "default_confirmations")]
+    pub confirmations: usize,
+    // Use the RPC's `finalized` block tag instead of a confirmation count
+    #[serde(default)]
+    pub finalized: bool,
+    // RPC requests in flight at once, across all providers
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    // RPC requests started per second on each provider, unlimited when unset
+    #[serde(default)]
+    pub requests_per_second: Option<f64>,
+    // Alert when the indexed cursor falls this many blocks behind the head, confirmations
+    // included
+    #[serde(default = "default_max_lag_blocks")]
+    pub max_lag_blocks: u64,
+    // Refuse to index the chain while a contract's `type` disagrees with the standard its
+    // ERC-165 interfaces say it implements, instead of only warning
+    #[serde(default)]
+    pub strict_contract_types: bool,
+}
+
+fn default_chunk_size() -> usize {
+    2000
+}
+
+fn default_confirmations() -> usize {
+    2
+}
+
+fn default_max_concurrent_requests() -> usize {
+    80
+}
+
+fn default_max_lag_blocks() -> u64 {
+    1000
+}
+
+impl Chain {
+    // All configured RPC endpoints, rpc_url first
+    pub fn rpc_endpoints(&self) -> Vec<String> {
+        let mut endpoints = Vec::new();
+        if !self.rpc_url.is_empty() {
+            endpoints.push(self.rpc_url.clone());
+        }
+        for url in &self.rpc_urls {
+            if !endpoints.contains(url) {
+                endpoints.push(url.clone());
+            }
+        }
+        endpoints
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -18,6 +87,69 @@ pub struct Contract {
     pub address: String,
     pub startblock: i32,
     pub r#type: String,
+    // Layout of the Transfer event, for ERC-721 contracts predating the final standard
+    #[serde(default)]
+    pub abi_variant: AbiVariant,
+    // Addresses other than zero and dead that the collection burns tokens by sending them to
+    #[serde(default)]
+    pub burn_addresses: Vec<String>,
+    // How the API scores the rarity of the tokens, trait_sum when not set
+    #[serde(default)]
+    pub rarity_model: Option<String>,
+    // Addresses that consume the tokens sent to them, such as a crafting contract
+    #[serde(default)]
+    pub sink_addresses: Vec<String>,
+    // Whether tokens sent to a sink still count toward the sender's leaderboard score
+    #[serde(default)]
+    pub credit_consumed: bool,
+    // Name the API also accepts in place of the address in URLs, /ethereum/relics/collection
+    #[serde(default)]
+    pub slug: Option<String>,
+}
+
+/// Which params of an ERC-721 Transfer(from, to, tokenId) are indexed
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AbiVariant {
+    // from, to and tokenId are all topics (EIP-721)
+    #[default]
+    Standard,
+    // from and to are topics, tokenId is in the data (early drafts, CryptoPunks-like contracts)
+    DataTokenId,
+    // Nothing is indexed, from, to and tokenId are all in the data (e.g. CryptoKitties)
+    Unindexed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MarketplaceProtocol {
+    // OrderFulfilled events (OpenSea and other Seaport deployments)
+    Seaport,
+    // TakerAsk / TakerBid events of the LooksRare v1 exchange
+    LooksRare,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Marketplace {
+    pub name: String,
+    pub address: String,
+    pub protocol: MarketplaceProtocol,
+}
+
+/// A custodial staking contract: deposits and withdrawals are the Transfer events of the
+/// indexed contracts into and out of `address`, which credit the tokens back to the
+/// depositor in the staked_balances view.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StakingContract {
+    pub name: String,
+    pub address: String,
+    // Score multiplier of staked tokens
+    #[serde(default = "default_staking_multiplier")]
+    pub multiplier: f64,
+}
+
+fn default_staking_multiplier() -> f64 {
+    1.0
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,7 +158,7 @@ pub struct IndexerConfig {
 }
 
 impl IndexerConfig {
-    pub fn from_env() -> Result<Self, serde_yaml::Error> {
+    pub fn from_env() -> Result<Self, String> {
         let path = env::var("AFTERLIFE_PATH_IDXCFG")
             .expect("Environment variable AFTERLIFE_PATH_IDXCFG not set");
         let file = File::open(&path).expect("Failed to open file");
@@ -35,7 +167,48 @@ impl IndexerConfig {
         buf_reader
             .read_to_string(&mut content)
             .expect("Failed to read file");
-        serde_yaml::from_str(&content)
+        let config: IndexerConfig = serde_yaml::from_str(&content).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks what the types alone can't, a config failing it isn't loaded
+    pub fn validate(&self) -> Result<(), String> {
+        for chain in &self.chains {
+            if let Some(per_second) = chain.requests_per_second {
+                // Also rejects NaN
+                if !(per_second > 0.0 && per_second.is_finite()) {
+                    return Err(format!(
+                        "requests_per_second of {} must be a positive number, got {}",
+                        chain.name, per_second
+                    ));
+                }
+            }
+            let mut slugs = HashSet::new();
+            for slug in chain.contracts.iter().filter_map(|c| c.slug.as_ref()) {
+                if !slugs.insert(slug.to_lowercase()) {
+                    return Err(format!(
+                        "Slug {} is used by several contracts of {}",
+                        slug, chain.name
+                    ));
+                }
+            }
+            // The API would only find out once asked to recompute the rarities
+            for contract in &chain.contracts {
+                if let Some(name) = &contract.rarity_model {
+                    if rarity_models::model(name).is_none() {
+                        return Err(format!(
+                            "Unknown rarity_model {} of {} on {}, expected one of {}",
+                            name,
+                            contract.name,
+                            chain.name,
+                            rarity_models::model_names().join(", ")
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 
     pub fn get_earliest_start_block_for_chain(&self, chain: &Chain) -> i32 {
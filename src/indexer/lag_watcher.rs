@@ -0,0 +1,135 @@
+use crate::common::database;
+use crate::indexer::indexer_config::{Chain, IndexerConfig};
+use crate::indexer::queries::get_chain_cursor;
+use crate::indexer::rpc_pool::RpcPool;
+use crate::indexer::webhooks;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_postgres::Client;
+
+const CHECK_PERIOD: Duration = Duration::from_secs(60);
+// A chain that stays behind is alerted again this often
+const ALERT_REPEAT_PERIOD: Duration = Duration::from_secs(30 * 60);
+
+/// Blocks between each chain's head and its indexed cursor as of the last check, by name
+pub type ChainLags = Arc<Mutex<HashMap<String, u64>>>;
+
+/// Compares every chain's cursor to its live head every minute. Chains further behind than
+/// their `max_lag_blocks` are logged and posted to the global webhooks, so a stalled
+/// indexer is noticed even when it stalls without erroring.
+pub async fn run(lags: ChainLags) {
+    // Chain name -> time of the last alert, for the chains currently behind
+    let mut alerted: HashMap<String, Instant> = HashMap::new();
+    let mut db_client: Option<Client> = None;
+
+    loop {
+        if db_client.as_ref().is_none_or(Client::is_closed) {
+            db_client = match database::connect().await.map_err(|e| e.to_string()) {
+                Ok(client) => Some(client),
+                Err(e) => {
+                    eprintln!("Lag watcher failed to connect to database: {}", e);
+                    None
+                }
+            };
+        }
+        let config = IndexerConfig::from_env();
+
+        match (&db_client, config) {
+            (Some(client), Ok(config)) => {
+                for chain in &config.chains {
+                    check_chain(chain, client, &lags, &mut alerted).await;
+                }
+            }
+            (_, Err(e)) => eprintln!("Lag watcher failed to load indexer config: {}", e),
+            (None, _) => {}
+        }
+
+        tokio::time::sleep(CHECK_PERIOD).await;
+    }
+}
+
+async fn check_chain(
+    chain: &Chain,
+    client: &Client,
+    lags: &ChainLags,
+    alerted: &mut HashMap<String, Instant>,
+) {
+    let (head_block, cursor) = match chain_position(chain, client).await {
+        Ok(Some(position)) => position,
+        // Nothing indexed yet
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("[{}] Lag check failed: {}", chain.name, e);
+            return;
+        }
+    };
+    let lag = head_block.saturating_sub(cursor);
+    lags.lock()
+        .expect("Lags lock poisoned")
+        .insert(chain.name.clone(), lag);
+
+    if lag > chain.max_lag_blocks {
+        let due = alerted
+            .get(&chain.name)
+            .is_none_or(|at| at.elapsed() >= ALERT_REPEAT_PERIOD);
+        if due {
+            eprintln!(
+                "[{}] WARNING: indexer is {} blocks behind the head ({} < {}), threshold {}",
+                chain.name, lag, cursor, head_block, chain.max_lag_blocks
+            );
+            webhooks::notify_chain_lag(
+                client,
+                chain,
+                head_block,
+                cursor,
+                chain.max_lag_blocks,
+                false,
+            )
+            .await;
+            alerted.insert(chain.name.clone(), Instant::now());
+        }
+    } else if alerted.remove(&chain.name).is_some() {
+        println!(
+            "[{}] Indexer caught up, {} blocks behind the head",
+            chain.name, lag
+        );
+        webhooks::notify_chain_lag(
+            client,
+            chain,
+            head_block,
+            cursor,
+            chain.max_lag_blocks,
+            true,
+        )
+        .await;
+    }
+}
+
+// The chain's live head and its indexed cursor
+async fn chain_position(chain: &Chain, client: &Client) -> Result<Option<(u64, u64)>, String> {
+    let cursor = match get_chain_cursor(chain, client)
+        .await
+        .map_err(|e| format!("Failed to get cursor: {}", e))?
+    {
+        Some(cursor) => cursor.max(0) as u64,
+        None => return Ok(None),
+    };
+
+    let rpc = RpcPool::new(&chain.rpc_endpoints(), 1, None).map_err(|e| e.to_string())?;
+    let (endpoint, web3, permit) = rpc.pick().await;
+    let result = web3.eth().block_number().await;
+    drop(permit);
+    let head_block = match result {
+        Ok(head_block) => head_block.as_u64(),
+        Err(e) => {
+            return Err(format!(
+                "Failed to fetch head from {}: {}",
+                rpc.url(endpoint),
+                e
+            ))
+        }
+    };
+
+    Ok(Some((head_block, cursor)))
+}
@@ -1,6 +1,7 @@
 extern crate ethabi;
-use ethabi::{Event, RawLog, Token};
-use web3::types::{Log, U256};
+use crate::indexer::indexer_config::AbiVariant;
+use ethabi::{Event, ParamType, RawLog, Token};
+use web3::types::{Log, H160, U256};
 
 fn token_to_u256(token: &Token) -> Option<U256> {
     if let Token::Uint(value) = token {
@@ -10,6 +11,58 @@ fn token_to_u256(token: &Token) -> Option<U256> {
     }
 }
 
+// Decodes an ERC-721 Transfer into (from, to, tokenId), reading each param from the topics
+// or the data depending on which of them the contract indexes
+pub(crate) fn decode_erc721_transfer(
+    log: &Log,
+    variant: AbiVariant,
+) -> Result<(H160, H160, U256), ethabi::Error> {
+    let (addresses_indexed, token_id_indexed) = match variant {
+        AbiVariant::Standard => (true, true),
+        AbiVariant::DataTokenId => (true, false),
+        AbiVariant::Unindexed => (false, false),
+    };
+    let event = Event {
+        name: "Transfer".into(),
+        inputs: vec![
+            ethabi::EventParam {
+                name: "from".into(),
+                kind: ethabi::ParamType::Address,
+                indexed: addresses_indexed,
+            },
+            ethabi::EventParam {
+                name: "to".into(),
+                kind: ethabi::ParamType::Address,
+                indexed: addresses_indexed,
+            },
+            ethabi::EventParam {
+                name: "tokenId".into(),
+                kind: ethabi::ParamType::Uint(256),
+                indexed: token_id_indexed,
+            },
+        ],
+        anonymous: false,
+    };
+
+    // parse_log checks the topic count, a log of another layout fails instead of panicking
+    let raw_log = RawLog {
+        topics: log.topics.clone(),
+        data: log.data.0.clone(),
+    };
+    let decoded = event.parse_log(raw_log)?;
+
+    let address_at = |index: usize| {
+        decoded.params[index]
+            .value
+            .clone()
+            .into_address()
+            .ok_or(ethabi::Error::InvalidData)
+    };
+    let token_id = token_to_u256(&decoded.params[2].value).ok_or(ethabi::Error::InvalidData)?;
+
+    Ok((address_at(0)?, address_at(1)?, token_id))
+}
+
 // Function to decode batch event using predefined ABI
 pub(crate) fn decode_erc1155_transfer_batch(
     log: &Log,
@@ -132,3 +185,241 @@ pub(crate) fn decode_erc1155_transfer_single(log: &Log) -> Result<(U256, U256),
 
     Ok((id, value))
 }
+
+// Function to decode ERC1155 URI event using predefined ABI
+pub(crate) fn decode_erc1155_uri(log: &Log) -> Result<(U256, String), ethabi::Error> {
+    // Define the ERC1155 URI event signature
+    let event = Event {
+        name: "URI".into(),
+        inputs: vec![
+            ethabi::EventParam {
+                name: "value".into(),
+                kind: ethabi::ParamType::String,
+                indexed: false,
+            },
+            ethabi::EventParam {
+                name: "id".into(),
+                kind: ethabi::ParamType::Uint(256),
+                indexed: true,
+            },
+        ],
+        anonymous: false,
+    };
+
+    // Create a RawLog from the log's topics and data
+    let raw_log = RawLog {
+        topics: log.topics.clone(),
+        data: log.data.0.clone(),
+    };
+
+    // Decode the log
+    let decoded = event.parse_log(raw_log)?;
+
+    // Extract the 'value' and 'id' from the tokens
+    let uri = if let Token::String(uri) = &decoded.params[0].value {
+        uri.clone()
+    } else {
+        return Err(ethabi::Error::InvalidData);
+    };
+
+    let id = if let Some(id_u256) = token_to_u256(&decoded.params[1].value) {
+        id_u256
+    } else {
+        return Err(ethabi::Error::InvalidData);
+    };
+
+    Ok((id, uri))
+}
+
+// ApprovalForAll(owner, operator, approved), the same event in ERC-721 and ERC-1155
+pub(crate) fn decode_approval_for_all(log: &Log) -> Result<(H160, H160, bool), ethabi::Error> {
+    let owner = topic_to_address(log, 1)?;
+    let operator = topic_to_address(log, 2)?;
+    let approved = ethabi::decode(&[ParamType::Bool], &log.data.0)?
+        .pop()
+        .and_then(|token| token.into_bool())
+        .ok_or(ethabi::Error::InvalidData)?;
+    Ok((owner, operator, approved))
+}
+
+// A single-token marketplace trade, before it is matched to a configured contract
+#[derive(Debug, Clone)]
+pub(crate) struct DecodedSale {
+    pub collection: H160,
+    pub token_id: U256,
+    pub amount: U256,
+    pub seller: H160,
+    pub buyer: H160,
+    // Total paid by the buyer, fees included, in the smallest unit of `currency`
+    pub price: U256,
+    // Payment token, the zero address for the chain's native currency
+    pub currency: H160,
+}
+
+fn topic_to_address(log: &Log, index: usize) -> Result<H160, ethabi::Error> {
+    log.topics
+        .get(index)
+        .map(|topic| H160::from_slice(&topic.0[12..]))
+        .ok_or(ethabi::Error::InvalidData)
+}
+
+// Seaport item types, criteria based items are resolved to an identifier by the time they are logged
+const SEAPORT_NATIVE: u8 = 0;
+const SEAPORT_ERC20: u8 = 1;
+const SEAPORT_ERC721: u8 = 2;
+const SEAPORT_ERC1155_WITH_CRITERIA: u8 = 5;
+
+// (item type, token, identifier, amount) of a SpentItem or ReceivedItem
+type SeaportItem = (u8, H160, U256, U256);
+
+fn seaport_items(token: &Token) -> Result<Vec<SeaportItem>, ethabi::Error> {
+    let items = match token {
+        Token::Array(items) => items,
+        _ => return Err(ethabi::Error::InvalidData),
+    };
+    items
+        .iter()
+        .map(|item| match item {
+            Token::Tuple(fields) if fields.len() >= 4 => Ok((
+                token_to_u256(&fields[0])
+                    .ok_or(ethabi::Error::InvalidData)?
+                    .low_u32() as u8,
+                fields[1]
+                    .clone()
+                    .into_address()
+                    .ok_or(ethabi::Error::InvalidData)?,
+                token_to_u256(&fields[2]).ok_or(ethabi::Error::InvalidData)?,
+                token_to_u256(&fields[3]).ok_or(ethabi::Error::InvalidData)?,
+            )),
+            _ => Err(ethabi::Error::InvalidData),
+        })
+        .collect()
+}
+
+fn is_seaport_nft(item: &SeaportItem) -> bool {
+    (SEAPORT_ERC721..=SEAPORT_ERC1155_WITH_CRITERIA).contains(&item.0)
+}
+
+// Sums the payment items, None if they are missing or mix currencies
+fn seaport_payment(items: &[SeaportItem]) -> Option<(U256, H160)> {
+    let payments: Vec<&SeaportItem> = items
+        .iter()
+        .filter(|item| item.0 == SEAPORT_NATIVE || item.0 == SEAPORT_ERC20)
+        .collect();
+    let currency = payments.first()?.1;
+    if payments.iter().any(|item| item.1 != currency) {
+        return None;
+    }
+    let price = payments
+        .iter()
+        .fold(U256::zero(), |total, item| total.saturating_add(item.3));
+    Some((price, currency))
+}
+
+// Decodes Seaport's OrderFulfilled. Orders trading anything but exactly one NFT for
+// payment (bundles, swaps) can't be priced per token and decode to None.
+pub(crate) fn decode_seaport_order_fulfilled(
+    log: &Log,
+) -> Result<Option<DecodedSale>, ethabi::Error> {
+    let offerer = topic_to_address(log, 1)?;
+    let spent_item = ParamType::Tuple(vec![
+        ParamType::Uint(8),
+        ParamType::Address,
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+    ]);
+    let received_item = ParamType::Tuple(vec![
+        ParamType::Uint(8),
+        ParamType::Address,
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Address,
+    ]);
+    // orderHash, recipient, offer, consideration
+    let decoded = ethabi::decode(
+        &[
+            ParamType::FixedBytes(32),
+            ParamType::Address,
+            ParamType::Array(Box::new(spent_item)),
+            ParamType::Array(Box::new(received_item)),
+        ],
+        &log.data.0,
+    )?;
+    let recipient = decoded[1]
+        .clone()
+        .into_address()
+        .ok_or(ethabi::Error::InvalidData)?;
+    let offer = seaport_items(&decoded[2])?;
+    let consideration = seaport_items(&decoded[3])?;
+
+    let offered_nfts: Vec<&SeaportItem> = offer.iter().filter(|i| is_seaport_nft(i)).collect();
+    let received_nfts: Vec<&SeaportItem> =
+        consideration.iter().filter(|i| is_seaport_nft(i)).collect();
+
+    let (nft, seller, buyer, payment) = match (offered_nfts.as_slice(), received_nfts.as_slice()) {
+        // A listing was filled: the offerer sells, the fulfiller pays the consideration
+        ([nft], []) => (*nft, offerer, recipient, seaport_payment(&consideration)),
+        // An offer was accepted: the offerer pays, the fulfiller hands over the NFT
+        ([], [nft]) => (*nft, recipient, offerer, seaport_payment(&offer)),
+        _ => return Ok(None),
+    };
+    let (price, currency) = match payment {
+        Some(payment) => payment,
+        None => return Ok(None),
+    };
+
+    Ok(Some(DecodedSale {
+        collection: nft.1,
+        token_id: nft.2,
+        amount: nft.3,
+        seller,
+        buyer,
+        price,
+        currency,
+    }))
+}
+
+// Decodes LooksRare's TakerAsk (taker accepted a bid) and TakerBid (taker bought a listing),
+// which share the same layout
+pub(crate) fn decode_looksrare_taker(
+    log: &Log,
+    taker_is_seller: bool,
+) -> Result<DecodedSale, ethabi::Error> {
+    let taker = topic_to_address(log, 1)?;
+    let maker = topic_to_address(log, 2)?;
+    // orderHash, orderNonce, currency, collection, tokenId, amount, price
+    let decoded = ethabi::decode(
+        &[
+            ParamType::FixedBytes(32),
+            ParamType::Uint(256),
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+        ],
+        &log.data.0,
+    )?;
+    let address_at = |index: usize| {
+        decoded[index]
+            .clone()
+            .into_address()
+            .ok_or(ethabi::Error::InvalidData)
+    };
+    let uint_at = |index: usize| token_to_u256(&decoded[index]).ok_or(ethabi::Error::InvalidData);
+    let (seller, buyer) = if taker_is_seller {
+        (taker, maker)
+    } else {
+        (maker, taker)
+    };
+
+    Ok(DecodedSale {
+        collection: address_at(3)?,
+        token_id: uint_at(4)?,
+        amount: uint_at(5)?,
+        seller,
+        buyer,
+        price: uint_at(6)?,
+        currency: address_at(2)?,
+    })
+}
@@ -1,5 +1,11 @@
+pub mod contract_types;
+pub mod deliveries;
 pub mod indexer_config;
+pub mod lag_watcher;
+pub mod notifications;
 pub mod remote_calls;
+pub mod rpc_pool;
+pub mod webhooks;
 
 pub mod log_decode;
 pub mod queries;
@@ -0,0 +1,292 @@
+use crate::common::addresses::is_mint;
+use crate::indexer::indexer_config::Chain;
+use crate::indexer::queries::{Event, Sale};
+use crate::indexer::webhooks::{load_usernames, transfer_payload};
+use eth_checksum::checksum;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde_json::{json, Value};
+use std::env;
+use tokio_postgres::Client;
+
+/// SMTP relay the email notifications are sent through, from AFTERLIFE_SMTP_RELAY (host,
+/// STARTTLS on the submission port), AFTERLIFE_SMTP_FROM and the optional
+/// AFTERLIFE_SMTP_USERNAME / AFTERLIFE_SMTP_PASSWORD. Email notifications are disabled
+/// while the relay or sender is unset.
+#[derive(Debug, Clone)]
+pub struct SmtpRelay {
+    pub host: String,
+    pub from: String,
+    pub credentials: Option<(String, String)>,
+}
+
+impl SmtpRelay {
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        Some(Self {
+            host: var("AFTERLIFE_SMTP_RELAY")?,
+            from: var("AFTERLIFE_SMTP_FROM")?,
+            credentials: var("AFTERLIFE_SMTP_USERNAME").zip(var("AFTERLIFE_SMTP_PASSWORD")),
+        })
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, text: String) -> Result<(), String> {
+        let message = Message::builder()
+            .from(
+                self.from
+                    .parse()
+                    .map_err(|e| format!("Invalid sender {}: {}", self.from, e))?,
+            )
+            .to(to
+                .parse()
+                .map_err(|e| format!("Invalid recipient {}: {}", to, e))?)
+            .subject(subject)
+            .body(text)
+            .map_err(|e| format!("Failed to build email: {}", e))?;
+
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)
+            .map_err(|e| format!("Invalid SMTP relay {}: {}", self.host, e))?;
+        if let Some((username, password)) = &self.credentials {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        transport
+            .build()
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to send email to {}: {}", to, e))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Channel {
+    Webhook { url: String, secret: String },
+    Email(String),
+}
+
+/// Which of the transfers involving a user's addresses they're notified of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventFilter {
+    Mints,
+    Sales,
+    All,
+}
+
+impl EventFilter {
+    fn matches(&self, kind: &str) -> bool {
+        match self {
+            EventFilter::Mints => kind == "mint",
+            EventFilter::Sales => kind == "sale",
+            EventFilter::All => true,
+        }
+    }
+}
+
+/// Notification preferences a user registered with `PUT /user/notifications`
+#[derive(Debug, Clone)]
+pub struct NotificationPreference {
+    pub username: String,
+    pub channel: Channel,
+    pub events: EventFilter,
+    // Unix timestamp the preferences were saved at, older transfers are never sent
+    pub since: i64,
+}
+
+/// Preferences of the users with a notification channel, rows that don't parse are skipped.
+/// Email addresses are only loaded once their owner confirmed them.
+pub async fn get_notification_preferences(
+    client: &Client,
+) -> Result<Vec<NotificationPreference>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT username, notify_channel, notify_target, notify_secret, notify_events, \
+            EXTRACT(EPOCH FROM notify_since)::bigint AS notify_since \
+            FROM user_settings WHERE notify_channel <> 'none' AND notify_target IS NOT NULL \
+            AND (notify_channel <> 'email' OR notify_confirmed_at IS NOT NULL)",
+            &[],
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let target: String = row.get("notify_target");
+            let channel = match row.get::<_, String>("notify_channel").as_str() {
+                "webhook" => Channel::Webhook {
+                    url: target,
+                    secret: row.get::<_, Option<String>>("notify_secret")?,
+                },
+                "email" => Channel::Email(target),
+                _ => return None,
+            };
+            let events = match row.get::<_, String>("notify_events").as_str() {
+                "mints" => EventFilter::Mints,
+                "sales" => EventFilter::Sales,
+                _ => EventFilter::All,
+            };
+            Some(NotificationPreference {
+                username: row.get("username"),
+                channel,
+                events,
+                since: row.get::<_, Option<i64>>("notify_since").unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+// Queues the notification for deliveries::run, nothing if the user was already notified of
+// this transfer
+async fn queue_notification(
+    client: &Client,
+    username: &str,
+    event: &Event,
+    payload: &str,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "INSERT INTO notification_deliveries \
+            (username, transaction_hash, log_index, payload, next_attempt_at) \
+            VALUES ($1, $2, $3, $4, now()) \
+            ON CONFLICT (username, transaction_hash, log_index) DO NOTHING",
+            &[
+                &username,
+                &event.transaction_hash,
+                &(event.log_index as i32),
+                &payload,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// The marketplace sale a transfer settled, if any
+pub fn sale_of<'a>(event: &Event, sales: &[&'a Sale]) -> Option<&'a Sale> {
+    sales.iter().copied().find(|sale| {
+        sale.transaction_hash == event.transaction_hash
+            && sale
+                .contract
+                .address
+                .eq_ignore_ascii_case(&event.contract.address)
+            && event.ids.contains(&sale.token_id)
+    })
+}
+
+/// "mint", "sale" or "transfer"
+pub fn event_kind(event: &Event, sale: Option<&Sale>) -> &'static str {
+    if is_mint(&event.from_address) {
+        "mint"
+    } else if sale.is_some() {
+        "sale"
+    } else {
+        "transfer"
+    }
+}
+
+pub(crate) fn email_text(payload: &Value) -> (String, String) {
+    let field = |name: &str| payload[name].as_str().unwrap_or_default().to_string();
+    let kind = field("kind");
+    let contract = payload["contract_name"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| field("contract_address"));
+    let tokens: Vec<String> = payload["tokens"]
+        .as_array()
+        .map(|tokens| {
+            tokens
+                .iter()
+                .map(|token| {
+                    format!(
+                        "#{} x{}",
+                        token["token_id"].as_str().unwrap_or_default(),
+                        token["value"].as_str().unwrap_or_default()
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let subject = format!("Afterlife {}: {} on {}", kind, contract, field("chain"));
+    let mut text = format!(
+        "{} of {} on {}\n\nTokens: {}\nFrom: {}\nTo: {}\nTransaction: {}\n",
+        kind,
+        contract,
+        field("chain"),
+        tokens.join(", "),
+        field("from"),
+        field("to"),
+        field("transaction_hash"),
+    );
+    if let Some(sale) = payload["sale"].as_object() {
+        text.push_str(&format!(
+            "Price: {} (currency {}) on {}\n",
+            sale["price"].as_str().unwrap_or_default(),
+            sale["currency"].as_str().unwrap_or_default(),
+            sale["marketplace"].as_str().unwrap_or_default(),
+        ));
+    }
+    (subject, text)
+}
+
+/// Notifies users of the new transfers sent or received by one of their addresses,
+/// through the channel and with the filter of their notification preferences. Like the
+/// webhooks, notifications are queued for deliveries::run, once per user and transfer.
+pub async fn notify_users(client: &Client, chain: &Chain, events: &[&Event], sales: &[&Sale]) {
+    if events.is_empty() {
+        return;
+    }
+    let preferences = match get_notification_preferences(client).await {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            eprintln!(
+                "[{}] Failed to load notification preferences: {}",
+                chain.name, e
+            );
+            return;
+        }
+    };
+    if preferences.is_empty() {
+        return;
+    }
+
+    let usernames = load_usernames(chain).await;
+    for event in events {
+        let from_username = usernames.get(&event.from_address.to_lowercase());
+        let to_username = usernames.get(&event.to_address.to_lowercase());
+        let sale = sale_of(event, sales);
+        let kind = event_kind(event, sale);
+
+        for preference in &preferences {
+            let involved = from_username == Some(&preference.username)
+                || to_username == Some(&preference.username);
+            let is_new = event
+                .block_timestamp
+                .is_some_and(|ts| ts as i64 >= preference.since);
+            if !involved || !is_new || !preference.events.matches(kind) {
+                continue;
+            }
+
+            let mut payload =
+                transfer_payload(chain, event, &usernames, Some(&preference.username));
+            payload["kind"] = json!(kind);
+            payload["sale"] = match sale {
+                Some(sale) => json!({
+                    "marketplace": sale.marketplace,
+                    "seller": checksum(&sale.seller),
+                    "buyer": checksum(&sale.buyer),
+                    "price": sale.price.to_string(),
+                    "currency": checksum(&sale.currency),
+                }),
+                None => Value::Null,
+            };
+
+            if let Err(e) =
+                queue_notification(client, &preference.username, event, &payload.to_string()).await
+            {
+                eprintln!(
+                    "Failed to queue notification of {}: {}",
+                    preference.username, e
+                );
+            }
+        }
+    }
+}
@@ -1,17 +1,24 @@
+use crate::common::addresses;
 use crate::indexer;
 use indexer::indexer_config::{Chain, Contract};
+use indexer::remote_calls::FetchProgress;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::result::Result;
-use tokio_postgres::{Client, Error, GenericClient};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, Error, GenericClient, Transaction};
 extern crate primitive_types;
 use eth_checksum::checksum;
 use web3::types::U256;
 
-/* DB SCHEMA
+/* DB SCHEMA (created and upgraded by `afterlife migrate`, see common::migrations)
 1. chains:
    - id: integer (Primary Key)
    - name: character varying
+   - head_block: integer (latest block seen by the indexer)
+   - safe_block: integer (last final block, per the chain's confirmations or finalized tag)
+   - head_updated_at: timestamp with time zone
 
 2. contracts:
    - id: integer (Primary Key)
@@ -20,6 +27,34 @@ use web3::types::U256;
    - address: character varying
    - type: character varying
    - last_processed_block: integer
+   - last_indexed_at: timestamp with time zone (last successful indexing run)
+   - detected_type: character varying (erc721, erc1155 or unknown, from ERC-165
+     supportsInterface calls when the contract is first indexed; NULL until detected)
+   - verified: boolean (default true, false for spam or scam contracts flagged by an admin,
+     left out of full collections unless asked for)
+   - score_weight: double precision (default 1, multiplies the collection's leaderboard points)
+   - score_normalization: character varying (none, or mean: scores rescaled so the
+     collection's average token scores 1, applied before score_weight)
+   - burn_addresses: character varying[] (lowercased, synced from the indexer config,
+     transfers to them count as burns along with the zero and dead addresses)
+   - archived_at: timestamp with time zone (set once the contract was removed from the
+     config and its events moved to events_archive, NULL for live contracts; archived
+     contracts don't hold back their chain's cursor)
+   - rarity_tier_rare, rarity_tier_epic, rarity_tier_legendary: double precision (lowest
+     rarity percentile of each tier, set by an admin; NULL for the default 80, 95 and 99)
+   - start_block: integer (synced from the indexer config, first block indexed; NULL for
+     contracts not indexed since it was added)
+   - slug: character varying (synced from the indexer config, lowercased, accepted by the
+     API in place of the address; unique per chain among live contracts)
+   - rarity_model: character varying (synced from the indexer config, how the rarity
+     scores are computed: trait_sum, openrarity or jaccard; NULL for trait_sum)
+   - sink_addresses: character varying[] (lowercased, synced from the indexer config,
+     transfers to them count as consumed rather than burned)
+   - credit_consumed: boolean (default false, synced from the indexer config; consumed
+     tokens stay in their sender's leaderboard score)
+   - approvals_indexed: boolean (default false, set once the contract's ApprovalForAll
+     events were fetched from its start block; cleared while its chain has
+     index_approvals off)
 
 3. events:
    - id: integer (Primary Key)
@@ -27,15 +62,386 @@ use web3::types::U256;
    - operator: character varying
    - from_address: character varying
    - to_address: character varying
-   - ids: character varying (JSON list of integers, e.g., "[99, 104, 105]")
-   - values: character varying (JSON list of integers, e.g., "[1, 2, 3...]")
+   - ids: numeric[] (e.g., {99, 104, 105})
+   - values: numeric[] (e.g., {1, 2, 3...}), same length as ids
    - block_number: integer
    - transaction_hash: character varying
+   - block_timestamp: timestamp with time zone
+   - transaction_index: integer
+   - log_index: integer
+
+   Unique: (contract_id, transaction_hash, log_index)
+   Indexes: LOWER(from_address), LOWER(to_address), block_timestamp (activity feeds),
+            GIN (ids) (token id lookups)
+
+   ids/values used to be varchar JSON lists, existing tables are converted with:
+     ALTER TABLE events
+       ALTER COLUMN ids TYPE numeric[] USING translate(ids, '[]', '{}')::numeric[],
+       ALTER COLUMN values TYPE numeric[] USING translate(values, '[]', '{}')::numeric[];
+     CREATE INDEX events_ids_gin ON events USING GIN (ids);
+
+4. metadata_updates (ERC1155 URI events):
+   - id: integer (Primary Key)
+   - contract_id: integer (Foreign Key -> contracts.id)
+   - token_id: character varying (decimal string)
+   - uri: character varying
+   - block_number: integer
+   - transaction_hash: character varying
+   - created_at: timestamp with time zone (default now())
+
+   Unique: (contract_id, transaction_hash, token_id)
+
+5. webhooks:
+   - id: integer (Primary Key)
+   - url: character varying
+   - secret: character varying (HMAC key for the X-Afterlife-Signature header)
+   - username: character varying (NULL = notified of every transfer)
+   - active: boolean (default true)
+   - created_at: timestamp with time zone (default now())
+
+6. webhook_deliveries:
+   - id: integer (Primary Key)
+   - webhook_id: integer (Foreign Key -> webhooks.id, ON DELETE CASCADE)
+   - transaction_hash: character varying
+   - log_index: integer
+   - created_at: timestamp with time zone (default now())
+   - payload: text (nullable, the JSON body until it's delivered)
+   - attempts: integer (default 0)
+   - next_attempt_at: timestamp with time zone (nullable, NULL once delivered or given up)
+   - delivered_at: timestamp with time zone (nullable)
+
+   Unique: (webhook_id, transaction_hash, log_index)
+
+7. user_settings (per-user data that doesn't belong in the users file):
+   - username: character varying (Primary Key, as in the users file)
+   - discord_id: character varying (Unique, nullable)
+   - avatar_chain: character varying (nullable, chain of the token picked as avatar)
+   - avatar_contract: character varying (nullable)
+   - avatar_token_id: numeric (nullable)
+   - hidden_addresses: character varying[] (lowercased, left out of the public profile)
+   - hide_collection: boolean (default false, no tokens or activity on the public profile)
+   - notify_channel: character varying (default 'none', 'webhook' or 'email')
+   - notify_target: character varying (nullable, webhook URL or email address)
+   - notify_secret: character varying (nullable, HMAC key of the notification webhook)
+   - notify_events: character varying (default 'all', 'mints' or 'sales')
+   - notify_since: timestamp with time zone (nullable, older transfers aren't notified)
+   - updated_at: timestamp with time zone
+
+8. listings (active marketplace asks, replaced on every marketplace refresh):
+   - id: integer (Primary Key)
+   - contract_id: integer (Foreign Key -> contracts.id)
+   - token_id: numeric
+   - order_id: character varying
+   - maker: character varying
+   - price: double precision (in the chain's native currency)
+   - currency: character varying (symbol of the currency the order was made in)
+   - source: character varying (marketplace domain, nullable)
+   - valid_until: timestamp with time zone (nullable)
+   - updated_at: timestamp with time zone
+
+   Unique: (contract_id, order_id)
+   Indexes: (contract_id, token_id)
+
+9. sales (decoded from the marketplaces configured per chain):
+   - id: integer (Primary Key)
+   - contract_id: integer (Foreign Key -> contracts.id)
+   - marketplace: character varying (name of the marketplace in the indexer config)
+   - token_id: numeric
+   - amount: numeric
+   - seller: character varying
+   - buyer: character varying
+   - price: numeric (smallest unit of the currency, fees included)
+   - currency: character varying (payment token, zero address = native currency)
+   - block_number: integer
+   - transaction_hash: character varying
+   - log_index: integer
+   - block_timestamp: timestamp with time zone
+
+   Unique: (contract_id, transaction_hash, log_index)
+   Indexes: (contract_id, token_id, block_number)
+
+10. score_history (default leaderboard snapshots, written by the API at most hourly):
+   - id: integer (Primary Key)
+   - name: character varying (username, or checksummed address)
+   - points: double precision
+   - rank: integer
+   - recorded_at: timestamp with time zone (same for every row of a snapshot)
+
+   Indexes: (recorded_at)
+
+11. exclusions (users and addresses kept off leaderboards, holder counts and owner lists):
+   - id: integer (Primary Key)
+   - project: character varying (project id, "default" for the unprefixed routes)
+   - value: character varying (username or address)
+   - reason: character varying (nullable)
+   - created_at: timestamp with time zone (default now())
+
+   Unique: (project, LOWER(value))
+
+   These used to be hardcoded, existing deployments keep them with:
+     INSERT INTO exclusions (project, value) VALUES
+       ('default', 'Danetron3030'),
+       ('default', 'AfterlifeTreasury'),
+       ('default', '0x3cc35873a61D925Ac46984f8C4F85d8fa6A892eF'),
+       ('default', 'AfterlifeCoinBank');
+
+12. labels (what known addresses are, shown next to them in owner lists and activity feeds):
+   - address: character varying (Primary Key, lowercased)
+   - label: character varying (e.g. "OpenSea escrow")
+   - kind: character varying (treasury, bank, vesting, escrow...)
+   - excluded: boolean (default false, protocol owned: no holder counts or leaderboards)
+   - updated_at: timestamp with time zone
+
+13. staking_contracts (synced from the indexer config on every run):
+   - id: integer (Primary Key)
+   - chain_id: integer (Foreign Key -> chains.id)
+   - name: character varying
+   - address: character varying (lowercased)
+   - multiplier: double precision (score multiplier of staked tokens)
+
+   Unique: (chain_id, address)
+
+   staked_balances is a view crediting the tokens held by a staking contract back to their
+   depositors, from the transfers into (deposits) and out of (withdrawals) the contract:
+     CREATE VIEW staked_balances AS
+     SELECT s.id AS staking_contract_id, e.contract_id, m.address, t.id AS token_id,
+            SUM(m.sign * t.value) AS balance
+     FROM events e
+     JOIN contracts c ON e.contract_id = c.id
+     JOIN staking_contracts s ON s.chain_id = c.chain_id
+         AND (LOWER(e.to_address) = s.address OR LOWER(e.from_address) = s.address)
+     CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value)
+     CROSS JOIN LATERAL (
+         SELECT LOWER(e.from_address) AS address, 1 AS sign WHERE LOWER(e.to_address) = s.address
+         UNION ALL
+         SELECT LOWER(e.to_address), -1 WHERE LOWER(e.from_address) = s.address
+     ) m
+     GROUP BY s.id, e.contract_id, m.address, t.id
+     HAVING SUM(m.sign * t.value) > 0;
+
+14. delegations (wallet-wide delegate.cash delegations, replaced on every delegation refresh):
+   - chain: character varying
+   - registry: character varying (lowercased registry address)
+   - vault: character varying (lowercased, the wallet holding the tokens)
+   - delegate: character varying (lowercased, the wallet acting for the vault)
+   - block_number: bigint (nullable, head block when the delegation was first seen)
+   - updated_at: timestamp with time zone
+
+   Unique: (chain, registry, vault, delegate)
+
+15. token_metadata (primary metadata store, the metadata directory is an optional export):
+   - chain: character varying (lowercased)
+   - contract_address: character varying (lowercased)
+   - token_id: numeric
+   - metadata: jsonb
+   - image_url: character varying (metadata's image, NULL if it has none)
+   - fetched_at: timestamp with time zone
+
+   Unique: (chain, contract_address, token_id)
+
+16. leaderboards (computed by the API replica holding the leader lock, read by the others):
+   - project: character varying (PRIMARY KEY)
+   - leaderboard: jsonb (name -> points)
+   - computed_at: timestamp with time zone
+
+17. sync_progress (latest backfill of each contract, written by the indexer while it runs):
+   - chain: character varying (lowercased)
+   - contract_address: character varying (lowercased)
+   - from_block: integer
+   - to_block: integer
+   - chunks_done: integer
+   - chunks_total: integer (estimate, chunk sizes adapt to the provider)
+   - started_at: timestamp with time zone
+   - updated_at: timestamp with time zone
+   - eta: timestamp with time zone (NULL once done)
+
+   Primary key: (chain, contract_address)
+
+18. api_keys (partner keys, sent as X-Api-Key):
+   - id: integer (Primary Key)
+   - name: character varying
+   - key_hash: character varying (Unique, hex SHA-256 of the key, the key itself isn't stored)
+   - requests_per_minute: integer
+   - active: boolean (false once revoked)
+   - created_at: timestamp with time zone
+   - last_used_at: timestamp with time zone (nullable)
+
+19. api_key_usage (requests per key and day, flushed by the API every minute):
+   - api_key_id: integer (Foreign Key -> api_keys.id, ON DELETE CASCADE)
+   - day: date (UTC)
+   - requests: bigint
+
+   Primary key: (api_key_id, day)
+
+20. jobs (expensive operations queued by the API, run by the job worker of any replica):
+   - id: integer (Primary Key)
+   - kind: character varying (rarity_recompute, metadata_refresh, holders_snapshot)
+   - params: jsonb (the job, kind included)
+   - status: character varying (queued, running, succeeded or failed)
+   - progress_done: integer
+   - progress_total: integer (nullable, unknown until the job starts)
+   - result: jsonb (nullable, set on success)
+   - error: character varying (nullable, set on failure)
+   - created_at: timestamp with time zone
+   - started_at: timestamp with time zone (nullable)
+   - finished_at: timestamp with time zone (nullable)
+
+21. approvals (ApprovalForAll events, on chains with index_approvals set):
+   - id: integer (Primary Key)
+   - contract_id: integer (Foreign Key -> contracts.id)
+   - owner: character varying (lowercased)
+   - operator: character varying (lowercased)
+   - approved: boolean (false for revocations)
+   - block_number: integer
+   - transaction_hash: character varying
+   - log_index: integer
+   - block_timestamp: timestamp with time zone
+
+   Unique: (contract_id, transaction_hash, log_index)
+   Indexes: (owner, contract_id, operator, block_number)
+
+   The latest event of an (owner, contract, operator) is the approval in effect.
+
+22. balances (materialized from events by the indexer, as it writes them):
+   - contract_id: integer (Foreign Key -> contracts.id)
+   - address: character varying (lowercased)
+   - token_id: numeric
+   - balance: numeric (positive, addresses whose balance drops to 0 lose their row)
+
+   Primary key: (contract_id, token_id, address)
+   Indexes: (address)
+
+   Only a replay of the events is authoritative, `afterlife reconcile` compares the two
+   and repairs the table.
+
+23. events_archive (events of contracts removed from the config, moved out of events by
+    the archive_contract admin job):
+   - id: integer (Primary Key, the id the event had in events)
+   - contract_id .. log_index: same as events
+   - archived_at: timestamp with time zone
+
+   Indexes: (contract_id)
+
+   Archiving also moves the contract's sales to sales_archive and drops its balances,
+   listings, approvals, supply history and token metadata. To index a contract again, move
+   its rows back to events and sales and clear contracts.archived_at before adding it back
+   to the config, its cursor was left where it was.
+
+24. notification_deliveries (transfers each user was notified of, see notify_channel):
+   - id: integer (Primary Key)
+   - username: character varying
+   - transaction_hash: character varying
+   - log_index: integer
+   - created_at: timestamp with time zone (default now())
+   - payload, attempts, next_attempt_at, delivered_at: as in webhook_deliveries
+
+   Unique: (username, transaction_hash, log_index)
+
+25. rpc_failures (failed RPC requests per chain, provider and hour, written by the indexer
+    after every run; kept a week):
+   - chain: character varying (lowercased)
+   - host: character varying (host of the RPC endpoint, the rest of the URL isn't stored)
+   - hour: timestamp with time zone
+   - failures: bigint
+   - last_error: character varying
+   - last_failed_at: timestamp with time zone
+
+   Primary key: (chain, host, hour)
+
+26. supply_history (mints and burns per contract and block bucket, rewritten by the indexer
+    for the buckets of every range it writes):
+   - contract_id: integer (Foreign Key -> contracts.id)
+   - start_block: integer (multiple of the bucket size, 1000 blocks)
+   - end_block: integer
+   - minted: numeric (units transferred from the zero address)
+   - burned: numeric (units transferred to the zero, dead or the contract's burn addresses)
+   - block_timestamp: timestamp with time zone (of the bucket's last event)
+
+   Primary key: (contract_id, start_block)
+   Only buckets with a mint or a burn have a row.
+
+27. lifetime_scores (default leaderboard points from the highest balance ever held of each
+    token, written by the API with the leaderboard and never lowered):
+   - name: character varying (PRIMARY KEY, username or checksummed address)
+   - points: double precision
+   - updated_at: timestamp with time zone
+
+28. season_results (final standings of each season of the seasons file, written by the API
+    once the season ended):
+   - season: integer (number of the season)
+   - name: character varying (username, or checksummed address)
+   - points: double precision (points gained during the season, negative if lost)
+   - rank: integer
+   - recorded_at: timestamp with time zone
+
+   Primary key: (season, name)
+
+29. teams (for community competitions, created by an admin or, when self_service, a user):
+   - id: integer (Primary Key)
+   - name: character varying (unique, case insensitive)
+   - self_service: boolean (default false; users join and leave by themselves, otherwise
+     only admins change the members)
+   - created_by: character varying (username, NULL when created by an admin)
+   - created_at: timestamp with time zone
+
+30. team_members (a user is in one team at most):
+   - username: character varying (Primary Key)
+   - team_id: integer (Foreign Key -> teams.id, deleted with the team)
+   - joined_at: timestamp with time zone
+
+   Indexes: (team_id)
+
+31. user_profiles_cache (the /user/level document of every registered user, precomputed by
+    the leading API replica after each leaderboard update):
+   - username: character varying (Primary Key)
+   - document: jsonb
+   - computed_at: timestamp with time zone
+
+32. native_prices (USD price of each chain's native currency, updated by the API's price
+    refresh):
+   - chain: character varying (Primary Key, lowercased)
+   - coin: character varying (id of the currency on the price API)
+   - usd: double precision
+   - fetched_at: timestamp with time zone
+
+33. native_price_history (last USD price of each chain's native currency per UTC day, used to
+    value sales at the price of their day):
+   - chain: character varying (Primary Key, lowercased)
+   - day: date (Primary Key)
+   - usd: double precision
+
+34. sales_archive (sales of contracts removed from the config, moved out of sales along
+    with their events):
+   - id .. block_timestamp: same as sales
+   - archived_at: timestamp with time zone
+
+   Indexes: (contract_id)
+
+35. auth_sessions (sign-in nonces, and the sessions signed in with them, see auth::user):
+   - id: character varying (Primary Key, the nonce, also the session id in the token)
+   - address: character varying (lowercased)
+   - expires_at: timestamp with time zone (of the nonce, then of the session)
+   - signed_in_at: timestamp with time zone (NULL until the nonce is used, once)
+   - revoked_at: timestamp with time zone (set on sign-out)
+
+   Indexes: (address)
 
 Relationships:
 
 - contracts.chain_id REFERENCES chains.id
 - events.contract_id REFERENCES contracts.id
+- metadata_updates.contract_id REFERENCES contracts.id
+- webhook_deliveries.webhook_id REFERENCES webhooks.id
+- listings.contract_id REFERENCES contracts.id
+- sales.contract_id REFERENCES contracts.id
+- approvals.contract_id REFERENCES contracts.id
+- balances.contract_id REFERENCES contracts.id
+- events_archive.contract_id REFERENCES contracts.id
+- sales_archive.contract_id REFERENCES contracts.id
+- supply_history.contract_id REFERENCES contracts.id
+- team_members.team_id REFERENCES teams.id
+- staking_contracts.chain_id REFERENCES chains.id
+- api_key_usage.api_key_id REFERENCES api_keys.id
 */
 
 // Event struct
@@ -50,10 +456,16 @@ pub struct Event {
     pub values: Vec<U256>,
     pub block_number: u64,
     pub transaction_hash: String,
+    pub transaction_index: u64,
+    // Position of the log within the block, unique per (transaction_hash, log_index)
+    pub log_index: u64,
+    // Unix timestamp of the block, filled in by the fetcher after decoding
+    pub block_timestamp: Option<u64>,
 }
 
 // Implement the Event struct, verify ids and values are the same length, and implement the From trait for the Event struct
 impl Event {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         contract: Contract,
         operator: String,
@@ -63,6 +475,8 @@ impl Event {
         values: Vec<U256>,
         block_number: u64,
         transaction_hash: String,
+        transaction_index: u64,
+        log_index: u64,
     ) -> Result<Self, &'static str> {
         if ids.len() != values.len() {
             return Err("ids and values must be the same length");
@@ -77,16 +491,62 @@ impl Event {
             values,
             block_number,
             transaction_hash,
+            transaction_index,
+            log_index,
+            block_timestamp: None,
         })
     }
     // Convert string containing JSON list of integers to Vec<u64>
 }
 
-fn u256_vec_to_json_decimal(vec: &Vec<U256>) -> Result<String, serde_json::Error> {
-    let decimal_strings: Vec<String> = vec.iter().map(|u| u.to_string()).collect();
-    let string = serde_json::to_string(&decimal_strings);
-    let stripped = string.unwrap().replace("\"", "");
-    Ok(stripped)
+// A token whose metadata URI changed on-chain (ERC1155 URI event)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataUpdate {
+    pub contract: Contract,
+    pub token_id: U256,
+    pub uri: String,
+    pub block_number: u64,
+    pub transaction_hash: String,
+}
+
+// A marketplace sale of a token of one of the configured contracts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sale {
+    pub contract: Contract,
+    // Name of the configured marketplace the sale was decoded from
+    pub marketplace: String,
+    pub token_id: U256,
+    pub amount: U256,
+    pub seller: String,
+    pub buyer: String,
+    // Total paid by the buyer in the smallest unit of the currency, fees included
+    pub price: U256,
+    // Payment token address, the zero address for the chain's native currency
+    pub currency: String,
+    pub block_number: u64,
+    pub transaction_hash: String,
+    pub log_index: u64,
+    // Unix timestamp of the block, filled in by the fetcher after decoding
+    pub block_timestamp: Option<u64>,
+}
+
+// An ApprovalForAll event of one of the configured contracts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Approval {
+    pub contract: Contract,
+    pub owner: String,
+    pub operator: String,
+    pub approved: bool,
+    pub block_number: u64,
+    pub transaction_hash: String,
+    pub log_index: u64,
+    // Unix timestamp of the block, filled in by the fetcher after decoding
+    pub block_timestamp: Option<u64>,
+}
+
+// tokio-postgres has no numeric encoder, so arrays are sent as text[] and cast in SQL
+fn u256_vec_to_decimal_strings(vec: &[U256]) -> Vec<String> {
+    vec.iter().map(|u| u.to_string()).collect()
 }
 
 pub async fn get_last_processed_block(contract: &Contract, client: &Client) -> Result<i32, Error> {
@@ -100,13 +560,15 @@ pub async fn get_last_processed_block(contract: &Contract, client: &Client) -> R
     Ok(row.get(0))
 }
 
+// None once every contract of the chain is archived, or before any is indexed
 pub async fn get_earliest_last_processed_block(
     chain: &Chain,
     client: &Client,
-) -> Result<i32, Error> {
+) -> Result<Option<i32>, Error> {
     let row = client
         .query_one(
-            "SELECT MIN(last_processed_block) FROM contracts WHERE chain_id = $1",
+            "SELECT MIN(last_processed_block) FROM contracts \
+             WHERE chain_id = $1 AND archived_at IS NULL",
             &[&(chain.id as i32)],
         )
         .await?;
@@ -114,6 +576,347 @@ pub async fn get_earliest_last_processed_block(
     Ok(row.get(0))
 }
 
+// The earliest cursor of the chain's configured contracts, None before the first one is
+// indexed. Archived contracts and those removed from the config no longer move.
+pub async fn get_chain_cursor(chain: &Chain, client: &Client) -> Result<Option<i32>, Error> {
+    let addresses: Vec<String> = chain
+        .contracts
+        .iter()
+        .map(|contract| contract.address.to_lowercase())
+        .collect();
+    let row = client
+        .query_one(
+            "SELECT MIN(c.last_processed_block) FROM contracts c \
+             JOIN chains ch ON c.chain_id = ch.id \
+             WHERE LOWER(ch.name) = $1 AND c.archived_at IS NULL \
+             AND LOWER(c.address) = ANY($2)",
+            &[&chain.name.to_lowercase(), &addresses],
+        )
+        .await?;
+
+    Ok(row.get(0))
+}
+
+/// Moves a contract's cursor back to `block`, the next indexing run re-fetches everything
+/// from there. Returns false if the contract isn't indexed yet.
+pub async fn rewind_contract(
+    chain_name: &str,
+    contract_address: &str,
+    block: i32,
+    client: &Client,
+) -> Result<bool, Error> {
+    let updated = client
+        .execute(
+            "UPDATE contracts SET last_processed_block = $1 \
+             WHERE LOWER(address) = $2 \
+             AND chain_id = (SELECT id FROM chains WHERE LOWER(name) = $3)",
+            &[
+                &block,
+                &contract_address.to_lowercase(),
+                &chain_name.to_lowercase(),
+            ],
+        )
+        .await?;
+    Ok(updated > 0)
+}
+
+pub async fn update_chain_head(
+    chain: &Chain,
+    head_block: u64,
+    safe_block: u64,
+    client: &Client,
+) -> Result<(), Error> {
+    client
+        .execute(
+            "UPDATE chains SET head_block = $1, safe_block = $2, head_updated_at = now() \
+             WHERE LOWER(name) = $3",
+            &[
+                &(head_block as i32),
+                &(safe_block as i32),
+                &chain.name.to_lowercase(),
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Records the progress of a backfill run for every contract of the chain it advances,
+/// that is every contract whose cursor is still before the run's last block
+pub async fn update_sync_progress(
+    chain: &Chain,
+    progress: &FetchProgress,
+    client: &Client,
+) -> Result<(), Error> {
+    client
+        .execute(
+            "INSERT INTO sync_progress (chain, contract_address, from_block, to_block, \
+                chunks_done, chunks_total, started_at, updated_at, eta) \
+             SELECT LOWER(ch.name), LOWER(c.address), $2, $3, $4, $5, \
+                now() - make_interval(secs => $6), now(), now() + make_interval(secs => $7) \
+             FROM contracts c JOIN chains ch ON c.chain_id = ch.id \
+             WHERE LOWER(ch.name) = $1 AND c.last_processed_block < $3 \
+             ON CONFLICT (chain, contract_address) DO UPDATE SET \
+                from_block = EXCLUDED.from_block, to_block = EXCLUDED.to_block, \
+                chunks_done = EXCLUDED.chunks_done, chunks_total = EXCLUDED.chunks_total, \
+                started_at = EXCLUDED.started_at, updated_at = EXCLUDED.updated_at, \
+                eta = EXCLUDED.eta",
+            &[
+                &chain.name.to_lowercase(),
+                &(progress.from_block as i32),
+                &(progress.to_block as i32),
+                &(progress.chunks_done as i32),
+                &(progress.chunks_total as i32),
+                &progress.elapsed.as_secs_f64(),
+                &progress.eta.map(|eta| eta.as_secs_f64()),
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Adds the RPC failures of a run to the chain's hourly counts, per provider host so API
+/// keys in the URLs aren't stored. Counts older than a week are dropped.
+pub async fn record_rpc_failures(
+    chain: &Chain,
+    failures: &[(String, u64, String)],
+    client: &Client,
+) -> Result<(), Error> {
+    for (url, count, error) in failures {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| "unknown".to_string());
+        client
+            .execute(
+                "INSERT INTO rpc_failures (chain, host, hour, failures, last_error, last_failed_at) \
+                VALUES ($1, $2, date_trunc('hour', now()), $3, $4, now()) \
+                ON CONFLICT (chain, host, hour) DO UPDATE \
+                SET failures = rpc_failures.failures + EXCLUDED.failures, \
+                last_error = EXCLUDED.last_error, last_failed_at = EXCLUDED.last_failed_at",
+                &[&chain.name.to_lowercase(), &host, &(*count as i64), error],
+            )
+            .await?;
+    }
+    client
+        .execute(
+            "DELETE FROM rpc_failures WHERE hour < now() - interval '7 days'",
+            &[],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Mirrors the chain's staking contracts into staking_contracts, dropping removed ones
+pub async fn sync_staking_contracts(chain: &Chain, client: &Client) -> Result<(), Error> {
+    // The chain row is created along with its first contract
+    let chain_id: i32 = match client
+        .query_opt(
+            "SELECT id FROM chains WHERE LOWER(name) = $1",
+            &[&chain.name.to_lowercase()],
+        )
+        .await?
+    {
+        Some(row) => row.get(0),
+        None => return Ok(()),
+    };
+
+    let addresses: Vec<String> = chain
+        .staking
+        .iter()
+        .map(|staking| staking.address.to_lowercase())
+        .collect();
+    client
+        .execute(
+            "DELETE FROM staking_contracts WHERE chain_id = $1 AND address <> ALL($2)",
+            &[&chain_id, &addresses],
+        )
+        .await?;
+    for staking in &chain.staking {
+        client
+            .execute(
+                "INSERT INTO staking_contracts (chain_id, name, address, multiplier) VALUES ($1, $2, $3, $4) \
+                ON CONFLICT (chain_id, address) DO UPDATE SET name = EXCLUDED.name, multiplier = EXCLUDED.multiplier",
+                &[
+                    &chain_id,
+                    &staking.name,
+                    &staking.address.to_lowercase(),
+                    &staking.multiplier,
+                ],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Copies the configured burn addresses of the chain's contracts onto their contracts rows.
+/// The zero and dead addresses are always burns and aren't stored.
+pub async fn sync_burn_addresses(chain: &Chain, client: &Client) -> Result<(), Error> {
+    for contract in &chain.contracts {
+        let burn_addresses: Vec<String> = contract
+            .burn_addresses
+            .iter()
+            .filter(|address| !addresses::is_burn(address, &[]))
+            .map(|address| address.to_lowercase())
+            .collect();
+        client
+            .execute(
+                "UPDATE contracts c SET burn_addresses = $3 FROM chains ch \
+                WHERE c.chain_id = ch.id AND LOWER(ch.name) = $1 AND LOWER(c.address) = $2 \
+                AND c.burn_addresses <> $3",
+                &[
+                    &chain.name.to_lowercase(),
+                    &contract.address.to_lowercase(),
+                    &burn_addresses,
+                ],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Copies the configured sink addresses and credit_consumed flag of the chain's contracts
+/// onto their contracts rows
+pub async fn sync_sink_addresses(chain: &Chain, client: &Client) -> Result<(), Error> {
+    for contract in &chain.contracts {
+        let sink_addresses: Vec<String> = contract
+            .sink_addresses
+            .iter()
+            .map(|address| address.to_lowercase())
+            .collect();
+        client
+            .execute(
+                "UPDATE contracts c SET sink_addresses = $3, credit_consumed = $4 FROM chains ch \
+                WHERE c.chain_id = ch.id AND LOWER(ch.name) = $1 AND LOWER(c.address) = $2 \
+                AND (c.sink_addresses <> $3 OR c.credit_consumed <> $4)",
+                &[
+                    &chain.name.to_lowercase(),
+                    &contract.address.to_lowercase(),
+                    &sink_addresses,
+                    &contract.credit_consumed,
+                ],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn sync_rarity_models(chain: &Chain, client: &Client) -> Result<(), Error> {
+    for contract in &chain.contracts {
+        client
+            .execute(
+                "UPDATE contracts c SET rarity_model = $3 FROM chains ch \
+                WHERE c.chain_id = ch.id AND LOWER(ch.name) = $1 AND LOWER(c.address) = $2 \
+                AND c.rarity_model IS DISTINCT FROM $3",
+                &[
+                    &chain.name.to_lowercase(),
+                    &contract.address.to_lowercase(),
+                    &contract.rarity_model,
+                ],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Slugs are cleared before they're set, in one transaction, so contracts can swap slugs
+/// without tripping the per-chain uniqueness
+pub async fn sync_contract_slugs(chain: &Chain, client: &mut Client) -> Result<(), Error> {
+    let addresses: Vec<String> = chain
+        .contracts
+        .iter()
+        .map(|contract| contract.address.to_lowercase())
+        .collect();
+    let slugs: Vec<Option<String>> = chain
+        .contracts
+        .iter()
+        .map(|contract| contract.slug.as_ref().map(|slug| slug.to_lowercase()))
+        .collect();
+    let transaction = client.transaction().await?;
+    transaction
+        .execute(
+            "UPDATE contracts c SET slug = NULL \
+            FROM chains ch, unnest($2::text[], $3::text[]) AS t(address, slug) \
+            WHERE c.chain_id = ch.id AND LOWER(ch.name) = $1 \
+            AND LOWER(c.address) = t.address AND c.slug IS DISTINCT FROM t.slug",
+            &[&chain.name.to_lowercase(), &addresses, &slugs],
+        )
+        .await?;
+    transaction
+        .execute(
+            "UPDATE contracts c SET slug = t.slug \
+            FROM chains ch, unnest($2::text[], $3::text[]) AS t(address, slug) \
+            WHERE c.chain_id = ch.id AND LOWER(ch.name) = $1 \
+            AND LOWER(c.address) = t.address AND c.slug IS DISTINCT FROM t.slug",
+            &[&chain.name.to_lowercase(), &addresses, &slugs],
+        )
+        .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+pub async fn sync_start_blocks(chain: &Chain, client: &Client) -> Result<(), Error> {
+    for contract in &chain.contracts {
+        client
+            .execute(
+                "UPDATE contracts c SET start_block = $3 FROM chains ch \
+                WHERE c.chain_id = ch.id AND LOWER(ch.name) = $1 AND LOWER(c.address) = $2 \
+                AND c.start_block IS DISTINCT FROM $3",
+                &[
+                    &chain.name.to_lowercase(),
+                    &contract.address.to_lowercase(),
+                    &contract.startblock,
+                ],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Rewinds to their start block the contracts whose approvals were never fetched since
+/// index_approvals was turned on, the next run fetches their ApprovalForAll events from
+/// there. While the flag is off the contracts are marked again, approvals emitted in the
+/// meantime would be missing otherwise. Returns the addresses of the rewound contracts.
+pub async fn sync_approvals_backfill(chain: &Chain, client: &Client) -> Result<Vec<String>, Error> {
+    if !chain.index_approvals {
+        client
+            .execute(
+                "UPDATE contracts c SET approvals_indexed = false FROM chains ch \
+                WHERE c.chain_id = ch.id AND LOWER(ch.name) = $1 AND c.approvals_indexed",
+                &[&chain.name.to_lowercase()],
+            )
+            .await?;
+        return Ok(Vec::new());
+    }
+
+    let addresses: Vec<String> = chain
+        .contracts
+        .iter()
+        .map(|contract| contract.address.to_lowercase())
+        .collect();
+    let rows = client
+        .query(
+            "UPDATE contracts c SET approvals_indexed = true, \
+                last_processed_block = LEAST(c.last_processed_block, COALESCE(c.start_block, 0)) \
+            FROM chains ch \
+            WHERE c.chain_id = ch.id AND LOWER(ch.name) = $1 AND LOWER(c.address) = ANY($2) \
+            AND c.archived_at IS NULL AND NOT c.approvals_indexed \
+            RETURNING c.address",
+            &[&chain.name.to_lowercase(), &addresses],
+        )
+        .await?;
+
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
 pub async fn contract_and_chain_to_contractid<C>(
     contract: &Contract,
     chain: &Chain,
@@ -133,7 +936,11 @@ where
         Err(_) => client_or_transaction
             .query_one(
                 "INSERT INTO chains (name, rpc_url, chunk_size) VALUES ($1, $2, $3) RETURNING id",
-                &[&chain.name, &chain.rpc_url, &(chain.chunk_size as i32)],
+                &[
+                    &chain.name,
+                    &chain.rpc_endpoints().first().cloned().unwrap_or_default(),
+                    &(chain.chunk_size as i32),
+                ],
             )
             .await?
             .get(0),
@@ -150,8 +957,8 @@ where
         Err(_) => {
             client_or_transaction
                 .query_one(
-                    "INSERT INTO contracts (chain_id, name, address, type, last_processed_block) VALUES ($1, $2, $3, $4, $5) RETURNING id",
-                    &[&chain_id, &contract.name, &contract.address.to_lowercase(), &contract.r#type, &(contract.startblock )],
+                    "INSERT INTO contracts (chain_id, name, address, type, last_processed_block, start_block, approvals_indexed) VALUES ($1, $2, $3, $4, $5, $5, $6) RETURNING id",
+                    &[&chain_id, &contract.name, &contract.address.to_lowercase(), &contract.r#type, &(contract.startblock ), &chain.index_approvals],
                 )
                 .await?
                 .get(0)
@@ -161,57 +968,470 @@ where
     Ok(contract_id)
 }
 
-pub async fn nuke_and_process_events_for_chain(
+pub async fn get_detected_contract_type(
+    contract_id: i32,
+    client: &Client,
+) -> Result<Option<String>, Error> {
+    Ok(client
+        .query_one(
+            "SELECT detected_type FROM contracts WHERE id = $1",
+            &[&contract_id],
+        )
+        .await?
+        .get(0))
+}
+
+// Only the first detection is kept, the interfaces a contract supports don't change
+pub async fn set_detected_contract_type(
+    contract_id: i32,
+    detected_type: &str,
+    client: &Client,
+) -> Result<(), Error> {
+    client
+        .execute(
+            "UPDATE contracts SET detected_type = $2 WHERE id = $1 AND detected_type IS NULL",
+            &[&contract_id, &detected_type],
+        )
+        .await?;
+    Ok(())
+}
+
+// Owned column values of one events row, so a batch can hand out &dyn ToSql references
+struct EventRow {
+    operator: String,
+    from_address: String,
+    to_address: String,
+    ids: Vec<String>,
+    values: Vec<String>,
+    block_number: i32,
+    transaction_hash: String,
+    block_timestamp: Option<SystemTime>,
+    transaction_index: i32,
+    log_index: i32,
+}
+
+const EVENT_ROW_COLUMNS: usize = 11;
+// Rows per multi-row INSERT, keeps the parameter count well under Postgres' 65535 limit
+const EVENTS_INSERT_BATCH_SIZE: usize = 1000;
+
+// "($n, ..., $n+10)" for one events row, ids and values are cast from text[] to numeric[]
+fn event_row_placeholders(first: usize) -> String {
+    let placeholders: Vec<String> = (0..EVENT_ROW_COLUMNS)
+        .map(|column| match column {
+            4 | 5 => format!("${}::text[]::numeric[]", first + column),
+            _ => format!("${}", first + column),
+        })
+        .collect();
+    format!("({})", placeholders.join(", "))
+}
+
+// What an upsert actually changed, rows written again with the same values don't count
+#[derive(Debug, Default)]
+struct WrittenRows {
+    // Rows inserted or updated
+    count: usize,
+    // Lowercased addresses of those rows
+    addresses: HashSet<String>,
+    // Whether some existing row was updated, its previous addresses aren't known
+    updated: bool,
+}
+
+// Upserts the events, returning the rows that were new or differ from the stored ones
+async fn insert_events(
+    transaction: &Transaction<'_>,
+    contract_id: i32,
+    events: &[Event],
+) -> Result<WrittenRows, Box<dyn std::error::Error>> {
+    // A single statement can't upsert the same key twice, drop duplicate logs up front
+    let mut seen = HashSet::new();
+    let mut rows = Vec::with_capacity(events.len());
+    for event in events {
+        if !seen.insert((event.transaction_hash.as_str(), event.log_index)) {
+            continue;
+        }
+        rows.push(EventRow {
+            operator: checksum(&event.operator),
+            from_address: checksum(&event.from_address),
+            to_address: checksum(&event.to_address),
+            ids: u256_vec_to_decimal_strings(&event.ids),
+            values: u256_vec_to_decimal_strings(&event.values),
+            block_number: event.block_number as i32,
+            transaction_hash: event.transaction_hash.clone(),
+            block_timestamp: event
+                .block_timestamp
+                .map(|ts| UNIX_EPOCH + Duration::from_secs(ts)),
+            transaction_index: event.transaction_index as i32,
+            log_index: event.log_index as i32,
+        });
+    }
+
+    let mut written = WrittenRows::default();
+    for batch in rows.chunks(EVENTS_INSERT_BATCH_SIZE) {
+        let mut placeholders = Vec::with_capacity(batch.len());
+        let mut params: Vec<&(dyn ToSql + Sync)> =
+            Vec::with_capacity(batch.len() * EVENT_ROW_COLUMNS);
+
+        for (i, row) in batch.iter().enumerate() {
+            let first = i * EVENT_ROW_COLUMNS + 1;
+            placeholders.push(event_row_placeholders(first));
+
+            params.push(&contract_id);
+            params.push(&row.operator);
+            params.push(&row.from_address);
+            params.push(&row.to_address);
+            params.push(&row.ids);
+            params.push(&row.values);
+            params.push(&row.block_number);
+            params.push(&row.transaction_hash);
+            params.push(&row.block_timestamp);
+            params.push(&row.transaction_index);
+            params.push(&row.log_index);
+        }
+
+        let query = format!(
+            "INSERT INTO events (contract_id, operator, from_address, to_address, ids, values, block_number, transaction_hash, block_timestamp, transaction_index, log_index) \
+            VALUES {} \
+            ON CONFLICT (contract_id, transaction_hash, log_index) DO UPDATE SET \
+            operator = EXCLUDED.operator, from_address = EXCLUDED.from_address, to_address = EXCLUDED.to_address, \
+            ids = EXCLUDED.ids, values = EXCLUDED.values, block_number = EXCLUDED.block_number, \
+            block_timestamp = EXCLUDED.block_timestamp, transaction_index = EXCLUDED.transaction_index \
+            WHERE (events.operator, events.from_address, events.to_address, events.ids, events.values, \
+                events.block_number, events.block_timestamp, events.transaction_index) \
+            IS DISTINCT FROM (EXCLUDED.operator, EXCLUDED.from_address, EXCLUDED.to_address, \
+                EXCLUDED.ids, EXCLUDED.values, EXCLUDED.block_number, EXCLUDED.block_timestamp, \
+                EXCLUDED.transaction_index) \
+            RETURNING LOWER(from_address), LOWER(to_address), xmax = 0 AS inserted",
+            placeholders.join(", ")
+        );
+        for row in transaction.query(query.as_str(), &params).await? {
+            written.count += 1;
+            written.addresses.insert(row.get(0));
+            written.addresses.insert(row.get(1));
+            written.updated |= !row.get::<_, bool>("inserted");
+        }
+    }
+
+    Ok(written)
+}
+
+// Returns the number of sales that weren't stored yet
+async fn insert_sales(
+    transaction: &Transaction<'_>,
+    contract_id: i32,
+    sales: &[Sale],
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut inserted = 0;
+    for sale in sales {
+        inserted += transaction
+            .execute(
+                "INSERT INTO sales (contract_id, marketplace, token_id, amount, seller, buyer, price, currency, block_number, transaction_hash, log_index, block_timestamp) \
+                VALUES ($1, $2, $3::text::numeric, $4::text::numeric, $5, $6, $7::text::numeric, $8, $9, $10, $11, $12) \
+                ON CONFLICT (contract_id, transaction_hash, log_index) DO NOTHING",
+                &[
+                    &contract_id,
+                    &sale.marketplace,
+                    &sale.token_id.to_string(),
+                    &sale.amount.to_string(),
+                    &checksum(&sale.seller),
+                    &checksum(&sale.buyer),
+                    &sale.price.to_string(),
+                    &checksum(&sale.currency),
+                    &(sale.block_number as i32),
+                    &sale.transaction_hash,
+                    &(sale.log_index as i32),
+                    &sale
+                        .block_timestamp
+                        .map(|ts| UNIX_EPOCH + Duration::from_secs(ts)),
+                ],
+            )
+            .await?;
+    }
+
+    Ok(inserted)
+}
+
+// Returns the lowercased owners of the approvals that weren't stored yet
+async fn insert_approvals(
+    transaction: &Transaction<'_>,
+    contract_id: i32,
+    approvals: &[Approval],
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut owners = Vec::new();
+    for approval in approvals {
+        let inserted = transaction
+            .execute(
+                "INSERT INTO approvals (contract_id, owner, operator, approved, block_number, transaction_hash, log_index, block_timestamp) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+                ON CONFLICT (contract_id, transaction_hash, log_index) DO NOTHING",
+                &[
+                    &contract_id,
+                    &approval.owner.to_lowercase(),
+                    &approval.operator.to_lowercase(),
+                    &approval.approved,
+                    &(approval.block_number as i32),
+                    &approval.transaction_hash,
+                    &(approval.log_index as i32),
+                    &approval
+                        .block_timestamp
+                        .map(|ts| UNIX_EPOCH + Duration::from_secs(ts)),
+                ],
+            )
+            .await?;
+        if inserted > 0 {
+            owners.push(approval.owner.to_lowercase());
+        }
+    }
+
+    Ok(owners)
+}
+
+/// Channel `write_events_for_chain` notifies once its transaction commits, listened to by
+/// the API to drop the cached responses the write made stale
+pub const EVENTS_CHANNEL: &str = "afterlife_events";
+// NOTIFY payloads are capped at 8000 bytes, past this the addresses are left out
+const MAX_EVENTS_NOTIFICATION_BYTES: usize = 7900;
+
+/// Payload of an `EVENTS_CHANNEL` notification, the contracts a write changed and the
+/// lowercased addresses whose holdings or approvals it changed. No addresses means too
+/// many to list, every address is to be treated as changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventsNotification {
+    pub chain: String,
+    pub contracts: Vec<String>,
+    pub addresses: Option<Vec<String>>,
+}
+
+impl EventsNotification {
+    fn payload(&self) -> String {
+        let payload = serde_json::to_string(self).unwrap_or_default();
+        if payload.len() <= MAX_EVENTS_NOTIFICATION_BYTES {
+            return payload;
+        }
+        let truncated = EventsNotification {
+            addresses: None,
+            ..self.clone()
+        };
+        serde_json::to_string(&truncated).unwrap_or_default()
+    }
+}
+
+/// Makes `from_block..=to_block` of every contract of the chain match what was fetched,
+/// then moves the contracts' cursors to `safe_block`, so the range after it, not final yet,
+/// is fetched again next run.
+///
+/// Rows are upserted on their (transaction hash, log index) key and only the rows of the
+/// range that weren't fetched again (reorged out) are deleted, so writing the same range
+/// twice, from overlapping runs or after a restart, leaves the tables unchanged. Writes and
+/// cursors move together in one transaction, which notifies `EVENTS_CHANNEL` when it
+/// changed anything.
+///
+/// Contracts with logs in the range that couldn't be decoded are left untouched, rows and
+/// cursor: the fetched range would be missing those logs, and a wrong type or abi_variant
+/// would otherwise delete every stored transfer of the range as reorged out.
+#[allow(clippy::too_many_arguments)]
+pub async fn write_events_for_chain(
     chain: &Chain,
     new_events_by_contract: &HashMap<i32, Vec<Event>>, // key is contract_id
+    metadata_updates_by_contract: &HashMap<i32, Vec<MetadataUpdate>>, // key is contract_id
+    sales_by_contract: &HashMap<i32, Vec<Sale>>,       // key is contract_id
+    approvals_by_contract: &HashMap<i32, Vec<Approval>>, // key is contract_id
+    undecodable_by_contract: &HashMap<String, usize>,  // key is the configured address
     from_block: u64,
     to_block: u64,
+    safe_block: u64,
     client: &mut Client,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let transaction = client.transaction().await?;
+    let range = [from_block as i32, to_block as i32];
+    let mut changed_contracts = Vec::new();
+    let mut changed_addresses: HashSet<String> = HashSet::new();
+    // Set when an existing row was rewritten, its previous addresses aren't known
+    let mut every_address_changed = false;
 
     for contract in &chain.contracts {
+        if let Some(count) = undecodable_by_contract.get(&contract.address) {
+            eprintln!(
+                "[{}] Not writing blocks {}-{} of {}, {} of its logs couldn't be decoded",
+                chain.name, from_block, to_block, contract.address, count
+            );
+            continue;
+        }
         let contract_id = contract_and_chain_to_contractid(contract, chain, &transaction).await?;
+        // Serializes writers of the same contract, an overlapping run waits for this one
+        transaction
+            .execute(
+                "SELECT 1 FROM contracts WHERE id = $1 FOR UPDATE",
+                &[&contract_id],
+            )
+            .await?;
 
-        if let Some(new_events) = new_events_by_contract.get(&contract_id) {
-            transaction
+        let events = new_events_by_contract
+            .get(&contract_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        // Balances can change for the tokens of the rows being replaced and of the new ones
+        let mut touched_token_ids: HashSet<String> = transaction
+            .query(
+                "SELECT DISTINCT unnest(ids)::text FROM events \
+                WHERE contract_id = $1 AND block_number >= $2 AND block_number <= $3",
+                &[&contract_id, &range[0], &range[1]],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        touched_token_ids.extend(
+            events
+                .iter()
+                .flat_map(|e| e.ids.iter().map(U256::to_string)),
+        );
+        let hashes: Vec<&str> = events.iter().map(|e| e.transaction_hash.as_str()).collect();
+        let log_indexes: Vec<i32> = events.iter().map(|e| e.log_index as i32).collect();
+        // Holders of the reorged out rows lose what they gained from them
+        let removed_addresses = transaction
+            .query(
+                "DELETE FROM events WHERE contract_id = $1 AND block_number >= $2 AND block_number <= $3 \
+                AND (transaction_hash, log_index) NOT IN (SELECT * FROM unnest($4::text[], $5::int4[])) \
+                RETURNING LOWER(from_address), LOWER(to_address)",
+                &[&contract_id, &range[0], &range[1], &hashes, &log_indexes],
+            )
+            .await?;
+        for row in &removed_addresses {
+            changed_addresses.insert(row.get(0));
+            changed_addresses.insert(row.get(1));
+        }
+        // The range is fetched again every run until final, only count what really changed
+        let written = insert_events(&transaction, contract_id, events).await?;
+        every_address_changed |= written.updated;
+        changed_addresses.extend(written.addresses);
+        let events_changed = written.count > 0 || !removed_addresses.is_empty();
+        let mut changed = events_changed;
+        if events_changed {
+            refresh_supply_history(
+                &transaction,
+                contract_id,
+                &addresses::burn_addresses(&contract.burn_addresses),
+                range[0],
+                range[1],
+            )
+            .await?;
+            let touched_token_ids: Vec<String> = touched_token_ids.into_iter().collect();
+            refresh_balances(
+                &transaction,
+                contract_id,
+                Some(touched_token_ids.as_slice()),
+            )
+            .await?;
+        }
+
+        let updates = metadata_updates_by_contract
+            .get(&contract_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let hashes: Vec<&str> = updates
+            .iter()
+            .map(|u| u.transaction_hash.as_str())
+            .collect();
+        let token_ids: Vec<String> = updates.iter().map(|u| u.token_id.to_string()).collect();
+        let removed = transaction
+            .execute(
+                "DELETE FROM metadata_updates WHERE contract_id = $1 AND block_number >= $2 AND block_number <= $3 \
+                AND (transaction_hash, token_id) NOT IN (SELECT * FROM unnest($4::text[], $5::text[]))",
+                &[&contract_id, &range[0], &range[1], &hashes, &token_ids],
+            )
+            .await?;
+        changed |= removed > 0;
+        for update in updates {
+            let written = transaction
                 .execute(
-                    "DELETE FROM events WHERE contract_id = $1 AND block_number >= $2 AND block_number <= $3",
-                    &[&contract_id, &(from_block as i32), &(to_block as i32)],
+                    "INSERT INTO metadata_updates (contract_id, token_id, uri, block_number, transaction_hash) \
+                    VALUES ($1, $2, $3, $4, $5) \
+                    ON CONFLICT (contract_id, transaction_hash, token_id) DO UPDATE SET \
+                    uri = EXCLUDED.uri, block_number = EXCLUDED.block_number \
+                    WHERE (metadata_updates.uri, metadata_updates.block_number) \
+                    IS DISTINCT FROM (EXCLUDED.uri, EXCLUDED.block_number)",
+                    &[
+                        &contract_id,
+                        &update.token_id.to_string(),
+                        &update.uri,
+                        &(update.block_number as i32),
+                        &update.transaction_hash,
+                    ],
                 )
                 .await?;
+            changed |= written > 0;
+        }
 
-            for event in new_events {
-                let ids_as_json = u256_vec_to_json_decimal(&event.ids)?;
-                let values_as_json = u256_vec_to_json_decimal(&event.values)?;
-                let operator_address = checksum(&event.operator);
-                let from_address = checksum(&event.from_address);
-                let to_address = checksum(&event.to_address);
-                let transaction_hash = &event.transaction_hash;
-
-                transaction
-                    .execute(
-                        "INSERT INTO events (contract_id, operator, from_address, to_address, ids, values, block_number, transaction_hash) \
-                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
-                        &[
-                            &contract_id,
-                            &operator_address,
-                            &from_address,
-                            &to_address,
-                            &ids_as_json,
-                            &values_as_json,
-                            &(event.block_number as i32),
-                            &transaction_hash,
-                        ],
-                    )
-                    .await?;
-            }
+        // Sales are only decoded when the chain has marketplaces configured
+        if !chain.marketplaces.is_empty() {
+            let sales = sales_by_contract
+                .get(&contract_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let hashes: Vec<&str> = sales.iter().map(|s| s.transaction_hash.as_str()).collect();
+            let log_indexes: Vec<i32> = sales.iter().map(|s| s.log_index as i32).collect();
+            let removed = transaction
+                .execute(
+                    "DELETE FROM sales WHERE contract_id = $1 AND block_number >= $2 AND block_number <= $3 \
+                    AND (transaction_hash, log_index) NOT IN (SELECT * FROM unnest($4::text[], $5::int4[]))",
+                    &[&contract_id, &range[0], &range[1], &hashes, &log_indexes],
+                )
+                .await?;
+            let inserted = insert_sales(&transaction, contract_id, sales).await?;
+            changed |= removed > 0 || inserted > 0;
+        }
+
+        // Approvals are only fetched when the chain has index_approvals set
+        if chain.index_approvals {
+            let approvals = approvals_by_contract
+                .get(&contract_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let hashes: Vec<&str> = approvals
+                .iter()
+                .map(|a| a.transaction_hash.as_str())
+                .collect();
+            let log_indexes: Vec<i32> = approvals.iter().map(|a| a.log_index as i32).collect();
+            let removed_owners = transaction
+                .query(
+                    "DELETE FROM approvals WHERE contract_id = $1 AND block_number >= $2 AND block_number <= $3 \
+                    AND (transaction_hash, log_index) NOT IN (SELECT * FROM unnest($4::text[], $5::int4[])) \
+                    RETURNING owner",
+                    &[&contract_id, &range[0], &range[1], &hashes, &log_indexes],
+                )
+                .await?;
+            let inserted_owners = insert_approvals(&transaction, contract_id, approvals).await?;
+            changed |= !removed_owners.is_empty() || !inserted_owners.is_empty();
+            changed_addresses.extend(removed_owners.iter().map(|row| row.get::<_, String>(0)));
+            changed_addresses.extend(inserted_owners);
+        }
+
+        transaction
+            .execute(
+                "UPDATE contracts SET last_processed_block = GREATEST(last_processed_block, $1), \
+                 last_indexed_at = now() WHERE id = $2",
+                &[&(std::cmp::min(to_block, safe_block) as i32), &contract_id],
+            )
+            .await?;
+        if changed {
+            changed_contracts.push(contract.address.to_lowercase());
         }
+    }
 
+    // Delivered to the listeners only once the transaction commits
+    if !changed_contracts.is_empty() {
+        changed_addresses.remove(addresses::ZERO_ADDRESS);
+        let mut changed_addresses: Vec<String> = changed_addresses.into_iter().collect();
+        changed_addresses.sort();
+        let notification = EventsNotification {
+            chain: chain.name.to_lowercase(),
+            contracts: changed_contracts,
+            addresses: (!every_address_changed).then_some(changed_addresses),
+        };
         transaction
             .execute(
-                "UPDATE contracts SET last_processed_block = $1 WHERE id = $2",
-                &[&(to_block as i32), &contract_id],
+                "SELECT pg_notify($1, $2)",
+                &[&EVENTS_CHANNEL, &notification.payload()],
             )
             .await?;
     }
@@ -220,3 +1440,265 @@ pub async fn nuke_and_process_events_for_chain(
 
     Ok(())
 }
+
+// Balance of every (address, token) of contract $1 replayed from its events, limited to
+// the token ids $2 unless NULL (`&&` lets the ids GIN index pick the events before they're
+// unnested). Addresses are lowercased, only non-zero balances are kept.
+const REPLAYED_BALANCES: &str = "\
+    SELECT address, id, SUM(value) AS balance FROM ( \
+        SELECT LOWER(e.to_address) AS address, t.id, t.value \
+        FROM events e CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value) \
+        WHERE e.contract_id = $1 \
+        AND ($2::text[] IS NULL OR (e.ids && $2::text[]::numeric[] AND t.id = ANY($2::text[]::numeric[]))) \
+        UNION ALL \
+        SELECT LOWER(e.from_address), t.id, -t.value \
+        FROM events e CROSS JOIN LATERAL unnest(e.ids, e.values) AS t(id, value) \
+        WHERE e.contract_id = $1 \
+        AND ($2::text[] IS NULL OR (e.ids && $2::text[]::numeric[] AND t.id = ANY($2::text[]::numeric[]))) \
+    ) transfers \
+    WHERE address IS NOT NULL \
+    GROUP BY address, id \
+    HAVING SUM(value) <> 0";
+
+/// Rewrites the balances rows of a contract from its events, for `token_ids` only when
+/// given. Returns the number of rows written.
+pub async fn refresh_balances<C>(
+    client: &C,
+    contract_id: i32,
+    token_ids: Option<&[String]>,
+) -> Result<u64, Error>
+where
+    C: GenericClient,
+{
+    client
+        .execute(
+            "DELETE FROM balances WHERE contract_id = $1 \
+            AND ($2::text[] IS NULL OR token_id = ANY($2::text[]::numeric[]))",
+            &[&contract_id, &token_ids],
+        )
+        .await?;
+    client
+        .execute(
+            &format!(
+                "INSERT INTO balances (contract_id, address, token_id, balance) \
+                SELECT $1, address, id, balance FROM ({}) replayed WHERE balance > 0",
+                REPLAYED_BALANCES
+            ),
+            &[&contract_id, &token_ids],
+        )
+        .await
+}
+
+/// Blocks covered by one supply_history row
+pub const SUPPLY_HISTORY_BUCKET_BLOCKS: i32 = 1000;
+
+/// Recomputes from the events the supply_history rows of contract `contract_id` for every
+/// bucket overlapping the blocks `from_block..=to_block`, so re-reading a range never counts
+/// a transfer twice. Mints are transfers from the zero address, burns transfers to one of
+/// `burn_addresses` (lowercased). Returns the number of rows written.
+pub async fn refresh_supply_history<C>(
+    client: &C,
+    contract_id: i32,
+    burn_addresses: &[String],
+    from_block: i32,
+    to_block: i32,
+) -> Result<u64, Error>
+where
+    C: GenericClient,
+{
+    let first_bucket = from_block / SUPPLY_HISTORY_BUCKET_BLOCKS * SUPPLY_HISTORY_BUCKET_BLOCKS;
+    let last_bucket = to_block / SUPPLY_HISTORY_BUCKET_BLOCKS * SUPPLY_HISTORY_BUCKET_BLOCKS;
+    client
+        .execute(
+            "DELETE FROM supply_history WHERE contract_id = $1 \
+            AND start_block >= $2 AND start_block <= $3",
+            &[&contract_id, &first_bucket, &last_bucket],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO supply_history (contract_id, start_block, end_block, minted, burned, block_timestamp) \
+            SELECT $1, bucket, bucket + $4 - 1, minted, burned, block_timestamp FROM ( \
+                SELECT e.block_number / $4 * $4 AS bucket, \
+                COALESCE(SUM(t.value) FILTER (WHERE LOWER(e.from_address) = $5), 0) AS minted, \
+                COALESCE(SUM(t.value) FILTER (WHERE LOWER(e.to_address) = ANY($6)), 0) AS burned, \
+                MAX(e.block_timestamp) AS block_timestamp \
+                FROM events e CROSS JOIN LATERAL unnest(e.values) AS t(value) \
+                WHERE e.contract_id = $1 AND e.block_number >= $2 AND e.block_number < $3 + $4 \
+                GROUP BY bucket \
+            ) buckets \
+            WHERE minted > 0 OR burned > 0",
+            &[
+                &contract_id,
+                &first_bucket,
+                &last_bucket,
+                &SUPPLY_HISTORY_BUCKET_BLOCKS,
+                &addresses::ZERO_ADDRESS,
+                &burn_addresses,
+            ],
+        )
+        .await
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexedContract {
+    pub id: i32,
+    pub chain_name: String,
+    pub name: String,
+    pub address: String,
+}
+
+/// Every contract with a row, whether or not it's still configured
+pub async fn get_indexed_contracts(client: &Client) -> Result<Vec<IndexedContract>, Error> {
+    let rows = client
+        .query(
+            "SELECT c.id, ch.name AS chain_name, c.name, c.address FROM contracts c \
+            JOIN chains ch ON c.chain_id = ch.id ORDER BY ch.name, c.address",
+            &[],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| IndexedContract {
+            id: row.get("id"),
+            chain_name: row.get("chain_name"),
+            name: row.get("name"),
+            address: row.get("address"),
+        })
+        .collect())
+}
+
+// A balances row that disagrees with the replay of the events. None is a missing row on
+// the stored side, and no balance on the replayed side.
+#[derive(Debug, Clone)]
+pub struct BalanceDiff {
+    pub address: String,
+    pub token_id: String,
+    pub stored: Option<String>,
+    pub replayed: Option<String>,
+}
+
+/// Rows of the balances table of a contract that differ from a replay of its events
+pub async fn diff_balances(contract_id: i32, client: &Client) -> Result<Vec<BalanceDiff>, Error> {
+    let rows = client
+        .query(
+            &format!(
+                "WITH replayed AS ({}), \
+                stored AS (SELECT address, token_id AS id, balance FROM balances WHERE contract_id = $1) \
+                SELECT COALESCE(r.address, s.address) AS address, COALESCE(r.id, s.id)::text AS token_id, \
+                s.balance::text AS stored, r.balance::text AS replayed \
+                FROM (SELECT * FROM replayed WHERE balance > 0) r \
+                FULL OUTER JOIN stored s ON r.address = s.address AND r.id = s.id \
+                WHERE r.balance IS DISTINCT FROM s.balance \
+                ORDER BY COALESCE(r.id, s.id), COALESCE(r.address, s.address)",
+                REPLAYED_BALANCES
+            ),
+            &[&contract_id, &None::<Vec<String>>],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| BalanceDiff {
+            address: row.get("address"),
+            token_id: row.get("token_id"),
+            stored: row.get("stored"),
+            replayed: row.get("replayed"),
+        })
+        .collect())
+}
+
+/// (address, token id, balance) of the addresses the replay leaves with a negative
+/// balance, the zero address aside: their incoming transfers are missing from the events
+pub async fn negative_balances(
+    contract_id: i32,
+    client: &Client,
+) -> Result<Vec<(String, String, String)>, Error> {
+    let rows = client
+        .query(
+            &format!(
+                "SELECT address, id::text AS token_id, balance::text AS balance FROM ({}) replayed \
+                WHERE balance < 0 AND address <> $3 ORDER BY id, address",
+                REPLAYED_BALANCES
+            ),
+            &[&contract_id, &None::<Vec<String>>, &addresses::ZERO_ADDRESS],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get("address"), row.get("token_id"), row.get("balance")))
+        .collect())
+}
+
+/// One token of a transfer event, as written by `afterlife export`
+#[derive(Debug, Clone)]
+pub struct ExportedTransfer {
+    pub block_number: i32,
+    // Microseconds since the epoch, and the same instant in RFC 3339
+    pub block_timestamp_micros: Option<i64>,
+    pub block_time: Option<String>,
+    pub transaction_hash: String,
+    pub transaction_index: Option<i32>,
+    pub log_index: i32,
+    pub operator: Option<String>,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    // Decimal strings, uint256 doesn't fit any integer column type
+    pub token_id: String,
+    pub value: String,
+}
+
+/// The UTC months (YYYY-MM) a contract has events in, None for events without a block
+/// timestamp
+pub async fn get_event_months(
+    contract_id: i32,
+    client: &Client,
+) -> Result<Vec<Option<String>>, Error> {
+    let rows = client
+        .query(
+            "SELECT DISTINCT to_char(block_timestamp AT TIME ZONE 'UTC', 'YYYY-MM') AS month \
+            FROM events WHERE contract_id = $1 ORDER BY month",
+            &[&contract_id],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get("month")).collect())
+}
+
+/// The events of a contract in a month from get_event_months, one row per token id, in
+/// chain order
+pub async fn get_exported_transfers(
+    contract_id: i32,
+    month: Option<&str>,
+    client: &Client,
+) -> Result<Vec<ExportedTransfer>, Error> {
+    let rows = client
+        .query(
+            "SELECT e.block_number, \
+                (EXTRACT(EPOCH FROM e.block_timestamp) * 1000000)::bigint AS block_timestamp_micros, \
+                to_char(e.block_timestamp AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS block_time, \
+                e.transaction_hash, e.transaction_index, e.log_index, e.operator, \
+                e.from_address, e.to_address, t.id::text AS token_id, t.value::text AS value \
+            FROM events e \
+            CROSS JOIN LATERAL unnest(e.ids, e.values) WITH ORDINALITY AS t(id, value, position) \
+            WHERE e.contract_id = $1 \
+            AND to_char(e.block_timestamp AT TIME ZONE 'UTC', 'YYYY-MM') IS NOT DISTINCT FROM $2 \
+            ORDER BY e.block_number, e.log_index, t.position",
+            &[&contract_id, &month],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| ExportedTransfer {
+            block_number: row.get("block_number"),
+            block_timestamp_micros: row.get("block_timestamp_micros"),
+            block_time: row.get("block_time"),
+            transaction_hash: row.get("transaction_hash"),
+            transaction_index: row.get("transaction_index"),
+            log_index: row.get("log_index"),
+            operator: row.get("operator"),
+            from_address: row.get("from_address"),
+            to_address: row.get("to_address"),
+            token_id: row.get("token_id"),
+            value: row.get("value"),
+        })
+        .collect())
+}
@@ -1,23 +1,35 @@
-use crate::indexer::indexer_config::{Chain, Contract};
-use crate::indexer::log_decode::{decode_erc1155_transfer_batch, decode_erc1155_transfer_single};
-use crate::indexer::queries::Event;
-use bigdecimal::num_traits::AsPrimitive;
-use futures::stream::{FuturesUnordered, StreamExt};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use crate::indexer::indexer_config::{Chain, Contract, Marketplace, MarketplaceProtocol};
+use crate::indexer::log_decode::{
+    decode_approval_for_all, decode_erc1155_transfer_batch, decode_erc1155_transfer_single,
+    decode_erc1155_uri, decode_erc721_transfer, decode_looksrare_taker,
+    decode_seaport_order_fulfilled, DecodedSale,
+};
+use crate::indexer::queries::{Approval, Event, MetadataUpdate, Sale};
+use crate::indexer::rpc_pool::RpcPool;
+use futures::future;
+use futures::stream::{self, FuturesUnordered, StreamExt};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::From;
-use std::convert::TryInto;
 use std::error::Error;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use tokio::sync::Semaphore;
-use tokio::time::{sleep, Duration};
+use std::sync::Mutex;
+use tokio::sync::watch;
+use tokio::time::{sleep, timeout, Duration, Instant};
 use web3::error::{Error as Web3Error, TransportError};
-use web3::transports::Http;
-use web3::types::{BlockNumber, FilterBuilder, Log, H160, H256, U256};
-use web3::Web3;
+use web3::types::{BlockId, BlockNumber, FilterBuilder, Log, H160, H256, U256};
+use web3::Transport;
 
 const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
 const MAX_RETRY_COUNT: usize = 5;
+// Upper bound for the adaptive chunk size
+const MAX_CHUNK_SIZE: usize = 100_000;
+const LOGS_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Concurrent eth_getBlockByNumber calls when resolving event timestamps
+const BLOCK_TIMESTAMP_CONCURRENCY: usize = 16;
+
+// (endpoint url, chain id) pairs whose eth_chainId matched, checked once per process
+static VERIFIED_ENDPOINTS: Lazy<Mutex<HashSet<(String, u64)>>> =
+    Lazy::new(|| Mutex::new(HashSet::new()));
 
 const TRANSFER_TOPIC: H256 = H256([
     0xdd, 0xf2, 0x52, 0xad, 0x1b, 0xe2, 0xc8, 0x9b, 0x69, 0xc2, 0xb0, 0x68, 0xfc, 0x37, 0x8d, 0xaa,
@@ -33,6 +45,45 @@ const TRANSFER_BATCH_TOPIC: H256 = H256([
     0x3a, 0x51, 0x8a, 0xa5, 0xd0, 0x7e, 0x59, 0x5d, 0x98, 0x3b, 0x8c, 0x05, 0x26, 0xc8, 0xf7, 0xfb,
 ]);
 
+const URI_TOPIC: H256 = H256([
+    0x6b, 0xb7, 0xff, 0x70, 0x86, 0x19, 0xba, 0x06, 0x10, 0xcb, 0xa2, 0x95, 0xa5, 0x85, 0x92, 0xe0,
+    0x45, 0x1d, 0xee, 0x26, 0x22, 0x93, 0x8c, 0x87, 0x55, 0x66, 0x76, 0x88, 0xda, 0xf3, 0x52, 0x9b,
+]);
+
+// ApprovalForAll(address,address,bool), ERC-721 and ERC-1155
+const APPROVAL_FOR_ALL_TOPIC: H256 = H256([
+    0x17, 0x30, 0x7e, 0xab, 0x39, 0xab, 0x61, 0x07, 0xe8, 0x89, 0x98, 0x45, 0xad, 0x3d, 0x59, 0xbd,
+    0x96, 0x53, 0xf2, 0x00, 0xf2, 0x20, 0x92, 0x04, 0x89, 0xca, 0x2b, 0x59, 0x37, 0x69, 0x6c, 0x31,
+]);
+
+// Seaport OrderFulfilled(bytes32,address,address,address,(uint8,address,uint256,uint256)[],(uint8,address,uint256,uint256,address)[])
+const ORDER_FULFILLED_TOPIC: H256 = H256([
+    0x9d, 0x9a, 0xf8, 0xe3, 0x8d, 0x66, 0xc6, 0x2e, 0x2c, 0x12, 0xf0, 0x22, 0x52, 0x49, 0xfd, 0x9d,
+    0x72, 0x1c, 0x54, 0xb8, 0x3f, 0x48, 0xd9, 0x35, 0x2c, 0x97, 0xc6, 0xca, 0xcd, 0xcb, 0x6f, 0x31,
+]);
+
+// LooksRare TakerAsk(bytes32,uint256,address,address,address,address,address,uint256,uint256,uint256)
+const TAKER_ASK_TOPIC: H256 = H256([
+    0x68, 0xcd, 0x25, 0x1d, 0x4d, 0x26, 0x7c, 0x6e, 0x20, 0x34, 0xff, 0x00, 0x88, 0xb9, 0x90, 0x35,
+    0x2b, 0x97, 0xb2, 0x00, 0x2c, 0x04, 0x76, 0x58, 0x7d, 0x0c, 0x4d, 0xa8, 0x89, 0xc1, 0x13, 0x30,
+]);
+
+// LooksRare TakerBid, same parameters as TakerAsk
+const TAKER_BID_TOPIC: H256 = H256([
+    0x95, 0xfb, 0x62, 0x05, 0xe2, 0x3f, 0xf6, 0xbd, 0xa1, 0x6a, 0x2d, 0x1d, 0xba, 0x56, 0xb9, 0xad,
+    0x7c, 0x78, 0x3f, 0x67, 0xc9, 0x6f, 0xa1, 0x49, 0x78, 0x50, 0x52, 0xf4, 0x76, 0x96, 0xf2, 0xbe,
+]);
+
+// Decoded logs of one block range: transfers, URI updates, marketplace sales and approvals,
+// then the number of logs that couldn't be decoded per contract address
+pub type ChunkLogs = (
+    Vec<Event>,
+    Vec<MetadataUpdate>,
+    Vec<Sale>,
+    Vec<Approval>,
+    HashMap<String, usize>,
+);
+
 #[derive(Debug)]
 pub enum EventFetcherError {
     Web3Error(web3::Error),
@@ -52,6 +103,78 @@ impl From<web3::Error> for EventFetcherError {
     }
 }
 
+fn log_transaction_index(log: &Log) -> Result<u64, EventFetcherError> {
+    log.transaction_index
+        .map(|index| index.as_u64())
+        .ok_or_else(|| EventFetcherError::Custom("Log without transaction_index".into()))
+}
+
+fn log_log_index(log: &Log) -> Result<u64, EventFetcherError> {
+    log.log_index
+        .map(|index| index.as_u64())
+        .ok_or_else(|| EventFetcherError::Custom("Log without log_index".into()))
+}
+
+fn log_block_number(log: &Log) -> Result<u64, EventFetcherError> {
+    log.block_number
+        .map(|number| number.as_u64())
+        .ok_or_else(|| EventFetcherError::Custom("Log without block_number".into()))
+}
+
+fn log_transaction_hash(log: &Log) -> Result<String, EventFetcherError> {
+    log.transaction_hash
+        .map(|hash| format!("{:?}", hash))
+        .ok_or_else(|| EventFetcherError::Custom("Log without transaction_hash".into()))
+}
+
+fn log_topic_address(log: &Log, index: usize) -> Result<H160, EventFetcherError> {
+    log.topics
+        .get(index)
+        .map(|&topic| H160::from(topic))
+        .ok_or_else(|| EventFetcherError::Custom(format!("Log without topic {}", index).into()))
+}
+
+// Logs of pending blocks have no block or transaction yet, they are picked up once mined
+fn is_pending(log: &Log) -> bool {
+    log.block_number.is_none() || log.transaction_hash.is_none() || log.log_index.is_none()
+}
+
+// Outcome of fetching a single block range
+#[derive(Debug)]
+enum ChunkError {
+    // The provider refused the range (too many results, or timed out), it should be split
+    RangeTooLarge(usize, usize),
+    Failed(EventFetcherError),
+}
+
+impl From<EventFetcherError> for ChunkError {
+    fn from(err: EventFetcherError) -> Self {
+        ChunkError::Failed(err)
+    }
+}
+
+// Providers word "range too large" errors differently, match the common phrasings
+fn is_range_too_large(err: &Web3Error) -> bool {
+    let message = match err {
+        Web3Error::Rpc(rpc_error) => rpc_error.message.to_lowercase(),
+        Web3Error::Transport(TransportError::Message(message)) => message.to_lowercase(),
+        Web3Error::InvalidResponse(message) => message.to_lowercase(),
+        _ => return false,
+    };
+    [
+        "more than",
+        "too many",
+        "range too large",
+        "block range",
+        "limit exceeded",
+        "response size",
+        "timeout",
+        "timed out",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
 impl From<Box<dyn Error>> for EventFetcherError {
     fn from(err: Box<dyn Error>) -> Self {
         EventFetcherError::Custom(err)
@@ -83,27 +206,66 @@ pub struct TransferBatch {
     pub values: Vec<U256>,
 }
 
+/// Where a fetch run is at, published after every chunk
+#[derive(Debug, Clone, Default)]
+pub struct FetchProgress {
+    pub from_block: usize,
+    pub to_block: usize,
+    pub chunks_done: usize,
+    // Estimate, the remaining blocks divided by the current chunk size
+    pub chunks_total: usize,
+    pub elapsed: Duration,
+    // None until a chunk is done, and once the run is
+    pub eta: Option<Duration>,
+}
+
 pub struct EventFetcher<'a> {
     chain: &'a Chain,
-    web3: Web3<Http>,
+    rpc: RpcPool,
     last_processed_block: usize,
+    progress: Option<watch::Sender<FetchProgress>>,
 }
 
 impl<'a> EventFetcher<'a> {
-    pub fn new(chain: &'a Chain, last_processed_block: usize) -> Self {
-        let http = Http::new(&chain.rpc_url).expect("RPC initialization failed");
-        let web3 = Web3::new(http);
-
-        Self {
+    pub fn new(chain: &'a Chain, last_processed_block: usize) -> Result<Self, EventFetcherError> {
+        let rpc = RpcPool::new(
+            &chain.rpc_endpoints(),
+            chain.max_concurrent_requests,
+            chain.requests_per_second,
+        )?;
+
+        Ok(Self {
             chain,
-            web3,
+            rpc,
             last_processed_block,
-        }
+            progress: None,
+        })
     }
 
-    pub async fn execute(&self) -> Result<(Vec<Event>, (usize, usize)), EventFetcherError> {
+    /// Publishes the run's progress to `progress` as chunks complete
+    pub fn with_progress(mut self, progress: watch::Sender<FetchProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Fetches everything up to the chain head. Returns the fetched range, and the chain's
+    /// head and safe block as read from the RPC: blocks after the safe block may still be
+    /// reorganized, they aren't final yet.
+    pub async fn execute(
+        &self,
+    ) -> Result<(ChunkLogs, (usize, usize), (usize, usize)), EventFetcherError> {
+        self.verify_chain_id().await?;
         let mut events = Vec::new();
+        let mut metadata_updates = Vec::new();
+        let mut sales = Vec::new();
+        let mut approvals = Vec::new();
+        let mut undecodable: HashMap<String, usize> = HashMap::new();
         let current_block = self.retry_fetch_current_block().await?;
+        let safe_block = if self.chain.finalized {
+            std::cmp::min(self.retry_fetch_finalized_block().await?, current_block)
+        } else {
+            current_block.saturating_sub(self.chain.confirmations)
+        };
 
         let look_back_start_block = if current_block <= self.last_processed_block + 2000 {
             // If we are within one chunk of the last processed block, look back a full chunk
@@ -126,131 +288,357 @@ impl<'a> EventFetcher<'a> {
         let mut from_block = usize::MAX;
         let mut to_block = 0;
 
-        let chunks: Vec<(usize, usize)> = (start_block..current_block)
-            .step_by(self.chain.chunk_size)
-            .map(|start| {
-                let end = std::cmp::min(start + self.chain.chunk_size - 1, current_block);
-                (start, end)
-            })
-            .collect();
+        // The configured chunk_size is only a starting point: ranges the provider refuses
+        // are split in half, and the size grows back a little after every successful chunk.
+        let mut chunk_size = self.chain.chunk_size.max(1);
+        let mut next_start = start_block;
+        let mut split_ranges: VecDeque<(usize, usize)> = VecDeque::new();
+        let mut tasks = FuturesUnordered::new();
 
-        let current_chunk = Arc::new(AtomicUsize::new(0));
-        let total_chunks = chunks.len() as f64; // Cast to f64 for floating-point division
+        let started_at = Instant::now();
+        let blocks_total = (current_block + 1).saturating_sub(start_block);
+        let mut blocks_done = 0;
+        let mut chunks_done = 0;
 
-        let semaphore = Arc::new(Semaphore::new(80)); // limit to 2 concurrent tasks
+        loop {
+            // More chunks than requests allowed in flight would only queue on the pool
+            while tasks.len() < self.chain.max_concurrent_requests.max(1) {
+                let (chunk_start, chunk_end) = if let Some(range) = split_ranges.pop_front() {
+                    range
+                } else if next_start <= current_block {
+                    let end = std::cmp::min(next_start + chunk_size - 1, current_block);
+                    let range = (next_start, end);
+                    next_start = end + 1;
+                    range
+                } else {
+                    break;
+                };
+                tasks.push(self.fetch_chunk(chunk_start, chunk_end));
+            }
 
-        let mut tasks = FuturesUnordered::new();
+            let result = match tasks.next().await {
+                Some(result) => result,
+                None => break,
+            };
 
-        for (chunk_start, chunk_end) in chunks {
-            let current_chunk_clone = Arc::clone(&current_chunk);
-            let addresses: Vec<H160> = self
-                .chain
-                .contracts
-                .iter()
-                .filter_map(|contract| contract.address.parse().ok())
-                .collect();
-
-            let filter = FilterBuilder::default()
-                .from_block(BlockNumber::Number(chunk_start.into()))
-                .to_block(BlockNumber::Number(chunk_end.into()))
-                .address(addresses)
-                .topics(
-                    Some(vec![
-                        TRANSFER_TOPIC,
-                        TRANSFER_SINGLE_TOPIC,
-                        TRANSFER_BATCH_TOPIC,
-                    ]),
-                    None,
-                    None,
-                    None,
-                )
-                .build();
-
-            let web3 = self.web3.clone();
-            let semaphore_clone = semaphore.clone();
-
-            tasks.push(async move {
-                let _permit = semaphore_clone
-                    .acquire_owned()
-                    .await
-                    .expect("Failed to acquire semaphore permit");
-                let mut retry_delay = INITIAL_RETRY_DELAY;
-                let mut attempts = 0;
-
-                loop {
-                    match web3.eth().logs(filter.clone()).await {
-                        Ok(logs) => {
-                            let mut events_chunk = Vec::new();
-                            for log in logs {
-                                // Your logic to convert logs to events goes here
-                                let contract_address = log.address;
-                                if let Some(contract) = self.chain.contracts.iter().find(|&c| {
-                                    c.address.parse::<H160>().unwrap_or_default()
-                                        == contract_address
-                                }) {
-                                    let event = if log.topics[0] == TRANSFER_TOPIC {
-                                        self.erc721_to_dbevent(&log, contract)?
-                                    } else if log.topics[0] == TRANSFER_SINGLE_TOPIC {
-                                        self.erc1155_to_single_dbevent(&log, contract)?
-                                    } else if log.topics[0] == TRANSFER_BATCH_TOPIC {
-                                        self.erc1155_to_batch_dbevent(&log, contract)?
-                                    } else {
-                                        eprintln!("Unknown topic: {:?}", log.topics[0]);
-                                        eprintln!("Log: {:?}", log);
-                                        continue;
-                                    };
-                                    events_chunk.push(event);
-                                }
+            match result {
+                Ok((
+                    (
+                        mut events_chunk,
+                        mut updates_chunk,
+                        mut sales_chunk,
+                        mut approvals_chunk,
+                        undecodable_chunk,
+                    ),
+                    (chunk_start, chunk_end),
+                )) => {
+                    from_block = std::cmp::min(from_block, chunk_start);
+                    to_block = std::cmp::max(to_block, chunk_end);
+
+                    events.append(&mut events_chunk);
+                    metadata_updates.append(&mut updates_chunk);
+                    sales.append(&mut sales_chunk);
+                    approvals.append(&mut approvals_chunk);
+                    for (address, count) in undecodable_chunk {
+                        *undecodable.entry(address).or_default() += count;
+                    }
+
+                    chunk_size = std::cmp::min(chunk_size + chunk_size / 4 + 1, MAX_CHUNK_SIZE);
+
+                    blocks_done += chunk_end - chunk_start + 1;
+                    chunks_done += 1;
+                    if let Some(progress) = &self.progress {
+                        let blocks_left = blocks_total.saturating_sub(blocks_done);
+                        let unscheduled = (current_block + 1).saturating_sub(next_start);
+                        let elapsed = started_at.elapsed();
+                        progress.send_replace(FetchProgress {
+                            from_block: start_block,
+                            to_block: current_block,
+                            chunks_done,
+                            chunks_total: chunks_done
+                                + tasks.len()
+                                + split_ranges.len()
+                                + unscheduled.div_ceil(chunk_size),
+                            elapsed,
+                            eta: (blocks_left > 0)
+                                .then(|| elapsed.mul_f64(blocks_left as f64 / blocks_done as f64)),
+                        });
+                    }
+                }
+                Err(ChunkError::RangeTooLarge(chunk_start, chunk_end)) => {
+                    let middle = chunk_start + (chunk_end - chunk_start) / 2;
+                    split_ranges.push_back((chunk_start, middle));
+                    split_ranges.push_back((middle + 1, chunk_end));
+
+                    chunk_size = std::cmp::max(chunk_size / 2, 1);
+                    eprintln!(
+                        "Block range {}-{} too large on {}, splitting (chunk size now {})",
+                        chunk_start, chunk_end, self.chain.name, chunk_size
+                    );
+                }
+                Err(ChunkError::Failed(e)) => {
+                    // Give up on this run, the remaining in-flight chunks are dropped
+                    return Err(e);
+                }
+            }
+        }
+
+        self.attach_block_timestamps(&mut events, &mut sales, &mut approvals)
+            .await;
+
+        Ok((
+            (events, metadata_updates, sales, approvals, undecodable),
+            (from_block, to_block),
+            (current_block, safe_block),
+        ))
+    }
+
+    // Fetch and decode the logs of one block range, retrying transient errors.
+    // Ranges the provider rejects as too large are handed back to be split.
+    async fn fetch_chunk(
+        &self,
+        chunk_start: usize,
+        chunk_end: usize,
+    ) -> Result<(ChunkLogs, (usize, usize)), ChunkError> {
+        let addresses: Vec<H160> = self
+            .chain
+            .contracts
+            .iter()
+            .map(|contract| &contract.address)
+            .chain(self.chain.marketplaces.iter().map(|m| &m.address))
+            .filter_map(|address| address.parse().ok())
+            .collect();
+        let mut topics = vec![
+            TRANSFER_TOPIC,
+            TRANSFER_SINGLE_TOPIC,
+            TRANSFER_BATCH_TOPIC,
+            URI_TOPIC,
+        ];
+        if !self.chain.marketplaces.is_empty() {
+            topics.extend([ORDER_FULFILLED_TOPIC, TAKER_ASK_TOPIC, TAKER_BID_TOPIC]);
+        }
+        if self.chain.index_approvals {
+            topics.push(APPROVAL_FOR_ALL_TOPIC);
+        }
+
+        let filter = FilterBuilder::default()
+            .from_block(BlockNumber::Number(chunk_start.into()))
+            .to_block(BlockNumber::Number(chunk_end.into()))
+            .address(addresses)
+            .topics(Some(topics), None, None, None)
+            .build();
+
+        let mut retry_delay = INITIAL_RETRY_DELAY;
+        let mut attempts = 0;
+
+        loop {
+            let (endpoint, web3, permit) = self.rpc.pick().await;
+            let response =
+                match timeout(LOGS_REQUEST_TIMEOUT, web3.eth().logs(filter.clone())).await {
+                    Ok(response) => response,
+                    // Slow responses usually mean the range holds too many logs
+                    Err(_) if chunk_start < chunk_end => {
+                        return Err(ChunkError::RangeTooLarge(chunk_start, chunk_end))
+                    }
+                    Err(_) => Err(Web3Error::Transport(TransportError::Message(
+                        "Request timed out".to_string(),
+                    ))),
+                };
+            drop(permit);
+
+            match response {
+                Ok(logs) => {
+                    self.rpc.report_success(endpoint);
+                    let mut events_chunk = Vec::new();
+                    let mut updates_chunk = Vec::new();
+                    let mut sales_chunk = Vec::new();
+                    let mut approvals_chunk = Vec::new();
+                    let mut undecodable_chunk: HashMap<String, usize> = HashMap::new();
+                    for log in logs {
+                        if is_pending(&log) {
+                            continue;
+                        }
+                        let contract_address = log.address;
+                        if let Some(marketplace) = self.chain.marketplaces.iter().find(|m| {
+                            m.address.parse::<H160>().unwrap_or_default() == contract_address
+                        }) {
+                            if let Some(sale) = self.marketplace_log_to_sale(&log, marketplace) {
+                                sales_chunk.push(sale);
                             }
-                            // After processing each chunk, we increment the counter
-                            let task_chunk_index =
-                                current_chunk_clone.fetch_add(1, Ordering::SeqCst);
-
-                            // We calculate the progress
-                            let progress = ((task_chunk_index + 1) as f64 / total_chunks) * 100.0;
-                            //println!("Chunk {} of {} completed. Progress: {:.2}%", task_chunk_index + 1, total_chunks, progress);
-                            return Ok((events_chunk, (chunk_start, chunk_end)));
+                            continue;
                         }
-                        Err(e) => {
-                            if attempts >= MAX_RETRY_COUNT {
-                                panic!(
-                                    "Failed to fetch logs after {} attempts: {:?}",
-                                    MAX_RETRY_COUNT, e
-                                );
-                                return Err(EventFetcherError::from(e));
+                        if let Some(contract) = self.chain.contracts.iter().find(|&c| {
+                            c.address.parse::<H160>().unwrap_or_default() == contract_address
+                        }) {
+                            let topic = log.topics.first().copied().unwrap_or_default();
+                            // A log that can't be decoded is reported and counted, it
+                            // shouldn't cost the rest of the chunk but the contract's
+                            // range isn't written, see write_events_for_chain
+                            let result = if topic == TRANSFER_TOPIC {
+                                self.erc721_to_dbevent(&log, contract).map(Some)
+                            } else if topic == TRANSFER_SINGLE_TOPIC {
+                                self.erc1155_to_single_dbevent(&log, contract).map(Some)
+                            } else if topic == TRANSFER_BATCH_TOPIC {
+                                self.erc1155_to_batch_dbevent(&log, contract).map(Some)
+                            } else if topic == URI_TOPIC {
+                                self.erc1155_uri_to_update(&log, contract).map(|update| {
+                                    updates_chunk.push(update);
+                                    None
+                                })
+                            } else if topic == APPROVAL_FOR_ALL_TOPIC {
+                                self.approval_for_all_to_approval(&log, contract)
+                                    .map(|approval| {
+                                        approvals_chunk.push(approval);
+                                        None
+                                    })
+                            } else {
+                                eprintln!("Unknown topic: {:?}", topic);
+                                eprintln!("Log: {:?}", log);
+                                continue;
+                            };
+                            match result {
+                                Ok(Some(event)) => events_chunk.push(event),
+                                Ok(None) => {}
+                                Err(e) => {
+                                    eprintln!(
+                                        "[{}] Undecodable log {:?} of {}: {:?}",
+                                        self.chain.name, log.transaction_hash, contract.address, e
+                                    );
+                                    *undecodable_chunk
+                                        .entry(contract.address.clone())
+                                        .or_default() += 1;
+                                }
                             }
-                            eprintln!(
-                                "Error fetching logs: {}. Retrying in {:?}... (Attempt {} of {})",
-                                e,
-                                retry_delay,
-                                attempts + 1,
-                                MAX_RETRY_COUNT
-                            );
-                            sleep(retry_delay).await;
-                            retry_delay *= 2;
-                            attempts += 1;
                         }
                     }
+                    return Ok((
+                        (
+                            events_chunk,
+                            updates_chunk,
+                            sales_chunk,
+                            approvals_chunk,
+                            undecodable_chunk,
+                        ),
+                        (chunk_start, chunk_end),
+                    ));
                 }
-            });
-        }
-
-        while let Some(result) = tasks.next().await {
-            match result {
-                Ok((mut events_chunk, (chunk_start, chunk_end))) => {
-                    from_block = std::cmp::min(from_block, chunk_start);
-                    to_block = std::cmp::max(to_block, chunk_end);
-
-                    events.append(&mut events_chunk);
+                Err(e) if chunk_start < chunk_end && is_range_too_large(&e) => {
+                    return Err(ChunkError::RangeTooLarge(chunk_start, chunk_end));
                 }
                 Err(e) => {
-                    // Handle any errors that arose within the spawned tasks
-                    panic!("Error fetching logs: {:?}", e)
+                    self.rpc.report_failure(endpoint, &e);
+                    if attempts >= MAX_RETRY_COUNT {
+                        eprintln!(
+                            "Failed to fetch logs after {} attempts: {:?}",
+                            MAX_RETRY_COUNT, e
+                        );
+                        return Err(ChunkError::Failed(EventFetcherError::from(e)));
+                    }
+                    eprintln!(
+                        "Error fetching logs: {}. Retrying in {:?}... (Attempt {} of {})",
+                        e,
+                        retry_delay,
+                        attempts + 1,
+                        MAX_RETRY_COUNT
+                    );
+                    sleep(retry_delay).await;
+                    retry_delay *= 2;
+                    attempts += 1;
                 }
             }
         }
+    }
+
+    // Fill in block_timestamp for every event, sale and approval. Blocks whose timestamp
+    // can't be fetched are left as None rather than failing the whole run.
+    async fn attach_block_timestamps(
+        &self,
+        events: &mut [Event],
+        sales: &mut [Sale],
+        approvals: &mut [Approval],
+    ) {
+        let blocks: HashSet<u64> = events
+            .iter()
+            .map(|e| e.block_number)
+            .chain(sales.iter().map(|s| s.block_number))
+            .chain(approvals.iter().map(|a| a.block_number))
+            .collect();
+        let timestamps: HashMap<u64, u64> = stream::iter(blocks)
+            .map(move |block_number| async move {
+                match self.retry_fetch_block_timestamp(block_number).await {
+                    Ok(timestamp) => Some((block_number, timestamp)),
+                    Err(e) => {
+                        eprintln!(
+                            "Failed to fetch timestamp of block {} on {}: {:?}",
+                            block_number, self.chain.name, e
+                        );
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(BLOCK_TIMESTAMP_CONCURRENCY)
+            .filter_map(future::ready)
+            .collect()
+            .await;
+
+        for event in events.iter_mut() {
+            event.block_timestamp = timestamps.get(&event.block_number).copied();
+        }
+        for sale in sales.iter_mut() {
+            sale.block_timestamp = timestamps.get(&sale.block_number).copied();
+        }
+        for approval in approvals.iter_mut() {
+            approval.block_timestamp = timestamps.get(&approval.block_number).copied();
+        }
+    }
+
+    // Decodes a marketplace log into a sale of one of the configured contracts. Logs that
+    // don't decode, or trade tokens of other collections, yield None.
+    fn marketplace_log_to_sale(&self, log: &Log, marketplace: &Marketplace) -> Option<Sale> {
+        let topic = *log.topics.first()?;
+        let decoded: Result<Option<DecodedSale>, ethabi::Error> = match marketplace.protocol {
+            MarketplaceProtocol::Seaport if topic == ORDER_FULFILLED_TOPIC => {
+                decode_seaport_order_fulfilled(log)
+            }
+            MarketplaceProtocol::LooksRare if topic == TAKER_ASK_TOPIC => {
+                decode_looksrare_taker(log, true).map(Some)
+            }
+            MarketplaceProtocol::LooksRare if topic == TAKER_BID_TOPIC => {
+                decode_looksrare_taker(log, false).map(Some)
+            }
+            _ => return None,
+        };
+        let decoded = match decoded {
+            Ok(decoded) => decoded?,
+            Err(e) => {
+                eprintln!(
+                    "Failed to decode {} sale in {:?}: {}",
+                    marketplace.name, log.transaction_hash, e
+                );
+                return None;
+            }
+        };
 
-        Ok((events, (from_block, to_block)))
+        let contract = self
+            .chain
+            .contracts
+            .iter()
+            .find(|c| c.address.parse::<H160>().unwrap_or_default() == decoded.collection)?;
+
+        Some(Sale {
+            contract: contract.clone(),
+            marketplace: marketplace.name.clone(),
+            token_id: decoded.token_id,
+            amount: decoded.amount,
+            seller: format!("{:?}", decoded.seller),
+            buyer: format!("{:?}", decoded.buyer),
+            price: decoded.price,
+            currency: format!("{:?}", decoded.currency),
+            block_number: log.block_number?.as_u64(),
+            transaction_hash: format!("{:?}", log.transaction_hash?),
+            log_index: log.log_index?.as_u64(),
+            block_timestamp: None,
+        })
     }
 
     fn erc721_to_dbevent(
@@ -258,24 +646,32 @@ impl<'a> EventFetcher<'a> {
         log: &Log,
         contract: &Contract,
     ) -> Result<Event, EventFetcherError> {
-        let from_address: H160 = log.topics[1].try_into().unwrap();
-        let to_address: H160 = log.topics[2].try_into().unwrap();
-        // id is topics[3]
-        let id = U256::from_big_endian(&log.topics[3].0);
+        let (from_address, to_address, id) = decode_erc721_transfer(log, contract.abi_variant)
+            .map_err(|e| {
+                EventFetcherError::Custom(
+                    format!(
+                        "Failed to decode Transfer of {} in {:?} (wrong abi_variant?): {}",
+                        contract.address, log.transaction_hash, e
+                    )
+                    .into(),
+                )
+            })?;
         let ids = vec![id];
         let values: Vec<U256> = vec![U256::from(1)]; // For ERC721, the value is always 1
 
-        Ok(Event::new(
+        Event::new(
             contract.clone(),
             format!("{:?}", from_address),
             format!("{:?}", from_address),
             format!("{:?}", to_address),
             ids,
             values,
-            log.block_number.unwrap().as_u64(),
-            format!("{:?}", log.transaction_hash.unwrap()),
+            log_block_number(log)?,
+            log_transaction_hash(log)?,
+            log_transaction_index(log)?,
+            log_log_index(log)?,
         )
-        .map_err(|e| EventFetcherError::Custom(e.into()))?)
+        .map_err(|e| EventFetcherError::Custom(e.into()))
     }
 
     fn erc1155_to_single_dbevent(
@@ -284,12 +680,12 @@ impl<'a> EventFetcher<'a> {
         contract: &Contract,
     ) -> Result<Event, EventFetcherError> {
         //println!("ERC1155 single event: {:?}", log);
-        let operator: H160 = log.topics[1].try_into().unwrap();
-        let from_address: H160 = log.topics[2].try_into().unwrap();
-        let to_address: H160 = log.topics[3].try_into().unwrap();
+        let operator = log_topic_address(log, 1)?;
+        let from_address = log_topic_address(log, 2)?;
+        let to_address = log_topic_address(log, 3)?;
 
-        let (id, value) = decode_erc1155_transfer_single(&log)
-            .map_err(|e| EventFetcherError::Custom(e.into()))?;
+        let (id, value) =
+            decode_erc1155_transfer_single(log).map_err(|e| EventFetcherError::Custom(e.into()))?;
 
         let ids: Vec<U256> = vec![id];
         let values: Vec<U256> = vec![value];
@@ -297,17 +693,19 @@ impl<'a> EventFetcher<'a> {
         // format!("{:?}", operator) will make the type printable but it will be lowercase
         // to get the checksum address, we need to parse it and then print it
 
-        Ok(Event::new(
+        Event::new(
             contract.clone(),
             format!("{:?}", operator),
             format!("{:?}", from_address),
             format!("{:?}", to_address),
             ids,
             values,
-            log.block_number.unwrap().as_u64(),
-            format!("{:?}", log.transaction_hash.unwrap()),
+            log_block_number(log)?,
+            log_transaction_hash(log)?,
+            log_transaction_index(log)?,
+            log_log_index(log)?,
         )
-        .map_err(|e| EventFetcherError::Custom(e.into()))?)
+        .map_err(|e| EventFetcherError::Custom(e.into()))
     }
 
     fn erc1155_to_batch_dbevent(
@@ -316,27 +714,202 @@ impl<'a> EventFetcher<'a> {
         contract: &Contract,
     ) -> Result<Event, EventFetcherError> {
         //println!("ERC1155 batch event: {:?}", log);
-        let operator: H160 = log.topics[1].try_into().unwrap();
-        let from_address: H160 = log.topics[2].try_into().unwrap();
-        let to_address: H160 = log.topics[3].try_into().unwrap();
+        let operator = log_topic_address(log, 1)?;
+        let from_address = log_topic_address(log, 2)?;
+        let to_address = log_topic_address(log, 3)?;
 
         // Assuming the rest of the data field is ids concatenated with values
         //println!("Data: {:?}", log.data.0);
 
         let (ids, values) =
-            decode_erc1155_transfer_batch(&log).map_err(|e| EventFetcherError::Custom(e.into()))?;
+            decode_erc1155_transfer_batch(log).map_err(|e| EventFetcherError::Custom(e.into()))?;
 
-        Ok(Event::new(
+        Event::new(
             contract.clone(),
             format!("{:?}", operator),
             format!("{:?}", from_address),
             format!("{:?}", to_address),
             ids,
             values,
-            log.block_number.unwrap().as_u64(),
-            format!("{:?}", log.transaction_hash.unwrap()),
+            log_block_number(log)?,
+            log_transaction_hash(log)?,
+            log_transaction_index(log)?,
+            log_log_index(log)?,
         )
-        .map_err(|e| EventFetcherError::Custom(e.into()))?)
+        .map_err(|e| EventFetcherError::Custom(e.into()))
+    }
+
+    fn erc1155_uri_to_update(
+        &self,
+        log: &Log,
+        contract: &Contract,
+    ) -> Result<MetadataUpdate, EventFetcherError> {
+        let (token_id, uri) =
+            decode_erc1155_uri(log).map_err(|e| EventFetcherError::Custom(e.into()))?;
+
+        Ok(MetadataUpdate {
+            contract: contract.clone(),
+            token_id,
+            uri,
+            block_number: log_block_number(log)?,
+            transaction_hash: log_transaction_hash(log)?,
+        })
+    }
+
+    fn approval_for_all_to_approval(
+        &self,
+        log: &Log,
+        contract: &Contract,
+    ) -> Result<Approval, EventFetcherError> {
+        let (owner, operator, approved) =
+            decode_approval_for_all(log).map_err(|e| EventFetcherError::Custom(e.into()))?;
+
+        Ok(Approval {
+            contract: contract.clone(),
+            owner: format!("{:?}", owner),
+            operator: format!("{:?}", operator),
+            approved,
+            block_number: log_block_number(log)?,
+            transaction_hash: log_transaction_hash(log)?,
+            log_index: log_log_index(log)?,
+            block_timestamp: None,
+        })
+    }
+
+    // Helper function to retry fetching a block's timestamp with exponential backoff
+    async fn retry_fetch_block_timestamp(
+        &self,
+        block_number: u64,
+    ) -> Result<u64, EventFetcherError> {
+        let mut attempts = 0;
+        let mut delay = INITIAL_RETRY_DELAY;
+
+        loop {
+            let (endpoint, web3, permit) = self.rpc.pick().await;
+            let result = web3
+                .eth()
+                .block(BlockId::Number(BlockNumber::Number(block_number.into())))
+                .await;
+            drop(permit);
+            match result {
+                Ok(Some(block)) => {
+                    self.rpc.report_success(endpoint);
+                    return Ok(block.timestamp.as_u64());
+                }
+                Ok(None) => {
+                    return Err(EventFetcherError::Custom(
+                        format!("Block {} not found", block_number).into(),
+                    ))
+                }
+                Err(e) => {
+                    self.rpc.report_failure(endpoint, &e);
+                    if attempts >= MAX_RETRY_COUNT {
+                        return Err(e.into());
+                    }
+                    sleep(delay).await;
+                    delay *= 2;
+                    attempts += 1;
+                }
+            }
+        }
+    }
+
+    // The `finalized` block of chains with protocol level finality, with exponential backoff
+    async fn retry_fetch_finalized_block(&self) -> Result<usize, EventFetcherError> {
+        let mut attempts = 0;
+        let mut delay = INITIAL_RETRY_DELAY;
+
+        loop {
+            let (endpoint, web3, permit) = self.rpc.pick().await;
+            let result = web3
+                .transport()
+                .execute(
+                    "eth_getBlockByNumber",
+                    vec![serde_json::json!("finalized"), serde_json::json!(false)],
+                )
+                .await;
+            drop(permit);
+            match result {
+                Ok(block) => {
+                    self.rpc.report_success(endpoint);
+                    return block["number"]
+                        .as_str()
+                        .and_then(|number| {
+                            usize::from_str_radix(number.trim_start_matches("0x"), 16).ok()
+                        })
+                        .ok_or_else(|| {
+                            EventFetcherError::Custom(
+                                format!("No finalized block on {}", self.chain.name).into(),
+                            )
+                        });
+                }
+                Err(e) => {
+                    self.rpc.report_failure(endpoint, &e);
+                    if attempts >= MAX_RETRY_COUNT {
+                        return Err(e.into());
+                    }
+                    eprintln!(
+                        "Error fetching finalized block: {}. Retrying in {:?}... (Attempt {} of {})",
+                        e,
+                        delay,
+                        attempts + 1,
+                        MAX_RETRY_COUNT
+                    );
+                    sleep(delay).await;
+                    delay *= 2;
+                    attempts += 1;
+                }
+            }
+        }
+    }
+
+    /// Refuses to index when an endpoint serves another network than the configured
+    /// `Chain.id`, its logs would be written as this chain's. Endpoints that don't answer
+    /// are left out of the run's pool as long as one does, matching endpoints are only
+    /// checked once per process.
+    async fn verify_chain_id(&self) -> Result<(), EventFetcherError> {
+        let expected = self.chain.id as u64;
+        let mut verified_any = false;
+        let mut last_error = None;
+        for index in 0..self.rpc.len() {
+            let url = self.rpc.url(index).to_string();
+            if VERIFIED_ENDPOINTS
+                .lock()
+                .unwrap()
+                .contains(&(url.clone(), expected))
+            {
+                verified_any = true;
+                continue;
+            }
+            match self.rpc.chain_id(index).await {
+                Ok(chain_id) if chain_id == expected => {
+                    VERIFIED_ENDPOINTS.lock().unwrap().insert((url, expected));
+                    verified_any = true;
+                }
+                Ok(chain_id) => {
+                    return Err(EventFetcherError::Custom(
+                        format!(
+                            "RPC endpoint {} of {} serves chain id {}, expected {}",
+                            index, self.chain.name, chain_id, expected
+                        )
+                        .into(),
+                    ))
+                }
+                Err(e) => {
+                    eprintln!(
+                        "[{}] Leaving out RPC endpoint {} this run, its chain id is unknown: {}",
+                        self.chain.name, index, e
+                    );
+                    self.rpc.exclude(index);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match (verified_any, last_error) {
+            (false, Some(e)) => Err(e.into()),
+            _ => Ok(()),
+        }
     }
 
     // Helper function to retry fetching the current block with exponential backoff
@@ -345,9 +918,18 @@ impl<'a> EventFetcher<'a> {
         let mut delay = INITIAL_RETRY_DELAY;
 
         loop {
-            match self.web3.eth().block_number().await {
-                Ok(block_number) => return Ok(usize::try_from(block_number).unwrap() - 2), // subtract 2 to account for block propagation delay
+            let (endpoint, web3, permit) = self.rpc.pick().await;
+            let result = web3.eth().block_number().await;
+            drop(permit);
+            match result {
+                Ok(block_number) => {
+                    self.rpc.report_success(endpoint);
+                    // Blocks past the safe block are fetched again next run, so logs missing
+                    // from a provider that lags behind the tip are picked up then
+                    return Ok(block_number.as_usize());
+                }
                 Err(e) => {
+                    self.rpc.report_failure(endpoint, &e);
                     if attempts >= MAX_RETRY_COUNT {
                         return Err(e.into());
                     }
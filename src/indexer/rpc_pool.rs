@@ -0,0 +1,284 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use web3::error::{Error as Web3Error, TransportError};
+use web3::transports::Http;
+use web3::Web3;
+
+// Cooldown after the first failure, doubled for each consecutive failure
+const BASE_COOLDOWN: Duration = Duration::from_secs(5);
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+// Providers that rate limit us are left alone for longer
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(60);
+
+// url -> failures since the last take_failures and the latest error, across the pools of
+// every run so the indexer can record them once per run
+static FAILURES: Lazy<Mutex<HashMap<String, (u64, String)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// url -> the endpoint's rate limiter, shared by every pool of the process so the limit
+// holds however many pools use the endpoint
+static RATE_LIMITERS: Lazy<Mutex<HashMap<String, Arc<RateLimiter>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Drains the failure counts of the given endpoints: (url, failures, latest error)
+pub fn take_failures(urls: &[String]) -> Vec<(String, u64, String)> {
+    let mut failures = FAILURES.lock().unwrap();
+    urls.iter()
+        .filter_map(|url| {
+            failures
+                .remove(url)
+                .map(|(count, error)| (url.clone(), count, error))
+        })
+        .collect()
+}
+
+struct RpcEndpoint {
+    url: String,
+    web3: Web3<Http>,
+    consecutive_failures: AtomicUsize,
+    cooldown_until: Mutex<Option<Instant>>,
+    // Never picked, see RpcPool::exclude
+    excluded: AtomicBool,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl RpcEndpoint {
+    fn is_healthy(&self, now: Instant) -> bool {
+        match *self.cooldown_until.lock().unwrap() {
+            Some(until) => until <= now,
+            None => true,
+        }
+    }
+
+    fn cooldown_end(&self) -> Instant {
+        self.cooldown_until
+            .lock()
+            .unwrap()
+            .unwrap_or_else(Instant::now)
+    }
+}
+
+// Spaces requests evenly so that at most `per_second` start each second
+struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / per_second),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    // The process wide limiter of `url`, replaced if it was made for another rate
+    fn for_endpoint(url: &str, per_second: f64) -> Arc<Self> {
+        let interval = Duration::from_secs_f64(1.0 / per_second);
+        let mut limiters = RATE_LIMITERS.lock().unwrap();
+        match limiters.get(url) {
+            Some(limiter) if limiter.interval == interval => limiter.clone(),
+            _ => {
+                let limiter = Arc::new(Self::new(per_second));
+                limiters.insert(url.to_string(), limiter.clone());
+                limiter
+            }
+        }
+    }
+
+    async fn wait(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = std::cmp::max(*next_slot, Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+/// A set of RPC providers for one chain. Requests are spread round-robin over the
+/// healthy endpoints; an endpoint that errors is put on a cooldown that grows with
+/// each consecutive failure and resets on the first success.
+///
+/// Requests across all endpoints are capped at `max_concurrent_requests` in flight and,
+/// when set, each endpoint gets at most `requests_per_second` started per second across
+/// every pool of the process.
+pub struct RpcPool {
+    endpoints: Vec<RpcEndpoint>,
+    next: AtomicUsize,
+    permits: Semaphore,
+}
+
+impl RpcPool {
+    pub fn new(
+        urls: &[String],
+        max_concurrent_requests: usize,
+        requests_per_second: Option<f64>,
+    ) -> Result<Self, Web3Error> {
+        if urls.is_empty() {
+            return Err(Web3Error::Transport(TransportError::Message(
+                "No RPC endpoints configured".to_string(),
+            )));
+        }
+
+        // Rejected with the config, see IndexerConfig::validate
+        let requests_per_second = requests_per_second.filter(|&per_second| per_second > 0.0);
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for url in urls {
+            endpoints.push(RpcEndpoint {
+                url: url.clone(),
+                web3: Web3::new(Http::new(url)?),
+                consecutive_failures: AtomicUsize::new(0),
+                cooldown_until: Mutex::new(None),
+                excluded: AtomicBool::new(false),
+                rate_limiter: requests_per_second
+                    .map(|per_second| RateLimiter::for_endpoint(url, per_second)),
+            });
+        }
+
+        Ok(Self {
+            endpoints,
+            next: AtomicUsize::new(0),
+            permits: Semaphore::new(max_concurrent_requests.max(1)),
+        })
+    }
+
+    /// Waits for a request slot, then returns the index and client of the endpoint to use
+    /// for the next request along with the permit to hold until the request completes.
+    /// If every endpoint is cooling down, the one that recovers first is used. Excluded
+    /// endpoints are only used when every endpoint is.
+    pub async fn pick(&self) -> (usize, Web3<Http>, SemaphorePermit<'_>) {
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("RPC semaphore is never closed");
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = self.endpoints.len();
+
+        let candidates: Vec<usize> = (0..count)
+            .map(|offset| (start + offset) % count)
+            .filter(|&i| !self.endpoints[i].excluded.load(Ordering::Relaxed))
+            .collect();
+        let index = candidates
+            .iter()
+            .copied()
+            .find(|&i| self.endpoints[i].is_healthy(now))
+            .unwrap_or_else(|| {
+                candidates
+                    .iter()
+                    .copied()
+                    .min_by_key(|&i| self.endpoints[i].cooldown_end())
+                    .unwrap_or(start % count)
+            });
+
+        if let Some(rate_limiter) = &self.endpoints[index].rate_limiter {
+            rate_limiter.wait().await;
+        }
+        (index, self.endpoints[index].web3.clone(), permit)
+    }
+
+    pub fn url(&self, index: usize) -> &str {
+        &self.endpoints[index].url
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    /// Stops picking an endpoint, for the lifetime of the pool
+    pub fn exclude(&self, index: usize) {
+        self.endpoints[index]
+            .excluded
+            .store(true, Ordering::Relaxed);
+    }
+
+    /// eth_chainId of one endpoint, outside the request slots and rate limit
+    pub async fn chain_id(&self, index: usize) -> Result<u64, Web3Error> {
+        let result = self.endpoints[index].web3.eth().chain_id().await;
+        match &result {
+            Ok(_) => self.report_success(index),
+            Err(e) => self.report_failure(index, e),
+        }
+        let chain_id = result?;
+        // as_u64 panics on ids past u64, which a broken endpoint could answer
+        u64::try_from(chain_id).map_err(|_| {
+            Web3Error::InvalidResponse(format!("Chain id {} doesn't fit in a u64", chain_id))
+        })
+    }
+
+    pub fn report_success(&self, index: usize) {
+        let endpoint = &self.endpoints[index];
+        if endpoint.consecutive_failures.swap(0, Ordering::Relaxed) > 0 {
+            *endpoint.cooldown_until.lock().unwrap() = None;
+        }
+    }
+
+    pub fn report_failure(&self, index: usize, err: &Web3Error) {
+        let endpoint = &self.endpoints[index];
+        let failures = endpoint
+            .consecutive_failures
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+
+        let cooldown = if is_rate_limited(err) {
+            RATE_LIMIT_COOLDOWN
+        } else {
+            std::cmp::min(
+                BASE_COOLDOWN * 2u32.saturating_pow(failures.min(16) as u32 - 1),
+                MAX_COOLDOWN,
+            )
+        };
+        *endpoint.cooldown_until.lock().unwrap() = Some(Instant::now() + cooldown);
+        {
+            let mut all_failures = FAILURES.lock().unwrap();
+            let entry = all_failures
+                .entry(endpoint.url.clone())
+                .or_insert((0, String::new()));
+            entry.0 += 1;
+            entry.1 = redact_url(&err.to_string(), &endpoint.url);
+        }
+
+        eprintln!(
+            "RPC endpoint {} failed ({} in a row), cooling down for {:?}: {}",
+            endpoint.url, failures, cooldown, err
+        );
+    }
+}
+
+/// Replaces `url` in an error message with its host, transport errors quote the URL and
+/// providers put API keys in its path or query
+pub fn redact_url(message: &str, url: &str) -> String {
+    let parsed = match reqwest::Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return message.replace(url, "<rpc>"),
+    };
+    let host = parsed.host_str().unwrap_or("<rpc>");
+    // Quoted as given or normalized, e.g. with a trailing slash
+    message.replace(parsed.as_str(), host).replace(url, host)
+}
+
+fn is_rate_limited(err: &Web3Error) -> bool {
+    match err {
+        Web3Error::Transport(TransportError::Code(429)) => true,
+        Web3Error::Transport(TransportError::Message(message)) => {
+            let message = message.to_lowercase();
+            message.contains("429") || message.contains("too many requests")
+        }
+        Web3Error::Rpc(rpc_error) => {
+            let message = rpc_error.message.to_lowercase();
+            message.contains("rate limit") || message.contains("too many requests")
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,354 @@
+use crate::common::file_loader::try_load_users_data;
+use crate::indexer::indexer_config::Chain;
+use crate::indexer::queries::Event;
+use eth_checksum::checksum;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio_postgres::Client;
+
+pub const SIGNATURE_HEADER: &str = "X-Afterlife-Signature";
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+// Doubled after every failed attempt: 2s, 4s, 8s, 16s
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    pub secret: String,
+    // None for global hooks that receive every transfer
+    pub username: Option<String>,
+    // Unix timestamp, transfers from before the hook existed are never sent
+    pub created_at: i64,
+}
+
+pub(crate) async fn get_active_webhooks(
+    client: &Client,
+) -> Result<Vec<Webhook>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT id, url, secret, username, EXTRACT(EPOCH FROM created_at)::bigint AS created_at \
+            FROM webhooks WHERE active",
+            &[],
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Webhook {
+            id: row.get("id"),
+            url: row.get("url"),
+            secret: row.get("secret"),
+            username: row.get("username"),
+            created_at: row.get("created_at"),
+        })
+        .collect())
+}
+
+// Queues the delivery for deliveries::run, nothing if this transfer was already queued for
+// the hook. The indexer re-reads a look-back window every run, so most events are seen
+// several times.
+async fn queue_delivery(
+    client: &Client,
+    webhook_id: i32,
+    event: &Event,
+    payload: &str,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "INSERT INTO webhook_deliveries \
+            (webhook_id, transaction_hash, log_index, payload, next_attempt_at) \
+            VALUES ($1, $2, $3, $4, now()) \
+            ON CONFLICT (webhook_id, transaction_hash, log_index) DO NOTHING",
+            &[
+                &webhook_id,
+                &event.transaction_hash,
+                &(event.log_index as i32),
+                &payload,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+pub(crate) fn transfer_payload(
+    chain: &Chain,
+    event: &Event,
+    usernames: &HashMap<String, String>,
+    username: Option<&str>,
+) -> Value {
+    let tokens: Vec<Value> = event
+        .ids
+        .iter()
+        .zip(event.values.iter())
+        .map(|(id, value)| json!({ "token_id": id.to_string(), "value": value.to_string() }))
+        .collect();
+
+    json!({
+        "type": "transfer",
+        "username": username,
+        "chain": chain.name,
+        "contract_address": checksum(&event.contract.address),
+        "contract_name": event.contract.name,
+        "from": checksum(&event.from_address),
+        "to": checksum(&event.to_address),
+        "from_username": usernames.get(&event.from_address.to_lowercase()),
+        "to_username": usernames.get(&event.to_address.to_lowercase()),
+        "tokens": tokens,
+        "block_number": event.block_number,
+        "transaction_hash": event.transaction_hash,
+        "log_index": event.log_index,
+        "timestamp": event.block_timestamp,
+    })
+}
+
+/// Hex HMAC-SHA256 of the body, sent as `X-Afterlife-Signature: sha256=<hex>`
+pub fn sign_payload(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// Whether the address is reachable from the internet, rather than loopback, private,
+// link-local (cloud metadata services live at 169.254.169.254) or otherwise reserved
+fn is_public_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [first, second, ..] = address.octets();
+            !(address.is_private()
+                || address.is_loopback()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_multicast()
+                || address.is_documentation()
+                || first == 0
+                // Shared address space, also home to some metadata services
+                || (first == 100 && (second & 0xc0) == 64)
+                // Benchmarking and reserved
+                || (first == 198 && (second & 0xfe) == 18)
+                || first >= 240)
+        }
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(mapped) => is_public_address(IpAddr::V4(mapped)),
+            None => {
+                let first = address.segments()[0];
+                !(address.is_loopback()
+                    || address.is_unspecified()
+                    || address.is_multicast()
+                    // Unique local, fd00:ec2::254 among them
+                    || (first & 0xfe00) == 0xfc00
+                    // Link local
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Checks a webhook URL given by a user: https only, and every address its host resolves
+/// to must be public. Returns the address to send to, run it again before each delivery
+/// since DNS can change in between.
+pub async fn resolve_public_url(url: &str) -> Result<SocketAddr, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid url {}: {}", url, e))?;
+    if parsed.scheme() != "https" {
+        return Err("Webhook url must be https".to_string());
+    }
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("Webhook url {} has no host", url))?;
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    // IPv6 literals come bracketed
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .collect();
+    if addresses.is_empty() {
+        return Err(format!("{} doesn't resolve to any address", host));
+    }
+    if let Some(address) = addresses.iter().find(|a| !is_public_address(a.ip())) {
+        return Err(format!(
+            "{} resolves to the non-public address {}",
+            host,
+            address.ip()
+        ));
+    }
+    Ok(addresses[0])
+}
+
+/// A client for user given webhook URLs: connections go to the `address` that was checked
+/// rather than to a new lookup, and redirects aren't followed
+pub fn pinned_client(url: &str, address: SocketAddr) -> Result<reqwest::Client, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid url {}: {}", url, e))?;
+    let mut builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if let Some(host) = parsed.domain() {
+        builder = builder.resolve(host, address);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build webhook client: {}", e))
+}
+
+async fn deliver(http: reqwest::Client, webhook: Webhook, body: String) {
+    post_signed(
+        http,
+        format!("Webhook {}", webhook.id),
+        webhook.url,
+        webhook.secret,
+        body,
+    )
+    .await
+}
+
+/// Posts the signed body to the URL once, errors unless it's accepted
+pub(crate) async fn send_signed(
+    http: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    body: &str,
+) -> Result<(), String> {
+    let result = http
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header(SIGNATURE_HEADER, sign_payload(secret, body))
+        .timeout(DELIVERY_TIMEOUT)
+        .body(body.to_string())
+        .send()
+        .await;
+
+    match result {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("status {}", response.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Posts the signed body to the URL, retrying with backoff until it's accepted.
+/// `label` names the recipient in the logs.
+pub(crate) async fn post_signed(
+    http: reqwest::Client,
+    label: String,
+    url: String,
+    secret: String,
+    body: String,
+) {
+    let mut delay = INITIAL_RETRY_DELAY;
+
+    for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+        let error = match send_signed(&http, &url, &secret, &body).await {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+
+        if attempt == MAX_DELIVERY_ATTEMPTS {
+            eprintln!(
+                "{} delivery to {} failed after {} attempts: {}",
+                label, url, attempt, error
+            );
+            return;
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// Posts a lag alert of the chain to the global webhooks, or its recovery once the chain
+/// is back under the threshold
+pub async fn notify_chain_lag(
+    client: &Client,
+    chain: &Chain,
+    head_block: u64,
+    last_processed_block: u64,
+    threshold: u64,
+    recovered: bool,
+) {
+    let webhooks = match get_active_webhooks(client).await {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            eprintln!("[{}] Failed to load webhooks: {}", chain.name, e);
+            return;
+        }
+    };
+    let payload = json!({
+        "type": if recovered { "chain_lag_recovered" } else { "chain_lag" },
+        "chain": chain.name,
+        "head_block": head_block,
+        "last_processed_block": last_processed_block,
+        "blocks_behind": head_block.saturating_sub(last_processed_block),
+        "threshold": threshold,
+    });
+
+    let http = reqwest::Client::new();
+    for webhook in webhooks.into_iter().filter(|w| w.username.is_none()) {
+        tokio::spawn(deliver(http.clone(), webhook, payload.to_string()));
+    }
+}
+
+/// lowercase address -> username, empty if the users file can't be read
+pub(crate) async fn load_usernames(chain: &Chain) -> HashMap<String, String> {
+    match try_load_users_data().await {
+        Ok(users) => users
+            .into_iter()
+            .flat_map(|(username, addresses)| {
+                addresses
+                    .into_iter()
+                    .map(move |address| (address.to_lowercase(), username.clone()))
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("[{}] Webhooks: {}", chain.name, e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Queues every new transfer of the chain for the matching webhooks.
+///
+/// Global hooks get all transfers, per-user hooks only those sent or received by one of the
+/// user's addresses. Deliveries are sent and retried by deliveries::run so indexing isn't
+/// held up.
+pub async fn notify_transfers(client: &Client, chain: &Chain, events: &[&Event]) {
+    if events.is_empty() {
+        return;
+    }
+    let webhooks = match get_active_webhooks(client).await {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            eprintln!("[{}] Failed to load webhooks: {}", chain.name, e);
+            return;
+        }
+    };
+    if webhooks.is_empty() {
+        return;
+    }
+
+    let usernames = load_usernames(chain).await;
+    for event in events {
+        let from_username = usernames.get(&event.from_address.to_lowercase());
+        let to_username = usernames.get(&event.to_address.to_lowercase());
+
+        for webhook in &webhooks {
+            let matches = match &webhook.username {
+                None => true,
+                Some(username) => from_username == Some(username) || to_username == Some(username),
+            };
+            let is_new = event
+                .block_timestamp
+                .is_some_and(|ts| ts as i64 >= webhook.created_at);
+            if !matches || !is_new {
+                continue;
+            }
+
+            let payload = transfer_payload(chain, event, &usernames, webhook.username.as_deref());
+            if let Err(e) = queue_delivery(client, webhook.id, event, &payload.to_string()).await {
+                eprintln!("Failed to queue webhook {} delivery: {}", webhook.id, e);
+            }
+        }
+    }
+}
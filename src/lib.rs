@@ -1,3 +1,11 @@
+// The API routes are one warp filter, whose type is too deep for the default limit
+#![recursion_limit = "256"]
+
 pub mod backend;
+pub mod commands;
 pub mod common;
+pub mod delegation;
 pub mod indexer;
+pub mod marketplace;
+pub mod metadata;
+pub mod prices;
@@ -0,0 +1,162 @@
+pub mod queries;
+
+use crate::marketplace::queries::{get_tracked_contracts, replace_listings, Listing};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::time::Duration;
+use tokio_postgres::Client;
+
+const DEFAULT_REFRESH_SECONDS: u64 = 300;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Pages of asks fetched per contract and refresh, bounds the work for huge collections
+const MAX_PAGES_PER_CONTRACT: usize = 20;
+const PAGE_SIZE: usize = 1000;
+
+/// Marketplace ingestion settings, read from the YAML file in AFTERLIFE_PATH_MARKETPLACE.
+/// Ingestion is disabled when the variable is unset.
+///
+/// ```yaml
+/// api_key: "..."
+/// refresh_seconds: 300
+/// chains:
+///   fantom: https://api-fantom.reservoir.tools
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct MarketplaceConfig {
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+    // chain name -> base URL of a Reservoir-compatible API for that chain
+    pub chains: HashMap<String, String>,
+}
+
+fn default_refresh_seconds() -> u64 {
+    DEFAULT_REFRESH_SECONDS
+}
+
+impl MarketplaceConfig {
+    pub fn from_env() -> Option<Result<Self, String>> {
+        let path = env::var("AFTERLIFE_PATH_MARKETPLACE").ok()?;
+        Some(
+            fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read marketplace config {}: {}", path, e))
+                .and_then(|content| {
+                    serde_yaml::from_str(&content)
+                        .map_err(|e| format!("Invalid marketplace config: {}", e))
+                }),
+        )
+    }
+
+    fn api_url(&self, chain_name: &str) -> Option<&String> {
+        self.chains
+            .iter()
+            .find(|(chain, _)| chain.eq_ignore_ascii_case(chain_name))
+            .map(|(_, url)| url)
+    }
+}
+
+// Active asks of one contract, following continuation tokens
+async fn fetch_listings(
+    http: &reqwest::Client,
+    config: &MarketplaceConfig,
+    api_url: &str,
+    contract_address: &str,
+) -> Result<Vec<Listing>, String> {
+    let mut listings = Vec::new();
+    let mut continuation: Option<String> = None;
+    let limit = PAGE_SIZE.to_string();
+
+    for _ in 0..MAX_PAGES_PER_CONTRACT {
+        let mut request = http
+            .get(format!("{}/orders/asks/v5", api_url.trim_end_matches('/')))
+            .query(&[
+                ("contracts", contract_address),
+                ("status", "active"),
+                ("limit", limit.as_str()),
+            ])
+            .timeout(REQUEST_TIMEOUT);
+        if let Some(continuation) = &continuation {
+            request = request.query(&[("continuation", continuation.as_str())]);
+        }
+        if let Some(api_key) = &config.api_key {
+            request = request.header("x-api-key", api_key);
+        }
+
+        let body = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to fetch listings: {}", e))?
+            .text()
+            .await
+            .map_err(|e| format!("Failed to read listings: {}", e))?;
+        let page: Value =
+            serde_json::from_str(&body).map_err(|e| format!("Invalid listings response: {}", e))?;
+
+        if let Some(orders) = page["orders"].as_array() {
+            listings.extend(orders.iter().filter_map(parse_order));
+        }
+        continuation = page["continuation"].as_str().map(str::to_string);
+        if continuation.is_none() {
+            break;
+        }
+    }
+
+    Ok(listings)
+}
+
+// Orders for a whole collection or token ranges have no single token id and are skipped
+fn parse_order(order: &Value) -> Option<Listing> {
+    Some(Listing {
+        order_id: order["id"].as_str()?.to_string(),
+        token_id: order["criteria"]["data"]["token"]["tokenId"]
+            .as_str()?
+            .parse()
+            .ok()?,
+        maker: order["maker"].as_str().unwrap_or_default().to_string(),
+        // Native currency amount, so listings made in different tokens stay comparable
+        price: order["price"]["amount"]["native"].as_f64()?,
+        currency: order["price"]["currency"]["symbol"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        source: order["source"]["domain"].as_str().map(str::to_string),
+        valid_until: order["validUntil"].as_i64(),
+    })
+}
+
+/// Replaces the stored listings of every indexed contract on a configured chain
+pub async fn refresh_listings(client: &Client, config: &MarketplaceConfig) {
+    let contracts = match get_tracked_contracts(client).await {
+        Ok(contracts) => contracts,
+        Err(e) => {
+            eprintln!("Marketplace: failed to load contracts: {}", e);
+            return;
+        }
+    };
+
+    let http = reqwest::Client::new();
+    for (contract_id, chain_name, contract_address) in contracts {
+        let api_url = match config.api_url(&chain_name) {
+            Some(api_url) => api_url,
+            None => continue,
+        };
+        let listings = match fetch_listings(&http, config, api_url, &contract_address).await {
+            Ok(listings) => listings,
+            Err(e) => {
+                eprintln!("Marketplace: [{}] {}: {}", chain_name, contract_address, e);
+                continue;
+            }
+        };
+        if let Err(e) = replace_listings(client, contract_id, &listings).await {
+            eprintln!(
+                "Marketplace: [{}] failed to store listings for {}: {}",
+                chain_name, contract_address, e
+            );
+        }
+    }
+}
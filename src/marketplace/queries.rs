@@ -0,0 +1,79 @@
+use crate::common::numeric::TokenId;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio_postgres::{Client, Error};
+
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub order_id: String,
+    pub token_id: TokenId,
+    pub maker: String,
+    // In the chain's native currency
+    pub price: f64,
+    // Symbol of the currency the order was made in
+    pub currency: String,
+    pub source: Option<String>,
+    // Unix timestamp
+    pub valid_until: Option<i64>,
+}
+
+// (contract id, chain name, contract address) of every indexed contract
+pub async fn get_tracked_contracts(client: &Client) -> Result<Vec<(i32, String, String)>, Error> {
+    let rows = client
+        .query(
+            "SELECT c.id, ch.name, c.address FROM contracts c JOIN chains ch ON c.chain_id = ch.id",
+            &[],
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect())
+}
+
+/// Swaps the contract's listings for a fresh snapshot in one transaction
+pub async fn replace_listings(
+    client: &Client,
+    contract_id: i32,
+    listings: &[Listing],
+) -> Result<(), Error> {
+    client.batch_execute("BEGIN").await?;
+    let result = async {
+        client
+            .execute("DELETE FROM listings WHERE contract_id = $1", &[&contract_id])
+            .await?;
+        for listing in listings {
+            let valid_until = listing
+                .valid_until
+                .filter(|ts| *ts > 0)
+                .map(|ts| UNIX_EPOCH + Duration::from_secs(ts as u64));
+            client
+                .execute(
+                    "INSERT INTO listings (contract_id, token_id, order_id, maker, price, currency, source, valid_until, updated_at) \
+                    VALUES ($1, $2::text::numeric, $3, $4, $5, $6, $7, $8, now()) \
+                    ON CONFLICT (contract_id, order_id) DO NOTHING",
+                    &[
+                        &contract_id,
+                        &listing.token_id.to_string(),
+                        &listing.order_id,
+                        &listing.maker,
+                        &listing.price,
+                        &listing.currency,
+                        &listing.source,
+                        &valid_until,
+                    ],
+                )
+                .await?;
+        }
+        Ok::<_, Error>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => client.batch_execute("COMMIT").await,
+        Err(e) => {
+            client.batch_execute("ROLLBACK").await?;
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use once_cell::sync::Lazy;
+use std::env;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DEFAULT_IPFS_GATEWAYS: &str =
+    "https://ipfs.io/ipfs/,https://cloudflare-ipfs.com/ipfs/,https://dweb.link/ipfs/";
+const DEFAULT_ARWEAVE_GATEWAYS: &str = "https://arweave.net/";
+const DEFAULT_GATEWAY_RPS: u32 = 5;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Cooldown after the first failure, doubled for each consecutive failure
+const BASE_COOLDOWN: Duration = Duration::from_secs(5);
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(60);
+
+pub static CONTENT_FETCHER: Lazy<ContentFetcher> = Lazy::new(ContentFetcher::from_env);
+
+/// Where a piece of token content lives, independent of any particular gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentUri {
+    // CID optionally followed by a path, e.g. "Qm.../1.json"
+    Ipfs(String),
+    // Arweave transaction id optionally followed by a path
+    Arweave(String),
+    Http(String),
+}
+
+impl ContentUri {
+    /// Normalizes ipfs://, ar://, /ipfs/ paths, gateway URLs and raw CIDs.
+    /// Returns None for URIs that can't be fetched (data: URIs are decoded elsewhere).
+    pub fn parse(uri: &str) -> Option<Self> {
+        let uri = uri.trim();
+        if let Some(rest) = uri.strip_prefix("ipfs://") {
+            let rest = rest.trim_start_matches("ipfs/");
+            return (!rest.is_empty()).then(|| ContentUri::Ipfs(rest.to_string()));
+        }
+        if let Some(rest) = uri.strip_prefix("ar://") {
+            return (!rest.is_empty()).then(|| ContentUri::Arweave(rest.to_string()));
+        }
+        if let Some(rest) = uri.strip_prefix("/ipfs/") {
+            return (!rest.is_empty()).then(|| ContentUri::Ipfs(rest.to_string()));
+        }
+        if uri.starts_with("http://") || uri.starts_with("https://") {
+            // Content pinned behind some public gateway is fetched through ours instead
+            if let Some((_, rest)) = uri.split_once("/ipfs/") {
+                if !rest.is_empty() {
+                    return Some(ContentUri::Ipfs(rest.to_string()));
+                }
+            }
+            return Some(ContentUri::Http(uri.to_string()));
+        }
+        let cid = uri.split('/').next().unwrap_or("");
+        if is_cid(cid) {
+            return Some(ContentUri::Ipfs(uri.to_string()));
+        }
+        None
+    }
+}
+
+// CIDv0 (base58 "Qm...") or CIDv1 in base32 ("b...")
+fn is_cid(candidate: &str) -> bool {
+    let is_v0 = candidate.len() == 46
+        && candidate.starts_with("Qm")
+        && candidate.chars().all(|c| c.is_ascii_alphanumeric());
+    let is_v1 = candidate.len() > 50
+        && candidate.starts_with('b')
+        && candidate
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c));
+    is_v0 || is_v1
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    UnsupportedUri(String),
+    TooLarge(usize),
+    // Every gateway was tried, holds the last error
+    Failed(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::UnsupportedUri(uri) => write!(f, "Unsupported content URI {}", uri),
+            FetchError::TooLarge(limit) => write!(f, "Content is larger than {} bytes", limit),
+            FetchError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+struct Gateway {
+    base_url: String,
+    min_interval: Duration,
+    // Earliest time the next request may be sent
+    next_slot: Mutex<Instant>,
+    consecutive_failures: AtomicUsize,
+    cooldown_until: Mutex<Option<Instant>>,
+}
+
+impl Gateway {
+    fn new(base_url: &str, requests_per_second: u32) -> Self {
+        let mut base_url = base_url.trim().to_string();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        Self {
+            base_url,
+            min_interval: Duration::from_secs(1) / requests_per_second.max(1),
+            next_slot: Mutex::new(Instant::now()),
+            consecutive_failures: AtomicUsize::new(0),
+            cooldown_until: Mutex::new(None),
+        }
+    }
+
+    fn is_healthy(&self, now: Instant) -> bool {
+        match *self.cooldown_until.lock().unwrap() {
+            Some(until) => until <= now,
+            None => true,
+        }
+    }
+
+    // Waits for this gateway's next free request slot
+    async fn throttle(&self) {
+        let wait = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = std::cmp::max(*next_slot, now);
+            *next_slot = slot + self.min_interval;
+            slot - now
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn report_success(&self) {
+        if self.consecutive_failures.swap(0, Ordering::Relaxed) > 0 {
+            *self.cooldown_until.lock().unwrap() = None;
+        }
+    }
+
+    fn report_failure(&self, rate_limited: bool) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let cooldown = if rate_limited {
+            RATE_LIMIT_COOLDOWN
+        } else {
+            std::cmp::min(
+                BASE_COOLDOWN * 2u32.saturating_pow(failures.min(16) as u32 - 1),
+                MAX_COOLDOWN,
+            )
+        };
+        *self.cooldown_until.lock().unwrap() = Some(Instant::now() + cooldown);
+    }
+}
+
+/// Fetches IPFS, Arweave and plain HTTP content.
+///
+/// Gateways are tried in configured order, skipping those cooling down after failures,
+/// and each is throttled to its own request rate. When AFTERLIFE_PATH_IPFS_PIN is set,
+/// IPFS content is kept on disk and served from there on later fetches.
+pub struct ContentFetcher {
+    http: reqwest::Client,
+    ipfs_gateways: Vec<Gateway>,
+    arweave_gateways: Vec<Gateway>,
+    pin_root: Option<PathBuf>,
+}
+
+/// Where the IPFS `path` is pinned under `root`, None for paths that would land outside
+/// of it: absolute ones (`ipfs:///etc/passwd`) and any with a `..` segment
+pub fn pin_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path);
+    let contained = relative.components().next().is_some()
+        && relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    contained.then(|| root.join(relative))
+}
+
+fn gateways_from_env(var: &str, default: &str, requests_per_second: u32) -> Vec<Gateway> {
+    env::var(var)
+        .unwrap_or_else(|_| default.to_owned())
+        .split(',')
+        .filter(|url| !url.trim().is_empty())
+        .map(|url| Gateway::new(url, requests_per_second))
+        .collect()
+}
+
+impl ContentFetcher {
+    pub fn from_env() -> Self {
+        let requests_per_second = env::var("AFTERLIFE_IPFS_GATEWAY_RPS")
+            .ok()
+            .and_then(|rps| rps.parse().ok())
+            .unwrap_or(DEFAULT_GATEWAY_RPS);
+
+        Self {
+            http: reqwest::Client::new(),
+            ipfs_gateways: gateways_from_env(
+                "AFTERLIFE_IPFS_GATEWAYS",
+                DEFAULT_IPFS_GATEWAYS,
+                requests_per_second,
+            ),
+            arweave_gateways: gateways_from_env(
+                "AFTERLIFE_ARWEAVE_GATEWAYS",
+                DEFAULT_ARWEAVE_GATEWAYS,
+                requests_per_second,
+            ),
+            pin_root: env::var("AFTERLIFE_PATH_IPFS_PIN").ok().map(PathBuf::from),
+        }
+    }
+
+    /// Fetches the content behind `uri`, at most `max_bytes` long
+    pub async fn fetch(&self, uri: &str, max_bytes: usize) -> Result<Vec<u8>, FetchError> {
+        match ContentUri::parse(uri) {
+            Some(ContentUri::Ipfs(path)) => {
+                if let Some(pinned) = self.read_pinned(&path).await {
+                    return Ok(pinned);
+                }
+                let content = self
+                    .fetch_from_gateways(&self.ipfs_gateways, &path, max_bytes)
+                    .await?;
+                self.pin(&path, &content).await;
+                Ok(content)
+            }
+            Some(ContentUri::Arweave(path)) => {
+                self.fetch_from_gateways(&self.arweave_gateways, &path, max_bytes)
+                    .await
+            }
+            Some(ContentUri::Http(url)) => self
+                .fetch_url(&url, max_bytes)
+                .await
+                .map_err(|(message, _)| FetchError::Failed(message)),
+            None => Err(FetchError::UnsupportedUri(uri.to_string())),
+        }
+    }
+
+    async fn fetch_from_gateways(
+        &self,
+        gateways: &[Gateway],
+        path: &str,
+        max_bytes: usize,
+    ) -> Result<Vec<u8>, FetchError> {
+        let now = Instant::now();
+        // Healthy gateways first, in configured order, then the ones cooling down as a last resort
+        let ordered = gateways
+            .iter()
+            .filter(|gateway| gateway.is_healthy(now))
+            .chain(gateways.iter().filter(|gateway| !gateway.is_healthy(now)));
+
+        let mut last_error = format!("No gateway configured for {}", path);
+        for gateway in ordered {
+            gateway.throttle().await;
+            let url = format!("{}{}", gateway.base_url, path);
+            match self.fetch_url(&url, max_bytes).await {
+                Ok(content) => {
+                    gateway.report_success();
+                    return Ok(content);
+                }
+                Err((message, rate_limited)) => {
+                    gateway.report_failure(rate_limited);
+                    last_error = message;
+                }
+            }
+        }
+        Err(FetchError::Failed(last_error))
+    }
+
+    // The error carries whether the server rate limited us
+    async fn fetch_url(&self, url: &str, max_bytes: usize) -> Result<Vec<u8>, (String, bool)> {
+        let response = self
+            .http
+            .get(url)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| (format!("Failed to fetch {}: {}", url, e), false))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err((
+                format!("Failed to fetch {}: status {}", url, status),
+                status.as_u16() == 429,
+            ));
+        }
+        if response
+            .content_length()
+            .is_some_and(|length| length as usize > max_bytes)
+        {
+            return Err((FetchError::TooLarge(max_bytes).to_string(), false));
+        }
+
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| (format!("Failed to read {}: {}", url, e), false))?;
+        if bytes.len() > max_bytes {
+            return Err((FetchError::TooLarge(max_bytes).to_string(), false));
+        }
+        Ok(bytes.to_vec())
+    }
+
+    fn pin_path(&self, path: &str) -> Option<PathBuf> {
+        pin_path(self.pin_root.as_ref()?, path)
+    }
+
+    async fn read_pinned(&self, path: &str) -> Option<Vec<u8>> {
+        tokio::fs::read(self.pin_path(path)?).await.ok()
+    }
+
+    async fn pin(&self, path: &str, content: &[u8]) {
+        let pin_path = match self.pin_path(path) {
+            Some(pin_path) => pin_path,
+            None => return,
+        };
+        if let Some(parent) = pin_path.parent() {
+            if let Err(e) = tokio::fs::create_dir_all(parent).await {
+                eprintln!("Failed to pin {}: {}", path, e);
+                return;
+            }
+        }
+        if let Err(e) = tokio::fs::write(&pin_path, content).await {
+            eprintln!("Failed to pin {}: {}", path, e);
+        }
+    }
+}
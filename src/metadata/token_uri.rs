@@ -0,0 +1,157 @@
+use crate::metadata::ipfs::{FetchError, CONTENT_FETCHER};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ethabi::{ParamType, Token};
+use serde_json::Value;
+use std::fmt;
+use web3::transports::Http;
+use web3::types::{Address, Bytes, CallRequest, U256};
+use web3::Web3;
+
+// Metadata documents are small, anything bigger is not metadata
+const MAX_METADATA_BYTES: usize = 1024 * 1024;
+
+// keccak256("tokenURI(uint256)")[..4], ERC-721
+const TOKEN_URI_SELECTOR: [u8; 4] = [0xc8, 0x7b, 0x56, 0xdd];
+// keccak256("uri(uint256)")[..4], ERC-1155
+const URI_SELECTOR: [u8; 4] = [0x0e, 0x89, 0x34, 0x1c];
+
+#[derive(Debug)]
+pub enum TokenUriError {
+    Call(web3::Error),
+    Decode(String),
+    Fetch(FetchError),
+    InvalidJson(serde_json::Error),
+}
+
+impl fmt::Display for TokenUriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenUriError::Call(e) => write!(f, "eth_call failed: {}", e),
+            TokenUriError::Decode(message) => write!(f, "{}", message),
+            TokenUriError::Fetch(e) => write!(f, "{}", e),
+            TokenUriError::InvalidJson(e) => write!(f, "Invalid metadata JSON: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TokenUriError {}
+
+fn is_erc1155(contract_type: &str) -> bool {
+    contract_type.eq_ignore_ascii_case("erc1155")
+}
+
+/// Calls `uri(id)` on ERC-1155 contracts and `tokenURI(id)` on everything else
+pub async fn read_token_uri(
+    web3: &Web3<Http>,
+    contract_address: Address,
+    contract_type: &str,
+    token_id: U256,
+) -> Result<String, TokenUriError> {
+    let selector = if is_erc1155(contract_type) {
+        URI_SELECTOR
+    } else {
+        TOKEN_URI_SELECTOR
+    };
+    let mut data = selector.to_vec();
+    data.extend(ethabi::encode(&[Token::Uint(token_id)]));
+
+    let output = web3
+        .eth()
+        .call(
+            CallRequest {
+                to: Some(contract_address),
+                data: Some(Bytes(data)),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .map_err(TokenUriError::Call)?;
+
+    match ethabi::decode(&[ParamType::String], &output.0)
+        .map_err(|e| TokenUriError::Decode(format!("Invalid token URI return data: {}", e)))?
+        .pop()
+    {
+        Some(Token::String(uri)) => Ok(uri),
+        _ => Err(TokenUriError::Decode(
+            "Token URI call did not return a string".to_string(),
+        )),
+    }
+}
+
+/// ERC-1155 clients replace `{id}` with the id as 64 lowercase hex digits, no 0x prefix
+pub fn substitute_id(uri: &str, token_id: U256) -> String {
+    if !uri.contains("{id}") {
+        return uri.to_string();
+    }
+    uri.replace("{id}", &format!("{:064x}", token_id))
+}
+
+/// Decodes `data:[<mediatype>][;base64],<data>` URIs, None if `uri` is not a data URI
+pub fn decode_data_uri(uri: &str) -> Option<Result<Vec<u8>, TokenUriError>> {
+    let rest = uri.strip_prefix("data:")?;
+    let (header, payload) = match rest.split_once(',') {
+        Some(parts) => parts,
+        None => {
+            return Some(Err(TokenUriError::Decode(
+                "Data URI without payload".to_string(),
+            )))
+        }
+    };
+
+    if header.ends_with(";base64") {
+        Some(
+            BASE64
+                .decode(payload.trim())
+                .map_err(|e| TokenUriError::Decode(format!("Invalid base64 data URI: {}", e))),
+        )
+    } else {
+        Some(Ok(percent_decode(payload)))
+    }
+}
+
+// Plain (non-base64) data URIs may percent-encode reserved characters
+fn percent_decode(payload: &str) -> Vec<u8> {
+    let bytes = payload.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+/// Reads a token's URI from the contract and resolves it to its metadata JSON,
+/// decoding inline data URIs and fetching everything else through the content fetcher.
+pub async fn fetch_token_metadata(
+    web3: &Web3<Http>,
+    contract_address: Address,
+    contract_type: &str,
+    token_id: U256,
+) -> Result<Value, TokenUriError> {
+    let uri = read_token_uri(web3, contract_address, contract_type, token_id).await?;
+    resolve_metadata(&uri, token_id).await
+}
+
+/// Resolves a token URI, as returned by the contract or a URI event, to metadata JSON
+pub async fn resolve_metadata(uri: &str, token_id: U256) -> Result<Value, TokenUriError> {
+    let uri = substitute_id(uri.trim(), token_id);
+    let contents = match decode_data_uri(&uri) {
+        Some(decoded) => decoded?,
+        None => CONTENT_FETCHER
+            .fetch(&uri, MAX_METADATA_BYTES)
+            .await
+            .map_err(TokenUriError::Fetch)?,
+    };
+    serde_json::from_slice(&contents).map_err(TokenUriError::InvalidJson)
+}
@@ -0,0 +1,122 @@
+pub mod queries;
+
+use crate::prices::queries::store_price;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::time::Duration;
+use tokio_postgres::Client;
+
+const DEFAULT_REFRESH_SECONDS: u64 = 300;
+const DEFAULT_API_URL: &str = "https://api.coingecko.com/api/v3";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Price oracle settings, read from the YAML file in AFTERLIFE_PATH_PRICES. Native currency
+/// prices aren't fetched when the variable is unset, USD values are null then.
+///
+/// ```yaml
+/// api_url: https://api.coingecko.com/api/v3
+/// api_key: "..."
+/// refresh_seconds: 300
+/// coins:
+///   ethereum: ethereum
+///   polygon: matic-network
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct PriceConfig {
+    // Base URL of a CoinGecko-compatible API
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+    // chain name -> id of its native currency on the API
+    pub coins: HashMap<String, String>,
+}
+
+fn default_api_url() -> String {
+    DEFAULT_API_URL.to_string()
+}
+
+fn default_refresh_seconds() -> u64 {
+    DEFAULT_REFRESH_SECONDS
+}
+
+impl PriceConfig {
+    pub fn from_env() -> Option<Result<Self, String>> {
+        let path = env::var("AFTERLIFE_PATH_PRICES").ok()?;
+        Some(
+            fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read price config {}: {}", path, e))
+                .and_then(|content| {
+                    serde_yaml::from_str(&content)
+                        .map_err(|e| format!("Invalid price config: {}", e))
+                }),
+        )
+    }
+}
+
+// USD price of every coin, in one request
+async fn fetch_prices(
+    http: &reqwest::Client,
+    config: &PriceConfig,
+) -> Result<HashMap<String, f64>, String> {
+    let mut ids: Vec<&str> = config.coins.values().map(String::as_str).collect();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let mut request = http
+        .get(format!(
+            "{}/simple/price",
+            config.api_url.trim_end_matches('/')
+        ))
+        .query(&[("ids", ids.join(",").as_str()), ("vs_currencies", "usd")])
+        .timeout(REQUEST_TIMEOUT);
+    if let Some(api_key) = &config.api_key {
+        request = request.header("x-cg-pro-api-key", api_key);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("invalid response: {}", e))?;
+
+    Ok(ids
+        .into_iter()
+        .filter_map(|id| Some((id.to_string(), body[id]["usd"].as_f64()?)))
+        .collect())
+}
+
+/// Stores the current USD price of every configured chain's native currency. A chain the
+/// API has no price for keeps its previous one.
+pub async fn refresh_prices(client: &Client, config: &PriceConfig) {
+    let http = reqwest::Client::new();
+    let prices = match fetch_prices(&http, config).await {
+        Ok(prices) => prices,
+        Err(e) => {
+            eprintln!("Prices: {}", e);
+            return;
+        }
+    };
+    for (chain_name, coin) in &config.coins {
+        let usd = match prices.get(coin) {
+            Some(usd) => *usd,
+            None => {
+                eprintln!("Prices: [{}] no USD price for {}", chain_name, coin);
+                continue;
+            }
+        };
+        if let Err(e) = store_price(client, chain_name, coin, usd).await {
+            eprintln!("Prices: [{}] failed to store price: {}", chain_name, e);
+        }
+    }
+}
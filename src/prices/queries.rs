@@ -0,0 +1,28 @@
+use tokio_postgres::{Client, Error};
+
+/// Stores the latest USD price of a chain's native currency, also as the price of the
+/// current UTC day
+pub async fn store_price(
+    client: &Client,
+    chain_name: &str,
+    coin: &str,
+    usd: f64,
+) -> Result<(), Error> {
+    client
+        .execute(
+            "INSERT INTO native_prices (chain, coin, usd, fetched_at) VALUES ($1, $2, $3, now()) \
+            ON CONFLICT (chain) DO UPDATE \
+            SET coin = EXCLUDED.coin, usd = EXCLUDED.usd, fetched_at = EXCLUDED.fetched_at",
+            &[&chain_name.to_lowercase(), &coin, &usd],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO native_price_history (chain, day, usd) \
+            VALUES ($1, (now() AT TIME ZONE 'UTC')::date, $2) \
+            ON CONFLICT (chain, day) DO UPDATE SET usd = EXCLUDED.usd",
+            &[&chain_name.to_lowercase(), &usd],
+        )
+        .await?;
+    Ok(())
+}
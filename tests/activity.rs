@@ -0,0 +1,165 @@
+mod common;
+
+use afterlife_backend::backend::queries::{set_privacy_settings, PrivacySettings};
+use common::{collection, get, transfer, TestDatabase, ALICE, BOB, CAROL, ZERO};
+use eth_checksum::checksum;
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c12";
+
+#[tokio::test]
+async fn activity_pages_through_transfers_sharing_a_timestamp() {
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("Paged", CONTRACT, "erc1155");
+    // Three mints in the same block, only their log index tells them apart
+    let events = (0..3)
+        .map(|log_index| {
+            let mut event = transfer(&erc1155, ZERO, ALICE, log_index + 1, 1, 10);
+            event.log_index = log_index;
+            event.block_timestamp = Some(1_700_000_000);
+            event
+        })
+        .collect();
+    db.index(&chain, events).await;
+
+    let mut seen = Vec::new();
+    let mut path = format!("/activity/{}?limit=2", ALICE);
+    loop {
+        let (status, body) = get(&db.database, &path).await;
+        assert_eq!(status, StatusCode::OK);
+        for entry in body["activity"].as_array().unwrap() {
+            seen.push(entry["log_index"].as_i64().unwrap());
+        }
+        match body["next_cursor"].as_str() {
+            Some(cursor) => path = format!("/activity/{}?limit=2&cursor={}", ALICE, cursor),
+            None => break,
+        }
+    }
+    assert_eq!(seen, vec![2, 1, 0]);
+
+    let (status, _) = get(&db.database, &format!("/activity/{}?cursor=oops", ALICE)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// Users and rarities read by the API from the environment, before it first loads them
+fn write_users_and_rarities() {
+    let root = std::env::temp_dir().join(format!("afterlife-activity-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let users = root.join("users.json");
+    std::fs::write(
+        &users,
+        json!({ "walker": [ALICE, BOB], "sleeper": [CAROL] }).to_string(),
+    )
+    .unwrap();
+    let rarities = (1..=3)
+        .map(|id| json!({ "token_id": id, "rarity_score": 1.0, "rarity_index": id }))
+        .collect::<Vec<_>>();
+    std::fs::write(
+        root.join(format!("Hiding_{}_rarity.json", checksum(CONTRACT))),
+        json!({ "rarities": rarities }).to_string(),
+    )
+    .unwrap();
+    std::env::set_var("AFTERLIFE_FILE_USERS", &users);
+    std::env::set_var("AFTERLIFE_PATH_METADATA", &root);
+    std::env::set_var("AFTERLIFE_PATH_RARITIES", &root);
+}
+
+// Token ids listed in a /user/level response
+fn listed_tokens(details: &Value) -> Vec<String> {
+    let mut token_ids: Vec<String> = details["all_nfts"]
+        .as_object()
+        .unwrap()
+        .values()
+        .flat_map(|contracts| contracts.as_object().unwrap().values())
+        .flat_map(|tokens| tokens.as_array().unwrap())
+        .map(|token| token["token_id"].as_str().unwrap().to_string())
+        .collect();
+    token_ids.sort();
+    token_ids
+}
+
+#[tokio::test]
+async fn hidden_wallets_stay_out_of_a_users_activity_and_level() {
+    write_users_and_rarities();
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("Hiding", CONTRACT, "erc721");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc721, ZERO, ALICE, 1, 1, 10),
+            transfer(&erc721, ZERO, BOB, 2, 1, 11),
+            transfer(&erc721, ZERO, CAROL, 3, 1, 12),
+        ],
+    )
+    .await;
+    let client = db.client().await;
+    let settings = PrivacySettings {
+        hidden_addresses: vec![BOB.to_string()],
+        hide_collection: false,
+    };
+    set_privacy_settings(&client, "walker", &settings)
+        .await
+        .unwrap();
+    let settings = PrivacySettings {
+        hidden_addresses: Vec::new(),
+        hide_collection: true,
+    };
+    set_privacy_settings(&client, "sleeper", &settings)
+        .await
+        .unwrap();
+
+    let (status, body) = get(&db.database, "/activity/walker").await;
+    assert_eq!(status, StatusCode::OK);
+    let recipients: Vec<&str> = body["activity"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["to"].as_str().unwrap())
+        .collect();
+    assert_eq!(recipients, vec![ALICE]);
+
+    // Still counted, just not listed
+    let (status, body) = get(&db.database, "/user/level/walker").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["addresses"], json!([ALICE]));
+    assert_eq!(listed_tokens(&body), vec!["1"]);
+    assert_eq!(body["top_nfts"].as_array().unwrap().len(), 1);
+    assert_eq!(body["afterlifepoints"], 2000.0);
+
+    // Asked for directly, the hidden wallet is unknown on every route
+    for path in [
+        format!("/activity/{}", BOB),
+        format!("/user/level/{}", BOB),
+        format!("/fullcollection/{}", BOB),
+        format!("/Hiding/{}/collection/{}", CONTRACT, BOB),
+    ] {
+        let (status, _) = get(&db.database, &path).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+    }
+
+    // A hidden collection lists no token nor transfer at all
+    let (status, body) = get(&db.database, "/activity/sleeper").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["activity"], json!([]));
+    let (status, body) = get(&db.database, "/user/level/sleeper").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(listed_tokens(&body).is_empty());
+    assert_eq!(body["top_nfts"], json!([]));
+    assert_eq!(body["afterlifepoints"], 1000.0);
+}
+
+#[tokio::test]
+async fn collection_activity_of_an_unknown_contract_is_not_found() {
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("Counted", CONTRACT, "erc721");
+    db.index(&chain, vec![transfer(&erc721, ZERO, ALICE, 1, 1, 10)])
+        .await;
+
+    let (status, _) = get(&db.database, &format!("/Counted/{}/activity", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = get(&db.database, &format!("/Counted/{}/activity", BOB)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+}
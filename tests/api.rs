@@ -0,0 +1,139 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use common::{
+    chain, collection, contract, get, transfer, TestDatabase, ALICE, BOB, CAROL, DEAD, ZERO,
+};
+use eth_checksum::checksum;
+use serde_json::json;
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c01";
+
+#[tokio::test]
+async fn token_owners_follow_transfers_and_leave_out_burns() {
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("api-owners", CONTRACT, "erc721");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc721, ZERO, ALICE, 1, 1, 10),
+            transfer(&erc721, ALICE, BOB, 1, 1, 11),
+            transfer(&erc721, ZERO, ALICE, 2, 1, 12),
+            transfer(&erc721, ALICE, DEAD, 2, 1, 13),
+        ],
+    )
+    .await;
+
+    let (status, owners) = get(&db.database, &format!("/api-owners/{}/owners/1", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    // Addresses are stored checksummed
+    assert_eq!(owners, json!([checksum(BOB)]));
+
+    // Owned by ALICE until the transfer of block 11
+    let (_, owners) = get(
+        &db.database,
+        &format!("/api-owners/{}/owners/1?block=10", CONTRACT),
+    )
+    .await;
+    assert_eq!(owners, json!([checksum(ALICE)]));
+
+    // Indexed up to block 13, later ownership isn't known yet
+    let (status, _) = get(
+        &db.database,
+        &format!("/api-owners/{}/owners/1?block=14", CONTRACT),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, owners) = get(&db.database, &format!("/api-owners/{}/owners/2", CONTRACT)).await;
+    assert_eq!(owners, json!([]));
+}
+
+#[tokio::test]
+async fn full_collection_sums_balances_and_hides_unverified_contracts() {
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("api-full-collection", CONTRACT, "erc1155");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc1155, ZERO, ALICE, 7, 5, 10),
+            transfer(&erc1155, ALICE, BOB, 7, 2, 11),
+            transfer(&erc1155, ZERO, ALICE, 8, 1, 12),
+        ],
+    )
+    .await;
+
+    let (status, collection) = get(&db.database, &format!("/fullcollection/{}", ALICE)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        collection,
+        json!({ "api-full-collection": { CONTRACT: { "7": 3, "8": 1 } } })
+    );
+
+    let client = db.database.client().await;
+    assert!(
+        queries::set_contract_verified(&client, "api-full-collection", CONTRACT, false)
+            .await
+            .unwrap()
+    );
+    let (_, collection) = get(&db.database, &format!("/fullcollection/{}", BOB)).await;
+    assert_eq!(collection, json!({}));
+    let (_, collection) = get(
+        &db.database,
+        &format!("/fullcollection/{}?include_unverified=true", BOB),
+    )
+    .await;
+    assert_eq!(
+        collection,
+        json!({ "api-full-collection": { CONTRACT: { "7": 2 } } })
+    );
+}
+
+#[tokio::test]
+async fn collection_stats_count_configured_burn_addresses() {
+    let db = TestDatabase::start().await;
+    let mut erc721 = contract(CONTRACT, "erc721");
+    erc721.burn_addresses = vec![CAROL.to_string()];
+    let chain = chain("api-stats", "", vec![erc721.clone()]);
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc721, ZERO, ALICE, 1, 1, 10),
+            transfer(&erc721, ZERO, ALICE, 2, 1, 11),
+            transfer(&erc721, ZERO, BOB, 3, 1, 12),
+            transfer(&erc721, ALICE, DEAD, 1, 1, 13),
+            transfer(&erc721, BOB, CAROL, 3, 1, 14),
+        ],
+    )
+    .await;
+
+    let (status, stats) = get(&db.database, &format!("/api-stats/{}/stats", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["token_count"], json!(1));
+    assert_eq!(stats["holders"], json!(1));
+
+    // The leaderboard leaves out what they hold of the contract
+    let burn_addresses = queries::get_all_burn_addresses(&db.client().await)
+        .await
+        .unwrap();
+    assert_eq!(
+        burn_addresses[&("api-stats".to_string(), CONTRACT.to_string())],
+        vec![ZERO.to_string(), DEAD.to_string(), CAROL.to_string()]
+    );
+}
+
+#[tokio::test]
+async fn offsets_past_the_end_of_usize_are_rejected() {
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("api-offsets", CONTRACT, "erc721");
+    db.index(&chain, vec![transfer(&erc721, ZERO, ALICE, 1, 1, 10)])
+        .await;
+
+    let (status, _) = get(
+        &db.database,
+        &format!("/v1/leaderboard?cursor={}", usize::MAX),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use afterlife_backend::indexer::queries::{get_chain_cursor, sync_approvals_backfill};
+use common::{chain, contract, transfer, TestDatabase, ALICE, ZERO};
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c14";
+
+#[tokio::test]
+async fn enabling_approvals_rewinds_the_contracts_to_their_start_block() {
+    let db = TestDatabase::start().await;
+    let erc721 = contract(CONTRACT, "erc721");
+    let mut chain = chain("Approving", "", vec![erc721.clone()]);
+    db.index(&chain, vec![transfer(&erc721, ZERO, ALICE, 1, 1, 10)])
+        .await;
+    let client = db.client().await;
+    assert_eq!(get_chain_cursor(&chain, &client).await.unwrap(), Some(10));
+
+    chain.index_approvals = true;
+    let rewound = sync_approvals_backfill(&chain, &client).await.unwrap();
+    assert_eq!(rewound, vec![CONTRACT.to_string()]);
+    assert_eq!(get_chain_cursor(&chain, &client).await.unwrap(), Some(0));
+
+    // Only once, the next runs move the cursor forward again
+    db.index(&chain, vec![transfer(&erc721, ZERO, ALICE, 2, 1, 12)])
+        .await;
+    assert!(sync_approvals_backfill(&chain, &client)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(get_chain_cursor(&chain, &client).await.unwrap(), Some(12));
+
+    // Approvals emitted while the flag was off are fetched again when it's back on
+    chain.index_approvals = false;
+    sync_approvals_backfill(&chain, &client).await.unwrap();
+    chain.index_approvals = true;
+    let rewound = sync_approvals_backfill(&chain, &client).await.unwrap();
+    assert_eq!(rewound, vec![CONTRACT.to_string()]);
+}
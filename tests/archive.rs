@@ -0,0 +1,149 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use afterlife_backend::indexer::queries::{
+    contract_and_chain_to_contractid, get_chain_cursor, get_earliest_last_processed_block,
+};
+use common::{chain, contract, transfer, TestDatabase, ALICE, BOB, ZERO};
+use serde_json::json;
+
+const PRUNED: &str = "0x0000000000000000000000000000000000000c03";
+const KEPT: &str = "0x0000000000000000000000000000000000000c04";
+
+#[tokio::test]
+async fn archiving_moves_the_events_and_leaves_other_contracts_alone() {
+    let db = TestDatabase::start().await;
+    let pruned = contract(PRUNED, "erc721");
+    let kept = contract(KEPT, "erc721");
+    let chain = chain("archive", "", vec![pruned.clone(), kept.clone()]);
+    db.index(
+        &chain,
+        vec![
+            transfer(&pruned, ZERO, ALICE, 1, 1, 10),
+            transfer(&pruned, ALICE, BOB, 1, 1, 11),
+            transfer(&kept, ZERO, BOB, 7, 1, 12),
+        ],
+    )
+    .await;
+
+    let client = db.client().await;
+    let pruned_id = contract_and_chain_to_contractid(&pruned, &chain, &client)
+        .await
+        .unwrap();
+    client
+        .execute(
+            "INSERT INTO sales (contract_id, marketplace, token_id, amount, seller, buyer, \
+            price, currency, block_number, transaction_hash, log_index) \
+            VALUES ($1, 'seaport', 1, 1, $2, $3, 1000, $4, 11, '0xabc', 0)",
+            &[&pruned_id, &ALICE, &BOB, &ZERO],
+        )
+        .await
+        .unwrap();
+    queries::upsert_token_metadata(&client, "archive", PRUNED, 1.into(), &json!({}))
+        .await
+        .unwrap();
+
+    let archived = queries::archive_contract(&client, "archive", PRUNED)
+        .await
+        .unwrap()
+        .expect("The contract is indexed");
+    assert_eq!(archived.archived_events, 2);
+    assert_eq!(archived.deleted_balances, 1);
+    assert_eq!(archived.archived_sales, 1);
+    assert_eq!(archived.deleted_token_metadata, 1);
+    assert!(archived.deleted_supply_history > 0);
+
+    let archived_rows: i64 = client
+        .query_one(
+            "SELECT COUNT(*) FROM events_archive WHERE contract_id = $1",
+            &[&pruned_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(archived_rows, 2);
+    let archived_sales: i64 = client
+        .query_one(
+            "SELECT COUNT(*) FROM sales_archive WHERE contract_id = $1",
+            &[&pruned_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(archived_sales, 1);
+    assert!(queries::get_entire_collection(&client, "archive", PRUNED)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        queries::get_entire_collection(&client, "archive", KEPT)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // The archived contract's cursor no longer holds the chain back
+    client
+        .execute(
+            "UPDATE contracts SET last_processed_block = 0 WHERE id = $1",
+            &[&pruned_id],
+        )
+        .await
+        .unwrap();
+    assert_eq!(get_chain_cursor(&chain, &client).await.unwrap(), Some(12));
+    assert_eq!(
+        get_earliest_last_processed_block(&chain, &client)
+            .await
+            .unwrap(),
+        Some(12)
+    );
+
+    // Nor does a contract removed from the config
+    client
+        .execute(
+            "UPDATE contracts SET archived_at = NULL WHERE id = $1",
+            &[&pruned_id],
+        )
+        .await
+        .unwrap();
+    let configured = common::chain("archive", "", vec![kept.clone()]);
+    assert_eq!(
+        get_chain_cursor(&configured, &client).await.unwrap(),
+        Some(12)
+    );
+    client
+        .execute(
+            "UPDATE contracts SET archived_at = now() WHERE id = $1",
+            &[&pruned_id],
+        )
+        .await
+        .unwrap();
+
+    // Archiving again is a no-op, unknown contracts aren't found
+    let again = queries::archive_contract(&client, "archive", PRUNED)
+        .await
+        .unwrap()
+        .expect("The contract is still there");
+    assert_eq!(again.archived_events, 0);
+    assert!(queries::archive_contract(
+        &client,
+        "archive",
+        "0x0000000000000000000000000000000000000c05"
+    )
+    .await
+    .unwrap()
+    .is_none());
+
+    // With every contract archived, the chain has no cursor left to index from
+    queries::archive_contract(&client, "archive", KEPT)
+        .await
+        .unwrap()
+        .expect("The contract is indexed");
+    assert_eq!(
+        get_earliest_last_processed_block(&chain, &client)
+            .await
+            .unwrap(),
+        None
+    );
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{collection, get, post, transfer, user_request, TestDatabase, BOB, ZERO};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+use web3::signing::{hash_message, Key, SecretKey, SecretKeyRef};
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c16";
+
+fn wallet() -> (SecretKey, String) {
+    let key = SecretKey::from_slice(&[7u8; 32]).unwrap();
+    let address = format!("{:?}", SecretKeyRef::new(&key).address());
+    (key, address)
+}
+
+fn write_users(users: Value) {
+    let path = std::env::temp_dir().join(format!("afterlife-users-{}.json", std::process::id()));
+    std::fs::write(&path, users.to_string()).unwrap();
+    std::env::set_var("AFTERLIFE_FILE_USERS", &path);
+}
+
+fn sign(key: &SecretKey, message: &str) -> String {
+    let signature = SecretKeyRef::new(key)
+        .sign_message(hash_message(message).as_bytes())
+        .unwrap();
+    let mut bytes = signature.r.as_bytes().to_vec();
+    bytes.extend_from_slice(signature.s.as_bytes());
+    bytes.push(signature.v as u8 + 27);
+    format!("0x{}", hex::encode(bytes))
+}
+
+// The login request of a fresh nonce, signed by `key`
+async fn login_body(db: &TestDatabase, key: &SecretKey, address: &str) -> String {
+    let (status, nonce) = post(
+        &db.database,
+        "/auth/nonce",
+        &json!({ "address": address }).to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    json!({
+        "address": address,
+        "nonce": nonce["nonce"],
+        "signature": sign(key, nonce["message"].as_str().unwrap()),
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn a_signed_nonce_signs_in_once_until_signed_out() {
+    std::env::set_var("AFTERLIFE_SESSION_SECRET", "test-session");
+    let (key, address) = wallet();
+    write_users(json!({ "walker": [address] }));
+    let db = TestDatabase::start().await;
+
+    let body = login_body(&db, &key, &address).await;
+    let (status, session) = post(&db.database, "/auth/login", &body).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = session["token"].as_str().unwrap();
+    let (status, _) = user_request(&db.database, "GET", "/user/privacy", token, "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The same signature can't open a second session
+    let (status, _) = post(&db.database, "/auth/login", &body).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = user_request(&db.database, "POST", "/auth/logout", token, "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["revoked"], 1);
+    let (status, _) = user_request(&db.database, "GET", "/user/privacy", token, "").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn an_avatar_is_dropped_once_its_token_is_sold() {
+    std::env::set_var("AFTERLIFE_SESSION_SECRET", "test-session");
+    let (key, address) = wallet();
+    write_users(json!({ "walker": [address] }));
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("avatars", CONTRACT, "erc721");
+    db.index(&chain, vec![transfer(&erc721, ZERO, &address, 1, 1, 10)])
+        .await;
+
+    let body = login_body(&db, &key, &address).await;
+    let (_, session) = post(&db.database, "/auth/login", &body).await;
+    let avatar = json!({ "chain": "avatars", "contract_address": CONTRACT, "token_id": "1" });
+    let (status, _) = user_request(
+        &db.database,
+        "PUT",
+        "/user/avatar",
+        session["token"].as_str().unwrap(),
+        &avatar.to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, found) = get(&db.database, "/users/search?q=walker").await;
+    assert_eq!(found["users"][0]["avatar"]["token_id"], "1");
+
+    db.index(&chain, vec![transfer(&erc721, &address, BOB, 1, 1, 11)])
+        .await;
+    let (_, found) = get(&db.database, "/users/search?q=walker").await;
+    assert_eq!(found["users"][0]["avatar"], Value::Null);
+}
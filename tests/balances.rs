@@ -0,0 +1,97 @@
+mod common;
+
+use afterlife_backend::indexer::queries::{
+    contract_and_chain_to_contractid, diff_balances, negative_balances, refresh_balances,
+};
+use common::{collection, transfer, TestDatabase, ALICE, BOB, ZERO};
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c02";
+
+#[tokio::test]
+async fn balances_table_follows_the_events_and_is_repaired_from_them() {
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("balances", CONTRACT, "erc1155");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc1155, ZERO, ALICE, 1, 10, 10),
+            transfer(&erc1155, ALICE, BOB, 1, 4, 11),
+            transfer(&erc1155, ZERO, BOB, 2, 1, 12),
+        ],
+    )
+    .await;
+
+    let client = db.client().await;
+    let contract_id = contract_and_chain_to_contractid(&erc1155, &chain, &client)
+        .await
+        .unwrap();
+    assert!(diff_balances(contract_id, &client)
+        .await
+        .unwrap()
+        .is_empty());
+    let rows = client
+        .query(
+            "SELECT address, token_id::text, balance::text FROM balances \
+            WHERE contract_id = $1 ORDER BY token_id, address",
+            &[&contract_id],
+        )
+        .await
+        .unwrap();
+    let rows: Vec<(String, String, String)> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            (ALICE.to_string(), "1".to_string(), "6".to_string()),
+            (BOB.to_string(), "1".to_string(), "4".to_string()),
+            (BOB.to_string(), "2".to_string(), "1".to_string()),
+        ]
+    );
+
+    // A lost write and a stray row
+    client
+        .execute(
+            "DELETE FROM balances WHERE contract_id = $1 AND token_id = 2",
+            &[&contract_id],
+        )
+        .await
+        .unwrap();
+    client
+        .execute(
+            "INSERT INTO balances (contract_id, address, token_id, balance) VALUES ($1, $2, 3, 1)",
+            &[&contract_id, &ALICE],
+        )
+        .await
+        .unwrap();
+    let differences = diff_balances(contract_id, &client).await.unwrap();
+    let mut differing: Vec<(&str, Option<&str>, Option<&str>)> = differences
+        .iter()
+        .map(|d| {
+            (
+                d.token_id.as_str(),
+                d.stored.as_deref(),
+                d.replayed.as_deref(),
+            )
+        })
+        .collect();
+    differing.sort();
+    assert_eq!(
+        differing,
+        vec![("2", None, Some("1")), ("3", Some("1"), None)]
+    );
+
+    let token_ids = vec!["2".to_string(), "3".to_string()];
+    refresh_balances(&client, contract_id, Some(token_ids.as_slice()))
+        .await
+        .unwrap();
+    assert!(diff_balances(contract_id, &client)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(negative_balances(contract_id, &client)
+        .await
+        .unwrap()
+        .is_empty());
+}
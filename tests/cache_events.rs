@@ -0,0 +1,61 @@
+mod common;
+
+use afterlife_backend::indexer::queries::{EventsNotification, EVENTS_CHANNEL};
+use common::{chain, collection, contract, transfer, TestDatabase, ALICE, BOB, ZERO};
+use std::time::Duration;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c11";
+
+#[tokio::test]
+async fn writes_notify_the_contracts_and_addresses_they_changed() {
+    let db = TestDatabase::start().await;
+    let mut listener = db.database.listen(&[EVENTS_CHANNEL]).await.unwrap();
+
+    let erc1155 = contract(CONTRACT, "erc1155");
+    let chain = chain("Notified", "", vec![erc1155.clone()]);
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc1155, ZERO, ALICE, 1, 5, 10),
+            transfer(&erc1155, ALICE, BOB, 1, 2, 11),
+        ],
+    )
+    .await;
+
+    let notification = tokio::time::timeout(Duration::from_secs(10), listener.recv())
+        .await
+        .expect("No notification after the write")
+        .unwrap();
+    assert_eq!(notification.channel(), EVENTS_CHANNEL);
+    let events: EventsNotification = serde_json::from_str(notification.payload()).unwrap();
+    // Sorted, without the zero address mints come from
+    assert_eq!(
+        events,
+        EventsNotification {
+            chain: "notified".to_string(),
+            contracts: vec![CONTRACT.to_string()],
+            addresses: Some(vec![BOB.to_string(), ALICE.to_string()]),
+        }
+    );
+}
+
+#[tokio::test]
+async fn rewriting_the_same_events_does_not_notify() {
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("Rewritten", CONTRACT, "erc1155");
+    let events = vec![
+        transfer(&erc1155, ZERO, ALICE, 1, 5, 10),
+        transfer(&erc1155, ALICE, BOB, 1, 2, 11),
+    ];
+    db.index(&chain, events.clone()).await;
+
+    let mut listener = db.database.listen(&[EVENTS_CHANNEL]).await.unwrap();
+    db.index(&chain, events).await;
+
+    assert!(
+        tokio::time::timeout(Duration::from_secs(2), listener.recv())
+            .await
+            .is_err(),
+        "Unchanged rows were notified"
+    );
+}
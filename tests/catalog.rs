@@ -0,0 +1,36 @@
+mod common;
+
+use common::{collection, get, transfer, TestDatabase, ALICE, BOB, DEAD, ZERO};
+use serde_json::json;
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c51";
+
+#[tokio::test]
+async fn catalog_lists_chains_with_their_contracts() {
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("cataloged", CONTRACT, "erc1155");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc1155, ZERO, ALICE, 1, 3, 10),
+            transfer(&erc1155, ZERO, BOB, 2, 1, 11),
+            transfer(&erc1155, ALICE, BOB, 1, 1, 12),
+            // Burned, neither the token nor the dead address count
+            transfer(&erc1155, ZERO, ALICE, 3, 1, 13),
+            transfer(&erc1155, ALICE, DEAD, 3, 1, 14),
+        ],
+    )
+    .await;
+
+    let (status, body) = get(&db.database, "/catalog").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["chains"][0]["name"], "cataloged");
+    let contract = &body["chains"][0]["contracts"][0];
+    assert_eq!(contract["address"], CONTRACT);
+    assert_eq!(contract["name"], "Fixture erc1155");
+    assert_eq!(contract["type"], "erc1155");
+    assert_eq!(contract["start_block"], json!(0));
+    assert_eq!(contract["token_count"], 2);
+    assert_eq!(contract["holder_count"], 2);
+}
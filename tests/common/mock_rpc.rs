@@ -0,0 +1,283 @@
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tiny_keccak::{Hasher, Keccak};
+use warp::Filter;
+use web3::types::{H160, H256, U256};
+
+// Blocks are 12 seconds apart from this timestamp on
+pub const GENESIS_TIMESTAMP: u64 = 1_700_000_000;
+
+pub fn block_timestamp(number: u64) -> u64 {
+    GENESIS_TIMESTAMP + number * 12
+}
+
+#[derive(Default)]
+struct State {
+    head: u64,
+    chain_id: u64,
+    logs: Vec<Value>,
+    // eth_getLogs over more blocks than this fails the way capped providers fail
+    max_range: Option<u64>,
+    // (from, to) of every eth_getLogs call, refused ones included
+    get_logs_calls: Vec<(u64, u64)>,
+    // Error every eth_call answers with, a revert when not set
+    call_error: Option<Value>,
+}
+
+/// JSON-RPC endpoint serving canned logs, for the calls the EventFetcher makes:
+/// eth_chainId, eth_blockNumber, eth_getLogs and eth_getBlockByNumber. eth_call reverts,
+/// as calls to a contract without the function do.
+pub struct MockRpc {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockRpc {
+    pub async fn start(head: u64) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            head,
+            // The id of the chain fixtures
+            chain_id: 1,
+            ..Default::default()
+        }));
+        let filter_state = state.clone();
+        let filter = warp::post()
+            .and(warp::body::json())
+            .map(move |request: Value| {
+                let response = match request {
+                    Value::Array(requests) => Value::Array(
+                        requests
+                            .iter()
+                            .map(|request| answer(&filter_state, request))
+                            .collect(),
+                    ),
+                    request => answer(&filter_state, &request),
+                };
+                warp::reply::json(&response)
+            });
+        let (address, server) = warp::serve(filter).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        Self {
+            url: format!("http://{}", address),
+            state,
+        }
+    }
+
+    pub fn push_log(&self, log: Value) {
+        self.state.lock().unwrap().logs.push(log);
+    }
+
+    pub fn set_chain_id(&self, chain_id: u64) {
+        self.state.lock().unwrap().chain_id = chain_id;
+    }
+
+    pub fn limit_range(&self, blocks: u64) {
+        self.state.lock().unwrap().max_range = Some(blocks);
+    }
+
+    /// Makes eth_call fail with a JSON-RPC error other than a revert
+    pub fn fail_calls(&self, code: i64, message: &str) {
+        self.state.lock().unwrap().call_error = Some(json!({ "code": code, "message": message }));
+    }
+
+    pub fn get_logs_calls(&self) -> Vec<(u64, u64)> {
+        self.state.lock().unwrap().get_logs_calls.clone()
+    }
+}
+
+fn answer(state: &Mutex<State>, request: &Value) -> Value {
+    let id = request["id"].clone();
+    let params = &request["params"];
+    let mut state = state.lock().unwrap();
+
+    let result = match request["method"].as_str().unwrap_or_default() {
+        "eth_chainId" => Ok(json!(format!("{:#x}", state.chain_id))),
+        "eth_blockNumber" => Ok(json!(format!("{:#x}", state.head))),
+        "eth_getBlockByNumber" => {
+            let number = match params[0].as_str().unwrap_or_default() {
+                "latest" | "safe" | "finalized" => state.head,
+                number => parse_quantity(number),
+            };
+            Ok(if number <= state.head {
+                block(number)
+            } else {
+                Value::Null
+            })
+        }
+        "eth_getLogs" => {
+            let from = parse_quantity(params[0]["fromBlock"].as_str().unwrap_or("0x0"));
+            let to = parse_quantity(params[0]["toBlock"].as_str().unwrap_or("0x0"));
+            state.get_logs_calls.push((from, to));
+            match state.max_range {
+                Some(max_range) if to - from + 1 > max_range => Err(json!({
+                    "code": -32005,
+                    "message": "query returned more than 10000 results",
+                })),
+                _ => Ok(Value::Array(
+                    state
+                        .logs
+                        .iter()
+                        .filter(|log| {
+                            let number =
+                                parse_quantity(log["blockNumber"].as_str().unwrap_or("0x0"));
+                            (from..=to).contains(&number)
+                        })
+                        .cloned()
+                        .collect(),
+                )),
+            }
+        }
+        "eth_call" => Err(state.call_error.clone().unwrap_or_else(|| {
+            json!({
+                "code": 3,
+                "message": "execution reverted",
+            })
+        })),
+        method => Err(json!({
+            "code": -32601,
+            "message": format!("the method {} does not exist", method),
+        })),
+    };
+
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+fn parse_quantity(quantity: &str) -> u64 {
+    u64::from_str_radix(quantity.trim_start_matches("0x"), 16).unwrap_or_default()
+}
+
+fn block(number: u64) -> Value {
+    let zero_hash = format!("{:?}", H256::zero());
+    json!({
+        "hash": format!("{:?}", H256::from_low_u64_be(number + 1)),
+        "parentHash": format!("{:?}", H256::from_low_u64_be(number)),
+        "sha3Uncles": zero_hash,
+        "miner": format!("{:?}", H160::zero()),
+        "stateRoot": zero_hash,
+        "transactionsRoot": zero_hash,
+        "receiptsRoot": zero_hash,
+        "number": format!("{:#x}", number),
+        "gasUsed": "0x0",
+        "gasLimit": "0x1c9c380",
+        "baseFeePerGas": "0x0",
+        "extraData": "0x",
+        "logsBloom": format!("0x{}", "00".repeat(256)),
+        "timestamp": format!("{:#x}", block_timestamp(number)),
+        "difficulty": "0x0",
+        "totalDifficulty": "0x0",
+        "sealFields": [],
+        "uncles": [],
+        "transactions": [],
+        "size": "0x0",
+        "mixHash": zero_hash,
+        "nonce": "0x0000000000000000",
+    })
+}
+
+/// keccak256 of an event signature, its topic 0
+pub fn topic(signature: &str) -> H256 {
+    let mut hash = [0u8; 32];
+    let mut keccak = Keccak::v256();
+    keccak.update(signature.as_bytes());
+    keccak.finalize(&mut hash);
+    H256(hash)
+}
+
+pub fn address_topic(address: &str) -> H256 {
+    H256::from(address.parse::<H160>().expect("Invalid address"))
+}
+
+/// A mined log, in its own transaction
+pub fn log(address: &str, topics: Vec<H256>, data: Vec<u8>, block: u64, log_index: u64) -> Value {
+    json!({
+        "address": address,
+        "topics": topics,
+        "data": format!("0x{}", hex::encode(data)),
+        "blockHash": format!("{:?}", H256::from_low_u64_be(block + 1)),
+        "blockNumber": format!("{:#x}", block),
+        "transactionHash": format!("{:?}", H256::from_low_u64_be(block * 1000 + log_index)),
+        "transactionIndex": "0x0",
+        "logIndex": format!("{:#x}", log_index),
+        "removed": false,
+    })
+}
+
+/// ERC-721 Transfer(from, to, tokenId), everything indexed
+pub fn erc721_transfer(contract: &str, from: &str, to: &str, id: u64, block: u64) -> Value {
+    let mut id_topic = [0u8; 32];
+    U256::from(id).to_big_endian(&mut id_topic);
+    log(
+        contract,
+        vec![
+            topic("Transfer(address,address,uint256)"),
+            address_topic(from),
+            address_topic(to),
+            H256(id_topic),
+        ],
+        Vec::new(),
+        block,
+        0,
+    )
+}
+
+/// ERC-1155 TransferSingle(operator, from, to, id, value)
+pub fn erc1155_transfer_single(
+    contract: &str,
+    operator: &str,
+    from: &str,
+    to: &str,
+    id: u64,
+    value: u64,
+    block: u64,
+) -> Value {
+    log(
+        contract,
+        vec![
+            topic("TransferSingle(address,address,address,uint256,uint256)"),
+            address_topic(operator),
+            address_topic(from),
+            address_topic(to),
+        ],
+        ethabi::encode(&[
+            ethabi::Token::Uint(U256::from(id)),
+            ethabi::Token::Uint(U256::from(value)),
+        ]),
+        block,
+        0,
+    )
+}
+
+/// ERC-1155 TransferBatch(operator, from, to, ids, values)
+pub fn erc1155_transfer_batch(
+    contract: &str,
+    operator: &str,
+    from: &str,
+    to: &str,
+    ids_and_values: &[(u64, u64)],
+    block: u64,
+) -> Value {
+    let ids = ids_and_values
+        .iter()
+        .map(|(id, _)| ethabi::Token::Uint(U256::from(*id)))
+        .collect();
+    let values = ids_and_values
+        .iter()
+        .map(|(_, value)| ethabi::Token::Uint(U256::from(*value)))
+        .collect();
+    log(
+        contract,
+        vec![
+            topic("TransferBatch(address,address,address,uint256[],uint256[])"),
+            address_topic(operator),
+            address_topic(from),
+            address_topic(to),
+        ],
+        ethabi::encode(&[ethabi::Token::Array(ids), ethabi::Token::Array(values)]),
+        block,
+        0,
+    )
+}
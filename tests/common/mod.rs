@@ -0,0 +1,303 @@
+// Shared by the test binaries, each uses only part of it
+#![allow(dead_code)]
+
+pub mod mock_rpc;
+
+use afterlife_backend::common::database::Database;
+use afterlife_backend::common::migrations;
+use afterlife_backend::indexer::indexer_config::{Chain, Contract};
+use afterlife_backend::indexer::queries::{
+    contract_and_chain_to_contractid, sync_burn_addresses, sync_sink_addresses,
+    write_events_for_chain, Event,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Weak};
+use testcontainers_modules::postgres::Postgres;
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use testcontainers_modules::testcontainers::ContainerAsync;
+use tokio::sync::{oneshot, Mutex};
+use tokio_postgres::{Client, Config, NoTls};
+use warp::http::StatusCode;
+use web3::types::U256;
+
+pub const ZERO: &str = "0x0000000000000000000000000000000000000000";
+pub const DEAD: &str = "0x000000000000000000000000000000000000dead";
+pub const ALICE: &str = "0x00000000000000000000000000000000000a11ce";
+pub const BOB: &str = "0x0000000000000000000000000000000000000b0b";
+pub const CAROL: &str = "0x00000000000000000000000000000000000ca201";
+
+// The Postgres container of the test binary, shared by the tests running at the same time
+static SHARED_POSTGRES: Mutex<Weak<SharedPostgres>> = Mutex::const_new(Weak::new());
+static NEXT_SCHEMA: AtomicUsize = AtomicUsize::new(0);
+
+// Each test runs on a runtime of its own, so the container lives on a thread of its own and
+// is removed there once the last test using it is done
+struct SharedPostgres {
+    config: Config,
+    _stop: mpsc::Sender<()>,
+}
+
+impl SharedPostgres {
+    async fn get() -> Arc<Self> {
+        let mut shared = SHARED_POSTGRES.lock().await;
+        if let Some(postgres) = shared.upgrade() {
+            return postgres;
+        }
+        let postgres = Arc::new(Self::start().await);
+        *shared = Arc::downgrade(&postgres);
+        postgres
+    }
+
+    async fn start() -> Self {
+        let (config_tx, config_rx) = oneshot::channel();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build the Postgres runtime");
+            let container: ContainerAsync<Postgres> = runtime.block_on(async {
+                let container = Postgres::default()
+                    .start()
+                    .await
+                    .expect("Failed to start Postgres, is Docker running?");
+                let host = container
+                    .get_host()
+                    .await
+                    .expect("Failed to get the Postgres host");
+                let port = container
+                    .get_host_port_ipv4(5432)
+                    .await
+                    .expect("Failed to get the Postgres port");
+
+                let mut config = Config::new();
+                config
+                    .host(&host.to_string())
+                    .port(port)
+                    .user("postgres")
+                    .password("postgres")
+                    .dbname("postgres");
+                let _ = config_tx.send(config);
+                container
+            });
+            // Until the sender is dropped with the last test
+            let _ = stop_rx.recv();
+            let _runtime = runtime.enter();
+            drop(container);
+        });
+
+        Self {
+            config: config_rx.await.expect("Failed to start Postgres"),
+            _stop: stop_tx,
+        }
+    }
+}
+
+/// A schema of its own in the binary's shared Postgres, migrated, for one test
+pub struct TestDatabase {
+    _postgres: Arc<SharedPostgres>,
+    config: Config,
+    pub database: Arc<Database>,
+}
+
+impl TestDatabase {
+    pub async fn start() -> Self {
+        let postgres = SharedPostgres::get().await;
+        let schema = format!("test_{}", NEXT_SCHEMA.fetch_add(1, Ordering::Relaxed));
+        connect(&postgres.config)
+            .await
+            .batch_execute(&format!("CREATE SCHEMA {}", schema))
+            .await
+            .expect("Failed to create the test schema");
+
+        let mut config = postgres.config.clone();
+        config.options(&format!("-c search_path={}", schema));
+        let mut client = connect(&config).await;
+        migrations::run(&mut client)
+            .await
+            .expect("Failed to apply the migrations");
+        let database = Database::connect_with("test", config.clone())
+            .await
+            .expect("Failed to connect to Postgres");
+
+        Self {
+            _postgres: postgres,
+            config,
+            database,
+        }
+    }
+
+    /// A connection of its own, for writes that need a `&mut Client`
+    pub async fn client(&self) -> Client {
+        connect(&self.config).await
+    }
+
+    /// Writes `events` the way an indexing run that fetched them would, cursors included
+    pub async fn index(&self, chain: &Chain, events: Vec<Event>) {
+        let mut client = self.client().await;
+        let mut events_by_contract: HashMap<i32, Vec<Event>> = HashMap::new();
+        for contract in &chain.contracts {
+            let contract_id = contract_and_chain_to_contractid(contract, chain, &client)
+                .await
+                .expect("Failed to create the contract");
+            events_by_contract.insert(contract_id, Vec::new());
+        }
+        for event in events {
+            let contract_id = contract_and_chain_to_contractid(&event.contract, chain, &client)
+                .await
+                .expect("Failed to get the contract id");
+            events_by_contract
+                .entry(contract_id)
+                .or_default()
+                .push(event);
+        }
+        let to_block = events_by_contract
+            .values()
+            .flatten()
+            .map(|event| event.block_number)
+            .max()
+            .unwrap_or(0);
+
+        write_events_for_chain(
+            chain,
+            &events_by_contract,
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::new(),
+            0,
+            to_block,
+            to_block,
+            &mut client,
+        )
+        .await
+        .expect("Failed to write the events");
+        sync_burn_addresses(chain, &client)
+            .await
+            .expect("Failed to sync the burn addresses");
+        sync_sink_addresses(chain, &client)
+            .await
+            .expect("Failed to sync the sink addresses");
+    }
+}
+
+async fn connect(config: &Config) -> Client {
+    let (client, connection) = config
+        .connect(NoTls)
+        .await
+        .expect("Failed to connect to Postgres");
+    tokio::spawn(connection);
+    client
+}
+
+/// Chain config as it would be read from the indexer YAML. Chain names end up in response
+/// cache keys, which are shared by the tests of a binary, so every test uses its own.
+pub fn chain(name: &str, rpc_url: &str, contracts: Vec<Contract>) -> Chain {
+    serde_json::from_value(json!({
+        "id": 1,
+        "name": name,
+        "rpc_url": rpc_url,
+        "contracts": contracts,
+    }))
+    .expect("Invalid chain fixture")
+}
+
+pub fn contract(address: &str, r#type: &str) -> Contract {
+    serde_json::from_value(json!({
+        "name": format!("Fixture {}", r#type),
+        "address": address,
+        "startblock": 0,
+        "type": r#type,
+    }))
+    .expect("Invalid contract fixture")
+}
+
+/// A transfer of `value` of token `id`, the only log of its block
+pub fn transfer(
+    contract: &Contract,
+    from: &str,
+    to: &str,
+    id: u64,
+    value: u64,
+    block: u64,
+) -> Event {
+    Event::new(
+        contract.clone(),
+        from.to_string(),
+        from.to_string(),
+        to.to_string(),
+        vec![U256::from(id)],
+        vec![U256::from(value)],
+        block,
+        format!("{:#066x}", block),
+        0,
+        0,
+    )
+    .expect("Invalid transfer fixture")
+}
+
+/// A chain holding a single contract, the fixture most tests start from
+pub fn collection(chain_name: &str, address: &str, r#type: &str) -> (Chain, Contract) {
+    let contract = contract(address, r#type);
+    (chain(chain_name, "", vec![contract.clone()]), contract)
+}
+
+const ADMIN_TOKEN: &str = "test-admin";
+
+async fn reply(
+    database: &Arc<Database>,
+    request: warp::test::RequestBuilder,
+) -> (StatusCode, Value) {
+    let response = request
+        .reply(&afterlife_backend::backend::api::routes(database.clone()))
+        .await;
+    let body = serde_json::from_slice(response.body()).unwrap_or(Value::Null);
+    (response.status(), body)
+}
+
+/// Runs a GET through the whole router, returns the status and the parsed JSON body
+pub async fn get(database: &Arc<Database>, path: &str) -> (StatusCode, Value) {
+    reply(database, warp::test::request().method("GET").path(path)).await
+}
+
+/// Runs a POST with a raw body through the whole router, returns the status and the parsed
+/// JSON body
+pub async fn post(database: &Arc<Database>, path: &str, body: &str) -> (StatusCode, Value) {
+    let request = warp::test::request()
+        .method("POST")
+        .path(path)
+        .header("content-type", "application/json")
+        .body(body);
+    reply(database, request).await
+}
+
+/// Same as [`post`], authenticated as the admin
+pub async fn admin_post(database: &Arc<Database>, path: &str, body: &str) -> (StatusCode, Value) {
+    std::env::set_var("AFTERLIFE_ADMIN_TOKEN", ADMIN_TOKEN);
+    let request = warp::test::request()
+        .method("POST")
+        .path(path)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .header("content-type", "application/json")
+        .body(body);
+    reply(database, request).await
+}
+
+/// Runs a request with a JSON body as the wallet of the session `token`
+pub async fn user_request(
+    database: &Arc<Database>,
+    method: &str,
+    path: &str,
+    token: &str,
+    body: &str,
+) -> (StatusCode, Value) {
+    let request = warp::test::request()
+        .method(method)
+        .path(path)
+        .header("authorization", format!("Bearer {}", token))
+        .header("content-type", "application/json")
+        .body(body);
+    reply(database, request).await
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use afterlife_backend::common::numeric::{Balance, TokenId};
+use common::{chain, contract, get, transfer, TestDatabase, ALICE, BOB, ZERO};
+use std::collections::HashMap;
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c11";
+const CRAFTING: &str = "0x0000000000000000000000000000000000c4af75";
+
+type Collections = HashMap<String, HashMap<String, HashMap<String, HashMap<TokenId, Balance>>>>;
+
+// Balance of token 1 held by the address, as a string
+fn balance(collections: &Collections, address: &str) -> Option<String> {
+    collections
+        .get(address)
+        .and_then(|chains| chains.get("consumption"))
+        .and_then(|contracts| contracts.get(CONTRACT))
+        .and_then(|tokens| tokens.get(&TokenId::from(1)))
+        .map(|balance| balance.to_string())
+}
+
+#[tokio::test]
+async fn transfers_to_a_sink_are_consumed_and_credited() {
+    let db = TestDatabase::start().await;
+    let mut erc1155 = contract(CONTRACT, "erc1155");
+    erc1155.sink_addresses = vec![CRAFTING.to_string()];
+    erc1155.credit_consumed = true;
+    let chain = chain("consumption", "", vec![erc1155.clone()]);
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc1155, ZERO, ALICE, 1, 5, 10),
+            transfer(&erc1155, ALICE, CRAFTING, 1, 2, 11),
+            transfer(&erc1155, ALICE, CRAFTING, 1, 1, 12),
+            transfer(&erc1155, ALICE, BOB, 1, 1, 13),
+            // The crafting contract hands one back
+            transfer(&erc1155, CRAFTING, BOB, 1, 1, 14),
+        ],
+    )
+    .await;
+
+    let client = db.client().await;
+    let counts = queries::get_transfer_counts_for_addresses(&client, &[ALICE.to_string()])
+        .await
+        .unwrap();
+    assert_eq!(counts.len(), 1);
+    assert_eq!(counts[0].mints, 1);
+    assert_eq!(counts[0].burns, 0);
+    assert_eq!(counts[0].consumed, 3);
+
+    // Scores keep the consumed tokens with their sender
+    let credited = queries::get_all_users_collections(&client, true)
+        .await
+        .unwrap();
+    assert_eq!(balance(&credited, ALICE).as_deref(), Some("4"));
+    assert_eq!(balance(&credited, CRAFTING), None);
+    // and leave out what the sink sends on, it's still ALICE's
+    assert_eq!(balance(&credited, BOB).as_deref(), Some("1"));
+
+    // Holdings don't
+    let held = queries::get_all_users_collections(&client, false)
+        .await
+        .unwrap();
+    assert_eq!(balance(&held, ALICE).as_deref(), Some("1"));
+    assert_eq!(balance(&held, CRAFTING).as_deref(), Some("2"));
+    assert_eq!(balance(&held, BOB).as_deref(), Some("2"));
+
+    let (status, stats) = get(&db.database, &format!("/consumption/{}/stats", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["consumed"], 3);
+}
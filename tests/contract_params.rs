@@ -0,0 +1,73 @@
+mod common;
+
+use common::{collection, get, transfer, TestDatabase, ALICE, ZERO};
+use eth_checksum::checksum;
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x00000000000000000000000000000000000abc31";
+
+#[tokio::test]
+async fn chain_and_contract_parameters_are_canonicalized() {
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("canon", CONTRACT, "erc721");
+    db.index(&chain, vec![transfer(&erc721, ZERO, ALICE, 1, 1, 10)])
+        .await;
+    let routes = afterlife_backend::backend::api::routes(db.database.clone());
+
+    // Lowercase and checksummed addresses are both served
+    let (status, _) = get(&db.database, &format!("/canon/{}/stats", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = get(
+        &db.database,
+        &format!("/canon/{}/stats", checksum(CONTRACT)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Anything else is redirected, query included
+    let response = warp::test::request()
+        .path(&format!(
+            "/Canon/{}/stats?window=7d",
+            CONTRACT.to_uppercase().replace("0X", "0x")
+        ))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers()["location"],
+        format!("/canon/{}/stats?window=7d", checksum(CONTRACT)).as_str()
+    );
+
+    // Unknown chains list the known ones
+    let (status, body) = get(&db.database, &format!("/nowhere/{}/stats", CONTRACT)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body["message"].as_str().unwrap().contains("canon"));
+
+    // Routes with an address after another literal aren't mistaken for contract routes
+    let (status, _) = get(&db.database, &format!("/fullcollection/{}", ALICE)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn chains_resolve_to_their_stored_name() {
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("Mixed", CONTRACT, "erc721");
+    db.index(&chain, vec![transfer(&erc721, ZERO, ALICE, 1, 1, 10)])
+        .await;
+    let routes = afterlife_backend::backend::api::routes(db.database.clone());
+
+    // Lowercase is served as is
+    let (status, _) = get(&db.database, &format!("/mixed/{}/stats", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Any other spelling goes to the stored one
+    let response = warp::test::request()
+        .path(&format!("/MIXED/{}/stats", CONTRACT))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers()["location"],
+        format!("/Mixed/{}/stats", checksum(CONTRACT)).as_str()
+    );
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use afterlife_backend::indexer::indexer_config::IndexerConfig;
+use afterlife_backend::indexer::queries::sync_contract_slugs;
+use common::{chain, contract, get, transfer, TestDatabase, ALICE, ZERO};
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c41";
+const OTHER: &str = "0x0000000000000000000000000000000000000c42";
+
+#[tokio::test]
+async fn contracts_are_reachable_by_their_slug() {
+    let db = TestDatabase::start().await;
+    let mut erc721 = contract(CONTRACT, "erc721");
+    erc721.slug = Some("Relics".to_string());
+    let chain = chain("slugged", "", vec![erc721.clone()]);
+    db.index(&chain, vec![transfer(&erc721, ZERO, ALICE, 1, 1, 10)])
+        .await;
+    sync_contract_slugs(&chain, &mut db.client().await)
+        .await
+        .unwrap();
+
+    let (status, by_address) = get(&db.database, &format!("/slugged/{}/stats", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, by_slug) = get(&db.database, "/slugged/relics/stats").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(by_slug, by_address);
+
+    let (status, body) = get(&db.database, "/slugged/relics/collection").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, _) = get(&db.database, "/slugged/unknown/stats").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn contracts_can_swap_slugs() {
+    let db = TestDatabase::start().await;
+    let mut first = contract(CONTRACT, "erc721");
+    let mut second = contract(OTHER, "erc721");
+    first.slug = Some("relics".to_string());
+    second.slug = Some("shards".to_string());
+    let mut chain = chain("swapped", "", vec![first.clone(), second.clone()]);
+    db.index(&chain, vec![transfer(&first, ZERO, ALICE, 1, 1, 10)])
+        .await;
+    let mut client = db.client().await;
+    sync_contract_slugs(&chain, &mut client).await.unwrap();
+
+    chain.contracts[0].slug = Some("shards".to_string());
+    chain.contracts[1].slug = Some("relics".to_string());
+    sync_contract_slugs(&chain, &mut client).await.unwrap();
+
+    let (status, by_address) = get(&db.database, &format!("/swapped/{}/stats", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, by_slug) = get(&db.database, "/swapped/shards/stats").await;
+    assert_eq!(by_slug, by_address);
+}
+
+#[test]
+fn duplicate_slugs_are_rejected_at_load() {
+    let mut first = contract(CONTRACT, "erc721");
+    let mut second = contract(OTHER, "erc721");
+    first.slug = Some("relics".to_string());
+    second.slug = Some("Relics".to_string());
+    let config = IndexerConfig {
+        chains: vec![chain("slugged", "", vec![first, second])],
+    };
+    assert!(config.validate().unwrap_err().contains("Relics"));
+}
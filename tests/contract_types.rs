@@ -0,0 +1,22 @@
+mod common;
+
+use afterlife_backend::indexer::contract_types::{detect, UNKNOWN_TYPE};
+use afterlife_backend::indexer::rpc_pool::RpcPool;
+use common::mock_rpc::MockRpc;
+use web3::types::Address;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c14";
+
+#[tokio::test]
+async fn only_reverts_mean_an_interface_is_unsupported() {
+    let rpc = MockRpc::start(100).await;
+    let pool = RpcPool::new(std::slice::from_ref(&rpc.url), 1, None).unwrap();
+    let address: Address = CONTRACT.parse().unwrap();
+
+    // The mock reverts every call, like a contract without supportsInterface
+    assert_eq!(detect(&pool, address).await.unwrap(), UNKNOWN_TYPE);
+
+    // A provider failing isn't taken for a contract without ERC-165
+    rpc.fail_calls(-32005, "daily request limit exceeded");
+    assert!(detect(&pool, address).await.is_err());
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use afterlife_backend::backend::queries::get_delegations;
+use afterlife_backend::delegation::queries::{replace_delegations, Delegation};
+use common::{TestDatabase, ALICE, BOB, CAROL};
+
+const REGISTRY: &str = "0x00000000000000447e69651d841bd8d104bed493";
+
+fn delegation(vault: &str, delegate: &str) -> Delegation {
+    Delegation {
+        vault: vault.to_string(),
+        delegate: delegate.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn the_latest_delegation_of_a_vault_comes_first() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+
+    replace_delegations(&client, "ethereum", REGISTRY, &[delegation(CAROL, BOB)], 10)
+        .await
+        .unwrap();
+    // BOB's delegation keeps the block it was first seen at
+    replace_delegations(
+        &client,
+        "ethereum",
+        REGISTRY,
+        &[delegation(CAROL, BOB), delegation(CAROL, ALICE)],
+        20,
+    )
+    .await
+    .unwrap();
+    let expected = vec![
+        (CAROL.to_string(), ALICE.to_string()),
+        (CAROL.to_string(), BOB.to_string()),
+    ];
+    assert_eq!(get_delegations(&client).await.unwrap(), expected);
+
+    // Same block, the delegate breaks the tie
+    replace_delegations(&client, "base", REGISTRY, &[delegation(ALICE, CAROL)], 30)
+        .await
+        .unwrap();
+    replace_delegations(&client, "optimism", REGISTRY, &[delegation(ALICE, BOB)], 30)
+        .await
+        .unwrap();
+    let delegations = get_delegations(&client).await.unwrap();
+    assert_eq!(
+        delegations[..2],
+        [
+            (ALICE.to_string(), BOB.to_string()),
+            (ALICE.to_string(), CAROL.to_string()),
+        ]
+    );
+
+    // Dropped from the snapshot, dropped from the table
+    replace_delegations(&client, "ethereum", REGISTRY, &[], 40)
+        .await
+        .unwrap();
+    assert_eq!(get_delegations(&client).await.unwrap().len(), 2);
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use afterlife_backend::indexer::queries::Event;
+use afterlife_backend::indexer::remote_calls::EventFetcher;
+use common::mock_rpc::{self, block_timestamp, MockRpc};
+use common::{chain, contract, ALICE, BOB, CAROL, ZERO};
+use web3::types::U256;
+
+const ERC721: &str = "0x0000000000000000000000000000000000000721";
+const ERC1155: &str = "0x0000000000000000000000000000000000001155";
+const OTHER: &str = "0x000000000000000000000000000000000000beef";
+
+fn sorted(mut events: Vec<Event>) -> Vec<Event> {
+    events.sort_by_key(|event| (event.block_number, event.log_index));
+    events
+}
+
+fn u256s(values: &[u64]) -> Vec<U256> {
+    values.iter().map(|value| U256::from(*value)).collect()
+}
+
+#[tokio::test]
+async fn decodes_erc721_and_erc1155_transfers() {
+    let rpc = MockRpc::start(100).await;
+    rpc.push_log(mock_rpc::erc721_transfer(ERC721, ZERO, ALICE, 42, 10));
+    rpc.push_log(mock_rpc::erc1155_transfer_single(
+        ERC1155, ALICE, ZERO, ALICE, 7, 5, 20,
+    ));
+    rpc.push_log(mock_rpc::erc1155_transfer_batch(
+        ERC1155,
+        BOB,
+        ALICE,
+        CAROL,
+        &[(7, 2), (8, 1)],
+        30,
+    ));
+    // Not a configured contract
+    rpc.push_log(mock_rpc::erc721_transfer(OTHER, ZERO, BOB, 1, 40));
+
+    let chain = chain(
+        "fetcher-decode",
+        &rpc.url,
+        vec![contract(ERC721, "erc721"), contract(ERC1155, "erc1155")],
+    );
+    let (
+        (events, updates, sales, approvals, undecodable),
+        (from_block, to_block),
+        (head_block, safe_block),
+    ) = EventFetcher::new(&chain, 0)
+        .unwrap()
+        .execute()
+        .await
+        .unwrap();
+
+    assert_eq!((from_block, to_block), (0, 100));
+    assert_eq!(head_block, 100);
+    // Two confirmations by default
+    assert_eq!(safe_block, 98);
+    assert!(updates.is_empty() && sales.is_empty() && approvals.is_empty());
+    assert!(undecodable.is_empty());
+
+    let events = sorted(events);
+    assert_eq!(events.len(), 3);
+
+    assert_eq!(events[0].contract.address, ERC721);
+    assert_eq!(events[0].from_address, ZERO);
+    assert_eq!(events[0].to_address, ALICE);
+    assert_eq!(events[0].ids, u256s(&[42]));
+    assert_eq!(events[0].values, u256s(&[1]));
+    assert_eq!(events[0].block_timestamp, Some(block_timestamp(10)));
+
+    assert_eq!(events[1].contract.address, ERC1155);
+    assert_eq!(events[1].operator, ALICE);
+    assert_eq!(events[1].to_address, ALICE);
+    assert_eq!(events[1].ids, u256s(&[7]));
+    assert_eq!(events[1].values, u256s(&[5]));
+
+    assert_eq!(events[2].operator, BOB);
+    assert_eq!(events[2].from_address, ALICE);
+    assert_eq!(events[2].to_address, CAROL);
+    assert_eq!(events[2].ids, u256s(&[7, 8]));
+    assert_eq!(events[2].values, u256s(&[2, 1]));
+    assert_eq!(events[2].block_timestamp, Some(block_timestamp(30)));
+}
+
+#[tokio::test]
+async fn splits_ranges_the_provider_refuses() {
+    let rpc = MockRpc::start(100).await;
+    for block in [3, 49, 50, 51, 97] {
+        rpc.push_log(mock_rpc::erc721_transfer(ERC721, ZERO, ALICE, block, block));
+    }
+    rpc.limit_range(10);
+
+    let chain = chain("fetcher-split", &rpc.url, vec![contract(ERC721, "erc721")]);
+    let ((events, _, _, _, _), (from_block, to_block), _) = EventFetcher::new(&chain, 0)
+        .unwrap()
+        .execute()
+        .await
+        .unwrap();
+
+    assert_eq!((from_block, to_block), (0, 100));
+    let blocks: Vec<u64> = sorted(events)
+        .iter()
+        .map(|event| event.block_number)
+        .collect();
+    assert_eq!(blocks, vec![3, 49, 50, 51, 97]);
+
+    // Every block was eventually fetched by a call the provider accepted
+    let mut accepted: Vec<(u64, u64)> = rpc
+        .get_logs_calls()
+        .into_iter()
+        .filter(|(from, to)| to - from < 10)
+        .collect();
+    accepted.sort();
+    let mut next = 0;
+    for (from, to) in accepted {
+        assert_eq!(from, next);
+        next = to + 1;
+    }
+    assert_eq!(next, 101);
+}
+
+#[tokio::test]
+async fn refuses_an_endpoint_of_another_chain() {
+    let rpc = MockRpc::start(100).await;
+    rpc.push_log(mock_rpc::erc721_transfer(ERC721, ZERO, ALICE, 1, 10));
+    rpc.set_chain_id(250);
+
+    let chain = chain(
+        "fetcher-chain-id",
+        &rpc.url,
+        vec![contract(ERC721, "erc721")],
+    );
+    let result = EventFetcher::new(&chain, 0).unwrap().execute().await;
+
+    assert!(result.is_err());
+    assert!(rpc.get_logs_calls().is_empty());
+}
+
+#[tokio::test]
+async fn counts_the_logs_it_cannot_decode() {
+    let rpc = MockRpc::start(100).await;
+    rpc.push_log(mock_rpc::erc721_transfer(ERC721, ZERO, ALICE, 1, 10));
+    // An ERC-20 style Transfer, the token id isn't indexed
+    rpc.push_log(mock_rpc::log(
+        ERC721,
+        vec![
+            mock_rpc::topic("Transfer(address,address,uint256)"),
+            mock_rpc::address_topic(ALICE),
+            mock_rpc::address_topic(BOB),
+        ],
+        vec![0; 32],
+        20,
+        0,
+    ));
+
+    let chain = chain(
+        "fetcher-undecodable",
+        &rpc.url,
+        vec![contract(ERC721, "erc721")],
+    );
+    let ((events, _, _, _, undecodable), _, _) = EventFetcher::new(&chain, 0)
+        .unwrap()
+        .execute()
+        .await
+        .unwrap();
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(undecodable.get(ERC721), Some(&1));
+}
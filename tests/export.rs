@@ -0,0 +1,57 @@
+mod common;
+
+use afterlife_backend::indexer::queries::{
+    contract_and_chain_to_contractid, get_event_months, get_exported_transfers,
+};
+use common::{collection, transfer, TestDatabase, ALICE, BOB, ZERO};
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c06";
+// 2024-01-15T00:00:00Z
+const JANUARY_15: u64 = 1_705_276_800;
+
+#[tokio::test]
+async fn exported_transfers_are_split_by_month_one_row_per_token() {
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("export", CONTRACT, "erc1155");
+    let mut batch = transfer(&erc1155, ZERO, ALICE, 1, 5, 10);
+    batch.ids.push(2.into());
+    batch.values.push(3.into());
+    batch.block_timestamp = Some(JANUARY_15);
+    db.index(
+        &chain,
+        vec![batch, transfer(&erc1155, ALICE, BOB, 1, 1, 11)],
+    )
+    .await;
+
+    let client = db.client().await;
+    let contract_id = contract_and_chain_to_contractid(&erc1155, &chain, &client)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_event_months(contract_id, &client).await.unwrap(),
+        vec![Some("2024-01".to_string()), None]
+    );
+
+    let january = get_exported_transfers(contract_id, Some("2024-01"), &client)
+        .await
+        .unwrap();
+    let tokens: Vec<(&str, &str)> = january
+        .iter()
+        .map(|t| (t.token_id.as_str(), t.value.as_str()))
+        .collect();
+    assert_eq!(tokens, vec![("1", "5"), ("2", "3")]);
+    assert_eq!(
+        january[0].block_time.as_deref(),
+        Some("2024-01-15T00:00:00Z")
+    );
+    assert_eq!(
+        january[0].block_timestamp_micros,
+        Some(JANUARY_15 as i64 * 1_000_000)
+    );
+
+    let undated = get_exported_transfers(contract_id, None, &client)
+        .await
+        .unwrap();
+    assert_eq!(undated.len(), 1);
+    assert_eq!(undated[0].block_number, 11);
+}
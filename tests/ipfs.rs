@@ -0,0 +1,22 @@
+use afterlife_backend::metadata::ipfs::{pin_path, ContentUri};
+use std::path::Path;
+
+#[test]
+fn pinned_paths_stay_under_the_pin_root() {
+    let root = Path::new("/var/pins");
+    let cid = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+
+    let path = format!("{}/1.json", cid);
+    assert_eq!(pin_path(root, &path), Some(root.join(cid).join("1.json")));
+
+    // ipfs:///etc/passwd parses to an absolute path, which join would put outside the root
+    let absolute = match ContentUri::parse("ipfs:///etc/passwd") {
+        Some(ContentUri::Ipfs(path)) => path,
+        other => panic!("Unexpected {:?}", other),
+    };
+    assert_eq!(pin_path(root, &absolute), None);
+
+    assert_eq!(pin_path(root, "../etc/passwd"), None);
+    assert_eq!(pin_path(root, &format!("{}/../../etc/passwd", cid)), None);
+    assert_eq!(pin_path(root, ""), None);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use afterlife_backend::backend::queries::{claim_job, get_job, insert_job};
+use common::TestDatabase;
+use serde_json::json;
+
+#[tokio::test]
+async fn jobs_of_dead_workers_are_claimed_again_then_failed() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+    let job = insert_job(
+        &client,
+        "rarity_recompute",
+        &json!({ "kind": "rarity_recompute" }),
+    )
+    .await
+    .unwrap();
+
+    // A lease of 0s has expired by the next claim, as if its worker died
+    for _ in 0..2 {
+        let claimed = claim_job(&client, 0.0, 2).await.unwrap().unwrap();
+        assert_eq!(claimed.id, job.id);
+        assert_eq!(claimed.status, "running");
+    }
+    assert!(claim_job(&client, 0.0, 2).await.unwrap().is_none());
+    let failed = get_job(&client, job.id).await.unwrap().unwrap();
+    assert_eq!(failed.status, "failed");
+
+    // Jobs leased to a live worker are left to it
+    insert_job(
+        &client,
+        "rarity_recompute",
+        &json!({ "kind": "rarity_recompute" }),
+    )
+    .await
+    .unwrap();
+    assert!(claim_job(&client, 60.0, 2).await.unwrap().is_some());
+    assert!(claim_job(&client, 60.0, 2).await.unwrap().is_none());
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use afterlife_backend::backend::repository::FullCollection;
+use afterlife_backend::common::numeric::TokenId;
+use common::{collection, transfer, TestDatabase, ALICE, BOB, CAROL, ZERO};
+use std::collections::HashMap;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c12";
+
+#[tokio::test]
+async fn peak_balances_are_kept_after_selling() {
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("lifetime", CONTRACT, "erc1155");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc1155, ZERO, ALICE, 1, 3, 10),
+            transfer(&erc1155, ALICE, BOB, 1, 2, 11),
+            transfer(&erc1155, BOB, ALICE, 1, 1, 12),
+            // Sending to oneself doesn't raise the peak
+            transfer(&erc1155, BOB, BOB, 1, 1, 13),
+            transfer(&erc1155, ALICE, CAROL, 1, 2, 14),
+            transfer(&erc1155, ZERO, BOB, 2, 1, 20),
+            transfer(&erc1155, BOB, CAROL, 2, 1, 21),
+        ],
+    )
+    .await;
+
+    let client = db.client().await;
+    let peaks = queries::get_all_users_peak_collections(&client, &HashMap::new())
+        .await
+        .unwrap();
+    let peak = |peaks: &HashMap<String, FullCollection>, address: &str, token_id: u64| {
+        peaks
+            .get(address)
+            .and_then(|chains| chains.get("lifetime"))
+            .and_then(|contracts| contracts.get(CONTRACT))
+            .and_then(|tokens| tokens.get(&TokenId::from(token_id)))
+            .map(|balance| balance.to_string())
+    };
+    assert_eq!(peak(&peaks, ALICE, 1).as_deref(), Some("3"));
+    assert_eq!(peak(&peaks, BOB, 1).as_deref(), Some("2"));
+    assert_eq!(peak(&peaks, CAROL, 1).as_deref(), Some("2"));
+    assert_eq!(peak(&peaks, ZERO, 1), None);
+    assert_eq!(peak(&peaks, BOB, 2).as_deref(), Some("1"));
+    assert_eq!(peak(&peaks, CAROL, 2).as_deref(), Some("1"));
+
+    // Moving a token between the addresses of a user doesn't raise the user's peak
+    let usernames = HashMap::from([
+        (BOB.to_string(), "bob".to_string()),
+        (CAROL.to_string(), "bob".to_string()),
+    ]);
+    let peaks = queries::get_all_users_peak_collections(&client, &usernames)
+        .await
+        .unwrap();
+    let user_peaks: Vec<String> = [BOB, CAROL]
+        .iter()
+        .filter_map(|address| peak(&peaks, address, 2))
+        .collect();
+    assert_eq!(user_peaks, vec!["1".to_string()]);
+
+    let scores = |points: f64| HashMap::from([("alice".to_string(), points)]);
+    queries::store_lifetime_scores(&client, &scores(3000.0))
+        .await
+        .unwrap();
+    queries::store_lifetime_scores(&client, &scores(1000.0))
+        .await
+        .unwrap();
+    assert_eq!(
+        queries::get_lifetime_points(&client, "alice")
+            .await
+            .unwrap(),
+        Some(3000.0)
+    );
+    queries::store_lifetime_scores(&client, &scores(4000.0))
+        .await
+        .unwrap();
+    assert_eq!(
+        queries::get_lifetime_scores(&client).await.unwrap(),
+        scores(4000.0)
+    );
+}
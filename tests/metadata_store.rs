@@ -0,0 +1,57 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use common::{collection, get, transfer, TestDatabase, ALICE, ZERO};
+use eth_checksum::checksum;
+use serde_json::json;
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c15";
+
+#[tokio::test]
+async fn tokens_without_a_row_fall_back_to_their_file() {
+    let background =
+        |value: &str| json!({ "attributes": [{ "trait_type": "Background", "value": value }] });
+    let root = std::env::temp_dir().join(format!("afterlife-metadata-{}", std::process::id()));
+    let dir = root.join("partial").join(checksum(CONTRACT));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("1.json"), background("Blue").to_string()).unwrap();
+    std::fs::write(dir.join("2.json"), background("Blue").to_string()).unwrap();
+    std::env::set_var("AFTERLIFE_PATH_METADATA", &root);
+    std::env::set_var("AFTERLIFE_PATH_RARITIES", &root);
+
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("partial", CONTRACT, "erc721");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc721, ZERO, ALICE, 1, 1, 10),
+            transfer(&erc721, ZERO, ALICE, 2, 1, 11),
+        ],
+    )
+    .await;
+    // Only the first token has a row, and it wins over its file
+    queries::upsert_token_metadata(
+        &db.client().await,
+        "partial",
+        CONTRACT,
+        1.into(),
+        &background("Red"),
+    )
+    .await
+    .unwrap();
+
+    let (status, body) = get(&db.database, &format!("/partial/{}/traits", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["token_count"], 2);
+    assert_eq!(
+        body["traits"],
+        json!([{
+            "trait_type": "Background",
+            "values": [
+                { "value": "Blue", "count": 1, "frequency": 50.0 },
+                { "value": "Red", "count": 1, "frequency": 50.0 },
+            ],
+        }])
+    );
+}
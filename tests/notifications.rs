@@ -0,0 +1,217 @@
+mod common;
+
+use afterlife_backend::backend::queries::{
+    confirm_notification_email, get_notification_preferences, set_notification_preferences,
+    NotificationChannel, NotificationEvents, NotificationPreferences,
+};
+use afterlife_backend::indexer::deliveries::send_due;
+use afterlife_backend::indexer::notifications::{self, event_kind, sale_of, Channel, EventFilter};
+use afterlife_backend::indexer::queries::Sale;
+use afterlife_backend::indexer::webhooks::{notify_transfers, resolve_public_url};
+use common::{collection, contract, transfer, TestDatabase, ALICE, BOB, ZERO};
+use warp::Filter;
+use web3::types::U256;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c08";
+
+#[tokio::test]
+async fn saved_preferences_reach_the_indexer() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+
+    let webhook = NotificationPreferences {
+        channel: NotificationChannel::Webhook,
+        webhook_url: Some("https://example.com/hook".to_string()),
+        email: None,
+        events: NotificationEvents::Sales,
+        webhook_secret: Some("secret".to_string()),
+        ..Default::default()
+    };
+    set_notification_preferences(&client, "alice", &webhook)
+        .await
+        .unwrap();
+    set_notification_preferences(&client, "bob", &NotificationPreferences::default())
+        .await
+        .unwrap();
+
+    let saved = get_notification_preferences(&client, "alice")
+        .await
+        .unwrap();
+    assert_eq!(saved.channel, NotificationChannel::Webhook);
+    assert_eq!(
+        saved.webhook_url.as_deref(),
+        Some("https://example.com/hook")
+    );
+    assert_eq!(saved.email, None);
+    assert_eq!(saved.events, NotificationEvents::Sales);
+
+    // Users without a channel aren't loaded by the indexer
+    let preferences = notifications::get_notification_preferences(&client)
+        .await
+        .unwrap();
+    assert_eq!(preferences.len(), 1);
+    assert_eq!(preferences[0].username, "alice");
+    assert_eq!(preferences[0].events, EventFilter::Sales);
+    assert_eq!(
+        preferences[0].channel,
+        Channel::Webhook {
+            url: "https://example.com/hook".to_string(),
+            secret: "secret".to_string(),
+        }
+    );
+}
+
+#[tokio::test]
+async fn emails_are_only_notified_once_confirmed() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+
+    let email = NotificationPreferences {
+        channel: NotificationChannel::Email,
+        email: Some("alice@example.com".to_string()),
+        confirmation_token: Some("token".to_string()),
+        ..Default::default()
+    };
+    set_notification_preferences(&client, "alice", &email)
+        .await
+        .unwrap();
+    let preferences = notifications::get_notification_preferences(&client)
+        .await
+        .unwrap();
+    assert!(preferences.is_empty());
+
+    assert!(!confirm_notification_email(&client, "alice", "wrong")
+        .await
+        .unwrap());
+    assert!(confirm_notification_email(&client, "alice", "token")
+        .await
+        .unwrap());
+    let saved = get_notification_preferences(&client, "alice")
+        .await
+        .unwrap();
+    assert!(saved.email_confirmed);
+    assert_eq!(saved.confirmation_token, None);
+    let preferences = notifications::get_notification_preferences(&client)
+        .await
+        .unwrap();
+    assert_eq!(
+        preferences[0].channel,
+        Channel::Email("alice@example.com".to_string())
+    );
+}
+
+#[tokio::test]
+async fn webhook_urls_must_be_public_https() {
+    for url in [
+        "http://example.com/hook",
+        "https://127.0.0.1/hook",
+        "https://169.254.169.254/latest/meta-data",
+        "https://10.0.0.1/hook",
+        "https://[::1]/hook",
+        "https://[fd00:ec2::254]/hook",
+        "https://localhost/hook",
+    ] {
+        assert!(resolve_public_url(url).await.is_err(), "{} accepted", url);
+    }
+    assert!(resolve_public_url("https://93.184.215.14/hook")
+        .await
+        .is_ok());
+}
+
+#[test]
+fn transfers_are_classified_as_mints_sales_or_transfers() {
+    let erc721 = contract(CONTRACT, "erc721");
+    let mint = transfer(&erc721, ZERO, ALICE, 1, 1, 10);
+    let sold = transfer(&erc721, ALICE, BOB, 1, 1, 11);
+    let gift = transfer(&erc721, BOB, ALICE, 1, 1, 12);
+    let sale = Sale {
+        contract: erc721.clone(),
+        marketplace: "fixture".to_string(),
+        token_id: U256::from(1),
+        amount: U256::from(1),
+        seller: ALICE.to_string(),
+        buyer: BOB.to_string(),
+        price: U256::from(1_000),
+        currency: ZERO.to_string(),
+        block_number: 11,
+        transaction_hash: sold.transaction_hash.clone(),
+        log_index: 1,
+        block_timestamp: None,
+    };
+    let sales = vec![&sale];
+
+    assert_eq!(event_kind(&mint, sale_of(&mint, &sales)), "mint");
+    assert_eq!(event_kind(&sold, sale_of(&sold, &sales)), "sale");
+    assert_eq!(event_kind(&gift, sale_of(&gift, &sales)), "transfer");
+}
+
+#[tokio::test]
+async fn failed_webhook_deliveries_are_retried_later() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+    let (address, server) =
+        warp::serve(warp::post().map(|| "ok")).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    // Nothing listens on the discard port
+    let rows = client
+        .query(
+            "INSERT INTO webhooks (url, secret) VALUES ($1, 'secret'), ($2, 'secret') \
+            RETURNING id",
+            &[
+                &"http://127.0.0.1:9/hook",
+                &format!("http://{}/hook", address),
+            ],
+        )
+        .await
+        .unwrap();
+    let (down, up): (i32, i32) = (rows[0].get(0), rows[1].get(0));
+
+    let (chain, erc721) = collection("delivered", CONTRACT, "erc721");
+    let mut event = transfer(&erc721, ZERO, ALICE, 1, 1, 10);
+    event.block_timestamp = Some(u64::MAX / 2);
+    notify_transfers(&client, &chain, &[&event]).await;
+    // Seen again by the next run's look-back window
+    notify_transfers(&client, &chain, &[&event]).await;
+
+    assert_eq!(send_due(&client, None).await.unwrap(), 2);
+    let delivery = |webhook_id: i32| {
+        let client = &client;
+        async move {
+            client
+                .query_one(
+                    "SELECT attempts, payload IS NOT NULL AS pending, \
+                    next_attempt_at > now() AS scheduled, delivered_at IS NOT NULL AS delivered \
+                    FROM webhook_deliveries WHERE webhook_id = $1",
+                    &[&webhook_id],
+                )
+                .await
+                .unwrap()
+        }
+    };
+    let sent = delivery(up).await;
+    assert_eq!(sent.get::<_, i32>("attempts"), 1);
+    assert!(sent.get::<_, bool>("delivered"));
+    assert!(!sent.get::<_, bool>("pending"));
+    let failed = delivery(down).await;
+    assert_eq!(failed.get::<_, i32>("attempts"), 1);
+    assert!(failed.get::<_, bool>("scheduled"));
+    assert!(failed.get::<_, bool>("pending"));
+    // Not due yet
+    assert_eq!(send_due(&client, None).await.unwrap(), 0);
+
+    // A hook deactivated in between isn't sent to anymore
+    client
+        .execute("UPDATE webhooks SET active = false WHERE id = $1", &[&down])
+        .await
+        .unwrap();
+    client
+        .execute(
+            "UPDATE webhook_deliveries SET next_attempt_at = now() WHERE webhook_id = $1",
+            &[&down],
+        )
+        .await
+        .unwrap();
+    assert_eq!(send_due(&client, None).await.unwrap(), 1);
+    assert_eq!(send_due(&client, None).await.unwrap(), 0);
+    assert!(!delivery(down).await.get::<_, bool>("delivered"));
+}
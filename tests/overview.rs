@@ -0,0 +1,50 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use afterlife_backend::indexer::queries::record_rpc_failures;
+use common::{collection, transfer, TestDatabase, ALICE, BOB, ZERO};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c09";
+
+#[tokio::test]
+async fn overview_counts_recent_events_and_rpc_failures_per_chain() {
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("overview", CONTRACT, "erc721");
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut old = transfer(&erc721, ZERO, ALICE, 1, 1, 10);
+    old.block_timestamp = Some(now - 3 * 24 * 3600);
+    let mut recent = transfer(&erc721, ALICE, BOB, 1, 1, 11);
+    recent.block_timestamp = Some(now - 3600);
+    db.index(&chain, vec![old, recent]).await;
+
+    let client = db.client().await;
+    let activity = queries::get_chain_activity(&client).await.unwrap();
+    assert_eq!(activity.len(), 1);
+    assert_eq!(activity[0].chain, "overview");
+    assert_eq!(activity[0].events_24h, 1);
+
+    let failures = |count: u64, error: &str| {
+        vec![(
+            "https://rpc.example.com/v1/secret-key".to_string(),
+            count,
+            error.to_string(),
+        )]
+    };
+    record_rpc_failures(&chain, &failures(2, "timeout"), &client)
+        .await
+        .unwrap();
+    record_rpc_failures(&chain, &failures(3, "status 429"), &client)
+        .await
+        .unwrap();
+
+    let counts = queries::get_rpc_failure_counts(&client).await.unwrap();
+    assert_eq!(counts.len(), 1);
+    // Only the host is kept, URLs may carry API keys
+    assert_eq!(counts[0].host, "rpc.example.com");
+    assert_eq!(counts[0].failures_24h, 5);
+    assert_eq!(counts[0].last_error, "status 429");
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use afterlife_backend::marketplace::queries::{replace_listings, Listing};
+use afterlife_backend::prices::queries::store_price;
+use common::{collection, get, transfer, TestDatabase, ALICE, BOB, CAROL, ZERO};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000f10";
+const ERC721: &str = "0x0000000000000000000000000000000000000f11";
+
+// Users file read by the API from the environment, before the default project loads it.
+// Every test of the binary writes the same users.
+fn write_users(users: Value) {
+    let path = std::env::temp_dir().join(format!("afterlife-users-{}.json", std::process::id()));
+    std::fs::write(&path, users.to_string()).unwrap();
+    std::env::set_var("AFTERLIFE_FILE_USERS", &path);
+}
+
+fn listing(order_id: &str, token_id: u64, price: f64) -> Listing {
+    Listing {
+        order_id: order_id.to_string(),
+        token_id: token_id.into(),
+        maker: BOB.to_string(),
+        price,
+        currency: "FTM".to_string(),
+        source: None,
+        valid_until: None,
+    }
+}
+
+#[tokio::test]
+async fn values_holdings_at_token_then_collection_floor() {
+    write_users(json!({ "alice": [ALICE], "carol": [CAROL] }));
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("valued", CONTRACT, "erc1155");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc1155, ZERO, ALICE, 1, 2, 10),
+            transfer(&erc1155, ZERO, ALICE, 2, 1, 11),
+            transfer(&erc1155, ZERO, BOB, 3, 1, 12),
+        ],
+    )
+    .await;
+    let client = db.client().await;
+    let contract_id: i32 = client
+        .query_one(
+            "SELECT id FROM contracts WHERE LOWER(address) = $1",
+            &[&CONTRACT.to_lowercase()],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    // Token 1 has its own floor, token 2 falls back to the collection's
+    replace_listings(
+        &client,
+        contract_id,
+        &[listing("a", 1, 2.0), listing("b", 3, 0.5)],
+    )
+    .await
+    .unwrap();
+    store_price(&client, "valued", "fantom", 2.0).await.unwrap();
+
+    let (status, body) = get(&db.database, "/user/value/alice").await;
+    assert_eq!(status, StatusCode::OK);
+    let collection = &body["collections"][0];
+    assert_eq!(collection["contract_name"], "Fixture erc1155");
+    assert_eq!(collection["token_count"], json!(3.0));
+    assert_eq!(collection["floor_price"], json!(0.5));
+    assert_eq!(collection["value"], json!(4.5));
+    assert_eq!(body["chains"][0]["chain"], "valued");
+    assert_eq!(body["chains"][0]["value"], json!(4.5));
+    assert_eq!(collection["value_usd"], json!(9.0));
+    assert_eq!(body["chains"][0]["usd_price"], json!(2.0));
+    assert_eq!(body["total_usd"], json!(9.0));
+    assert!(body["prices_at"].is_number());
+    assert!(body["computed_at"].is_number());
+
+    let (status, _) = get(&db.database, "/user/value/nobody").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn erc721_tokens_are_valued_at_the_collection_floor() {
+    write_users(json!({ "alice": [ALICE], "carol": [CAROL] }));
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("valued721", ERC721, "erc721");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc721, ZERO, CAROL, 1, 1, 10),
+            transfer(&erc721, ZERO, BOB, 2, 1, 11),
+        ],
+    )
+    .await;
+    let client = db.client().await;
+    let contract_id: i32 = client
+        .query_one(
+            "SELECT id FROM contracts WHERE LOWER(address) = $1",
+            &[&ERC721.to_lowercase()],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    // Carol's own ask for her token doesn't value it
+    replace_listings(
+        &client,
+        contract_id,
+        &[listing("a", 1, 100.0), listing("b", 2, 1.5)],
+    )
+    .await
+    .unwrap();
+
+    let (status, body) = get(&db.database, "/user/value/carol").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["collections"][0]["value"], json!(1.5));
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{chain, contract, get, transfer, TestDatabase, ALICE, ZERO};
+use eth_checksum::checksum;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use warp::http::StatusCode;
+
+const COMPLETE: &str = "0x0000000000000000000000000000000000000c21";
+const BARE: &str = "0x0000000000000000000000000000000000000c22";
+
+// Metadata and rarities of COMPLETE only, read by the metadata store from the environment
+fn write_contract_data() {
+    let root: PathBuf =
+        std::env::temp_dir().join(format!("afterlife-preflight-{}", std::process::id()));
+    let dir = root.join("checked").join(checksum(COMPLETE));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("1.json"), "{}").unwrap();
+    std::fs::write(
+        root.join(format!("checked_{}_rarity.json", checksum(COMPLETE))),
+        r#"{"rarities": []}"#,
+    )
+    .unwrap();
+    std::env::set_var("AFTERLIFE_PATH_METADATA", &root);
+    std::env::set_var("AFTERLIFE_PATH_RARITIES", &root);
+}
+
+#[tokio::test]
+async fn contracts_without_metadata_or_rarities_are_reported() {
+    write_contract_data();
+    let db = TestDatabase::start().await;
+    let complete = contract(COMPLETE, "erc721");
+    let bare = contract(BARE, "erc1155");
+    let chain = chain("checked", "", vec![complete.clone(), bare.clone()]);
+    db.index(
+        &chain,
+        vec![
+            transfer(&complete, ZERO, ALICE, 1, 1, 10),
+            transfer(&bare, ZERO, ALICE, 1, 1, 11),
+        ],
+    )
+    .await;
+
+    let (status, body) = get(&db.database, "/status/data").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["contracts_checked"], 2);
+    let addresses = |contracts: &Value| -> Vec<String> {
+        contracts
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["contract_address"].as_str().unwrap().to_lowercase())
+            .collect()
+    };
+    assert_eq!(addresses(&body["missing_metadata"]), vec![BARE.to_string()]);
+    assert_eq!(addresses(&body["missing_rarity"]), vec![BARE.to_string()]);
+    assert_eq!(body["missing_rarity"][0]["chain"], json!("checked"));
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use afterlife_backend::backend::queries::get_native_prices_at;
+use afterlife_backend::prices::queries::store_price;
+use common::{get, TestDatabase};
+use serde_json::json;
+use warp::http::StatusCode;
+
+#[tokio::test]
+async fn prices_lists_the_latest_price_of_each_chain() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+    store_price(&client, "Ethereum", "ethereum", 3000.0)
+        .await
+        .unwrap();
+    store_price(&client, "polygon", "matic-network", 0.5)
+        .await
+        .unwrap();
+    store_price(&client, "ethereum", "ethereum", 3100.0)
+        .await
+        .unwrap();
+
+    let (status, body) = get(&db.database, "/prices").await;
+    assert_eq!(status, StatusCode::OK);
+    let prices = body["prices"].as_array().unwrap();
+    assert_eq!(prices.len(), 2);
+    assert_eq!(prices[0]["chain"], "ethereum");
+    assert_eq!(prices[0]["usd"], json!(3100.0));
+    assert_eq!(prices[1]["coin"], "matic-network");
+    assert!(prices[1]["fetched_at"].is_number());
+}
+
+#[tokio::test]
+async fn sales_are_valued_at_the_price_of_their_day() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+    client
+        .batch_execute(
+            "INSERT INTO native_price_history (chain, day, usd) VALUES \
+            ('history', '2024-01-10', 2000), ('history', '2024-01-15', 2500)",
+        )
+        .await
+        .unwrap();
+
+    // 2024-01-15T12:00:00Z, 2024-01-12T00:00:00Z, 2024-01-01T00:00:00Z
+    let prices = get_native_prices_at(
+        &client,
+        "History",
+        &[1_705_320_000, 1_705_017_600, 1_704_067_200],
+    )
+    .await
+    .unwrap();
+    assert_eq!(prices.get(&1_705_320_000), Some(&2500.0));
+    // No price that day, the last one of the week before is used
+    assert_eq!(prices.get(&1_705_017_600), Some(&2000.0));
+    // Nothing recent enough
+    assert_eq!(prices.get(&1_704_067_200), None);
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use afterlife_backend::backend::rarity_models::{self, Traits};
+use afterlife_backend::common::numeric::TokenId;
+use afterlife_backend::indexer::indexer_config::IndexerConfig;
+use common::{chain, contract};
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c51";
+
+fn token(id: u64, traits: &[(&str, &str)]) -> (TokenId, Traits) {
+    let traits = traits
+        .iter()
+        .map(|(trait_type, value)| (trait_type.to_string(), value.to_string()))
+        .collect();
+    (id.into(), traits)
+}
+
+// Three blue tokens and a red one, the last blue one also wearing a hat
+fn collection() -> Vec<(TokenId, Traits)> {
+    vec![
+        token(1, &[("Background", "Blue")]),
+        token(2, &[("Background", "Blue")]),
+        token(3, &[("Background", "Blue"), ("Hat", "Crown")]),
+        token(4, &[("Background", "Red")]),
+    ]
+}
+
+fn scores(name: &str) -> Vec<f64> {
+    rarity_models::model(name).unwrap().score(&collection())
+}
+
+fn assert_close(actual: &[f64], expected: &[f64]) {
+    assert_eq!(actual.len(), expected.len());
+    for (actual, expected) in actual.iter().zip(expected) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+}
+
+#[test]
+fn trait_sum_splits_a_point_per_value() {
+    // Blue is shared by 3, Red by 1, None by 3 and Crown by 1
+    let third = 1.0 / 3.0;
+    assert_close(
+        &scores("trait_sum"),
+        &[2.0 * third, 2.0 * third, third + 1.0, 1.0 + third],
+    );
+}
+
+#[test]
+fn openrarity_divides_information_by_entropy() {
+    let scores = scores("openrarity");
+    // Tokens 1 and 2 are alike, 3 and 4 each have a value no other token has
+    assert_close(&[scores[0]], &[scores[1]]);
+    assert!(scores[2] > scores[0] && scores[3] > scores[0]);
+    // The hat also sets token 3 apart by its trait count
+    assert!(scores[2] > scores[3]);
+
+    let same = vec![token(1, &[("Background", "Blue")]); 3];
+    let model = rarity_models::model("openrarity").unwrap();
+    assert_close(&model.score(&same), &[0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn jaccard_averages_the_distance_to_the_other_tokens() {
+    // Token 3 shares half its values with 1 and 2, token 4 shares none
+    assert_close(
+        &scores("jaccard"),
+        &[
+            (0.0 + 0.5 + 1.0) / 3.0,
+            (0.0 + 0.5 + 1.0) / 3.0,
+            (0.5 + 0.5 + 1.0) / 3.0,
+            1.0,
+        ],
+    );
+    let model = rarity_models::model("jaccard").unwrap();
+    assert_close(&model.score(&collection()[..1]), &[0.0]);
+}
+
+#[test]
+fn models_are_found_by_name_in_any_case() {
+    for name in rarity_models::model_names() {
+        let model = rarity_models::model(&name.to_uppercase()).unwrap();
+        assert_eq!(model.name(), name);
+    }
+    assert!(rarity_models::model("unknown").is_none());
+}
+
+#[test]
+fn unknown_rarity_models_are_rejected_at_load() {
+    let mut erc721 = contract(CONTRACT, "erc721");
+    erc721.rarity_model = Some("OpenRarity".to_string());
+    let mut config = IndexerConfig {
+        chains: vec![chain("rated", "", vec![erc721])],
+    };
+    config.validate().unwrap();
+
+    config.chains[0].contracts[0].rarity_model = Some("open_rarity".to_string());
+    assert!(config.validate().unwrap_err().contains("open_rarity"));
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use afterlife_backend::backend::api::repository_routes;
+use afterlife_backend::backend::repository::{
+    InMemoryContract, InMemoryRepository, InMemoryTransfer,
+};
+use common::{ALICE, BOB, CAROL, DEAD, ZERO};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c13";
+
+fn transfer(from: &str, to: &str, token_id: u64, value: &str, block: i32) -> InMemoryTransfer {
+    InMemoryTransfer {
+        chain_name: "memory".to_string(),
+        contract_address: CONTRACT.to_string(),
+        from_address: from.to_string(),
+        to_address: to.to_string(),
+        token_id: token_id.into(),
+        value: value.parse().unwrap(),
+        block_number: block,
+    }
+}
+
+fn repository() -> Arc<InMemoryRepository> {
+    let repository = InMemoryRepository::new();
+    repository.add_contract(InMemoryContract {
+        chain_name: "memory".to_string(),
+        address: CONTRACT.to_string(),
+        verified: true,
+        burn_addresses: Vec::new(),
+        last_processed_block: 20,
+    });
+    for transfer in [
+        transfer(ZERO, ALICE, 1, "1", 10),
+        transfer(ALICE, BOB, 1, "1", 11),
+        transfer(ZERO, ALICE, 2, "3", 12),
+        transfer(ALICE, DEAD, 2, "1", 13),
+        // CAROL's incoming transfer is missing
+        transfer(CAROL, BOB, 3, "1", 14),
+    ] {
+        repository.add_transfer(transfer);
+    }
+    Arc::new(repository)
+}
+
+async fn get(repository: &Arc<InMemoryRepository>, path: &str) -> (StatusCode, Value) {
+    let response = warp::test::request()
+        .method("GET")
+        .path(path)
+        .reply(&repository_routes(repository.clone()))
+        .await;
+    let body = serde_json::from_slice(response.body()).unwrap_or(Value::Null);
+    (response.status(), body)
+}
+
+#[tokio::test]
+async fn owners_are_served_from_the_repository() {
+    let repository = repository();
+
+    let (status, owners) = get(&repository, &format!("/memory/{}/owners/1", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(owners, json!([BOB]));
+
+    let (_, owners) = get(
+        &repository,
+        &format!("/memory/{}/owners/1?block=10", CONTRACT),
+    )
+    .await;
+    assert_eq!(owners, json!([ALICE]));
+
+    // Burned tokens have no owner
+    let (_, owners) = get(&repository, &format!("/memory/{}/owners/2", CONTRACT)).await;
+    assert_eq!(owners, json!([ALICE]));
+
+    let (status, _) = get(
+        &repository,
+        &format!("/memory/{}/owners/1?block=21", CONTRACT),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn full_collections_never_hold_negative_balances() {
+    let repository = repository();
+
+    let (status, collection) = get(&repository, &format!("/fullcollection/{}", ALICE)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(collection, json!({ "memory": { CONTRACT: { "2": 2 } } }));
+
+    let (status, collection) = get(&repository, &format!("/fullcollection/{}", CAROL)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(collection, json!({}));
+
+    let (status, body) = get(&repository, "/fullcollection/nobody").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+}
+
+#[tokio::test]
+async fn hidden_wallets_have_no_full_collection() {
+    let repository = repository();
+    repository.hide_address(ALICE);
+
+    let (status, body) = get(&repository, &format!("/fullcollection/{}", ALICE)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+
+    let (status, _) = get(&repository, &format!("/fullcollection/{}", BOB)).await;
+    assert_eq!(status, StatusCode::OK);
+}
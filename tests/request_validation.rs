@@ -0,0 +1,71 @@
+mod common;
+
+use common::{admin_post, post, TestDatabase};
+use warp::http::StatusCode;
+
+#[tokio::test]
+async fn bodies_are_limited_and_their_bad_fields_named() {
+    let db = TestDatabase::start().await;
+
+    // Bodies that don't fit keep the bad_request code, with the field at fault when serde
+    // was at one
+    let (status, body) = post(&db.database, "/get-username", r#"{"address": 5}"#).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "bad_request");
+    assert_eq!(body["field"], "address");
+
+    let (status, body) = post(&db.database, "/get-username", "{}").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "bad_request");
+    assert!(body["message"].as_str().unwrap().contains("address"));
+
+    // Unknown fields are ignored
+    let (status, _) = post(
+        &db.database,
+        "/get-username",
+        r#"{"address": "0x0000000000000000000000000000000000000001", "extra": 1}"#,
+    )
+    .await;
+    assert_ne!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = post(&db.database, "/get-username", r#"{"address": "nope"}"#).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_field");
+    assert_eq!(body["field"], "address");
+
+    let (status, body) = post(&db.database, "/get-username", "[").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "bad_request");
+    assert!(body.get("field").is_none());
+
+    let padding = "x".repeat(4096);
+    let oversized = format!(r#"{{"address": "{}"}}"#, padding);
+    let (status, body) = post(&db.database, "/get-username", &oversized).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["code"], "payload_too_large");
+}
+
+#[tokio::test]
+async fn admin_bodies_are_validated_like_the_others() {
+    let db = TestDatabase::start().await;
+
+    let (status, body) =
+        admin_post(&db.database, "/admin/webhooks", r#"{"url": "not a url"}"#).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["field"], "url");
+
+    // Mixed case that isn't the checksum
+    let (status, body) = admin_post(
+        &db.database,
+        "/admin/webhooks",
+        r#"{"url": "https://example.com/hook", "username": "0xAbcdef0000000000000000000000000000000001"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["field"], "username");
+
+    let (status, body) =
+        admin_post(&db.database, "/admin/exclusions", r#"{"value": "0x12ab"}"#).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["field"], "value");
+}
@@ -0,0 +1,59 @@
+use afterlife_backend::indexer::indexer_config::{Chain, IndexerConfig};
+use afterlife_backend::indexer::rpc_pool::{redact_url, RpcPool};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+#[test]
+fn failure_messages_only_keep_the_host_of_the_endpoint() {
+    let url = "https://rpc.example.com/v1/secret-key";
+    let message =
+        "error sending request for url (https://rpc.example.com/v1/secret-key): timed out";
+    assert_eq!(
+        redact_url(message, url),
+        "error sending request for url (rpc.example.com): timed out"
+    );
+
+    // Normalized by the HTTP client
+    let url = "https://rpc.example.com?key=secret";
+    let message = "error sending request for url (https://rpc.example.com/?key=secret)";
+    assert_eq!(
+        redact_url(message, url),
+        "error sending request for url (rpc.example.com)"
+    );
+}
+
+#[tokio::test]
+async fn the_rate_limit_holds_per_endpoint_across_pools() {
+    let urls = vec!["http://rate-limited.invalid".to_string()];
+    let first = RpcPool::new(&urls, 4, Some(20.0)).unwrap();
+    let second = RpcPool::new(&urls, 4, Some(20.0)).unwrap();
+
+    let start = Instant::now();
+    for _ in 0..2 {
+        drop(first.pick().await);
+        drop(second.pick().await);
+    }
+    // The first request starts at once, then one every 50ms
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn invalid_request_rates_are_rejected_at_load() {
+    for per_second in [0.0, -1.0, f64::NAN] {
+        let mut chain: Chain = serde_json::from_value(json!({
+            "id": 1,
+            "name": "limited",
+            "rpc_url": "",
+            "contracts": [],
+        }))
+        .unwrap();
+        chain.requests_per_second = Some(per_second);
+        let config = IndexerConfig {
+            chains: vec![chain],
+        };
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .contains("requests_per_second"));
+    }
+}
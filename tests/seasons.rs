@@ -0,0 +1,85 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use common::{get, TestDatabase};
+use serde_json::json;
+use warp::http::StatusCode;
+
+// Seasons file read by the API from the environment
+fn write_seasons(contents: &str) {
+    let path = std::env::temp_dir().join(format!("afterlife-seasons-{}.yaml", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    std::env::set_var("AFTERLIFE_PATH_SEASONS", &path);
+}
+
+#[tokio::test]
+async fn ended_seasons_serve_their_recorded_standings() {
+    write_seasons(
+        "- number: 1\n  name: Genesis\n  start: 2020-01-01\n  end: 2020-02-01T12:00:00Z\n\
+         - number: 2\n  start: 2100-01-01\n  end: 2100-02-01\n\
+         - number: 3\n  start: 2020-03-01\n  end: 2020-04-01\n",
+    );
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+
+    client
+        .batch_execute(
+            "INSERT INTO score_history (name, points, rank, recorded_at) VALUES \
+            ('alice', 100, 1, '2019-12-31T23:00:00Z'), \
+            ('alice', 150, 2, '2020-02-01T11:00:00Z'), \
+            ('bob', 400, 1, '2020-02-01T11:00:00Z'), \
+            ('alice', 150, 1, '2020-03-01T00:30:00Z')",
+        )
+        .await
+        .unwrap();
+    let start = queries::get_score_snapshot_at(&client, 1_577_836_800, 7200)
+        .await
+        .unwrap();
+    assert_eq!(start.get("alice"), Some(&(100.0, 1)));
+    assert_eq!(start.get("bob"), None);
+    // Nothing that close
+    assert!(queries::get_score_snapshot_at(&client, 1_577_836_800, 60)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let standings = vec![
+        ("bob".to_string(), 400.0, 1),
+        ("alice".to_string(), 50.0, 2),
+    ];
+    assert!(queries::store_season_results(&client, 1, &standings)
+        .await
+        .unwrap());
+    // Final standings are only written once
+    assert!(
+        !queries::store_season_results(&client, 1, &[("carol".to_string(), 1.0, 1)])
+            .await
+            .unwrap()
+    );
+
+    let (status, body) = get(&db.database, "/leaderboard/season/1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "final");
+    assert_eq!(body["name"], "Genesis");
+    assert_eq!(body["start"], 1_577_836_800);
+    assert_eq!(body["end"], 1_580_558_400);
+    assert_eq!(
+        body["standings"],
+        json!([
+            { "name": "bob", "points": 400.0, "rank": 1 },
+            { "name": "alice", "points": 50.0, "rank": 2 },
+        ])
+    );
+
+    let (status, body) = get(&db.database, "/leaderboard/season/2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "upcoming");
+    assert_eq!(body["standings"], json!([]));
+
+    // No snapshot around the end of season 3, its standings can't be told
+    let (status, _) = get(&db.database, "/leaderboard/season/3").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let (status, _) = get(&db.database, "/leaderboard/season/4").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
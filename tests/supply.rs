@@ -0,0 +1,53 @@
+mod common;
+
+use common::{collection, get, transfer, TestDatabase, ALICE, BOB, DEAD, ZERO};
+use serde_json::json;
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c10";
+
+#[tokio::test]
+async fn supply_history_sums_mints_and_burns_per_bucket() {
+    let db = TestDatabase::start().await;
+    let (chain, erc1155) = collection("supply", CONTRACT, "erc1155");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc1155, ZERO, ALICE, 1, 5, 10),
+            transfer(&erc1155, ZERO, BOB, 2, 3, 20),
+            // Neither a mint nor a burn
+            transfer(&erc1155, ALICE, BOB, 1, 1, 30),
+            transfer(&erc1155, ALICE, DEAD, 1, 2, 1500),
+            transfer(&erc1155, BOB, ZERO, 2, 3, 2500),
+        ],
+    )
+    .await;
+
+    let (status, body) = get(
+        &db.database,
+        &format!("/supply/{}/supply/history", CONTRACT),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let history: Vec<_> = body["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|bucket| {
+            json!([
+                bucket["start_block"],
+                bucket["minted"],
+                bucket["burned"],
+                bucket["supply"]
+            ])
+        })
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            json!([0, "8", "0", "8"]),
+            json!([1000, "0", "2", "6"]),
+            json!([2000, "0", "3", "3"]),
+        ]
+    );
+}
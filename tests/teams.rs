@@ -0,0 +1,69 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use common::{get, TestDatabase};
+use warp::http::StatusCode;
+
+#[tokio::test]
+async fn users_are_in_one_team_and_empty_self_service_teams_go() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+
+    let guild = queries::create_team(&client, "Ghouls", false, None)
+        .await
+        .unwrap()
+        .unwrap();
+    // Names are unique regardless of case
+    assert_eq!(
+        queries::create_team(&client, "ghouls", true, Some("bob"))
+            .await
+            .unwrap(),
+        None
+    );
+    let squad = queries::create_team(&client, "Wraiths", true, Some("bob"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(queries::add_team_member(&client, guild, "alice")
+        .await
+        .unwrap());
+    assert!(queries::add_team_member(&client, squad, "bob")
+        .await
+        .unwrap());
+    assert!(!queries::add_team_member(&client, squad, "alice")
+        .await
+        .unwrap());
+
+    let team = queries::get_user_team(&client, "alice")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(team.name, "Ghouls");
+    assert_eq!(team.members, vec!["alice".to_string()]);
+
+    let (status, body) = get(&db.database, "/teams").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["teams"].as_array().unwrap().len(), 2);
+
+    // The self-service team goes with its last member, the admin one stays
+    assert!(queries::remove_team_member(&client, squad, "bob")
+        .await
+        .unwrap());
+    assert!(!queries::remove_team_member(&client, squad, "bob")
+        .await
+        .unwrap());
+    assert!(queries::get_team(&client, squad).await.unwrap().is_none());
+    assert!(queries::remove_team_member(&client, guild, "alice")
+        .await
+        .unwrap());
+    let team = queries::get_team(&client, guild).await.unwrap().unwrap();
+    assert!(team.members.is_empty());
+
+    // Past the bound, ?top= is the bound
+    let (status, body) = get(&db.database, "/leaderboard/teams?top=1000000").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["top"], 100);
+    let (_, body) = get(&db.database, "/leaderboard/teams?top=0").await;
+    assert!(body["top"].is_null());
+}
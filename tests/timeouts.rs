@@ -0,0 +1,47 @@
+use afterlife_backend::backend::errors::rejection_to_error;
+use afterlife_backend::backend::timeouts::{detached_within, within};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use warp::http::StatusCode;
+
+#[tokio::test]
+async fn handlers_over_their_timeout_answer_504() {
+    let rejection = within(
+        Duration::from_millis(50),
+        std::future::pending::<Result<(), _>>(),
+    )
+    .await
+    .unwrap_err();
+    let (status, error) = rejection_to_error(&rejection);
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(error.code, "timeout");
+
+    // Handlers answering in time are left alone
+    let result = within(Duration::from_secs(5), async {
+        Ok::<_, warp::Rejection>(42)
+    })
+    .await;
+    assert_eq!(result.unwrap(), 42);
+}
+
+#[tokio::test]
+async fn writes_over_their_timeout_still_finish() {
+    let finished = Arc::new(AtomicBool::new(false));
+    let handler = {
+        let finished = finished.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            finished.store(true, Ordering::SeqCst);
+            Ok::<_, warp::Rejection>(())
+        }
+    };
+    let rejection = detached_within(Duration::from_millis(50), handler)
+        .await
+        .unwrap_err();
+    let (status, _) = rejection_to_error(&rejection);
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(finished.load(Ordering::SeqCst));
+}
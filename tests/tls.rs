@@ -0,0 +1,27 @@
+use afterlife_backend::backend::tls::redirect_to_https;
+use warp::http::StatusCode;
+
+#[tokio::test]
+async fn plain_http_is_redirected_to_the_same_url_over_https() {
+    let response = warp::test::request()
+        .path("/leaderboard?metric=lifetime")
+        .header("host", "api.example.com:80")
+        .reply(&redirect_to_https(443))
+        .await;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers()["location"],
+        "https://api.example.com/leaderboard?metric=lifetime"
+    );
+
+    // A non-standard HTTPS port is kept in the redirect
+    let response = warp::test::request()
+        .path("/user/level/alice")
+        .header("host", "api.example.com")
+        .reply(&redirect_to_https(8443))
+        .await;
+    assert_eq!(
+        response.headers()["location"],
+        "https://api.example.com:8443/user/level/alice"
+    );
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{collection, get, transfer, TestDatabase, ALICE, DEAD, ZERO};
+use eth_checksum::checksum;
+use serde_json::json;
+use std::path::PathBuf;
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c07";
+
+// Metadata files of the fixture tokens, read by the metadata store from the environment
+fn write_metadata(tokens: &[(u64, serde_json::Value)]) {
+    let root: PathBuf =
+        std::env::temp_dir().join(format!("afterlife-traits-{}", std::process::id()));
+    let dir = root.join("traits").join(checksum(CONTRACT));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (token_id, metadata) in tokens {
+        std::fs::write(dir.join(format!("{}.json", token_id)), metadata.to_string()).unwrap();
+    }
+    std::env::set_var("AFTERLIFE_PATH_METADATA", &root);
+    std::env::set_var("AFTERLIFE_PATH_RARITIES", &root);
+}
+
+#[tokio::test]
+async fn traits_count_the_tokens_in_circulation() {
+    let attributes = |background: &str, hat: Option<&str>| {
+        let mut attributes = vec![json!({ "trait_type": "Background", "value": background })];
+        if let Some(hat) = hat {
+            attributes.push(json!({ "trait_type": "Hat", "value": hat }));
+        }
+        json!({ "attributes": attributes })
+    };
+    write_metadata(&[
+        (1, attributes("Blue", Some("Cap"))),
+        (2, attributes("Blue", None)),
+        (3, attributes("Red", Some("Crown"))),
+        (4, attributes("Red", Some("Crown"))),
+    ]);
+
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("traits", CONTRACT, "erc721");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc721, ZERO, ALICE, 1, 1, 10),
+            transfer(&erc721, ZERO, ALICE, 2, 1, 11),
+            transfer(&erc721, ZERO, ALICE, 3, 1, 12),
+            transfer(&erc721, ZERO, ALICE, 4, 1, 13),
+            // Burned, its traits don't count
+            transfer(&erc721, ALICE, DEAD, 4, 1, 14),
+        ],
+    )
+    .await;
+
+    let (status, body) = get(&db.database, &format!("/traits/{}/traits", CONTRACT)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["token_count"], 3);
+    assert_eq!(
+        body["traits"],
+        json!([
+            {
+                "trait_type": "Background",
+                "values": [
+                    { "value": "Blue", "count": 2, "frequency": 2.0 * 100.0 / 3.0 },
+                    { "value": "Red", "count": 1, "frequency": 100.0 / 3.0 },
+                ],
+            },
+            {
+                "trait_type": "Hat",
+                "values": [
+                    { "value": "Cap", "count": 1, "frequency": 100.0 / 3.0 },
+                    { "value": "Crown", "count": 1, "frequency": 100.0 / 3.0 },
+                ],
+            },
+        ])
+    );
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use afterlife_backend::indexer::queries::write_events_for_chain;
+use common::{collection, transfer, TestDatabase, ALICE, ZERO};
+use std::collections::HashMap;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c13";
+
+#[tokio::test]
+async fn ranges_with_undecodable_logs_are_not_written() {
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("undecodable", CONTRACT, "erc721");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc721, ZERO, ALICE, 1, 1, 10),
+            transfer(&erc721, ZERO, ALICE, 2, 1, 11),
+        ],
+    )
+    .await;
+
+    // Every log of the range failed to decode, as with a wrong abi_variant
+    let mut client = db.client().await;
+    let undecodable = HashMap::from([(CONTRACT.to_string(), 2)]);
+    write_events_for_chain(
+        &chain,
+        &HashMap::new(),
+        &HashMap::new(),
+        &HashMap::new(),
+        &HashMap::new(),
+        &undecodable,
+        0,
+        20,
+        20,
+        &mut client,
+    )
+    .await
+    .unwrap();
+
+    let row = client
+        .query_one(
+            "SELECT COUNT(*), MAX(c.last_processed_block) FROM events e \
+            JOIN contracts c ON c.id = e.contract_id WHERE LOWER(c.address) = $1",
+            &[&CONTRACT],
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, i64>(0), 2);
+    assert_eq!(row.get::<_, Option<i32>>(1), Some(11));
+}
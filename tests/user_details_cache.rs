@@ -0,0 +1,73 @@
+mod common;
+
+use afterlife_backend::backend::queries;
+use common::{get, TestDatabase};
+use serde_json::json;
+use warp::http::StatusCode;
+
+#[tokio::test]
+async fn warmed_documents_are_served_with_their_freshness() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+
+    let document = json!({ "username": "ghost", "afterlifepoints": 1200.0 });
+    queries::store_user_details_documents(&client, &[("ghost".to_string(), document)])
+        .await
+        .unwrap();
+
+    let (status, body) = get(&db.database, "/user/level/ghost").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["afterlifepoints"], json!(1200.0));
+    assert!(body["computed_at"].as_i64().unwrap() > 0);
+
+    // Progress reads the same document
+    let (status, body) = get(&db.database, "/user/level/ghost/progress").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "ghost");
+
+    // Users no longer registered lose theirs
+    assert_eq!(
+        queries::prune_user_details_documents(&client, &["alice".to_string()])
+            .await
+            .unwrap(),
+        1
+    );
+    assert!(queries::get_user_details_document(&client, "ghost", 60.0)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn documents_of_changed_users_are_dropped() {
+    let db = TestDatabase::start().await;
+    let client = db.client().await;
+
+    let documents: Vec<(String, serde_json::Value)> = ["ghost", "shade"]
+        .iter()
+        .map(|username| (username.to_string(), json!({ "username": username })))
+        .collect();
+    queries::store_user_details_documents(&client, &documents)
+        .await
+        .unwrap();
+
+    queries::delete_user_details_documents(&client, &["ghost".to_string()])
+        .await
+        .unwrap();
+    assert!(queries::get_user_details_document(&client, "ghost", 60.0)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(queries::get_user_details_document(&client, "shade", 60.0)
+        .await
+        .unwrap()
+        .is_some());
+
+    queries::delete_all_user_details_documents(&client)
+        .await
+        .unwrap();
+    assert!(queries::get_user_details_document(&client, "shade", 60.0)
+        .await
+        .unwrap()
+        .is_none());
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use afterlife_backend::backend::queries::{self, Avatar};
+use common::{collection, get, transfer, TestDatabase, ALICE, BOB, CAROL, ZERO};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c61";
+
+// Users file read by the API from the environment, before the default project loads it
+fn write_users(users: Value) {
+    let path = std::env::temp_dir().join(format!("afterlife-users-{}.json", std::process::id()));
+    std::fs::write(&path, users.to_string()).unwrap();
+    std::env::set_var("AFTERLIFE_FILE_USERS", &path);
+}
+
+#[tokio::test]
+async fn search_puts_prefix_matches_first() {
+    write_users(json!({
+        "jordan": [CAROL],
+        "Daniel": [BOB],
+        "dan": [ALICE],
+        "eve": [],
+    }));
+    let db = TestDatabase::start().await;
+
+    let (status, body) = get(&db.database, "/users/search?q=DAN").await;
+    assert_eq!(status, StatusCode::OK);
+    let usernames: Vec<&str> = body["users"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["username"].as_str().unwrap())
+        .collect();
+    assert_eq!(usernames, vec!["dan", "Daniel", "jordan"]);
+    assert!(body["users"][0]["level"].is_number());
+    assert_eq!(body["users"][0]["avatar"], Value::Null);
+
+    let (_, body) = get(&db.database, "/users/search?q=dan&limit=1").await;
+    assert_eq!(body["users"].as_array().unwrap().len(), 1);
+
+    let (status, _) = get(&db.database, "/users/search?q=%20").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn search_results_show_the_avatars_still_held() {
+    write_users(json!({
+        "jordan": [CAROL],
+        "Daniel": [BOB],
+        "dan": [ALICE],
+        "eve": [],
+    }));
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("searched", CONTRACT, "erc721");
+    db.index(
+        &chain,
+        vec![
+            transfer(&erc721, ZERO, ALICE, 1, 1, 10),
+            transfer(&erc721, ZERO, BOB, 2, 1, 11),
+            transfer(&erc721, BOB, CAROL, 2, 1, 12),
+        ],
+    )
+    .await;
+    let client = db.client().await;
+    // dan still holds their token, Daniel has since sent theirs to jordan
+    for (username, token_id) in [("dan", 1), ("Daniel", 2)] {
+        let avatar = Avatar {
+            chain: "searched".to_string(),
+            contract_address: CONTRACT.to_string(),
+            token_id: token_id.into(),
+        };
+        queries::set_avatar(&client, username, Some(&avatar))
+            .await
+            .unwrap();
+    }
+
+    let (status, body) = get(&db.database, "/users/search?q=dan").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["users"][0]["username"], "dan");
+    assert_eq!(body["users"][0]["avatar"]["token_id"], "1");
+    assert_eq!(body["users"][1]["username"], "Daniel");
+    assert_eq!(body["users"][1]["avatar"], Value::Null);
+    assert_eq!(body["users"][2]["avatar"], Value::Null);
+}
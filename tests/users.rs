@@ -0,0 +1,59 @@
+mod common;
+
+use afterlife_backend::backend::queries::{self, Avatar};
+use common::{collection, get, transfer, TestDatabase, ALICE, BOB, CAROL, ZERO};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000c62";
+
+// Users file read by the API from the environment, before the default project loads it
+fn write_users(users: Value) {
+    let path = std::env::temp_dir().join(format!("afterlife-users-{}.json", std::process::id()));
+    std::fs::write(&path, users.to_string()).unwrap();
+    std::env::set_var("AFTERLIFE_FILE_USERS", &path);
+}
+
+#[tokio::test]
+async fn directory_lists_every_registered_user() {
+    write_users(json!({
+        "jordan": [CAROL],
+        "Daniel": [BOB],
+        "dan": [ALICE],
+        "eve": [],
+    }));
+    let db = TestDatabase::start().await;
+    let (chain, erc721) = collection("directory", CONTRACT, "erc721");
+    db.index(&chain, vec![transfer(&erc721, ZERO, CAROL, 1, 1, 10)])
+        .await;
+    let avatar = Avatar {
+        chain: "directory".to_string(),
+        contract_address: CONTRACT.to_string(),
+        token_id: 1.into(),
+    };
+    queries::set_avatar(&db.client().await, "jordan", Some(&avatar))
+        .await
+        .unwrap();
+
+    let (status, body) = get(&db.database, "/users?sort=name&offset=1&limit=2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 4);
+    let usernames: Vec<&str> = body["users"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["username"].as_str().unwrap())
+        .collect();
+    assert_eq!(usernames, vec!["Daniel", "eve"]);
+    // Nobody holds a scored token, so nobody is ranked
+    assert_eq!(body["users"][0]["rank"], Value::Null);
+    assert_eq!(body["users"][0]["points"], 0.0);
+
+    // Avatars are looked up for the page asked for, not only the first one
+    let (status, body) = get(&db.database, "/users?sort=name&offset=3&limit=1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["users"][0]["username"], "jordan");
+    assert_eq!(body["users"][0]["avatar"]["token_id"], "1");
+    let (_, body) = get(&db.database, "/users?sort=name&offset=2&limit=1").await;
+    assert_eq!(body["users"][0]["avatar"], Value::Null);
+}